@@ -0,0 +1,504 @@
+// nt_rustos/src/block/cache.rs
+
+//! Block buffer cache.
+//!
+//! `get` returns the cached copy of one block of a disk, reading it from
+//! the device on a miss, so filesystem metadata is read once rather than
+//! on every access; `read_bytes` copies out a byte range spanning blocks.
+//! Buffers are shared: a writer locks the data, changes it and calls
+//! `mark_dirty`. Dirty buffers reach the disk through `sync`, when they
+//! are evicted, or from the `bflush` thread once they have been dirty for
+//! `WRITEBACK_DELAY_MS`.
+//!
+//! The cache holds up to a byte limit, evicting the least recently used
+//! buffers nobody holds. The limit starts at `MAX_BYTES`. When an
+//! allocation fails, the allocator's memory-pressure callback drops clean,
+//! unused buffers and lowers the limit to what is left; every miss raises
+//! it again by one block, up to `MAX_BYTES`.
+//!
+//! A miss on the block following the previous miss on the same disk reads
+//! ahead up to `READ_AHEAD` uncached blocks, which the request queue merges
+//! with the missed block into one device request.
+
+use super::{BlockError, Disk, Op, Request};
+use crate::init::alloc::{register_shrinker, AllocError, Shrinker};
+use crate::init::initcall::InitResult;
+use crate::{info_print, println, task, time, warn_print};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::{Mutex, MutexGuard};
+
+/// Largest cache size in bytes.
+pub const MAX_BYTES: usize = 256 * 1024;
+/// Memory pressure never shrinks the limit below this.
+pub const MIN_BYTES: usize = 16 * 1024;
+/// Most blocks read ahead after a sequential miss.
+pub const READ_AHEAD: u64 = 8;
+/// How long a buffer may stay dirty before `bflush` writes it back.
+pub const WRITEBACK_DELAY_MS: u64 = 1000;
+/// How often `bflush` looks for expired dirty buffers.
+const WRITEBACK_INTERVAL_MS: u64 = 500;
+
+/// A cached block.
+pub struct Buffer {
+    disk: Arc<Disk>,
+    lba: u64,
+    data: Mutex<Vec<u8>>,
+    dirty: AtomicBool,
+    /// Time the buffer last became dirty, in timer ticks.
+    dirtied_at: AtomicU64,
+}
+
+impl Buffer {
+    pub fn disk(&self) -> &Arc<Disk> {
+        &self.disk
+    }
+
+    pub fn lba(&self) -> u64 {
+        self.lba
+    }
+
+    /// Locks the block's data.
+    pub fn lock(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.data.lock()
+    }
+
+    /// Records that the data was changed and must be written back.
+    pub fn mark_dirty(&self) {
+        if !self.dirty.swap(true, Ordering::AcqRel) {
+            self.dirtied_at.store(time::now_ticks(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    /// Writes the block back if it is dirty. The data stays locked during
+    /// the write, so changes made meanwhile dirty it again.
+    pub fn write_back(&self) -> Result<(), BlockError> {
+        let data = self.data.lock();
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let result = self.disk.write(self.lba, &data);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Release);
+        }
+        result
+    }
+
+    /// Queues a write of the block if it is dirty, without running the
+    /// queue, and calls `done` with the outcome. The data is copied, so
+    /// changes made after queueing dirty the buffer again. Returns `false`
+    /// if the buffer was clean.
+    fn queue_write_back(self: &Arc<Self>, done: impl FnOnce(Result<(), BlockError>) + Send + 'static) -> bool {
+        let data = {
+            let data = self.data.lock();
+            if !self.dirty.swap(false, Ordering::AcqRel) {
+                return false;
+            }
+            data.clone()
+        };
+        let buffer = self.clone();
+        self.disk.submit(Request::new(Op::Write, self.lba, data, move |completion| {
+            if completion.result.is_err() {
+                buffer.dirty.store(true, Ordering::Release);
+            }
+            done(completion.result);
+        }));
+        true
+    }
+}
+
+/// Cache counters.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks read ahead of a miss.
+    pub read_ahead: u64,
+    pub evictions: u64,
+    pub write_backs: u64,
+    /// Memory-pressure callbacks that released memory.
+    pub shrinks: u64,
+    /// Bytes held by cached buffers.
+    pub bytes: usize,
+    /// Current size limit in bytes.
+    pub limit: usize,
+}
+
+/// A disk, by the address of its `Arc`, and a block on it. Buffers hold
+/// their disk, so the address is not reused while the block is cached.
+type Key = (usize, u64);
+
+fn disk_id(disk: &Arc<Disk>) -> usize {
+    Arc::as_ptr(disk) as usize
+}
+
+struct Entry {
+    buffer: Arc<Buffer>,
+    /// Position in `Inner::lru`.
+    used: u64,
+}
+
+struct Inner {
+    entries: BTreeMap<Key, Entry>,
+    /// Keys by last use, least recent first.
+    lru: BTreeMap<u64, Key>,
+    clock: u64,
+    /// Per disk, the block whose miss counts as sequential.
+    next_miss: BTreeMap<usize, u64>,
+    stats: CacheStats,
+}
+
+static CACHE: Mutex<Inner> = Mutex::new(Inner {
+    entries: BTreeMap::new(),
+    lru: BTreeMap::new(),
+    clock: 0,
+    next_miss: BTreeMap::new(),
+    stats: CacheStats {
+        hits: 0,
+        misses: 0,
+        read_ahead: 0,
+        evictions: 0,
+        write_backs: 0,
+        shrinks: 0,
+        bytes: 0,
+        limit: MAX_BYTES,
+    },
+});
+
+impl Inner {
+    /// Returns the cached buffer for `key`, making it the most recent.
+    fn touch(&mut self, key: Key) -> Option<Arc<Buffer>> {
+        let entry = self.entries.get_mut(&key)?;
+        self.lru.remove(&entry.used);
+        self.clock += 1;
+        entry.used = self.clock;
+        self.lru.insert(self.clock, key);
+        Some(entry.buffer.clone())
+    }
+
+    /// Caches `buffer` unless its block is cached already, and returns
+    /// the cached buffer.
+    fn insert(&mut self, buffer: Arc<Buffer>) -> Arc<Buffer> {
+        let key = (disk_id(&buffer.disk), buffer.lba);
+        if let Some(cached) = self.touch(key) {
+            return cached;
+        }
+        self.clock += 1;
+        self.stats.bytes += buffer.disk.block_size();
+        self.lru.insert(self.clock, key);
+        self.entries.insert(key, Entry { buffer: buffer.clone(), used: self.clock });
+        buffer
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Arc<Buffer>> {
+        let entry = self.entries.remove(&key)?;
+        self.lru.remove(&entry.used);
+        self.stats.bytes -= entry.buffer.disk.block_size();
+        Some(entry.buffer)
+    }
+
+    /// Evicts clean, unused buffers in LRU order until the cache is within
+    /// `limit`, and returns the dirty, unused ones that stand in the way.
+    fn evict(&mut self, limit: usize) -> Vec<Arc<Buffer>> {
+        let mut excess = self.stats.bytes.saturating_sub(limit);
+        let mut victims = Vec::new();
+        let mut dirty = Vec::new();
+        for key in self.lru.values() {
+            if excess == 0 {
+                break;
+            }
+            let buffer = &self.entries[key].buffer;
+            if Arc::strong_count(buffer) > 1 {
+                continue;
+            }
+            if buffer.is_dirty() {
+                dirty.push(buffer.clone());
+            } else {
+                victims.push(*key);
+            }
+            excess = excess.saturating_sub(buffer.disk.block_size());
+        }
+        for key in victims {
+            self.remove(key);
+            self.stats.evictions += 1;
+        }
+        dirty
+    }
+}
+
+/// Brings the cache within its limit, writing back dirty buffers that
+/// would be evicted.
+fn make_room() {
+    let dirty = {
+        let mut inner = CACHE.lock();
+        let limit = inner.stats.limit;
+        inner.evict(limit)
+    };
+    if dirty.is_empty() {
+        return;
+    }
+    let written = dirty.iter().filter(|b| b.write_back().is_ok()).count();
+    drop(dirty);
+    let mut inner = CACHE.lock();
+    inner.stats.write_backs += written as u64;
+    let limit = inner.stats.limit;
+    inner.evict(limit);
+}
+
+fn new_buffer(disk: &Arc<Disk>, lba: u64, data: Vec<u8>) -> Arc<Buffer> {
+    Arc::new(Buffer {
+        disk: disk.clone(),
+        lba,
+        data: Mutex::new(data),
+        dirty: AtomicBool::new(false),
+        dirtied_at: AtomicU64::new(0),
+    })
+}
+
+/// Returns block `lba` of `disk`, reading it on a miss.
+pub fn get(disk: &Arc<Disk>, lba: u64) -> Result<Arc<Buffer>, BlockError> {
+    if lba >= disk.block_count() {
+        return Err(BlockError::OutOfRange);
+    }
+    let id = disk_id(disk);
+    let block_size = disk.block_size();
+    let ahead = {
+        let mut inner = CACHE.lock();
+        if let Some(buffer) = inner.touch((id, lba)) {
+            inner.stats.hits += 1;
+            return Ok(buffer);
+        }
+        inner.stats.misses += 1;
+        let sequential = inner.next_miss.get(&id) == Some(&lba);
+        let mut ahead = 0;
+        if sequential {
+            // Stop at the first cached block and keep the window to a
+            // quarter of the cache.
+            let window = READ_AHEAD.min((inner.stats.limit / 4 / block_size) as u64);
+            let end = (lba + 1 + window).min(disk.block_count());
+            let next_cached = inner.entries.range((id, lba + 1)..(id, end)).next().map(|(&(_, next), _)| next);
+            ahead = next_cached.unwrap_or(end) - (lba + 1);
+        }
+        inner.next_miss.insert(id, lba + 1 + ahead);
+        ahead
+    };
+
+    if ahead > 0 {
+        // Queued first: the missed block merges in front of it.
+        let owner = disk.clone();
+        disk.submit(Request::new(Op::Read, lba + 1, vec![0u8; ahead as usize * block_size], move |c| {
+            if c.result.is_err() {
+                return;
+            }
+            let mut inner = CACHE.lock();
+            for (i, chunk) in c.data.chunks(block_size).enumerate() {
+                inner.insert(new_buffer(&owner, c.lba + i as u64, chunk.to_vec()));
+            }
+            inner.stats.read_ahead += ahead;
+        }));
+    }
+    let completion = disk.queue().submit_and_wait(Op::Read, lba, vec![0u8; block_size])?;
+
+    let buffer = {
+        let mut inner = CACHE.lock();
+        inner.stats.limit = (inner.stats.limit + block_size).min(MAX_BYTES);
+        inner.insert(new_buffer(disk, lba, completion.data))
+    };
+    make_room();
+    Ok(buffer)
+}
+
+/// Reads `buf.len()` bytes at byte `offset` of `disk` through the cache,
+/// for structures that do not line up with the disk's blocks.
+pub fn read_bytes(disk: &Arc<Disk>, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+    let block_size = disk.block_size() as u64;
+    let mut done = 0;
+    while done < buf.len() {
+        let pos = offset + done as u64;
+        let buffer = get(disk, pos / block_size)?;
+        let data = buffer.lock();
+        let start = (pos % block_size) as usize;
+        let len = (buf.len() - done).min(data.len() - start);
+        buf[done..done + len].copy_from_slice(&data[start..start + len]);
+        done += len;
+    }
+    Ok(())
+}
+
+/// Returns block `lba` of `disk` without reading it, for callers about to
+/// overwrite the whole block. An uncached block starts zeroed.
+pub fn get_for_overwrite(disk: &Arc<Disk>, lba: u64) -> Result<Arc<Buffer>, BlockError> {
+    if lba >= disk.block_count() {
+        return Err(BlockError::OutOfRange);
+    }
+    if disk.is_read_only() {
+        return Err(BlockError::ReadOnly);
+    }
+    let buffer = CACHE.lock().insert(new_buffer(disk, lba, vec![0u8; disk.block_size()]));
+    make_room();
+    Ok(buffer)
+}
+
+/// Returns the cached dirty buffers of `disk`, or of every disk.
+fn dirty_buffers(disk: Option<&Arc<Disk>>) -> Vec<Arc<Buffer>> {
+    let inner = CACHE.lock();
+    let range = match disk {
+        Some(disk) => (disk_id(disk), 0)..=(disk_id(disk), u64::MAX),
+        None => (0, 0)..=(usize::MAX, u64::MAX),
+    };
+    inner.entries.range(range).map(|(_, e)| e.buffer.clone()).filter(|b| b.is_dirty()).collect()
+}
+
+/// Writes back `buffers`, grouped by disk, and flushes each disk. The
+/// writes of a disk are queued together, so its elevator can sort and merge
+/// them.
+fn write_back_all(buffers: Vec<Arc<Buffer>>) -> Result<(), BlockError> {
+    let outcome: Arc<Mutex<(u64, Result<(), BlockError>)>> = Arc::new(Mutex::new((0, Ok(()))));
+    let mut result = Ok(());
+    for (i, buffer) in buffers.iter().enumerate() {
+        let outcome = outcome.clone();
+        buffer.queue_write_back(move |write| {
+            let mut outcome = outcome.lock();
+            match write {
+                Ok(()) => outcome.0 += 1,
+                Err(e) => outcome.1 = outcome.1.and(Err(e)),
+            }
+        });
+        let last_of_disk = buffers.get(i + 1).is_none_or(|next| !Arc::ptr_eq(&next.disk, &buffer.disk));
+        if last_of_disk {
+            // The flush follows the writes, which the queue runs first.
+            result = result.and(buffer.disk.flush());
+        }
+    }
+    let (written, written_result) = *outcome.lock();
+    CACHE.lock().stats.write_backs += written;
+    written_result.and(result)
+}
+
+/// Writes back every dirty buffer of `disk` and flushes it.
+pub fn sync(disk: &Arc<Disk>) -> Result<(), BlockError> {
+    write_back_all(dirty_buffers(Some(disk)))
+}
+
+/// Writes back every dirty buffer.
+pub fn sync_all() -> Result<(), BlockError> {
+    write_back_all(dirty_buffers(None))
+}
+
+/// Writes back the buffers that have been dirty for `WRITEBACK_DELAY_MS`.
+pub fn write_back_expired() -> Result<(), BlockError> {
+    let delay = time::ms_to_ticks(WRITEBACK_DELAY_MS);
+    let now = time::now_ticks();
+    let mut expired = dirty_buffers(None);
+    expired.retain(|b| now.saturating_sub(b.dirtied_at.load(Ordering::Relaxed)) >= delay);
+    if expired.is_empty() {
+        return Ok(());
+    }
+    write_back_all(expired)
+}
+
+/// Writes back the dirty buffers of `disk` and drops all its buffers.
+/// Buffers still held elsewhere stay valid but are no longer cached.
+pub fn invalidate(disk: &Arc<Disk>) -> Result<(), BlockError> {
+    let result = sync(disk);
+    let id = disk_id(disk);
+    let mut inner = CACHE.lock();
+    let keys: Vec<Key> = inner.entries.range((id, 0)..=(id, u64::MAX)).map(|(k, _)| *k).collect();
+    for key in keys {
+        inner.remove(key);
+    }
+    inner.next_miss.remove(&id);
+    result
+}
+
+pub fn stats() -> CacheStats {
+    CACHE.lock().stats
+}
+
+/// Bytes held by clean, unused buffers.
+fn reclaimable_bytes() -> usize {
+    let Some(inner) = CACHE.try_lock() else {
+        return 0;
+    };
+    inner
+        .entries
+        .values()
+        .filter(|e| Arc::strong_count(&e.buffer) == 1 && !e.buffer.is_dirty())
+        .map(|e| e.buffer.disk.block_size())
+        .sum()
+}
+
+/// The memory-pressure callback: drops clean, unused buffers to release
+/// `bytes`, and keeps the limit at what is left. It runs on the allocation
+/// path, so it gives up rather than wait for the cache lock or do I/O.
+fn shrink(bytes: usize) -> usize {
+    let Some(mut inner) = CACHE.try_lock() else {
+        return 0;
+    };
+    let before = inner.stats.bytes;
+    let target = before.saturating_sub(bytes);
+    // Dirty buffers are skipped; writing them back would allocate.
+    drop(inner.evict(target));
+    let freed = before - inner.stats.bytes;
+    inner.stats.limit = inner.stats.bytes.max(MIN_BYTES);
+    if freed > 0 {
+        inner.stats.shrinks += 1;
+    }
+    freed
+}
+
+static SHRINKER: Shrinker = Shrinker { name: "block-cache", count: reclaimable_bytes, scan: shrink };
+
+/// Writes back expired dirty buffers periodically.
+fn flusher() {
+    loop {
+        task::sleep_ms(WRITEBACK_INTERVAL_MS);
+        if let Err(e) = write_back_expired() {
+            warn_print!("bflush: write-back failed: {}", e);
+        }
+    }
+}
+
+/// Registers the memory-pressure callback and starts the `bflush` thread.
+pub fn init() -> Result<(), AllocError> {
+    register_shrinker(&SHRINKER)?;
+    match task::spawn_kernel_thread("bflush", flusher) {
+        Ok(pid) => info_print!("Buffer cache write-back thread started (pid {}).", pid),
+        Err(e) => warn_print!("Buffer cache write-back thread not started: {:?}; use sync", e),
+    }
+    Ok(())
+}
+
+/// Sets up the cache at boot; the writeback thread needs the task
+/// subsystem.
+fn init_cache() -> InitResult {
+    init().map_err(|e| format!("failed to initialize buffer cache: {:?}", e))?;
+    info_print!("Buffer cache initialized (up to {} KB).", MAX_BYTES / 1024);
+    Ok(())
+}
+
+crate::initcall!(subsys, 60, init_cache);
+
+/// Prints the cache counters.
+pub fn print_stats() {
+    let stats = stats();
+    println!(
+        "Buffer cache: {} / {} KB, {} hits, {} misses, {} read ahead, {} evicted, {} written back, {} shrinks",
+        stats.bytes / 1024,
+        stats.limit / 1024,
+        stats.hits,
+        stats.misses,
+        stats.read_ahead,
+        stats.evictions,
+        stats.write_backs,
+        stats.shrinks
+    );
+}
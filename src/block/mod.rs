@@ -0,0 +1,205 @@
+// nt_rustos/src/block/mod.rs
+
+//! # Block Layer
+//!
+//! A `BlockDevice` reads and writes fixed-size blocks addressed by logical
+//! block address (LBA). Drivers register their devices by name; each
+//! registered device becomes a `Disk`, which owns the device's request
+//! queue. Filesystems look disks up by name and do their I/O through the
+//! queue, either synchronously with `Disk::read`/`write`/`flush` or by
+//! submitting `Request`s with completion callbacks. Block-sized metadata
+//! I/O goes through the buffer cache in `cache`.
+//!
+//! Devices are driven synchronously: a queued request is handed to the
+//! device when the queue is run, and its callback is called once the
+//! device has finished it.
+
+pub mod cache;
+pub mod queue;
+pub mod ramdisk;
+
+pub use self::queue::{Completion, Op, QueueStats, Request, RequestQueue, DEFAULT_QUEUE_DEPTH};
+pub use self::ramdisk::RamDisk;
+
+use crate::println;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+/// Errors returned by the block layer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    /// A device with the same name is already registered.
+    AlreadyRegistered,
+    /// No device has this name.
+    NotFound,
+    /// The request extends past the end of the device.
+    OutOfRange,
+    /// The buffer is not a whole number of blocks.
+    Misaligned,
+    /// The device is read-only.
+    ReadOnly,
+    /// The device failed the request.
+    Io,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyRegistered => write!(f, "block device already registered"),
+            Self::NotFound => write!(f, "no such block device"),
+            Self::OutOfRange => write!(f, "block address out of range"),
+            Self::Misaligned => write!(f, "buffer not a multiple of the block size"),
+            Self::ReadOnly => write!(f, "block device is read-only"),
+            Self::Io => write!(f, "block device I/O error"),
+        }
+    }
+}
+
+/// A device storing fixed-size blocks.
+///
+/// The buffers passed to `read_blocks` and `write_blocks` hold one or more
+/// whole blocks; the caller has checked them against `block_count`.
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Size of a block in bytes, a power of two.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks on the device.
+    fn block_count(&self) -> u64;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Requests the device's queue collects before dispatching them.
+    fn queue_depth(&self) -> usize {
+        DEFAULT_QUEUE_DEPTH
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Makes completed writes durable.
+    fn flush(&self) -> Result<(), BlockError>;
+}
+
+/// A registered block device and its request queue.
+pub struct Disk {
+    device: Arc<dyn BlockDevice>,
+    queue: RequestQueue,
+}
+
+impl Disk {
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+
+    /// Returns the underlying device.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    /// Returns the disk's request queue.
+    pub fn queue(&self) -> &RequestQueue {
+        &self.queue
+    }
+
+    /// Queues `request`; its callback runs when the queue is run.
+    pub fn submit(&self, request: Request) {
+        self.queue.submit(request)
+    }
+
+    /// Reads the blocks starting at `lba` into `buf`.
+    pub fn read(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let completion = self.queue.submit_and_wait(Op::Read, lba, alloc::vec![0u8; buf.len()])?;
+        buf.copy_from_slice(&completion.data);
+        Ok(())
+    }
+
+    /// Writes `buf` to the blocks starting at `lba`.
+    pub fn write(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.queue.submit_and_wait(Op::Write, lba, buf.to_vec()).map(|_| ())
+    }
+
+    /// Runs the queue and makes all writes durable.
+    pub fn flush(&self) -> Result<(), BlockError> {
+        self.queue.submit_and_wait(Op::Flush, 0, Vec::new()).map(|_| ())
+    }
+}
+
+static DISKS: Mutex<Vec<Arc<Disk>>> = Mutex::new(Vec::new());
+
+/// Registers `device` under its name and returns its disk.
+pub fn register(device: Arc<dyn BlockDevice>) -> Result<Arc<Disk>, BlockError> {
+    let mut disks = DISKS.lock();
+    if disks.iter().any(|d| d.name() == device.name()) {
+        return Err(BlockError::AlreadyRegistered);
+    }
+    let disk = Arc::new(Disk { queue: RequestQueue::new(device.clone()), device });
+    disks.push(disk.clone());
+    Ok(disk)
+}
+
+/// Removes the disk called `name`. Its dirty cached blocks are written
+/// back and requests still queued on it are run. The disk is removed even
+/// if the write-back fails; the error is returned.
+pub fn unregister(name: &str) -> Result<(), BlockError> {
+    let disk = {
+        let mut disks = DISKS.lock();
+        let index = disks.iter().position(|d| d.name() == name).ok_or(BlockError::NotFound)?;
+        disks.remove(index)
+    };
+    let result = cache::invalidate(&disk);
+    disk.queue.run();
+    result
+}
+
+/// Returns the disk called `name`.
+pub fn find(name: &str) -> Option<Arc<Disk>> {
+    DISKS.lock().iter().find(|d| d.name() == name).cloned()
+}
+
+/// Returns the registered disks, in registration order.
+pub fn disks() -> Vec<Arc<Disk>> {
+    DISKS.lock().clone()
+}
+
+/// Prints the registered disks with their sizes and queue statistics.
+pub fn print_disks() {
+    let disks = disks();
+    println!("Block devices ({}):", disks.len());
+    for disk in disks {
+        let stats = disk.queue.stats();
+        println!(
+            "  {:<8} {} x {} B ({} KB){}, {} reads, {} writes, {} flushes, {} merged ({} at dispatch), depth {}/{}",
+            disk.name(),
+            disk.block_count(),
+            disk.block_size(),
+            disk.block_count() * disk.block_size() as u64 / 1024,
+            if disk.is_read_only() { " ro" } else { "" },
+            stats.reads,
+            stats.writes,
+            stats.flushes,
+            stats.merges,
+            stats.elevator_merges,
+            stats.max_depth,
+            disk.queue.depth_limit()
+        );
+    }
+}
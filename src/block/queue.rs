@@ -0,0 +1,405 @@
+// nt_rustos/src/block/queue.rs
+
+//! Block request queue.
+//!
+//! Requests are queued until the queue is run, which happens when a
+//! synchronous caller waits for its request or when the queue reaches its
+//! depth limit. The limit comes from the device (`BlockDevice::queue_depth`)
+//! and can be changed per queue with `set_depth_limit`.
+//!
+//! A new read or write that continues the last queued request of the same
+//! kind, at either end, is merged into it, so a run of small sequential
+//! requests reaches the device as one. Each merged request keeps its own
+//! buffer and callback.
+//!
+//! When the queue is run, an elevator orders the batch before dispatch. The
+//! batch is cut into runs at flushes, which act as barriers, and before any
+//! request overlapping an earlier request of the run where either is a
+//! write, so conflicting requests keep their submission order. Each run is
+//! sorted into one upward sweep starting at the block after the last
+//! dispatched request, wrapping around to the lowest block (C-LOOK), and
+//! neighbours that became contiguous are merged.
+
+use super::{BlockDevice, BlockError};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Largest merged request, in blocks.
+const MAX_MERGED_BLOCKS: u64 = 256;
+/// Queued requests that make `submit` run the queue itself, unless the
+/// device asks for another limit.
+pub const DEFAULT_QUEUE_DEPTH: usize = 32;
+
+/// The kind of a request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    Read,
+    Write,
+    Flush,
+}
+
+/// Called with the outcome of a request.
+pub type CompletionFn = Box<dyn FnOnce(Completion) + Send>;
+
+/// A request submitted to a queue.
+pub struct Request {
+    pub op: Op,
+    /// First block; ignored for flushes.
+    pub lba: u64,
+    /// Whole blocks to write, or a buffer the size of the blocks to read.
+    /// Empty for flushes.
+    pub data: Vec<u8>,
+    pub on_complete: CompletionFn,
+}
+
+impl Request {
+    pub fn new(op: Op, lba: u64, data: Vec<u8>, on_complete: impl FnOnce(Completion) + Send + 'static) -> Self {
+        Self { op, lba, data, on_complete: Box::new(on_complete) }
+    }
+}
+
+/// The outcome of a request, handed to its callback together with the
+/// request's buffer (holding the data read, for reads).
+#[derive(Debug)]
+pub struct Completion {
+    pub op: Op,
+    pub lba: u64,
+    pub data: Vec<u8>,
+    pub result: Result<(), BlockError>,
+}
+
+/// Request counters of a queue. Merged requests count once.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub flushes: u64,
+    /// Requests merged into a queued one.
+    pub merges: u64,
+    /// Queued requests merged by the elevator after sorting.
+    pub elevator_merges: u64,
+    pub blocks_read: u64,
+    pub blocks_written: u64,
+    pub errors: u64,
+    /// Blocks between the end of each dispatched request and the start of
+    /// the next, summed; the distance the elevator saves.
+    pub seek_blocks: u64,
+    /// Most requests queued at once, after merging.
+    pub max_depth: usize,
+    /// Submissions that found the queue at its depth limit and ran it.
+    pub full_runs: u64,
+}
+
+/// One submitted request inside a queued one.
+struct Segment {
+    data: Vec<u8>,
+    on_complete: CompletionFn,
+}
+
+/// A queued request: one or more merged requests covering contiguous
+/// blocks, in block order.
+struct Pending {
+    op: Op,
+    lba: u64,
+    blocks: u64,
+    segments: VecDeque<Segment>,
+}
+
+impl Pending {
+    /// Returns `true` if the two requests must not be reordered: they share
+    /// a block and one of them writes it.
+    fn conflicts(&self, other: &Pending) -> bool {
+        let overlap = self.lba < other.lba + other.blocks && other.lba < self.lba + self.blocks;
+        overlap && (self.op == Op::Write || other.op == Op::Write)
+    }
+
+    /// Returns `true` if `next` continues this request and fits into it.
+    fn can_append(&self, next: &Pending) -> bool {
+        self.op == next.op
+            && self.op != Op::Flush
+            && self.lba + self.blocks == next.lba
+            && self.blocks + next.blocks <= MAX_MERGED_BLOCKS
+    }
+}
+
+struct Inner {
+    pending: VecDeque<Pending>,
+    stats: QueueStats,
+    depth_limit: usize,
+    /// The block after the last dispatched request, where the elevator's
+    /// next sweep starts.
+    head: u64,
+}
+
+impl Inner {
+    /// Orders `batch` for dispatch, as described in the module
+    /// documentation.
+    fn schedule(&mut self, batch: Vec<Pending>) -> Vec<Pending> {
+        let mut ordered = Vec::with_capacity(batch.len());
+        let mut run: Vec<Pending> = Vec::new();
+        for pending in batch {
+            let barrier = pending.op == Op::Flush;
+            if barrier || run.iter().any(|p| p.conflicts(&pending)) {
+                self.sweep(&mut run, &mut ordered);
+            }
+            if barrier {
+                ordered.push(pending);
+            } else {
+                run.push(pending);
+            }
+        }
+        self.sweep(&mut run, &mut ordered);
+        ordered
+    }
+
+    /// Sorts `run` into one sweep from `head`, wrapping around once, and
+    /// appends it to `ordered`, merging contiguous neighbours.
+    fn sweep(&mut self, run: &mut Vec<Pending>, ordered: &mut Vec<Pending>) {
+        let head = self.head;
+        // Stable, so requests for the same block keep their order.
+        run.sort_by_key(|p| (p.lba < head, p.lba));
+        for pending in run.drain(..) {
+            match ordered.last_mut() {
+                Some(last) if last.can_append(&pending) => {
+                    last.blocks += pending.blocks;
+                    last.segments.extend(pending.segments);
+                    self.stats.elevator_merges += 1;
+                }
+                _ => ordered.push(pending),
+            }
+        }
+        if let Some(last) = ordered.iter().rev().find(|p| p.op != Op::Flush) {
+            self.head = last.lba + last.blocks;
+        }
+    }
+}
+
+/// The request queue of a block device.
+pub struct RequestQueue {
+    device: Arc<dyn BlockDevice>,
+    inner: Mutex<Inner>,
+    /// Held while requests are handed to the device, so that they reach
+    /// it in queue order.
+    dispatch: Mutex<()>,
+}
+
+impl RequestQueue {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        let inner = Inner {
+            pending: VecDeque::new(),
+            stats: QueueStats::default(),
+            depth_limit: device.queue_depth().max(1),
+            head: 0,
+        };
+        Self { device, inner: Mutex::new(inner), dispatch: Mutex::new(()) }
+    }
+
+    /// Returns how many requests may be queued before `submit` runs the
+    /// queue.
+    pub fn depth_limit(&self) -> usize {
+        self.inner.lock().depth_limit
+    }
+
+    /// Sets the depth limit; at least one request is always queued. A
+    /// limit of 1 dispatches every request as it is submitted.
+    pub fn set_depth_limit(&self, limit: usize) {
+        self.inner.lock().depth_limit = limit.max(1);
+    }
+
+    /// Checks `request` against the device, returning its length in blocks.
+    fn validate(&self, request: &Request) -> Result<u64, BlockError> {
+        if request.op == Op::Flush {
+            return Ok(0);
+        }
+        let block_size = self.device.block_size();
+        if request.data.is_empty() || request.data.len() % block_size != 0 {
+            return Err(BlockError::Misaligned);
+        }
+        let blocks = (request.data.len() / block_size) as u64;
+        match request.lba.checked_add(blocks) {
+            Some(end) if end <= self.device.block_count() => {}
+            _ => return Err(BlockError::OutOfRange),
+        }
+        if request.op == Op::Write && self.device.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        Ok(blocks)
+    }
+
+    /// Queues `request`. Invalid requests complete at once with an error.
+    pub fn submit(&self, request: Request) {
+        let blocks = match self.validate(&request) {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                self.inner.lock().stats.errors += 1;
+                let Request { op, lba, data, on_complete } = request;
+                on_complete(Completion { op, lba, data, result: Err(e) });
+                return;
+            }
+        };
+        let Request { op, lba, data, on_complete } = request;
+        let segment = Segment { data, on_complete };
+
+        let mut guard = self.inner.lock();
+        let inner = &mut *guard;
+        let merge = inner.pending.back_mut().and_then(|tail| {
+            if tail.op != op {
+                return None;
+            }
+            if op == Op::Flush {
+                return Some((tail, true));
+            }
+            if tail.blocks + blocks > MAX_MERGED_BLOCKS {
+                return None;
+            }
+            if tail.lba + tail.blocks == lba {
+                Some((tail, true))
+            } else if lba + blocks == tail.lba {
+                Some((tail, false))
+            } else {
+                None
+            }
+        });
+        match merge {
+            Some((tail, back)) => {
+                if back {
+                    tail.segments.push_back(segment);
+                } else {
+                    tail.segments.push_front(segment);
+                    tail.lba = lba;
+                }
+                tail.blocks += blocks;
+                inner.stats.merges += 1;
+            }
+            None => inner.pending.push_back(Pending { op, lba, blocks, segments: VecDeque::from([segment]) }),
+        }
+        inner.stats.max_depth = inner.stats.max_depth.max(inner.pending.len());
+        let full = inner.pending.len() >= inner.depth_limit;
+        if full {
+            inner.stats.full_runs += 1;
+        }
+        drop(guard);
+        if full {
+            self.run();
+        }
+    }
+
+    /// Hands every queued request to the device in elevator order and calls
+    /// the callbacks, until the queue is empty.
+    pub fn run(&self) {
+        loop {
+            let completions: Vec<(Completion, CompletionFn)> = {
+                let _dispatch = self.dispatch.lock();
+                let batch = {
+                    let mut inner = self.inner.lock();
+                    let batch: Vec<Pending> = inner.pending.drain(..).collect();
+                    let previous = inner.head;
+                    let batch = inner.schedule(batch);
+                    let mut position = previous;
+                    for pending in batch.iter().filter(|p| p.op != Op::Flush) {
+                        inner.stats.seek_blocks += position.abs_diff(pending.lba);
+                        position = pending.lba + pending.blocks;
+                    }
+                    batch
+                };
+                if batch.is_empty() {
+                    return;
+                }
+                batch.into_iter().flat_map(|pending| self.execute(pending)).collect()
+            };
+            // Outside the dispatch lock: callbacks may submit requests.
+            for (completion, on_complete) in completions {
+                on_complete(completion);
+            }
+        }
+    }
+
+    /// Performs a queued request on the device.
+    fn execute(&self, pending: Pending) -> Vec<(Completion, CompletionFn)> {
+        let Pending { op, lba, blocks, segments } = pending;
+        let block_size = self.device.block_size();
+        let mut segments: Vec<Segment> = segments.into();
+        let result = match op {
+            Op::Read if segments.len() == 1 => self.device.read_blocks(lba, &mut segments[0].data),
+            Op::Read => {
+                let mut buf = vec![0u8; blocks as usize * block_size];
+                let result = self.device.read_blocks(lba, &mut buf);
+                if result.is_ok() {
+                    let mut offset = 0;
+                    for segment in segments.iter_mut() {
+                        let len = segment.data.len();
+                        segment.data.copy_from_slice(&buf[offset..offset + len]);
+                        offset += len;
+                    }
+                }
+                result
+            }
+            Op::Write if segments.len() == 1 => self.device.write_blocks(lba, &segments[0].data),
+            Op::Write => {
+                let buf: Vec<u8> = segments.iter().flat_map(|s| s.data.iter().copied()).collect();
+                self.device.write_blocks(lba, &buf)
+            }
+            Op::Flush => self.device.flush(),
+        };
+
+        {
+            let stats = &mut self.inner.lock().stats;
+            match op {
+                Op::Read => {
+                    stats.reads += 1;
+                    stats.blocks_read += blocks;
+                }
+                Op::Write => {
+                    stats.writes += 1;
+                    stats.blocks_written += blocks;
+                }
+                Op::Flush => stats.flushes += 1,
+            }
+            if result.is_err() {
+                stats.errors += 1;
+            }
+        }
+
+        let mut next_lba = lba;
+        segments
+            .into_iter()
+            .map(|segment| {
+                let lba = next_lba;
+                next_lba += (segment.data.len() / block_size) as u64;
+                (Completion { op, lba, data: segment.data, result }, segment.on_complete)
+            })
+            .collect()
+    }
+
+    /// Submits a request, runs the queue and returns the completion.
+    pub fn submit_and_wait(&self, op: Op, lba: u64, data: Vec<u8>) -> Result<Completion, BlockError> {
+        let slot = Arc::new(Mutex::new(None));
+        let filled = slot.clone();
+        self.submit(Request::new(op, lba, data, move |completion| *filled.lock() = Some(completion)));
+        loop {
+            if let Some(completion) = slot.lock().take() {
+                return completion.result.map(|()| completion);
+            }
+            // Another hart may be running the queue and calling our callback.
+            self.run();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the number of queued requests, after merging.
+    pub fn len(&self) -> usize {
+        self.inner.lock().pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.inner.lock().stats
+    }
+}
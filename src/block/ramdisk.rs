@@ -0,0 +1,77 @@
+// nt_rustos/src/block/ramdisk.rs
+
+//! A block device backed by heap memory, for tests and scratch space.
+
+use super::{BlockDevice, BlockError};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub struct RamDisk {
+    name: String,
+    block_size: usize,
+    read_only: bool,
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// Creates a zero-filled disk of `blocks` blocks of `block_size` bytes.
+    pub fn new(name: &str, block_size: usize, blocks: usize) -> Self {
+        Self::from_image(name, block_size, vec![0u8; block_size * blocks], false)
+    }
+
+    /// Creates a disk holding `image`, padded to a whole number of blocks.
+    pub fn from_image(name: &str, block_size: usize, mut image: Vec<u8>, read_only: bool) -> Self {
+        assert!(block_size.is_power_of_two(), "block size must be a power of two");
+        let len = image.len().div_ceil(block_size) * block_size;
+        image.resize(len, 0);
+        Self { name: String::from(name), block_size, read_only, data: Mutex::new(image) }
+    }
+
+    fn range(&self, lba: u64, len: usize) -> Result<core::ops::Range<usize>, BlockError> {
+        let start = (lba as usize).checked_mul(self.block_size).ok_or(BlockError::OutOfRange)?;
+        let end = start.checked_add(len).ok_or(BlockError::OutOfRange)?;
+        if end > self.data.lock().len() {
+            return Err(BlockError::OutOfRange);
+        }
+        Ok(start..end)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let range = self.range(lba, buf.len())?;
+        buf.copy_from_slice(&self.data.lock()[range]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        let range = self.range(lba, buf.len())?;
+        self.data.lock()[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+// nt_rustos/src/boot.rs
+
+//! # Boot Information
+//!
+//! OpenSBI enters the kernel with the ID of the boot hart in `a0` and the
+//! physical address of the flattened device tree in `a1`. `_start` only
+//! sets up the stack and passes both on untouched; they are recorded here
+//! once `.bss` has been cleared, for the device tree parser and SMP bring-up.
+//!
+//! If the firmware starts several harts at `_start`, only the one elected
+//! there boots; `park` holds the others until they are released.
+//! `timing` times the main boot phases.
+
+pub mod park;
+pub mod timing;
+
+use spin::Once;
+
+/// The arguments the kernel was booted with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BootInfo {
+    /// The hart that entered the kernel.
+    pub hart_id: usize,
+    /// Physical address of the flattened device tree blob.
+    pub dtb_addr: usize,
+}
+
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+/// Records the boot arguments. Only the first call has an effect.
+pub fn record(hart_id: usize, dtb_addr: usize) {
+    BOOT_INFO.call_once(|| BootInfo { hart_id, dtb_addr });
+}
+
+/// Returns the boot arguments, or `None` before `record`.
+pub fn boot_info() -> Option<BootInfo> {
+    BOOT_INFO.get().copied()
+}
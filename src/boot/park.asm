@@ -0,0 +1,60 @@
+# nt_rustos/src/boot/park.asm
+# 多hart启动时的抽签与停放
+# 固件可能让所有hart同时从 _start 进入内核：_start 对 __boot_lottery 做原子加，
+# 抽到0的hart继续启动，其余hart跳到 __boot_park 等待，直到启动hart填写它们的邮箱。
+# 这些数据放在.data段而非.bss段：启动hart清理BSS时，停放的hart可能正在读取它们。
+# 布局必须与 boot::park 中的 Mailbox 和 MAX_PARKED 保持一致
+
+.section .data
+.align 3
+.globl __boot_lottery
+__boot_lottery:
+    .dword 0
+# 已停放hart的位掩码，第n位对应hart n
+.globl __parked_harts
+__parked_harts:
+    .dword 0
+# 每个hart一个32字节的邮箱：入口地址、栈顶、参数、保留
+.globl __park_mailboxes
+__park_mailboxes:
+    .zero 64 * 32
+
+.section .text
+.globl __boot_park
+.align 2
+
+# __boot_park: 停放当前hart，不使用栈
+#   a0: hart ID
+__boot_park:
+    csrci sstatus, 2
+    li t0, 64
+    bgeu a0, t0, 3f
+    # 在停放掩码中登记
+    li t1, 1
+    sll t1, t1, a0
+    la t0, __parked_harts
+    amoor.d.aqrl zero, t1, (t0)
+    la t0, __park_mailboxes
+    slli t1, a0, 5
+    add t0, t0, t1
+    # 只开启软件中断：sstatus.SIE为0，IPI唤醒wfi但不进入trap
+    csrsi sie, 2
+1:
+    ld t1, 0(t0)
+    bnez t1, 2f
+    wfi
+    j 1b
+2:
+    # 先读到入口地址，再读栈顶和参数
+    fence r, rw
+    ld sp, 8(t0)
+    ld a1, 16(t0)
+    # 清空入口，hart再次进入 _start 时重新停放
+    sd zero, 0(t0)
+    csrci sie, 2
+    csrci sip, 2
+    jr t1
+3:
+    # 没有邮箱的hart永久停放
+    wfi
+    j 3b
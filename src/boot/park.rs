@@ -0,0 +1,125 @@
+// nt_rustos/src/boot/park.rs
+
+//! # Secondary Hart Parking
+//!
+//! Some firmware starts every hart at `_start` at once instead of only the
+//! boot hart. `_start` therefore holds a lottery first: the hart that draws
+//! zero clears `.bss`, sets up the allocator and boots the kernel, and every
+//! other hart jumps to `__boot_park` in `park.asm` without touching memory
+//! the boot hart is initialising.
+//!
+//! A parked hart sets its bit in a mask, enables only supervisor software
+//! interrupts and sleeps in `wfi` on its mailbox. `release` fills in the
+//! mailbox with an entry point, a stack and an argument and sends the hart
+//! an IPI; the hart then jumps to the entry with its ID in `a0` and the
+//! argument in `a1`. The kernel does not run secondary harts yet, so harts
+//! stay parked until SMP bring-up releases them once the per-hart state
+//! their entry uses is ready.
+//!
+//! Harts with an ID of `MAX_PARKED` or above have no mailbox and are parked
+//! for good.
+
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use crate::util::sbi::ipi;
+use core::arch::global_asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+global_asm!(include_str!("park.asm"));
+
+/// Harts that can be parked and released; one bit of the parked mask each.
+pub const MAX_PARKED: usize = 64;
+
+/// What a parked hart waits for. The layout is shared with `park.asm`.
+#[repr(C)]
+struct Mailbox {
+    /// Address to jump to; zero while the hart stays parked.
+    entry: AtomicUsize,
+    stack_top: AtomicUsize,
+    arg: AtomicUsize,
+    _reserved: usize,
+}
+
+extern "C" {
+    static __parked_harts: AtomicU64;
+    static __park_mailboxes: [Mailbox; MAX_PARKED];
+}
+
+/// Code a released hart runs, on the stack it was given. Paging and
+/// interrupts are off, as the firmware left them.
+pub type HartEntry = extern "C" fn(hart_id: usize, arg: usize) -> !;
+
+/// Why a hart could not be released.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParkError {
+    /// The hart is not waiting in `__boot_park`.
+    NotParked,
+    /// The stack top is not 16-byte aligned.
+    MisalignedStack,
+    /// The wakeup IPI could not be sent; the hart starts at its next wakeup.
+    Ipi,
+}
+
+impl fmt::Display for ParkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParkError::NotParked => write!(f, "hart is not parked"),
+            ParkError::MisalignedStack => write!(f, "stack top is not 16-byte aligned"),
+            ParkError::Ipi => write!(f, "failed to send the wakeup IPI"),
+        }
+    }
+}
+
+fn parked() -> &'static AtomicU64 {
+    unsafe { &__parked_harts }
+}
+
+/// Returns the mask of parked harts, bit `n` for hart `n`.
+pub fn parked_mask() -> u64 {
+    parked().load(Ordering::Acquire)
+}
+
+/// Returns whether `hart_id` is parked.
+pub fn is_parked(hart_id: usize) -> bool {
+    hart_id < MAX_PARKED && parked_mask() & (1 << hart_id) != 0
+}
+
+/// Returns the IDs of the parked harts, in increasing order.
+pub fn parked_harts() -> impl Iterator<Item = usize> {
+    let mask = parked_mask();
+    (0..MAX_PARKED).filter(move |hart| mask & (1 << hart) != 0)
+}
+
+/// Releases the parked hart `hart_id` to run `entry(hart_id, arg)` on the
+/// stack ending at `stack_top`.
+///
+/// # Safety
+/// The stack must be valid and unused for as long as the hart runs on it,
+/// and everything `entry` touches must be ready to be used from another
+/// hart, at physical addresses.
+pub unsafe fn release(hart_id: usize, entry: HartEntry, stack_top: usize, arg: usize) -> Result<(), ParkError> {
+    if !stack_top.is_multiple_of(16) {
+        return Err(ParkError::MisalignedStack);
+    }
+    if hart_id >= MAX_PARKED || parked().fetch_and(!(1 << hart_id), Ordering::AcqRel) & (1 << hart_id) == 0 {
+        return Err(ParkError::NotParked);
+    }
+    let mailbox = &__park_mailboxes[hart_id];
+    mailbox.stack_top.store(stack_top, Ordering::Relaxed);
+    mailbox.arg.store(arg, Ordering::Relaxed);
+    // The hart reads the stack and argument after seeing the entry.
+    mailbox.entry.store(entry as usize, Ordering::Release);
+    ipi::send_ipi(1 << hart_id).map(|_| ()).map_err(|_| ParkError::Ipi)
+}
+
+/// Reports the harts that lost the lottery at `_start`.
+fn report_parked() -> InitResult {
+    let parked = parked_mask();
+    if parked != 0 {
+        info_print!("{} secondary hart(s) parked (mask {:#x}).", parked.count_ones(), parked);
+    }
+    Ok(())
+}
+
+crate::initcall!(early, 40, report_parked);
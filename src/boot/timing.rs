@@ -0,0 +1,189 @@
+// nt_rustos/src/boot/timing.rs
+
+//! # Boot Timing
+//!
+//! Timestamps of the main boot phases, so that a slower boot shows which
+//! phase grew. Stamps are raw readings of the `time` CSR: the first is
+//! taken on entry, before `.bss` is cleared and before the platform has
+//! given the timebase, and they are only converted to microseconds when
+//! reported. Offsets count from kernel entry.
+//!
+//! A phase is timed by holding the guard `measure` returns, or recorded
+//! afterwards with `record` when its start was read before the stamps
+//! could be stored. Each phase is recorded once; later measurements of it,
+//! such as a test rerunning an initcall, leave the first one in place.
+
+use crate::println;
+use crate::time;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// A timed boot phase, in boot order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// Clearing `.bss`.
+    BssClear,
+    /// Setting up the early heap.
+    Heap,
+    /// Setting up the trap subsystem.
+    Traps,
+    /// Probing devices and binding drivers.
+    Devices,
+    /// Running the kernel test suites.
+    Tests,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [Phase::BssClear, Phase::Heap, Phase::Traps, Phase::Devices, Phase::Tests];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::BssClear => "bss clear",
+            Phase::Heap => "heap",
+            Phase::Traps => "traps",
+            Phase::Devices => "devices",
+            Phase::Tests => "tests",
+        }
+    }
+}
+
+/// Start and end stamps of a phase.
+struct Span {
+    start: AtomicU64,
+    end: AtomicU64,
+    started: AtomicBool,
+    done: AtomicBool,
+}
+
+static SPANS: [Span; Phase::ALL.len()] = [const {
+    Span {
+        start: AtomicU64::new(0),
+        end: AtomicU64::new(0),
+        started: AtomicBool::new(false),
+        done: AtomicBool::new(false),
+    }
+}; Phase::ALL.len()];
+
+/// Counter reading at kernel entry.
+static ENTRY: AtomicU64 = AtomicU64::new(0);
+
+/// Records the counter reading taken at kernel entry. Called once `.bss`
+/// has been cleared.
+pub fn record_entry(ticks: u64) {
+    ENTRY.store(ticks, Ordering::Relaxed);
+}
+
+fn begin(phase: Phase, ticks: u64) -> bool {
+    let span = &SPANS[phase as usize];
+    // Phases are timed by the boot hart alone.
+    if span.started.load(Ordering::Acquire) {
+        return false;
+    }
+    span.start.store(ticks, Ordering::Relaxed);
+    span.started.store(true, Ordering::Release);
+    true
+}
+
+fn end(phase: Phase, ticks: u64) {
+    let span = &SPANS[phase as usize];
+    span.end.store(ticks, Ordering::Relaxed);
+    span.done.store(true, Ordering::Release);
+}
+
+/// Records `phase` as running from counter reading `start` to `end_ticks`.
+pub fn record(phase: Phase, start: u64, end_ticks: u64) {
+    if begin(phase, start) {
+        end(phase, end_ticks);
+    }
+}
+
+/// Times `phase` until the returned guard is dropped.
+pub fn measure(phase: Phase) -> PhaseGuard {
+    PhaseGuard { phase: begin(phase, time::now_ticks()).then_some(phase) }
+}
+
+/// Ends the phase it was returned for when dropped.
+pub struct PhaseGuard {
+    /// `None` if the phase was already recorded.
+    phase: Option<Phase>,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        if let Some(phase) = self.phase {
+            end(phase, time::now_ticks());
+        }
+    }
+}
+
+/// When a phase ran, relative to kernel entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    pub start_us: u64,
+    /// `None` while the phase is still running.
+    pub duration_us: Option<u64>,
+}
+
+/// The phases timed so far.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BootReport {
+    /// Indexed like `Phase::ALL`; `None` for phases not started.
+    pub phases: [Option<PhaseTiming>; Phase::ALL.len()],
+    /// Time from kernel entry to the end of the last finished phase.
+    pub total_us: u64,
+}
+
+impl BootReport {
+    pub fn get(&self, phase: Phase) -> Option<PhaseTiming> {
+        self.phases[phase as usize]
+    }
+
+    /// Prints each phase with its start, its duration and its share of
+    /// the total, then the time spent outside the phases.
+    pub fn print(&self) {
+        println!("Boot timing (us since kernel entry):");
+        println!("  {:<10} {:>10} {:>10} {:>6}", "phase", "start", "time", "share");
+        let mut timed = 0;
+        for timing in self.phases.iter().flatten() {
+            match timing.duration_us {
+                Some(duration) => {
+                    timed += duration;
+                    let permille = (duration * 1000).checked_div(self.total_us).unwrap_or(0);
+                    println!("  {:<10} {:>10} {:>10} {:>3}.{}%", timing.phase.name(), timing.start_us, duration,
+                             permille / 10, permille % 10);
+                }
+                None => println!("  {:<10} {:>10} {:>10}", timing.phase.name(), timing.start_us, "running"),
+            }
+        }
+        println!("  {:<10} {:>10} {:>10}", "other", "", self.total_us.saturating_sub(timed));
+        println!("  {:<10} {:>10} {:>10}", "total", "", self.total_us);
+    }
+}
+
+/// Returns the phases timed so far.
+pub fn report() -> BootReport {
+    let entry = ENTRY.load(Ordering::Relaxed);
+    let mut report = BootReport { phases: [None; Phase::ALL.len()], total_us: 0 };
+    let mut last_end = entry;
+    for phase in Phase::ALL {
+        let span = &SPANS[phase as usize];
+        if !span.started.load(Ordering::Acquire) {
+            continue;
+        }
+        let start = span.start.load(Ordering::Relaxed);
+        let duration_us = span.done.load(Ordering::Acquire).then(|| {
+            let end = span.end.load(Ordering::Relaxed);
+            if time::ticks_before(last_end, end) {
+                last_end = end;
+            }
+            time::ticks_to_us(end.wrapping_sub(start))
+        });
+        report.phases[phase as usize] = Some(PhaseTiming {
+            phase,
+            start_us: time::ticks_to_us(start.wrapping_sub(entry)),
+            duration_us,
+        });
+    }
+    report.total_us = time::ticks_to_us(last_end.wrapping_sub(entry));
+    report
+}
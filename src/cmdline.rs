@@ -0,0 +1,64 @@
+// nt_rustos/src/cmdline.rs
+
+//! # Kernel Command Line
+//!
+//! The boot loader passes the command line in the `bootargs` property of
+//! `/chosen`; under QEMU it is the string given with `-append`. The line is
+//! a list of arguments separated by whitespace, each either `key=value` or
+//! a bare flag. A key given more than once takes its last value. Values
+//! cannot contain whitespace; there is no quoting.
+//!
+//! The line is read once during early boot and lives in the device tree,
+//! which is never freed, so the strings handed out are `'static`.
+
+use crate::boot;
+use crate::fdt::Fdt;
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use spin::Once;
+
+static CMDLINE: Once<&'static str> = Once::new();
+
+/// Splits `line` into its arguments: a key, and the value after the first
+/// `=` if there is one.
+pub fn parse(line: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    line.split_whitespace().map(|arg| match arg.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (arg, None),
+    })
+}
+
+/// Reads `/chosen/bootargs` from the boot device tree. Only the first call
+/// has an effect.
+pub fn init() -> &'static str {
+    CMDLINE.call_once(|| {
+        let fdt = boot::boot_info().and_then(|info| unsafe { Fdt::from_addr(info.dtb_addr) }.ok());
+        let bootargs = fdt.and_then(|fdt| fdt.find_node("chosen")?.property("bootargs")?.as_str());
+        bootargs.unwrap_or("").trim()
+    })
+}
+
+/// Returns the command line, empty before `init` or if there is none.
+pub fn get() -> &'static str {
+    CMDLINE.get().copied().unwrap_or("")
+}
+
+/// Returns the value of the last `key=value` argument.
+pub fn value(key: &str) -> Option<&'static str> {
+    parse(get()).filter(|(k, _)| *k == key).filter_map(|(_, v)| v).last()
+}
+
+/// Returns `true` if `key` is given, as a flag or with a value.
+pub fn has(key: &str) -> bool {
+    parse(get()).any(|(k, _)| k == key)
+}
+
+fn init_cmdline() -> InitResult {
+    let line = init();
+    if !line.is_empty() {
+        info_print!("Command line: {}", line);
+    }
+    Ok(())
+}
+
+crate::initcall!(early, 12, init_cmdline);
@@ -0,0 +1,519 @@
+// nt_rustos/src/config.rs
+
+//! # Persistent Configuration Store
+//!
+//! A small key-value store for kernel settings that must survive a reboot,
+//! such as log levels, the panic policy or which tests to run. Keys are
+//! short ASCII names like `log.level`; values are byte strings, usually
+//! text.
+//!
+//! The store lives on a `Storage`: a reserved region of a disk or a file.
+//! The storage is split into two slots, each holding a complete snapshot of
+//! the store: a header with a generation number and a CRC-32, followed by
+//! the entries. A change writes the new snapshot, with the next generation,
+//! to the slot not holding the current one and flushes the storage. Opening
+//! the store loads the valid slot with the highest generation, so a write
+//! torn by a crash or power loss leaves the previous snapshot in effect.
+//!
+//! At boot `init` looks for a store at the start of each writable disk and
+//! formats the first disk whose region is entirely zero, such as a freshly
+//! created image attached for this purpose. Without a store, `get` finds
+//! nothing and `set` fails with `NoStore`.
+
+use crate::block::{self, BlockError, Disk};
+use crate::fs::{self, Dentry, FsError, OpenFlags};
+use crate::init::initcall::InitResult;
+use crate::println;
+use crate::util::crc::crc32;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+const MAGIC: &[u8; 4] = b"NTKV";
+const VERSION: u16 = 1;
+/// Magic, version, flags, generation, payload length and CRC.
+const HEADER_SIZE: usize = 24;
+
+/// Longest key in bytes.
+pub const MAX_KEY: usize = 64;
+/// Longest value in bytes.
+pub const MAX_VALUE: usize = 1024;
+/// Size of the region `init` uses at the start of a disk.
+pub const REGION_SIZE: u64 = 64 * 1024;
+
+/// Errors returned by the configuration store.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// Keys are 1 to `MAX_KEY` printable ASCII characters without spaces.
+    InvalidKey,
+    /// The value is longer than `MAX_VALUE`.
+    ValueTooLong,
+    /// The entries do not fit in a slot.
+    NoSpace,
+    /// The storage is too small to hold two slots.
+    TooSmall,
+    /// Neither slot holds a snapshot.
+    NotFormatted,
+    /// A slot carries the magic but neither passes its checks.
+    Corrupted,
+    /// No store was found at boot.
+    NoStore,
+    /// The underlying disk failed.
+    Block(BlockError),
+    /// The underlying file failed.
+    Fs(FsError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKey => write!(f, "invalid configuration key"),
+            Self::ValueTooLong => write!(f, "configuration value too long"),
+            Self::NoSpace => write!(f, "configuration store full"),
+            Self::TooSmall => write!(f, "storage too small for a configuration store"),
+            Self::NotFormatted => write!(f, "no configuration store on the storage"),
+            Self::Corrupted => write!(f, "configuration store corrupted"),
+            Self::NoStore => write!(f, "no configuration store available"),
+            Self::Block(e) => write!(f, "{}", e),
+            Self::Fs(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<BlockError> for ConfigError {
+    fn from(e: BlockError) -> Self {
+        Self::Block(e)
+    }
+}
+
+impl From<FsError> for ConfigError {
+    fn from(e: FsError) -> Self {
+        Self::Fs(e)
+    }
+}
+
+/// Where a store keeps its slots.
+///
+/// Reads and writes start at multiples of `unit` and cover whole units.
+pub trait Storage: Send + Sync {
+    /// Size in bytes.
+    fn size(&self) -> u64;
+
+    /// Granularity of reads and writes in bytes.
+    fn unit(&self) -> usize;
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), ConfigError>;
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<(), ConfigError>;
+
+    /// Makes completed writes durable.
+    fn flush(&self) -> Result<(), ConfigError>;
+
+    /// Describes the storage for messages.
+    fn describe(&self) -> String;
+}
+
+/// A range of blocks of a disk. I/O bypasses the buffer cache, so a flush
+/// covers every write made.
+#[derive(Clone)]
+pub struct DiskRegion {
+    disk: Arc<Disk>,
+    start: u64,
+    blocks: u64,
+}
+
+impl DiskRegion {
+    /// Returns the `blocks` blocks of `disk` from `start`.
+    pub fn new(disk: Arc<Disk>, start: u64, blocks: u64) -> Result<Self, ConfigError> {
+        match start.checked_add(blocks) {
+            Some(end) if end <= disk.block_count() => Ok(Self { disk, start, blocks }),
+            _ => Err(ConfigError::Block(BlockError::OutOfRange)),
+        }
+    }
+}
+
+impl Storage for DiskRegion {
+    fn size(&self) -> u64 {
+        self.blocks * self.disk.block_size() as u64
+    }
+
+    fn unit(&self) -> usize {
+        self.disk.block_size()
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), ConfigError> {
+        Ok(self.disk.read(self.start + offset / self.unit() as u64, buf)?)
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<(), ConfigError> {
+        Ok(self.disk.write(self.start + offset / self.unit() as u64, buf)?)
+    }
+
+    fn flush(&self) -> Result<(), ConfigError> {
+        Ok(self.disk.flush()?)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} blocks {}..{}", self.disk.name(), self.start, self.start + self.blocks)
+    }
+}
+
+/// A file of a writable filesystem.
+pub struct FileStorage {
+    dentry: Arc<Dentry>,
+}
+
+impl FileStorage {
+    /// Opens the file at `path`, creating it with `size` zero bytes if it
+    /// does not exist.
+    pub fn open(path: &str, size: u64) -> Result<Self, ConfigError> {
+        let dentry = match fs::lookup(path) {
+            Ok(dentry) => dentry,
+            Err(FsError::NotFound) => {
+                fs::open(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE)?;
+                let dentry = fs::lookup(path)?;
+                dentry.inode().truncate(size)?;
+                dentry
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if dentry.mount().is_read_only() {
+            return Err(FsError::ReadOnly.into());
+        }
+        Ok(Self { dentry })
+    }
+}
+
+impl Storage for FileStorage {
+    fn size(&self) -> u64 {
+        self.dentry.inode().metadata().size
+    }
+
+    fn unit(&self) -> usize {
+        1
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), ConfigError> {
+        let mut done = 0;
+        while done < buf.len() {
+            match self.dentry.inode().read_at(offset + done as u64, &mut buf[done..])? {
+                0 => return Err(FsError::Corrupted.into()),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<(), ConfigError> {
+        let mut done = 0;
+        while done < buf.len() {
+            match self.dentry.inode().write_at(offset + done as u64, &buf[done..])? {
+                0 => return Err(FsError::NoSpace.into()),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), ConfigError> {
+        Ok(self.dentry.mount().fs().sync()?)
+    }
+
+    fn describe(&self) -> String {
+        String::from(self.dentry.path())
+    }
+}
+
+/// Checks that `key` may be stored.
+fn check_key(key: &str) -> Result<(), ConfigError> {
+    if key.is_empty() || key.len() > MAX_KEY || !key.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(ConfigError::InvalidKey);
+    }
+    Ok(())
+}
+
+/// Encodes a snapshot: header, then per entry the key length (1 byte),
+/// value length (2 bytes), key and value.
+fn encode(generation: u64, entries: &BTreeMap<String, Vec<u8>>) -> Vec<u8> {
+    let mut image = vec![0u8; HEADER_SIZE];
+    for (key, value) in entries {
+        image.push(key.len() as u8);
+        image.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        image.extend_from_slice(key.as_bytes());
+        image.extend_from_slice(value);
+    }
+    let length = (image.len() - HEADER_SIZE) as u32;
+    image[..4].copy_from_slice(MAGIC);
+    image[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    image[8..16].copy_from_slice(&generation.to_le_bytes());
+    image[16..20].copy_from_slice(&length.to_le_bytes());
+    // The CRC covers the header before it and the entries after it.
+    let crc = crc32(&[&image[..20], &image[HEADER_SIZE..]].concat());
+    image[20..24].copy_from_slice(&crc.to_le_bytes());
+    image
+}
+
+/// What a slot holds.
+enum Slot {
+    /// No magic: never written, or erased.
+    Empty,
+    /// The magic, but a bad header or CRC.
+    Invalid,
+    Valid(u64, BTreeMap<String, Vec<u8>>),
+}
+
+/// Decodes the snapshot at the start of `slot`.
+fn decode(slot: &[u8]) -> Slot {
+    if &slot[..4] != MAGIC {
+        return Slot::Empty;
+    }
+    let field = |range: core::ops::Range<usize>| &slot[range];
+    let version = u16::from_le_bytes(field(4..6).try_into().unwrap());
+    let generation = u64::from_le_bytes(field(8..16).try_into().unwrap());
+    let length = u32::from_le_bytes(field(16..20).try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(field(20..24).try_into().unwrap());
+    let Some(payload) = slot.get(HEADER_SIZE..HEADER_SIZE + length) else {
+        return Slot::Invalid;
+    };
+    if version != VERSION || crc32(&[&slot[..20], payload].concat()) != crc {
+        return Slot::Invalid;
+    }
+    let mut entries = BTreeMap::new();
+    let mut pos = 0;
+    while pos < payload.len() {
+        let Some(lengths) = payload.get(pos..pos + 3) else {
+            return Slot::Invalid;
+        };
+        let key_len = lengths[0] as usize;
+        let value_len = u16::from_le_bytes([lengths[1], lengths[2]]) as usize;
+        let start = pos + 3;
+        let (Some(key), Some(value)) =
+            (payload.get(start..start + key_len), payload.get(start + key_len..start + key_len + value_len))
+        else {
+            return Slot::Invalid;
+        };
+        let Ok(key) = core::str::from_utf8(key) else {
+            return Slot::Invalid;
+        };
+        entries.insert(String::from(key), value.to_vec());
+        pos = start + key_len + value_len;
+    }
+    Slot::Valid(generation, entries)
+}
+
+/// A configuration store on a storage.
+pub struct Store {
+    storage: Box<dyn Storage>,
+    slot_size: u64,
+    entries: BTreeMap<String, Vec<u8>>,
+    generation: u64,
+    /// Slot holding the current snapshot.
+    slot: usize,
+}
+
+impl Store {
+    /// Returns the slot size for `storage`: half of it, in whole units.
+    fn slot_size(storage: &dyn Storage) -> Result<u64, ConfigError> {
+        let unit = storage.unit() as u64;
+        let slot_size = storage.size() / 2 / unit * unit;
+        if slot_size < HEADER_SIZE as u64 {
+            return Err(ConfigError::TooSmall);
+        }
+        Ok(slot_size)
+    }
+
+    /// Opens the store on `storage`, loading the newest valid snapshot.
+    pub fn open(storage: Box<dyn Storage>) -> Result<Self, ConfigError> {
+        let slot_size = Self::slot_size(&*storage)?;
+        let mut best: Option<(u64, usize, BTreeMap<String, Vec<u8>>)> = None;
+        let mut invalid = false;
+        let mut buf = vec![0u8; slot_size as usize];
+        for slot in 0..2 {
+            storage.read(slot as u64 * slot_size, &mut buf)?;
+            match decode(&buf) {
+                Slot::Empty => {}
+                Slot::Invalid => invalid = true,
+                Slot::Valid(generation, entries) => {
+                    if best.as_ref().is_none_or(|(g, _, _)| generation > *g) {
+                        best = Some((generation, slot, entries));
+                    }
+                }
+            }
+        }
+        match best {
+            Some((generation, slot, entries)) => Ok(Self { storage, slot_size, entries, generation, slot }),
+            None if invalid => Err(ConfigError::Corrupted),
+            None => Err(ConfigError::NotFormatted),
+        }
+    }
+
+    /// Creates an empty store on `storage`, replacing anything on it.
+    pub fn format(storage: Box<dyn Storage>) -> Result<Self, ConfigError> {
+        let slot_size = Self::slot_size(&*storage)?;
+        // Erase the second slot first, so a crash leaves no old snapshot
+        // behind the new one.
+        let unit = vec![0u8; storage.unit()];
+        storage.write(slot_size, &unit)?;
+        let mut store = Self { storage, slot_size, entries: BTreeMap::new(), generation: 0, slot: 1 };
+        store.commit(BTreeMap::new())?;
+        Ok(store)
+    }
+
+    /// Writes `entries` as the next snapshot, to the other slot. The store
+    /// is unchanged if that fails.
+    fn commit(&mut self, entries: BTreeMap<String, Vec<u8>>) -> Result<(), ConfigError> {
+        let generation = self.generation + 1;
+        let mut image = encode(generation, &entries);
+        if image.len() as u64 > self.slot_size {
+            return Err(ConfigError::NoSpace);
+        }
+        image.resize(image.len().next_multiple_of(self.storage.unit()), 0);
+        let slot = 1 - self.slot;
+        self.storage.write(slot as u64 * self.slot_size, &image)?;
+        self.storage.flush()?;
+        self.entries = entries;
+        self.generation = generation;
+        self.slot = slot;
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(Vec::as_slice)
+    }
+
+    /// Returns the value of `key` if it is UTF-8 text.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        core::str::from_utf8(self.get(key)?).ok()
+    }
+
+    /// Sets `key` to `value` and writes the store.
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), ConfigError> {
+        check_key(key)?;
+        if value.len() > MAX_VALUE {
+            return Err(ConfigError::ValueTooLong);
+        }
+        if self.get(key) == Some(value) {
+            return Ok(());
+        }
+        let mut entries = self.entries.clone();
+        entries.insert(String::from(key), value.to_vec());
+        self.commit(entries)
+    }
+
+    /// Removes `key` and writes the store. Returns whether it was set.
+    pub fn remove(&mut self, key: &str) -> Result<bool, ConfigError> {
+        if !self.entries.contains_key(key) {
+            return Ok(false);
+        }
+        let mut entries = self.entries.clone();
+        entries.remove(key);
+        self.commit(entries).map(|_| true)
+    }
+
+    /// Returns the keys, in order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Returns the number of snapshots written since formatting.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Describes the storage for messages.
+    pub fn describe(&self) -> String {
+        self.storage.describe()
+    }
+}
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+/// Returns whether `region` holds only zeros.
+fn is_blank(region: &DiskRegion) -> Result<bool, ConfigError> {
+    let mut buf = vec![0u8; region.size() as usize];
+    region.read(0, &mut buf)?;
+    Ok(buf.iter().all(|&b| b == 0))
+}
+
+/// Opens the store on the first writable disk holding one, or formats the
+/// first blank one. Returns the storage used, or `None` if there is none.
+pub fn init() -> Result<Option<String>, ConfigError> {
+    let mut blank = None;
+    for disk in block::disks() {
+        let blocks = REGION_SIZE / disk.block_size() as u64;
+        if disk.is_read_only() || disk.block_count() < blocks {
+            continue;
+        }
+        let region = DiskRegion::new(disk, 0, blocks)?;
+        match Store::open(Box::new(region.clone())) {
+            Ok(store) => return Ok(Some(install(store))),
+            Err(ConfigError::NotFormatted) if blank.is_none() && is_blank(&region)? => blank = Some(region),
+            Err(_) => {}
+        }
+    }
+    match blank {
+        Some(region) => Ok(Some(install(Store::format(Box::new(region))?))),
+        None => Ok(None),
+    }
+}
+
+/// Opens the store on a writable disk once the disks are known.
+fn open_at_boot() -> InitResult {
+    match init() {
+        Ok(Some(storage)) => crate::info_print!("Configuration store on {}.", storage),
+        Ok(None) => crate::info_print!("No configuration store; settings will not persist."),
+        Err(e) => return Err(format!("failed to open the configuration store: {}", e)),
+    }
+    Ok(())
+}
+
+crate::initcall!(device, 30, open_at_boot);
+
+/// Makes `store` the kernel's store and returns its description.
+pub fn install(store: Store) -> String {
+    let description = store.describe();
+    *STORE.lock() = Some(store);
+    description
+}
+
+/// Returns the value of `key` in the kernel's store.
+pub fn get(key: &str) -> Option<Vec<u8>> {
+    STORE.lock().as_ref()?.get(key).map(<[u8]>::to_vec)
+}
+
+/// Returns the value of `key` in the kernel's store if it is UTF-8 text.
+pub fn get_str(key: &str) -> Option<String> {
+    STORE.lock().as_ref()?.get_str(key).map(String::from)
+}
+
+/// Sets `key` in the kernel's store and writes it.
+pub fn set(key: &str, value: &[u8]) -> Result<(), ConfigError> {
+    STORE.lock().as_mut().ok_or(ConfigError::NoStore)?.set(key, value)
+}
+
+/// Removes `key` from the kernel's store. Returns whether it was set.
+pub fn remove(key: &str) -> Result<bool, ConfigError> {
+    STORE.lock().as_mut().ok_or(ConfigError::NoStore)?.remove(key)
+}
+
+/// Prints the kernel's store.
+pub fn print() {
+    let store = STORE.lock();
+    let Some(store) = store.as_ref() else {
+        println!("Configuration: no store");
+        return;
+    };
+    println!("Configuration ({}, generation {}):", store.describe(), store.generation());
+    for key in store.keys() {
+        match store.get_str(key) {
+            Some(text) => println!("  {} = {}", key, text),
+            None => println!("  {} = <{} bytes>", key, store.get(key).map_or(0, <[u8]>::len)),
+        }
+    }
+}
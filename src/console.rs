@@ -1,8 +1,137 @@
 // 控制台输出模块
-// 使用封装的SBI API实现控制台功能
+// 输出经由可替换的控制台后端，默认使用封装的SBI API；
+// 另可注册附加输出 (如图形控制台)，它们收到同样的输出但不提供输入。
+// 宿主机测试 (host-test) 时默认后端改为标准输出
 
 use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(not(feature = "host-test"))]
 use crate::util::sbi;
+use spin::RwLock;
+
+/// 控制台驱动接口
+///
+/// 驱动探测成功后可通过 `register_backend` 接管控制台输出。
+/// 实现必须能在中断上下文中调用，且不能在输出时打印。
+pub trait ConsoleDriver: Sync {
+    /// 驱动名称
+    fn name(&self) -> &'static str;
+
+    /// 输出字符串
+    fn write_str(&self, s: &str);
+
+    /// 读取一个输入字节 (非阻塞)，不支持输入时返回None
+    fn read_byte(&self) -> Option<u8> {
+        None
+    }
+}
+
+/// 基于SBI legacy控制台调用的默认后端
+#[cfg(not(feature = "host-test"))]
+struct SbiConsole;
+
+#[cfg(not(feature = "host-test"))]
+impl ConsoleDriver for SbiConsole {
+    fn name(&self) -> &'static str {
+        "sbi"
+    }
+
+    fn write_str(&self, s: &str) {
+        let _ = sbi::console::puts(s);
+    }
+}
+
+#[cfg(not(feature = "host-test"))]
+static DEFAULT_CONSOLE: SbiConsole = SbiConsole;
+#[cfg(feature = "host-test")]
+static DEFAULT_CONSOLE: crate::host::StdoutConsole = crate::host::StdoutConsole;
+
+/// 当前控制台后端
+static BACKEND: RwLock<&'static dyn ConsoleDriver> = RwLock::new(&DEFAULT_CONSOLE as &dyn ConsoleDriver);
+
+/// 附加输出的最大数量
+const MAX_SINKS: usize = 4;
+
+/// 附加输出：与后端一起收到所有控制台输出
+static SINKS: RwLock<[Option<&'static dyn ConsoleDriver>; MAX_SINKS]> = RwLock::new([None; MAX_SINKS]);
+
+/// 注册附加输出，已满或同名输出已存在时返回false
+pub fn register_sink(driver: &'static dyn ConsoleDriver) -> bool {
+    let mut sinks = SINKS.write();
+    if sinks.iter().flatten().any(|s| s.name() == driver.name()) {
+        return false;
+    }
+    match sinks.iter_mut().find(|s| s.is_none()) {
+        Some(slot) => {
+            *slot = Some(driver);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 注销名为 `name` 的附加输出
+pub fn unregister_sink(name: &str) {
+    for slot in SINKS.write().iter_mut() {
+        if slot.is_some_and(|s| s.name() == name) {
+            *slot = None;
+        }
+    }
+}
+
+/// 已注册附加输出的名称
+pub fn sink_names() -> impl Iterator<Item = &'static str> {
+    let sinks = *SINKS.read();
+    sinks.into_iter().flatten().map(|s| s.name())
+}
+
+/// 将控制台输出切换到 `driver`
+pub fn register_backend(driver: &'static dyn ConsoleDriver) {
+    *BACKEND.write() = driver;
+}
+
+/// 恢复默认的SBI控制台后端
+pub fn reset_backend() {
+    register_backend(&DEFAULT_CONSOLE);
+}
+
+/// 当前控制台后端；后端正在切换时退回SBI控制台
+pub fn backend() -> &'static dyn ConsoleDriver {
+    match BACKEND.try_read() {
+        Some(backend) => *backend,
+        None => &DEFAULT_CONSOLE,
+    }
+}
+
+/// 当前控制台后端的名称
+pub fn backend_name() -> &'static str {
+    backend().name()
+}
+
+/// 日志级别：只输出错误
+pub const LOG_ERROR: u8 = 1;
+/// 日志级别：输出错误与警告
+pub const LOG_WARN: u8 = 2;
+/// 日志级别：输出错误、警告与信息 (默认)
+pub const LOG_INFO: u8 = 3;
+
+/// 当前日志级别，error_print/warn_print/info_print按它过滤，print不受影响
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LOG_INFO);
+
+/// 设置日志级别
+pub fn set_log_level(level: u8) {
+    LOG_LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// 当前日志级别
+pub fn log_level() -> u8 {
+    LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// 给定级别的日志是否输出
+pub fn log_enabled(level: u8) -> bool {
+    level <= log_level()
+}
 
 /// 格式化输出函数
 pub fn print(args: fmt::Arguments) {
@@ -10,29 +139,40 @@ pub fn print(args: fmt::Arguments) {
     Stdout.write_fmt(args).unwrap();
 }
 
-/// 直接输出字符串
+/// 直接输出字符串，同时写入各附加输出；附加输出正在注册或注销时跳过它们
 pub fn print_str(s: &str) {
-    let _ = sbi::console::puts(s);
+    backend().write_str(s);
+    if let Some(sinks) = SINKS.try_read() {
+        for sink in sinks.iter().flatten() {
+            sink.write_str(s);
+        }
+    }
 }
 
 /// 输出单个字符
 pub fn print_char(ch: char) {
-    let _ = sbi::console::putchar(ch);
+    let mut buf = [0u8; 4];
+    print_str(ch.encode_utf8(&mut buf));
 }
 
 /// 输出十进制数字
 pub fn print_num(num: usize) {
-    let _ = sbi::console::putnum(num, 10);
+    print(format_args!("{}", num));
 }
 
 /// 输出十六进制数字
 pub fn print_hex(num: usize) {
-    let _ = sbi::console::putnum(num, 16);
+    print(format_args!("{:x}", num));
 }
 
 /// 输出八进制数字
 pub fn print_oct(num: usize) {
-    let _ = sbi::console::putnum(num, 8);
+    print(format_args!("{:o}", num));
+}
+
+/// 读取一个输入字节 (非阻塞)
+pub fn getchar() -> Option<u8> {
+    backend().read_byte()
 }
 
 /// 标准输出结构体，实现Write trait以支持格式化输出
@@ -77,9 +217,11 @@ macro_rules! debug_print {
 #[macro_export]
 macro_rules! error_print {
     ($($arg:tt)*) => {{
-        $crate::print!("\x1b[31m[ERROR] ");
-        $crate::print!($($arg)*);
-        $crate::print!("\x1b[0m\n");
+        if $crate::console::log_enabled($crate::console::LOG_ERROR) {
+            $crate::print!("\x1b[31m[ERROR] ");
+            $crate::print!($($arg)*);
+            $crate::print!("\x1b[0m\n");
+        }
     }};
 }
 
@@ -87,9 +229,11 @@ macro_rules! error_print {
 #[macro_export]
 macro_rules! warn_print {
     ($($arg:tt)*) => {{
-        $crate::print!("\x1b[33m[WARN] ");
-        $crate::print!($($arg)*);
-        $crate::print!("\x1b[0m\n");
+        if $crate::console::log_enabled($crate::console::LOG_WARN) {
+            $crate::print!("\x1b[33m[WARN] ");
+            $crate::print!($($arg)*);
+            $crate::print!("\x1b[0m\n");
+        }
     }};
 }
 
@@ -97,8 +241,10 @@ macro_rules! warn_print {
 #[macro_export]
 macro_rules! info_print {
     ($($arg:tt)*) => {{
-        $crate::print!("\x1b[32m[INFO] ");
-        $crate::print!($($arg)*);
-        $crate::print!("\x1b[0m\n");
+        if $crate::console::log_enabled($crate::console::LOG_INFO) {
+            $crate::print!("\x1b[32m[INFO] ");
+            $crate::print!($($arg)*);
+            $crate::print!("\x1b[0m\n");
+        }
     }};
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+// nt_rustos/src/coverage.rs
+
+//! # Coverage Counters
+//!
+//! Lightweight hit counters that show what the in-kernel tests actually
+//! exercise. Code is instrumented by hand with `cov!`: `cov!("alloc")` at
+//! the top of a function counts calls to it, `cov!("alloc", "zero_size")`
+//! on a branch counts how often that branch was taken. Each point is a
+//! static `CovPoint` placed in the `.kernel_cov` linker section, which the
+//! linker script collects between `skernel_cov` and `ekernel_cov`, so the
+//! report knows every point, including those never reached.
+//!
+//! Counting is compiled in only with the `coverage` feature; without it,
+//! and in host tests, `cov!` expands to nothing. The test runner clears
+//! the counters before the tests and prints `print_report` afterwards: the
+//! functions and branches the tests never reached. With `coverage=dump` on
+//! the command line it also prints every counter with `dump`, one line
+//! each, for tools that merge runs.
+
+use crate::println;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// One instrumented location and its hit count.
+pub struct CovPoint {
+    module: &'static str,
+    function: &'static str,
+    /// `None` for a function entry.
+    branch: Option<&'static str>,
+    file: &'static str,
+    line: u32,
+    hits: AtomicU64,
+}
+
+impl CovPoint {
+    /// Used by `cov!`.
+    pub const fn new(
+        module: &'static str,
+        function: &'static str,
+        branch: Option<&'static str>,
+        file: &'static str,
+        line: u32,
+    ) -> Self {
+        Self { module, function, branch, file, line, hits: AtomicU64::new(0) }
+    }
+
+    #[inline(always)]
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn module(&self) -> &'static str {
+        self.module
+    }
+
+    pub fn function(&self) -> &'static str {
+        self.function
+    }
+
+    pub fn branch(&self) -> Option<&'static str> {
+        self.branch
+    }
+
+    /// Returns whether the point marks a function entry.
+    pub fn is_function(&self) -> bool {
+        self.branch.is_none()
+    }
+}
+
+/// Counts a pass through the enclosing function (`cov!("name")`) or
+/// through one of its branches (`cov!("name", "branch")`).
+///
+/// ```ignore
+/// pub fn alloc(size: usize) -> Option<*mut u8> {
+///     crate::cov!("alloc");
+///     if size == 0 {
+///         crate::cov!("alloc", "zero_size");
+///         return None;
+///     }
+///     ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! cov {
+    ($function:literal) => {
+        $crate::cov!(@point $function, None)
+    };
+    ($function:literal, $branch:literal) => {
+        $crate::cov!(@point $function, Some($branch))
+    };
+    (@point $function:literal, $branch:expr) => {{
+        #[cfg(all(feature = "coverage", not(feature = "host-test")))]
+        {
+            #[used]
+            #[link_section = ".kernel_cov"]
+            static POINT: $crate::coverage::CovPoint =
+                $crate::coverage::CovPoint::new(module_path!(), $function, $branch, file!(), line!());
+            POINT.hit();
+        }
+    }};
+}
+
+/// Returns every instrumented point, in link order.
+#[cfg(not(feature = "host-test"))]
+pub fn points() -> &'static [CovPoint] {
+    extern "C" {
+        static skernel_cov: u8;
+        static ekernel_cov: u8;
+    }
+    unsafe {
+        let start = core::ptr::addr_of!(skernel_cov).cast::<CovPoint>();
+        let end = core::ptr::addr_of!(ekernel_cov).cast::<CovPoint>();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Host tests have no linker script to collect the points, and count none.
+#[cfg(feature = "host-test")]
+pub fn points() -> &'static [CovPoint] {
+    &[]
+}
+
+/// Returns whether the kernel was built with counters.
+pub fn is_enabled() -> bool {
+    cfg!(feature = "coverage")
+}
+
+/// Clears every counter, so a report covers only what ran afterwards.
+pub fn reset() {
+    for point in points() {
+        point.hits.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Points reached and in total, for function entries and for branches.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub functions_hit: usize,
+    pub functions: usize,
+    pub branches_hit: usize,
+    pub branches: usize,
+}
+
+impl Summary {
+    /// Returns the covered share of `hit` out of `total`, in percent.
+    fn percent(hit: usize, total: usize) -> usize {
+        (hit * 100).checked_div(total).unwrap_or(100)
+    }
+}
+
+/// Counts the points reached since the last `reset`.
+pub fn summary() -> Summary {
+    let mut summary = Summary::default();
+    for point in points() {
+        let hit = (point.hits() > 0) as usize;
+        if point.is_function() {
+            summary.functions += 1;
+            summary.functions_hit += hit;
+        } else {
+            summary.branches += 1;
+            summary.branches_hit += hit;
+        }
+    }
+    summary
+}
+
+/// Prints the coverage totals and every function and branch with no hits.
+pub fn print_report() {
+    let summary = summary();
+    println!("=== Coverage ===");
+    println!(
+        "Functions: {}/{} ({}%), branches: {}/{} ({}%)",
+        summary.functions_hit,
+        summary.functions,
+        Summary::percent(summary.functions_hit, summary.functions),
+        summary.branches_hit,
+        summary.branches,
+        Summary::percent(summary.branches_hit, summary.branches)
+    );
+    if summary.functions_hit < summary.functions {
+        println!("Untouched functions:");
+        for point in points().iter().filter(|p| p.is_function() && p.hits() == 0) {
+            println!("  {}::{} ({}:{})", point.module, point.function, point.file, point.line);
+        }
+    }
+    if summary.branches_hit < summary.branches {
+        println!("Untaken branches:");
+        for point in points().iter().filter(|p| !p.is_function() && p.hits() == 0) {
+            println!(
+                "  {}::{} [{}] ({}:{})",
+                point.module,
+                point.function,
+                point.branch.unwrap_or(""),
+                point.file,
+                point.line
+            );
+        }
+    }
+    println!("================");
+}
+
+/// Prints every counter as `cov <hits> <module>::<function>[#<branch>] <file>:<line>`.
+pub fn dump() {
+    for point in points() {
+        match point.branch {
+            Some(branch) => println!(
+                "cov {} {}::{}#{} {}:{}",
+                point.hits(),
+                point.module,
+                point.function,
+                branch,
+                point.file,
+                point.line
+            ),
+            None => println!("cov {} {}::{} {}:{}", point.hits(), point.module, point.function, point.file, point.line),
+        }
+    }
+}
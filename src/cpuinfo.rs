@@ -0,0 +1,412 @@
+// nt_rustos/src/cpuinfo.rs
+
+//! # CPU Information
+//!
+//! The ISA of every hart, from the `riscv,isa-extensions` list or the
+//! `riscv,isa` string of its cpu node in the boot device tree, and the
+//! machine IDs the SBI reports for the boot hart.
+//!
+//! Code that depends on an extension asks `has_letter` or `has_extension`,
+//! which answer for the ISA all enabled harts share, since a thread may run
+//! on any of them. Without a device tree that describes the harts, the ISA
+//! falls back to `rv64gc`, QEMU's default CPU.
+//!
+//! Only the extensions in `Extension` are tracked; other multi-letter names
+//! are ignored. Harts with an ID of `MAX_HARTS` or above are not recorded.
+
+use crate::boot;
+use crate::fdt::{Fdt, Node};
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use crate::println;
+use crate::task::MAX_HARTS;
+use crate::util::sbi;
+use core::fmt;
+use spin::Once;
+
+/// Multi-letter extensions the kernel knows about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Extension {
+    Zicsr,
+    Zifencei,
+    Zicntr,
+    Zihpm,
+    Zihintpause,
+    Zicbom,
+    Zicboz,
+    Zba,
+    Zbb,
+    Zbs,
+    /// Supervisor timer compare (`stimecmp`).
+    Sstc,
+    Sscofpmf,
+    Svinval,
+    Svnapot,
+    Svpbmt,
+    Svadu,
+}
+
+impl Extension {
+    pub const ALL: [Extension; 16] = [
+        Extension::Zicsr,
+        Extension::Zifencei,
+        Extension::Zicntr,
+        Extension::Zihpm,
+        Extension::Zihintpause,
+        Extension::Zicbom,
+        Extension::Zicboz,
+        Extension::Zba,
+        Extension::Zbb,
+        Extension::Zbs,
+        Extension::Sstc,
+        Extension::Sscofpmf,
+        Extension::Svinval,
+        Extension::Svnapot,
+        Extension::Svpbmt,
+        Extension::Svadu,
+    ];
+
+    /// Returns the name in lower case, as ISA strings spell it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::Zicsr => "zicsr",
+            Extension::Zifencei => "zifencei",
+            Extension::Zicntr => "zicntr",
+            Extension::Zihpm => "zihpm",
+            Extension::Zihintpause => "zihintpause",
+            Extension::Zicbom => "zicbom",
+            Extension::Zicboz => "zicboz",
+            Extension::Zba => "zba",
+            Extension::Zbb => "zbb",
+            Extension::Zbs => "zbs",
+            Extension::Sstc => "sstc",
+            Extension::Sscofpmf => "sscofpmf",
+            Extension::Svinval => "svinval",
+            Extension::Svnapot => "svnapot",
+            Extension::Svpbmt => "svpbmt",
+            Extension::Svadu => "svadu",
+        }
+    }
+
+    /// Looks up an extension by name, in any case.
+    pub fn from_name(name: &str) -> Option<Extension> {
+        Self::ALL.iter().copied().find(|e| e.name().eq_ignore_ascii_case(name))
+    }
+
+    fn bit(&self) -> u32 {
+        1 << *self as u32
+    }
+}
+
+/// Skips a version at the start of `s`: digits, optionally followed by `p`
+/// and more digits.
+fn skip_version(s: &str) -> &str {
+    let rest = s.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == s.len() {
+        return s;
+    }
+    match rest.strip_prefix(['p', 'P']) {
+        Some(minor) if minor.starts_with(|c: char| c.is_ascii_digit()) => {
+            minor.trim_start_matches(|c: char| c.is_ascii_digit())
+        }
+        _ => rest,
+    }
+}
+
+/// Removes a version such as `2p0` from the end of an extension name.
+fn strip_version(name: &str) -> &str {
+    let rest = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == name.len() {
+        return name;
+    }
+    match rest.strip_suffix(['p', 'P']) {
+        Some(major) if major.ends_with(|c: char| c.is_ascii_digit()) => {
+            major.trim_end_matches(|c: char| c.is_ascii_digit())
+        }
+        _ => rest,
+    }
+}
+
+/// Single-letter extensions in the order ISA strings list them.
+const CANONICAL_ORDER: &str = "iemafdqlcbkjtpvh";
+
+/// The extensions a hart implements.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Isa {
+    /// 32 or 64; zero if unknown.
+    pub xlen: u32,
+    /// Bit `n` for the single-letter extension `'a' + n`.
+    letters: u32,
+    /// Bits of `Extension`s.
+    extensions: u32,
+}
+
+impl Isa {
+    /// Parses an ISA string such as `rv64imafdc_zicsr_zifencei_sstc`.
+    /// Version numbers (`rv64i2p1m2p0`) are skipped and `g` stands for
+    /// `imafd_zicsr_zifencei`.
+    pub fn parse(isa: &str) -> Isa {
+        let mut result = Isa::default();
+        let mut parts = isa.split('_');
+        let first = parts.next().unwrap_or("");
+        let rest = match first.get(..4).map(|p| p.to_ascii_lowercase()) {
+            Some(prefix) if prefix == "rv64" => {
+                result.xlen = 64;
+                &first[4..]
+            }
+            Some(prefix) if prefix == "rv32" => {
+                result.xlen = 32;
+                &first[4..]
+            }
+            _ => return result,
+        };
+        // Single letters, each possibly followed by a version.
+        let mut letters = rest;
+        while let Some(c) = letters.chars().next() {
+            result.add_letter(c.to_ascii_lowercase());
+            letters = skip_version(&letters[c.len_utf8()..]);
+        }
+        for part in parts {
+            result.add_name(strip_version(part));
+        }
+        result
+    }
+
+    /// Reads the ISA of a cpu node: its `riscv,isa-extensions` list, or
+    /// failing that its `riscv,isa` string.
+    pub fn from_node(cpu: &Node) -> Isa {
+        if let Some(extensions) = cpu.property("riscv,isa-extensions") {
+            let base = cpu.property("riscv,isa-base").and_then(|p| p.as_str()).unwrap_or("");
+            let mut result = Isa::parse(base);
+            if result.xlen == 0 {
+                result.xlen = Isa::parse(cpu.property("riscv,isa").and_then(|p| p.as_str()).unwrap_or("")).xlen;
+            }
+            for name in extensions.as_str_list() {
+                result.add_name(name);
+            }
+            return result;
+        }
+        Isa::parse(cpu.property("riscv,isa").and_then(|p| p.as_str()).unwrap_or(""))
+    }
+
+    fn add_letter(&mut self, c: char) {
+        if c == 'g' {
+            for c in "imafd".chars() {
+                self.add_letter(c);
+            }
+            self.extensions |= Extension::Zicsr.bit() | Extension::Zifencei.bit();
+        } else if c.is_ascii_lowercase() {
+            self.letters |= 1 << (c as u32 - 'a' as u32);
+        }
+    }
+
+    /// Adds an extension given by name, single-letter or not.
+    fn add_name(&mut self, name: &str) {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => self.add_letter(c.to_ascii_lowercase()),
+            _ => {
+                if let Some(extension) = Extension::from_name(name) {
+                    self.extensions |= extension.bit();
+                }
+            }
+        }
+    }
+
+    /// Returns whether the single-letter extension `c` is present, such as
+    /// `'c'` for compressed instructions or `'d'` for double precision.
+    pub fn has_letter(&self, c: char) -> bool {
+        let c = c.to_ascii_lowercase();
+        c.is_ascii_lowercase() && self.letters & (1 << (c as u32 - 'a' as u32)) != 0
+    }
+
+    pub fn has(&self, extension: Extension) -> bool {
+        self.extensions & extension.bit() != 0
+    }
+
+    /// Returns the extensions both ISAs have.
+    pub fn intersect(&self, other: &Isa) -> Isa {
+        Isa {
+            xlen: if self.xlen == other.xlen { self.xlen } else { 0 },
+            letters: self.letters & other.letters,
+            extensions: self.extensions & other.extensions,
+        }
+    }
+}
+
+impl fmt::Display for Isa {
+    /// Writes the ISA in canonical form, `rv64imafdc_zicsr_...`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv{}", self.xlen)?;
+        for c in CANONICAL_ORDER.chars() {
+            if self.has_letter(c) {
+                write!(f, "{}", c)?;
+            }
+        }
+        for c in ('a'..='z').filter(|&c| !CANONICAL_ORDER.contains(c) && self.has_letter(c)) {
+            write!(f, "{}", c)?;
+        }
+        for extension in Extension::ALL.iter().filter(|e| self.has(**e)) {
+            write!(f, "_{}", extension.name())?;
+        }
+        Ok(())
+    }
+}
+
+/// One hart's cpu node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HartInfo {
+    pub hart_id: usize,
+    pub enabled: bool,
+    pub isa: Isa,
+    /// The `riscv,isa` string as the device tree gives it.
+    pub isa_string: Option<&'static str>,
+    /// The `mmu-type`, such as `riscv,sv39`.
+    pub mmu: Option<&'static str>,
+}
+
+/// Machine and SBI implementation IDs, from the SBI base extension.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MachineIds {
+    pub mvendorid: usize,
+    pub marchid: usize,
+    pub mimpid: usize,
+    pub sbi_spec_version: usize,
+    pub sbi_impl_id: usize,
+    pub sbi_impl_version: usize,
+}
+
+impl MachineIds {
+    /// Asks the SBI; IDs it does not report are zero.
+    pub fn read() -> Self {
+        let id = |result: sbi::SbiResult| result.unwrap_or(0);
+        Self {
+            mvendorid: id(sbi::base::get_mvendorid()),
+            marchid: id(sbi::base::get_marchid()),
+            mimpid: id(sbi::base::get_mimpid()),
+            sbi_spec_version: id(sbi::base::get_spec_version()),
+            sbi_impl_id: id(sbi::base::get_impl_id()),
+            sbi_impl_version: id(sbi::base::get_impl_version()),
+        }
+    }
+}
+
+/// What QEMU's default CPU implements, for when the harts are not described.
+pub fn fallback_isa() -> Isa {
+    Isa::parse("rv64gc")
+}
+
+/// The harts and machine IDs.
+#[derive(Debug, Copy, Clone)]
+pub struct CpuInfo {
+    harts: [Option<HartInfo>; MAX_HARTS],
+    pub ids: MachineIds,
+}
+
+impl CpuInfo {
+    /// Reads the cpu nodes of `fdt`.
+    pub fn from_fdt(fdt: &Fdt, ids: MachineIds) -> Self {
+        let mut info = CpuInfo { harts: [None; MAX_HARTS], ids };
+        let cpus = fdt
+            .nodes()
+            .filter_map(Result::ok)
+            .filter(|n| n.property("device_type").and_then(|p| p.as_str()) == Some("cpu"));
+        for cpu in cpus {
+            let Some(hart_id) = cpu.reg().next().map(|r| r.address as usize) else {
+                continue;
+            };
+            if let Some(slot) = info.harts.get_mut(hart_id) {
+                *slot = Some(HartInfo {
+                    hart_id,
+                    enabled: cpu.is_enabled(),
+                    isa: Isa::from_node(&cpu),
+                    isa_string: cpu.property("riscv,isa").and_then(|p| p.as_str()),
+                    mmu: cpu.property("mmu-type").and_then(|p| p.as_str()),
+                });
+            }
+        }
+        info
+    }
+
+    /// Returns the described harts, by increasing hart ID.
+    pub fn harts(&self) -> impl Iterator<Item = &HartInfo> {
+        self.harts.iter().flatten()
+    }
+
+    pub fn hart(&self, hart_id: usize) -> Option<&HartInfo> {
+        self.harts.get(hart_id)?.as_ref()
+    }
+
+    /// Returns the extensions every enabled hart has.
+    pub fn common(&self) -> Isa {
+        self.harts()
+            .filter(|h| h.enabled)
+            .map(|h| h.isa)
+            .reduce(|a, b| a.intersect(&b))
+            .unwrap_or_else(fallback_isa)
+    }
+
+    /// Prints the harts and IDs.
+    pub fn print(&self) {
+        let ids = &self.ids;
+        println!("CPU info:");
+        println!("  mvendorid 0x{:x}, marchid 0x{:x}, mimpid 0x{:x}", ids.mvendorid, ids.marchid, ids.mimpid);
+        println!("  SBI spec 0x{:x}, implementation {} version 0x{:x}",
+                 ids.sbi_spec_version, ids.sbi_impl_id, ids.sbi_impl_version);
+        for hart in self.harts() {
+            println!("  hart {:<3} {:<8} {:<10} {}", hart.hart_id, if hart.enabled { "enabled" } else { "disabled" },
+                     hart.mmu.unwrap_or("-"), hart.isa);
+        }
+        println!("  common   {}", self.common());
+    }
+}
+
+static CPUINFO: Once<CpuInfo> = Once::new();
+
+/// Reads the harts from the boot device tree and the IDs from the SBI.
+/// Only the first call has an effect; it must follow `boot::record`.
+pub fn init() -> &'static CpuInfo {
+    CPUINFO.call_once(|| {
+        let ids = MachineIds::read();
+        let fdt = boot::boot_info().and_then(|info| unsafe { Fdt::from_addr(info.dtb_addr) }.ok());
+        match fdt {
+            Some(fdt) => CpuInfo::from_fdt(&fdt, ids),
+            None => CpuInfo { harts: [None; MAX_HARTS], ids },
+        }
+    })
+}
+
+/// Returns the CPU information, if `init` has run.
+pub fn get() -> Option<&'static CpuInfo> {
+    CPUINFO.get()
+}
+
+/// Returns the extensions every enabled hart has; the fallback before
+/// `init`.
+pub fn common() -> Isa {
+    get().map_or_else(fallback_isa, |info| info.common())
+}
+
+/// Returns whether every enabled hart has the single-letter extension `c`.
+pub fn has_letter(c: char) -> bool {
+    common().has_letter(c)
+}
+
+/// Returns whether every enabled hart has `extension`.
+pub fn has_extension(extension: Extension) -> bool {
+    common().has(extension)
+}
+
+/// Prints the CPU information; what a `cpuinfo` command shows.
+pub fn print() {
+    init().print();
+}
+
+/// Reads the CPU information and reports the shared ISA.
+fn init_cpuinfo() -> InitResult {
+    let info = init();
+    info_print!("CPU: {} hart(s) described, common ISA {}.", info.harts().count(), info.common());
+    Ok(())
+}
+
+crate::initcall!(early, 15, init_cpuinfo);
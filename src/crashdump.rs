@@ -0,0 +1,195 @@
+// nt_rustos/src/crashdump.rs
+
+//! # Crash Dumps
+//!
+//! The last `REGION_SIZE` bytes of RAM are kept out of the heap (they are
+//! a reservation of `mm::guard`) and hold the report of the most recent
+//! kernel panic. The panic handler writes the report there as text behind
+//! a header with a magic number, the length and a CRC-32 of the text. RAM
+//! keeps its contents across a warm reboot, so on the next boot
+//! `detect_at_boot` finds a valid header, prints the previous crash and
+//! keeps a copy for `previous`, then clears the magic so the region is
+//! free for the next report.
+//!
+//! Writing never allocates or takes locks. The magic is written last, so a
+//! panic inside the panic handler leaves no half-written report behind
+//! that would pass for a valid one.
+
+use crate::init::initcall::InitResult;
+use crate::platform::{self, Region};
+use crate::util::crc;
+use crate::{info_print, println, time, warn_print};
+use alloc::string::String;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use spin::Once;
+
+/// Bytes reserved at the end of RAM, header included.
+pub const REGION_SIZE: usize = 64 * 1024;
+
+/// `"NTCRASH1"`, little-endian.
+const MAGIC: u64 = u64::from_le_bytes(*b"NTCRASH1");
+
+/// What precedes the report text in the region.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    /// Milliseconds since boot when the report was written.
+    uptime_ms: u64,
+    /// Length of the text.
+    len: u32,
+    /// CRC-32 of the text.
+    crc: u32,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+/// Bytes of text a report can hold.
+pub const CAPACITY: usize = REGION_SIZE - HEADER_SIZE;
+
+/// Ends a report that did not fit; room for it is always kept.
+const TRUNCATED: &str = "\n[truncated]\n";
+
+/// The region: the last `REGION_SIZE` bytes of RAM, page aligned. `None`
+/// if RAM is too small to spare it.
+pub fn region() -> Option<Region> {
+    let ram = platform::get().ram;
+    if ram.size < 16 * REGION_SIZE {
+        return None;
+    }
+    let base = (ram.end() - REGION_SIZE) & !(crate::mm::PAGE_SIZE - 1);
+    Some(Region { base, size: REGION_SIZE })
+}
+
+fn header(region: Region) -> *mut Header {
+    region.base as *mut Header
+}
+
+fn text(region: Region) -> *mut u8 {
+    (region.base + HEADER_SIZE) as *mut u8
+}
+
+/// A report being written into the region; text beyond `CAPACITY` is
+/// dropped.
+pub struct DumpWriter {
+    region: Region,
+    len: usize,
+    limit: usize,
+    crc: u32,
+    truncated: bool,
+}
+
+impl fmt::Write for DumpWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.limit - self.len;
+        let bytes = if s.len() > room {
+            self.truncated = true;
+            &s.as_bytes()[..room]
+        } else {
+            s.as_bytes()
+        };
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), text(self.region).add(self.len), bytes.len()) };
+        self.crc = crc::crc32_update(self.crc, bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+/// Writes a report with `fill`, replacing the previous one. Does nothing
+/// if there is no region.
+pub fn write(fill: impl FnOnce(&mut DumpWriter) -> fmt::Result) {
+    let Some(region) = region() else {
+        return;
+    };
+    let header = header(region);
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!((*header).magic), 0) };
+    let mut writer = DumpWriter { region, len: 0, limit: CAPACITY - TRUNCATED.len(), crc: 0, truncated: false };
+    let _ = fill(&mut writer);
+    if writer.truncated {
+        writer.limit = CAPACITY;
+        let _ = fmt::Write::write_str(&mut writer, TRUNCATED);
+    }
+    unsafe {
+        ptr::write_volatile(ptr::addr_of_mut!((*header).uptime_ms), time::monotonic_ms());
+        ptr::write_volatile(ptr::addr_of_mut!((*header).len), writer.len as u32);
+        ptr::write_volatile(ptr::addr_of_mut!((*header).crc), writer.crc);
+        fence(Ordering::SeqCst);
+        ptr::write_volatile(ptr::addr_of_mut!((*header).magic), MAGIC);
+    }
+}
+
+/// A report left in the region by an earlier boot.
+#[derive(Debug, Clone)]
+pub struct CrashReport {
+    /// Milliseconds the crashed kernel had been up.
+    pub uptime_ms: u64,
+    pub text: String,
+}
+
+/// Reads a valid report from the region, if there is one.
+fn read(region: Region) -> Option<CrashReport> {
+    let header = unsafe { ptr::read_volatile(header(region)) };
+    if header.magic != MAGIC || header.len as usize > CAPACITY {
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(text(region), header.len as usize) };
+    if crc::crc32(bytes) != header.crc {
+        return None;
+    }
+    Some(CrashReport { uptime_ms: header.uptime_ms, text: String::from_utf8_lossy(bytes).into_owned() })
+}
+
+/// Forgets the report in the region.
+fn clear(region: Region) {
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!((*header(region)).magic), 0) };
+}
+
+/// Reads the report currently in the region, as the next boot would.
+pub fn stored() -> Option<CrashReport> {
+    read(region()?)
+}
+
+/// Forgets the report currently in the region.
+pub fn discard() {
+    if let Some(region) = region() {
+        clear(region);
+    }
+}
+
+static PREVIOUS: Once<Option<CrashReport>> = Once::new();
+
+/// The crash of the previous boot, found by `detect_at_boot`.
+pub fn previous() -> Option<&'static CrashReport> {
+    PREVIOUS.get()?.as_ref()
+}
+
+/// Prints a report.
+pub fn print(report: &CrashReport) {
+    println!("=== Previous crash ({} ms after boot) ===", report.uptime_ms);
+    for line in report.text.lines() {
+        println!("  {}", line);
+    }
+    println!("=========================================");
+}
+
+/// Reports the crash of the previous boot, if its report survived, and
+/// frees the region for this boot.
+fn detect_at_boot() -> InitResult {
+    let Some(region) = region() else {
+        warn_print!("Crash dump: RAM too small to reserve {} KB.", REGION_SIZE / 1024);
+        return Ok(());
+    };
+    let report = PREVIOUS.call_once(|| read(region));
+    clear(region);
+    match report {
+        Some(report) => {
+            warn_print!("Crash dump: the previous boot crashed.");
+            print(report);
+        }
+        None => info_print!("Crash dump: region {}, no previous crash.", region),
+    }
+    Ok(())
+}
+
+crate::initcall!(late, 15, detect_at_boot);
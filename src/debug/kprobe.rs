@@ -0,0 +1,257 @@
+// nt_rustos/src/debug/kprobe.rs
+
+//! # Kernel Probes
+//!
+//! Traces arbitrary kernel instructions without rebuilding the kernel.
+//! `register` saves the instruction at the probed address and patches a
+//! `c.ebreak` over its first halfword. When the breakpoint is hit, the
+//! handler calls the probe's pre callback with the trapped `TrapContext`,
+//! then steps the original instruction from a per-probe slot (see
+//! `debug::step`) and calls the post callback once it has run.
+//!
+//! Patching only writes one halfword, so another hart fetching the
+//! instruction sees either the original or the breakpoint, never a mix.
+//! Kernel text lies in RAM, which the kernel maps read-write-execute, and
+//! every write is followed by `fence.i` locally and on the other online
+//! harts.
+//!
+//! The step runs the copy at another address. That rules out instructions
+//! whose effect depends on their own `pc` (`auipc`, jumps, branches) and
+//! system instructions, which `register` refuses.
+//!
+//! Callbacks run inside the trap handler, with the trap system's handler
+//! lock held. They must not allocate or block, and neither they nor any
+//! other trap-path code may be probed: a breakpoint taken there would
+//! deadlock on that lock. A pre callback that moves `sepc` away from the
+//! probed address takes over control flow: the original instruction is
+//! skipped and no post callback runs.
+
+use super::step::{self, Instruction, Slot, C_EBREAK};
+use crate::trap::{self, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID};
+use crate::util::backtrace;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Probes that can be registered at once.
+pub const MAX_PROBES: usize = 16;
+
+/// Priority of the breakpoint handler: ahead of every other breakpoint
+/// consumer, since only it knows which breakpoints are probes.
+const KPROBE_HANDLER_PRIORITY: u8 = 0;
+
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// A probe callback, given the probed address and the trapped context.
+pub type ProbeHandler = fn(addr: usize, context: &mut TrapContext);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KprobeError {
+    /// The breakpoint handler is not registered yet.
+    NotInitialized,
+    /// The address is not in kernel text.
+    OutsideText,
+    /// The address is not halfword aligned.
+    Misaligned,
+    /// The instruction depends on its own address or is a system
+    /// instruction, and cannot be stepped out of line.
+    Unsupported,
+    AlreadyProbed,
+    NotProbed,
+    /// All `MAX_PROBES` probes are in use.
+    NoFreeSlot,
+}
+
+impl fmt::Display for KprobeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInitialized => write!(f, "kprobes not initialized"),
+            Self::OutsideText => write!(f, "address is not in kernel text"),
+            Self::Misaligned => write!(f, "address is not halfword aligned"),
+            Self::Unsupported => write!(f, "instruction cannot be stepped out of line"),
+            Self::AlreadyProbed => write!(f, "address already probed"),
+            Self::NotProbed => write!(f, "address not probed"),
+            Self::NoFreeSlot => write!(f, "no free probe slot"),
+        }
+    }
+}
+
+/// A registered probe, as listed by `probes`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProbeInfo {
+    pub addr: usize,
+    /// Length of the probed instruction, 2 or 4 bytes.
+    pub len: usize,
+    pub hits: u64,
+}
+
+struct Probe {
+    /// The probed address; 0 while the entry is free. Published last, with
+    /// release ordering, once the other fields are set.
+    addr: AtomicUsize,
+    hits: AtomicU64,
+    /// Written under `REGISTRY` while `addr` is 0, read-only afterwards.
+    instruction: UnsafeCell<Instruction>,
+    pre: UnsafeCell<Option<ProbeHandler>>,
+    post: UnsafeCell<Option<ProbeHandler>>,
+    slot: UnsafeCell<Slot>,
+}
+
+// Mutable fields are only written while the entry is unpublished.
+unsafe impl Sync for Probe {}
+
+impl Probe {
+    const fn new() -> Self {
+        Self {
+            addr: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            instruction: UnsafeCell::new(Instruction { bits: 0, len: 0 }),
+            pre: UnsafeCell::new(None),
+            post: UnsafeCell::new(None),
+            slot: UnsafeCell::new(Slot::new()),
+        }
+    }
+
+    fn instruction(&self) -> Instruction {
+        unsafe { *self.instruction.get() }
+    }
+
+    fn slot(&self) -> &Slot {
+        unsafe { &*self.slot.get() }
+    }
+}
+
+static PROBES: [Probe; MAX_PROBES] = [const { Probe::new() }; MAX_PROBES];
+
+/// Serializes registration and removal; the breakpoint handler never takes
+/// it.
+static REGISTRY: Mutex<()> = Mutex::new(());
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+fn find(addr: usize) -> Option<usize> {
+    PROBES.iter().position(|probe| probe.addr.load(Ordering::Acquire) == addr)
+}
+
+/// Places a probe on the instruction at `addr`: `pre` runs before it
+/// executes and `post` after, both with the trapped context.
+pub fn register(addr: usize, pre: Option<ProbeHandler>, post: Option<ProbeHandler>) -> Result<(), KprobeError> {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        return Err(KprobeError::NotInitialized);
+    }
+    let (text_start, text_end) = backtrace::text_range();
+    if !(text_start..text_end).contains(&addr) {
+        return Err(KprobeError::OutsideText);
+    }
+    if addr % 2 != 0 {
+        return Err(KprobeError::Misaligned);
+    }
+    let _registry = REGISTRY.lock();
+    if find(addr).is_some() {
+        return Err(KprobeError::AlreadyProbed);
+    }
+    let instruction = unsafe { Instruction::read(addr) };
+    if addr + instruction.len > text_end {
+        return Err(KprobeError::OutsideText);
+    }
+    if !instruction.is_relocatable() {
+        return Err(KprobeError::Unsupported);
+    }
+    let index = find(0).ok_or(KprobeError::NoFreeSlot)?;
+    let probe = &PROBES[index];
+    unsafe {
+        *probe.instruction.get() = instruction;
+        *probe.pre.get() = pre;
+        *probe.post.get() = post;
+        (*probe.slot.get()).load(instruction);
+    }
+    probe.hits.store(0, Ordering::Relaxed);
+    probe.addr.store(addr, Ordering::Release);
+    unsafe { ptr::write_volatile(addr as *mut u16, C_EBREAK) };
+    step::sync_icache();
+    Ok(())
+}
+
+/// Removes the probe at `addr`, restoring the original instruction, and
+/// returns how many times it was hit.
+pub fn unregister(addr: usize) -> Result<u64, KprobeError> {
+    let _registry = REGISTRY.lock();
+    let index = find(addr).filter(|_| addr != 0).ok_or(KprobeError::NotProbed)?;
+    let probe = &PROBES[index];
+    unsafe { ptr::write_volatile(addr as *mut u16, probe.instruction().bits as u16) };
+    step::sync_icache();
+    // A hart may still be stepping through the slot.
+    while step::in_progress(probe.slot()) {
+        core::hint::spin_loop();
+    }
+    let hits = probe.hits.load(Ordering::Relaxed);
+    probe.addr.store(0, Ordering::Release);
+    Ok(hits)
+}
+
+/// Times the probe at `addr` has been hit.
+pub fn hits(addr: usize) -> Option<u64> {
+    let index = find(addr).filter(|_| addr != 0)?;
+    Some(PROBES[index].hits.load(Ordering::Relaxed))
+}
+
+/// Lists the registered probes.
+pub fn probes() -> Vec<ProbeInfo> {
+    PROBES
+        .iter()
+        .filter_map(|probe| {
+            let addr = probe.addr.load(Ordering::Acquire);
+            (addr != 0).then(|| ProbeInfo {
+                addr,
+                len: probe.instruction().len,
+                hits: probe.hits.load(Ordering::Relaxed),
+            })
+        })
+        .collect()
+}
+
+/// Runs the post callback of probe `index` once its instruction is stepped.
+fn after_step(index: usize, context: &mut TrapContext) {
+    let probe = &PROBES[index];
+    if let Some(post) = unsafe { *probe.post.get() } {
+        post(probe.addr.load(Ordering::Acquire), context);
+    }
+}
+
+fn breakpoint_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.sstatus & SSTATUS_SPP == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let addr = context.sepc;
+    let Some(index) = find(addr).filter(|_| addr != 0) else {
+        return TrapHandlerResult::Pass;
+    };
+    let probe = &PROBES[index];
+    probe.hits.fetch_add(1, Ordering::Relaxed);
+    if let Some(pre) = unsafe { *probe.pre.get() } {
+        pre(addr, context);
+        if context.sepc != addr {
+            return TrapHandlerResult::Handled;
+        }
+    }
+    step::begin(context, probe.slot(), probe.instruction(), Some((after_step, index)));
+    TrapHandlerResult::Handled
+}
+
+/// Registers the breakpoint handler that runs the probes.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    trap::register_trap_handler(
+        TrapType::Breakpoint,
+        breakpoint_handler,
+        KPROBE_HANDLER_PRIORITY,
+        "Kernel Probe",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    INITIALIZED.store(true, Ordering::Release);
+    Ok(())
+}
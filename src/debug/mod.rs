@@ -0,0 +1,53 @@
+// nt_rustos/src/debug/mod.rs
+
+//! # Kernel Debugging Facilities
+//!
+//! Tools for observing a running kernel without rebuilding it: probes on
+//! arbitrary kernel instructions (`kprobe`) and watchpoints on memory
+//! (`watchpoint`, with hardware triggers or else page protection through
+//! `pagewatch`). They resume trapped code by stepping an instruction out
+//! of line (`step`).
+
+pub mod kprobe;
+pub mod pagewatch;
+pub mod step;
+pub mod watchpoint;
+
+use crate::init::initcall::InitResult;
+use crate::println;
+use crate::trap::TrapContext;
+use crate::util::backtrace;
+use crate::util::ksyms::WithSymbol;
+use alloc::format;
+
+/// ABI names of `x0`..`x31`.
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// Prints the registers of a trapped kernel context and the call chain it
+/// trapped in.
+pub fn print_context(context: &TrapContext) {
+    println!("  sepc {}  stval {:#x}  sstatus {:#x}", WithSymbol(context.sepc), context.stval, context.sstatus);
+    for row in (0..32).step_by(4) {
+        let [a, b, c, d] = [row, row + 1, row + 2, row + 3];
+        println!("  {:>4} {:#018x}  {:>4} {:#018x}  {:>4} {:#018x}  {:>4} {:#018x}",
+                 REGISTER_NAMES[a], context.x[a], REGISTER_NAMES[b], context.x[b],
+                 REGISTER_NAMES[c], context.x[c], REGISTER_NAMES[d], context.x[d]);
+    }
+    backtrace::capture_from(context.sepc, context.x[8]).print();
+}
+
+/// Registers the trap handlers of the debugging facilities.
+fn init_debug() -> InitResult {
+    step::init().map_err(|e| format!("failed to register the step handler: {}", e))?;
+    kprobe::init().map_err(|e| format!("failed to register the kprobe handler: {}", e))?;
+    watchpoint::init().map_err(|e| format!("failed to register the watchpoint handlers: {}", e))?;
+    pagewatch::init().map_err(|e| format!("failed to register the page watch handlers: {}", e))?;
+    crate::info_print!("Kernel probes ready ({} slots), {} hardware watchpoints.",
+                       kprobe::MAX_PROBES, watchpoint::available());
+    Ok(())
+}
+
+crate::initcall!(subsys, 45, init_debug);
@@ -0,0 +1,251 @@
+// nt_rustos/src/debug/pagewatch.rs
+
+//! # Page-Protection Watchpoints
+//!
+//! The fallback of `watchpoint` for platforms without hardware triggers:
+//! the kernel page holding a watched range is made read-only (see
+//! `mm::kprotect`), so every kernel store to it raises a store page fault.
+//! The fault handler logs stores that hit a watched range, like a hardware
+//! watchpoint hit, then makes the page writable for the one faulting
+//! instruction: it steps the instruction out of line (see `debug::step`)
+//! and protects the page again once it has run. Stores to the rest of the
+//! page are let through the same way, without a report, and counted by
+//! `stray_faults`.
+//!
+//! Only stores can be watched, and only with paging enabled. A
+//! store-conditional cannot be stepped, since the trap handler's own locks
+//! take the hart's reservation and the stepped `sc` would always fail.
+//! After one is logged, its page is left writable for the retry and
+//! protected again at the next timer interrupt; stores in between go
+//! unlogged.
+//!
+//! The trap path must never store to a watched page: the fault would recur
+//! inside the handler. The stack in use cannot be watched, and neither can
+//! data the trap path writes, such as locks taken while handling traps.
+
+use super::step::{self, Instruction};
+use super::watchpoint::{self, WatchError, WatchHandler, WatchHit, WatchInfo, WatchKind, WatchMode};
+use crate::mm::{self, kprotect, PAGE_SIZE};
+use crate::trap::{self, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID};
+use crate::util::ksyms::WithSymbol;
+use crate::warn_print;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Page watchpoints that can be set at once.
+pub const MAX_PAGE_WATCHES: usize = 8;
+
+/// Priority of the fault handler: same as the user access fixups,
+/// registered earlier, so it sees kernel store faults first.
+const PAGE_WATCH_HANDLER_PRIORITY: u8 = 0;
+
+const SSTATUS_SPP: usize = 1 << 8;
+
+struct PageWatch {
+    /// Start of the watched range; 0 while the entry is free. Published
+    /// last, with release ordering, once the other fields are set.
+    addr: AtomicUsize,
+    hits: AtomicU64,
+    /// Written under `REGISTRY` while `addr` is 0, read-only afterwards.
+    len: UnsafeCell<usize>,
+    handler: UnsafeCell<Option<WatchHandler>>,
+}
+
+// Mutable fields are only written while the entry is unpublished.
+unsafe impl Sync for PageWatch {}
+
+impl PageWatch {
+    fn len(&self) -> usize {
+        unsafe { *self.len.get() }
+    }
+}
+
+static WATCHES: [PageWatch; MAX_PAGE_WATCHES] = [const {
+    PageWatch {
+        addr: AtomicUsize::new(0),
+        hits: AtomicU64::new(0),
+        len: UnsafeCell::new(0),
+        handler: UnsafeCell::new(None),
+    }
+}; MAX_PAGE_WATCHES];
+
+/// Serializes setting and clearing; the trap handlers never take it.
+static REGISTRY: Mutex<()> = Mutex::new(());
+
+/// Faults on watched pages outside any watched range.
+static STRAY: AtomicU64 = AtomicU64::new(0);
+
+/// A page left writable for a store-conditional, protected again at the
+/// next timer interrupt; 0 if none.
+static REOPENED: AtomicUsize = AtomicUsize::new(0);
+
+fn page_of(addr: usize) -> usize {
+    addr & !(PAGE_SIZE - 1)
+}
+
+fn find(addr: usize) -> Option<usize> {
+    WATCHES.iter().position(|watch| watch.addr.load(Ordering::Acquire) == addr)
+}
+
+/// Whether a set watchpoint lies in the page at `page`.
+fn is_watched(page: usize) -> bool {
+    WATCHES.iter().any(|watch| {
+        let addr = watch.addr.load(Ordering::Acquire);
+        addr != 0 && page_of(addr) == page
+    })
+}
+
+/// Watches `len` bytes at `addr` for kernel stores by protecting its page.
+/// The range follows the rules of `watchpoint::set`.
+pub fn set(addr: usize, len: usize, handler: Option<WatchHandler>) -> Result<(), WatchError> {
+    watchpoint::check_range(addr, len)?;
+    if !mm::is_initialized() {
+        return Err(WatchError::Unavailable);
+    }
+    let sp: usize;
+    unsafe { asm!("mv {}, sp", out(reg) sp) };
+    if page_of(sp) == page_of(addr) {
+        return Err(WatchError::InvalidRange);
+    }
+    let _registry = REGISTRY.lock();
+    if find(addr).is_some() {
+        return Err(WatchError::AlreadyWatched);
+    }
+    let index = find(0).ok_or(WatchError::NoFreeSlot)?;
+    kprotect::split(addr).map_err(|_| WatchError::InvalidRange)?;
+    let watch = &WATCHES[index];
+    unsafe {
+        *watch.len.get() = len;
+        *watch.handler.get() = handler;
+    }
+    watch.hits.store(0, Ordering::Relaxed);
+    watch.addr.store(addr, Ordering::Release);
+    let _ = kprotect::set_writable(addr, false);
+    Ok(())
+}
+
+/// Removes the page watchpoint at `addr` and returns how many times it
+/// was hit. The page becomes writable again once nothing on it is watched.
+pub fn clear(addr: usize) -> Result<u64, WatchError> {
+    let _registry = REGISTRY.lock();
+    let index = find(addr).filter(|_| addr != 0).ok_or(WatchError::NotWatched)?;
+    let watch = &WATCHES[index];
+    watch.addr.store(0, Ordering::Release);
+    if !is_watched(page_of(addr)) {
+        let _ = kprotect::set_writable(addr, true);
+    }
+    Ok(watch.hits.load(Ordering::Relaxed))
+}
+
+/// Lists the page watchpoints.
+pub fn watchpoints() -> impl Iterator<Item = WatchInfo> {
+    WATCHES.iter().filter_map(|watch| {
+        let addr = watch.addr.load(Ordering::Acquire);
+        (addr != 0).then(|| WatchInfo {
+            addr,
+            len: watch.len(),
+            kind: WatchKind::Store,
+            mode: WatchMode::Page,
+            hits: watch.hits.load(Ordering::Relaxed),
+        })
+    })
+}
+
+/// Stores to watched pages that missed every watched range.
+pub fn stray_faults() -> u64 {
+    STRAY.load(Ordering::Relaxed)
+}
+
+/// Protects `page` again once the stepped store has run.
+fn reprotect(page: usize, _context: &mut TrapContext) {
+    if is_watched(page) {
+        let _ = kprotect::set_writable(page, false);
+    }
+}
+
+/// Whether `instruction` is `sc.w` or `sc.d`.
+fn is_store_conditional(instruction: Instruction) -> bool {
+    instruction.len == 4 && instruction.bits & 0x7f == 0x2f && instruction.bits >> 27 == 0b00011
+}
+
+fn store_fault_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.sstatus & SSTATUS_SPP == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let access = context.stval;
+    let page = page_of(access);
+    if !is_watched(page) {
+        return TrapHandlerResult::Pass;
+    }
+    let pc = context.sepc;
+    let hit = WATCHES.iter().find(|watch| {
+        let addr = watch.addr.load(Ordering::Acquire);
+        addr != 0 && (addr..addr + watch.len()).contains(&access)
+    });
+    match hit {
+        Some(watch) => {
+            watch.hits.fetch_add(1, Ordering::Relaxed);
+            let hit = WatchHit {
+                addr: watch.addr.load(Ordering::Relaxed),
+                len: watch.len(),
+                access,
+                pc,
+                kind: Some(WatchKind::Store),
+            };
+            match unsafe { *watch.handler.get() } {
+                Some(handler) => handler(&hit, context),
+                None => watchpoint::report(&hit, context),
+            }
+            if context.sepc != pc {
+                return TrapHandlerResult::Handled;
+            }
+        }
+        None => {
+            STRAY.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    let _ = kprotect::set_writable(page, true);
+    let instruction = unsafe { Instruction::read(pc) };
+    if is_store_conditional(instruction) {
+        // Retried in place; the trap has already broken its reservation.
+        REOPENED.store(page, Ordering::Release);
+    } else if !step::begin_copy(context, Some((reprotect, page))) {
+        warn_print!("Page watch {:#x}: cannot step {}, page left writable.", page, WithSymbol(pc));
+    }
+    TrapHandlerResult::Handled
+}
+
+/// Protects the page left writable for a store-conditional.
+fn timer_handler(_context: &mut TrapContext) -> TrapHandlerResult {
+    let page = REOPENED.swap(0, Ordering::AcqRel);
+    if page != 0 && is_watched(page) {
+        let _ = kprotect::set_writable(page, false);
+    }
+    TrapHandlerResult::Pass
+}
+
+/// Registers the store fault handler and the timer hook re-protecting
+/// pages.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    trap::register_trap_handler(
+        TrapType::StorePageFault,
+        store_fault_handler,
+        PAGE_WATCH_HANDLER_PRIORITY,
+        "Page Watch",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    trap::register_trap_handler(
+        TrapType::TimerInterrupt,
+        timer_handler,
+        PAGE_WATCH_HANDLER_PRIORITY,
+        "Page Watch Reprotect",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    Ok(())
+}
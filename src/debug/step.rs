@@ -0,0 +1,218 @@
+// nt_rustos/src/debug/step.rs
+
+//! # Out-of-Line Stepping
+//!
+//! Runs one trapped kernel instruction at another address and regains
+//! control right after it. The instruction is copied into a slot followed
+//! by `c.ebreak`; `begin` points `sepc` at the slot, and when the
+//! breakpoint after the copy traps, the step ends: execution resumes after
+//! the original instruction and the step's `done` callback runs.
+//!
+//! Kernel probes keep a prepared slot per probe. Watchpoints step whatever
+//! instruction trapped, so `begin_copy` copies it into the slot of the
+//! current hart. Either way, only instructions that behave the same at
+//! another address can be stepped (see `Instruction::is_relocatable`).
+//! Interrupts stay masked during the step, so the copy and its breakpoint
+//! run back to back and a hart steps one instruction at a time.
+
+use crate::smp;
+use crate::task::{scheduler::current_hart, MAX_HARTS};
+use crate::trap::{self, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID};
+use crate::util::sbi::rfence;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// `c.ebreak`.
+pub const C_EBREAK: u16 = 0x9002;
+
+/// Priority of the handler ending steps; a step's breakpoint is never
+/// anyone else's.
+const STEP_HANDLER_PRIORITY: u8 = 0;
+
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// Called when a step ends, with the token given to `begin` and the
+/// context about to resume after the stepped instruction.
+pub type StepDone = fn(token: usize, context: &mut TrapContext);
+
+/// A kernel instruction: its encoding and length (2 or 4 bytes).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub bits: u32,
+    pub len: usize,
+}
+
+impl Instruction {
+    /// Reads the instruction at `addr`, which must be halfword aligned
+    /// and hold code.
+    pub unsafe fn read(addr: usize) -> Self {
+        let low = ptr::read_volatile(addr as *const u16);
+        if low & 0b11 != 0b11 {
+            return Self { bits: low as u32, len: 2 };
+        }
+        let high = ptr::read_volatile((addr + 2) as *const u16);
+        Self { bits: low as u32 | (high as u32) << 16, len: 4 }
+    }
+
+    /// Whether the instruction behaves the same when run from another
+    /// address: not `auipc`, a jump, a branch or a system instruction.
+    pub fn is_relocatable(&self) -> bool {
+        if self.len == 4 {
+            // AUIPC, JAL, JALR, BRANCH, SYSTEM
+            return !matches!(self.bits & 0x7f, 0x17 | 0x6f | 0x67 | 0x63 | 0x73);
+        }
+        let funct3 = (self.bits >> 13) & 0b111;
+        match self.bits & 0b11 {
+            // C.J, C.BEQZ, C.BNEZ
+            0b01 => !matches!(funct3, 0b101 | 0b110 | 0b111),
+            // C.JR, C.JALR and C.EBREAK have funct3 100 and rs2 = 0
+            0b10 => !(funct3 == 0b100 && (self.bits >> 2) & 0x1f == 0),
+            _ => true,
+        }
+    }
+
+    /// Whether this is `ebreak` or `c.ebreak`.
+    pub fn is_ebreak(&self) -> bool {
+        match self.len {
+            2 => self.bits == C_EBREAK as u32,
+            _ => self.bits == 0x0010_0073,
+        }
+    }
+}
+
+/// Where an instruction is stepped: a copy of it followed by `c.ebreak`.
+/// Kernel data lies in RAM, which the kernel maps executable.
+#[repr(C, align(8))]
+pub struct Slot([u16; 4]);
+
+impl Slot {
+    pub const fn new() -> Self {
+        Self([0; 4])
+    }
+
+    /// Copies `instruction` into the slot; callers sync the instruction
+    /// cache before stepping it.
+    pub fn load(&mut self, instruction: Instruction) {
+        let (low, high) = (instruction.bits as u16, (instruction.bits >> 16) as u16);
+        self.0 = match instruction.len {
+            2 => [low, C_EBREAK, 0, 0],
+            _ => [low, high, C_EBREAK, 0],
+        };
+    }
+
+    pub fn addr(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+}
+
+/// The step a hart is taking. Only that hart writes it, from its trap
+/// handler; `end` is also read by other harts through `in_progress`.
+struct Step {
+    /// Address of the breakpoint ending the step; 0 when not stepping.
+    end: AtomicUsize,
+    resume: UnsafeCell<usize>,
+    spie: UnsafeCell<bool>,
+    done: UnsafeCell<Option<(StepDone, usize)>>,
+    /// The slot of the hart, for `begin_copy`.
+    slot: UnsafeCell<Slot>,
+}
+
+unsafe impl Sync for Step {}
+
+static STEPS: [Step; MAX_HARTS] = [const {
+    Step {
+        end: AtomicUsize::new(0),
+        resume: UnsafeCell::new(0),
+        spie: UnsafeCell::new(false),
+        done: UnsafeCell::new(None),
+        slot: UnsafeCell::new(Slot::new()),
+    }
+}; MAX_HARTS];
+
+/// Makes instruction writes visible to instruction fetch on every online
+/// hart.
+pub fn sync_icache() {
+    unsafe { asm!("fence.i") };
+    let others = smp::online_mask() & !(1 << current_hart());
+    if others != 0 {
+        let _ = rfence::remote_fence_i(others as usize);
+    }
+}
+
+/// Continues the trapped `context` by running `instruction`, copied in
+/// `slot`, in place of the instruction at `sepc`, then resumes after that
+/// instruction and calls `done` with `token`.
+pub fn begin(context: &mut TrapContext, slot: &Slot, instruction: Instruction, done: Option<(StepDone, usize)>) {
+    let step = &STEPS[current_hart()];
+    unsafe {
+        *step.resume.get() = context.sepc + instruction.len;
+        *step.spie.get() = context.sstatus & SSTATUS_SPIE != 0;
+        *step.done.get() = done;
+    }
+    context.sstatus &= !SSTATUS_SPIE;
+    context.sepc = slot.addr();
+    step.end.store(slot.addr() + instruction.len, Ordering::Release);
+}
+
+/// Steps the trapped instruction out of line through the slot of the
+/// current hart. Returns `false`, leaving `context` alone, if it cannot be
+/// stepped.
+pub fn begin_copy(context: &mut TrapContext, done: Option<(StepDone, usize)>) -> bool {
+    let instruction = unsafe { Instruction::read(context.sepc) };
+    if !instruction.is_relocatable() {
+        return false;
+    }
+    let step = &STEPS[current_hart()];
+    let slot = unsafe { &mut *step.slot.get() };
+    slot.load(instruction);
+    // Only this hart runs its slot.
+    unsafe { asm!("fence.i") };
+    begin(context, slot, instruction, done);
+    true
+}
+
+/// Whether any hart is stepping through `slot`.
+pub fn in_progress(slot: &Slot) -> bool {
+    let range = slot.addr()..slot.addr() + core::mem::size_of::<Slot>();
+    STEPS.iter().any(|step| range.contains(&step.end.load(Ordering::Acquire)))
+}
+
+fn breakpoint_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.sstatus & SSTATUS_SPP == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let step = &STEPS[current_hart()];
+    let end = step.end.load(Ordering::Acquire);
+    if end == 0 || context.sepc != end {
+        return TrapHandlerResult::Pass;
+    }
+    let done = unsafe {
+        context.sepc = *step.resume.get();
+        if *step.spie.get() {
+            context.sstatus |= SSTATUS_SPIE;
+        }
+        (*step.done.get()).take()
+    };
+    step.end.store(0, Ordering::Release);
+    if let Some((done, token)) = done {
+        done(token, context);
+    }
+    TrapHandlerResult::Handled
+}
+
+/// Registers the breakpoint handler ending steps.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    trap::register_trap_handler(
+        TrapType::Breakpoint,
+        breakpoint_handler,
+        STEP_HANDLER_PRIORITY,
+        "Out-of-line Step",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    Ok(())
+}
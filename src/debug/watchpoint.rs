@@ -0,0 +1,550 @@
+// nt_rustos/src/debug/watchpoint.rs
+
+//! # Hardware Watchpoints
+//!
+//! Breaks on loads or stores to chosen addresses using the RISC-V trigger
+//! module (Sdtrig): each watchpoint programs one address-match trigger
+//! (`mcontrol` or `mcontrol6`) through `tselect`/`tdata1`/`tdata2` to
+//! raise a breakpoint exception on a matching kernel access. The trap
+//! handler reports the hit (the accessing instruction, the registers and a
+//! backtrace) or passes it to the watchpoint's own handler, then lets the
+//! access happen: it disarms the trigger, steps the instruction out of
+//! line (see `debug::step`) and re-arms the trigger afterwards.
+//!
+//! Whether the trigger CSRs are reachable from S-mode is up to the
+//! platform. `init` probes them with an illegal-instruction handler that
+//! skips trigger CSR accesses made while probing, and enumerates the
+//! triggers that can match addresses. Without any, `set` fails with
+//! `WatchError::Unavailable`.
+//!
+//! Where no trigger is available or free, store watchpoints fall back to
+//! page protection (see `debug::pagewatch`), which is slower and traps on
+//! every store to the page but needs nothing beyond paging.
+//!
+//! Triggers belong to a hart: watchpoints are programmed on the calling
+//! hart, which is the only one running the kernel. A watched range is a
+//! single byte or a naturally aligned power-of-two block (NAPOT match).
+//! Handlers run inside the trap handler under the same rules as kprobe
+//! callbacks, and must not touch the watched memory themselves.
+
+use super::pagewatch;
+use super::step::{self, Instruction};
+use crate::task::{scheduler::current_hart, MAX_HARTS};
+use crate::trap::{self, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID};
+use crate::util::ksyms::WithSymbol;
+use crate::warn_print;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::{Mutex, Once};
+
+/// Watchpoints that can be set at once, at most one per trigger.
+pub const MAX_WATCHPOINTS: usize = 8;
+
+/// Largest watched range; NAPOT masks beyond this are rarely implemented.
+pub const MAX_WATCH_LEN: usize = 4096;
+
+/// Triggers examined by `init`.
+const MAX_TRIGGERS: usize = 32;
+
+/// Priority of the handlers: the breakpoint handler comes after kprobes
+/// and steps, whose breakpoints are `ebreak`s it ignores anyway.
+const WATCH_HANDLER_PRIORITY: u8 = 2;
+
+const SSTATUS_SPP: usize = 1 << 8;
+
+// tdata1 fields shared by mcontrol and mcontrol6 (RV64).
+const TDATA1_TYPE_SHIFT: usize = 60;
+const TDATA1_MATCH_SHIFT: usize = 7;
+const TDATA1_S: usize = 1 << 4;
+const TDATA1_STORE: usize = 1 << 1;
+const TDATA1_LOAD: usize = 1 << 0;
+/// mcontrol only: the trigger fires after the access instead of before.
+const MCONTROL_TIMING: usize = 1 << 18;
+const MATCH_EQUAL: usize = 0;
+const MATCH_NAPOT: usize = 1;
+
+/// Trigger CSR numbers.
+const CSR_TSELECT: u32 = 0x7a0;
+const CSR_LAST: u32 = 0x7a5;
+
+/// What a watchpoint breaks on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Load,
+    Store,
+    /// Loads and stores.
+    Access,
+}
+
+impl WatchKind {
+    fn tdata1_bits(self) -> usize {
+        match self {
+            WatchKind::Load => TDATA1_LOAD,
+            WatchKind::Store => TDATA1_STORE,
+            WatchKind::Access => TDATA1_LOAD | TDATA1_STORE,
+        }
+    }
+
+    /// The accesses `instruction` makes, if it is a load, store or AMO.
+    pub fn of(instruction: Instruction) -> Option<Self> {
+        if instruction.len == 4 {
+            return match instruction.bits & 0x7f {
+                0x03 | 0x07 => Some(WatchKind::Load),
+                0x23 | 0x27 => Some(WatchKind::Store),
+                0x2f => Some(WatchKind::Access),
+                _ => None,
+            };
+        }
+        // Quadrants 0 and 2: funct3 1-3 load, 5-7 store (sp-relative in quadrant 2).
+        match (instruction.bits & 0b11, (instruction.bits >> 13) & 0b111) {
+            (0b00 | 0b10, 1..=3) => Some(WatchKind::Load),
+            (0b00 | 0b10, 5..=7) => Some(WatchKind::Store),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WatchKind::Load => "load",
+            WatchKind::Store => "store",
+            WatchKind::Access => "access",
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchError {
+    /// No trigger can match addresses, or the CSRs are not reachable.
+    Unavailable,
+    /// The range is empty, too long, not a power of two or not aligned to
+    /// its length.
+    InvalidRange,
+    AlreadyWatched,
+    NotWatched,
+    /// Every usable trigger is in use.
+    NoFreeTrigger,
+    /// All page watchpoints are in use.
+    NoFreeSlot,
+    /// The trigger did not accept the configuration.
+    Rejected,
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable => write!(f, "no hardware trigger available"),
+            Self::InvalidRange => write!(f, "range must be a naturally aligned power of two"),
+            Self::AlreadyWatched => write!(f, "address already watched"),
+            Self::NotWatched => write!(f, "address not watched"),
+            Self::NoFreeTrigger => write!(f, "no free trigger"),
+            Self::NoFreeSlot => write!(f, "no free page watch slot"),
+            Self::Rejected => write!(f, "trigger rejected the configuration"),
+        }
+    }
+}
+
+/// A watchpoint hit, as passed to handlers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Start and length of the watched range.
+    pub addr: usize,
+    pub len: usize,
+    /// The address accessed (`stval`).
+    pub access: usize,
+    /// The accessing instruction, or the one after it if the trigger fires
+    /// after the access.
+    pub pc: usize,
+    /// The kind of access, when the instruction at `pc` made it.
+    pub kind: Option<WatchKind>,
+}
+
+/// A watchpoint handler, given the hit and the trapped context.
+pub type WatchHandler = fn(hit: &WatchHit, context: &mut TrapContext);
+
+/// How a watchpoint catches accesses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchMode {
+    /// The hardware trigger with this index.
+    Trigger(usize),
+    /// A write-protected page.
+    Page,
+}
+
+/// A set watchpoint, as listed by `watchpoints`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WatchInfo {
+    pub addr: usize,
+    pub len: usize,
+    pub kind: WatchKind,
+    pub mode: WatchMode,
+    pub hits: u64,
+}
+
+/// The address-match trigger types.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TriggerType {
+    Mcontrol = 2,
+    Mcontrol6 = 6,
+}
+
+struct Watch {
+    /// Start of the watched range; 0 while the entry is free. Published
+    /// last, with release ordering, once the other fields are set.
+    addr: AtomicUsize,
+    hits: AtomicU64,
+    /// Written under `REGISTRY` while `addr` is 0, read-only afterwards.
+    len: UnsafeCell<usize>,
+    kind: UnsafeCell<WatchKind>,
+    trigger: UnsafeCell<usize>,
+    /// `tdata1` and `tdata2` as programmed, for re-arming.
+    tdata: UnsafeCell<(usize, usize)>,
+    /// The trigger fires after the access, which needs no step.
+    after: UnsafeCell<bool>,
+    handler: UnsafeCell<Option<WatchHandler>>,
+}
+
+// Mutable fields are only written while the entry is unpublished.
+unsafe impl Sync for Watch {}
+
+impl Watch {
+    const fn new() -> Self {
+        Self {
+            addr: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            len: UnsafeCell::new(0),
+            kind: UnsafeCell::new(WatchKind::Access),
+            trigger: UnsafeCell::new(0),
+            tdata: UnsafeCell::new((0, 0)),
+            after: UnsafeCell::new(false),
+            handler: UnsafeCell::new(None),
+        }
+    }
+
+    fn len(&self) -> usize {
+        unsafe { *self.len.get() }
+    }
+
+    fn trigger(&self) -> usize {
+        unsafe { *self.trigger.get() }
+    }
+
+    fn arm(&self) {
+        let (tdata1, tdata2) = unsafe { *self.tdata.get() };
+        unsafe { program(self.trigger(), tdata1, tdata2) };
+    }
+
+    fn disarm(&self) {
+        unsafe { program(self.trigger(), 0, 0) };
+    }
+}
+
+static WATCHES: [Watch; MAX_WATCHPOINTS] = [const { Watch::new() }; MAX_WATCHPOINTS];
+
+/// Serializes setting and clearing; the trap handlers never take it.
+static REGISTRY: Mutex<()> = Mutex::new(());
+
+/// The address-match triggers found by `init`: index and type.
+static TRIGGERS: Once<Vec<(usize, TriggerType)>> = Once::new();
+
+/// Set while a hart accesses trigger CSRs that may not exist, and set by
+/// the illegal-instruction handler when one did not.
+static PROBING: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+static FAULTED: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
+unsafe fn read_tselect() -> usize {
+    let value: usize;
+    asm!("csrr {}, 0x7a0", out(reg) value);
+    value
+}
+
+unsafe fn write_tselect(value: usize) {
+    asm!("csrw 0x7a0, {}", in(reg) value);
+}
+
+unsafe fn read_tdata1() -> usize {
+    let value: usize;
+    asm!("csrr {}, 0x7a1", out(reg) value);
+    value
+}
+
+unsafe fn write_tdata1(value: usize) {
+    asm!("csrw 0x7a1, {}", in(reg) value);
+}
+
+unsafe fn write_tdata2(value: usize) {
+    asm!("csrw 0x7a2, {}", in(reg) value);
+}
+
+unsafe fn read_tinfo() -> usize {
+    let value: usize;
+    asm!("csrr {}, 0x7a4", out(reg) value);
+    value
+}
+
+/// Programs `trigger`, writing `tdata1` last as the spec recommends;
+/// `tdata1` of 0 disables it. Returns `tdata1` as read back.
+unsafe fn program(trigger: usize, tdata1: usize, tdata2: usize) -> usize {
+    write_tselect(trigger);
+    write_tdata1(0);
+    write_tdata2(tdata2);
+    write_tdata1(tdata1);
+    read_tdata1()
+}
+
+/// Runs trigger CSR accesses that may not exist; `None` if one trapped.
+fn guarded<T>(access: impl FnOnce() -> T) -> Option<T> {
+    let hart = current_hart();
+    FAULTED[hart].store(false, Ordering::Relaxed);
+    PROBING[hart].store(true, Ordering::Release);
+    let value = access();
+    PROBING[hart].store(false, Ordering::Release);
+    (!FAULTED[hart].load(Ordering::Acquire)).then_some(value)
+}
+
+/// Skips a trigger CSR access that trapped while probing.
+fn illegal_instruction_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    let hart = current_hart();
+    if context.sstatus & SSTATUS_SPP == 0 || !PROBING[hart].load(Ordering::Acquire) {
+        return TrapHandlerResult::Pass;
+    }
+    let instruction = unsafe { Instruction::read(context.sepc) };
+    let csr = instruction.bits >> 20;
+    let is_csr_access = instruction.len == 4 && instruction.bits & 0x7f == 0x73 && (instruction.bits >> 12) & 0b111 != 0;
+    if !is_csr_access || !(CSR_TSELECT..=CSR_LAST).contains(&csr) {
+        return TrapHandlerResult::Pass;
+    }
+    FAULTED[hart].store(true, Ordering::Release);
+    context.sepc += 4;
+    TrapHandlerResult::Handled
+}
+
+/// Finds the triggers of this hart that can match addresses.
+fn enumerate() -> Vec<(usize, TriggerType)> {
+    let mut found = Vec::new();
+    for index in 0..MAX_TRIGGERS {
+        let selected = guarded(|| unsafe {
+            write_tselect(index);
+            read_tselect()
+        });
+        if selected != Some(index) {
+            break;
+        }
+        // tinfo lists the supported types; without it, tdata1 shows the current one.
+        let types = match guarded(|| unsafe { read_tinfo() }) {
+            Some(info) => info & 0xffff,
+            None => match guarded(|| unsafe { read_tdata1() }) {
+                Some(tdata1) => 1 << (tdata1 >> TDATA1_TYPE_SHIFT),
+                None => break,
+            },
+        };
+        // Type 0 alone: no trigger at this index, nor after it.
+        if types == 1 {
+            break;
+        }
+        if types & (1 << TriggerType::Mcontrol6 as usize) != 0 {
+            found.push((index, TriggerType::Mcontrol6));
+        } else if types & (1 << TriggerType::Mcontrol as usize) != 0 {
+            found.push((index, TriggerType::Mcontrol));
+        }
+    }
+    found
+}
+
+/// Checks that `len` bytes at `addr` form a range a watchpoint can cover.
+pub(super) fn check_range(addr: usize, len: usize) -> Result<(), WatchError> {
+    if addr == 0 || len == 0 || len > MAX_WATCH_LEN || !len.is_power_of_two() || addr % len != 0 {
+        return Err(WatchError::InvalidRange);
+    }
+    Ok(())
+}
+
+/// Number of triggers usable for watchpoints.
+pub fn available() -> usize {
+    TRIGGERS.get().map_or(0, |triggers| triggers.len().min(MAX_WATCHPOINTS))
+}
+
+pub fn is_available() -> bool {
+    available() > 0
+}
+
+fn find(addr: usize) -> Option<usize> {
+    WATCHES.iter().position(|watch| watch.addr.load(Ordering::Acquire) == addr)
+}
+
+/// Watches `len` bytes at `addr` for `kind` accesses by the kernel. Hits
+/// go to `handler`, or are reported on the console without one.
+///
+/// Uses a hardware trigger; store watchpoints fall back to page
+/// protection when none is available or free.
+pub fn set(addr: usize, len: usize, kind: WatchKind, handler: Option<WatchHandler>) -> Result<(), WatchError> {
+    check_range(addr, len)?;
+    match set_trigger(addr, len, kind, handler) {
+        Err(WatchError::Unavailable | WatchError::NoFreeTrigger) if kind == WatchKind::Store => {
+            pagewatch::set(addr, len, handler)
+        }
+        result => result,
+    }
+}
+
+fn set_trigger(addr: usize, len: usize, kind: WatchKind, handler: Option<WatchHandler>) -> Result<(), WatchError> {
+    let triggers = TRIGGERS.get().filter(|t| !t.is_empty()).ok_or(WatchError::Unavailable)?;
+    let _registry = REGISTRY.lock();
+    if find(addr).is_some() {
+        return Err(WatchError::AlreadyWatched);
+    }
+    let in_use: Vec<usize> = WATCHES
+        .iter()
+        .filter(|watch| watch.addr.load(Ordering::Acquire) != 0)
+        .map(Watch::trigger)
+        .collect();
+    let &(trigger, trigger_type) = triggers
+        .iter()
+        .find(|(index, _)| !in_use.contains(index))
+        .ok_or(WatchError::NoFreeTrigger)?;
+    let index = find(0).ok_or(WatchError::NoFreeTrigger)?;
+
+    let (matching, tdata2) = if len == 1 { (MATCH_EQUAL, addr) } else { (MATCH_NAPOT, addr | (len / 2 - 1)) };
+    let tdata1 = (trigger_type as usize) << TDATA1_TYPE_SHIFT
+        | matching << TDATA1_MATCH_SHIFT
+        | TDATA1_S
+        | kind.tdata1_bits();
+    let checked = 0xf << TDATA1_TYPE_SHIFT | 0xf << TDATA1_MATCH_SHIFT | TDATA1_S | TDATA1_LOAD | TDATA1_STORE;
+
+    let watch = &WATCHES[index];
+    unsafe {
+        *watch.len.get() = len;
+        *watch.kind.get() = kind;
+        *watch.trigger.get() = trigger;
+        *watch.tdata.get() = (tdata1, tdata2);
+        *watch.handler.get() = handler;
+    }
+    watch.hits.store(0, Ordering::Relaxed);
+    // A hit from an interrupt handler would select another trigger midway,
+    // or find the trigger armed before the watchpoint is published.
+    let was_enabled = trap::disable_interrupts();
+    let readback = unsafe { program(trigger, tdata1, tdata2) };
+    let accepted = readback & checked == tdata1 & checked;
+    if accepted {
+        unsafe { *watch.after.get() = trigger_type == TriggerType::Mcontrol && readback & MCONTROL_TIMING != 0 };
+        watch.addr.store(addr, Ordering::Release);
+    } else {
+        watch.disarm();
+    }
+    trap::restore_interrupts(was_enabled);
+    if accepted { Ok(()) } else { Err(WatchError::Rejected) }
+}
+
+/// Removes the watchpoint at `addr` and returns how many times it was hit.
+pub fn clear(addr: usize) -> Result<u64, WatchError> {
+    let _registry = REGISTRY.lock();
+    let Some(index) = find(addr).filter(|_| addr != 0) else {
+        return pagewatch::clear(addr);
+    };
+    let watch = &WATCHES[index];
+    let was_enabled = trap::disable_interrupts();
+    watch.disarm();
+    trap::restore_interrupts(was_enabled);
+    let hits = watch.hits.load(Ordering::Relaxed);
+    watch.addr.store(0, Ordering::Release);
+    Ok(hits)
+}
+
+/// Lists the set watchpoints, hardware and page-protection ones.
+pub fn watchpoints() -> Vec<WatchInfo> {
+    let triggered = WATCHES.iter().filter_map(|watch| {
+        let addr = watch.addr.load(Ordering::Acquire);
+        (addr != 0).then(|| WatchInfo {
+            addr,
+            len: watch.len(),
+            kind: unsafe { *watch.kind.get() },
+            mode: WatchMode::Trigger(watch.trigger()),
+            hits: watch.hits.load(Ordering::Relaxed),
+        })
+    });
+    triggered.chain(pagewatch::watchpoints()).collect()
+}
+
+/// Reports a hit of a watchpoint without its own handler.
+pub(super) fn report(hit: &WatchHit, context: &TrapContext) {
+    match hit.kind {
+        Some(kind) => warn_print!("Watchpoint {:#x}+{}: {} of {:#x} at {}",
+                                  hit.addr, hit.len, kind, hit.access, WithSymbol(hit.pc)),
+        None => warn_print!("Watchpoint {:#x}+{}: access to {:#x} before {}",
+                            hit.addr, hit.len, hit.access, WithSymbol(hit.pc)),
+    }
+    super::print_context(context);
+}
+
+/// Re-arms watchpoint `index` once the access that hit it has run.
+fn rearm(index: usize, _context: &mut TrapContext) {
+    WATCHES[index].arm();
+}
+
+fn breakpoint_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.sstatus & SSTATUS_SPP == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let instruction = unsafe { Instruction::read(context.sepc) };
+    if instruction.is_ebreak() {
+        return TrapHandlerResult::Pass;
+    }
+    let access = context.stval;
+    let Some(index) = WATCHES.iter().position(|watch| {
+        let addr = watch.addr.load(Ordering::Acquire);
+        addr != 0 && (addr..addr + watch.len()).contains(&access)
+    }) else {
+        return TrapHandlerResult::Pass;
+    };
+    let watch = &WATCHES[index];
+    watch.hits.fetch_add(1, Ordering::Relaxed);
+    let after = unsafe { *watch.after.get() };
+    let hit = WatchHit {
+        addr: watch.addr.load(Ordering::Relaxed),
+        len: watch.len(),
+        access,
+        pc: context.sepc,
+        kind: if after { None } else { WatchKind::of(instruction) },
+    };
+    match unsafe { *watch.handler.get() } {
+        Some(handler) => handler(&hit, context),
+        None => report(&hit, context),
+    }
+    if after || context.sepc != hit.pc {
+        return TrapHandlerResult::Handled;
+    }
+    // Let the access through with the trigger off.
+    watch.disarm();
+    if !step::begin_copy(context, Some((rearm, index))) {
+        warn_print!("Watchpoint {:#x}: cannot step {}, watchpoint left disarmed.", hit.addr, WithSymbol(hit.pc));
+    }
+    TrapHandlerResult::Handled
+}
+
+/// Registers the trap handlers and finds the usable triggers.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    trap::register_trap_handler(
+        TrapType::IllegalInstruction,
+        illegal_instruction_handler,
+        WATCH_HANDLER_PRIORITY,
+        "Trigger CSR Probe",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    trap::register_trap_handler(
+        TrapType::Breakpoint,
+        breakpoint_handler,
+        WATCH_HANDLER_PRIORITY,
+        "Hardware Watchpoint",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    TRIGGERS.call_once(enumerate);
+    Ok(())
+}
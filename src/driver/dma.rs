@@ -0,0 +1,180 @@
+// nt_rustos/src/driver/dma.rs
+
+//! # DMA Mapping
+//!
+//! Handing memory to a device takes two steps besides the transfer itself:
+//!
+//! - The device needs the buffer's bus address. Most kernel memory is
+//!   identity mapped, but kernel thread stacks are mapped elsewhere, so
+//!   `map_for_device` translates the buffer through the kernel page table
+//!   and rejects a buffer whose pages are not physically contiguous.
+//! - The CPU's accesses to the buffer must be ordered against the device's.
+//!   `map_for_device` and `sync_for_device` issue `wmb`, so the CPU's writes
+//!   to the buffer land before the writes that publish it, ring entries and
+//!   doorbells alike. `sync_for_cpu` issues `rmb` for buffers the device
+//!   writes, so reading the data is ordered after seeing the completion,
+//!   whether that is a ring index in memory or a device register.
+//!
+//! Devices on the `virt` machine are cache coherent, so no cache
+//! maintenance is needed on top of the fences.
+//!
+//! `DmaBuffer` allocates memory meant for a device, such as virtqueue
+//! rings: zeroed, aligned and physically contiguous.
+
+use super::{mmio, DriverError};
+use crate::init::alloc::{self, AllocPurpose};
+use crate::mm::{self, page_table, PAGE_SIZE};
+use core::ops::Range;
+use core::ptr;
+
+/// Who accesses a mapped buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// The device reads the buffer.
+    ToDevice,
+    /// The device writes the buffer.
+    FromDevice,
+    /// The device reads and writes the buffer.
+    Bidirectional,
+}
+
+/// A buffer mapped for a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DmaMapping {
+    pub cpu_addr: usize,
+    /// The address the device uses.
+    pub bus_addr: usize,
+    pub len: usize,
+    pub direction: Direction,
+}
+
+impl DmaMapping {
+    /// Returns `true` if the device may write the buffer.
+    pub fn device_writes(&self) -> bool {
+        self.direction != Direction::ToDevice
+    }
+}
+
+/// Returns the bus address of the kernel address `cpu_addr`: its physical
+/// address, which equals it before paging is enabled.
+pub fn bus_address(cpu_addr: usize) -> Option<usize> {
+    match mm::kernel_root() {
+        Some(root) => page_table::translate(root, cpu_addr).map(|(pa, _)| pa),
+        None => Some(cpu_addr),
+    }
+}
+
+/// Maps `len` bytes at `cpu_addr` for the device and orders the CPU's
+/// earlier writes to them before whatever publishes the buffer next.
+///
+/// Fails with `Unsupported` if the buffer is not mapped or not physically
+/// contiguous.
+pub fn map_for_device(cpu_addr: usize, len: usize, direction: Direction) -> Result<DmaMapping, DriverError> {
+    let bus_addr = bus_address(cpu_addr).ok_or(DriverError::Unsupported)?;
+    let end = cpu_addr.checked_add(len).ok_or(DriverError::Unsupported)?;
+    let mut page = (cpu_addr & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+    while page < end {
+        if bus_address(page) != Some(bus_addr + (page - cpu_addr)) {
+            return Err(DriverError::Unsupported);
+        }
+        page += PAGE_SIZE;
+    }
+    mmio::wmb();
+    Ok(DmaMapping { cpu_addr, bus_addr, len, direction })
+}
+
+/// Orders the CPU's writes to a mapped buffer since it was mapped before
+/// the device is told to read it again.
+pub fn sync_for_device(mapping: &DmaMapping) {
+    if mapping.direction != Direction::FromDevice {
+        mmio::wmb();
+    }
+}
+
+/// Orders the CPU's reads of a buffer the device wrote after the read that
+/// saw the device complete.
+pub fn sync_for_cpu(mapping: &DmaMapping) {
+    if mapping.device_writes() {
+        mmio::rmb();
+    }
+}
+
+/// Zeroed, physically contiguous memory for a device, freed on drop.
+pub struct DmaBuffer {
+    addr: usize,
+    bus_addr: usize,
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates `len` bytes aligned to `align`, a power of two.
+    pub fn new(len: usize, align: usize) -> Result<Self, DriverError> {
+        if len == 0 || !align.is_power_of_two() {
+            return Err(DriverError::Unsupported);
+        }
+        let base = alloc::alloc_aligned(len, align).ok_or(DriverError::InitFailed)?;
+        unsafe {
+            ptr::write_bytes(base, 0, len);
+        }
+        let _ = alloc::set_purpose(base, AllocPurpose::DriverBuffer);
+        let addr = base as usize;
+        match map_for_device(addr, len, Direction::Bidirectional) {
+            Ok(mapping) => Ok(Self { addr, bus_addr: mapping.bus_addr, len }),
+            Err(e) => {
+                alloc::dealloc(base);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the kernel address of the buffer.
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Returns the address the device uses.
+    pub fn bus_addr(&self) -> usize {
+        self.bus_addr
+    }
+
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the mapping of the whole buffer, for the `sync` calls.
+    pub fn mapping(&self) -> DmaMapping {
+        DmaMapping { cpu_addr: self.addr, bus_addr: self.bus_addr, len: self.len, direction: Direction::Bidirectional }
+    }
+
+    /// Maps the bytes `range` of the buffer like `map_for_device`, which
+    /// needs no translation since the buffer is contiguous.
+    ///
+    /// # Panics
+    /// If `range` lies outside the buffer.
+    pub fn map(&self, range: Range<usize>, direction: Direction) -> DmaMapping {
+        assert!(range.start <= range.end && range.end <= self.len, "DMA range outside the buffer");
+        if direction != Direction::FromDevice {
+            mmio::wmb();
+        }
+        DmaMapping {
+            cpu_addr: self.addr + range.start,
+            bus_addr: self.bus_addr + range.start,
+            len: range.len(),
+            direction,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.addr as *mut u8, self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        alloc::dealloc(self.addr as *mut u8);
+    }
+}
@@ -0,0 +1,295 @@
+// nt_rustos/src/driver/fb/mod.rs
+
+//! # Framebuffer Text Console
+//!
+//! A `Framebuffer` is an in-memory picture of 32-bit `0x00RRGGBB` pixels,
+//! row after row, for a display device to scan out. A `TextConsole` draws
+//! text on one with the 8x8 font in `font`: a grid of character cells with
+//! a cursor that wraps at the right edge and scrolls the picture up at the
+//! bottom.
+//!
+//! Kernel log lines carry ANSI escape sequences for their colors. These are
+//! consumed, not drawn: SGR foreground colors (30-37, 90-97) and resets are
+//! honoured, `ESC[2J` clears the screen and `ESC[H` homes the cursor; other
+//! sequences are dropped.
+//!
+//! Drawing records the pixel rows it touched, which `take_dirty` hands to
+//! the display driver so it only copies those to the device.
+
+pub mod font;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const BLACK: u32 = 0x00_0000;
+pub const LIGHT_GRAY: u32 = 0xAA_AAAA;
+
+/// The VGA palette: SGR colors 30-37, then the bright 90-97.
+const PALETTE: [u32; 16] = [
+    0x00_0000, 0xAA_0000, 0x00_AA00, 0xAA_5500, 0x00_00AA, 0xAA_00AA, 0x00_AAAA, 0xAA_AAAA, 0x55_5555, 0xFF_5555,
+    0x55_FF55, 0xFF_FF55, 0x55_55FF, 0xFF_55FF, 0x55_FFFF, 0xFF_FFFF,
+];
+
+/// Columns between tab stops.
+const TAB_WIDTH: usize = 8;
+/// Numeric parameters kept of an escape sequence.
+const MAX_PARAMS: usize = 4;
+
+/// A picture in memory.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    /// Creates a black picture of `width` x `height` pixels.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: vec![BLACK; width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixels, row after row.
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    /// Returns the pixel at (`x`, `y`), if it is on the picture.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u32> {
+        (x < self.width && y < self.height).then(|| self.pixels[y * self.width + x])
+    }
+
+    /// Sets the pixel at (`x`, `y`); pixels off the picture are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    /// Fills a rectangle, clipped to the picture.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        for row in y.min(y_end)..y_end {
+            let start = row * self.width;
+            self.pixels[start + x.min(x_end)..start + x_end].fill(color);
+        }
+    }
+
+    /// Draws `c` with its top left corner at (`x`, `y`).
+    pub fn draw_glyph(&mut self, x: usize, y: usize, c: char, fg: u32, bg: u32) {
+        for (dy, bits) in font::glyph(c).iter().enumerate() {
+            for dx in 0..font::WIDTH {
+                let color = if bits & (1 << dx) != 0 { fg } else { bg };
+                self.set_pixel(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    /// Moves the picture up by `lines` pixel rows and fills the rows freed
+    /// at the bottom with `color`.
+    pub fn scroll_up(&mut self, lines: usize, color: u32) {
+        let lines = lines.min(self.height);
+        self.pixels.copy_within(lines * self.width.., 0);
+        self.fill_rect(0, self.height - lines, self.width, lines, color);
+    }
+}
+
+/// Where the console is in an escape sequence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`.
+    Esc,
+    /// In a control sequence, after `ESC [`.
+    Csi,
+}
+
+/// A text terminal drawn on a `Framebuffer`.
+pub struct TextConsole {
+    fb: Framebuffer,
+    cols: usize,
+    rows: usize,
+    col: usize,
+    row: usize,
+    fg: u32,
+    bg: u32,
+    escape: Escape,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    /// Pixel rows changed since the last `take_dirty`.
+    dirty: Option<(usize, usize)>,
+}
+
+impl TextConsole {
+    /// Creates a console covering `fb`, which is cleared.
+    pub fn new(fb: Framebuffer) -> Self {
+        let mut console = Self {
+            cols: (fb.width / font::WIDTH).max(1),
+            rows: (fb.height / font::HEIGHT).max(1),
+            fb,
+            col: 0,
+            row: 0,
+            fg: LIGHT_GRAY,
+            bg: BLACK,
+            escape: Escape::None,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            dirty: None,
+        };
+        console.clear();
+        console
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.fb
+    }
+
+    /// Returns the picture for drawing on; all of it is taken as changed.
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        self.mark_dirty(0, self.fb.height);
+        &mut self.fb
+    }
+
+    /// Returns the number of columns and rows.
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Returns the cursor's column and row.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.col, self.row)
+    }
+
+    /// Clears the screen and homes the cursor.
+    pub fn clear(&mut self) {
+        let (width, height) = (self.fb.width, self.fb.height);
+        self.fb.fill_rect(0, 0, width, height, self.bg);
+        self.col = 0;
+        self.row = 0;
+        self.mark_dirty(0, height);
+    }
+
+    /// Returns the pixel rows changed since the last call, as a range.
+    pub fn take_dirty(&mut self) -> Option<(usize, usize)> {
+        self.dirty.take()
+    }
+
+    fn mark_dirty(&mut self, start: usize, end: usize) {
+        let end = end.min(self.fb.height);
+        self.dirty = match self.dirty {
+            Some((s, e)) => Some((s.min(start), e.max(end))),
+            None if start < end => Some((start, end)),
+            None => None,
+        };
+    }
+
+    /// Draws `s`, interpreting control characters and escape sequences.
+    pub fn write_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.put_char(c);
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        match self.escape {
+            Escape::Esc => {
+                self.escape = if c == '[' { Escape::Csi } else { Escape::None };
+                self.params = [0; MAX_PARAMS];
+                self.param_count = 0;
+                return;
+            }
+            Escape::Csi => {
+                self.escape_char(c);
+                return;
+            }
+            Escape::None => {}
+        }
+        match c {
+            '\x1b' => self.escape = Escape::Esc,
+            '\n' => self.newline(),
+            '\r' => self.col = 0,
+            '\t' => {
+                self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1);
+            }
+            '\x08' => self.col = self.col.saturating_sub(1),
+            c if c.is_control() => {}
+            c => {
+                if self.col >= self.cols {
+                    self.newline();
+                }
+                let (x, y) = (self.col * font::WIDTH, self.row * font::HEIGHT);
+                self.fb.draw_glyph(x, y, c, self.fg, self.bg);
+                self.mark_dirty(y, y + font::HEIGHT);
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Takes one character of a control sequence.
+    fn escape_char(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                let index = self.param_count.max(1) - 1;
+                self.param_count = self.param_count.max(1);
+                if index < MAX_PARAMS {
+                    let digit = c as u16 - '0' as u16;
+                    self.params[index] = self.params[index].saturating_mul(10).saturating_add(digit);
+                }
+            }
+            ';' => self.param_count = self.param_count.max(1) + 1,
+            '\x40'..='\x7e' => {
+                self.escape = Escape::None;
+                let count = self.param_count.min(MAX_PARAMS);
+                match c {
+                    'm' if count == 0 => self.select_graphics(0),
+                    'm' => {
+                        for i in 0..count {
+                            self.select_graphics(self.params[i]);
+                        }
+                    }
+                    'J' if self.params[0] == 2 => self.clear(),
+                    'H' => {
+                        self.col = 0;
+                        self.row = 0;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies an SGR parameter; only colors are supported.
+    fn select_graphics(&mut self, param: u16) {
+        match param {
+            0 => {
+                self.fg = LIGHT_GRAY;
+                self.bg = BLACK;
+            }
+            30..=37 => self.fg = PALETTE[(param - 30) as usize],
+            39 => self.fg = LIGHT_GRAY,
+            40..=47 => self.bg = PALETTE[(param - 40) as usize],
+            49 => self.bg = BLACK,
+            90..=97 => self.fg = PALETTE[(param - 90) as usize + 8],
+            _ => {}
+        }
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.fb.scroll_up(font::HEIGHT, self.bg);
+            self.mark_dirty(0, self.fb.height);
+        }
+    }
+}
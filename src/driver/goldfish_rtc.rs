@@ -0,0 +1,43 @@
+// nt_rustos/src/driver/goldfish_rtc.rs
+
+//! The Goldfish real-time clock.
+//!
+//! QEMU's `virt` machine has a `google,goldfish-rtc` device that counts
+//! nanoseconds since the Unix epoch, taken from the host clock. Reading the
+//! low word latches the high word, so the two halves of a reading belong
+//! together. The probe reads it once to set the wall clock (`time::now`);
+//! the alarm is not used.
+
+use super::mmio::ReadOnly;
+use super::{Device, Driver, DriverError};
+use crate::{info_print, time};
+
+crate::mmio_registers! {
+    struct RtcRegs {
+        0x00 => time_low: ReadOnly<u32>,
+        0x04 => time_high: ReadOnly<u32>,
+    }
+}
+
+/// Reads the clock, in nanoseconds since the epoch.
+fn read(regs: &RtcRegs) -> u64 {
+    let low = regs.time_low().read() as u64;
+    let high = regs.time_high().read() as u64;
+    high << 32 | low
+}
+
+fn probe(device: &Device) -> Result<(), DriverError> {
+    let region = device.region()?;
+    let regs = unsafe { RtcRegs::new(region.base) };
+    time::wall::set_unix_ns(read(&regs));
+    info_print!("rtc: {} set the clock to {}", device.path, time::now());
+    Ok(())
+}
+
+pub static GOLDFISH_RTC_DRIVER: Driver = Driver {
+    name: "goldfish-rtc",
+    compatible: &["google,goldfish-rtc"],
+    probe,
+    irq_handler: None,
+    remove: None,
+};
@@ -0,0 +1,204 @@
+// nt_rustos/src/driver/irq.rs
+
+//! # Device Interrupt Bindings
+//!
+//! A driver with an `irq_handler` does not register interrupt numbers
+//! itself: when one of its devices is probed successfully, the driver model
+//! binds every interrupt in the device's `interrupts` to the handler, with
+//! the device, and unbinding the device releases them again. Interrupts
+//! whose parent is the PLIC are enabled there; others, such as those of
+//! devices found on a bus, are bound but not routed, and reach their
+//! handler only through `dispatch`.
+//!
+//! A single `ExternalInterrupt` trap handler claims pending sources from
+//! the PLIC and calls the handlers bound to each, so several devices may
+//! share an interrupt.
+//!
+//! Handlers run in the trap handler with the binding table locked, and must
+//! not bind or unbind devices. Outside the trap handler the table is only
+//! locked with interrupts disabled.
+
+use super::{plic, Device};
+use crate::init::initcall::InitResult;
+use crate::trap::{
+    self, Interrupt, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use crate::{info_print, println, warn_print};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// `sie.SEIE`: supervisor external interrupt enable.
+const SIE_SEIE: usize = 1 << 9;
+
+/// Priority of the external interrupt handler.
+const EXTERNAL_HANDLER_PRIORITY: u8 = 60;
+
+/// Handles interrupt `irq` of `device`.
+pub type IrqHandler = fn(irq: u32, device: &Device);
+
+struct Binding {
+    irq: u32,
+    device: Device,
+    driver: &'static str,
+    handler: IrqHandler,
+    /// Whether the interrupt is enabled at the PLIC.
+    routed: bool,
+    count: u64,
+}
+
+/// An interrupt bound to a device, as listed by `bindings`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqBinding {
+    pub irq: u32,
+    /// Path of the device.
+    pub path: String,
+    pub driver: &'static str,
+    pub routed: bool,
+    /// Times the handler was called.
+    pub count: u64,
+}
+
+static BINDINGS: Mutex<Vec<Binding>> = Mutex::new(Vec::new());
+/// Claimed interrupts no handler was bound to.
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// Runs `f` on the binding table with interrupts disabled.
+fn with_bindings<R>(f: impl FnOnce(&mut Vec<Binding>) -> R) -> R {
+    let was_enabled = trap::disable_interrupts();
+    let result = f(&mut BINDINGS.lock());
+    trap::restore_interrupts(was_enabled);
+    result
+}
+
+/// Binds every interrupt of `device` to `handler` of `driver`, enabling at
+/// the PLIC those it routes. Returns how many were routed.
+pub fn bind_device(device: &Device, driver: &'static str, handler: IrqHandler) -> usize {
+    let mut routed = 0;
+    with_bindings(|bindings| {
+        for &irq in &device.irqs {
+            let route = plic::routes(device.irq_parent, irq);
+            if route {
+                plic::enable(irq);
+                routed += 1;
+            }
+            bindings.push(Binding { irq, device: device.clone(), driver, handler, routed: route, count: 0 });
+        }
+    });
+    if routed < device.irqs.len() && device.irq_parent.is_some() && plic::is_present() {
+        warn_print!("irq: {} of {}'s interrupts not routed through the PLIC", device.irqs.len() - routed, device.path);
+    }
+    routed
+}
+
+/// Releases the interrupts bound to the device at `path`, disabling at the
+/// PLIC those no other device is routed on. Returns how many were bound.
+pub fn unbind_device(path: &str) -> usize {
+    with_bindings(|bindings| {
+        let before = bindings.len();
+        let mut released = Vec::new();
+        bindings.retain(|b| {
+            let keep = b.device.path != path;
+            if !keep && b.routed {
+                released.push(b.irq);
+            }
+            keep
+        });
+        for irq in released {
+            if !bindings.iter().any(|b| b.irq == irq && b.routed) {
+                plic::disable(irq);
+            }
+        }
+        before - bindings.len()
+    })
+}
+
+/// Calls the handlers bound to `irq`. Returns `false` if there are none.
+pub fn dispatch(irq: u32) -> bool {
+    with_bindings(|bindings| {
+        let mut handled = false;
+        for binding in bindings.iter_mut().filter(|b| b.irq == irq) {
+            binding.count += 1;
+            (binding.handler)(irq, &binding.device);
+            handled = true;
+        }
+        handled
+    })
+}
+
+/// Returns the bound interrupts, in binding order.
+pub fn bindings() -> Vec<IrqBinding> {
+    with_bindings(|bindings| {
+        bindings
+            .iter()
+            .map(|b| IrqBinding {
+                irq: b.irq,
+                path: b.device.path.clone(),
+                driver: b.driver,
+                routed: b.routed,
+                count: b.count,
+            })
+            .collect()
+    })
+}
+
+/// Returns how many claimed interrupts had no handler.
+pub fn spurious() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+/// Prints the bound interrupts.
+pub fn print() {
+    let list = bindings();
+    println!("Device interrupts ({}):", list.len());
+    for b in &list {
+        let routed = if b.routed { "plic" } else { "-" };
+        println!("  {:>4} {:<5} {:>8}  {} [{}]", b.irq, routed, b.count, b.path, b.driver);
+    }
+    println!("  {} spurious", spurious());
+}
+
+fn external_interrupt_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    let cause = context.cause();
+    if !cause.is_interrupt() || cause.code() != Interrupt::SupervisorExternal as usize {
+        return TrapHandlerResult::Pass;
+    }
+    while let Some(irq) = plic::claim() {
+        if !dispatch(irq) {
+            SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        }
+        plic::complete(irq);
+    }
+    TrapHandlerResult::Handled
+}
+
+/// Sets up the PLIC before devices are probed, registers the external
+/// interrupt handler and enables external interrupts.
+fn init_irq() -> InitResult {
+    if !plic::init() {
+        info_print!("No PLIC; device interrupts are not routed.");
+        return Ok(());
+    }
+    trap::register_trap_handler(
+        TrapType::ExternalInterrupt,
+        external_interrupt_handler,
+        EXTERNAL_HANDLER_PRIORITY,
+        "Device Interrupts",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )
+    .map_err(|e| format!("external interrupt handler not registered: {:?}", e))?;
+    unsafe {
+        asm!("csrs sie, {}", in(reg) SIE_SEIE);
+    }
+    if let Some(plic) = plic::get() {
+        info_print!("PLIC at {:#x}: {} sources, context {}.", plic.base, plic.ndev, plic.context);
+    }
+    Ok(())
+}
+
+crate::initcall!(subsys, 90, init_irq);
@@ -0,0 +1,158 @@
+// nt_rustos/src/driver/mmio.rs
+
+//! # Typed MMIO Registers
+//!
+//! `ReadOnly`, `WriteOnly` and `ReadWrite` wrap a device register of type
+//! `T` so that every access is a single volatile load or store of the
+//! register's width, and the type says which accesses make sense.
+//! `mmio_registers!` declares a register block: a handle holding the
+//! block's base address with one accessor per register at a fixed offset.
+//!
+//! Volatile accesses are not ordered against normal memory accesses on
+//! RISC-V. The fence helpers provide the orderings drivers need, named after
+//! their Linux counterparts: `wmb` before a doorbell write that makes the
+//! device read memory the CPU just wrote, `rmb` after a status read before
+//! reading memory the device wrote, and `dma_wmb`/`dma_rmb` between
+//! accesses to memory shared with a device.
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ptr;
+
+/// A register that is only read.
+#[repr(transparent)]
+pub struct ReadOnly<T: Copy>(UnsafeCell<T>);
+
+/// A register that is only written.
+#[repr(transparent)]
+pub struct WriteOnly<T: Copy>(UnsafeCell<T>);
+
+/// A register that is read and written.
+#[repr(transparent)]
+pub struct ReadWrite<T: Copy>(UnsafeCell<T>);
+
+impl<T: Copy> ReadOnly<T> {
+    #[inline(always)]
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+}
+
+impl<T: Copy> WriteOnly<T> {
+    #[inline(always)]
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+}
+
+impl<T: Copy> ReadWrite<T> {
+    #[inline(always)]
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.0.get()) }
+    }
+
+    #[inline(always)]
+    pub fn write(&self, value: T) {
+        unsafe { ptr::write_volatile(self.0.get(), value) }
+    }
+
+    /// Reads the register, applies `f` and writes the result back. The
+    /// read and write are separate accesses.
+    #[inline(always)]
+    pub fn modify(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+}
+
+/// Returns the register of type `R` at `addr`.
+///
+/// # Safety
+/// `addr` must be the mapped, suitably aligned address of a device register
+/// that stays mapped for `'a`.
+#[inline(always)]
+pub unsafe fn register<'a, R>(addr: usize) -> &'a R {
+    &*(addr as *const R)
+}
+
+/// Declares a register block handle.
+///
+/// ```ignore
+/// mmio_registers! {
+///     /// A UART.
+///     pub struct UartRegs {
+///         0x00 => data: ReadWrite<u8>,
+///         0x05 => line_status: ReadOnly<u8>,
+///     }
+/// }
+/// let uart = unsafe { UartRegs::new(0x1000_0000) };
+/// uart.data().write(b'x');
+/// ```
+#[macro_export]
+macro_rules! mmio_registers {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($offset:literal => $reg:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone)]
+        $vis struct $name {
+            base: usize,
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            /// Creates a handle for the block at `base`.
+            ///
+            /// # Safety
+            /// `base` must be the mapped address of such a register block
+            /// for as long as the handle is used.
+            pub const unsafe fn new(base: usize) -> Self {
+                Self { base }
+            }
+
+            /// Returns the base address of the block.
+            pub fn base(&self) -> usize {
+                self.base
+            }
+
+            $(
+                #[inline(always)]
+                pub fn $reg(&self) -> &$ty {
+                    unsafe { $crate::driver::mmio::register(self.base + $offset) }
+                }
+            )*
+        }
+    };
+}
+
+/// Orders all earlier memory and I/O accesses before all later ones.
+#[inline(always)]
+pub fn mb() {
+    unsafe { asm!("fence iorw, iorw", options(nostack, preserves_flags)) }
+}
+
+/// Orders earlier reads, including device reads, before later reads.
+#[inline(always)]
+pub fn rmb() {
+    unsafe { asm!("fence ir, ir", options(nostack, preserves_flags)) }
+}
+
+/// Orders earlier writes, including device writes, before later writes.
+#[inline(always)]
+pub fn wmb() {
+    unsafe { asm!("fence ow, ow", options(nostack, preserves_flags)) }
+}
+
+/// Orders earlier reads of memory shared with a device before later ones.
+#[inline(always)]
+pub fn dma_rmb() {
+    unsafe { asm!("fence r, r", options(nostack, preserves_flags)) }
+}
+
+/// Orders earlier writes to memory shared with a device before later ones.
+#[inline(always)]
+pub fn dma_wmb() {
+    unsafe { asm!("fence w, w", options(nostack, preserves_flags)) }
+}
@@ -0,0 +1,372 @@
+// nt_rustos/src/driver/mod.rs
+
+//! # Device/Driver Model
+//!
+//! Drivers declare the device tree `compatible` strings they handle and a
+//! probe function. At boot `probe_all` walks the device tree, matches every
+//! enabled node against the registered drivers (trying the node's most
+//! specific `compatible` string first) and calls the probe function with
+//! the node's memory regions and interrupts. Devices whose probe succeeds
+//! are recorded as bound, in device tree order, and can be listed with
+//! `print_devices`.
+//!
+//! Built-in drivers are listed in `BUILTIN_DRIVERS` and registered by
+//! `init`; others can be added with `register_driver` before probing. Bus
+//! drivers offer the devices they discover, which have no device tree
+//! node, with `probe_device`.
+//!
+//! A driver with an `irq_handler` gets the interrupts of its devices bound
+//! to it when they are probed (see `irq`); `unbind` releases them and calls
+//! the driver's `remove` function.
+
+pub mod dma;
+pub mod fb;
+pub mod goldfish_rtc;
+pub mod irq;
+pub mod mmio;
+pub mod pci;
+pub mod plic;
+pub mod sifive_test;
+pub mod virtio;
+
+use crate::boot;
+use crate::fdt::{Fdt, FdtError, Node, MAX_DEPTH};
+use crate::init::initcall::InitResult;
+use crate::println;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+/// Errors returned by the driver model.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DriverError {
+    /// A driver with the same name is already registered.
+    AlreadyRegistered,
+    /// The node describes an empty slot; there is nothing to bind.
+    NotPresent,
+    /// No device tree was passed at boot.
+    NoDeviceTree,
+    /// The device tree is malformed.
+    BadDeviceTree(FdtError),
+    /// The device lacks a resource the driver needs.
+    MissingResource,
+    /// The device is present but not usable by the driver.
+    Unsupported,
+    /// Setting up the device failed.
+    InitFailed,
+    /// No device is bound at the path.
+    NotBound,
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyRegistered => write!(f, "driver already registered"),
+            Self::NotPresent => write!(f, "no device present"),
+            Self::NoDeviceTree => write!(f, "no device tree available"),
+            Self::BadDeviceTree(e) => write!(f, "malformed device tree: {:?}", e),
+            Self::MissingResource => write!(f, "device resource missing"),
+            Self::Unsupported => write!(f, "device not supported"),
+            Self::InitFailed => write!(f, "device initialization failed"),
+            Self::NotBound => write!(f, "no device bound there"),
+        }
+    }
+}
+
+/// A memory-mapped register region of a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MmioRegion {
+    pub base: usize,
+    pub size: usize,
+}
+
+/// A device found in the device tree or on a bus, as handed to a probe
+/// function.
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// Full device tree path, e.g. `/soc/serial@10000000`; bus devices
+    /// extend the path of their bus node.
+    pub path: String,
+    /// The `compatible` string the driver was matched on.
+    pub compatible: &'static str,
+    /// The `reg` regions, in device tree order.
+    pub regions: Vec<MmioRegion>,
+    /// The interrupt numbers from the `interrupts` property.
+    pub irqs: Vec<u32>,
+    /// The `phandle` of the interrupt controller numbering `irqs`: the
+    /// node's `interrupt-parent` or its nearest ancestor's. Devices found
+    /// by enumerating a bus have none.
+    pub irq_parent: Option<u32>,
+    /// The device tree node, for driver-specific properties. Devices found
+    /// by enumerating a bus have none.
+    pub node: Option<Node>,
+}
+
+impl Device {
+    /// Returns the last component of the path: for device tree devices the
+    /// node name with its unit address.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Returns the nesting depth of the path; top-level devices are at 1.
+    pub fn depth(&self) -> usize {
+        self.path.matches('/').count()
+    }
+
+    /// Returns the first register region.
+    pub fn region(&self) -> Result<MmioRegion, DriverError> {
+        self.regions.first().copied().ok_or(DriverError::MissingResource)
+    }
+
+    /// Returns the first interrupt.
+    pub fn irq(&self) -> Result<u32, DriverError> {
+        self.irqs.first().copied().ok_or(DriverError::MissingResource)
+    }
+}
+
+/// Sets up a matched device.
+pub type ProbeFn = fn(&Device) -> Result<(), DriverError>;
+
+/// Shuts down a device that is being unbound.
+pub type RemoveFn = fn(&Device);
+
+/// A driver and the devices it handles.
+pub struct Driver {
+    pub name: &'static str,
+    /// Device tree `compatible` strings the driver handles.
+    pub compatible: &'static [&'static str],
+    pub probe: ProbeFn,
+    /// Handles the interrupts of the driver's devices, which are bound to
+    /// it after a successful probe.
+    pub irq_handler: Option<irq::IrqHandler>,
+    /// Called when a device is unbound, after its interrupts are released.
+    pub remove: Option<RemoveFn>,
+}
+
+/// A device bound to a driver.
+#[derive(Debug, Clone)]
+pub struct BoundDevice {
+    pub device: Device,
+    pub driver: &'static str,
+}
+
+/// Drivers built into the kernel, registered by `init`.
+static BUILTIN_DRIVERS: &[&Driver] = &[
+    &virtio::VIRTIO_MMIO_DRIVER,
+    &pci::PCI_HOST_DRIVER,
+    &virtio::VIRTIO_PCI_DRIVER,
+    &sifive_test::SIFIVE_TEST_DRIVER,
+    &goldfish_rtc::GOLDFISH_RTC_DRIVER,
+];
+
+static DRIVERS: Mutex<Vec<&'static Driver>> = Mutex::new(Vec::new());
+static DEVICES: Mutex<Vec<BoundDevice>> = Mutex::new(Vec::new());
+
+/// Registers a driver for later probing.
+pub fn register_driver(driver: &'static Driver) -> Result<(), DriverError> {
+    let mut drivers = DRIVERS.lock();
+    if drivers.iter().any(|d| d.name == driver.name) {
+        return Err(DriverError::AlreadyRegistered);
+    }
+    drivers.push(driver);
+    Ok(())
+}
+
+/// Returns the driver handling `compatible`, if any.
+fn find_driver(compatible: &str) -> Option<&'static Driver> {
+    DRIVERS
+        .lock()
+        .iter()
+        .copied()
+        .find(|d| d.compatible.contains(&compatible))
+}
+
+/// Outcome of a probe pass.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ProbeSummary {
+    /// Devices bound to a driver in this pass.
+    pub bound: usize,
+    /// Devices whose driver's probe failed.
+    pub failed: usize,
+}
+
+/// Matches the nodes of `fdt` to registered drivers and probes them.
+///
+/// Nodes that are already bound are skipped, so the pass can be repeated
+/// after registering more drivers. Probes reporting `NotPresent` are
+/// neither bound nor counted as failed.
+pub fn probe_all(fdt: &Fdt) -> Result<ProbeSummary, DriverError> {
+    let mut summary = ProbeSummary::default();
+    let mut names: [&str; MAX_DEPTH + 1] = [""; MAX_DEPTH + 1];
+    // `interrupt-parent` is inherited from the nearest ancestor naming one.
+    let mut irq_parents: [Option<u32>; MAX_DEPTH + 1] = [None; MAX_DEPTH + 1];
+
+    for node in fdt.nodes() {
+        let node = node.map_err(DriverError::BadDeviceTree)?;
+        names[node.depth] = node.name;
+        let inherited = if node.depth > 0 { irq_parents[node.depth - 1] } else { None };
+        irq_parents[node.depth] = node.property("interrupt-parent").and_then(|p| p.as_u32()).or(inherited);
+        if !node.is_enabled() {
+            continue;
+        }
+        let found = node
+            .compatible()
+            .find_map(|c| find_driver(c).map(|driver| (c, driver)));
+        let Some((compatible, driver)) = found else {
+            continue;
+        };
+        let path = node_path(&names[1..=node.depth]);
+        if DEVICES.lock().iter().any(|b| b.device.path == path) {
+            continue;
+        }
+
+        let device = Device {
+            path,
+            compatible,
+            regions: node
+                .reg()
+                .map(|r| MmioRegion { base: r.address as usize, size: r.size as usize })
+                .collect(),
+            irqs: node.interrupts().collect(),
+            irq_parent: irq_parents[node.depth],
+            node: Some(node),
+        };
+        match bind(driver, device) {
+            Ok(true) => summary.bound += 1,
+            Ok(false) => {}
+            Err(_) => summary.failed += 1,
+        }
+    }
+    Ok(summary)
+}
+
+/// Probes `device` with `driver` and records it if the probe succeeds,
+/// binding its interrupts to the driver's handler. Returns `Ok(false)` if
+/// the driver found nothing to bind.
+fn bind(driver: &'static Driver, device: Device) -> Result<bool, DriverError> {
+    // Probed without holding the registry locks: probe functions may look
+    // up other devices or probe devices behind the one they handle.
+    match (driver.probe)(&device) {
+        Ok(()) => {
+            if let Some(handler) = driver.irq_handler {
+                irq::bind_device(&device, driver.name, handler);
+            }
+            DEVICES.lock().push(BoundDevice { device, driver: driver.name });
+            Ok(true)
+        }
+        Err(DriverError::NotPresent) => Ok(false),
+        Err(e) => {
+            crate::warn_print!("driver {}: probe of {} failed: {}", driver.name, device.path, e);
+            Err(e)
+        }
+    }
+}
+
+/// Matches a device found outside the device tree, such as a function on
+/// an enumerated bus, to a registered driver by its `compatible` strings
+/// (most specific first) and probes it.
+///
+/// Returns `Ok(false)` if no driver matches, the device is already bound or
+/// the driver found nothing to bind.
+pub fn probe_device(
+    path: String,
+    compatible: &[&str],
+    regions: Vec<MmioRegion>,
+    irqs: Vec<u32>,
+) -> Result<bool, DriverError> {
+    let found = compatible.iter().find_map(|c| {
+        let driver = find_driver(c)?;
+        // Devices refer to the driver's copy of the string, which is static.
+        driver.compatible.iter().find(|dc| *dc == c).map(|dc| (*dc, driver))
+    });
+    let Some((compatible, driver)) = found else {
+        return Ok(false);
+    };
+    if DEVICES.lock().iter().any(|b| b.device.path == path) {
+        return Ok(false);
+    }
+    bind(driver, Device { path, compatible, regions, irqs, irq_parent: None, node: None })
+}
+
+/// Unbinds the device at `path`: releases its interrupts, then calls its
+/// driver's `remove` function. The device may be probed again afterwards.
+pub fn unbind(path: &str) -> Result<BoundDevice, DriverError> {
+    let bound = {
+        let mut devices = DEVICES.lock();
+        let index = devices.iter().position(|b| b.device.path == path).ok_or(DriverError::NotBound)?;
+        devices.remove(index)
+    };
+    irq::unbind_device(path);
+    let remove = DRIVERS.lock().iter().find(|d| d.name == bound.driver).and_then(|d| d.remove);
+    if let Some(remove) = remove {
+        remove(&bound.device);
+    }
+    Ok(bound)
+}
+
+fn node_path(names: &[&str]) -> String {
+    if names.is_empty() {
+        return String::from("/");
+    }
+    let mut path = String::new();
+    for name in names {
+        path.push('/');
+        path.push_str(name);
+    }
+    path
+}
+
+/// Registers the built-in drivers and probes the device tree passed at
+/// boot.
+pub fn init() -> Result<ProbeSummary, DriverError> {
+    for driver in BUILTIN_DRIVERS {
+        match register_driver(driver) {
+            Ok(()) | Err(DriverError::AlreadyRegistered) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let info = boot::boot_info().ok_or(DriverError::NoDeviceTree)?;
+    let fdt = unsafe { Fdt::from_addr(info.dtb_addr) }.map_err(DriverError::BadDeviceTree)?;
+    probe_all(&fdt)
+}
+
+/// Probes the device tree; drivers may register interrupt handlers, block
+/// devices and network interfaces.
+fn probe_devices() -> InitResult {
+    let _timing = crate::boot::timing::measure(crate::boot::timing::Phase::Devices);
+    let summary = init().map_err(|e| format!("device probe skipped: {}", e))?;
+    crate::info_print!("Device probe: {} bound, {} failed.", summary.bound, summary.failed);
+    Ok(())
+}
+
+crate::initcall!(device, 10, probe_devices);
+
+/// Returns the bound devices, in device tree order.
+pub fn devices() -> Vec<BoundDevice> {
+    DEVICES.lock().clone()
+}
+
+/// Returns the bound device at `path`.
+pub fn find_device(path: &str) -> Option<BoundDevice> {
+    DEVICES.lock().iter().find(|b| b.device.path == path).cloned()
+}
+
+/// Prints the bound devices as a tree, with their drivers and resources.
+pub fn print_devices() {
+    let devices = DEVICES.lock();
+    println!("Bound devices ({}):", devices.len());
+    for bound in devices.iter() {
+        let device = &bound.device;
+        let indent = device.depth().saturating_sub(1) * 2;
+        println!("  {:indent$}{} [{}] driver={}", "", device.name(), device.compatible, bound.driver, indent = indent);
+        for region in &device.regions {
+            println!("  {:indent$}  mmio {:#x}+{:#x}", "", region.base, region.size, indent = indent);
+        }
+        if !device.irqs.is_empty() {
+            println!("  {:indent$}  irqs {:?}", "", device.irqs, indent = indent);
+        }
+    }
+}
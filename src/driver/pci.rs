@@ -0,0 +1,464 @@
+// nt_rustos/src/driver/pci.rs
+
+//! # PCIe over ECAM
+//!
+//! Drives a `pci-host-ecam-generic` host bridge such as the one on QEMU's
+//! virt machine. Probing the host scans its root bus for functions, sizes
+//! their BARs and assigns them addresses from the windows in the host's
+//! `ranges` property, routes their legacy INTx pin through the host's
+//! `interrupt-map` and enables decoding and bus mastering. Each function is
+//! then offered to the driver model with the compatible strings
+//! `pciVVVV,DDDD` and `pciclass,CCSSPP`, with its memory BARs as regions.
+//!
+//! Only the root bus is scanned; functions behind PCI-PCI bridges are
+//! listed but their secondary buses are left unconfigured. 64-bit memory
+//! BARs are placed in the 32-bit window, which the kernel's identity map
+//! covers.
+
+use super::mmio::{self, ReadWrite};
+use super::{Device, Driver, DriverError, MmioRegion};
+use crate::fdt::Node;
+use crate::info_print;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+const BAR_COUNT: usize = 6;
+
+const CFG_VENDOR_ID: usize = 0x00;
+const CFG_DEVICE_ID: usize = 0x02;
+const CFG_COMMAND: usize = 0x04;
+const CFG_STATUS: usize = 0x06;
+const CFG_CLASS_REVISION: usize = 0x08;
+const CFG_HEADER_TYPE: usize = 0x0e;
+const CFG_BAR0: usize = 0x10;
+const CFG_SUBSYSTEM_ID: usize = 0x2e;
+const CFG_CAPABILITIES: usize = 0x34;
+const CFG_INTERRUPT_LINE: usize = 0x3c;
+const CFG_INTERRUPT_PIN: usize = 0x3d;
+
+const COMMAND_IO: u16 = 1 << 0;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const HEADER_TYPE_MASK: u8 = 0x7f;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+/// Header type of a PCI-PCI bridge; bridges have only two BARs.
+const HEADER_BRIDGE: u8 = 0x01;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_TYPE_MASK: u32 = 0x6;
+const BAR_TYPE_64: u32 = 0x4;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// Address space codes in bits 24-25 of a `ranges` child address.
+const SPACE_IO: u32 = 1;
+const SPACE_MEM32: u32 = 2;
+const SPACE_MEM64: u32 = 3;
+
+/// The location of a function on the bus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// The kind of a BAR.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BarKind {
+    Io,
+    Mem32,
+    Mem64,
+}
+
+/// An assigned base address register.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bar {
+    pub kind: BarKind,
+    pub prefetchable: bool,
+    /// Address on the PCI bus, as programmed into the BAR.
+    pub bus_addr: u64,
+    /// Address at which the CPU reaches the BAR.
+    pub cpu_addr: usize,
+    pub size: u64,
+}
+
+/// A function found while scanning the bus.
+#[derive(Debug, Clone)]
+pub struct PciFunction {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    /// Class, subclass and programming interface.
+    pub class: u32,
+    pub revision: u8,
+    pub subsystem_id: u16,
+    pub header_type: u8,
+    /// BARs by index; the upper half of a 64-bit BAR is `None`.
+    pub bars: [Option<Bar>; BAR_COUNT],
+    /// Interrupt routed from the function's INTx pin.
+    pub irq: Option<u32>,
+    /// Path under which the function was offered to the driver model.
+    pub path: String,
+    /// Address of the function's configuration space.
+    config_base: usize,
+}
+
+impl PciFunction {
+    fn reg<T: Copy>(&self, offset: usize) -> &ReadWrite<T> {
+        unsafe { mmio::register(self.config_base + offset) }
+    }
+
+    pub fn read_config_u8(&self, offset: usize) -> u8 {
+        self.reg::<u8>(offset).read()
+    }
+
+    pub fn read_config_u16(&self, offset: usize) -> u16 {
+        self.reg::<u16>(offset).read()
+    }
+
+    pub fn read_config_u32(&self, offset: usize) -> u32 {
+        self.reg::<u32>(offset).read()
+    }
+
+    pub fn write_config_u16(&self, offset: usize, value: u16) {
+        self.reg::<u16>(offset).write(value)
+    }
+
+    pub fn write_config_u32(&self, offset: usize, value: u32) {
+        self.reg::<u32>(offset).write(value)
+    }
+
+    /// Iterates over the capability list as (capability ID, offset).
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        let mut next = if self.read_config_u16(CFG_STATUS) & STATUS_CAPABILITIES != 0 {
+            (self.read_config_u8(CFG_CAPABILITIES) & !3) as usize
+        } else {
+            0
+        };
+        // Bounds a malformed, looping list.
+        let mut budget = 48;
+        core::iter::from_fn(move || {
+            if next == 0 || budget == 0 {
+                return None;
+            }
+            budget -= 1;
+            let offset = next;
+            next = (self.read_config_u8(offset + 1) & !3) as usize;
+            Some((self.read_config_u8(offset), offset))
+        })
+    }
+
+    /// Returns the BAR at `index`.
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.get(index).copied().flatten()
+    }
+
+    fn is_bridge(&self) -> bool {
+        self.header_type & HEADER_TYPE_MASK == HEADER_BRIDGE
+    }
+}
+
+/// An address window of the host bridge, handed out bottom-up.
+#[derive(Debug, Copy, Clone)]
+struct Window {
+    bus_base: u64,
+    cpu_base: u64,
+    size: u64,
+    next: u64,
+}
+
+impl Window {
+    /// Allocates `size` bytes aligned to `size`, returning the bus address.
+    fn alloc(&mut self, size: u64) -> Option<u64> {
+        let start = (self.next + size - 1) & !(size - 1);
+        let end = start.checked_add(size)?;
+        if end > self.bus_base + self.size {
+            return None;
+        }
+        self.next = end;
+        Some(start)
+    }
+
+    fn cpu_addr(&self, bus_addr: u64) -> usize {
+        (bus_addr - self.bus_base + self.cpu_base) as usize
+    }
+}
+
+/// Reads a number spanning `cells` cells.
+fn read_cells(cells: &[u32]) -> u64 {
+    cells.iter().fold(0u64, |value, cell| (value << 32) | *cell as u64)
+}
+
+fn own_cells(node: &Node, name: &str, default: u32) -> usize {
+    node.property(name).and_then(|p| p.as_u32()).unwrap_or(default) as usize
+}
+
+/// Parses the host's `ranges` into I/O and 32-bit memory windows.
+fn windows(node: &Node) -> (Option<Window>, Option<Window>) {
+    let child_cells = own_cells(node, "#address-cells", 3);
+    let size_cells = own_cells(node, "#size-cells", 2);
+    let parent_cells = node.address_cells as usize;
+    let entry = child_cells + parent_cells + size_cells;
+    let cells: Vec<u32> = node.property("ranges").map(|p| p.cells().collect()).unwrap_or_default();
+
+    let (mut io, mut mem) = (None, None);
+    for range in cells.chunks_exact(entry) {
+        let space = (range[0] >> 24) & 0x3;
+        let bus_base = read_cells(&range[1..child_cells]);
+        let window = Window {
+            bus_base,
+            cpu_base: read_cells(&range[child_cells..child_cells + parent_cells]),
+            size: read_cells(&range[child_cells + parent_cells..]),
+            next: bus_base,
+        };
+        match space {
+            SPACE_IO => io = Some(window),
+            // Only windows reachable with 32-bit addresses are used.
+            SPACE_MEM32 | SPACE_MEM64 if window.bus_base + window.size <= 1 << 32 => {
+                if mem.map_or(true, |m: Window| window.size > m.size) {
+                    mem = Some(window);
+                }
+            }
+            _ => {}
+        }
+    }
+    (io, mem)
+}
+
+/// Routes INTx `pin` (1-4) of the function at `address` through the host's
+/// `interrupt-map`.
+fn route_irq(node: &Node, address: PciAddress, pin: u8) -> Option<u32> {
+    let mask: Vec<u32> = node.property("interrupt-map-mask")?.cells().collect();
+    let map: Vec<u32> = node.property("interrupt-map")?.cells().collect();
+    if mask.len() != 4 {
+        return None;
+    }
+    let unit = [
+        ((address.bus as u32) << 16) | ((address.device as u32) << 11) | ((address.function as u32) << 8),
+        0,
+        0,
+        pin as u32,
+    ];
+    let tree = node.tree();
+    let mut rest = &map[..];
+    while rest.len() > 5 {
+        let parent = tree.find_phandle(rest[4])?;
+        let parent_address = own_cells(&parent, "#address-cells", 0);
+        let parent_interrupt = own_cells(&parent, "#interrupt-cells", 1);
+        let len = 5 + parent_address + parent_interrupt;
+        if rest.len() < len {
+            return None;
+        }
+        if (0..4).all(|i| unit[i] & mask[i] == rest[i]) {
+            return rest.get(5 + parent_address).copied();
+        }
+        rest = &rest[len..];
+    }
+    None
+}
+
+/// Functions found on all host bridges.
+static FUNCTIONS: Mutex<Vec<PciFunction>> = Mutex::new(Vec::new());
+
+/// Sizes, assigns and programs the BARs of `function`.
+fn assign_bars(function: &mut PciFunction, io: &mut Option<Window>, mem: &mut Option<Window>) {
+    let count = if function.is_bridge() { 2 } else { BAR_COUNT };
+    let mut index = 0;
+    while index < count {
+        let offset = CFG_BAR0 + index * 4;
+        let original = function.read_config_u32(offset);
+        let is_64 = original & BAR_IO == 0 && original & BAR_TYPE_MASK == BAR_TYPE_64;
+        function.write_config_u32(offset, u32::MAX);
+        let mut probe = function.read_config_u32(offset) as u64;
+        if is_64 {
+            function.write_config_u32(offset + 4, u32::MAX);
+            probe |= (function.read_config_u32(offset + 4) as u64) << 32;
+        } else {
+            probe |= 0xffff_ffff_u64 << 32;
+        }
+
+        let (kind, flag_mask) = if original & BAR_IO != 0 {
+            (BarKind::Io, 0x3)
+        } else if is_64 {
+            (BarKind::Mem64, 0xf)
+        } else {
+            (BarKind::Mem32, 0xf)
+        };
+        // The size is given by the lowest address bit that reads back set;
+        // I/O BARs may implement only 16 bits.
+        let mask = probe & !flag_mask;
+        let size = 1u64 << mask.trailing_zeros().min(63);
+        let window = if kind == BarKind::Io { io.as_mut() } else { mem.as_mut() };
+        let assigned = match window {
+            Some(window) if mask != 0 => window.alloc(size).map(|bus| (bus, window.cpu_addr(bus))),
+            _ => None,
+        };
+
+        let bus_addr = assigned.map_or(0, |(bus, _)| bus);
+        function.write_config_u32(offset, bus_addr as u32 | (original & flag_mask as u32));
+        if is_64 {
+            function.write_config_u32(offset + 4, (bus_addr >> 32) as u32);
+        }
+        function.bars[index] = assigned.map(|(bus_addr, cpu_addr)| Bar {
+            kind,
+            prefetchable: original & BAR_PREFETCHABLE != 0,
+            bus_addr,
+            cpu_addr,
+            size,
+        });
+        index += if is_64 { 2 } else { 1 };
+    }
+}
+
+/// Scans the root bus behind the host bridge `host`.
+fn scan(host: &Device, node: &Node, ecam: MmioRegion) -> Vec<PciFunction> {
+    let (mut io, mut mem) = windows(node);
+    let bus = node
+        .property("bus-range")
+        .and_then(|p| p.cells().next())
+        .unwrap_or(0) as u8;
+    let mut found = Vec::new();
+
+    for device in 0..DEVICES_PER_BUS {
+        for function in 0..FUNCTIONS_PER_DEVICE {
+            let address = PciAddress { bus, device, function };
+            // ECAM places the bus number relative to the start of the region.
+            let config_base = ecam.base + ((device as usize) << 15 | (function as usize) << 12);
+            if config_base + 0x1000 > ecam.base + ecam.size {
+                break;
+            }
+            let mut pci = PciFunction {
+                address,
+                vendor_id: 0,
+                device_id: 0,
+                class: 0,
+                revision: 0,
+                subsystem_id: 0,
+                header_type: 0,
+                bars: [None; BAR_COUNT],
+                irq: None,
+                path: format!("{}/{}", host.path, address),
+                config_base,
+            };
+            pci.vendor_id = pci.read_config_u16(CFG_VENDOR_ID);
+            if pci.vendor_id == 0xffff {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+            pci.device_id = pci.read_config_u16(CFG_DEVICE_ID);
+            let class_revision = pci.read_config_u32(CFG_CLASS_REVISION);
+            pci.class = class_revision >> 8;
+            pci.revision = class_revision as u8;
+            pci.header_type = pci.read_config_u8(CFG_HEADER_TYPE);
+            if !pci.is_bridge() {
+                pci.subsystem_id = pci.read_config_u16(CFG_SUBSYSTEM_ID);
+            }
+
+            let command = pci.read_config_u16(CFG_COMMAND);
+            pci.write_config_u16(CFG_COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+            assign_bars(&mut pci, &mut io, &mut mem);
+            let mut command = command | COMMAND_BUS_MASTER;
+            if pci.bars.iter().flatten().any(|b| b.kind == BarKind::Io) {
+                command |= COMMAND_IO;
+            }
+            if pci.bars.iter().flatten().any(|b| b.kind != BarKind::Io) {
+                command |= COMMAND_MEMORY;
+            }
+            pci.write_config_u16(CFG_COMMAND, command);
+
+            let pin = pci.read_config_u8(CFG_INTERRUPT_PIN);
+            if (1..=4).contains(&pin) {
+                pci.irq = route_irq(node, address, pin);
+                if let Some(irq) = pci.irq {
+                    pci.reg::<u8>(CFG_INTERRUPT_LINE).write(irq as u8);
+                }
+            }
+
+            let multifunction = pci.header_type & HEADER_MULTIFUNCTION != 0;
+            found.push(pci);
+            if function == 0 && !multifunction {
+                break;
+            }
+        }
+    }
+    found
+}
+
+fn probe(host: &Device) -> Result<(), DriverError> {
+    let node = host.node.ok_or(DriverError::MissingResource)?;
+    let ecam = host.region()?;
+    let functions = scan(host, &node, ecam);
+    info_print!("pci: {} function(s) behind {}", functions.len(), host.path);
+    FUNCTIONS.lock().extend(functions.iter().cloned());
+
+    for function in functions.iter().filter(|f| !f.is_bridge()) {
+        let id = format!("pci{:04x},{:04x}", function.vendor_id, function.device_id);
+        let class = format!("pciclass,{:06x}", function.class);
+        let regions = function
+            .bars
+            .iter()
+            .flatten()
+            .filter(|b| b.kind != BarKind::Io)
+            .map(|b| MmioRegion { base: b.cpu_addr, size: b.size as usize })
+            .collect();
+        let irqs = function.irq.into_iter().collect();
+        // Failures are reported by the driver model; other functions are
+        // still probed.
+        let _ = super::probe_device(function.path.clone(), &[&id, &class], regions, irqs);
+    }
+    Ok(())
+}
+
+/// The driver for ECAM host bridges.
+pub static PCI_HOST_DRIVER: Driver = Driver {
+    name: "pci-host-ecam",
+    compatible: &["pci-host-ecam-generic"],
+    probe,
+    irq_handler: None,
+    remove: None,
+};
+
+/// Returns all functions found so far, in bus order.
+pub fn functions() -> Vec<PciFunction> {
+    FUNCTIONS.lock().clone()
+}
+
+/// Returns the function offered to the driver model under `path`.
+pub fn function(path: &str) -> Option<PciFunction> {
+    FUNCTIONS.lock().iter().find(|f| f.path == path).cloned()
+}
+
+/// Prints the functions found, with their BARs and interrupts.
+pub fn print_functions() {
+    let functions = FUNCTIONS.lock();
+    crate::println!("PCI functions ({}):", functions.len());
+    for f in functions.iter() {
+        crate::println!(
+            "  {} {:04x}:{:04x} class {:06x}{}",
+            f.address,
+            f.vendor_id,
+            f.device_id,
+            f.class,
+            f.irq.map(|irq| format!(" irq {}", irq)).unwrap_or_default()
+        );
+        for (index, bar) in f.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                crate::println!("    BAR{} {:?} {:#x}+{:#x}", index, bar.kind, bar.cpu_addr, bar.size);
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+// nt_rustos/src/driver/plic.rs
+
+//! # Platform-Level Interrupt Controller
+//!
+//! The PLIC (`riscv,plic0`) gathers the interrupts of the devices and
+//! raises a supervisor external interrupt on the harts whose context has
+//! them enabled. The kernel uses the S-mode context of the boot hart: each
+//! source it enables gets priority 1 against a threshold of 0, and the
+//! external interrupt handler claims pending sources one by one and
+//! completes each once its handler has run (see `driver::irq`).
+//!
+//! Registers, for source `n` and context `c`:
+//!
+//! - priority: `base + 4 * n`;
+//! - enable bits: `base + 0x2000 + 0x80 * c`, one bit per source;
+//! - threshold: `base + 0x20_0000 + 0x1000 * c`, claim/complete 4 bytes
+//!   after it.
+//!
+//! The context is found through the PLIC's `interrupts-extended`, whose
+//! n-th entry names the interrupt controller of a hart and the interrupt
+//! that context raises there; without it the `virt` machine's numbering,
+//! M-mode then S-mode context for every hart, is assumed.
+
+use super::mmio::{self, ReadWrite};
+use crate::boot;
+use crate::fdt::{Fdt, Node};
+use crate::trap::Interrupt;
+use spin::Once;
+
+const PRIORITY_OFFSET: usize = 0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CLAIM_OFFSET: usize = 4;
+
+/// Largest source number a PLIC can have.
+const MAX_SOURCES: u32 = 1023;
+/// Priority given to enabled sources; 0 would mask them.
+const DEFAULT_PRIORITY: u32 = 1;
+
+/// The PLIC found in the device tree.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Plic {
+    pub base: usize,
+    /// Highest source number (`riscv,ndev`).
+    pub ndev: u32,
+    /// Context of the boot hart's S-mode.
+    pub context: usize,
+    /// The `phandle` devices name as their `interrupt-parent`.
+    pub phandle: Option<u32>,
+}
+
+static PLIC: Once<Option<Plic>> = Once::new();
+
+/// Returns the phandle of the interrupt controller of hart `hart_id`.
+fn hart_intc(fdt: &Fdt, hart_id: usize) -> Option<u32> {
+    let mut cpu_depth = None;
+    for node in fdt.nodes().filter_map(Result::ok) {
+        match cpu_depth {
+            Some(depth) if node.depth == depth + 1 && node.property("interrupt-controller").is_some() => {
+                return node.property("phandle").and_then(|p| p.as_u32());
+            }
+            Some(depth) if node.depth > depth => continue,
+            _ => cpu_depth = None,
+        }
+        let is_cpu = node.property("device_type").and_then(|p| p.as_str()) == Some("cpu");
+        if is_cpu && node.reg().next().map(|r| r.address as usize) == Some(hart_id) {
+            cpu_depth = Some(node.depth);
+        }
+    }
+    None
+}
+
+/// Returns the context in which `node` raises the supervisor external
+/// interrupt of hart `hart_id`.
+fn s_mode_context(fdt: &Fdt, node: &Node, hart_id: usize) -> usize {
+    let intc = hart_intc(fdt, hart_id);
+    let cells = node.property("interrupts-extended").map(|p| p.cells());
+    let found = intc.zip(cells).and_then(|(intc, mut cells)| {
+        let mut context = 0;
+        while let (Some(phandle), Some(cause)) = (cells.next(), cells.next()) {
+            if phandle == intc && cause == Interrupt::SupervisorExternal as u32 {
+                return Some(context);
+            }
+            context += 1;
+        }
+        None
+    });
+    found.unwrap_or(2 * hart_id + 1)
+}
+
+fn probe() -> Option<Plic> {
+    let info = boot::boot_info()?;
+    let fdt = unsafe { Fdt::from_addr(info.dtb_addr) }.ok()?;
+    let node = fdt
+        .nodes()
+        .filter_map(Result::ok)
+        .find(|n| n.is_enabled() && n.compatible().any(|c| c == "riscv,plic0" || c == "sifive,plic-1.0.0"))?;
+    let base = node.reg().next()?.address as usize;
+    let ndev = node
+        .property("riscv,ndev")
+        .and_then(|p| p.as_u32())
+        .unwrap_or(MAX_SOURCES)
+        .min(MAX_SOURCES);
+    Some(Plic {
+        base,
+        ndev,
+        context: s_mode_context(&fdt, &node, info.hart_id),
+        phandle: node.property("phandle").and_then(|p| p.as_u32()),
+    })
+}
+
+impl Plic {
+    fn reg(&self, offset: usize) -> &ReadWrite<u32> {
+        unsafe { mmio::register(self.base + offset) }
+    }
+
+    fn enable_word(&self, irq: u32) -> &ReadWrite<u32> {
+        self.reg(ENABLE_OFFSET + ENABLE_STRIDE * self.context + 4 * (irq as usize / 32))
+    }
+
+    fn threshold(&self) -> &ReadWrite<u32> {
+        self.reg(CONTEXT_OFFSET + CONTEXT_STRIDE * self.context)
+    }
+
+    fn claim_complete(&self) -> &ReadWrite<u32> {
+        self.reg(CONTEXT_OFFSET + CONTEXT_STRIDE * self.context + CLAIM_OFFSET)
+    }
+
+    /// Returns whether `irq` is a source of this PLIC; source 0 does not
+    /// exist.
+    pub fn has_source(&self, irq: u32) -> bool {
+        irq != 0 && irq <= self.ndev
+    }
+}
+
+/// Finds the PLIC, masks all its sources for the boot hart's context and
+/// opens the context's threshold. Only the first call has an effect.
+/// Returns `false` if the device tree describes no PLIC.
+pub fn init() -> bool {
+    PLIC.call_once(|| {
+        let plic = probe()?;
+        for word in 0..=plic.ndev / 32 {
+            plic.enable_word(word * 32).write(0);
+        }
+        plic.threshold().write(0);
+        Some(plic)
+    })
+    .is_some()
+}
+
+/// Returns the PLIC, once `init` has found it.
+pub fn get() -> Option<Plic> {
+    PLIC.get().copied().flatten()
+}
+
+pub fn is_present() -> bool {
+    get().is_some()
+}
+
+/// Returns whether interrupts of a device whose interrupt parent is
+/// `parent` and whose number is `irq` can be routed through the PLIC.
+pub fn routes(parent: Option<u32>, irq: u32) -> bool {
+    get().map_or(false, |plic| plic.phandle.is_some() && plic.phandle == parent && plic.has_source(irq))
+}
+
+/// Gives `irq` the default priority and enables it for the boot hart.
+pub fn enable(irq: u32) {
+    if let Some(plic) = get().filter(|p| p.has_source(irq)) {
+        plic.reg(PRIORITY_OFFSET + 4 * irq as usize).write(DEFAULT_PRIORITY);
+        plic.enable_word(irq).modify(|word| word | (1 << (irq % 32)));
+    }
+}
+
+/// Stops `irq` from interrupting the boot hart.
+pub fn disable(irq: u32) {
+    if let Some(plic) = get().filter(|p| p.has_source(irq)) {
+        plic.enable_word(irq).modify(|word| word & !(1 << (irq % 32)));
+    }
+}
+
+/// Claims the highest-priority pending source, if any.
+pub fn claim() -> Option<u32> {
+    get().map(|plic| plic.claim_complete().read()).filter(|&irq| irq != 0)
+}
+
+/// Signals that the handler of a claimed `irq` has finished, so the source
+/// can interrupt again.
+pub fn complete(irq: u32) {
+    if let Some(plic) = get() {
+        plic.claim_complete().write(irq);
+    }
+}
@@ -0,0 +1,81 @@
+// nt_rustos/src/driver/sifive_test.rs
+
+//! The SiFive test finisher.
+//!
+//! QEMU's `virt` machine has a `sifive,test0` device with a single
+//! write-only register: writing `PASS` exits QEMU with status 0, writing
+//! `code << 16 | FAIL` exits with status `code`, and writing `RESET`
+//! resets the machine. Unlike SBI SRST it can report an exit status, which
+//! is what automated test runs need, and it works on firmware without SRST.
+//!
+//! The register is found through the bound device, or the platform
+//! description before drivers are probed, so a panic during boot can still
+//! exit.
+
+use super::mmio::{self, WriteOnly};
+use super::{Device, Driver, DriverError};
+use crate::platform;
+use crate::util::sbi::{system, system_reset};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const FAIL: u32 = 0x3333;
+const PASS: u32 = 0x5555;
+const RESET: u32 = 0x7777;
+
+/// Address of the finisher register of the bound device, or 0.
+static REGISTER: AtomicUsize = AtomicUsize::new(0);
+
+fn probe(device: &Device) -> Result<(), DriverError> {
+    let region = device.region()?;
+    REGISTER.store(region.base, Ordering::Release);
+    Ok(())
+}
+
+pub static SIFIVE_TEST_DRIVER: Driver = Driver {
+    name: "sifive-test",
+    compatible: &["sifive,test1", "sifive,test0"],
+    probe,
+    irq_handler: None,
+    remove: None,
+};
+
+/// Returns the address of the finisher register, if the platform has one.
+pub fn register_addr() -> Option<usize> {
+    match REGISTER.load(Ordering::Acquire) {
+        0 => platform::get().test_device.map(|region| region.base),
+        addr => Some(addr),
+    }
+}
+
+/// Returns `true` if the platform has a test finisher.
+pub fn is_present() -> bool {
+    register_addr().is_some()
+}
+
+/// Writes `value` to the finisher. Returns only if there is none, or the
+/// write did not stop the machine.
+fn finish(value: u32) {
+    if let Some(addr) = register_addr() {
+        mmio::mb();
+        unsafe { mmio::register::<WriteOnly<u32>>(addr) }.write(value);
+    }
+}
+
+/// Exits QEMU with status `code`: 0 reports success, anything else
+/// failure. Without a finisher the machine is shut down through SBI, which
+/// cannot report the status beyond success or system failure.
+pub fn exit(code: u16) -> ! {
+    finish(if code == 0 { PASS } else { (code as u32) << 16 | FAIL });
+    let reason = if code == 0 {
+        system_reset::RESET_REASON_NO_REASON
+    } else {
+        system_reset::RESET_REASON_SYSTEM_FAILURE
+    };
+    system_reset::system_reset(system_reset::RESET_TYPE_SHUTDOWN, reason)
+}
+
+/// Resets the machine, through SBI if there is no finisher.
+pub fn reset() -> ! {
+    finish(RESET);
+    system::reboot()
+}
@@ -0,0 +1,229 @@
+// nt_rustos/src/driver/virtio/blk.rs
+
+//! virtio block device.
+//!
+//! Every device is registered with the block layer as `vda`, `vdb`, ...
+//! Requests are issued one at a time: a read-only header, the data
+//! buffer and a status byte the device writes, chained in one descriptor
+//! chain. Transfers larger than `MAX_TRANSFER` are split. Buffers are
+//! mapped with `dma::map_for_device`, so a data buffer must be physically
+//! contiguous: heap memory, or a kernel stack buffer within one page.
+
+use super::{device_id, Transport, VirtQueue, VirtioDriver};
+use crate::block::{self, BlockDevice, BlockError};
+use crate::driver::dma::{self, Direction};
+use crate::driver::{Device, DriverError};
+use crate::info_print;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+const F_RO: u64 = 1 << 5;
+const F_BLK_SIZE: u64 = 1 << 6;
+const F_FLUSH: u64 = 1 << 9;
+
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+
+/// virtio-blk addresses the device in 512-byte sectors.
+const SECTOR_SIZE: usize = 512;
+/// Largest transfer per request.
+const MAX_TRANSFER: usize = 64 * 1024;
+const QUEUE_SIZE: u16 = 8;
+/// How long to wait for the device to complete a request.
+const REQUEST_TIMEOUT_MS: u64 = 1000;
+
+/// The request header the device reads.
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+struct Channel {
+    transport: Transport,
+    queue: VirtQueue,
+    header: RequestHeader,
+    status: u8,
+    /// Set after a timeout, when the device was reset.
+    broken: bool,
+}
+
+impl Channel {
+    /// Performs one request; `data` is written to the device for `T_OUT`
+    /// and filled by it for `T_IN`.
+    fn request(&mut self, kind: u32, sector: u64, data: Option<(usize, usize)>) -> Result<(), BlockError> {
+        if self.broken {
+            return Err(BlockError::Io);
+        }
+        self.header = RequestHeader { kind, reserved: 0, sector };
+        self.status = 0xff;
+        let map = |addr: usize, len: usize, direction| {
+            dma::map_for_device(addr, len, direction).map_err(|_| BlockError::Io)
+        };
+        let header = map(
+            &self.header as *const RequestHeader as usize,
+            core::mem::size_of::<RequestHeader>(),
+            Direction::ToDevice,
+        )?;
+        let status = map(&mut self.status as *mut u8 as usize, 1, Direction::FromDevice)?;
+        let data = match data {
+            Some((addr, len)) => {
+                let direction = if kind == T_IN { Direction::FromDevice } else { Direction::ToDevice };
+                Some(map(addr, len, direction)?)
+            }
+            None => None,
+        };
+        let added = match data {
+            Some(data) => self.queue.add(&[header.into(), data.into(), status.into()]),
+            None => self.queue.add(&[header.into(), status.into()]),
+        };
+        added.ok_or(BlockError::Io)?;
+        let completed = self.transport.submit_and_wait(&mut self.queue, REQUEST_TIMEOUT_MS);
+        if completed.is_ok() {
+            dma::sync_for_cpu(&status);
+            data.iter().for_each(dma::sync_for_cpu);
+        }
+        match completed {
+            Ok(_) if unsafe { core::ptr::read_volatile(&self.status) } == S_OK => Ok(()),
+            Ok(_) => Err(BlockError::Io),
+            Err(_) => {
+                // The device may still own the buffers; stop it for good.
+                self.transport.reset();
+                self.broken = true;
+                Err(BlockError::Io)
+            }
+        }
+    }
+}
+
+pub struct VirtioBlk {
+    name: String,
+    sectors: u64,
+    block_size: usize,
+    read_only: bool,
+    flush_supported: bool,
+    channel: Mutex<Channel>,
+}
+
+impl VirtioBlk {
+    /// Transfers the blocks at `lba` in chunks of at most `MAX_TRANSFER`.
+    fn transfer(&self, kind: u32, lba: u64, addr: usize, len: usize) -> Result<(), BlockError> {
+        let mut channel = self.channel.lock();
+        let sectors_per_block = (self.block_size / SECTOR_SIZE) as u64;
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(MAX_TRANSFER);
+            let sector = lba * sectors_per_block + (done / SECTOR_SIZE) as u64;
+            channel.request(kind, sector, Some((addr + done, chunk)))?;
+            done += chunk;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors / (self.block_size / SECTOR_SIZE) as u64
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.transfer(T_IN, lba, buf.as_mut_ptr() as usize, buf.len())
+    }
+
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        self.transfer(T_OUT, lba, buf.as_ptr() as usize, buf.len())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if !self.flush_supported {
+            // Without the feature writes are durable once complete.
+            return Ok(());
+        }
+        self.channel.lock().request(T_FLUSH, 0, None)
+    }
+}
+
+/// Number of virtio block devices probed so far, for naming.
+static PROBED: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) static DRIVER: VirtioDriver = VirtioDriver {
+    name: "virtio-blk",
+    device_id: device_id::BLOCK,
+    probe,
+};
+
+fn probe(_device: &Device, transport: Transport) -> Result<(), DriverError> {
+    let features = transport.begin_init(F_RO | F_BLK_SIZE | F_FLUSH)?;
+    let queue = match transport.setup_queue(0, QUEUE_SIZE) {
+        Ok(queue) => queue,
+        Err(e) => {
+            transport.fail();
+            return Err(e);
+        }
+    };
+    transport.driver_ok();
+
+    // Read as two halves: the MMIO transport only allows 32-bit accesses.
+    let sectors = transport.config::<u32>(CONFIG_CAPACITY).read() as u64
+        | (transport.config::<u32>(CONFIG_CAPACITY + 4).read() as u64) << 32;
+    let block_size = if features & F_BLK_SIZE != 0 {
+        transport.config::<u32>(CONFIG_BLK_SIZE).read() as usize
+    } else {
+        SECTOR_SIZE
+    };
+    // The block layer needs whole sectors per block.
+    let block_size = if block_size.is_power_of_two() && block_size >= SECTOR_SIZE { block_size } else { SECTOR_SIZE };
+
+    let index = PROBED.fetch_add(1, Ordering::Relaxed);
+    let name = format!("vd{}", (b'a' + (index % 26) as u8) as char);
+    let device = VirtioBlk {
+        name,
+        sectors,
+        block_size,
+        read_only: features & F_RO != 0,
+        flush_supported: features & F_FLUSH != 0,
+        channel: Mutex::new(Channel {
+            transport,
+            queue,
+            header: RequestHeader { kind: 0, reserved: 0, sector: 0 },
+            status: 0,
+            broken: false,
+        }),
+    };
+    info_print!(
+        "virtio-blk: {} with {} sectors ({} KB){}",
+        device.name,
+        sectors,
+        sectors * SECTOR_SIZE as u64 / 1024,
+        if device.read_only { ", read-only" } else { "" }
+    );
+    match block::register(Arc::new(device)) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(DriverError::InitFailed),
+    }
+}
@@ -0,0 +1,506 @@
+// nt_rustos/src/driver/virtio/console.rs
+
+//! virtio console device.
+//!
+//! Handles both the single-port device and the multiport variant, where a
+//! control queue announces ports, marks one of them as the console and
+//! reports whether the host side of each port is connected. Output to a
+//! port is copied into per-descriptor DMA bounce buffers; when the transmit
+//! queue is full the writer waits for the device to return buffers, up to
+//! a timeout. Input is buffered per port, and receive buffers are only
+//! re-posted while that backlog has room, so a reader that falls behind
+//! stalls the host rather than losing bytes.
+//!
+//! If the platform has no 16550 UART the console port takes over kernel
+//! console output. Output that finds the device busy, for example from a
+//! trap handler, goes through the emergency write register when the device
+//! offers one and to the SBI console otherwise.
+
+use super::{device_id, Buffer, Transport, VirtQueue, VirtioDriver};
+use crate::console::{self, ConsoleDriver};
+use crate::driver::dma::{self, Direction, DmaBuffer};
+use crate::driver::mmio::{self, WriteOnly};
+use crate::driver::{Device, DriverError};
+use crate::platform;
+use crate::time;
+use crate::util::sbi;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+const F_SIZE: u64 = 1 << 0;
+const F_MULTIPORT: u64 = 1 << 1;
+const F_EMERG_WRITE: u64 = 1 << 2;
+
+const CONFIG_COLS: usize = 0;
+const CONFIG_ROWS: usize = 2;
+const CONFIG_MAX_NR_PORTS: usize = 4;
+const CONFIG_EMERG_WR: usize = 8;
+
+/// Control message events.
+const DEVICE_READY: u16 = 0;
+const DEVICE_ADD: u16 = 1;
+const DEVICE_REMOVE: u16 = 2;
+const PORT_READY: u16 = 3;
+const CONSOLE_PORT: u16 = 4;
+const RESIZE: u16 = 5;
+const PORT_OPEN: u16 = 6;
+const PORT_NAME: u16 = 7;
+
+/// Size of a control message header: id, event, value.
+const CONTROL_HEADER: usize = 8;
+
+const QUEUE_SIZE: u16 = 8;
+const DATA_BUF_SIZE: usize = 256;
+const CONTROL_BUF_SIZE: usize = 64;
+/// Ports the driver sets up queues for.
+const MAX_PORTS: usize = 4;
+/// Input bytes buffered per port before receive buffers stop being posted.
+const INPUT_LIMIT: usize = 1024;
+/// How long a writer waits for the device to free a transmit buffer.
+const TX_TIMEOUT_MS: u64 = 10;
+/// How long probing waits for the device to announce its ports.
+const ANNOUNCE_TIMEOUT_MS: u64 = 10;
+
+/// A queue and one bounce buffer per descriptor.
+struct Channel {
+    queue: VirtQueue,
+    buffers: DmaBuffer,
+    buf_size: usize,
+}
+
+impl Channel {
+    fn new(transport: &Transport, index: u16, buf_size: usize) -> Result<Self, DriverError> {
+        let queue = transport.setup_queue(index, QUEUE_SIZE)?;
+        let buffers = DmaBuffer::new(queue.size() as usize * buf_size, core::mem::align_of::<u64>())?;
+        Ok(Self { queue, buffers, buf_size })
+    }
+
+    fn slot(&self, head: u16) -> core::ops::Range<usize> {
+        let start = head as usize * self.buf_size;
+        start..start + self.buf_size
+    }
+
+    /// Hands every free descriptor to the device as a receive buffer.
+    fn post_receive(&mut self) {
+        while let Some(head) = self.queue.next_head() {
+            let mapping = self.buffers.map(self.slot(head), Direction::FromDevice);
+            self.queue.add(&[Buffer::from(mapping)]);
+        }
+    }
+
+    /// Takes the next buffer the device filled.
+    fn receive(&mut self) -> Option<&[u8]> {
+        let (head, len) = self.queue.pop_used()?;
+        let range = self.slot(head);
+        let len = (len as usize).min(self.buf_size);
+        dma::sync_for_cpu(&self.buffers.mapping());
+        Some(&self.buffers.as_slice()[range.start..range.start + len])
+    }
+
+    /// Queues `bytes` (at most one buffer's worth) for transmission,
+    /// waiting for a free buffer if necessary.
+    fn send(&mut self, transport: &Transport, bytes: &[u8]) -> Result<(), DriverError> {
+        let deadline = time::deadline_after_ms(TX_TIMEOUT_MS);
+        let head = loop {
+            while self.queue.pop_used().is_some() {}
+            if let Some(head) = self.queue.next_head() {
+                break head;
+            }
+            if time::reached(deadline) {
+                return Err(DriverError::InitFailed);
+            }
+            core::hint::spin_loop();
+        };
+        let range = self.slot(head);
+        self.buffers.as_mut_slice()[range.start..range.start + bytes.len()].copy_from_slice(bytes);
+        let mapping = self.buffers.map(range.start..range.start + bytes.len(), Direction::ToDevice);
+        self.queue.add(&[Buffer::from(mapping)]);
+        transport.notify(&self.queue);
+        Ok(())
+    }
+}
+
+struct Port {
+    id: u32,
+    rx: Channel,
+    tx: Channel,
+    input: VecDeque<u8>,
+    /// Announced by the device. Always set in single-port mode.
+    added: bool,
+    /// The host side is connected.
+    host_open: bool,
+    console: bool,
+    name: Option<String>,
+    /// Output bytes dropped because the host was not connected or did not
+    /// drain the transmit queue in time.
+    dropped: usize,
+}
+
+impl Port {
+    /// Moves received bytes into the input backlog while it has room.
+    fn pump_input(&mut self) {
+        while self.input.len() < INPUT_LIMIT {
+            match self.rx.receive() {
+                Some(bytes) => self.input.extend(bytes.iter().copied()),
+                None => break,
+            }
+        }
+        if self.input.len() < INPUT_LIMIT {
+            self.rx.post_receive();
+        }
+    }
+}
+
+/// Information about a console port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortInfo {
+    pub id: u32,
+    pub name: Option<String>,
+    pub host_open: bool,
+    pub console: bool,
+    pub dropped: usize,
+}
+
+struct VirtioConsole {
+    transport: Transport,
+    /// Receive and transmit channels of the control queue pair.
+    control: Option<(Channel, Channel)>,
+    ports: Vec<Port>,
+    size: Option<(u16, u16)>,
+}
+
+impl VirtioConsole {
+    fn port_mut(&mut self, id: u32) -> Option<&mut Port> {
+        self.ports.iter_mut().find(|p| p.id == id && p.added)
+    }
+
+    fn send_control(&mut self, id: u32, event: u16, value: u16) {
+        let Some((_, tx)) = self.control.as_mut() else {
+            return;
+        };
+        let mut msg = [0u8; CONTROL_HEADER];
+        msg[..4].copy_from_slice(&id.to_le_bytes());
+        msg[4..6].copy_from_slice(&event.to_le_bytes());
+        msg[6..].copy_from_slice(&value.to_le_bytes());
+        if tx.send(&self.transport, &msg).is_err() {
+            crate::warn_print!("virtio-console: control message {} for port {} not sent", event, id);
+        }
+    }
+
+    /// Processes pending control messages and received data.
+    fn poll(&mut self) {
+        let mut messages: Vec<(u32, u16, u16, Vec<u8>)> = Vec::new();
+        if let Some((rx, _)) = self.control.as_mut() {
+            while let Some(msg) = rx.receive() {
+                if msg.len() >= CONTROL_HEADER {
+                    let id = u32::from_le_bytes([msg[0], msg[1], msg[2], msg[3]]);
+                    let event = u16::from_le_bytes([msg[4], msg[5]]);
+                    let value = u16::from_le_bytes([msg[6], msg[7]]);
+                    messages.push((id, event, value, msg[CONTROL_HEADER..].to_vec()));
+                }
+            }
+            rx.post_receive();
+        }
+        for (id, event, value, payload) in messages {
+            self.handle_control(id, event, value, &payload);
+        }
+        for port in self.ports.iter_mut().filter(|p| p.added) {
+            port.pump_input();
+        }
+    }
+
+    fn handle_control(&mut self, id: u32, event: u16, value: u16, payload: &[u8]) {
+        match event {
+            DEVICE_ADD => {
+                let ready = match self.ports.iter_mut().find(|p| p.id == id) {
+                    Some(port) => {
+                        port.added = true;
+                        1
+                    }
+                    // No queues were set up for ports past MAX_PORTS.
+                    None => 0,
+                };
+                self.send_control(id, PORT_READY, ready);
+            }
+            DEVICE_REMOVE => {
+                if let Some(port) = self.port_mut(id) {
+                    port.added = false;
+                    port.host_open = false;
+                }
+            }
+            CONSOLE_PORT => {
+                if let Some(port) = self.port_mut(id) {
+                    port.console = true;
+                    self.send_control(id, PORT_OPEN, 1);
+                }
+            }
+            PORT_OPEN => {
+                if let Some(port) = self.port_mut(id) {
+                    port.host_open = value != 0;
+                }
+            }
+            PORT_NAME => {
+                if let Some(port) = self.port_mut(id) {
+                    let name = payload.split(|&b| b == 0).next().unwrap_or(&[]);
+                    port.name = core::str::from_utf8(name).ok().map(String::from);
+                }
+            }
+            RESIZE => {
+                if payload.len() >= 4 {
+                    // The message carries rows, then columns.
+                    let rows = u16::from_le_bytes([payload[0], payload[1]]);
+                    let cols = u16::from_le_bytes([payload[2], payload[3]]);
+                    self.size = Some((cols, rows));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes `bytes` to port `id`, returning how many were queued.
+    fn write(&mut self, id: u32, bytes: &[u8]) -> Result<usize, DriverError> {
+        self.poll();
+        let Self { transport, ports, .. } = self;
+        let port = ports
+            .iter_mut()
+            .find(|p| p.id == id && p.added)
+            .ok_or(DriverError::NotPresent)?;
+        // Console ports are written even without a connected host, like a
+        // UART without a cable.
+        if !port.host_open && !port.console {
+            port.dropped += bytes.len();
+            return Ok(0);
+        }
+        let mut written = 0;
+        for chunk in bytes.chunks(DATA_BUF_SIZE) {
+            if port.tx.send(transport, chunk).is_err() {
+                port.dropped += bytes.len() - written;
+                break;
+            }
+            written += chunk.len();
+        }
+        Ok(written)
+    }
+
+    fn read(&mut self, id: u32, dest: &mut [u8]) -> Result<usize, DriverError> {
+        self.poll();
+        let port = self.port_mut(id).ok_or(DriverError::NotPresent)?;
+        let n = dest.len().min(port.input.len());
+        for (slot, byte) in dest.iter_mut().zip(port.input.drain(..n)) {
+            *slot = byte;
+        }
+        // Draining may have made room for more receive buffers.
+        port.pump_input();
+        Ok(n)
+    }
+
+    fn console_port(&self) -> Option<u32> {
+        self.ports
+            .iter()
+            .find(|p| p.added && p.console)
+            .or_else(|| self.ports.iter().find(|p| p.added))
+            .map(|p| p.id)
+    }
+}
+
+static DEVICE: Mutex<Option<VirtioConsole>> = Mutex::new(None);
+
+/// Address of the emergency write register, or 0 if unavailable.
+static EMERGENCY_REGISTER: AtomicUsize = AtomicUsize::new(0);
+
+pub(super) static DRIVER: VirtioDriver = VirtioDriver {
+    name: "virtio-console",
+    device_id: device_id::CONSOLE,
+    probe,
+};
+
+/// Receive and transmit queue indices of port `id`.
+fn port_queues(id: u32) -> (u16, u16) {
+    match id {
+        0 => (0, 1),
+        id => (2 + 2 * id as u16, 3 + 2 * id as u16),
+    }
+}
+
+fn setup(transport: &Transport, features: u64) -> Result<(Option<(Channel, Channel)>, Vec<Port>), DriverError> {
+    let multiport = features & F_MULTIPORT != 0;
+    let nr_ports = if multiport {
+        (transport.config::<u32>(CONFIG_MAX_NR_PORTS).read() as usize).clamp(1, MAX_PORTS)
+    } else {
+        1
+    };
+    let control = if multiport {
+        Some((Channel::new(transport, 2, CONTROL_BUF_SIZE)?, Channel::new(transport, 3, CONTROL_BUF_SIZE)?))
+    } else {
+        None
+    };
+    let mut ports = Vec::with_capacity(nr_ports);
+    for id in 0..nr_ports as u32 {
+        let (rx, tx) = port_queues(id);
+        ports.push(Port {
+            id,
+            rx: Channel::new(transport, rx, DATA_BUF_SIZE)?,
+            tx: Channel::new(transport, tx, DATA_BUF_SIZE)?,
+            input: VecDeque::new(),
+            added: !multiport,
+            host_open: !multiport,
+            console: !multiport,
+            name: None,
+            dropped: 0,
+        });
+    }
+    Ok((control, ports))
+}
+
+fn probe(_device: &Device, transport: Transport) -> Result<(), DriverError> {
+    let features = transport.begin_init(F_SIZE | F_MULTIPORT | F_EMERG_WRITE)?;
+    let (mut control, mut ports) = match setup(&transport, features) {
+        Ok(queues) => queues,
+        Err(e) => {
+            transport.fail();
+            return Err(e);
+        }
+    };
+    if let Some((rx, _)) = control.as_mut() {
+        rx.post_receive();
+    }
+    for port in ports.iter_mut() {
+        port.rx.post_receive();
+    }
+    transport.driver_ok();
+
+    let size = (features & F_SIZE != 0)
+        .then(|| (transport.config::<u16>(CONFIG_COLS).read(), transport.config::<u16>(CONFIG_ROWS).read()));
+    let emergency = (features & F_EMERG_WRITE != 0).then(|| transport.config_addr(CONFIG_EMERG_WR));
+
+    let mut slot = DEVICE.lock();
+    if slot.is_some() {
+        transport.reset();
+        return Err(DriverError::Unsupported);
+    }
+    let device = slot.insert(VirtioConsole { transport, control, ports, size });
+    if device.control.is_some() {
+        device.send_control(0, DEVICE_READY, 1);
+        // Let the device announce its ports before anyone writes.
+        let deadline = time::deadline_after_ms(ANNOUNCE_TIMEOUT_MS);
+        while !time::reached(deadline) && device.console_port().is_none() {
+            device.poll();
+        }
+    }
+    drop(slot);
+    EMERGENCY_REGISTER.store(emergency.unwrap_or(0), Ordering::Release);
+
+    if platform::get().uart.is_none() {
+        console::register_backend(&CONSOLE_BACKEND);
+    }
+    Ok(())
+}
+
+
+/// Returns `true` if a console device was probed.
+pub fn is_present() -> bool {
+    DEVICE.lock().is_some()
+}
+
+/// Lists the ports the device has announced.
+pub fn ports() -> Vec<PortInfo> {
+    let mut slot = DEVICE.lock();
+    let Some(device) = slot.as_mut() else {
+        return Vec::new();
+    };
+    device.poll();
+    device
+        .ports
+        .iter()
+        .filter(|p| p.added)
+        .map(|p| PortInfo {
+            id: p.id,
+            name: p.name.clone(),
+            host_open: p.host_open,
+            console: p.console,
+            dropped: p.dropped,
+        })
+        .collect()
+}
+
+/// Returns the console size as columns and rows, if the device reports it.
+pub fn size() -> Option<(u16, u16)> {
+    DEVICE.lock().as_ref().and_then(|d| d.size)
+}
+
+/// Opens port `id` from the guest side, telling the host it can send.
+pub fn open_port(id: u32) -> Result<(), DriverError> {
+    let mut slot = DEVICE.lock();
+    let device = slot.as_mut().ok_or(DriverError::NotPresent)?;
+    device.port_mut(id).ok_or(DriverError::NotPresent)?;
+    device.send_control(id, PORT_OPEN, 1);
+    Ok(())
+}
+
+/// Writes `bytes` to port `id`. Returns the number of bytes queued, which
+/// is 0 if the host side of a non-console port is not connected.
+pub fn write(id: u32, bytes: &[u8]) -> Result<usize, DriverError> {
+    DEVICE.lock().as_mut().ok_or(DriverError::NotPresent)?.write(id, bytes)
+}
+
+/// Reads buffered input of port `id` without blocking.
+pub fn read(id: u32, dest: &mut [u8]) -> Result<usize, DriverError> {
+    DEVICE.lock().as_mut().ok_or(DriverError::NotPresent)?.read(id, dest)
+}
+
+/// Returns the ID of the port used as console.
+pub fn console_port() -> Option<u32> {
+    DEVICE.lock().as_ref().and_then(|d| d.console_port())
+}
+
+/// Kernel console backend writing to the console port.
+struct ConsoleBackend;
+
+static CONSOLE_BACKEND: ConsoleBackend = ConsoleBackend;
+
+impl ConsoleBackend {
+    fn emergency_write(s: &str) {
+        match EMERGENCY_REGISTER.load(Ordering::Acquire) {
+            0 => {
+                let _ = sbi::console::puts(s);
+            }
+            register => {
+                let register = unsafe { mmio::register::<WriteOnly<u32>>(register) };
+                for byte in s.bytes() {
+                    register.write(byte as u32);
+                }
+            }
+        }
+    }
+}
+
+impl ConsoleDriver for ConsoleBackend {
+    fn name(&self) -> &'static str {
+        DRIVER.name
+    }
+
+    fn write_str(&self, s: &str) {
+        let written = DEVICE.try_lock().and_then(|mut slot| {
+            let device = slot.as_mut()?;
+            let id = device.console_port()?;
+            device.write(id, s.as_bytes()).ok()
+        });
+        if written.is_none() {
+            Self::emergency_write(s);
+        }
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        let mut slot = DEVICE.try_lock()?;
+        let device = slot.as_mut()?;
+        let id = device.console_port()?;
+        let mut byte = [0u8];
+        match device.read(id, &mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+}
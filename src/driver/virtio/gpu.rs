@@ -0,0 +1,275 @@
+// nt_rustos/src/driver/virtio/gpu.rs
+
+//! virtio GPU device, as a framebuffer console.
+//!
+//! Only the 2D commands are used. On probe the driver asks for the display
+//! layout, creates a host resource the size of the first enabled scanout
+//! (at most `MAX_WIDTH` x `MAX_HEIGHT`), backs it with a `fb::Framebuffer`
+//! in guest memory and shows it on that scanout. A `fb::TextConsole` on the
+//! framebuffer is then registered as an additional console sink, so kernel
+//! output also appears in QEMU's window.
+//!
+//! The host does not see guest memory changes by itself: after drawing,
+//! the changed rows are copied to the resource (`TRANSFER_TO_HOST_2D`) and
+//! redrawn (`RESOURCE_FLUSH`). Commands are issued one at a time and polled
+//! for, like the other virtio drivers here.
+
+use super::{device_id, Transport, VirtQueue, VirtioDriver};
+use crate::console::{self, ConsoleDriver};
+use crate::driver::dma::{self, Direction, DmaMapping};
+use crate::driver::fb::{Framebuffer, TextConsole};
+use crate::driver::{Device, DriverError};
+use crate::info_print;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Command types.
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+/// Response types.
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Blue, green, red and an unused byte: a little-endian `0x00RRGGBB`.
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+/// Command header: type, flags, fence ID, context ID, ring index and
+/// padding.
+const HEADER_LEN: usize = 24;
+/// Scanouts a display info response describes.
+const MAX_SCANOUTS: usize = 16;
+/// A scanout's rectangle, enabled flag and flags.
+const DISPLAY_ONE_LEN: usize = 24;
+const RESPONSE_LEN: usize = HEADER_LEN + MAX_SCANOUTS * DISPLAY_ONE_LEN;
+
+/// Resource ID of the framebuffer.
+const RESOURCE_ID: u32 = 1;
+/// Largest framebuffer the driver allocates.
+const MAX_WIDTH: u32 = 1280;
+const MAX_HEIGHT: u32 = 800;
+/// Size used when no scanout reports one.
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+
+const QUEUE_SIZE: u16 = 8;
+/// How long to wait for the device to complete a command.
+const COMMAND_TIMEOUT_MS: u64 = 1000;
+
+/// A rectangle in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn words(&self) -> [u32; 4] {
+        [self.x, self.y, self.width, self.height]
+    }
+}
+
+struct VirtioGpu {
+    transport: Transport,
+    control: VirtQueue,
+    /// Bounce buffers for the command and its response.
+    request: Vec<u8>,
+    response: Vec<u8>,
+    scanout: u32,
+    console: TextConsole,
+    /// The framebuffer as mapped for the device once it backs the resource.
+    backing: Option<DmaMapping>,
+}
+
+static DEVICE: Mutex<Option<VirtioGpu>> = Mutex::new(None);
+
+pub(super) static DRIVER: VirtioDriver = VirtioDriver {
+    name: "virtio-gpu",
+    device_id: device_id::GPU,
+    probe,
+};
+
+impl VirtioGpu {
+    /// Sends the command `kind` with the 32-bit little-endian fields `body`
+    /// and returns the response type.
+    fn command(&mut self, kind: u32, body: &[u32]) -> Result<u32, DriverError> {
+        self.request.clear();
+        self.request.extend_from_slice(&kind.to_le_bytes());
+        self.request.resize(HEADER_LEN, 0);
+        for word in body {
+            self.request.extend_from_slice(&word.to_le_bytes());
+        }
+        self.response.fill(0);
+        let request = dma::map_for_device(self.request.as_ptr() as usize, self.request.len(), Direction::ToDevice)?;
+        let response = dma::map_for_device(self.response.as_mut_ptr() as usize, RESPONSE_LEN, Direction::FromDevice)?;
+        self.control.add(&[request.into(), response.into()]).ok_or(DriverError::InitFailed)?;
+        self.transport.submit_and_wait(&mut self.control, COMMAND_TIMEOUT_MS)?;
+        dma::sync_for_cpu(&response);
+        Ok(self.response_word(0))
+    }
+
+    /// Like `command`, failing unless the device answers with no data.
+    fn command_ok(&mut self, kind: u32, body: &[u32]) -> Result<(), DriverError> {
+        match self.command(kind, body)? {
+            RESP_OK_NODATA => Ok(()),
+            _ => Err(DriverError::InitFailed),
+        }
+    }
+
+    fn response_word(&self, offset: usize) -> u32 {
+        let bytes = &self.response[offset..offset + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// Returns the first enabled scanout and its rectangle.
+    fn display_info(&mut self) -> Result<Option<(u32, Rect)>, DriverError> {
+        if self.command(CMD_GET_DISPLAY_INFO, &[])? != RESP_OK_DISPLAY_INFO {
+            return Err(DriverError::InitFailed);
+        }
+        Ok((0..MAX_SCANOUTS).find_map(|i| {
+            let offset = HEADER_LEN + i * DISPLAY_ONE_LEN;
+            let rect = Rect {
+                x: self.response_word(offset),
+                y: self.response_word(offset + 4),
+                width: self.response_word(offset + 8),
+                height: self.response_word(offset + 12),
+            };
+            let enabled = self.response_word(offset + 16) != 0;
+            (enabled && rect.width > 0 && rect.height > 0).then_some((i as u32, rect))
+        }))
+    }
+
+    /// Creates the resource, backs it with the framebuffer and shows it.
+    fn set_up_scanout(&mut self) -> Result<(), DriverError> {
+        let fb = self.console.framebuffer();
+        let (width, height) = (fb.width() as u32, fb.height() as u32);
+        let backing = dma::map_for_device(fb.pixels().as_ptr() as usize, fb.pixels().len() * 4, Direction::ToDevice)?;
+        let (addr, len) = (backing.bus_addr as u64, backing.len as u32);
+        let whole = Rect { x: 0, y: 0, width, height };
+        self.command_ok(CMD_RESOURCE_CREATE_2D, &[RESOURCE_ID, FORMAT_B8G8R8X8_UNORM, width, height])?;
+        self.command_ok(CMD_RESOURCE_ATTACH_BACKING, &[RESOURCE_ID, 1, addr as u32, (addr >> 32) as u32, len, 0])?;
+        self.backing = Some(backing);
+        let [x, y, w, h] = whole.words();
+        self.command_ok(CMD_SET_SCANOUT, &[x, y, w, h, self.scanout, RESOURCE_ID])
+    }
+
+    /// Copies the rows `start..end` to the host and redraws them.
+    fn flush_rows(&mut self, start: usize, end: usize) -> Result<(), DriverError> {
+        let width = self.console.framebuffer().width() as u32;
+        let rect = Rect { x: 0, y: start as u32, width, height: (end - start) as u32 };
+        let offset = (start * width as usize * 4) as u64;
+        if let Some(backing) = &self.backing {
+            // The host reads the rows drawn since the last transfer.
+            dma::sync_for_device(backing);
+        }
+        let [x, y, w, h] = rect.words();
+        self.command_ok(CMD_TRANSFER_TO_HOST_2D, &[x, y, w, h, offset as u32, (offset >> 32) as u32, RESOURCE_ID, 0])?;
+        self.command_ok(CMD_RESOURCE_FLUSH, &[x, y, w, h, RESOURCE_ID, 0])
+    }
+
+    /// Shows what was drawn since the last flush.
+    fn flush(&mut self) -> Result<(), DriverError> {
+        match self.console.take_dirty() {
+            Some((start, end)) => self.flush_rows(start, end),
+            None => Ok(()),
+        }
+    }
+}
+
+fn probe(_device: &Device, transport: Transport) -> Result<(), DriverError> {
+    transport.begin_init(0)?;
+    let control = match transport.setup_queue(0, QUEUE_SIZE) {
+        Ok(queue) => queue,
+        Err(e) => {
+            transport.fail();
+            return Err(e);
+        }
+    };
+    transport.driver_ok();
+
+    let mut slot = DEVICE.lock();
+    if slot.is_some() {
+        // One display is enough.
+        transport.reset();
+        return Err(DriverError::Unsupported);
+    }
+    // A placeholder console until the display size is known.
+    let mut gpu = VirtioGpu {
+        transport,
+        control,
+        request: Vec::with_capacity(HEADER_LEN + 64),
+        response: vec![0; RESPONSE_LEN],
+        scanout: 0,
+        console: TextConsole::new(Framebuffer::new(0, 0)),
+        backing: None,
+    };
+    let result = gpu.display_info().and_then(|info| {
+        let (scanout, rect) = info.unwrap_or((0, Rect { x: 0, y: 0, width: DEFAULT_WIDTH, height: DEFAULT_HEIGHT }));
+        gpu.scanout = scanout;
+        let (width, height) = (rect.width.min(MAX_WIDTH), rect.height.min(MAX_HEIGHT));
+        gpu.console = TextConsole::new(Framebuffer::new(width as usize, height as usize));
+        gpu.set_up_scanout()?;
+        gpu.flush()
+    });
+    if let Err(e) = result {
+        // The device may still own the buffers.
+        gpu.transport.reset();
+        return Err(e);
+    }
+    let fb = gpu.console.framebuffer();
+    let (width, height) = (fb.width(), fb.height());
+    let (cols, rows) = gpu.console.size();
+    info_print!("virtio-gpu: {}x{} framebuffer on scanout {}, {}x{} text", width, height, gpu.scanout, cols, rows);
+    *slot = Some(gpu);
+    drop(slot);
+    console::register_sink(&CONSOLE_SINK);
+    Ok(())
+}
+
+/// Returns `true` if a GPU device was probed.
+pub fn is_present() -> bool {
+    DEVICE.lock().is_some()
+}
+
+/// Returns the framebuffer's width and height in pixels.
+pub fn resolution() -> Option<(usize, usize)> {
+    DEVICE.lock().as_ref().map(|gpu| (gpu.console.framebuffer().width(), gpu.console.framebuffer().height()))
+}
+
+/// Calls `f` to draw on the framebuffer, then shows the whole picture.
+pub fn draw<R>(f: impl FnOnce(&mut Framebuffer) -> R) -> Result<R, DriverError> {
+    let mut slot = DEVICE.lock();
+    let gpu = slot.as_mut().ok_or(DriverError::NotPresent)?;
+    let result = f(gpu.console.framebuffer_mut());
+    gpu.flush()?;
+    Ok(result)
+}
+
+/// Console sink drawing kernel output on the framebuffer.
+struct ConsoleSink;
+
+static CONSOLE_SINK: ConsoleSink = ConsoleSink;
+
+impl ConsoleDriver for ConsoleSink {
+    fn name(&self) -> &'static str {
+        DRIVER.name
+    }
+
+    fn write_str(&self, s: &str) {
+        // Output that finds the device busy, from a trap handler for
+        // example, is not drawn.
+        if let Some(mut slot) = DEVICE.try_lock() {
+            if let Some(gpu) = slot.as_mut() {
+                gpu.console.write_str(s);
+                let _ = gpu.flush();
+            }
+        }
+    }
+}
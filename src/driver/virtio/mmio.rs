@@ -0,0 +1,201 @@
+// nt_rustos/src/driver/virtio/mmio.rs
+
+//! The virtio-mmio transport.
+//!
+//! QEMU's virt machine exposes virtio devices through a row of
+//! `virtio,mmio` slots, most of them empty. Both the legacy (version 1) and
+//! the modern (version 2) register layouts are supported.
+
+use super::{
+    Transport, VirtQueue, F_VERSION_1, STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK, STATUS_FAILED,
+    STATUS_FEATURES_OK,
+};
+use crate::driver::mmio::{self, ReadOnly, ReadWrite, WriteOnly};
+use crate::driver::{Device, Driver, DriverError, MmioRegion};
+use crate::mm::PAGE_SIZE;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+
+/// Offset of the device-specific configuration space.
+const CONFIG_OFFSET: usize = 0x100;
+
+crate::mmio_registers! {
+    /// The virtio-mmio register layout. `guest_page_size`, `queue_align`
+    /// and `queue_pfn` exist in the legacy layout only, `queue_ready` and
+    /// the split queue address registers in the modern one.
+    struct VirtioRegs {
+        0x000 => magic: ReadOnly<u32>,
+        0x004 => version: ReadOnly<u32>,
+        0x008 => device_id: ReadOnly<u32>,
+        0x010 => device_features: ReadOnly<u32>,
+        0x014 => device_features_sel: WriteOnly<u32>,
+        0x020 => driver_features: WriteOnly<u32>,
+        0x024 => driver_features_sel: WriteOnly<u32>,
+        0x028 => guest_page_size: WriteOnly<u32>,
+        0x030 => queue_sel: WriteOnly<u32>,
+        0x034 => queue_num_max: ReadOnly<u32>,
+        0x038 => queue_num: WriteOnly<u32>,
+        0x03c => queue_align: WriteOnly<u32>,
+        0x040 => queue_pfn: ReadWrite<u32>,
+        0x044 => queue_ready: ReadWrite<u32>,
+        0x050 => queue_notify: WriteOnly<u32>,
+        0x060 => interrupt_status: ReadOnly<u32>,
+        0x064 => interrupt_ack: WriteOnly<u32>,
+        0x070 => status: ReadWrite<u32>,
+        0x080 => queue_desc_low: WriteOnly<u32>,
+        0x084 => queue_desc_high: WriteOnly<u32>,
+        0x090 => queue_driver_low: WriteOnly<u32>,
+        0x094 => queue_driver_high: WriteOnly<u32>,
+        0x0a0 => queue_device_low: WriteOnly<u32>,
+        0x0a4 => queue_device_high: WriteOnly<u32>,
+    }
+}
+
+/// A virtio device behind an MMIO slot.
+pub struct VirtioMmio {
+    regs: VirtioRegs,
+    version: u32,
+}
+
+impl VirtioMmio {
+    /// Checks the slot at `region` for the virtio magic value.
+    pub fn new(region: MmioRegion) -> Result<Self, DriverError> {
+        let regs = unsafe { VirtioRegs::new(region.base) };
+        if regs.magic().read() != MAGIC {
+            return Err(DriverError::Unsupported);
+        }
+        match regs.version().read() {
+            version @ (1 | 2) => Ok(Self { regs, version }),
+            _ => Err(DriverError::Unsupported),
+        }
+    }
+
+    /// Returns the device ID, 0 for an empty slot.
+    pub fn device_id(&self) -> u32 {
+        self.regs.device_id().read()
+    }
+
+    /// Returns `true` for the legacy register layout.
+    pub fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    /// Resets the device and negotiates features: the device's offer
+    /// intersected with `supported`. Returns the accepted features.
+    pub fn begin_init(&self, supported: u64) -> Result<u64, DriverError> {
+        let status = self.regs.status();
+        status.write(0);
+        status.write(STATUS_ACKNOWLEDGE);
+        status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut offered = 0u64;
+        for select in 0..2 {
+            self.regs.device_features_sel().write(select);
+            offered |= (self.regs.device_features().read() as u64) << (32 * select);
+        }
+        let supported = if self.is_legacy() { supported } else { supported | F_VERSION_1 };
+        let accepted = offered & supported;
+        for select in 0..2 {
+            self.regs.driver_features_sel().write(select);
+            self.regs.driver_features().write((accepted >> (32 * select)) as u32);
+        }
+
+        if self.is_legacy() {
+            self.regs.guest_page_size().write(PAGE_SIZE as u32);
+        } else {
+            if accepted & F_VERSION_1 == 0 {
+                self.fail();
+                return Err(DriverError::Unsupported);
+            }
+            status.write(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+            if status.read() & STATUS_FEATURES_OK == 0 {
+                self.fail();
+                return Err(DriverError::Unsupported);
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Sets up queue `index` with at most `size` entries.
+    pub fn setup_queue(&self, index: u16, size: u16) -> Result<VirtQueue, DriverError> {
+        self.regs.queue_sel().write(index as u32);
+        let max = self.regs.queue_num_max().read();
+        if max == 0 {
+            return Err(DriverError::MissingResource);
+        }
+        let size = size.min(max.min(u16::MAX as u32) as u16);
+        let queue = VirtQueue::new(index, size)?;
+        let (desc, driver, device) = queue.addresses();
+
+        self.regs.queue_num().write(size as u32);
+        if self.is_legacy() {
+            self.regs.queue_align().write(PAGE_SIZE as u32);
+            self.regs.queue_pfn().write((desc / PAGE_SIZE) as u32);
+        } else {
+            let split = |addr: usize| (addr as u32, (addr as u64 >> 32) as u32);
+            let ((desc_low, desc_high), (driver_low, driver_high), (device_low, device_high)) =
+                (split(desc), split(driver), split(device));
+            self.regs.queue_desc_low().write(desc_low);
+            self.regs.queue_desc_high().write(desc_high);
+            self.regs.queue_driver_low().write(driver_low);
+            self.regs.queue_driver_high().write(driver_high);
+            self.regs.queue_device_low().write(device_low);
+            self.regs.queue_device_high().write(device_high);
+            self.regs.queue_ready().write(1);
+        }
+        Ok(queue)
+    }
+
+    /// Marks the device ready after its queues are set up.
+    pub fn driver_ok(&self) {
+        self.regs.status().modify(|status| status | STATUS_DRIVER_OK);
+    }
+
+    /// Tells the device that initialization failed.
+    pub fn fail(&self) {
+        self.regs.status().modify(|status| status | STATUS_FAILED);
+    }
+
+    /// Resets the device, which stops it from using its queues.
+    pub fn reset(&self) {
+        self.regs.status().write(0);
+        super::wait_for_reset(|| self.regs.status().read());
+    }
+
+    /// Tells the device that `queue` has new buffers.
+    pub fn notify(&self, queue: &VirtQueue) {
+        // The ring updates must reach memory before the device hears of them.
+        mmio::wmb();
+        self.regs.queue_notify().write(queue.index() as u32);
+    }
+
+    /// Acknowledges pending interrupts and returns the interrupt status.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.regs.interrupt_status().read();
+        self.regs.interrupt_ack().write(status);
+        status
+    }
+
+    /// Returns the address of a configuration space field, for drivers that
+    /// must reach it without going through the transport.
+    pub fn config_addr(&self, offset: usize) -> usize {
+        self.regs.base() + CONFIG_OFFSET + offset
+    }
+}
+
+fn probe(device: &Device) -> Result<(), DriverError> {
+    let transport = VirtioMmio::new(device.region()?)?;
+    if transport.device_id() == 0 {
+        return Err(DriverError::NotPresent);
+    }
+    super::probe_transport(device, Transport::Mmio(transport))
+}
+
+/// The driver claiming `virtio,mmio` slots.
+pub static VIRTIO_MMIO_DRIVER: Driver = Driver {
+    name: "virtio-mmio",
+    compatible: &["virtio,mmio"],
+    probe,
+    irq_handler: None,
+    remove: None,
+};
@@ -0,0 +1,183 @@
+// nt_rustos/src/driver/virtio/mod.rs
+
+//! # virtio
+//!
+//! virtio devices are reached through one of two transports: MMIO slots
+//! described by the device tree (`mmio`) or PCI functions (`pci`). The
+//! transport's driver model driver reads the device ID and hands the device
+//! to the virtio driver registered for that ID in `VIRTIO_DRIVERS`, which
+//! only sees the transport-neutral `Transport`.
+//!
+//! Devices are driven by polling for now: drivers notify a queue and wait
+//! for the used ring to advance. Queue rings and the buffers handed to the
+//! device go through `driver::dma`, which supplies bus addresses and the
+//! fences around the device's accesses.
+
+pub mod blk;
+pub mod console;
+pub mod gpu;
+pub mod mmio;
+pub mod pci;
+pub mod queue;
+pub mod rng;
+
+pub use self::mmio::{VirtioMmio, VIRTIO_MMIO_DRIVER};
+pub use self::pci::{VirtioPci, VIRTIO_PCI_DRIVER};
+pub use self::queue::{Buffer, VirtQueue};
+
+use super::mmio::ReadWrite;
+use super::{Device, DriverError};
+use crate::info_print;
+use crate::time;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// Longest wait for a device to finish resetting.
+const RESET_TIMEOUT_US: u64 = 1000;
+
+/// Feature bit every modern device offers and modern drivers must accept.
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// virtio device IDs.
+pub mod device_id {
+    pub const BLOCK: u32 = 2;
+    pub const CONSOLE: u32 = 3;
+    pub const ENTROPY: u32 = 4;
+    pub const GPU: u32 = 16;
+}
+
+/// The transport a virtio device is reached through.
+pub enum Transport {
+    Mmio(VirtioMmio),
+    Pci(VirtioPci),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $transport:ident => $call:expr) => {
+        match $self {
+            Transport::Mmio($transport) => $call,
+            Transport::Pci($transport) => $call,
+        }
+    };
+}
+
+impl Transport {
+    /// Returns the virtio device ID.
+    pub fn device_id(&self) -> u32 {
+        dispatch!(self, t => t.device_id())
+    }
+
+    /// Returns `true` for a legacy (pre-1.0) device interface.
+    pub fn is_legacy(&self) -> bool {
+        matches!(self, Transport::Mmio(t) if t.is_legacy())
+    }
+
+    /// Resets the device and negotiates features: the device's offer
+    /// intersected with `supported`. Returns the accepted features.
+    pub fn begin_init(&self, supported: u64) -> Result<u64, DriverError> {
+        dispatch!(self, t => t.begin_init(supported))
+    }
+
+    /// Sets up queue `index` with at most `size` entries.
+    pub fn setup_queue(&self, index: u16, size: u16) -> Result<VirtQueue, DriverError> {
+        dispatch!(self, t => t.setup_queue(index, size))
+    }
+
+    /// Marks the device ready after its queues are set up.
+    pub fn driver_ok(&self) {
+        dispatch!(self, t => t.driver_ok())
+    }
+
+    /// Tells the device that initialization failed.
+    pub fn fail(&self) {
+        dispatch!(self, t => t.fail())
+    }
+
+    /// Resets the device, which stops it from using its queues.
+    pub fn reset(&self) {
+        dispatch!(self, t => t.reset())
+    }
+
+    /// Tells the device that `queue` has new buffers.
+    pub fn notify(&self, queue: &VirtQueue) {
+        dispatch!(self, t => t.notify(queue))
+    }
+
+    /// Acknowledges pending interrupts and returns the interrupt status.
+    pub fn ack_interrupt(&self) -> u32 {
+        dispatch!(self, t => t.ack_interrupt())
+    }
+
+    /// Returns the address of a configuration space field, for drivers that
+    /// must reach it without going through the transport.
+    pub fn config_addr(&self, offset: usize) -> usize {
+        dispatch!(self, t => t.config_addr(offset))
+    }
+
+    /// Returns the configuration space field of type `T` at `offset`.
+    pub fn config<T: Copy>(&self, offset: usize) -> &ReadWrite<T> {
+        unsafe { super::mmio::register(self.config_addr(offset)) }
+    }
+
+    /// Notifies `queue` and polls until the device returns a chain or
+    /// `timeout_ms` pass.
+    pub fn submit_and_wait(&self, queue: &mut VirtQueue, timeout_ms: u64) -> Result<(u16, u32), DriverError> {
+        self.notify(queue);
+        let deadline = time::deadline_after_ms(timeout_ms);
+        loop {
+            if let Some(used) = queue.pop_used() {
+                self.ack_interrupt();
+                return Ok(used);
+            }
+            if time::reached(deadline) {
+                return Err(DriverError::InitFailed);
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Waits after writing 0 to the device status until `status` reads 0
+/// again, which is how the device reports that the reset is complete.
+/// Polls with `time::udelay`, since resets also happen before timers run.
+fn wait_for_reset(status: impl Fn() -> u32) {
+    for _ in 0..RESET_TIMEOUT_US {
+        if status() == 0 {
+            return;
+        }
+        time::udelay(1);
+    }
+}
+
+/// Sets up a virtio device of the driver's type.
+pub type VirtioProbeFn = fn(&Device, Transport) -> Result<(), DriverError>;
+
+/// A driver for one virtio device type.
+pub struct VirtioDriver {
+    pub name: &'static str,
+    pub device_id: u32,
+    pub probe: VirtioProbeFn,
+}
+
+/// Virtio device drivers built into the kernel.
+static VIRTIO_DRIVERS: &[&VirtioDriver] = &[&rng::DRIVER, &console::DRIVER, &blk::DRIVER, &gpu::DRIVER];
+
+/// Hands `device` to the virtio driver for its device ID.
+fn probe_transport(device: &Device, transport: Transport) -> Result<(), DriverError> {
+    let id = transport.device_id();
+    let driver = VIRTIO_DRIVERS
+        .iter()
+        .find(|d| d.device_id == id)
+        .ok_or(DriverError::Unsupported)?;
+    let kind = match &transport {
+        Transport::Mmio(t) if t.is_legacy() => "mmio, legacy",
+        Transport::Mmio(_) => "mmio",
+        Transport::Pci(_) => "pci",
+    };
+    info_print!("virtio: {} at {} (device id {}, {})", driver.name, device.path, id, kind);
+    (driver.probe)(device, transport)
+}
@@ -0,0 +1,213 @@
+// nt_rustos/src/driver/virtio/pci.rs
+
+//! The virtio-pci transport (modern interface).
+//!
+//! The device's registers live in BAR regions located by vendor-specific
+//! PCI capabilities: the common configuration, the per-queue notification
+//! doorbells, the interrupt status byte and the device-specific
+//! configuration. Transitional devices are driven through the same modern
+//! interface; the legacy I/O port interface is not supported.
+
+use super::{
+    Transport, VirtQueue, F_VERSION_1, STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK, STATUS_FAILED,
+    STATUS_FEATURES_OK,
+};
+use crate::driver::mmio::{self, ReadOnly, ReadWrite, WriteOnly};
+use crate::driver::pci::{self, PciFunction};
+use crate::driver::{Device, Driver, DriverError};
+
+const VENDOR_ID: u16 = 0x1af4;
+/// Device IDs of modern devices are this plus the virtio device ID.
+const MODERN_DEVICE_BASE: u16 = 0x1040;
+
+/// PCI capability ID of the virtio structures.
+const CAP_VENDOR: u8 = 0x09;
+const CAP_CFG_TYPE: usize = 3;
+const CAP_BAR: usize = 4;
+const CAP_OFFSET: usize = 8;
+const CAP_NOTIFY_MULTIPLIER: usize = 16;
+
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_ISR: u8 = 3;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+crate::mmio_registers! {
+    /// The common configuration structure.
+    struct CommonCfg {
+        0x00 => device_feature_select: ReadWrite<u32>,
+        0x04 => device_feature: ReadOnly<u32>,
+        0x08 => driver_feature_select: ReadWrite<u32>,
+        0x0c => driver_feature: ReadWrite<u32>,
+        0x12 => num_queues: ReadOnly<u16>,
+        0x14 => device_status: ReadWrite<u8>,
+        0x16 => queue_select: ReadWrite<u16>,
+        0x18 => queue_size: ReadWrite<u16>,
+        0x1c => queue_enable: ReadWrite<u16>,
+        0x1e => queue_notify_off: ReadOnly<u16>,
+        0x20 => queue_desc_low: WriteOnly<u32>,
+        0x24 => queue_desc_high: WriteOnly<u32>,
+        0x28 => queue_driver_low: WriteOnly<u32>,
+        0x2c => queue_driver_high: WriteOnly<u32>,
+        0x30 => queue_device_low: WriteOnly<u32>,
+        0x34 => queue_device_high: WriteOnly<u32>,
+    }
+}
+
+/// A virtio device behind a PCI function.
+pub struct VirtioPci {
+    device_id: u32,
+    common: CommonCfg,
+    notify_base: usize,
+    notify_multiplier: u32,
+    isr: usize,
+    device_cfg: usize,
+}
+
+impl VirtioPci {
+    /// Locates the virtio structures of `function`.
+    pub fn new(function: &PciFunction) -> Result<Self, DriverError> {
+        if function.vendor_id != VENDOR_ID {
+            return Err(DriverError::Unsupported);
+        }
+        let device_id = if function.device_id >= MODERN_DEVICE_BASE {
+            (function.device_id - MODERN_DEVICE_BASE) as u32
+        } else {
+            // Transitional devices carry the device ID in the subsystem ID.
+            function.subsystem_id as u32
+        };
+
+        let (mut common, mut notify, mut isr, mut device_cfg) = (None, None, None, 0);
+        for (id, cap) in function.capabilities() {
+            if id != CAP_VENDOR {
+                continue;
+            }
+            let bar = function.read_config_u8(cap + CAP_BAR) as usize;
+            let Some(bar) = function.bar(bar) else {
+                continue;
+            };
+            let addr = bar.cpu_addr + function.read_config_u32(cap + CAP_OFFSET) as usize;
+            match function.read_config_u8(cap + CAP_CFG_TYPE) {
+                CFG_TYPE_COMMON if common.is_none() => common = Some(addr),
+                CFG_TYPE_NOTIFY if notify.is_none() => {
+                    notify = Some((addr, function.read_config_u32(cap + CAP_NOTIFY_MULTIPLIER)));
+                }
+                CFG_TYPE_ISR if isr.is_none() => isr = Some(addr),
+                CFG_TYPE_DEVICE if device_cfg == 0 => device_cfg = addr,
+                _ => {}
+            }
+        }
+        let (Some(common), Some((notify_base, notify_multiplier)), Some(isr)) = (common, notify, isr) else {
+            return Err(DriverError::MissingResource);
+        };
+        Ok(Self {
+            device_id,
+            common: unsafe { CommonCfg::new(common) },
+            notify_base,
+            notify_multiplier,
+            isr,
+            device_cfg,
+        })
+    }
+
+    /// Returns the virtio device ID.
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    pub fn begin_init(&self, supported: u64) -> Result<u64, DriverError> {
+        let status = self.common.device_status();
+        status.write(0);
+        status.write(STATUS_ACKNOWLEDGE as u8);
+        status.write((STATUS_ACKNOWLEDGE | STATUS_DRIVER) as u8);
+
+        let mut offered = 0u64;
+        for select in 0..2 {
+            self.common.device_feature_select().write(select);
+            offered |= (self.common.device_feature().read() as u64) << (32 * select);
+        }
+        let accepted = offered & (supported | F_VERSION_1);
+        for select in 0..2 {
+            self.common.driver_feature_select().write(select);
+            self.common.driver_feature().write((accepted >> (32 * select)) as u32);
+        }
+        if accepted & F_VERSION_1 == 0 {
+            self.fail();
+            return Err(DriverError::Unsupported);
+        }
+        status.write((STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK) as u8);
+        if status.read() as u32 & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            return Err(DriverError::Unsupported);
+        }
+        Ok(accepted)
+    }
+
+    pub fn setup_queue(&self, index: u16, size: u16) -> Result<VirtQueue, DriverError> {
+        if index >= self.common.num_queues().read() {
+            return Err(DriverError::MissingResource);
+        }
+        self.common.queue_select().write(index);
+        let max = self.common.queue_size().read();
+        if max == 0 {
+            return Err(DriverError::MissingResource);
+        }
+        let mut queue = VirtQueue::new(index, size.min(max))?;
+        let (desc, driver, device) = queue.addresses();
+
+        self.common.queue_size().write(queue.size());
+        self.common.queue_desc_low().write(desc as u32);
+        self.common.queue_desc_high().write((desc as u64 >> 32) as u32);
+        self.common.queue_driver_low().write(driver as u32);
+        self.common.queue_driver_high().write((driver as u64 >> 32) as u32);
+        self.common.queue_device_low().write(device as u32);
+        self.common.queue_device_high().write((device as u64 >> 32) as u32);
+        queue.notify_addr = self.notify_base
+            + self.common.queue_notify_off().read() as usize * self.notify_multiplier as usize;
+        self.common.queue_enable().write(1);
+        Ok(queue)
+    }
+
+    pub fn driver_ok(&self) {
+        self.common.device_status().modify(|status| status | STATUS_DRIVER_OK as u8);
+    }
+
+    pub fn fail(&self) {
+        self.common.device_status().modify(|status| status | STATUS_FAILED as u8);
+    }
+
+    pub fn reset(&self) {
+        self.common.device_status().write(0);
+        super::wait_for_reset(|| self.common.device_status().read() as u32);
+    }
+
+    pub fn notify(&self, queue: &VirtQueue) {
+        mmio::wmb();
+        unsafe { mmio::register::<WriteOnly<u16>>(queue.notify_addr) }.write(queue.index());
+    }
+
+    /// Reading the ISR status acknowledges the interrupt.
+    pub fn ack_interrupt(&self) -> u32 {
+        unsafe { mmio::register::<ReadOnly<u8>>(self.isr) }.read() as u32
+    }
+
+    pub fn config_addr(&self, offset: usize) -> usize {
+        self.device_cfg + offset
+    }
+}
+
+fn probe(device: &Device) -> Result<(), DriverError> {
+    let function = pci::function(&device.path).ok_or(DriverError::MissingResource)?;
+    let transport = VirtioPci::new(&function)?;
+    super::probe_transport(device, Transport::Pci(transport))
+}
+
+/// The driver claiming virtio PCI functions of the device types that have
+/// a virtio driver.
+pub static VIRTIO_PCI_DRIVER: Driver = Driver {
+    name: "virtio-pci",
+    compatible: &["pci1af4,1042", "pci1af4,1043", "pci1af4,1044", "pci1af4,1001", "pci1af4,1003", "pci1af4,1005"],
+    probe,
+    irq_handler: None,
+    remove: None,
+};
@@ -0,0 +1,231 @@
+// nt_rustos/src/driver/virtio/queue.rs
+
+//! Split virtqueues.
+//!
+//! The descriptor table, available ring and used ring live in one
+//! page-aligned `DmaBuffer` laid out as the legacy interface requires (the
+//! used ring starts on the next page), which also satisfies the alignment
+//! rules of the modern interface.
+//!
+//! Buffers are added as `dma::DmaMapping`s, so the device is given their
+//! bus addresses; drivers call `dma::sync_for_cpu` on the buffers of a
+//! chain the device returned before reading them.
+
+use crate::driver::dma::{DmaBuffer, DmaMapping};
+use crate::driver::{mmio, DriverError};
+use crate::mm::PAGE_SIZE;
+use core::ptr;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer handed to the device.
+#[derive(Debug, Copy, Clone)]
+pub struct Buffer {
+    /// Bus address of the buffer.
+    pub addr: usize,
+    pub len: u32,
+    /// `true` if the device writes the buffer, `false` if it reads it.
+    pub writable: bool,
+}
+
+impl From<DmaMapping> for Buffer {
+    fn from(mapping: DmaMapping) -> Self {
+        Self { addr: mapping.bus_addr, len: mapping.len as u32, writable: mapping.device_writes() }
+    }
+}
+
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// A split virtqueue owned by the driver.
+///
+/// Ring addresses are kept as plain integers so that drivers can keep the
+/// queue in a global lock.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    /// The rings; they start with the descriptor table.
+    ring: DmaBuffer,
+    base: usize,
+    avail: usize,
+    used: usize,
+    free_head: u16,
+    num_free: u16,
+    last_used: u16,
+    /// Length of the descriptor chain starting at each head.
+    chain_len: [u16; MAX_QUEUE_SIZE],
+    /// Doorbell address, for transports with one per queue.
+    pub(super) notify_addr: usize,
+}
+
+/// Largest queue size the driver sets up.
+pub const MAX_QUEUE_SIZE: usize = 64;
+
+impl VirtQueue {
+    /// Allocates queue `index` with `size` entries, a power of two no larger
+    /// than `MAX_QUEUE_SIZE`.
+    pub(super) fn new(index: u16, size: u16) -> Result<Self, DriverError> {
+        let n = size as usize;
+        if n == 0 || n > MAX_QUEUE_SIZE || !n.is_power_of_two() {
+            return Err(DriverError::Unsupported);
+        }
+        let avail_offset = 16 * n;
+        let used_offset = align_up(avail_offset + 6 + 2 * n, PAGE_SIZE);
+        let total = align_up(used_offset + 6 + 8 * n, PAGE_SIZE);
+
+        let ring = DmaBuffer::new(total, PAGE_SIZE)?;
+        let base = ring.addr();
+
+        let mut queue = Self {
+            index,
+            size,
+            ring,
+            base,
+            avail: base + avail_offset,
+            used: base + used_offset,
+            free_head: 0,
+            num_free: size,
+            last_used: 0,
+            chain_len: [0; MAX_QUEUE_SIZE],
+            notify_addr: 0,
+        };
+        for i in 0..size {
+            queue.desc_mut(i).next = (i + 1) % size;
+        }
+        Ok(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Bus addresses of the descriptor table, available ring and used ring.
+    pub(super) fn addresses(&self) -> (usize, usize, usize) {
+        let bus = |addr: usize| self.ring.bus_addr() + (addr - self.base);
+        (bus(self.base), bus(self.avail), bus(self.used))
+    }
+
+    fn desc_mut(&mut self, i: u16) -> &mut Descriptor {
+        unsafe { &mut *(self.base as *mut Descriptor).add(i as usize) }
+    }
+
+    fn avail_idx(&self) -> *mut u16 {
+        (self.avail + 2) as *mut u16
+    }
+
+    fn avail_slot(&self, i: u16) -> *mut u16 {
+        (self.avail + 4 + 2 * (i % self.size) as usize) as *mut u16
+    }
+
+    fn used_idx(&self) -> *const u16 {
+        (self.used + 2) as *const u16
+    }
+
+    fn used_elem(&self, i: u16) -> *const UsedElem {
+        (self.used + 4 + 8 * (i % self.size) as usize) as *const UsedElem
+    }
+
+    /// Returns the number of free descriptors.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Returns the descriptor the next `add` will use as chain head, which
+    /// lets drivers pick a per-descriptor buffer before adding it.
+    pub fn next_head(&self) -> Option<u16> {
+        (self.num_free > 0).then_some(self.free_head)
+    }
+
+    /// Publishes `buffers` as one descriptor chain and returns its head.
+    ///
+    /// Returns `None` if the queue lacks free descriptors. The device is not
+    /// notified.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut last = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let more = i + 1 < buffers.len();
+            let desc = self.desc_mut(last);
+            desc.addr = buffer.addr as u64;
+            desc.len = buffer.len;
+            let mut flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            if more {
+                flags |= DESC_F_NEXT;
+            }
+            desc.flags = flags;
+            let next = desc.next;
+            if more {
+                last = next;
+            } else {
+                self.free_head = next;
+            }
+        }
+        self.num_free -= buffers.len() as u16;
+        self.chain_len[head as usize] = buffers.len() as u16;
+
+        unsafe {
+            let idx = ptr::read_volatile(self.avail_idx());
+            ptr::write_volatile(self.avail_slot(idx), head);
+            // The descriptors and ring entry must be visible before the
+            // index moves.
+            mmio::dma_wmb();
+            ptr::write_volatile(self.avail_idx(), idx.wrapping_add(1));
+        }
+        Some(head)
+    }
+
+    /// Returns `true` if the device has returned buffers not yet popped.
+    pub fn has_used(&self) -> bool {
+        unsafe { ptr::read_volatile(self.used_idx()) != self.last_used }
+    }
+
+    /// Takes the next chain the device has finished with, returning its
+    /// head and the number of bytes the device wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        // The used element is read only after its index was seen.
+        mmio::dma_rmb();
+        let elem = unsafe { ptr::read_volatile(self.used_elem(self.last_used)) };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = elem.id as u16;
+        let count = self.chain_len[head as usize];
+        let mut tail = head;
+        for _ in 1..count {
+            tail = self.desc_mut(tail).next;
+        }
+        // Return the chain to the front of the free list.
+        let free_head = self.free_head;
+        self.desc_mut(tail).next = free_head;
+        self.free_head = head;
+        self.num_free += count;
+        Some((head, elem.len))
+    }
+}
@@ -0,0 +1,98 @@
+// nt_rustos/src/driver/virtio/rng.rs
+
+//! virtio entropy device.
+//!
+//! The device fills driver-supplied buffers with random bytes from the
+//! host. On probe the driver draws a seed for the kernel RNG
+//! (`util::rand`); `reseed` can be called later to mix in fresh entropy.
+
+use super::{device_id, Buffer, Transport, VirtQueue, VirtioDriver};
+use crate::driver::dma::{self, Direction};
+use crate::driver::{Device, DriverError};
+use crate::util::rand::{self, SeedSource};
+use alloc::vec;
+use spin::Mutex;
+
+const QUEUE_SIZE: u16 = 8;
+/// Bytes drawn from the device per reseed.
+const SEED_BYTES: usize = 64;
+/// How long to wait for the device to fill a buffer.
+const REQUEST_TIMEOUT_MS: u64 = 100;
+
+struct VirtioRng {
+    transport: Transport,
+    queue: VirtQueue,
+}
+
+static DEVICE: Mutex<Option<VirtioRng>> = Mutex::new(None);
+
+pub(super) static DRIVER: VirtioDriver = VirtioDriver {
+    name: "virtio-rng",
+    device_id: device_id::ENTROPY,
+    probe,
+};
+
+fn probe(_device: &Device, transport: Transport) -> Result<(), DriverError> {
+    transport.begin_init(0)?;
+    let queue = match transport.setup_queue(0, QUEUE_SIZE) {
+        Ok(queue) => queue,
+        Err(e) => {
+            transport.fail();
+            return Err(e);
+        }
+    };
+    transport.driver_ok();
+
+    let mut slot = DEVICE.lock();
+    if slot.is_some() {
+        // One entropy source is enough.
+        transport.reset();
+        return Err(DriverError::Unsupported);
+    }
+    *slot = Some(VirtioRng { transport, queue });
+    drop(slot);
+    reseed()
+}
+
+/// Fills `dest` with bytes from the device. Returns the number of bytes
+/// the device provided, which may be less than requested.
+pub fn read(dest: &mut [u8]) -> Result<usize, DriverError> {
+    let mut slot = DEVICE.lock();
+    let rng = slot.as_mut().ok_or(DriverError::NotPresent)?;
+    let len = dest.len().min(u32::MAX as usize);
+    let mapping = dma::map_for_device(dest.as_mut_ptr() as usize, len, Direction::FromDevice)?;
+    rng.queue.add(&[Buffer::from(mapping)]).ok_or(DriverError::InitFailed)?;
+    match rng.transport.submit_and_wait(&mut rng.queue, REQUEST_TIMEOUT_MS) {
+        Ok((_, written)) => {
+            dma::sync_for_cpu(&mapping);
+            Ok((written as usize).min(len))
+        }
+        Err(e) => {
+            // The device still owns `dest`; reset it before the buffer goes
+            // away and give up on it.
+            rng.transport.reset();
+            *slot = None;
+            Err(e)
+        }
+    }
+}
+
+/// Returns `true` if an entropy device was probed.
+pub fn is_present() -> bool {
+    DEVICE.lock().is_some()
+}
+
+/// Mixes fresh device entropy into the kernel RNG.
+pub fn reseed() -> Result<(), DriverError> {
+    let mut seed = vec![0u8; SEED_BYTES];
+    let mut filled = 0;
+    while filled < SEED_BYTES {
+        match read(&mut seed[filled..])? {
+            0 => return Err(DriverError::InitFailed),
+            n => filled += n,
+        }
+    }
+    rand::add_entropy(&seed, SeedSource::Device(DRIVER.name));
+    seed.fill(0);
+    Ok(())
+}
@@ -0,0 +1,389 @@
+// nt_rustos/src/fdt.rs
+
+//! # Flattened Device Tree
+//!
+//! A read-only, allocation-free parser for the flattened device tree blob
+//! (DTB) handed over by the firmware. Nodes are visited in the blob's
+//! depth-first order; each carries the `#address-cells` / `#size-cells` of
+//! its parent, which is what decoding its `reg` property needs.
+//!
+//! All multi-byte values in the blob are big-endian.
+
+use core::str;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// Blob versions whose layout the parser understands.
+const FDT_MIN_VERSION: u32 = 16;
+const FDT_VERSION: u32 = 17;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Deepest node nesting the parser follows.
+pub const MAX_DEPTH: usize = 16;
+
+/// Errors found while validating or walking a blob.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic,
+    /// The blob's version is not compatible with version 16 or 17.
+    BadVersion,
+    /// An offset or length points outside the blob.
+    Truncated,
+    /// The structure block holds an unknown token.
+    BadToken,
+    /// Nodes nest deeper than `MAX_DEPTH`.
+    TooDeep,
+}
+
+/// A validated device tree blob.
+#[derive(Debug, Copy, Clone)]
+pub struct Fdt {
+    blob: &'static [u8],
+    structs: &'static [u8],
+    strings: &'static [u8],
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+fn be64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(((be32(bytes, offset)? as u64) << 32) | be32(bytes, offset + 4)? as u64)
+}
+
+/// Reads the NUL-terminated string at `offset`.
+fn c_str(bytes: &[u8], offset: usize) -> Option<&str> {
+    let rest = bytes.get(offset..)?;
+    let len = rest.iter().position(|&b| b == 0)?;
+    str::from_utf8(&rest[..len]).ok()
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+impl Fdt {
+    /// Validates the blob at physical address `addr`.
+    ///
+    /// # Safety
+    /// `addr` must point to readable memory holding a device tree that
+    /// stays valid and unmodified for the rest of the kernel's life.
+    pub unsafe fn from_addr(addr: usize) -> Result<Self, FdtError> {
+        let header = core::slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if be32(header, 0) != Some(FDT_MAGIC) {
+            return Err(FdtError::BadMagic);
+        }
+        let total = be32(header, 4).ok_or(FdtError::Truncated)? as usize;
+        Self::from_bytes(core::slice::from_raw_parts(addr as *const u8, total))
+    }
+
+    /// Validates a blob held in memory.
+    pub fn from_bytes(blob: &'static [u8]) -> Result<Self, FdtError> {
+        let field = |index: usize| be32(blob, index * 4).ok_or(FdtError::Truncated);
+        if field(0)? != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+        let total = field(1)? as usize;
+        if total > blob.len() || total < HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+        if field(5)? < FDT_MIN_VERSION || field(6)? > FDT_VERSION {
+            return Err(FdtError::BadVersion);
+        }
+        let (struct_off, strings_off) = (field(2)? as usize, field(3)? as usize);
+        let (strings_size, struct_size) = (field(8)? as usize, field(9)? as usize);
+        let structs = blob
+            .get(struct_off..struct_off + struct_size)
+            .ok_or(FdtError::Truncated)?;
+        let strings = blob
+            .get(strings_off..strings_off + strings_size)
+            .ok_or(FdtError::Truncated)?;
+        Ok(Self { blob: &blob[..total], structs, strings })
+    }
+
+    /// Returns the size of the blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// Returns the ID of the boot CPU recorded in the header.
+    pub fn boot_cpuid(&self) -> u32 {
+        be32(self.blob, 28).unwrap_or(0)
+    }
+
+    /// Iterates over the memory reservation block: ranges the firmware
+    /// keeps for itself (`/memreserve/` entries), outside the tree proper.
+    pub fn memory_reservations(&self) -> impl Iterator<Item = RegEntry> {
+        let blob = self.blob;
+        let mut offset = be32(blob, 16).map_or(blob.len(), |off| off as usize);
+        core::iter::from_fn(move || {
+            let entry = RegEntry { address: be64(blob, offset)?, size: be64(blob, offset + 8)? };
+            if entry.address == 0 && entry.size == 0 {
+                return None;
+            }
+            offset += 16;
+            Some(entry)
+        })
+    }
+
+    /// Iterates over all nodes, depth first, starting with the root.
+    pub fn nodes(&self) -> Nodes {
+        Nodes { fdt: *self, offset: 0, depth: 0, cells: [(2, 1); MAX_DEPTH + 1], done: false }
+    }
+
+    /// Returns the first node named `name`, with or without its unit
+    /// address.
+    pub fn find_node(&self, name: &str) -> Option<Node> {
+        self.nodes().filter_map(Result::ok).find(|n| n.name == name || n.base_name() == name)
+    }
+
+    /// Returns the first node compatible with `compatible`.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node> {
+        self.nodes()
+            .filter_map(Result::ok)
+            .find(|n| n.compatible().any(|c| c == compatible))
+    }
+
+    /// Returns the node whose `phandle` is `phandle`.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node> {
+        self.nodes().filter_map(Result::ok).find(|n| {
+            n.property("phandle").and_then(|p| p.as_u32()) == Some(phandle)
+        })
+    }
+
+    fn token(&self, offset: usize) -> Result<u32, FdtError> {
+        be32(self.structs, offset).ok_or(FdtError::Truncated)
+    }
+}
+
+/// A node of the tree.
+#[derive(Debug, Copy, Clone)]
+pub struct Node {
+    fdt: Fdt,
+    /// The node name including its unit address, empty for the root.
+    pub name: &'static str,
+    /// Nesting depth; the root is at depth 0.
+    pub depth: usize,
+    /// `#address-cells` and `#size-cells` of the parent.
+    pub address_cells: u32,
+    pub size_cells: u32,
+    /// Offset of the node's first property in the structure block.
+    props: usize,
+}
+
+/// A property of a node.
+#[derive(Debug, Copy, Clone)]
+pub struct Property {
+    pub name: &'static str,
+    pub value: &'static [u8],
+}
+
+impl Property {
+    /// Returns the value as a big-endian 32-bit cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        (self.value.len() == 4).then(|| be32(self.value, 0)).flatten()
+    }
+
+    /// Returns the value as a NUL-terminated string.
+    pub fn as_str(&self) -> Option<&'static str> {
+        c_str(self.value, 0)
+    }
+
+    /// Iterates over the value as a list of NUL-terminated strings.
+    pub fn as_str_list(&self) -> impl Iterator<Item = &'static str> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| str::from_utf8(s).ok())
+    }
+
+    /// Iterates over the value as big-endian 32-bit cells.
+    pub fn cells(&self) -> impl Iterator<Item = u32> {
+        self.value
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+    }
+}
+
+/// A `reg` entry: a bus address range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegEntry {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// Reads a number spanning `count` cells at `cells`.
+fn read_cells(cells: &mut impl Iterator<Item = u32>, count: u32) -> Option<u64> {
+    let mut value = 0u64;
+    for _ in 0..count {
+        value = (value << 32) | cells.next()? as u64;
+    }
+    Some(value)
+}
+
+impl Node {
+    /// Returns the tree the node belongs to.
+    pub fn tree(&self) -> Fdt {
+        self.fdt
+    }
+
+    /// Returns the name without its unit address.
+    pub fn base_name(&self) -> &'static str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    /// Iterates over the node's properties.
+    pub fn properties(&self) -> Properties {
+        Properties { fdt: self.fdt, offset: self.props }
+    }
+
+    /// Returns the property named `name`.
+    pub fn property(&self, name: &str) -> Option<Property> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    /// Iterates over the `compatible` strings, most specific first.
+    pub fn compatible(&self) -> impl Iterator<Item = &'static str> {
+        self.property("compatible").into_iter().flat_map(|p| p.as_str_list())
+    }
+
+    /// Returns `false` if the node's `status` is other than "okay".
+    pub fn is_enabled(&self) -> bool {
+        self.property("status")
+            .and_then(|p| p.as_str())
+            .map_or(true, |s| s == "okay" || s == "ok")
+    }
+
+    /// Iterates over the `reg` entries, decoded with the parent's cell sizes.
+    pub fn reg(&self) -> impl Iterator<Item = RegEntry> {
+        let (address_cells, size_cells) = (self.address_cells, self.size_cells);
+        let mut cells = self.property("reg").into_iter().flat_map(|p| p.cells());
+        core::iter::from_fn(move || {
+            let address = read_cells(&mut cells, address_cells)?;
+            let size = read_cells(&mut cells, size_cells)?;
+            Some(RegEntry { address, size })
+        })
+    }
+
+    /// Iterates over the cells of the `interrupts` property.
+    ///
+    /// On the virt machine every interrupt specifier is a single cell.
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> {
+        self.property("interrupts").into_iter().flat_map(|p| p.cells())
+    }
+}
+
+/// Iterator over the properties of a node.
+pub struct Properties {
+    fdt: Fdt,
+    offset: usize,
+}
+
+impl Iterator for Properties {
+    type Item = Property;
+
+    fn next(&mut self) -> Option<Property> {
+        loop {
+            match self.fdt.token(self.offset).ok()? {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let len = be32(self.fdt.structs, self.offset + 4)? as usize;
+                    let name_off = be32(self.fdt.structs, self.offset + 8)? as usize;
+                    let start = self.offset + 12;
+                    let value = self.fdt.structs.get(start..start + len)?;
+                    self.offset = align4(start + len);
+                    let name = c_str(self.fdt.strings, name_off)?;
+                    return Some(Property { name, value });
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Depth-first iterator over the nodes of a tree.
+pub struct Nodes {
+    fdt: Fdt,
+    offset: usize,
+    depth: usize,
+    /// `#address-cells` / `#size-cells` declared at each depth.
+    cells: [(u32, u32); MAX_DEPTH + 1],
+    done: bool,
+}
+
+impl Nodes {
+    fn fail(&mut self, error: FdtError) -> Option<Result<Node, FdtError>> {
+        self.done = true;
+        Some(Err(error))
+    }
+}
+
+impl Iterator for Nodes {
+    type Item = Result<Node, FdtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let token = match self.fdt.token(self.offset) {
+                Ok(token) => token,
+                Err(e) => return self.fail(e),
+            };
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = match c_str(self.fdt.structs, self.offset + 4) {
+                        Some(name) => name,
+                        None => return self.fail(FdtError::Truncated),
+                    };
+                    if self.depth > MAX_DEPTH {
+                        return self.fail(FdtError::TooDeep);
+                    }
+                    let depth = self.depth;
+                    let (address_cells, size_cells) =
+                        if depth == 0 { (2, 1) } else { self.cells[depth - 1] };
+                    let props = align4(self.offset + 4 + name.len() + 1);
+                    let node = Node {
+                        fdt: self.fdt,
+                        name,
+                        depth,
+                        address_cells,
+                        size_cells,
+                        props,
+                    };
+                    // The cell sizes this node declares apply to its children.
+                    let own = (
+                        node.property("#address-cells").and_then(|p| p.as_u32()).unwrap_or(2),
+                        node.property("#size-cells").and_then(|p| p.as_u32()).unwrap_or(1),
+                    );
+                    self.cells[depth] = own;
+                    let mut properties = node.properties();
+                    while properties.next().is_some() {}
+                    self.offset = properties.offset;
+                    self.depth += 1;
+                    return Some(Ok(node));
+                }
+                FDT_END_NODE => {
+                    if self.depth == 0 {
+                        return self.fail(FdtError::BadToken);
+                    }
+                    self.depth -= 1;
+                    self.offset += 4;
+                }
+                FDT_NOP => self.offset += 4,
+                FDT_END => {
+                    self.done = true;
+                    return None;
+                }
+                // Properties are consumed with their node.
+                _ => return self.fail(FdtError::BadToken),
+            }
+        }
+    }
+}
@@ -0,0 +1,555 @@
+// nt_rustos/src/fs/debugfs.rs
+
+//! A read-only filesystem of kernel state, mounted on `/debug`.
+//!
+//! Subsystems register files by path, such as `/alloc/stats`, each with a
+//! render function. A render function describes the state as records of
+//! named fields; it knows nothing of formats. Reading the file shows the
+//! records as text: a single record as one `name: value` line per field,
+//! several records as a table with a header row. `render` also gives them
+//! as JSON Lines, one object per record, or as `name=value` lines, for
+//! machine-readable exports; `format` serializes records built elsewhere
+//! the same ways.
+//!
+//! Directories are implied by the registered paths and disappear with the
+//! last file below them. A file is rendered afresh for each read and
+//! `stat`, so its size is that of the current text; a reader wanting a
+//! consistent snapshot reads it in one go, as `fs::read_file` does.
+
+use super::{dentry, path};
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata};
+use crate::init::initcall::InitResult;
+use crate::task::{self, scheduler};
+use crate::trap::{self, TrapType};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+/// Where `init` mounts the filesystem.
+pub const MOUNT_POINT: &str = "/debug";
+
+const FILE_MODE: u16 = 0o444;
+const DIR_MODE: u16 = 0o555;
+
+/// The value of a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    UInt(u64),
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::UInt(value) => write!(f, "{}", value),
+            Field::Int(value) => write!(f, "{}", value),
+            Field::Bool(value) => write!(f, "{}", value),
+            Field::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+macro_rules! field_from {
+    ($($ty:ty => $variant:ident as $as:ty),* $(,)?) => {
+        $(impl From<$ty> for Field {
+            fn from(value: $ty) -> Self {
+                Field::$variant(value as $as)
+            }
+        })*
+    };
+}
+
+field_from!(u8 => UInt as u64, u32 => UInt as u64, u64 => UInt as u64, usize => UInt as u64,
+            i32 => Int as i64, i64 => Int as i64);
+
+impl From<bool> for Field {
+    fn from(value: bool) -> Self {
+        Field::Bool(value)
+    }
+}
+
+impl From<&str> for Field {
+    fn from(value: &str) -> Self {
+        Field::Str(String::from(value))
+    }
+}
+
+impl From<String> for Field {
+    fn from(value: String) -> Self {
+        Field::Str(value)
+    }
+}
+
+/// One record of a file: named fields in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    fields: Vec<(&'static str, Field)>,
+}
+
+impl Record {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a field, builder style.
+    pub fn field(mut self, name: &'static str, value: impl Into<Field>) -> Self {
+        self.fields.push((name, value.into()));
+        self
+    }
+
+    pub fn fields(&self) -> &[(&'static str, Field)] {
+        &self.fields
+    }
+}
+
+/// What a render function fills in.
+#[derive(Debug, Default)]
+pub struct Output {
+    records: Vec<Record>,
+}
+
+impl Output {
+    pub fn push(&mut self, record: Record) {
+        self.records.push(record);
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+}
+
+/// Describes the state a file shows.
+pub type Render = fn(&mut Output);
+
+/// How `render` formats the records.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// What reading the file gives.
+    Text,
+    /// One JSON object per line.
+    Json,
+    /// One line of space-separated `name=value` pairs per record. Strings
+    /// that are empty or contain spaces, quotes or `=` are quoted as in
+    /// JSON.
+    KeyValue,
+}
+
+/// Registered files by normalized path.
+static FILES: Mutex<BTreeMap<String, Render>> = Mutex::new(BTreeMap::new());
+
+/// Registers the file `path` (relative to the mount point), shown by
+/// `render`. Fails with `AlreadyExists` if the path, or a directory or
+/// file it would clash with, is taken.
+pub fn register(path: &str, render: Render) -> Result<(), FsError> {
+    let path = path::normalize(path)?;
+    if path == "/" {
+        return Err(FsError::InvalidPath);
+    }
+    let mut files = FILES.lock();
+    if files.keys().any(|file| path::is_under(file, &path) || path::is_under(&path, file)) {
+        return Err(FsError::AlreadyExists);
+    }
+    files.insert(path, render);
+    Ok(())
+}
+
+/// Removes the file `path`.
+pub fn unregister(path: &str) -> Result<(), FsError> {
+    let path = path::normalize(path)?;
+    FILES.lock().remove(&path).ok_or(FsError::NotFound)?;
+    // The file, and directories left empty, may be cached under any mount.
+    for mount in super::mounts().iter().filter(|m| m.fs().name() == "debugfs") {
+        dentry::invalidate(mount.path());
+    }
+    Ok(())
+}
+
+/// Returns the registered file paths, sorted.
+pub fn files() -> Vec<String> {
+    FILES.lock().keys().cloned().collect()
+}
+
+/// Renders the file `path` in `format`.
+pub fn render(path: &str, format: Format) -> Result<String, FsError> {
+    let path = path::normalize(path)?;
+    let render = FILES.lock().get(&path).copied();
+    match render {
+        Some(render) => Ok(render_with(render, format)),
+        None if is_dir(&path) => Err(FsError::IsADirectory),
+        None => Err(FsError::NotFound),
+    }
+}
+
+/// Runs `render` without holding `FILES`, so it may look at other files.
+fn render_with(render: Render, format: Format) -> String {
+    let mut output = Output::default();
+    render(&mut output);
+    self::format(&output.records, format)
+}
+
+/// Serializes `records` in `format`, as `render` does a file's.
+pub fn format(records: &[Record], format: Format) -> String {
+    let mut text = String::new();
+    let _ = match format {
+        Format::Text => write_text(&mut text, records),
+        Format::Json => write_json(&mut text, records),
+        Format::KeyValue => write_key_value(&mut text, records),
+    };
+    text
+}
+
+fn write_text(out: &mut String, records: &[Record]) -> fmt::Result {
+    if let [record] = records {
+        let width = record.fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, value) in &record.fields {
+            writeln!(out, "{:<width$}  {}", format!("{}:", name), value, width = width + 1)?;
+        }
+        return Ok(());
+    }
+    let Some(first) = records.first() else {
+        return Ok(());
+    };
+    let cells: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| record.fields.iter().map(|(_, value)| value.to_string()).collect())
+        .collect();
+    let widths: Vec<usize> = first
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| cells.iter().filter_map(|row| row.get(i)).map(String::len).fold(name.len(), usize::max))
+        .collect();
+    let header: Vec<&str> = first.fields.iter().map(|(name, _)| *name).collect();
+    write_row(out, &header, &widths)?;
+    for row in &cells {
+        let row: Vec<&str> = row.iter().map(String::as_str).collect();
+        write_row(out, &row, &widths)?;
+    }
+    Ok(())
+}
+
+fn write_row(out: &mut String, cells: &[&str], widths: &[usize]) -> fmt::Result {
+    let mut line = String::new();
+    for (i, cell) in cells.iter().enumerate() {
+        if i > 0 {
+            line.push_str("  ");
+        }
+        let width = widths.get(i).copied().unwrap_or(0);
+        write!(line, "{:<width$}", cell, width = width)?;
+    }
+    writeln!(out, "{}", line.trim_end())
+}
+
+fn write_json(out: &mut String, records: &[Record]) -> fmt::Result {
+    for record in records {
+        out.push('{');
+        for (i, (name, value)) in record.fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_json_str(out, name)?;
+            out.push(':');
+            match value {
+                Field::Str(text) => write_json_str(out, text)?,
+                other => write!(out, "{}", other)?,
+            }
+        }
+        out.push_str("}\n");
+    }
+    Ok(())
+}
+
+fn write_key_value(out: &mut String, records: &[Record]) -> fmt::Result {
+    for record in records {
+        for (i, (name, value)) in record.fields.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            write!(out, "{}=", name)?;
+            match value {
+                Field::Str(text) if needs_quotes(text) => write_json_str(out, text)?,
+                other => write!(out, "{}", other)?,
+            }
+        }
+        out.push('\n');
+    }
+    Ok(())
+}
+
+/// Whether a string value would not read back as one `name=value` pair.
+fn needs_quotes(text: &str) -> bool {
+    text.is_empty() || text.chars().any(|c| c == ' ' || c == '"' || c == '=' || c.is_control())
+}
+
+fn write_json_str(out: &mut String, text: &str) -> fmt::Result {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+/// Whether files are registered below `path`.
+fn is_dir(path: &str) -> bool {
+    path == "/" || FILES.lock().keys().any(|file| file != path && path::is_under(file, path))
+}
+
+/// An inode number for `path`, stable while the path exists.
+fn ino(path: &str) -> u64 {
+    // FNV-1a
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// A file or directory, named by its path in the filesystem.
+struct DebugInode {
+    path: String,
+}
+
+impl DebugInode {
+    fn render(&self) -> Result<String, FsError> {
+        render(&self.path, Format::Text)
+    }
+
+    fn child(&self, name: &str) -> String {
+        match self.path.as_str() {
+            "/" => format!("/{}", name),
+            parent => format!("{}/{}", parent, name),
+        }
+    }
+}
+
+impl Inode for DebugInode {
+    fn metadata(&self) -> Metadata {
+        let (file_type, size, mode) = match self.render() {
+            Ok(text) => (FileType::Regular, text.len() as u64, FILE_MODE),
+            Err(_) => (FileType::Directory, 0, DIR_MODE),
+        };
+        Metadata { ino: ino(&self.path), file_type, size, nlink: 1, mode, mtime: 0 }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let text = self.render()?;
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(text.len());
+        let len = buf.len().min(text.len() - start);
+        buf[..len].copy_from_slice(&text.as_bytes()[start..start + len]);
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if !is_dir(&self.path) {
+            return Err(FsError::NotADirectory);
+        }
+        let path = self.child(name);
+        let is_file = FILES.lock().contains_key(&path);
+        if !is_file && !is_dir(&path) {
+            return Err(FsError::NotFound);
+        }
+        Ok(Arc::new(DebugInode { path }))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if !is_dir(&self.path) {
+            return Err(FsError::NotADirectory);
+        }
+        let prefix = match self.path.as_str() {
+            "/" => String::from("/"),
+            parent => format!("{}/", parent),
+        };
+        // Files and directories directly below, each once, by name.
+        let mut children: BTreeMap<String, FileType> = BTreeMap::new();
+        for file in FILES.lock().keys() {
+            let Some(rest) = file.strip_prefix(&prefix) else {
+                continue;
+            };
+            match rest.split_once('/') {
+                Some((dir, _)) => children.insert(String::from(dir), FileType::Directory),
+                None => children.insert(String::from(rest), FileType::Regular),
+            };
+        }
+        Ok(children
+            .into_iter()
+            .map(|(name, file_type)| DirEntry { ino: ino(&self.child(&name)), name, file_type })
+            .collect())
+    }
+
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
+
+/// The filesystem over the registered files; every instance shows the
+/// same files.
+pub struct DebugFs;
+
+impl FileSystem for DebugFs {
+    fn name(&self) -> &str {
+        "debugfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(DebugInode { path: String::from("/") })
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+fn render_alloc_stats(out: &mut Output) {
+    let Some(stats) = crate::init::alloc::stats() else {
+        return;
+    };
+    out.push(
+        Record::new()
+            .field("total", stats.total_size)
+            .field("used", stats.used_size)
+            .field("free", stats.free_size)
+            .field("peak_used", stats.peak_used_size)
+            .field("largest_free", stats.max_free_block_size)
+            .field("live_allocs", stats.alloc_count)
+            .field("allocs", stats.total_allocs)
+            .field("frees", stats.total_frees)
+            .field("failed_allocs", stats.failed_allocs)
+            .field("fragmentation_percent", stats.fragmentation_percent),
+    );
+}
+
+fn render_alloc_heaps(out: &mut Output) {
+    for heap in crate::init::alloc::subheap::heaps() {
+        out.push(
+            Record::new()
+                .field("name", heap.name)
+                .field("quota", heap.size)
+                .field("used", heap.stats.used_size)
+                .field("peak_used", heap.stats.peak_used_size)
+                .field("live_allocs", heap.stats.alloc_count)
+                .field("failed_allocs", heap.stats.failed_allocs),
+        );
+    }
+}
+
+fn render_alloc_regions(out: &mut Output) {
+    for region in crate::init::alloc::regions() {
+        out.push(
+            Record::new()
+                .field("region", region.index)
+                .field("start", region.start)
+                .field("size", region.size)
+                .field("used", region.stats.used_size)
+                .field("free", region.stats.free_size)
+                .field("live_allocs", region.stats.alloc_count)
+                .field("free_blocks", region.stats.free_count),
+        );
+    }
+}
+
+fn render_alloc_slabs(out: &mut Output) {
+    for cache in crate::init::alloc::slab::caches() {
+        out.push(
+            Record::new()
+                .field("name", cache.name)
+                .field("object_size", cache.object_size)
+                .field("per_slab", cache.objects_per_slab)
+                .field("slabs", cache.slabs)
+                .field("empty_slabs", cache.empty_slabs)
+                .field("in_use", cache.in_use)
+                .field("peak_in_use", cache.peak_in_use)
+                .field("allocs", cache.total_allocs)
+                .field("frees", cache.total_frees)
+                .field("failed_allocs", cache.failed_allocs),
+        );
+    }
+}
+
+fn render_trap_counts(out: &mut Output) {
+    for trap_type in (0..TrapType::COUNT).filter_map(TrapType::from_index) {
+        out.push(Record::new().field("trap", format!("{:?}", trap_type)).field("count", trap::trap_count(trap_type)));
+    }
+}
+
+fn render_trap_handlers(out: &mut Output) {
+    for handler in trap::handlers() {
+        out.push(
+            Record::new()
+                .field("trap", format!("{:?}", handler.trap_type))
+                .field("priority", handler.priority)
+                .field("level", format!("{:?}", handler.protection_level))
+                .field("owner", handler.registrar_id)
+                .field("description", handler.description),
+        );
+    }
+}
+
+fn render_sched_harts(out: &mut Output) {
+    for load in scheduler::hart_loads() {
+        out.push(
+            Record::new()
+                .field("hart", load.hart)
+                .field("online", load.online)
+                .field("queued", load.queued)
+                .field("utilization", load.utilization),
+        );
+    }
+}
+
+fn render_sched_cpu(out: &mut Output) {
+    let times = task::cpu_stats();
+    out.push(
+        Record::new()
+            .field("tasks", scheduler::task_count())
+            .field("user_ticks", times.user)
+            .field("system_ticks", times.system)
+            .field("irq_ticks", times.irq)
+            .field("idle_ticks", times.idle),
+    );
+}
+
+/// Files of the core subsystems.
+const CORE_FILES: &[(&str, Render)] = &[
+    ("/alloc/stats", render_alloc_stats),
+    ("/alloc/heaps", render_alloc_heaps),
+    ("/alloc/regions", render_alloc_regions),
+    ("/alloc/slabs", render_alloc_slabs),
+    ("/trap/counts", render_trap_counts),
+    ("/trap/handlers", render_trap_handlers),
+    ("/sched/harts", render_sched_harts),
+    ("/sched/cpu", render_sched_cpu),
+];
+
+/// Registers the core files and mounts the filesystem on `MOUNT_POINT`.
+pub fn init() -> Result<(), FsError> {
+    for (path, render) in CORE_FILES {
+        register(path, *render)?;
+    }
+    match super::mkdir(MOUNT_POINT) {
+        Ok(()) | Err(FsError::AlreadyExists) => {}
+        Err(e) => return Err(e),
+    }
+    super::mount(MOUNT_POINT, Arc::new(DebugFs))
+}
+
+/// Mounts `/debug` once the root filesystem is up.
+fn mount_at_boot() -> InitResult {
+    init().map_err(|e| format!("failed to mount {}: {}", MOUNT_POINT, e))
+}
+
+crate::initcall!(subsys, 75, mount_at_boot);
@@ -0,0 +1,92 @@
+// nt_rustos/src/fs/dentry.rs
+
+//! Directory entries and the dentry cache.
+//!
+//! A `Dentry` ties a normalized path to the inode it resolved to and the
+//! mount it lies on. Resolved paths are cached, so repeated lookups do not
+//! walk the filesystem again. Operations that change the namespace drop
+//! the affected paths, and everything below them, from the cache. The
+//! cache is small: when it fills up it is emptied.
+
+use super::mount::Mount;
+use super::path;
+use super::Inode;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Bound;
+use spin::Mutex;
+
+/// Cached dentries before the cache is emptied.
+const CACHE_CAPACITY: usize = 128;
+
+/// A resolved path.
+pub struct Dentry {
+    path: String,
+    inode: Arc<dyn Inode>,
+    mount: Arc<Mount>,
+}
+
+impl Dentry {
+    pub(super) fn new(path: String, inode: Arc<dyn Inode>, mount: Arc<Mount>) -> Self {
+        Self { path, inode, mount }
+    }
+
+    /// Returns the normalized path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the last path component, or `/` for the root.
+    pub fn name(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(i) if self.path.len() > 1 => &self.path[i + 1..],
+            _ => "/",
+        }
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    /// Returns the mount the path lies on.
+    pub fn mount(&self) -> &Arc<Mount> {
+        &self.mount
+    }
+}
+
+static CACHE: Mutex<BTreeMap<String, Arc<Dentry>>> = Mutex::new(BTreeMap::new());
+
+/// Returns the cached dentry of normalized `path`.
+pub(super) fn lookup(path: &str) -> Option<Arc<Dentry>> {
+    CACHE.lock().get(path).cloned()
+}
+
+/// Caches `dentry`.
+pub(super) fn insert(dentry: Arc<Dentry>) {
+    let mut cache = CACHE.lock();
+    if cache.len() >= CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(dentry.path.clone(), dentry);
+}
+
+/// Drops normalized `path` and every path below it from the cache.
+pub(super) fn invalidate(path: &str) {
+    let mut cache = CACHE.lock();
+    let stale: Vec<String> = cache
+        .range::<str, _>((Bound::Included(path), Bound::Unbounded))
+        .take_while(|(p, _)| p.starts_with(path))
+        .filter(|(p, _)| path::is_under(p, path))
+        .map(|(p, _)| p.clone())
+        .collect();
+    for p in stale {
+        cache.remove(&p);
+    }
+}
+
+/// Returns the number of cached dentries.
+pub fn cached() -> usize {
+    CACHE.lock().len()
+}
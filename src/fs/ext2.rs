@@ -0,0 +1,419 @@
+// nt_rustos/src/fs/ext2.rs
+
+//! ext2 filesystem, read-only.
+//!
+//! The superblock, 1024 bytes into the disk, gives the block size and how
+//! blocks and inodes are split into block groups; the group descriptors in
+//! the block after it locate each group's inode table. An inode maps its
+//! data through twelve direct block pointers and single, double and triple
+//! indirect blocks of further pointers; a zero pointer is a hole that reads
+//! as zeros. Directories are files of variable-length entries, each giving
+//! the inode number and name of a child.
+//!
+//! All reads go through the buffer cache. Volumes using features that
+//! change the on-disk layout beyond this, such as extents or 64-bit block
+//! numbers, are refused with `NotSupported`.
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata};
+use crate::block::{cache, Disk};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+
+/// Directory entries record the file type.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Group metadata may lie outside its group; descriptors still locate it.
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+/// Inodes of revision 0 filesystems, which have no size field.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: usize = 32;
+const ROOT_INO: u32 = 2;
+
+/// The inode maps its data with extents rather than block pointers.
+const EXTENTS_FL: u32 = 0x0008_0000;
+
+const S_IFMT: u16 = 0o170000;
+const S_IFREG: u16 = 0o100000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFLNK: u16 = 0o120000;
+const S_IFCHR: u16 = 0o020000;
+const S_IFBLK: u16 = 0o060000;
+
+/// Direct block pointers in an inode; the next three are indirect.
+const DIRECT_BLOCKS: u64 = 12;
+/// Bytes of `i_block`, where short symlink targets are stored inline.
+const INLINE_SIZE: u64 = 60;
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Returns the file type of inode mode `mode`; FIFOs and sockets have none.
+fn file_type(mode: u16) -> Option<FileType> {
+    match mode & S_IFMT {
+        S_IFREG => Some(FileType::Regular),
+        S_IFDIR => Some(FileType::Directory),
+        S_IFLNK => Some(FileType::Symlink),
+        S_IFCHR => Some(FileType::CharDevice),
+        S_IFBLK => Some(FileType::BlockDevice),
+        _ => None,
+    }
+}
+
+/// Geometry of a mounted volume.
+struct Volume {
+    disk: Arc<Disk>,
+    block_size: u64,
+    blocks: u32,
+    inodes: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    /// First block of each group's inode table.
+    inode_tables: Vec<u32>,
+    has_file_type: bool,
+    label: String,
+}
+
+impl Volume {
+    /// Parses the superblock and group descriptors of `disk`. Disks
+    /// without an ext2 superblock give `NotSupported`.
+    fn probe(disk: Arc<Disk>) -> Result<Self, FsError> {
+        let mut sb = [0u8; SUPERBLOCK_SIZE];
+        cache::read_bytes(&disk, SUPERBLOCK_OFFSET, &mut sb)?;
+        if le16(&sb, 56) != MAGIC {
+            return Err(FsError::NotSupported);
+        }
+        let incompat = le32(&sb, 96);
+        if incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(FsError::NotSupported);
+        }
+        let inodes = le32(&sb, 0);
+        let blocks = le32(&sb, 4);
+        let first_data_block = le32(&sb, 20);
+        let log_block_size = le32(&sb, 24);
+        let blocks_per_group = le32(&sb, 32);
+        let inodes_per_group = le32(&sb, 40);
+        let inode_size = match le32(&sb, 76) {
+            0 => GOOD_OLD_INODE_SIZE,
+            _ => le16(&sb, 88) as usize,
+        };
+        if log_block_size > 6 || blocks_per_group == 0 || inodes_per_group == 0 || first_data_block >= blocks {
+            return Err(FsError::Corrupted);
+        }
+        let block_size = 1024u64 << log_block_size;
+        if inode_size < GOOD_OLD_INODE_SIZE || !inode_size.is_power_of_two() || inode_size as u64 > block_size {
+            return Err(FsError::Corrupted);
+        }
+        if blocks as u64 * block_size > disk.block_count() * disk.block_size() as u64 {
+            return Err(FsError::Corrupted);
+        }
+        let groups = (blocks - first_data_block).div_ceil(blocks_per_group);
+        if (groups as u64) * (inodes_per_group as u64) < inodes as u64 {
+            return Err(FsError::Corrupted);
+        }
+
+        // The descriptor table starts in the block after the superblock.
+        let mut descriptors = vec![0u8; groups as usize * GROUP_DESC_SIZE];
+        cache::read_bytes(&disk, (first_data_block as u64 + 1) * block_size, &mut descriptors)?;
+        let inode_tables: Vec<u32> = descriptors.chunks_exact(GROUP_DESC_SIZE).map(|d| le32(d, 8)).collect();
+        if inode_tables.iter().any(|&table| table == 0 || table >= blocks) {
+            return Err(FsError::Corrupted);
+        }
+
+        let name = &sb[120..136];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        Ok(Self {
+            disk,
+            block_size,
+            blocks,
+            inodes,
+            inodes_per_group,
+            inode_size,
+            inode_tables,
+            has_file_type: incompat & INCOMPAT_FILETYPE != 0,
+            label: String::from_utf8_lossy(&name[..len]).into(),
+        })
+    }
+
+    /// Reads from block `block` at byte `offset` within it.
+    fn read_block(&self, block: u32, offset: u64, buf: &mut [u8]) -> Result<(), FsError> {
+        if block >= self.blocks {
+            return Err(FsError::Corrupted);
+        }
+        Ok(cache::read_bytes(&self.disk, block as u64 * self.block_size + offset, buf)?)
+    }
+
+    /// Reads the on-disk inode `ino`.
+    fn read_inode(&self, ino: u32) -> Result<RawInode, FsError> {
+        if ino == 0 || ino > self.inodes {
+            return Err(FsError::Corrupted);
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as u64;
+        let table = *self.inode_tables.get(group).ok_or(FsError::Corrupted)?;
+        let byte = index * self.inode_size as u64;
+        let mut raw = [0u8; GOOD_OLD_INODE_SIZE];
+        self.read_block(table + (byte / self.block_size) as u32, byte % self.block_size, &mut raw)?;
+
+        let mode = le16(&raw, 0);
+        // Regular files keep the upper half of the size where directories
+        // keep their ACL block.
+        let size_high = if mode & S_IFMT == S_IFREG { le32(&raw, 108) } else { 0 };
+        let mut block = [0u32; 15];
+        for (i, pointer) in block.iter_mut().enumerate() {
+            *pointer = le32(&raw, 40 + i * 4);
+        }
+        Ok(RawInode {
+            mode,
+            size: (size_high as u64) << 32 | le32(&raw, 4) as u64,
+            mtime: le32(&raw, 16),
+            links: le16(&raw, 26),
+            sectors: le32(&raw, 28),
+            flags: le32(&raw, 32),
+            block,
+        })
+    }
+
+    /// Returns the pointer at `index` in indirect block `block`.
+    fn pointer(&self, block: u32, index: u64) -> Result<u32, FsError> {
+        let mut entry = [0u8; 4];
+        self.read_block(block, index * 4, &mut entry)?;
+        Ok(u32::from_le_bytes(entry))
+    }
+
+    /// Maps logical block `logical` of `inode` to a disk block, 0 for a hole.
+    fn map(&self, inode: &RawInode, logical: u64) -> Result<u32, FsError> {
+        let per_block = self.block_size / 4;
+        if logical < DIRECT_BLOCKS {
+            return Ok(inode.block[logical as usize]);
+        }
+        // Walk down from the indirect root covering `logical`, taking one
+        // pointer per level.
+        let mut rest = logical - DIRECT_BLOCKS;
+        let mut span = per_block;
+        for level in 0..3 {
+            if rest < span {
+                let mut block = inode.block[DIRECT_BLOCKS as usize + level];
+                for _ in 0..=level {
+                    if block == 0 {
+                        return Ok(0);
+                    }
+                    span /= per_block;
+                    block = self.pointer(block, rest / span)?;
+                    rest %= span;
+                }
+                return Ok(block);
+            }
+            rest -= span;
+            span *= per_block;
+        }
+        Err(FsError::Corrupted)
+    }
+}
+
+/// The fields of an on-disk inode that reading needs.
+struct RawInode {
+    mode: u16,
+    size: u64,
+    mtime: u32,
+    links: u16,
+    /// Allocated 512-byte sectors, 0 for an inline symlink.
+    sectors: u32,
+    flags: u32,
+    block: [u32; 15],
+}
+
+struct Ext2Inode {
+    volume: Arc<Volume>,
+    ino: u32,
+    file_type: FileType,
+    raw: RawInode,
+}
+
+impl Ext2Inode {
+    fn open(volume: &Arc<Volume>, ino: u32) -> Result<Arc<Self>, FsError> {
+        let raw = volume.read_inode(ino)?;
+        let file_type = file_type(raw.mode).ok_or(FsError::NotSupported)?;
+        if raw.flags & EXTENTS_FL != 0 {
+            return Err(FsError::NotSupported);
+        }
+        Ok(Arc::new(Self { volume: volume.clone(), ino, file_type, raw }))
+    }
+
+    /// Reads file data from `offset`, zero-filling holes.
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let size = self.raw.size;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let block_size = self.volume.block_size;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let within = pos % block_size;
+            let n = (len - done).min((block_size - within) as usize);
+            match self.volume.map(&self.raw, pos / block_size)? {
+                0 => buf[done..done + n].fill(0),
+                block => self.volume.read_block(block, within, &mut buf[done..done + n])?,
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Reads and decodes the entries of this directory, without `.` and
+    /// `..`, as (name, inode, file type) triples.
+    fn entries(&self) -> Result<Vec<(String, u32, Option<FileType>)>, FsError> {
+        let block_size = self.volume.block_size as usize;
+        let mut entries = Vec::new();
+        let mut block = vec![0u8; block_size];
+        let mut offset = 0;
+        while offset < self.raw.size {
+            let len = self.read_data(offset, &mut block)?;
+            let mut pos = 0;
+            while pos + 8 <= len {
+                let ino = le32(&block, pos);
+                let rec_len = le16(&block, pos + 4) as usize;
+                let name_len = if self.volume.has_file_type {
+                    block[pos + 6] as usize
+                } else {
+                    le16(&block, pos + 6) as usize
+                };
+                if rec_len < 8 || !rec_len.is_multiple_of(4) || pos + rec_len > len || 8 + name_len > rec_len {
+                    return Err(FsError::Corrupted);
+                }
+                let name = &block[pos + 8..pos + 8 + name_len];
+                if ino != 0 && name != b"." && name != b".." {
+                    let file_type = match block[pos + 7] {
+                        _ if !self.volume.has_file_type => None,
+                        1 => Some(FileType::Regular),
+                        2 => Some(FileType::Directory),
+                        3 => Some(FileType::CharDevice),
+                        4 => Some(FileType::BlockDevice),
+                        7 => Some(FileType::Symlink),
+                        _ => None,
+                    };
+                    entries.push((String::from_utf8_lossy(name).into(), ino, file_type));
+                }
+                pos += rec_len;
+            }
+            offset += block_size as u64;
+        }
+        Ok(entries)
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: self.ino as u64,
+            file_type: self.file_type,
+            size: self.raw.size,
+            nlink: self.raw.links as u32,
+            mode: self.raw.mode & !S_IFMT,
+            mtime: self.raw.mtime as u64,
+        }
+    }
+
+    /// Reads file data; a symlink reads as its target.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        match self.file_type {
+            FileType::Regular => self.read_data(offset, buf),
+            FileType::Symlink if self.raw.sectors == 0 && self.raw.size <= INLINE_SIZE => {
+                // Short targets are stored in the block pointers themselves.
+                let mut inline = [0u8; INLINE_SIZE as usize];
+                for (chunk, pointer) in inline.chunks_exact_mut(4).zip(self.raw.block) {
+                    chunk.copy_from_slice(&pointer.to_le_bytes());
+                }
+                let target = &inline[..self.raw.size as usize];
+                let start = (offset as usize).min(target.len());
+                let len = buf.len().min(target.len() - start);
+                buf[..len].copy_from_slice(&target[start..start + len]);
+                Ok(len)
+            }
+            FileType::Symlink => self.read_data(offset, buf),
+            FileType::Directory => Err(FsError::IsADirectory),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if self.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let (_, ino, _) = self.entries()?.into_iter().find(|(n, _, _)| n == name).ok_or(FsError::NotFound)?;
+        Ok(Ext2Inode::open(&self.volume, ino)?)
+    }
+
+    /// Lists the directory. Entries whose type is not recorded, and FIFOs
+    /// and sockets, have their inode read to find it or are left out.
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if self.file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        let mut listing = Vec::new();
+        for (name, ino, recorded) in self.entries()? {
+            let file_type = match recorded {
+                Some(file_type) => Some(file_type),
+                None => file_type(self.volume.read_inode(ino)?.mode),
+            };
+            if let Some(file_type) = file_type {
+                listing.push(DirEntry { name, ino: ino as u64, file_type });
+            }
+        }
+        Ok(listing)
+    }
+}
+
+/// An ext2 volume on a disk, mounted read-only.
+pub struct Ext2Fs {
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2Fs {
+    /// Opens the ext2 volume on `disk`. Disks that do not hold one, or
+    /// whose volume uses unsupported features, give `NotSupported`.
+    pub fn new(disk: Arc<Disk>) -> Result<Self, FsError> {
+        let volume = Arc::new(Volume::probe(disk)?);
+        let root = Ext2Inode::open(&volume, ROOT_INO)?;
+        if root.file_type != FileType::Directory {
+            return Err(FsError::Corrupted);
+        }
+        Ok(Self { root })
+    }
+
+    /// Returns the volume name from the superblock.
+    pub fn label(&self) -> &str {
+        &self.root.volume.label
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.root.volume.block_size as usize
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,469 @@
+// nt_rustos/src/fs/fat32.rs
+
+//! FAT32 filesystem, read-only.
+//!
+//! A FAT32 volume starts with a boot sector describing its geometry,
+//! followed by reserved sectors, the file allocation tables and the data
+//! area, which is divided into clusters numbered from 2. A file's data is
+//! a chain of clusters linked through the FAT; a directory is a file of
+//! 32-byte entries. Long file names are stored in extra entries placed
+//! before the 8.3 entry they belong to, in UTF-16 pieces of 13 characters,
+//! each carrying a checksum of the short name.
+//!
+//! All reads go through the buffer cache, so the FAT and directories are
+//! read from the disk once. Names are matched case-insensitively, by long
+//! name or by 8.3 alias.
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata};
+use crate::block::{cache, Disk};
+use crate::time::wall;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Marks the volume label and serial number in the boot sector as valid.
+const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// Read-only, hidden, system and volume ID together mark a long name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// Case flags of an 8.3 entry: base name and extension are lower case.
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXT: u8 = 0x10;
+
+const DIR_ENTRY_SIZE: usize = 32;
+/// First byte of a free entry; no entries follow.
+const ENTRY_END: u8 = 0x00;
+/// First byte of a deleted entry.
+const ENTRY_DELETED: u8 = 0xE5;
+/// Stands for a name starting with 0xE5.
+const ENTRY_KANJI_E5: u8 = 0x05;
+
+/// Flag of the sequence number in the last piece of a long name.
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// UTF-16 units in each long name entry.
+const LONG_NAME_UNITS: usize = 13;
+/// Byte offsets of the units in a long name entry.
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// FAT entries hold 28 bits.
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+/// Entries from this value on end a chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+
+const ROOT_INO: u64 = 1;
+const FILE_MODE: u16 = 0o444;
+const DIR_MODE: u16 = 0o555;
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Geometry of a mounted volume.
+struct Volume {
+    disk: Arc<Disk>,
+    cluster_size: usize,
+    /// Byte offset of the first FAT.
+    fat_offset: u64,
+    /// Byte offset of cluster 2.
+    data_offset: u64,
+    /// Number of data clusters.
+    clusters: u32,
+    root_cluster: u32,
+    label: String,
+}
+
+impl Volume {
+    /// Parses the boot sector of `disk`. Disks without a FAT32 boot sector
+    /// give `NotSupported`; inconsistent geometry gives `Corrupted`.
+    fn probe(disk: Arc<Disk>) -> Result<Self, FsError> {
+        let mut boot = [0u8; 512];
+        cache::read_bytes(&disk, 0, &mut boot)?;
+        if boot[510..512] != BOOT_SIGNATURE {
+            return Err(FsError::NotSupported);
+        }
+        let bytes_per_sector = le16(&boot, 11) as u64;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = le16(&boot, 14) as u64;
+        let fats = boot[16] as u64;
+        let total = match le16(&boot, 19) {
+            0 => le32(&boot, 32) as u64,
+            n => n as u64,
+        };
+        let fat_sectors = le32(&boot, 36) as u64;
+        // FAT12 and FAT16 have a fixed root directory and 16-bit FAT size.
+        if le16(&boot, 17) != 0 || le16(&boot, 22) != 0 || fat_sectors == 0 || le16(&boot, 42) != 0 {
+            return Err(FsError::NotSupported);
+        }
+        if !(512..=4096).contains(&bytes_per_sector)
+            || !bytes_per_sector.is_power_of_two()
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fats == 0
+        {
+            return Err(FsError::Corrupted);
+        }
+        let data_sector = reserved + fats * fat_sectors;
+        if total <= data_sector || total * bytes_per_sector > disk.block_count() * disk.block_size() as u64 {
+            return Err(FsError::Corrupted);
+        }
+        // The FAT may have room for more clusters than the data area holds.
+        let fat_entries = fat_sectors * bytes_per_sector / 4;
+        let clusters = ((total - data_sector) / sectors_per_cluster).min(fat_entries.saturating_sub(2));
+        let label = if boot[66] == EXTENDED_BOOT_SIGNATURE {
+            String::from_utf8_lossy(&boot[71..82]).trim_end().into()
+        } else {
+            String::new()
+        };
+        let volume = Self {
+            cluster_size: (sectors_per_cluster * bytes_per_sector) as usize,
+            fat_offset: reserved * bytes_per_sector,
+            data_offset: data_sector * bytes_per_sector,
+            clusters: clusters.min(CLUSTER_MASK as u64) as u32,
+            root_cluster: le32(&boot, 44),
+            label,
+            disk,
+        };
+        if !volume.is_data_cluster(volume.root_cluster) {
+            return Err(FsError::Corrupted);
+        }
+        Ok(volume)
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.clusters
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + (cluster - 2) as u64 * self.cluster_size as u64
+    }
+
+    /// Returns the cluster after `cluster` in its chain, if any.
+    fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        let mut entry = [0u8; 4];
+        cache::read_bytes(&self.disk, self.fat_offset + cluster as u64 * 4, &mut entry)?;
+        match u32::from_le_bytes(entry) & CLUSTER_MASK {
+            next if next >= END_OF_CHAIN => Ok(None),
+            next if self.is_data_cluster(next) => Ok(Some(next)),
+            // A free, reserved or bad cluster inside a chain.
+            _ => Err(FsError::Corrupted),
+        }
+    }
+
+    /// Returns the clusters of the chain starting at `first`, which is 0
+    /// for an empty file.
+    fn chain(&self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut chain = Vec::new();
+        if first == 0 {
+            return Ok(chain);
+        }
+        if !self.is_data_cluster(first) {
+            return Err(FsError::Corrupted);
+        }
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            // Longer than the volume: the chain loops.
+            if chain.len() >= self.clusters as usize {
+                return Err(FsError::Corrupted);
+            }
+            chain.push(current);
+            cluster = self.next_cluster(current)?;
+        }
+        Ok(chain)
+    }
+}
+
+/// Computes the checksum of an 8.3 name stored in its long name entries.
+fn short_name_checksum(name: &[u8]) -> u8 {
+    name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Formats the 8.3 name of a directory entry as `NAME.EXT`, applying the
+/// entry's lower-case flags.
+fn short_name(entry: &[u8]) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let text: String = bytes.iter().map(|&b| char::from(b)).collect();
+        let text = text.trim_end_matches(' ');
+        if lower {
+            text.to_ascii_lowercase()
+        } else {
+            String::from(text)
+        }
+    };
+    let mut raw = [0u8; 11];
+    raw.copy_from_slice(&entry[..11]);
+    if raw[0] == ENTRY_KANJI_E5 {
+        raw[0] = ENTRY_DELETED;
+    }
+    let base = part(&raw[..8], entry[12] & LOWER_BASE != 0);
+    let ext = part(&raw[8..], entry[12] & LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+/// Converts a FAT date and time to seconds since the epoch.
+fn fat_time(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xF).clamp(1, 12) as u8;
+    let day = (date & 0x1F).max(1) as u8;
+    let days = wall::days_from_civil(year, month, day) as u64;
+    let seconds = (time >> 11) as u64 * 3600 + ((time >> 5) & 0x3F) as u64 * 60 + (time & 0x1F) as u64 * 2;
+    days * 86400 + seconds
+}
+
+/// A long name being collected from its entries, last piece first.
+struct LongName {
+    units: Vec<u16>,
+    checksum: u8,
+    /// Sequence number of the piece expected next; 0 when complete.
+    expected: u8,
+}
+
+impl LongName {
+    /// Returns the name if it is complete and belongs to the 8.3 `entry`.
+    fn finish(self, entry: &[u8]) -> Option<String> {
+        if self.expected != 0 || self.checksum != short_name_checksum(&entry[..11]) {
+            return None;
+        }
+        let len = self.units.iter().position(|&u| u == 0).unwrap_or(self.units.len());
+        let name: String = char::decode_utf16(self.units[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        (!name.is_empty()).then_some(name)
+    }
+}
+
+/// An entry of a directory, as read from the disk.
+struct RawEntry {
+    name: String,
+    /// The 8.3 alias; the same as `name` when there is no long name.
+    alias: String,
+    /// Position of the 8.3 entry on the disk, in entries.
+    ino: u64,
+    is_dir: bool,
+    first_cluster: u32,
+    size: u32,
+    mtime: u64,
+}
+
+struct FatInode {
+    volume: Arc<Volume>,
+    ino: u64,
+    is_dir: bool,
+    first_cluster: u32,
+    size: u32,
+    mtime: u64,
+    /// The cluster chain, read from the FAT on first use.
+    chain: Mutex<Option<Arc<Vec<u32>>>>,
+}
+
+impl FatInode {
+    fn new(volume: &Arc<Volume>, entry: &RawEntry) -> Arc<Self> {
+        Arc::new(Self {
+            volume: volume.clone(),
+            ino: entry.ino,
+            is_dir: entry.is_dir,
+            first_cluster: entry.first_cluster,
+            size: entry.size,
+            mtime: entry.mtime,
+            chain: Mutex::new(None),
+        })
+    }
+
+    fn chain(&self) -> Result<Arc<Vec<u32>>, FsError> {
+        let mut cached = self.chain.lock();
+        if let Some(chain) = &*cached {
+            return Ok(chain.clone());
+        }
+        let chain = Arc::new(self.volume.chain(self.first_cluster)?);
+        *cached = Some(chain.clone());
+        Ok(chain)
+    }
+
+    /// Reads and decodes the entries of this directory.
+    fn entries(&self) -> Result<Vec<RawEntry>, FsError> {
+        let volume = &self.volume;
+        let mut entries = Vec::new();
+        let mut long_name: Option<LongName> = None;
+        let mut cluster_data = vec![0u8; volume.cluster_size];
+        for &cluster in self.chain()?.iter() {
+            let offset = volume.cluster_offset(cluster);
+            cache::read_bytes(&volume.disk, offset, &mut cluster_data)?;
+            for (index, entry) in cluster_data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+                match entry[0] {
+                    ENTRY_END => return Ok(entries),
+                    ENTRY_DELETED => {
+                        long_name = None;
+                        continue;
+                    }
+                    _ => {}
+                }
+                let attr = entry[11];
+                if attr & 0x3F == ATTR_LONG_NAME {
+                    long_name = Self::long_name_piece(long_name.take(), entry);
+                    continue;
+                }
+                let long = long_name.take().and_then(|l| l.finish(entry));
+                if attr & ATTR_VOLUME_ID != 0 || entry[0] == b'.' {
+                    continue;
+                }
+                let alias = short_name(entry);
+                entries.push(RawEntry {
+                    name: long.unwrap_or_else(|| alias.clone()),
+                    alias,
+                    ino: (offset + (index * DIR_ENTRY_SIZE) as u64) / DIR_ENTRY_SIZE as u64,
+                    is_dir: attr & ATTR_DIRECTORY != 0,
+                    first_cluster: (le16(entry, 20) as u32) << 16 | le16(entry, 26) as u32,
+                    size: le32(entry, 28),
+                    mtime: fat_time(le16(entry, 24), le16(entry, 22)),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Adds a long name entry to the name being collected. A piece out of
+    /// sequence discards the name.
+    fn long_name_piece(current: Option<LongName>, entry: &[u8]) -> Option<LongName> {
+        let sequence = entry[0] & !LAST_LONG_ENTRY;
+        let checksum = entry[13];
+        let mut name = if entry[0] & LAST_LONG_ENTRY != 0 {
+            LongName { units: vec![0xFFFF; sequence as usize * LONG_NAME_UNITS], checksum, expected: sequence }
+        } else {
+            current?
+        };
+        if sequence == 0 || sequence != name.expected || checksum != name.checksum {
+            return None;
+        }
+        let start = (sequence as usize - 1) * LONG_NAME_UNITS;
+        for (i, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+            name.units[start + i] = le16(entry, offset);
+        }
+        name.expected -= 1;
+        Some(name)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let (file_type, mode) = if self.is_dir {
+            (FileType::Directory, DIR_MODE)
+        } else {
+            (FileType::Regular, FILE_MODE)
+        };
+        Metadata { ino: self.ino, file_type, size: self.size as u64, nlink: 1, mode, mtime: self.mtime }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if self.is_dir {
+            return Err(FsError::IsADirectory);
+        }
+        let size = self.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let chain = self.chain()?;
+        let cluster_size = self.volume.cluster_size as u64;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let cluster = *chain.get((pos / cluster_size) as usize).ok_or(FsError::Corrupted)?;
+            let within = pos % cluster_size;
+            let n = (len - done).min((cluster_size - within) as usize);
+            let disk_offset = self.volume.cluster_offset(cluster) + within;
+            cache::read_bytes(&self.volume.disk, disk_offset, &mut buf[done..done + n])?;
+            done += n;
+        }
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        if !self.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        let entry = self
+            .entries()?
+            .into_iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name) || e.alias.eq_ignore_ascii_case(name))
+            .ok_or(FsError::NotFound)?;
+        Ok(FatInode::new(&self.volume, &entry))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        if !self.is_dir {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(self
+            .entries()?
+            .into_iter()
+            .map(|e| DirEntry {
+                name: e.name,
+                ino: e.ino,
+                file_type: if e.is_dir { FileType::Directory } else { FileType::Regular },
+            })
+            .collect())
+    }
+}
+
+/// A FAT32 volume on a disk, mounted read-only.
+pub struct Fat32Fs {
+    root: Arc<FatInode>,
+}
+
+impl Fat32Fs {
+    /// Opens the FAT32 volume on `disk`. Disks that do not hold one give
+    /// `NotSupported`.
+    pub fn new(disk: Arc<Disk>) -> Result<Self, FsError> {
+        let volume = Arc::new(Volume::probe(disk)?);
+        let root = RawEntry {
+            name: String::new(),
+            alias: String::new(),
+            ino: ROOT_INO,
+            is_dir: true,
+            first_cluster: volume.root_cluster,
+            size: 0,
+            mtime: 0,
+        };
+        Ok(Self { root: FatInode::new(&volume, &root) })
+    }
+
+    /// Returns the volume label from the boot sector, if it has one.
+    pub fn label(&self) -> &str {
+        &self.root.volume.label
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.root.volume.cluster_size
+    }
+}
+
+impl FileSystem for Fat32Fs {
+    fn name(&self) -> &str {
+        "fat32"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,112 @@
+// nt_rustos/src/fs/file.rs
+
+//! Open files.
+//!
+//! `File` is what `open` returns. `InodeFile` implements it for files in
+//! a mounted filesystem: it keeps the open flags and the file position,
+//! and reads and writes the inode at that position.
+
+use super::dentry::Dentry;
+use super::{DirEntry, FileType, FsError, Metadata, OpenFlags};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Where `File::seek` counts from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file.
+pub trait File: Send + Sync {
+    /// Reads at the file position and advances it. Returns 0 at the end.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError>;
+
+    /// Writes at the file position, or at the end when opened with
+    /// `APPEND`, and advances it.
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError>;
+
+    /// Moves the file position and returns the new one.
+    fn seek(&self, _pos: SeekFrom) -> Result<u64, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn stat(&self) -> Result<Metadata, FsError>;
+
+    /// Lists the entries of an open directory.
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+}
+
+/// An open file of a mounted filesystem.
+pub struct InodeFile {
+    dentry: Arc<Dentry>,
+    flags: OpenFlags,
+    pos: Mutex<u64>,
+}
+
+impl InodeFile {
+    pub(super) fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Self {
+        Self { dentry, flags, pos: Mutex::new(0) }
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    fn is_directory(&self) -> bool {
+        self.dentry.inode().metadata().file_type == FileType::Directory
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(FsError::NotPermitted);
+        }
+        if self.is_directory() {
+            return Err(FsError::IsADirectory);
+        }
+        let mut pos = self.pos.lock();
+        let read = self.dentry.inode().read_at(*pos, buf)?;
+        *pos += read as u64;
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::NotPermitted);
+        }
+        let inode = self.dentry.inode();
+        let mut pos = self.pos.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *pos = inode.metadata().size;
+        }
+        let written = inode.write_at(*pos, buf)?;
+        *pos += written as u64;
+        Ok(written)
+    }
+
+    fn seek(&self, target: SeekFrom) -> Result<u64, FsError> {
+        let mut pos = self.pos.lock();
+        let new = match target {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.dentry.inode().metadata().size.checked_add_signed(delta),
+        };
+        *pos = new.ok_or(FsError::InvalidArgument)?;
+        Ok(*pos)
+    }
+
+    fn stat(&self) -> Result<Metadata, FsError> {
+        Ok(self.dentry.inode().metadata())
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        self.dentry.inode().readdir()
+    }
+}
@@ -0,0 +1,213 @@
+// nt_rustos/src/fs/initramfs.rs
+
+//! Initial ramdisk unpacking.
+//!
+//! The initramfs is a cpio archive in the "newc" format: each member is a
+//! 110-byte ASCII header of hexadecimal fields, the NUL-terminated name
+//! and the file data, with the header plus name and the data each padded
+//! to four bytes. A member named `TRAILER!!!` ends the archive.
+//!
+//! At boot `init` looks for the archive where the boot loader put it (the
+//! `linux,initrd-start`/`-end` range in `/chosen`) or, when built with the
+//! `embedded-initramfs` feature, in the kernel image, and unpacks its
+//! directories and regular files into the root filesystem.
+
+use super::{FsError, OpenFlags};
+use crate::init::initcall::InitResult;
+use crate::{info_print, platform, warn_print};
+use alloc::format;
+use alloc::string::String;
+
+const MAGIC: &[u8] = b"070701";
+/// The same layout, with a checksum in the last field.
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+#[cfg(feature = "embedded-initramfs")]
+static EMBEDDED: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/initramfs.cpio"));
+
+/// A member of the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// The name as stored, relative and possibly starting with `./`.
+    pub name: &'a str,
+    pub mode: u32,
+    pub mtime: u32,
+    pub data: &'a [u8],
+}
+
+impl Entry<'_> {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+}
+
+/// Iterates over the members of a newc archive. A malformed member yields
+/// `Corrupted` and ends the iteration.
+pub struct Archive<'a> {
+    image: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(image: &'a [u8]) -> Self {
+        Self { image, offset: 0, done: false }
+    }
+
+    /// Parses the member at the current offset and moves past it.
+    fn parse(&mut self) -> Result<Option<Entry<'a>>, FsError> {
+        let header = self.image.get(self.offset..self.offset + HEADER_SIZE).ok_or(FsError::Corrupted)?;
+        if &header[..6] != MAGIC && &header[..6] != MAGIC_CRC {
+            return Err(FsError::Corrupted);
+        }
+        // Field `index` is the index-th eight hex digits after the magic.
+        let field = |index: usize| {
+            let digits = core::str::from_utf8(&header[6 + index * 8..14 + index * 8]).ok()?;
+            u32::from_str_radix(digits, 16).ok()
+        };
+        let (mode, mtime, size, name_size) = match (field(1), field(5), field(6), field(11)) {
+            (Some(mode), Some(mtime), Some(size), Some(name_size)) => (mode, mtime, size as usize, name_size as usize),
+            _ => return Err(FsError::Corrupted),
+        };
+        let name_start = self.offset + HEADER_SIZE;
+        let name = self
+            .image
+            .get(name_start..name_start + name_size)
+            .and_then(|n| n.split_last())
+            .filter(|(nul, _)| **nul == 0)
+            .and_then(|(_, name)| core::str::from_utf8(name).ok())
+            .ok_or(FsError::Corrupted)?;
+        let data_start = align4(name_start + name_size);
+        let data = self.image.get(data_start..data_start + size).ok_or(FsError::Corrupted)?;
+        self.offset = align4(data_start + size);
+        if name == TRAILER {
+            return Ok(None);
+        }
+        Ok(Some(Entry { name, mode, mtime, data }))
+    }
+}
+
+impl<'a> Iterator for Archive<'a> {
+    type Item = Result<Entry<'a>, FsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let parsed = self.parse();
+        self.done = !matches!(parsed, Ok(Some(_)));
+        parsed.transpose()
+    }
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// What `unpack` created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnpackStats {
+    pub dirs: usize,
+    pub files: usize,
+    pub bytes: usize,
+    /// Members of other types (symlinks, devices), which are not created.
+    pub skipped: usize,
+}
+
+/// Creates directory `path` and any missing parents.
+fn create_dirs(path: &str) -> Result<usize, FsError> {
+    let components = super::path::components(path)?;
+    let mut created = 0;
+    for depth in 1..=components.len() {
+        match super::mkdir(&super::path::join(&components[..depth])) {
+            Ok(()) => created += 1,
+            Err(FsError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(created)
+}
+
+/// Unpacks the directories and regular files of `image` below directory
+/// `root`. Existing files are replaced.
+pub fn unpack(image: &[u8], root: &str) -> Result<UnpackStats, FsError> {
+    let mut stats = UnpackStats::default();
+    for entry in Archive::new(image) {
+        let entry = entry?;
+        let name = entry.name.trim_start_matches("./").trim_start_matches('/');
+        if name.is_empty() || name == "." {
+            continue;
+        }
+        let path = super::path::normalize(&format!("{}/{}", root, name))?;
+        if entry.is_dir() {
+            stats.dirs += create_dirs(&path)?;
+        } else if entry.is_file() {
+            let (parent, _) = super::path::split(&path)?;
+            stats.dirs += create_dirs(&parent)?;
+            let file = super::open(&path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)?;
+            if file.write(entry.data)? != entry.data.len() {
+                return Err(FsError::NoSpace);
+            }
+            stats.files += 1;
+            stats.bytes += entry.data.len();
+        } else {
+            stats.skipped += 1;
+        }
+    }
+    Ok(stats)
+}
+
+/// Returns the archive the boot loader or the kernel image provides, and
+/// where it came from.
+pub fn locate() -> Option<(&'static [u8], String)> {
+    if let Some(initrd) = platform::get().initrd {
+        // The initrd lies in RAM, which the kernel maps one to one.
+        let image = unsafe { core::slice::from_raw_parts(initrd.base as *const u8, initrd.size) };
+        return Some((image, format!("initrd at {}", initrd)));
+    }
+    #[cfg(feature = "embedded-initramfs")]
+    return Some((EMBEDDED, String::from("kernel image")));
+    #[cfg(not(feature = "embedded-initramfs"))]
+    None
+}
+
+/// Unpacks the initramfs, if there is one, into the root filesystem.
+pub fn init() -> Result<Option<UnpackStats>, FsError> {
+    let Some((image, source)) = locate() else {
+        return Ok(None);
+    };
+    let stats = unpack(image, "/")?;
+    if stats.skipped > 0 {
+        warn_print!("initramfs: skipped {} members that are neither files nor directories", stats.skipped);
+    }
+    info_print!(
+        "initramfs: unpacked {} files ({} KB) and {} directories from the {}",
+        stats.files,
+        stats.bytes / 1024,
+        stats.dirs,
+        source
+    );
+    Ok(Some(stats))
+}
+
+/// Unpacks the initramfs into the root filesystem, which must be mounted.
+fn unpack_at_boot() -> InitResult {
+    match init() {
+        Ok(Some(_)) => {}
+        Ok(None) => info_print!("No initramfs provided."),
+        Err(e) => return Err(format!("failed to unpack the initramfs: {}", e)),
+    }
+    Ok(())
+}
+
+crate::initcall!(subsys, 80, unpack_at_boot);
@@ -0,0 +1,468 @@
+// nt_rustos/src/fs/mod.rs
+
+//! # Virtual Filesystem
+//!
+//! Concrete filesystems implement `FileSystem`, which hands out the root
+//! `Inode`; inodes look up, list, create and remove directory entries and
+//! read and write file data. Filesystems are mounted on directories of the
+//! single namespace (see `mount`), and paths are resolved to `Dentry`s
+//! across mounts. The kernel API in this module works on absolute paths:
+//! `open` returns a `File` to `read`, `write` and `seek`, and `stat`,
+//! `readdir`, `mkdir`, `unlink` and `rename` operate on paths directly.
+//!
+//! Operations that change a directory check that the mount is writable
+//! and that no filesystem is mounted on the path they remove or move.
+//!
+//! At boot `init` mounts a `RamFs` as the root, so there is always a
+//! writable namespace; other filesystems are mounted on its directories.
+//! `initramfs::init` then fills it from the initial ramdisk, if any. Once
+//! drivers have registered their disks, `mount_disks` mounts the ext2 and
+//! FAT32 volumes found on them read-only under `/mnt`. Kernel state that
+//! subsystems register with `debugfs` is readable under `/debug`.
+
+pub mod debugfs;
+pub mod dentry;
+pub mod ext2;
+pub mod fat32;
+pub mod file;
+pub mod initramfs;
+pub mod mount;
+pub mod path;
+pub mod ramfs;
+
+pub use self::debugfs::DebugFs;
+pub use self::dentry::Dentry;
+pub use self::ext2::Ext2Fs;
+pub use self::fat32::Fat32Fs;
+pub use self::file::{File, InodeFile, SeekFrom};
+pub use self::mount::{mount, mounts, print_mounts, unmount, Mount};
+pub use self::ramfs::RamFs;
+
+use crate::block::{self, BlockError, Disk};
+use crate::init::initcall::InitResult;
+use crate::{info_print, warn_print};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::BitOr;
+
+/// Errors returned by the filesystem layer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FsError {
+    /// No file or directory has this path.
+    NotFound,
+    /// The path already exists.
+    AlreadyExists,
+    /// A path component, or the target, is not a directory.
+    NotADirectory,
+    /// The operation needs a file, not a directory.
+    IsADirectory,
+    /// The directory to remove is not empty.
+    NotEmpty,
+    /// The path is not absolute or contains invalid characters.
+    InvalidPath,
+    /// The path or one of its components is too long.
+    NameTooLong,
+    /// The filesystem is mounted read-only.
+    ReadOnly,
+    /// The file was not opened for this kind of access.
+    NotPermitted,
+    /// The path is a mount point, or the filesystem is in use.
+    Busy,
+    /// A rename would move a file to another filesystem.
+    CrossDevice,
+    /// An argument is out of range, such as a seek before the start.
+    InvalidArgument,
+    /// The filesystem does not support the operation.
+    NotSupported,
+    /// The filesystem is full.
+    NoSpace,
+    /// The on-disk structures are inconsistent.
+    Corrupted,
+    /// The underlying device failed.
+    Io,
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "no such file or directory"),
+            Self::AlreadyExists => write!(f, "file exists"),
+            Self::NotADirectory => write!(f, "not a directory"),
+            Self::IsADirectory => write!(f, "is a directory"),
+            Self::NotEmpty => write!(f, "directory not empty"),
+            Self::InvalidPath => write!(f, "invalid path"),
+            Self::NameTooLong => write!(f, "file name too long"),
+            Self::ReadOnly => write!(f, "read-only filesystem"),
+            Self::NotPermitted => write!(f, "file not open for this access"),
+            Self::Busy => write!(f, "resource busy"),
+            Self::CrossDevice => write!(f, "cross-device rename"),
+            Self::InvalidArgument => write!(f, "invalid argument"),
+            Self::NotSupported => write!(f, "operation not supported"),
+            Self::NoSpace => write!(f, "no space left on filesystem"),
+            Self::Corrupted => write!(f, "filesystem corrupted"),
+            Self::Io => write!(f, "I/O error"),
+        }
+    }
+}
+
+impl From<BlockError> for FsError {
+    fn from(e: BlockError) -> Self {
+        match e {
+            BlockError::ReadOnly => Self::ReadOnly,
+            BlockError::OutOfRange => Self::Corrupted,
+            _ => Self::Io,
+        }
+    }
+}
+
+/// The kind of a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    CharDevice,
+    BlockDevice,
+    Symlink,
+}
+
+/// File attributes, as returned by `stat`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Inode number, unique within the filesystem.
+    pub ino: u64,
+    pub file_type: FileType,
+    /// Size in bytes; for directories, as reported by the filesystem.
+    pub size: u64,
+    pub nlink: u32,
+    /// Permission bits.
+    pub mode: u16,
+    /// Modification time in seconds since the epoch, 0 if unknown.
+    pub mtime: u64,
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub file_type: FileType,
+}
+
+/// How `open` opens a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    /// Create the file if it does not exist.
+    pub const CREATE: Self = Self(1 << 2);
+    /// With `CREATE`, fail if the file exists.
+    pub const EXCLUSIVE: Self = Self(1 << 3);
+    /// Truncate a regular file opened for writing to zero length.
+    pub const TRUNCATE: Self = Self(1 << 4);
+    /// Write at the end of the file.
+    pub const APPEND: Self = Self(1 << 5);
+
+    pub const READ_WRITE: Self = Self(Self::READ.0 | Self::WRITE.0);
+
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// A file or directory of a filesystem.
+///
+/// Directory operations default to `NotADirectory` and data operations to
+/// `NotSupported`, so a read-only filesystem implements only what it has.
+/// Names passed in are single components, never `.` or `..`.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// Reads from `offset`, returning the bytes read; 0 at the end.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Writes at `offset`, extending the file as needed.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Sets the file size, zero-filling when it grows.
+    fn truncate(&self, _size: u64) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Returns the entry `name` of this directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Lists this directory, without `.` and `..`.
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Creates entry `name` of type `file_type` in this directory.
+    fn create(&self, _name: &str, _file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Removes entry `name`, which must not be a non-empty directory.
+    fn unlink(&self, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Moves entry `old_name` to `new_name` in `new_parent`, a directory of
+    /// the same filesystem, replacing a file or empty directory there.
+    fn rename(&self, _old_name: &str, _new_parent: &Arc<dyn Inode>, _new_name: &str) -> Result<(), FsError> {
+        Err(FsError::NotADirectory)
+    }
+}
+
+/// A filesystem instance that can be mounted.
+pub trait FileSystem: Send + Sync {
+    /// Returns the filesystem type, such as `ramfs`.
+    fn name(&self) -> &str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Writes cached changes to the underlying device.
+    fn sync(&self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+/// Directories created in the root filesystem at boot.
+const ROOT_DIRS: &[&str] = &["/dev", "/mnt", "/tmp"];
+
+/// Mounts a ramfs as the root filesystem, allowed to hold up to half of
+/// the free heap, and creates the standard directories.
+pub fn init() -> Result<usize, FsError> {
+    let free = crate::init::alloc::usage_summary().map_or(0, |(_, _, free)| free);
+    let capacity = free / 2;
+    mount("/", Arc::new(RamFs::new(capacity)))?;
+    for dir in ROOT_DIRS {
+        mkdir(dir)?;
+    }
+    Ok(capacity)
+}
+
+/// Mounts the root filesystem; the initramfs and disks go below it.
+fn mount_root() -> InitResult {
+    let capacity = init().map_err(|e| format!("failed to mount the root filesystem: {}", e))?;
+    info_print!("Root filesystem: ramfs (up to {} KB).", capacity / 1024);
+    Ok(())
+}
+
+crate::initcall!(subsys, 70, mount_root);
+
+/// Opens the filesystem on `disk`, trying each on-disk format in turn.
+/// Disks holding none of them give `NotSupported`.
+pub fn probe(disk: &Arc<Disk>) -> Result<Arc<dyn FileSystem>, FsError> {
+    match Ext2Fs::new(disk.clone()) {
+        Err(FsError::NotSupported) => {}
+        result => return result.map(|fs| Arc::new(fs) as Arc<dyn FileSystem>),
+    }
+    Ok(Arc::new(Fat32Fs::new(disk.clone())?))
+}
+
+/// Mounts the filesystem of every disk that holds one on `/mnt/<disk
+/// name>` and returns how many were mounted.
+pub fn mount_disks() -> usize {
+    let mut mounted = 0;
+    for disk in block::disks() {
+        let fs = match probe(&disk) {
+            Ok(fs) => fs,
+            Err(FsError::NotSupported) => continue,
+            Err(e) => {
+                warn_print!("fs: {}: {}", disk.name(), e);
+                continue;
+            }
+        };
+        let path = format!("/mnt/{}", disk.name());
+        let kind = String::from(fs.name());
+        let result = match mkdir(&path) {
+            Ok(()) | Err(FsError::AlreadyExists) => mount(&path, fs),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                info_print!("fs: mounted {} ({}) on {}", disk.name(), kind, path);
+                mounted += 1;
+            }
+            Err(e) => warn_print!("fs: cannot mount {} on {}: {}", disk.name(), path, e),
+        }
+    }
+    mounted
+}
+
+/// Mounts the disks read-only once their drivers have registered them.
+fn mount_disks_at_boot() -> InitResult {
+    let volumes = mount_disks();
+    if volumes > 0 {
+        info_print!("Mounted {} disk volume(s).", volumes);
+    }
+    Ok(())
+}
+
+crate::initcall!(device, 20, mount_disks_at_boot);
+
+/// Resolves `path` to its dentry.
+pub fn lookup(path: &str) -> Result<Arc<Dentry>, FsError> {
+    mount::resolve(&path::normalize(path)?)
+}
+
+/// Resolves the parent of `path`, checking that it is a writable
+/// directory, and returns it with the last component.
+fn writable_parent(path: &str) -> Result<(Arc<Dentry>, String), FsError> {
+    let (parent, name) = path::split(path)?;
+    let parent = mount::resolve(&parent)?;
+    if parent.inode().metadata().file_type != FileType::Directory {
+        return Err(FsError::NotADirectory);
+    }
+    if parent.mount().is_read_only() {
+        return Err(FsError::ReadOnly);
+    }
+    Ok((parent, name))
+}
+
+/// Opens the file at `path`.
+pub fn open(path: &str, flags: OpenFlags) -> Result<Arc<dyn File>, FsError> {
+    let path = path::normalize(path)?;
+    let dentry = match mount::resolve(&path) {
+        Ok(_) if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) => return Err(FsError::AlreadyExists),
+        Ok(dentry) => dentry,
+        Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => {
+            let (parent, name) = writable_parent(&path)?;
+            let inode = parent.inode().create(&name, FileType::Regular)?;
+            let dentry = Arc::new(Dentry::new(path, inode, parent.mount().clone()));
+            dentry::insert(dentry.clone());
+            dentry
+        }
+        Err(e) => return Err(e),
+    };
+    if flags.contains(OpenFlags::WRITE) {
+        if dentry.inode().metadata().file_type == FileType::Directory {
+            return Err(FsError::IsADirectory);
+        }
+        if dentry.mount().is_read_only() {
+            return Err(FsError::ReadOnly);
+        }
+        if flags.contains(OpenFlags::TRUNCATE) {
+            dentry.inode().truncate(0)?;
+        }
+    }
+    Ok(Arc::new(InodeFile::new(dentry, flags)))
+}
+
+/// Returns the attributes of the file at `path`.
+pub fn stat(path: &str) -> Result<Metadata, FsError> {
+    Ok(lookup(path)?.inode().metadata())
+}
+
+/// Lists the directory at `path`, sorted by name.
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>, FsError> {
+    let mut entries = lookup(path)?.inode().readdir()?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Creates the directory `path`.
+pub fn mkdir(path: &str) -> Result<(), FsError> {
+    let path = path::normalize(path)?;
+    let (parent, name) = writable_parent(&path)?;
+    let inode = parent.inode().create(&name, FileType::Directory)?;
+    dentry::insert(Arc::new(Dentry::new(path, inode, parent.mount().clone())));
+    Ok(())
+}
+
+/// Removes the file or empty directory at `path`.
+pub fn unlink(path: &str) -> Result<(), FsError> {
+    let path = path::normalize(path)?;
+    if mount::has_mounts_under(&path) {
+        return Err(FsError::Busy);
+    }
+    let (parent, name) = writable_parent(&path)?;
+    parent.inode().unlink(&name)?;
+    dentry::invalidate(&path);
+    Ok(())
+}
+
+/// Moves the file or directory at `old` to `new`, within one filesystem.
+pub fn rename(old: &str, new: &str) -> Result<(), FsError> {
+    let old = path::normalize(old)?;
+    let new = path::normalize(new)?;
+    if old == new {
+        return Ok(());
+    }
+    if path::is_under(&new, &old) {
+        // A directory cannot move below itself.
+        return Err(FsError::InvalidArgument);
+    }
+    if mount::has_mounts_under(&old) || mount::has_mounts_under(&new) {
+        return Err(FsError::Busy);
+    }
+    let (old_parent, old_name) = writable_parent(&old)?;
+    let (new_parent, new_name) = writable_parent(&new)?;
+    if !Arc::ptr_eq(old_parent.mount(), new_parent.mount()) {
+        return Err(FsError::CrossDevice);
+    }
+    old_parent.inode().rename(&old_name, new_parent.inode(), &new_name)?;
+    dentry::invalidate(&old);
+    dentry::invalidate(&new);
+    Ok(())
+}
+
+/// Reads the whole file at `path`.
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+    let file = open(path, OpenFlags::READ)?;
+    let mut data = vec![0u8; file.stat()?.size as usize];
+    let mut filled = 0;
+    while filled < data.len() {
+        match file.read(&mut data[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    data.truncate(filled);
+    Ok(data)
+}
+
+/// Creates or replaces the file at `path` with `data`.
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), FsError> {
+    let file = open(path, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE)?;
+    let mut written = 0;
+    while written < data.len() {
+        match file.write(&data[written..])? {
+            0 => return Err(FsError::NoSpace),
+            n => written += n,
+        }
+    }
+    Ok(())
+}
+
+/// Writes every mounted filesystem's cached changes to its device.
+pub fn sync() -> Result<(), FsError> {
+    mounts().iter().try_for_each(|m| m.fs().sync())
+}
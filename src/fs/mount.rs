@@ -0,0 +1,140 @@
+// nt_rustos/src/fs/mount.rs
+
+//! The mount table and path resolution.
+//!
+//! A filesystem is mounted on a directory, or on `/` as the root. A path
+//! belongs to the mount with the longest mount path above it; resolution
+//! starts at that filesystem's root and looks the remaining components up
+//! one at a time, caching every dentry on the way.
+
+use super::dentry::{self, Dentry};
+use super::path;
+use super::{FileSystem, FileType, FsError};
+use crate::println;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// A mounted filesystem.
+pub struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+impl Mount {
+    /// Returns the normalized path the filesystem is mounted on.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn fs(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.fs.is_read_only()
+    }
+}
+
+static MOUNTS: Mutex<Vec<Arc<Mount>>> = Mutex::new(Vec::new());
+
+/// Mounts `fs` on directory `path`, or on `/` as the root filesystem.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), FsError> {
+    let path = path::normalize(path)?;
+    if path != "/" {
+        let target = resolve(&path)?;
+        if target.inode().metadata().file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+    }
+    {
+        let mut mounts = MOUNTS.lock();
+        if mounts.iter().any(|m| m.path == path) {
+            return Err(FsError::Busy);
+        }
+        mounts.push(Arc::new(Mount { path: path.clone(), fs }));
+    }
+    // Cached paths below the mount point now belong to the new filesystem.
+    dentry::invalidate(&path);
+    Ok(())
+}
+
+/// Unmounts the filesystem mounted on `path` after syncing it. Fails with
+/// `Busy` while files on it are open or other filesystems are mounted
+/// below it.
+pub fn unmount(path: &str) -> Result<(), FsError> {
+    let path = path::normalize(path)?;
+    dentry::invalidate(&path);
+    let mount = {
+        let mut mounts = MOUNTS.lock();
+        let index = mounts.iter().position(|m| m.path == path).ok_or(FsError::NotFound)?;
+        if mounts.iter().any(|m| m.path != path && path::is_under(&m.path, &path)) {
+            return Err(FsError::Busy);
+        }
+        // Open files hold dentries, which hold the mount.
+        if Arc::strong_count(&mounts[index]) > 1 {
+            return Err(FsError::Busy);
+        }
+        mounts.remove(index)
+    };
+    mount.fs.sync()
+}
+
+/// Returns the mounts, in mount order.
+pub fn mounts() -> Vec<Arc<Mount>> {
+    MOUNTS.lock().clone()
+}
+
+/// Returns whether a filesystem is mounted on normalized `path` or below.
+pub(super) fn has_mounts_under(path: &str) -> bool {
+    MOUNTS.lock().iter().any(|m| path::is_under(&m.path, path))
+}
+
+/// Resolves normalized `path` to its dentry.
+pub(super) fn resolve(path: &str) -> Result<Arc<Dentry>, FsError> {
+    if let Some(dentry) = dentry::lookup(path) {
+        return Ok(dentry);
+    }
+    let mount = MOUNTS
+        .lock()
+        .iter()
+        .filter(|m| path::is_under(path, &m.path))
+        .max_by_key(|m| m.path.len())
+        .cloned()
+        .ok_or(FsError::NotFound)?;
+
+    let mut current = match dentry::lookup(&mount.path) {
+        Some(root) => root,
+        None => {
+            let root = Arc::new(Dentry::new(mount.path.clone(), mount.fs.root(), mount.clone()));
+            dentry::insert(root.clone());
+            root
+        }
+    };
+    let rest = if mount.path == "/" { path } else { &path[mount.path.len()..] };
+    let mut walked = String::from(if mount.path == "/" { "" } else { mount.path.as_str() });
+    for name in rest.split('/').filter(|c| !c.is_empty()) {
+        walked.push('/');
+        walked.push_str(name);
+        current = match dentry::lookup(&walked) {
+            Some(cached) => cached,
+            None => {
+                let inode = current.inode().lookup(name)?;
+                let next = Arc::new(Dentry::new(walked.clone(), inode, mount.clone()));
+                dentry::insert(next.clone());
+                next
+            }
+        };
+    }
+    Ok(current)
+}
+
+/// Prints the mount table.
+pub fn print_mounts() {
+    let mounts = mounts();
+    println!("Mounts ({}):", mounts.len());
+    for mount in mounts {
+        println!("  {:<16} {}{}", mount.path, mount.fs.name(), if mount.is_read_only() { " (ro)" } else { "" });
+    }
+}
@@ -0,0 +1,73 @@
+// nt_rustos/src/fs/path.rs
+
+//! Path handling.
+//!
+//! Paths are absolute and resolved lexically: empty components and `.`
+//! are dropped and `..` removes the previous component (at the root it
+//! stays at the root). There are no symbolic links, so the normalized
+//! string names the same file as the original.
+
+use super::FsError;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Longest accepted path, in bytes.
+pub const PATH_MAX: usize = 4096;
+/// Longest accepted path component, in bytes.
+pub const NAME_MAX: usize = 255;
+
+/// Splits `path` into its normalized components.
+pub fn components(path: &str) -> Result<Vec<&str>, FsError> {
+    if !path.starts_with('/') {
+        return Err(FsError::InvalidPath);
+    }
+    if path.len() > PATH_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name if name.len() > NAME_MAX => return Err(FsError::NameTooLong),
+            name if name.contains('\0') => return Err(FsError::InvalidPath),
+            name => components.push(name),
+        }
+    }
+    Ok(components)
+}
+
+/// Joins components into a normalized path.
+pub fn join(components: &[&str]) -> String {
+    if components.is_empty() {
+        return String::from("/");
+    }
+    let mut path = String::new();
+    for component in components {
+        path.push('/');
+        path.push_str(component);
+    }
+    path
+}
+
+/// Returns the normalized form of `path`.
+pub fn normalize(path: &str) -> Result<String, FsError> {
+    Ok(join(&components(path)?))
+}
+
+/// Splits `path` into its normalized parent and its last component. The
+/// root has no last component.
+pub fn split(path: &str) -> Result<(String, String), FsError> {
+    let mut components = components(path)?;
+    let name = components.pop().ok_or(FsError::InvalidPath)?;
+    Ok((join(&components), String::from(name)))
+}
+
+/// Returns whether normalized `path` is `prefix` or lies below it.
+pub fn is_under(path: &str, prefix: &str) -> bool {
+    prefix == "/"
+        || path == prefix
+        || (path.starts_with(prefix) && path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
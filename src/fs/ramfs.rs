@@ -0,0 +1,282 @@
+// nt_rustos/src/fs/ramfs.rs
+
+//! A filesystem kept entirely in heap memory.
+//!
+//! Every inode holds its data: a byte vector for a file, a name-ordered map
+//! of child inodes for a directory. Nothing is ever written to a device, so
+//! the contents are lost on reboot. File data counts against a capacity
+//! given at creation; writes that would exceed it fail with `NoSpace`.
+
+use super::{DirEntry, FileSystem, FileType, FsError, Inode, Metadata};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+const FILE_MODE: u16 = 0o644;
+const DIR_MODE: u16 = 0o755;
+
+/// State shared by all inodes of one ramfs.
+struct Shared {
+    next_ino: AtomicU64,
+    /// Bytes of file data stored.
+    used: AtomicUsize,
+    capacity: usize,
+    /// Live directories by inode number, to find a rename's target.
+    dirs: Mutex<BTreeMap<u64, Weak<RamInode>>>,
+}
+
+impl Shared {
+    /// Accounts for a file growing by `bytes`.
+    fn reserve(&self, bytes: usize) -> Result<(), FsError> {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|&total| total <= self.capacity)
+            })
+            .map(|_| ())
+            .map_err(|_| FsError::NoSpace)
+    }
+
+    fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+}
+
+enum Content {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Arc<RamInode>>),
+}
+
+struct RamInode {
+    ino: u64,
+    shared: Arc<Shared>,
+    content: Mutex<Content>,
+}
+
+impl RamInode {
+    fn new(shared: &Arc<Shared>, content: Content) -> Arc<Self> {
+        let is_dir = matches!(content, Content::Dir(_));
+        let ino = shared.next_ino.fetch_add(1, Ordering::Relaxed);
+        let inode = Arc::new(Self { ino, shared: shared.clone(), content: Mutex::new(content) });
+        if is_dir {
+            shared.dirs.lock().insert(ino, Arc::downgrade(&inode));
+        }
+        inode
+    }
+
+    fn file_type(&self) -> FileType {
+        match *self.content.lock() {
+            Content::File(_) => FileType::Regular,
+            Content::Dir(_) => FileType::Directory,
+        }
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&*self.content.lock(), Content::Dir(children) if children.is_empty())
+    }
+
+    /// Finds the directory `inode` refers to, if it belongs to this ramfs.
+    fn same_fs_dir(&self, inode: &Arc<dyn Inode>) -> Option<Arc<RamInode>> {
+        // Not under the `dirs` lock: dropping an inode takes it while its
+        // parent's content is locked.
+        let ino = inode.metadata().ino;
+        let dir = self.shared.dirs.lock().get(&ino)?.upgrade()?;
+        let same = core::ptr::eq(Arc::as_ptr(&dir) as *const u8, Arc::as_ptr(inode) as *const u8);
+        same.then_some(dir)
+    }
+}
+
+impl Drop for RamInode {
+    fn drop(&mut self) {
+        match self.content.get_mut() {
+            Content::File(data) => self.shared.release(data.len()),
+            Content::Dir(_) => {
+                self.shared.dirs.lock().remove(&self.ino);
+            }
+        }
+    }
+}
+
+/// Checks that `old` may replace `new` in a rename.
+fn check_replace(old: &RamInode, new: &RamInode) -> Result<(), FsError> {
+    match (old.file_type(), new.file_type()) {
+        (FileType::Directory, FileType::Directory) if !new.is_empty_dir() => Err(FsError::NotEmpty),
+        (FileType::Directory, FileType::Regular) => Err(FsError::NotADirectory),
+        (FileType::Regular, FileType::Directory) => Err(FsError::IsADirectory),
+        _ => Ok(()),
+    }
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Metadata {
+        let (file_type, size, nlink, mode) = match &*self.content.lock() {
+            Content::File(data) => (FileType::Regular, data.len() as u64, 1, FILE_MODE),
+            Content::Dir(children) => {
+                let subdirs = children.values().filter(|c| c.file_type() == FileType::Directory).count();
+                (FileType::Directory, children.len() as u64, 2 + subdirs as u32, DIR_MODE)
+            }
+        };
+        Metadata { ino: self.ino, file_type, size, nlink, mode, mtime: 0 }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let Content::File(data) = &*self.content.lock() else {
+            return Err(FsError::IsADirectory);
+        };
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, FsError> {
+        let Content::File(data) = &mut *self.content.lock() else {
+            return Err(FsError::IsADirectory);
+        };
+        let start = usize::try_from(offset).map_err(|_| FsError::NoSpace)?;
+        let end = start.checked_add(buf.len()).ok_or(FsError::NoSpace)?;
+        if end > data.len() {
+            self.shared.reserve(end - data.len())?;
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), FsError> {
+        let Content::File(data) = &mut *self.content.lock() else {
+            return Err(FsError::IsADirectory);
+        };
+        let size = usize::try_from(size).map_err(|_| FsError::NoSpace)?;
+        if size > data.len() {
+            self.shared.reserve(size - data.len())?;
+        } else {
+            self.shared.release(data.len() - size);
+        }
+        data.resize(size, 0);
+        data.shrink_to_fit();
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let Content::Dir(children) = &*self.content.lock() else {
+            return Err(FsError::NotADirectory);
+        };
+        let child = children.get(name).ok_or(FsError::NotFound)?;
+        Ok(child.clone())
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let Content::Dir(children) = &*self.content.lock() else {
+            return Err(FsError::NotADirectory);
+        };
+        Ok(children
+            .iter()
+            .map(|(name, child)| DirEntry { name: name.clone(), ino: child.ino, file_type: child.file_type() })
+            .collect())
+    }
+
+    fn create(&self, name: &str, file_type: FileType) -> Result<Arc<dyn Inode>, FsError> {
+        let Content::Dir(children) = &mut *self.content.lock() else {
+            return Err(FsError::NotADirectory);
+        };
+        if children.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let content = match file_type {
+            FileType::Regular => Content::File(Vec::new()),
+            FileType::Directory => Content::Dir(BTreeMap::new()),
+            _ => return Err(FsError::NotSupported),
+        };
+        let child = RamInode::new(&self.shared, content);
+        children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), FsError> {
+        let Content::Dir(children) = &mut *self.content.lock() else {
+            return Err(FsError::NotADirectory);
+        };
+        let child = children.get(name).ok_or(FsError::NotFound)?;
+        if child.file_type() == FileType::Directory && !child.is_empty_dir() {
+            return Err(FsError::NotEmpty);
+        }
+        children.remove(name);
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_parent: &Arc<dyn Inode>, new_name: &str) -> Result<(), FsError> {
+        let target = self.same_fs_dir(new_parent).ok_or(FsError::CrossDevice)?;
+        if target.ino == self.ino {
+            let Content::Dir(children) = &mut *self.content.lock() else {
+                return Err(FsError::NotADirectory);
+            };
+            let moved = children.get(old_name).ok_or(FsError::NotFound)?.clone();
+            if let Some(replaced) = children.get(new_name) {
+                check_replace(&moved, replaced)?;
+            }
+            children.remove(old_name);
+            children.insert(String::from(new_name), moved);
+            return Ok(());
+        }
+
+        // Lock both directories in inode order, so that concurrent renames
+        // between the same two directories cannot deadlock.
+        let (mut source, mut dest) = if self.ino < target.ino {
+            let source = self.content.lock();
+            (source, target.content.lock())
+        } else {
+            let dest = target.content.lock();
+            (self.content.lock(), dest)
+        };
+        let (Content::Dir(from), Content::Dir(to)) = (&mut *source, &mut *dest) else {
+            return Err(FsError::NotADirectory);
+        };
+        let moved = from.get(old_name).ok_or(FsError::NotFound)?.clone();
+        if let Some(replaced) = to.get(new_name) {
+            check_replace(&moved, replaced)?;
+        }
+        from.remove(old_name);
+        to.insert(String::from(new_name), moved);
+        Ok(())
+    }
+}
+
+/// A heap-backed filesystem.
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    /// Creates an empty ramfs holding up to `capacity` bytes of file data.
+    pub fn new(capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            next_ino: AtomicU64::new(1),
+            used: AtomicUsize::new(0),
+            capacity,
+            dirs: Mutex::new(BTreeMap::new()),
+        });
+        Self { root: RamInode::new(&shared, Content::Dir(BTreeMap::new())) }
+    }
+
+    /// Returns the bytes of file data stored.
+    pub fn used(&self) -> usize {
+        self.root.shared.used.load(Ordering::Acquire)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.root.shared.capacity
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
@@ -0,0 +1,120 @@
+// nt_rustos/src/host/low_level.rs
+
+//! # Host Trap Hardware Mock
+//!
+//! Stands in for `trap::infrastructure::low_level` in host tests. There is
+//! no trap vector to install and no `sstatus` to change: the trap mode,
+//! the stack limit and the interrupt enable bit are kept in memory, where
+//! tests can inspect them. Traps are delivered by calling `handle_trap`
+//! with a context, as the assembly entry point would.
+
+// Delivering traps and reading back the mocked state is left to the tests.
+#![cfg_attr(not(test), allow(dead_code))]
+
+use crate::trap::ds::{TrapContext, TrapMode};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// `stvec` as `init_trap_vector` would have written it, the mode in the low
+/// bits; 0 before.
+static STVEC: AtomicUsize = AtomicUsize::new(0);
+static KSTACK_LIMIT: AtomicUsize = AtomicUsize::new(0);
+/// `sstatus.SIE`.
+static SIE: AtomicBool = AtomicBool::new(false);
+
+/// Address standing in for `__trap_entry`.
+const TRAP_ENTRY: usize = 0x8020_0000;
+
+pub fn set_kernel_stack_limit(limit: usize) {
+    KSTACK_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// Returns the limit `set_kernel_stack_limit` last set.
+pub fn kernel_stack_limit() -> usize {
+    KSTACK_LIMIT.load(Ordering::Relaxed)
+}
+
+pub fn init_trap_vector(mode: TrapMode) {
+    STVEC.store(TRAP_ENTRY | mode as usize, Ordering::Relaxed);
+}
+
+/// Returns the value `init_trap_vector` would have written to `stvec`.
+pub fn trap_vector() -> usize {
+    STVEC.load(Ordering::Relaxed)
+}
+
+/// Delivers a trap with `context`, as `__trap_entry` does.
+pub extern "C" fn handle_trap(context: *mut TrapContext) {
+    crate::trap::infrastructure::di::dispatch_trap(context);
+}
+
+pub fn enable_interrupts() -> bool {
+    SIE.swap(true, Ordering::AcqRel)
+}
+
+pub fn disable_interrupts() -> bool {
+    SIE.swap(false, Ordering::AcqRel)
+}
+
+pub fn restore_interrupts(was_enabled: bool) {
+    if was_enabled {
+        SIE.store(true, Ordering::Release);
+    }
+}
+
+/// Returns `true` if interrupts are enabled.
+pub fn interrupts_enabled() -> bool {
+    SIE.load(Ordering::Acquire)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trap::{self, ProtectionLevel, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID};
+
+    /// `scause` of an environment call from S-mode.
+    const SUPERVISOR_ECALL: usize = 9;
+
+    fn syscall(context: &mut TrapContext) -> TrapHandlerResult {
+        context.x[10] = context.x[17] + 1;
+        context.sepc += 4;
+        TrapHandlerResult::Handled
+    }
+
+    /// The only test that initializes the global trap system, which can
+    /// happen once per process.
+    #[test]
+    fn trap_delivered_through_mock() {
+        trap::init(TrapMode::Direct);
+        assert_eq!(trap_vector(), TRAP_ENTRY | TrapMode::Direct as usize);
+
+        let handle = trap::register_trap_handler(
+            TrapType::SystemCall,
+            syscall,
+            50,
+            "Host Test Syscall",
+            ProtectionLevel::Kernel,
+            KERNEL_REGISTRAR_ID,
+            None,
+        )
+        .unwrap();
+
+        let mut context = TrapContext::new();
+        context.scause = SUPERVISOR_ECALL;
+        context.sepc = 0x1000;
+        context.x[17] = 41;
+        handle_trap(&mut context);
+        assert_eq!(context.x[10], 42);
+        assert_eq!(context.sepc, 0x1004);
+
+        assert!(!trap::in_trap_context());
+        trap::enable_interrupts();
+        let was_enabled = trap::disable_interrupts();
+        assert!(was_enabled && !interrupts_enabled());
+        trap::restore_interrupts(was_enabled);
+        assert!(interrupts_enabled());
+
+        trap::set_kernel_stack_bottom(0x8100_0000);
+        assert_eq!(kernel_stack_limit(), 0x8100_0000 + core::mem::size_of::<TrapContext>());
+        trap::unregister_trap_handler(handle, KERNEL_REGISTRAR_ID).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+// nt_rustos/src/host/mod.rs
+
+//! # Host-Side Testing
+//!
+//! With the `host-test` feature the crate builds against `std` for the
+//! host, so the logic that does not touch hardware, such as the early
+//! allocator, the handover validation and the trap handler manager, runs
+//! under `cargo test`:
+//!
+//! ```text
+//! cargo test --lib --features host-test --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! The modules that need the machine are left out. What the remaining ones
+//! use of it is replaced here:
+//!
+//! - the console's default backend writes to the host's standard output
+//!   instead of calling the SBI (`StdoutConsole`);
+//! - the monotonic clock counts from the first reading on the host's clock
+//!   (`crate::time`, from `host/time.rs`);
+//! - the trap vector and the interrupt enable bit are kept in memory
+//!   (`trap::infrastructure::low_level`, from `host/low_level.rs`);
+//! - there is no kernel image, so no embedded symbol table; of `util`
+//!   only `ksyms` is built, for the trap dumps.
+//!
+//! Initcalls are registered but never run; tests initialize what they use.
+
+use crate::console::ConsoleDriver;
+use std::io::Write;
+
+/// Console backend writing to the host's standard output, so `cargo test`
+/// captures it like any other test output.
+pub struct StdoutConsole;
+
+impl ConsoleDriver for StdoutConsole {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn write_str(&self, s: &str) {
+        // `print!` rather than `io::stdout()`, so the test harness captures it.
+        print!("{}", s);
+        let _ = std::io::stdout().flush();
+    }
+}
@@ -0,0 +1,42 @@
+// nt_rustos/src/host/time.rs
+
+//! # Host Monotonic Clock
+//!
+//! Stands in for `crate::time` in host tests: the clock counts from its
+//! first reading, on the host's monotonic clock, at a nominal 1 GHz
+//! timebase.
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+pub const USEC_PER_SEC: u64 = 1_000_000;
+pub const MSEC_PER_SEC: u64 = 1_000;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Nanoseconds since the first reading, standing in for the `time` CSR.
+pub fn now_ticks() -> u64 {
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// The nominal timebase frequency in Hz: one tick per nanosecond.
+pub fn frequency() -> u64 {
+    NSEC_PER_SEC
+}
+
+pub fn monotonic_ticks() -> u64 {
+    now_ticks()
+}
+
+pub fn monotonic_ns() -> u64 {
+    now_ticks()
+}
+
+pub fn monotonic_us() -> u64 {
+    now_ticks() / 1_000
+}
+
+pub fn monotonic_ms() -> u64 {
+    now_ticks() / 1_000_000
+}
@@ -1,11 +1,16 @@
 // 生产级早期堆内存分配器核心实现
-// 使用基于地址排序的双向空闲链表的分配策略
+// 空闲块按大小分级 (`SIZE_CLASSES`) 挂在各级的双向链表上，每级链表内按地址排序；
+// 块头的边界标记 (`prev_size`) 记着前一个块的大小，释放时不用查找链表就能与前一个空闲块合并
 
 use core::ptr::{self, NonNull};
 use core::mem;
+use core::ops::Range;
 use super::metadata::{BlockHeader, AllocStats, BlockStatus, BLOCK_MAGIC};
 use super::handover::{HandoverInfo, AllocatedBlock, AllocPurpose, MAX_TRACKED_BLOCKS, MemoryPermissions};
+use super::buddy::BuddyAllocator;
+use super::compact::{self, CompactionReport, Relocation, RelocationTable};
 use super::global::advanced;
+use crate::util::arrayvec::ArrayVec;
 use crate::{error_print, warn_print, debug_print};
 
 // 分配器错误类型
@@ -32,14 +37,28 @@ struct FreeBlock {
     prev: *mut FreeBlock,
 }
 
+/// 空闲链表的分级：每级收纳数据区不小于该值、小于下一级的空闲块，最后一级收纳4K及以上的块
+///
+/// 查找从能容纳请求的最低一级开始，更高级的块都足够大，常见大小的分配只需看链表头。
+pub const SIZE_CLASSES: [usize; 8] = [32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// 数据区为`size`字节的空闲块所在的级，小于32字节的块归入第一级
+pub fn size_class(size: usize) -> usize {
+    SIZE_CLASSES.iter().rposition(|&bound| bound <= size).unwrap_or(0)
+}
+
 /// 生产级早期分配器实现
 pub struct EarlyAllocator {
     heap_start: usize,
     heap_end: usize,
-    free_list_head: *mut FreeBlock,
+    /// 各级空闲链表的表头
+    free_lists: [*mut FreeBlock; SIZE_CLASSES.len()],
     stats: AllocStats,
     frozen: bool,
     next_alloc_id: u64,
+    policy: AllocPolicy,
+    /// 下次适配从这个地址起查找
+    next_fit_cursor: usize,
 }
 
 // 通过手动实现 Send Trait，我们向编译器保证：
@@ -50,7 +69,8 @@ unsafe impl Send for EarlyAllocator {}
 impl EarlyAllocator {
     /// 创建新的早期分配器
     pub fn new(heap_start: usize, heap_size: usize) -> Result<Self, AllocError> {
-        if heap_start == 0 || heap_size < Self::min_heap_size() {
+        // 边界标记是u32
+        if heap_start == 0 || heap_size < Self::min_heap_size() || heap_size > u32::MAX as usize {
             return Err(AllocError::InvalidParameter);
         }
         
@@ -76,14 +96,18 @@ impl EarlyAllocator {
         stats.free_count = 1;
         stats.max_free_block_size = heap_size;
 
-        Ok(Self {
+        let mut allocator = Self {
             heap_start,
             heap_end,
-            free_list_head: initial_free_block,
+            free_lists: [ptr::null_mut(); SIZE_CLASSES.len()],
             stats,
             frozen: false,
             next_alloc_id: 1,
-        })
+            policy: AllocPolicy::FirstFit,
+            next_fit_cursor: heap_start,
+        };
+        allocator.insert_into_free_list(initial_free_block);
+        Ok(allocator)
     }
     
     /// 分配内存
@@ -103,13 +127,14 @@ impl EarlyAllocator {
             return None;
         }
 
-        // 规范化请求的大小，至少要能容纳一个FreeBlock
-        let alloc_size = size.max(mem::size_of::<FreeBlock>());
+        // 规范化请求的大小，至少要能容纳一个FreeBlock，并向上取整到16字节以保证后续块头对齐
+        let alloc_size = (size.max(mem::size_of::<FreeBlock>()) + 15) & !15;
 
         // 寻找合适的空闲块
-        if let Some((block_header, user_addr)) = self.find_free_block(alloc_size, align) {
-            let block_addr = block_header as usize;
-            let block_size = unsafe { (*block_header).size };
+        if let Some((found_header, user_addr)) = self.find_free_block(alloc_size, align) {
+            let mut block_header = found_header;
+            let mut block_addr = block_header as usize;
+            let mut block_size = unsafe { (*block_header).size };
             let free_block = unsafe { &mut *((block_addr + mem::size_of::<BlockHeader>()) as *mut FreeBlock) };
             
             // 从空闲链表中移除
@@ -117,6 +142,29 @@ impl EarlyAllocator {
             self.stats.free_size -= block_size + mem::size_of::<BlockHeader>();
             self.stats.free_count -= 1;
 
+            // 大对齐会在块头与用户数据之间产生空隙：将其拆分为独立的空闲块，
+            // 使块头紧邻用户指针，dealloc才能正确找到块头
+            let header_addr = user_addr - mem::size_of::<BlockHeader>();
+            if header_addr > block_addr {
+                let gap = header_addr - block_addr;
+                unsafe {
+                    let prev_size = (*block_header).prev_size();
+                    *block_header = BlockHeader::new(gap - mem::size_of::<BlockHeader>(), BlockStatus::Free);
+                    (*block_header).set_prev_size(prev_size);
+                    let lead_free = (block_addr + mem::size_of::<BlockHeader>()) as *mut FreeBlock;
+                    self.insert_into_free_list(lead_free);
+
+                    block_header = header_addr as *mut BlockHeader;
+                    block_size -= gap;
+                    *block_header = BlockHeader::new(block_size, BlockStatus::Free);
+                    (*block_header).set_prev_size(gap);
+                }
+                self.stats.record_split(gap - mem::size_of::<BlockHeader>());
+                self.stats.free_size += gap;
+                self.stats.free_count += 1;
+                block_addr = header_addr;
+            }
+
             let required_size = user_addr - block_addr + alloc_size;
 
             // 如果剩余空间足够大，则分裂块
@@ -137,6 +185,8 @@ impl EarlyAllocator {
                     // 创建新的空闲块头
                     let new_header = new_free_block_addr as *mut BlockHeader;
                     *new_header = BlockHeader::new(new_free_block_size, BlockStatus::Free);
+                    (*new_header).set_prev_size(required_size);
+                    self.tag_next(new_header);
 
                     // 创建新的FreeBlock并插入链表
                     let new_free = (new_free_block_addr + mem::size_of::<BlockHeader>()) as *mut FreeBlock;
@@ -154,6 +204,8 @@ impl EarlyAllocator {
                     (*block_header).update_timestamp();
                     (*block_header).update_checksum();
                 }
+                // 拆出对齐空隙后块变小了
+                self.tag_next(block_header);
             }
 
             self.stats.record_alloc(unsafe { (*block_header).size });
@@ -193,6 +245,8 @@ impl EarlyAllocator {
         
         unsafe {
             (*header_ptr).status = BlockStatus::Free;
+            // 用途属于这次分配，块被重新分配时不应沿用
+            (*header_ptr).purpose = AllocPurpose::Unknown;
             (*header_ptr).update_timestamp();
             (*header_ptr).update_checksum();
             
@@ -204,14 +258,162 @@ impl EarlyAllocator {
         Ok(())
     }
     
+    /// 原地改变已分配块的大小，成功时指针不变
+    ///
+    /// 缩小时把多出的部分分裂为空闲块；扩大时并入物理上相邻的下一个空闲块，
+    /// 剩余部分足够大则再分裂出去。下一个块不是空闲块或不够大时返回`OutOfMemory`，
+    /// 调用者应改为分配、复制、释放。
+    pub fn resize_in_place(&mut self, ptr: NonNull<u8>, new_size: usize) -> Result<(), AllocError> {
+        if self.frozen { return Err(AllocError::AllocatorFrozen); }
+        if new_size == 0 { return Err(AllocError::InvalidParameter); }
+
+        let header_ptr = self.allocated_header(ptr)?;
+        let old_size = unsafe { (*header_ptr).size };
+        let new_alloc = (new_size.max(mem::size_of::<FreeBlock>()) + 15) & !15;
+        let mut size = old_size;
+
+        if new_alloc > old_size {
+            let next_addr = header_ptr as usize + mem::size_of::<BlockHeader>() + old_size;
+            if next_addr >= self.heap_end {
+                return Err(AllocError::OutOfMemory);
+            }
+            let next_header = next_addr as *mut BlockHeader;
+            let next_total = unsafe {
+                if !(*next_header).validate() || (*next_header).status != BlockStatus::Free {
+                    return Err(AllocError::OutOfMemory);
+                }
+                (*next_header).total_size()
+            };
+            if old_size + next_total < new_alloc {
+                return Err(AllocError::OutOfMemory);
+            }
+            // 吸收下一个空闲块
+            self.remove_from_free_list((next_addr + mem::size_of::<BlockHeader>()) as *mut FreeBlock);
+            size += next_total;
+            self.stats.record_merge();
+            self.stats.free_size -= next_total;
+            self.stats.free_count -= 1;
+        }
+
+        // 多出的部分足够大时分裂为空闲块，并与其后的空闲块合并
+        if size >= new_alloc + Self::min_block_size() {
+            let free_addr = header_ptr as usize + mem::size_of::<BlockHeader>() + new_alloc;
+            let free_size = size - new_alloc - mem::size_of::<BlockHeader>();
+            unsafe {
+                *(free_addr as *mut BlockHeader) = BlockHeader::new(free_size, BlockStatus::Free);
+                (*(free_addr as *mut BlockHeader)).set_prev_size(mem::size_of::<BlockHeader>() + new_alloc);
+                self.tag_next(free_addr as *mut BlockHeader);
+                let free_block = (free_addr + mem::size_of::<BlockHeader>()) as *mut FreeBlock;
+                self.insert_into_free_list(free_block);
+                self.stats.record_split(free_size);
+                self.stats.free_size += free_size + mem::size_of::<BlockHeader>();
+                self.stats.free_count += 1;
+                self.coalesce(free_block);
+            }
+            size = new_alloc;
+        }
+
+        unsafe {
+            (*header_ptr).size = size;
+            (*header_ptr).update_checksum();
+        }
+        self.tag_next(header_ptr);
+        self.stats.record_resize(old_size, size);
+        Ok(())
+    }
+    
+    /// 整理堆：把紧跟在空闲块之后的可重定位块搬到空闲块的位置，搬动记入`relocations`
+    ///
+    /// 见`compact`模块。冻结的分配器不整理。
+    pub fn compact(&mut self, relocations: &mut RelocationTable) -> Result<CompactionReport, AllocError> {
+        if self.frozen { return Err(AllocError::AllocatorFrozen); }
+
+        let mut report = CompactionReport {
+            free_blocks_before: self.stats.free_count,
+            largest_free_before: self.largest_free_block(),
+            ..CompactionReport::default()
+        };
+        let header_size = mem::size_of::<BlockHeader>();
+        let mut current = self.heap_start;
+        while current < self.heap_end && !relocations.is_full() {
+            let header = current as *mut BlockHeader;
+            let (status, total) = unsafe { ((*header).status, (*header).total_size()) };
+            let next = current + total;
+            if status != BlockStatus::Free || next >= self.heap_end {
+                current = next;
+                continue;
+            }
+            let next_header = next as *mut BlockHeader;
+            let (movable, moved_total, size, purpose) = unsafe {
+                ((*next_header).validate() && (*next_header).status == BlockStatus::Allocated
+                    && (*next_header).purpose.is_relocatable(),
+                 (*next_header).total_size(), (*next_header).size, (*next_header).purpose)
+            };
+            let (old_user, new_user) = (next + header_size, current + header_size);
+            if !movable || !compact::keeps_alignment(old_user, new_user) {
+                current = next;
+                continue;
+            }
+
+            // 已分配块连同块头搬到空闲块处，空闲块移到其后
+            self.remove_from_free_list((current + header_size) as *mut FreeBlock);
+            let prev_size = unsafe { (*header).prev_size() };
+            unsafe {
+                ptr::copy(next as *const u8, current as *mut u8, moved_total);
+                (*header).set_prev_size(prev_size);
+            }
+            let free_addr = current + moved_total;
+            unsafe {
+                *(free_addr as *mut BlockHeader) = BlockHeader::new(total - header_size, BlockStatus::Free);
+                (*(free_addr as *mut BlockHeader)).set_prev_size(moved_total);
+                self.tag_next(free_addr as *mut BlockHeader);
+                let free_block = (free_addr + header_size) as *mut FreeBlock;
+                self.insert_into_free_list(free_block);
+                self.coalesce(free_block);
+            }
+            relocations.record(Relocation { old: old_user, new: new_user, size, purpose });
+            report.moved_blocks += 1;
+            report.moved_bytes += size;
+            current = free_addr;
+        }
+
+        report.free_blocks_after = self.stats.free_count;
+        report.largest_free_after = self.largest_free_block();
+        self.stats.max_free_block_size = report.largest_free_after;
+        Ok(report)
+    }
+
+    /// 最大空闲块的字节数 (含块头)，在最高的非空一级中
+    fn largest_free_block(&self) -> usize {
+        let Some(class) = self.free_lists.iter().rposition(|head| !head.is_null()) else {
+            return 0;
+        };
+        self.class_blocks(class)
+            .map(|block| unsafe { (*Self::get_header_from_free_block(block)).total_size() })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 各级空闲链表上的块数
+    pub fn class_lengths(&self) -> [usize; SIZE_CLASSES.len()] {
+        core::array::from_fn(|class| self.class_blocks(class).count())
+    }
+
     /// 获取统计信息
     pub fn stats(&self) -> AllocStats {
         self.stats.clone()
     }
+
+    /// 管理的地址范围
+    pub fn heap_range(&self) -> Range<usize> {
+        self.heap_start..self.heap_end
+    }
     
-    /// 执行完整性检查
+    /// 执行完整性检查：块头依次覆盖整个堆且边界标记相符，每个空闲块都在且只在与其大小相符的一级链表上
     pub fn integrity_check(&self) -> Result<(), AllocError> {
         let mut current_addr = self.heap_start;
+        let mut prev_size = 0;
+        let mut free_blocks = 0;
         while current_addr < self.heap_end {
             let header = current_addr as *const BlockHeader;
             unsafe {
@@ -219,49 +421,57 @@ impl EarlyAllocator {
                     error_print!("Integrity check failed at 0x{:x}", current_addr);
                     return Err(AllocError::CorruptedHeader);
                 }
-                current_addr += (*header).total_size();
+                if (*header).prev_size() != prev_size {
+                    error_print!("Boundary tag at 0x{:x} says {} bytes, the previous block has {}",
+                                 current_addr, (*header).prev_size(), prev_size);
+                    return Err(AllocError::InternalError);
+                }
+                if (*header).status == BlockStatus::Free {
+                    free_blocks += 1;
+                }
+                prev_size = (*header).total_size();
+                current_addr += prev_size;
             }
         }
         if current_addr != self.heap_end {
             error_print!("Heap corruption: size mismatch. Expected end 0x{:x}, got 0x{:x}", self.heap_end, current_addr);
             return Err(AllocError::InternalError);
         }
-        Ok(())
-    }
-    
-    /// 准备接管信息
-    pub fn prepare_handover(&mut self) -> Option<advanced::EarlyBox<HandoverInfo>> {
-        let stats = self.stats();
-        let mut info = HandoverInfo::new(self.heap_start, self.heap_end - self.heap_start, stats);
 
-        let mut current_addr = self.heap_start;
-        while current_addr < self.heap_end {
-            let header = current_addr as *const BlockHeader;
-            unsafe {
-                if (*header).status == BlockStatus::Allocated {
-                    if info.allocated_count < MAX_TRACKED_BLOCKS {
-                        let block = AllocatedBlock {
-                            addr: (*header).user_data_addr(),
-                            size: (*header).size,
-                            purpose: (*header).purpose,
-                            alloc_id: (*header).alloc_id,
-                            timestamp: (*header).timestamp,
-                            permissions: MemoryPermissions::READ_WRITE,
-                            alignment: 8,
-                            reserved: [0; 2],
-                        };
-                        info.allocated_blocks[info.allocated_count] = block;
-                        info.allocated_count += 1;
-                    } else {
-                        warn_print!("MAX_TRACKED_BLOCKS limit reached, handover info is incomplete.");
-                        break;
-                    }
+        // 链表上的块都是堆内的空闲块，所在级与大小相符，链接完好且按地址递增
+        let mut listed = 0;
+        for class in 0..SIZE_CLASSES.len() {
+            let mut prev: *mut FreeBlock = ptr::null_mut();
+            for block in self.class_blocks(class) {
+                let header = unsafe { Self::get_header_from_free_block(block) };
+                let addr = header as usize;
+                let valid = (self.heap_start..self.heap_end).contains(&addr)
+                    && unsafe { (*header).validate() && (*header).status == BlockStatus::Free };
+                if !valid {
+                    error_print!("Free list {} holds a bad block at 0x{:x}", class, addr);
+                    return Err(AllocError::CorruptedHeader);
+                }
+                let size = unsafe { (*header).size };
+                let linked = unsafe { (*block).prev == prev };
+                let ordered = prev.is_null() || (prev as usize) < block as usize;
+                if size_class(size) != class || !linked || !ordered {
+                    error_print!("Free list {} misplaces the block at 0x{:x} ({} bytes)", class, addr, size);
+                    return Err(AllocError::InternalError);
                 }
-                current_addr += (*header).total_size();
+                prev = block;
+                listed += 1;
             }
         }
-        info.update_checksum();
-        advanced::EarlyBox::new(info)
+        if listed != free_blocks {
+            error_print!("Free lists hold {} blocks, the heap has {} free blocks", listed, free_blocks);
+            return Err(AllocError::InternalError);
+        }
+        Ok(())
+    }
+
+    /// 准备接管信息
+    pub fn prepare_handover(&mut self) -> Option<advanced::EarlyBox<HandoverInfo>> {
+        collect_handover(self.heap_start, self.heap_end, self.stats())
     }
     
     /// 冻结分配器
@@ -272,6 +482,9 @@ impl EarlyAllocator {
     /// 设置分配用途
     pub fn set_purpose(&mut self, ptr: NonNull<u8>, purpose: AllocPurpose) -> Result<(), AllocError> {
         let user_ptr = ptr.as_ptr() as usize;
+        if user_ptr < self.heap_start + mem::size_of::<BlockHeader>() || user_ptr >= self.heap_end {
+            return Err(AllocError::InvalidPointer);
+        }
         let header_ptr = (user_ptr - mem::size_of::<BlockHeader>()) as *mut BlockHeader;
         unsafe {
             if !(*header_ptr).validate() { return Err(AllocError::CorruptedHeader); }
@@ -282,6 +495,25 @@ impl EarlyAllocator {
         Ok(())
     }
 
+    /// 找回已分配块的块头，指针不指向已分配块时返回错误
+    fn allocated_header(&mut self, ptr: NonNull<u8>) -> Result<*mut BlockHeader, AllocError> {
+        let user_ptr = ptr.as_ptr() as usize;
+        if user_ptr < self.heap_start + mem::size_of::<BlockHeader>() || user_ptr >= self.heap_end {
+            return Err(AllocError::InvalidPointer);
+        }
+        let header_ptr = (user_ptr - mem::size_of::<BlockHeader>()) as *mut BlockHeader;
+        unsafe {
+            if !(*header_ptr).validate() {
+                self.stats.record_corruption();
+                return Err(AllocError::CorruptedHeader);
+            }
+            if (*header_ptr).status != BlockStatus::Allocated {
+                return Err(AllocError::InvalidPointer);
+            }
+        }
+        Ok(header_ptr)
+    }
+
     fn min_block_size() -> usize {
         mem::size_of::<BlockHeader>() + mem::size_of::<FreeBlock>()
     }
@@ -290,23 +522,110 @@ impl EarlyAllocator {
         Self::min_block_size() * 2
     }
 
-    /// 寻找合适的空闲块 (First-Fit)
-    fn find_free_block(&self, size: usize, align: usize) -> Option<(*mut BlockHeader, usize)> {
-        let mut current = self.free_list_head;
-        while !current.is_null() {
-            let header = unsafe { Self::get_header_from_free_block(current) };
-            let block_size = unsafe { (*header).size };
-            let block_addr = header as usize;
+    /// 按当前策略查找能容纳`size`字节 (按`align`对齐) 的空闲块，记录检查的块数
+    ///
+    /// 从`size`所在的一级起向上查找，更低级的块都放不下请求：首次适配与下次适配在其中按级、级内按地址查找，
+    /// 最佳适配取第一个有合适块的级中最小的块 (更高级的块都更大)。
+    fn find_free_block(&mut self, size: usize, align: usize) -> Option<(*mut BlockHeader, usize)> {
+        let class = size_class(size);
+        let mut steps = 0;
+        let found = match self.policy {
+            AllocPolicy::FirstFit => self.free_blocks(class).find_map(|block| {
+                steps += 1;
+                Self::fit(block, size, align)
+            }),
+            AllocPolicy::BestFit => {
+                let mut best: Option<(*mut BlockHeader, usize)> = None;
+                for class in class..SIZE_CLASSES.len() {
+                    for block in self.class_blocks(class) {
+                        steps += 1;
+                        let Some((header, user_addr)) = Self::fit(block, size, align) else {
+                            continue;
+                        };
+                        let block_size = unsafe { (*header).size };
+                        if best.is_none_or(|(b, _)| block_size < unsafe { (*b).size }) {
+                            best = Some((header, user_addr));
+                        }
+                        // 正好放下时不会有更合适的块
+                        if block_size == user_addr - unsafe { (*header).user_data_addr() } + size {
+                            break;
+                        }
+                    }
+                    // 更高级的块都更大
+                    if best.is_some() {
+                        break;
+                    }
+                }
+                best
+            }
+            AllocPolicy::NextFit => {
+                // 先查游标之后的块，查完后回到开头
+                let cursor = self.next_fit_cursor;
+                let after = |block: &*mut FreeBlock| unsafe { Self::get_header_from_free_block(*block) as usize >= cursor };
+                let found = self
+                    .free_blocks(class)
+                    .filter(after)
+                    .chain(self.free_blocks(class).filter(|block| !after(block)))
+                    .find_map(|block| {
+                        steps += 1;
+                        Self::fit(block, size, align)
+                    });
+                // 下次从这次分配的末尾 (拆分出的剩余部分) 起查找
+                if let Some((_, user_addr)) = found {
+                    self.next_fit_cursor = user_addr + size;
+                }
+                found
+            }
+        };
+        self.stats.record_search(self.policy, steps);
+        found
+    }
+
+    /// 按级、级内按地址遍历`class`级及以上的空闲块
+    fn free_blocks(&self, class: usize) -> impl Iterator<Item = *mut FreeBlock> + '_ {
+        (class..SIZE_CLASSES.len()).flat_map(move |class| self.class_blocks(class))
+    }
 
-            let user_addr = Self::calculate_aligned_addr(block_addr, align);
-            let required_space = user_addr - block_addr + size;
-            
-            if block_size >= required_space {
-                return Some((header, user_addr));
+    /// 按地址遍历一级空闲链表
+    fn class_blocks(&self, class: usize) -> impl Iterator<Item = *mut FreeBlock> {
+        let mut current = self.free_lists[class];
+        core::iter::from_fn(move || {
+            let block = current;
+            if block.is_null() {
+                return None;
             }
-            current = unsafe { (*current).next };
+            current = unsafe { (*block).next };
+            Some(block)
+        })
+    }
+
+    /// 空闲块能否容纳`size`字节，能则返回块头与对齐后的用户地址
+    fn fit(block: *mut FreeBlock, size: usize, align: usize) -> Option<(*mut BlockHeader, usize)> {
+        let header = unsafe { Self::get_header_from_free_block(block) };
+        let block_size = unsafe { (*header).size };
+        let block_addr = header as usize;
+
+        let mut user_addr = Self::calculate_aligned_addr(block_addr, align);
+        // 对齐空隙必须为0或足以容纳一个独立的空闲块
+        let gap = user_addr - mem::size_of::<BlockHeader>() - block_addr;
+        if gap != 0 && gap < Self::min_block_size() {
+            user_addr = Self::calculate_aligned_addr(block_addr + Self::min_block_size(), align);
         }
-        None
+        // 数据区从块头之后算起，对齐后的用户数据要在块内结束
+        let required_space = user_addr - (block_addr + mem::size_of::<BlockHeader>()) + size;
+
+        (block_size >= required_space).then_some((header, user_addr))
+    }
+
+    /// 当前的分配策略
+    pub fn policy(&self) -> AllocPolicy {
+        self.policy
+    }
+
+    /// 切换分配策略，下次适配从堆起点重新开始
+    pub fn set_policy(&mut self, policy: AllocPolicy) {
+        self.policy = policy;
+        self.next_fit_cursor = self.heap_start;
     }
 
     fn calculate_aligned_addr(block_addr: usize, align: usize) -> usize {
@@ -314,13 +633,14 @@ impl EarlyAllocator {
         (data_addr + align - 1) & !(align - 1)
     }
 
-    /// 将块从空闲链表中移除
+    /// 将块从所在级的空闲链表中移除，块头的大小此时必须仍是入链时的大小
     fn remove_from_free_list(&mut self, block: *mut FreeBlock) {
+        let class = size_class(unsafe { (*Self::get_header_from_free_block(block)).size });
         unsafe {
             if !(*block).prev.is_null() {
                 (*(*block).prev).next = (*block).next;
             } else {
-                self.free_list_head = (*block).next;
+                self.free_lists[class] = (*block).next;
             }
             if !(*block).next.is_null() {
                 (*(*block).next).prev = (*block).prev;
@@ -328,10 +648,12 @@ impl EarlyAllocator {
         }
     }
 
-    /// 将块插入到空闲链表中（保持地址有序）
+    /// 将块按块头的大小插入对应一级的空闲链表（保持地址有序）
     fn insert_into_free_list(&mut self, block: *mut FreeBlock) {
-        let block_addr = unsafe{ Self::get_header_from_free_block(block) } as usize;
-        let mut current = self.free_list_head;
+        let header = unsafe { Self::get_header_from_free_block(block) };
+        let block_addr = header as usize;
+        let class = size_class(unsafe { (*header).size });
+        let mut current = self.free_lists[class];
 
         if current.is_null() || (unsafe { Self::get_header_from_free_block(current) } as usize) > block_addr {
             unsafe {
@@ -340,7 +662,7 @@ impl EarlyAllocator {
                 if !current.is_null() {
                     (*current).prev = block;
                 }
-                self.free_list_head = block;
+                self.free_lists[class] = block;
             }
             return;
         }
@@ -359,10 +681,19 @@ impl EarlyAllocator {
         }
     }
 
-    /// 合并相邻的空闲块
+    /// 把块的总大小写入物理上下一个块的边界标记
+    fn tag_next(&self, header: *mut BlockHeader) {
+        let total = unsafe { (*header).total_size() };
+        let next = header as usize + total;
+        if next < self.heap_end {
+            unsafe { (*(next as *mut BlockHeader)).set_prev_size(total) };
+        }
+    }
+
+    /// 合并相邻的空闲块，变大的块移到对应一级的链表
     fn coalesce(&mut self, block: *mut FreeBlock) {
         let header = unsafe { Self::get_header_from_free_block(block) };
-        
+
         // 尝试与下一个块合并
         let next_header_addr = (header as usize) + unsafe { (*header).total_size() };
         if next_header_addr < self.heap_end {
@@ -370,28 +701,36 @@ impl EarlyAllocator {
             if unsafe { (*next_header).status == BlockStatus::Free } {
                 let next_free = (next_header_addr + mem::size_of::<BlockHeader>()) as *mut FreeBlock;
                 self.remove_from_free_list(next_free);
+                self.remove_from_free_list(block);
                 unsafe {
                     (*header).size += (*next_header).total_size();
                     (*header).update_checksum();
                 }
+                self.tag_next(header);
+                self.insert_into_free_list(block);
                 self.stats.record_merge();
                 self.stats.free_count -= 1;
             }
         }
-        
-        // 尝试与上一个块合并
-        if unsafe { !(*block).prev.is_null() } {
-            let prev_block = unsafe { (*block).prev };
-            let prev_header = unsafe { Self::get_header_from_free_block(prev_block) };
-            if (prev_header as usize) + unsafe { (*prev_header).total_size() } == header as usize {
-                self.remove_from_free_list(block);
-                unsafe {
-                    (*prev_header).size += (*header).total_size();
-                    (*prev_header).update_checksum();
-                }
-                self.stats.record_merge();
-                self.stats.free_count -= 1;
+
+        // 尝试与上一个块合并：边界标记给出上一个块的位置
+        let prev_size = unsafe { (*header).prev_size() };
+        if prev_size == 0 {
+            return;
+        }
+        let prev_header = (header as usize - prev_size) as *mut BlockHeader;
+        if unsafe { (*prev_header).status == BlockStatus::Free } {
+            let prev_block = (prev_header as usize + mem::size_of::<BlockHeader>()) as *mut FreeBlock;
+            self.remove_from_free_list(block);
+            self.remove_from_free_list(prev_block);
+            unsafe {
+                (*prev_header).size += (*header).total_size();
+                (*prev_header).update_checksum();
             }
+            self.tag_next(prev_header);
+            self.insert_into_free_list(prev_block);
+            self.stats.record_merge();
+            self.stats.free_count -= 1;
         }
     }
 
@@ -400,83 +739,790 @@ impl EarlyAllocator {
     }
 }
 
+/// 遍历`[heap_start, heap_end)`内的块头，把已分配的块追加到接管信息
+///
+/// 两种后端的块头格式相同，共用这一遍历。块的地址是紧跟块头的地址。
+pub(super) fn collect_blocks(info: &mut HandoverInfo, heap_start: usize, heap_end: usize) {
+    let mut current_addr = heap_start;
+    while current_addr < heap_end {
+        let header = current_addr as *const BlockHeader;
+        unsafe {
+            if (*header).status == BlockStatus::Allocated {
+                if info.allocated_count < MAX_TRACKED_BLOCKS {
+                    let block = AllocatedBlock {
+                        addr: (*header).user_data_addr(),
+                        size: (*header).size,
+                        purpose: (*header).purpose,
+                        alloc_id: (*header).alloc_id,
+                        timestamp: (*header).timestamp,
+                        permissions: MemoryPermissions::READ_WRITE,
+                        alignment: 8,
+                        reserved: [0; 2],
+                    };
+                    info.allocated_blocks[info.allocated_count] = block;
+                    info.allocated_count += 1;
+                } else {
+                    warn_print!("MAX_TRACKED_BLOCKS limit reached, handover info is incomplete.");
+                    break;
+                }
+            }
+            current_addr += (*header).total_size();
+        }
+    }
+}
+
+/// 为`[heap_start, heap_end)`上的单个分配器生成接管信息
+pub(super) fn collect_handover(heap_start: usize, heap_end: usize, stats: AllocStats) -> Option<advanced::EarlyBox<HandoverInfo>> {
+    let mut info = HandoverInfo::new(heap_start, heap_end, stats);
+    collect_blocks(&mut info, heap_start, heap_end);
+    info.update_checksum();
+    advanced::EarlyBox::new(info)
+}
+
+/// 早期堆的分配策略，在初始化时选定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// 按大小分级的空闲链表，块头带边界标记 (`EarlyAllocator`)，查找策略见`AllocPolicy`
+    FirstFit,
+    /// 伙伴系统 (`BuddyAllocator`)
+    Buddy,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::FirstFit => "first-fit",
+            Backend::Buddy => "buddy",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "first-fit" | "firstfit" => Some(Backend::FirstFit),
+            "buddy" => Some(Backend::Buddy),
+            _ => None,
+        }
+    }
+}
+
+/// 首次适配后端 (`EarlyAllocator`) 在空闲链表中挑选空闲块的策略，可在运行时切换
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocPolicy {
+    /// 从能容纳请求的一级起，取第一个足够大的块 (级内地址最低)
+    FirstFit,
+    /// 取最小的足够大的块，要遍历第一个有合适块的一级
+    BestFit,
+    /// 从上次分配的位置继续查找，到链表末尾后回到开头
+    NextFit,
+}
+
+impl AllocPolicy {
+    pub const ALL: [AllocPolicy; 3] = [AllocPolicy::FirstFit, AllocPolicy::BestFit, AllocPolicy::NextFit];
+
+    /// 在`ALL`中的位置
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AllocPolicy::FirstFit => "first-fit",
+            AllocPolicy::BestFit => "best-fit",
+            AllocPolicy::NextFit => "next-fit",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "first-fit" | "firstfit" | "first" => Some(AllocPolicy::FirstFit),
+            "best-fit" | "bestfit" | "best" => Some(AllocPolicy::BestFit),
+            "next-fit" | "nextfit" | "next" => Some(AllocPolicy::NextFit),
+            _ => None,
+        }
+    }
+}
+
+/// 所选后端的分配器实例
+enum HeapBackend {
+    FirstFit(EarlyAllocator),
+    Buddy(BuddyAllocator),
+}
+
+/// 把调用转发给所选后端
+macro_rules! dispatch {
+    ($heap:expr, $allocator:ident => $call:expr) => {
+        match $heap {
+            HeapBackend::FirstFit($allocator) => $call,
+            HeapBackend::Buddy($allocator) => $call,
+        }
+    };
+}
+
+impl HeapBackend {
+    fn backend(&self) -> Backend {
+        match self {
+            HeapBackend::FirstFit(_) => Backend::FirstFit,
+            HeapBackend::Buddy(_) => Backend::Buddy,
+        }
+    }
+
+    fn range(&self) -> Range<usize> {
+        dispatch!(self, a => a.heap_range())
+    }
+
+    /// 分配策略，伙伴系统没有可选的策略
+    fn policy(&self) -> Option<AllocPolicy> {
+        match self {
+            HeapBackend::FirstFit(a) => Some(a.policy()),
+            HeapBackend::Buddy(_) => None,
+        }
+    }
+}
+
+/// 早期堆最多管理的内存区域数
+pub const MAX_REGIONS: usize = 8;
+
+/// 一个堆区域的概况
+#[derive(Debug, Clone)]
+pub struct RegionInfo {
+    /// 区域编号，0为`init`建立的区域
+    pub index: usize,
+    pub start: usize,
+    pub size: usize,
+    pub stats: AllocStats,
+}
+
+/// 早期堆的全部区域，每个区域由一个同类后端管理
+struct Regions {
+    regions: ArrayVec<HeapBackend, MAX_REGIONS>,
+    /// 所有区域都无法满足的分配请求
+    failed_allocs: u64,
+}
+
+impl Regions {
+    /// 指针所在的区域
+    fn region_of(&mut self, ptr: NonNull<u8>) -> Result<&mut HeapBackend, AllocError> {
+        if self.regions.is_empty() {
+            return Err(AllocError::NotInitialized);
+        }
+        let addr = ptr.as_ptr() as usize;
+        self.regions.iter_mut().find(|r| r.range().contains(&addr)).ok_or(AllocError::InvalidPointer)
+    }
+}
+
 /// 线程安全包装
+///
+/// 管理`init`建立的区域与`add_region`加入的区域。分配按区域加入的顺序尝试，
+/// 释放等操作交给指针所在的区域。
 pub struct ThreadSafeEarlyAllocator {
-    allocator: spin::Mutex<Option<EarlyAllocator>>,
+    allocator: spin::Mutex<Regions>,
 }
 
 impl ThreadSafeEarlyAllocator {
     pub const fn new() -> Self {
         Self {
-            allocator: spin::Mutex::new(None),
+            allocator: spin::Mutex::new(Regions { regions: ArrayVec::new(), failed_allocs: 0 }),
         }
     }
     
     pub fn init(&self, heap_start: usize, heap_size: usize) -> Result<(), AllocError> {
-        let mut guard = self.allocator.lock();
-        if guard.is_some() {
+        self.init_with_backend(heap_start, heap_size, Backend::FirstFit)
+    }
+
+    pub fn init_with_backend(&self, heap_start: usize, heap_size: usize, backend: Backend) -> Result<(), AllocError> {
+        let mut heap = self.allocator.lock();
+        if !heap.regions.is_empty() {
             return Err(AllocError::AlreadyInitialized);
         }
         
-        match EarlyAllocator::new(heap_start, heap_size) {
-            Ok(allocator) => {
-                *guard = Some(allocator);
-                Ok(())
+        let region = Self::create(heap_start, heap_size, backend)?;
+        let _ = heap.regions.push(region);
+        Ok(())
+    }
+
+    /// 加入一个区域，使用与首个区域相同的后端，返回区域编号
+    ///
+    /// 区域不得与已有区域重叠；区域已满时返回`OutOfMemory`。
+    pub fn add_region(&self, start: usize, size: usize) -> Result<usize, AllocError> {
+        let mut heap = self.allocator.lock();
+        let backend = heap.regions.first().ok_or(AllocError::NotInitialized)?.backend();
+        let end = start.checked_add(size).ok_or(AllocError::InvalidParameter)?;
+        if heap.regions.iter().any(|r| r.range().start < end && start < r.range().end) {
+            return Err(AllocError::InvalidParameter);
+        }
+        if heap.regions.is_full() {
+            return Err(AllocError::OutOfMemory);
+        }
+        let mut region = Self::create(start, size, backend)?;
+        if let (HeapBackend::FirstFit(allocator), Some(policy)) = (&mut region, heap.regions[0].policy()) {
+            allocator.set_policy(policy);
+        }
+        let _ = heap.regions.push(region);
+        Ok(heap.regions.len() - 1)
+    }
+
+    fn create(start: usize, size: usize, backend: Backend) -> Result<HeapBackend, AllocError> {
+        Ok(match backend {
+            Backend::FirstFit => HeapBackend::FirstFit(EarlyAllocator::new(start, size)?),
+            Backend::Buddy => HeapBackend::Buddy(BuddyAllocator::new(start, size)?),
+        })
+    }
+
+    /// 当前使用的后端，未初始化时为None
+    pub fn backend(&self) -> Option<Backend> {
+        self.allocator.lock().regions.first().map(HeapBackend::backend)
+    }
+
+    /// 当前的分配策略，未初始化或使用伙伴系统时为None
+    pub fn policy(&self) -> Option<AllocPolicy> {
+        self.allocator.lock().regions.first().and_then(HeapBackend::policy)
+    }
+
+    /// 切换所有区域的分配策略，伙伴系统后端不支持
+    pub fn set_policy(&self, policy: AllocPolicy) -> Result<(), AllocError> {
+        let mut heap = self.allocator.lock();
+        for region in heap.regions.iter_mut() {
+            match region {
+                HeapBackend::FirstFit(allocator) => allocator.set_policy(policy),
+                HeapBackend::Buddy(_) => return Err(AllocError::InvalidParameter),
             }
-            Err(e) => Err(e),
         }
+        if heap.regions.is_empty() {
+            return Err(AllocError::NotInitialized);
+        }
+        Ok(())
+    }
+
+    /// 各区域的范围与统计信息
+    pub fn regions(&self) -> ArrayVec<RegionInfo, MAX_REGIONS> {
+        let heap = self.allocator.lock();
+        let mut infos = ArrayVec::new();
+        for (index, region) in heap.regions.iter().enumerate() {
+            let range = region.range();
+            let stats = dispatch!(region, a => a.stats());
+            let _ = infos.push(RegionInfo { index, start: range.start, size: range.len(), stats });
+        }
+        infos
     }
     
     pub fn alloc(&self, size: usize) -> Option<NonNull<u8>> {
-        self.allocator.lock().as_mut()?.alloc(size)
+        self.alloc_aligned(size, mem::align_of::<usize>())
     }
     
     pub fn alloc_aligned(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
-        self.allocator.lock().as_mut()?.alloc_aligned(size, align)
+        let mut heap = self.allocator.lock();
+        if heap.regions.is_empty() {
+            return None;
+        }
+        let ptr = heap.regions.iter_mut().find_map(|region| dispatch!(region, a => a.alloc_aligned(size, align)));
+        if ptr.is_none() {
+            heap.failed_allocs += 1;
+        }
+        ptr
     }
     
     pub fn dealloc(&self, ptr: NonNull<u8>) -> Result<(), AllocError> {
-        match self.allocator.lock().as_mut() {
-            Some(allocator) => allocator.dealloc(ptr),
-            None => Err(AllocError::NotInitialized),
+        let mut heap = self.allocator.lock();
+        dispatch!(heap.region_of(ptr)?, a => a.dealloc(ptr))
+    }
+    
+    pub fn resize_in_place(&self, ptr: NonNull<u8>, new_size: usize) -> Result<(), AllocError> {
+        let mut heap = self.allocator.lock();
+        dispatch!(heap.region_of(ptr)?, a => a.resize_in_place(ptr, new_size))
+    }
+    
+    pub fn compact(&self, relocations: &mut RelocationTable) -> Result<CompactionReport, AllocError> {
+        let mut heap = self.allocator.lock();
+        if heap.regions.is_empty() {
+            return Err(AllocError::NotInitialized);
         }
+        let mut total = CompactionReport::default();
+        for region in heap.regions.iter_mut() {
+            let report = dispatch!(region, a => a.compact(relocations))?;
+            total.moved_blocks += report.moved_blocks;
+            total.moved_bytes += report.moved_bytes;
+            total.free_blocks_before += report.free_blocks_before;
+            total.free_blocks_after += report.free_blocks_after;
+            total.largest_free_before = total.largest_free_before.max(report.largest_free_before);
+            total.largest_free_after = total.largest_free_after.max(report.largest_free_after);
+        }
+        Ok(total)
     }
     
+    /// 全部区域合计的统计信息
+    ///
+    /// 各区域各自统计无法满足的请求，合计中的`failed_allocs`只计所有区域都无法满足的请求；
+    /// 多个区域时`peak_used_size`为各区域峰值之和。
     pub fn stats(&self) -> Option<AllocStats> {
-        self.allocator.lock().as_ref().map(|a| a.stats())
+        let heap = self.allocator.lock();
+        Self::total_stats(&heap)
+    }
+
+    fn total_stats(heap: &Regions) -> Option<AllocStats> {
+        let mut regions = heap.regions.iter().map(|region| dispatch!(region, a => a.stats()));
+        let mut total = regions.next()?;
+        for stats in regions {
+            total.total_size += stats.total_size;
+            total.used_size += stats.used_size;
+            total.free_size += stats.free_size;
+            total.alloc_count += stats.alloc_count;
+            total.free_count += stats.free_count;
+            total.total_allocs += stats.total_allocs;
+            total.total_frees += stats.total_frees;
+            total.double_free_attempts += stats.double_free_attempts;
+            total.corrupted_blocks += stats.corrupted_blocks;
+            total.max_alloc_size = total.max_alloc_size.max(stats.max_alloc_size);
+            total.min_alloc_size = total.min_alloc_size.min(stats.min_alloc_size);
+            total.merge_count += stats.merge_count;
+            total.split_count += stats.split_count;
+            total.coalesce_count += stats.coalesce_count;
+            total.peak_used_size += stats.peak_used_size;
+            total.max_free_block_size = total.max_free_block_size.max(stats.max_free_block_size);
+            total.fragmentation_percent = total.fragmentation_percent.max(stats.fragmentation_percent);
+            for (sum, search) in total.search.iter_mut().zip(&stats.search) {
+                sum.merge(search);
+            }
+        }
+        if let Some(avg) = (total.used_size as u64).checked_div(total.total_allocs) {
+            total.avg_alloc_size = avg as usize;
+        }
+        total.failed_allocs = heap.failed_allocs;
+        Some(total)
     }
     
+    /// 生成全部区域的接管信息，堆范围为覆盖所有区域的最小范围
     pub fn prepare_handover(&self) -> Option<advanced::EarlyBox<HandoverInfo>> {
-        self.allocator.lock().as_mut().and_then(|a| a.prepare_handover())
+        let info = {
+            let heap = self.allocator.lock();
+            let stats = Self::total_stats(&heap)?;
+            let start = heap.regions.iter().map(|r| r.range().start).min()?;
+            let end = heap.regions.iter().map(|r| r.range().end).max()?;
+            let mut info = HandoverInfo::new(start, end, stats);
+            for region in heap.regions.iter() {
+                let range = region.range();
+                collect_blocks(&mut info, range.start, range.end);
+            }
+            info.update_checksum();
+            info
+        };
+        // 在锁外分配，接管信息本身也来自这个堆
+        advanced::EarlyBox::new(info)
     }
     
     pub fn freeze(&self) -> Result<(), AllocError> {
-        match self.allocator.lock().as_mut() {
-            Some(allocator) => {
-                allocator.freeze();
-                Ok(())
-            }
-            None => Err(AllocError::NotInitialized),
+        let mut heap = self.allocator.lock();
+        if heap.regions.is_empty() {
+            return Err(AllocError::NotInitialized);
+        }
+        for region in heap.regions.iter_mut() {
+            dispatch!(region, a => a.freeze());
         }
+        Ok(())
     }
     
     pub fn integrity_check(&self) -> Result<(), AllocError> {
-        match self.allocator.lock().as_ref() {
-            Some(allocator) => allocator.integrity_check(),
-            None => Err(AllocError::NotInitialized),
+        self.check_regions().map_err(|(_, e)| e)
+    }
+
+    /// 逐个区域检查，返回第一个未通过检查的区域编号与错误
+    pub fn check_regions(&self) -> Result<(), (usize, AllocError)> {
+        let heap = self.allocator.lock();
+        if heap.regions.is_empty() {
+            return Err((0, AllocError::NotInitialized));
+        }
+        for (index, region) in heap.regions.iter().enumerate() {
+            dispatch!(region, a => a.integrity_check()).map_err(|e| (index, e))?;
         }
+        Ok(())
     }
 
     pub fn set_purpose(&self, ptr: NonNull<u8>, purpose: AllocPurpose) -> Result<(), AllocError> {
-        match self.allocator.lock().as_mut() {
-            Some(allocator) => allocator.set_purpose(ptr, purpose),
-            None => Err(AllocError::NotInitialized),
-        }
+        let mut heap = self.allocator.lock();
+        dispatch!(heap.region_of(ptr)?, a => a.set_purpose(ptr, purpose))
     }
 }
 
-/// 获取时间戳（简化实现）
-fn get_timestamp() -> u64 {
-    static COUNTER: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
-    COUNTER.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const HEAP_SIZE: usize = 64 * 1024;
+
+    /// 在宿主机内存上建立分配器，返回的缓冲区必须比分配器活得更久
+    fn heap() -> (Vec<u128>, EarlyAllocator) {
+        let mut buffer = alloc::vec![0u128; HEAP_SIZE / mem::size_of::<u128>()];
+        let allocator = EarlyAllocator::new(buffer.as_mut_ptr() as usize, HEAP_SIZE).unwrap();
+        (buffer, allocator)
+    }
+
+    #[test]
+    fn rejects_tiny_heap() {
+        let mut buffer = [0u128; 2];
+        let result = EarlyAllocator::new(buffer.as_mut_ptr() as usize, mem::size_of_val(&buffer));
+        assert_eq!(result.err(), Some(AllocError::InvalidParameter));
+        assert_eq!(EarlyAllocator::new(0, HEAP_SIZE).err(), Some(AllocError::InvalidParameter));
+    }
+
+    #[test]
+    fn free_coalesces_back_to_one_block() {
+        let (_buffer, mut allocator) = heap();
+        let blocks: Vec<_> = (1..=16).map(|i| allocator.alloc(i * 24).unwrap()).collect();
+        assert_eq!(allocator.stats().alloc_count, 16);
+        allocator.integrity_check().unwrap();
+
+        // 先释放奇数块再释放偶数块，两个方向的合并都会发生
+        for ptr in blocks.iter().skip(1).step_by(2).chain(blocks.iter().step_by(2)) {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        allocator.integrity_check().unwrap();
+
+        let stats = allocator.stats();
+        assert_eq!(stats.alloc_count, 0);
+        assert_eq!(stats.free_size, HEAP_SIZE);
+        assert!(allocator.alloc(HEAP_SIZE - 2 * mem::size_of::<BlockHeader>()).is_some());
+    }
+
+    #[test]
+    fn policies_pick_different_blocks() {
+        let (_buffer, mut allocator) = heap();
+        // 同一级中三个互不相邻的空闲块：320、272、304字节，其余内存都已分配
+        let mut holes = Vec::new();
+        for size in [320, 272, 304] {
+            holes.push(allocator.alloc(size).unwrap());
+            allocator.alloc(64).unwrap();
+        }
+        while allocator.alloc(1024).is_some() {}
+        while allocator.alloc(16).is_some() {}
+        for ptr in &holes {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        let mut alloc_free = |allocator: &mut EarlyAllocator| {
+            let ptr = allocator.alloc(200).unwrap();
+            allocator.dealloc(ptr).unwrap();
+            ptr
+        };
+
+        assert_eq!(alloc_free(&mut allocator), holes[0]);
+        allocator.set_policy(AllocPolicy::BestFit);
+        assert_eq!(alloc_free(&mut allocator), holes[1]);
+        let best = allocator.stats().search_stats(AllocPolicy::BestFit);
+        assert_eq!((best.searches, best.longest), (1, 3));
+
+        // 下次适配依次使用后面的块，到末尾后回到开头
+        allocator.set_policy(AllocPolicy::NextFit);
+        for expected in [0, 1, 2, 0] {
+            assert_eq!(alloc_free(&mut allocator), holes[expected]);
+        }
+        assert_eq!(allocator.stats().search_stats(AllocPolicy::NextFit).searches, 4);
+        allocator.integrity_check().unwrap();
+    }
+
+    #[test]
+    fn free_lists_are_segregated_by_size() {
+        let (_buffer, mut allocator) = heap();
+        let blocks: Vec<_> = [48, 96, 208, 1040].iter().map(|&size| {
+            let ptr = allocator.alloc(size).unwrap();
+            allocator.alloc(64).unwrap();
+            ptr
+        }).collect();
+        for ptr in &blocks {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        // 四个空块各在自己的一级，堆末尾的剩余部分在最高一级
+        assert_eq!(allocator.class_lengths(), [1, 1, 1, 0, 0, 1, 0, 1]);
+        allocator.integrity_check().unwrap();
+
+        // 请求所在一级的链表头放得下时直接取用
+        let ptr = allocator.alloc(48).unwrap();
+        assert_eq!(ptr, blocks[0]);
+        assert_eq!(allocator.stats().search_stats(AllocPolicy::FirstFit).longest, 1);
+        allocator.dealloc(ptr).unwrap();
+
+        // 挂错了级的块被完整性检查发现
+        let misplaced = (blocks[1].as_ptr() as usize) as *mut FreeBlock;
+        allocator.remove_from_free_list(misplaced);
+        unsafe {
+            (*misplaced).next = ptr::null_mut();
+            (*misplaced).prev = ptr::null_mut();
+        }
+        allocator.free_lists[0] = misplaced;
+        assert_eq!(allocator.integrity_check(), Err(AllocError::InternalError));
+    }
+
+    #[test]
+    fn free_lists_stay_in_address_order() {
+        let (_buffer, mut allocator) = heap();
+        let blocks: Vec<_> = (0..3).map(|_| {
+            let ptr = allocator.alloc(48).unwrap();
+            allocator.alloc(64).unwrap();
+            ptr
+        }).collect();
+        // 按与地址相反的顺序释放，链表仍按地址排序
+        for ptr in blocks.iter().rev() {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        let listed: Vec<_> = allocator.class_blocks(size_class(48)).map(|block| block as usize).collect();
+        let expected: Vec<_> = blocks.iter().map(|ptr| ptr.as_ptr() as usize).collect();
+        assert_eq!(listed, expected);
+        allocator.integrity_check().unwrap();
+
+        // 顺序颠倒的链表被完整性检查发现
+        let (first, second) = (blocks[0].as_ptr() as *mut FreeBlock, blocks[1].as_ptr() as *mut FreeBlock);
+        allocator.remove_from_free_list(first);
+        unsafe {
+            (*first).next = (*second).next;
+            (*first).prev = second;
+            (*(*second).next).prev = first;
+            (*second).next = first;
+        }
+        assert_eq!(allocator.integrity_check(), Err(AllocError::InternalError));
+    }
+
+    #[test]
+    fn boundary_tags_follow_block_sizes() {
+        let (_buffer, mut allocator) = heap();
+        let header = |ptr: NonNull<u8>| (ptr.as_ptr() as usize - mem::size_of::<BlockHeader>()) as *mut BlockHeader;
+        let first = allocator.alloc(64).unwrap();
+        let second = allocator.alloc(128).unwrap();
+        assert_eq!(unsafe { (*header(first)).prev_size() }, 0);
+        assert_eq!(unsafe { (*header(second)).prev_size() }, 64 + mem::size_of::<BlockHeader>());
+
+        // 释放第二块时经边界标记与前面的空闲块合并，堆末尾的块随之更新
+        allocator.dealloc(first).unwrap();
+        allocator.dealloc(second).unwrap();
+        assert_eq!(allocator.stats().free_count, 1);
+        allocator.integrity_check().unwrap();
+
+        // 边界标记与前一个块不符
+        allocator.alloc(64).unwrap();
+        let second = allocator.alloc(64).unwrap();
+        unsafe { (*header(second)).set_prev_size(16) };
+        assert_eq!(allocator.integrity_check(), Err(AllocError::InternalError));
+    }
+
+    #[test]
+    fn freed_small_block_is_reused() {
+        let (_buffer, mut allocator) = heap();
+        let smallest = allocator.alloc(16).unwrap();
+        allocator.alloc(64).unwrap();
+        let small = allocator.alloc(96).unwrap();
+        allocator.alloc(64).unwrap();
+        allocator.dealloc(smallest).unwrap();
+        allocator.dealloc(small).unwrap();
+
+        // 释放的块本身就能放下同样大小或更小的请求，最低一级也会被查找
+        assert_eq!(allocator.alloc(16), Some(smallest));
+        assert_eq!(allocator.alloc(96), Some(small));
+        allocator.dealloc(small).unwrap();
+        assert_eq!(allocator.alloc(50), Some(small));
+        allocator.integrity_check().unwrap();
+    }
+
+    #[test]
+    fn aligned_allocations() {
+        let (_buffer, mut allocator) = heap();
+        let mut blocks = Vec::new();
+        for shift in 3..12 {
+            let align = 1 << shift;
+            let ptr = allocator.alloc_aligned(100, align).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % align, 0, "alignment {}", align);
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0xa5, 100) };
+            blocks.push(ptr);
+        }
+        allocator.integrity_check().unwrap();
+        for ptr in blocks {
+            allocator.dealloc(ptr).unwrap();
+        }
+        allocator.integrity_check().unwrap();
+        assert_eq!(allocator.stats().free_size, HEAP_SIZE);
+        assert!(allocator.alloc_aligned(16, 3).is_none());
+    }
+
+    #[test]
+    fn detects_double_free_and_foreign_pointers() {
+        let (_buffer, mut allocator) = heap();
+        let ptr = allocator.alloc(64).unwrap();
+        let keep = allocator.alloc(64).unwrap();
+        allocator.dealloc(ptr).unwrap();
+        assert_eq!(allocator.dealloc(ptr), Err(AllocError::DoubleFree));
+        assert_eq!(allocator.stats().double_free_attempts, 1);
+
+        let mut outside = 0u64;
+        assert_eq!(allocator.dealloc(NonNull::from(&mut outside).cast()), Err(AllocError::InvalidPointer));
+        allocator.dealloc(keep).unwrap();
+        allocator.integrity_check().unwrap();
+    }
+
+    #[test]
+    fn integrity_check_finds_corrupted_header() {
+        let (_buffer, mut allocator) = heap();
+        let ptr = allocator.alloc(32).unwrap();
+        let header = (ptr.as_ptr() as usize - mem::size_of::<BlockHeader>()) as *mut BlockHeader;
+        unsafe { (*header).magic ^= 1 };
+        assert_eq!(allocator.integrity_check(), Err(AllocError::CorruptedHeader));
+        assert_eq!(allocator.dealloc(ptr), Err(AllocError::CorruptedHeader));
+    }
+
+    #[test]
+    fn purpose_is_recorded_until_free() {
+        let (_buffer, mut allocator) = heap();
+        let ptr = allocator.alloc(48).unwrap();
+        let header = (ptr.as_ptr() as usize - mem::size_of::<BlockHeader>()) as *const BlockHeader;
+        allocator.set_purpose(ptr, AllocPurpose::PageTable).unwrap();
+        assert_eq!(unsafe { (*header).purpose }, AllocPurpose::PageTable);
+        allocator.integrity_check().unwrap();
+
+        allocator.dealloc(ptr).unwrap();
+        assert_eq!(allocator.set_purpose(ptr, AllocPurpose::PageTable), Err(AllocError::InvalidPointer));
+        // 同一块再次分配时不带上次的用途
+        let again = allocator.alloc(48).unwrap();
+        assert_eq!(again, ptr);
+        assert_eq!(unsafe { (*header).purpose }, AllocPurpose::Unknown);
+
+        let mut outside = 0u64;
+        assert_eq!(allocator.set_purpose(NonNull::from(&mut outside).cast(), AllocPurpose::Testing),
+                   Err(AllocError::InvalidPointer));
+    }
+
+    #[test]
+    fn resize_grows_into_next_free_block() {
+        let (_buffer, mut allocator) = heap();
+        let ptr = allocator.alloc(64).unwrap();
+        let next = allocator.alloc(256).unwrap();
+        let keep = allocator.alloc(64).unwrap();
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0x5a, 64) };
+        allocator.dealloc(next).unwrap();
+        let before = allocator.stats();
+
+        // 下一个空闲块放得下：原地扩大，剩余部分重新成为空闲块
+        allocator.resize_in_place(ptr, 200).unwrap();
+        allocator.integrity_check().unwrap();
+        let stats = allocator.stats();
+        assert_eq!(stats.merge_count, before.merge_count + 1);
+        assert_eq!(stats.split_count, before.split_count + 1);
+        assert_eq!(stats.used_size, before.used_size + 208 - 64);
+        assert_eq!(stats.free_size, before.free_size - (208 - 64));
+        assert!(unsafe { core::slice::from_raw_parts(ptr.as_ptr(), 64) }.iter().all(|&b| b == 0x5a));
+
+        // 恰好用尽下一个空闲块时不再分裂
+        let rest = 64 + mem::size_of::<BlockHeader>() + 256 - 208;
+        allocator.resize_in_place(ptr, 208 + rest).unwrap();
+        assert_eq!(allocator.stats().free_count, before.free_count - 1);
+        assert_eq!(allocator.resize_in_place(ptr, 1024), Err(AllocError::OutOfMemory));
+        allocator.integrity_check().unwrap();
+
+        allocator.dealloc(ptr).unwrap();
+        allocator.dealloc(keep).unwrap();
+        assert_eq!(allocator.stats().free_size, HEAP_SIZE);
+    }
+
+    #[test]
+    fn resize_shrinks_by_splitting() {
+        let (_buffer, mut allocator) = heap();
+        let ptr = allocator.alloc(1024).unwrap();
+        let keep = allocator.alloc(64).unwrap();
+        let before = allocator.stats();
+        allocator.resize_in_place(ptr, 100).unwrap();
+        allocator.integrity_check().unwrap();
+        let stats = allocator.stats();
+        assert_eq!(stats.used_size, before.used_size - (1024 - 112));
+        assert_eq!((stats.split_count, stats.free_count), (before.split_count + 1, before.free_count + 1));
+        // 多出的部分太小时保持原样
+        allocator.resize_in_place(ptr, 96).unwrap();
+        assert_eq!(allocator.stats().free_count, stats.free_count);
+
+        allocator.dealloc(ptr).unwrap();
+        assert_eq!(allocator.resize_in_place(ptr, 64), Err(AllocError::InvalidPointer));
+        allocator.dealloc(keep).unwrap();
+        allocator.integrity_check().unwrap();
+        assert_eq!(allocator.stats().free_count, 1);
+    }
+
+    #[test]
+    fn compaction_slides_relocatable_blocks_down() {
+        let (_buffer, mut allocator) = heap();
+        let blocks: Vec<_> = (0..8).map(|_| allocator.alloc(64).unwrap()).collect();
+        for (i, ptr) in blocks.iter().enumerate() {
+            unsafe { ptr::write_bytes(ptr.as_ptr(), i as u8, 64) };
+        }
+        allocator.set_purpose(blocks[1], AllocPurpose::Relocatable).unwrap();
+        allocator.set_purpose(blocks[3], AllocPurpose::Relocatable).unwrap();
+        allocator.set_purpose(blocks[5], AllocPurpose::UserData).unwrap();
+        allocator.set_purpose(blocks[7], AllocPurpose::Testing).unwrap();
+        for i in [0, 2, 4, 6] {
+            allocator.dealloc(blocks[i]).unwrap();
+        }
+        let used = allocator.stats().used_size;
+
+        let mut relocations = RelocationTable::new();
+        let report = allocator.compact(&mut relocations).unwrap();
+        allocator.integrity_check().unwrap();
+        // 1、3号块依次下滑；5、7号块的所有者没有选择重定位，原地不动，空闲空间合并在5号块之前
+        assert_eq!(report.moved_blocks, 2);
+        assert_eq!((report.free_blocks_before, report.free_blocks_after), (5, 3));
+        assert_eq!(report.recovered(), 0);
+        let moved: Vec<_> = relocations.entries().iter().map(|r| (r.old, r.new)).collect();
+        let stride = 64 + mem::size_of::<BlockHeader>();
+        let base = blocks[0].as_ptr() as usize;
+        assert_eq!(moved, [(base + stride, base), (base + 3 * stride, base + stride)]);
+        for (old, data) in [(1, 1u8), (3, 3)] {
+            let new = relocations.translate(blocks[old].as_ptr() as usize).unwrap();
+            assert!(unsafe { core::slice::from_raw_parts(new as *const u8, 64) }.iter().all(|&b| b == data));
+        }
+        assert_eq!(allocator.stats().used_size, used);
+
+        // 释放5、7号块后，整理把空闲空间并成一整块
+        allocator.dealloc(blocks[5]).unwrap();
+        allocator.dealloc(blocks[7]).unwrap();
+        let report = allocator.compact(&mut RelocationTable::new()).unwrap();
+        assert_eq!((report.moved_blocks, report.free_blocks_after), (0, 1));
+        assert!(allocator.alloc(HEAP_SIZE - 3 * stride).is_some());
+    }
+
+    #[test]
+    fn regions_serve_allocations_in_order() {
+        const REGION_SIZE: usize = 4 * 1024;
+        let mut first = alloc::vec![0u128; REGION_SIZE / mem::size_of::<u128>()];
+        let mut second = alloc::vec![0u128; REGION_SIZE / mem::size_of::<u128>()];
+        let (first, second) = (first.as_mut_ptr() as usize, second.as_mut_ptr() as usize);
+        let heap = ThreadSafeEarlyAllocator::new();
+        assert_eq!(heap.add_region(second, REGION_SIZE), Err(AllocError::NotInitialized));
+        heap.init(first, REGION_SIZE).unwrap();
+        assert_eq!(heap.add_region(first + 1024, REGION_SIZE), Err(AllocError::InvalidParameter));
+        assert_eq!(heap.add_region(second, REGION_SIZE), Ok(1));
+
+        // 第一个区域放不下时落到第二个区域
+        let a = heap.alloc(3 * 1024).unwrap();
+        let b = heap.alloc(3 * 1024).unwrap();
+        assert!((first..first + REGION_SIZE).contains(&(a.as_ptr() as usize)));
+        assert!((second..second + REGION_SIZE).contains(&(b.as_ptr() as usize)));
+        assert!(heap.alloc(3 * 1024).is_none());
+
+        let regions = heap.regions();
+        assert_eq!(regions.iter().map(|r| (r.start, r.stats.alloc_count)).collect::<Vec<_>>(), [(first, 1), (second, 1)]);
+        let stats = heap.stats().unwrap();
+        assert_eq!((stats.total_size, stats.alloc_count, stats.failed_allocs), (2 * REGION_SIZE, 2, 1));
+
+        // 块头损坏按区域报告
+        let header = (b.as_ptr() as usize - mem::size_of::<BlockHeader>()) as *mut BlockHeader;
+        unsafe { (*header).magic ^= 1 };
+        assert_eq!(heap.check_regions(), Err((1, AllocError::CorruptedHeader)));
+        unsafe { (*header).magic ^= 1 };
+        heap.dealloc(b).unwrap();
+        heap.dealloc(a).unwrap();
+        heap.check_regions().unwrap();
+        let mut outside = 0u64;
+        assert_eq!(heap.dealloc(NonNull::from(&mut outside).cast()), Err(AllocError::InvalidPointer));
+        assert_eq!(heap.stats().unwrap().free_size, 2 * REGION_SIZE);
+    }
+
+    #[test]
+    fn frozen_allocator_refuses_requests() {
+        let (_buffer, mut allocator) = heap();
+        let ptr = allocator.alloc(32).unwrap();
+        allocator.freeze();
+        assert!(allocator.alloc(32).is_none());
+        assert_eq!(allocator.dealloc(ptr), Err(AllocError::AllocatorFrozen));
+    }
+}
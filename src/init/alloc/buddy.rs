@@ -0,0 +1,496 @@
+// 伙伴系统分配器：早期堆的另一种后端
+//
+// 堆被划分为大小为`MIN_BLOCK << order`的块，每个块按自身大小对齐 (相对于堆起点)。
+// 分配时取不小于请求的最小阶，从更高阶的空闲块逐级对半拆分得到；释放时若伙伴块
+// (地址与块大小异或得到) 同为空闲且大小相同，则逐级合并回去。每阶一个空闲链表，
+// 分配与释放都只需O(阶数)的工作。块大小都是2的幂，释放后总能合并回原来的大块，
+// 大小为2的幂的分配几乎不产生外部碎片，代价是块内部的取整浪费。
+//
+// 块头与首次适配后端相同 (`BlockHeader`位于每个块的开头)，因此统计信息、完整性检查与
+// 接管信息的格式都一样。堆起点向上对齐到`BASE_ALIGN`，块因而也按绝对地址对齐：
+// 对齐要求超过16字节的分配，用户指针位于块内`align_up(块头大小, align)`处，
+// 释放时由指针相对堆起点偏移的最低位找回块头。接管信息中这类块的地址是块头之后的地址。
+
+use core::mem;
+use core::ops::Range;
+use core::ptr::{self, NonNull};
+use super::allocator::{self, AllocError};
+use super::compact::{CompactionReport, RelocationTable};
+use super::handover::{AllocPurpose, HandoverInfo};
+use super::metadata::{AllocStats, BlockHeader, BlockStatus};
+use super::global::advanced;
+use crate::error_print;
+
+/// 0阶块的大小，容纳块头与空闲链表指针
+pub const MIN_BLOCK: usize = 64;
+/// 最高阶，对应1GB的块 (早期堆的上限)
+pub const MAX_ORDER: usize = 24;
+/// 堆起点的对齐，也是支持的最大对齐要求
+pub const BASE_ALIGN: usize = 4096;
+
+const HEADER_SIZE: usize = mem::size_of::<BlockHeader>();
+
+const _: () = assert!(HEADER_SIZE + mem::size_of::<FreeBlock>() <= MIN_BLOCK);
+
+/// 空闲块的链表节点，位于块头之后
+#[repr(C)]
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
+}
+
+/// 伙伴系统分配器
+pub struct BuddyAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    /// 每阶空闲块的链表头
+    free_lists: [*mut FreeBlock; MAX_ORDER + 1],
+    stats: AllocStats,
+    frozen: bool,
+    next_alloc_id: u64,
+}
+
+// 与EarlyAllocator相同：所有访问都经过Mutex同步
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    /// 在`[heap_start, heap_start + heap_size)`上创建伙伴分配器
+    ///
+    /// 起点向上对齐到`BASE_ALIGN`，末尾不足`MIN_BLOCK`的部分不使用；
+    /// 剩余部分按二进制分解为若干最大的块。
+    pub fn new(heap_start: usize, heap_size: usize) -> Result<Self, AllocError> {
+        if heap_start == 0 {
+            return Err(AllocError::InvalidParameter);
+        }
+        let end = heap_start.checked_add(heap_size).ok_or(AllocError::InvalidParameter)?;
+        let base = heap_start.checked_next_multiple_of(BASE_ALIGN).ok_or(AllocError::InvalidParameter)?;
+        let usable = end.saturating_sub(base) & !(MIN_BLOCK - 1);
+        if usable < 2 * MIN_BLOCK {
+            return Err(AllocError::InvalidParameter);
+        }
+
+        let mut allocator = Self {
+            heap_start: base,
+            heap_end: base + usable,
+            free_lists: [ptr::null_mut(); MAX_ORDER + 1],
+            stats: AllocStats::new(usable),
+            frozen: false,
+            next_alloc_id: 1,
+        };
+        allocator.stats.free_count = 0;
+        let mut offset = 0;
+        while offset < usable {
+            let order = (0..=MAX_ORDER)
+                .rev()
+                .find(|&order| {
+                    let size = MIN_BLOCK << order;
+                    offset.is_multiple_of(size) && size <= usable - offset
+                })
+                .unwrap_or(0);
+            let block = base + offset;
+            unsafe {
+                *(block as *mut BlockHeader) = BlockHeader::new((MIN_BLOCK << order) - HEADER_SIZE, BlockStatus::Free);
+            }
+            allocator.push(order, block);
+            allocator.stats.free_count += 1;
+            offset += MIN_BLOCK << order;
+        }
+        allocator.update_max_free();
+        Ok(allocator)
+    }
+
+    /// 分配内存
+    pub fn alloc(&mut self, size: usize) -> Option<NonNull<u8>> {
+        self.alloc_aligned(size, mem::align_of::<usize>())
+    }
+
+    /// 对齐分配内存
+    pub fn alloc_aligned(&mut self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        if self.frozen || size == 0 || !align.is_power_of_two() || align > BASE_ALIGN {
+            self.stats.record_alloc_failure();
+            return None;
+        }
+        let gap = Self::data_offset(align);
+        let Some(order) = size.checked_add(gap).and_then(Self::order_for) else {
+            self.stats.record_alloc_failure();
+            return None;
+        };
+        let Some(mut current) = (order..=MAX_ORDER).find(|&o| !self.free_lists[o].is_null()) else {
+            self.stats.record_alloc_failure();
+            return None;
+        };
+
+        let block = self.pop(current);
+        self.stats.free_count -= 1;
+        // 逐级对半拆分，把后一半放回低一阶的空闲链表
+        while current > order {
+            current -= 1;
+            let half = MIN_BLOCK << current;
+            let buddy = block + half;
+            unsafe {
+                *(buddy as *mut BlockHeader) = BlockHeader::new(half - HEADER_SIZE, BlockStatus::Free);
+            }
+            self.push(current, buddy);
+            self.stats.record_split(half);
+            self.stats.free_count += 1;
+        }
+
+        let block_size = MIN_BLOCK << order;
+        unsafe {
+            let header = block as *mut BlockHeader;
+            *header = BlockHeader::new(block_size - HEADER_SIZE, BlockStatus::Allocated);
+            (*header).set_alloc_id(self.next_alloc_id);
+        }
+        self.next_alloc_id += 1;
+        self.stats.free_size -= block_size;
+        self.stats.record_alloc(block_size - HEADER_SIZE);
+        self.update_max_free();
+        NonNull::new((block + gap) as *mut u8)
+    }
+
+    /// 释放内存
+    pub fn dealloc(&mut self, ptr: NonNull<u8>) -> Result<(), AllocError> {
+        if self.frozen {
+            return Err(AllocError::AllocatorFrozen);
+        }
+        let header = self.header_of(ptr).inspect_err(|e| match e {
+            AllocError::CorruptedHeader => self.stats.record_corruption(),
+            AllocError::DoubleFree => self.stats.record_double_free(),
+            _ => {}
+        })?;
+        let mut block = header as usize;
+        let mut block_size = unsafe { (*header).total_size() };
+
+        self.stats.record_dealloc(block_size - HEADER_SIZE);
+        self.stats.free_size += block_size;
+        self.stats.free_count += 1;
+
+        // 伙伴同为空闲且大小相同时合并，直到最高阶或伙伴不可合并
+        while block_size < MIN_BLOCK << MAX_ORDER {
+            let buddy = self.heap_start + ((block - self.heap_start) ^ block_size);
+            if buddy + block_size > self.heap_end {
+                break;
+            }
+            let buddy_header = buddy as *mut BlockHeader;
+            let mergeable = unsafe {
+                (*buddy_header).validate()
+                    && (*buddy_header).status == BlockStatus::Free
+                    && (*buddy_header).total_size() == block_size
+            };
+            if !mergeable {
+                break;
+            }
+            self.remove(buddy);
+            // 被吸收的块头不再是块的边界，抹去魔数以免被误认
+            let absorbed = block.max(buddy) as *mut BlockHeader;
+            unsafe { (*absorbed).magic = 0 };
+            block = block.min(buddy);
+            block_size *= 2;
+            self.stats.record_merge();
+            self.stats.free_count -= 1;
+        }
+
+        unsafe {
+            *(block as *mut BlockHeader) = BlockHeader::new(block_size - HEADER_SIZE, BlockStatus::Free);
+        }
+        self.push(Self::order_of(block_size), block);
+        self.update_max_free();
+        Ok(())
+    }
+
+    /// 原地改变已分配块的大小
+    ///
+    /// 块的大小固定为2的幂，新的大小仍放得进原块时成功，否则返回`OutOfMemory`。
+    pub fn resize_in_place(&mut self, ptr: NonNull<u8>, new_size: usize) -> Result<(), AllocError> {
+        if self.frozen {
+            return Err(AllocError::AllocatorFrozen);
+        }
+        let header = self.header_of(ptr).map_err(|e| match e {
+            AllocError::DoubleFree => AllocError::InvalidPointer,
+            e => e,
+        })?;
+        let end = header as usize + unsafe { (*header).total_size() };
+        if new_size == 0 {
+            return Err(AllocError::InvalidParameter);
+        }
+        match (ptr.as_ptr() as usize).checked_add(new_size) {
+            Some(new_end) if new_end <= end => Ok(()),
+            _ => Err(AllocError::OutOfMemory),
+        }
+    }
+
+    /// 整理堆：伙伴块的位置由大小决定，释放时已尽可能合并，不搬动任何块
+    pub fn compact(&mut self, _relocations: &mut RelocationTable) -> Result<CompactionReport, AllocError> {
+        if self.frozen {
+            return Err(AllocError::AllocatorFrozen);
+        }
+        Ok(CompactionReport {
+            free_blocks_before: self.stats.free_count,
+            free_blocks_after: self.stats.free_count,
+            largest_free_before: self.stats.max_free_block_size,
+            largest_free_after: self.stats.max_free_block_size,
+            ..CompactionReport::default()
+        })
+    }
+
+    /// 获取统计信息
+    pub fn stats(&self) -> AllocStats {
+        self.stats.clone()
+    }
+
+    /// 管理的地址范围 (对齐之后)
+    pub fn heap_range(&self) -> Range<usize> {
+        self.heap_start..self.heap_end
+    }
+
+    /// 执行完整性检查：块头完好、块按自身大小对齐且恰好铺满堆，空闲块数与统计一致
+    pub fn integrity_check(&self) -> Result<(), AllocError> {
+        let mut current = self.heap_start;
+        let mut free_blocks = 0;
+        while current < self.heap_end {
+            let header = current as *const BlockHeader;
+            let size = unsafe {
+                if !(*header).validate() {
+                    error_print!("Buddy integrity check failed at 0x{:x}", current);
+                    return Err(AllocError::CorruptedHeader);
+                }
+                if (*header).status == BlockStatus::Free {
+                    free_blocks += 1;
+                }
+                (*header).total_size()
+            };
+            if !size.is_power_of_two() || size < MIN_BLOCK || !(current - self.heap_start).is_multiple_of(size) {
+                error_print!("Buddy block at 0x{:x} has an invalid size {}", current, size);
+                return Err(AllocError::CorruptedHeader);
+            }
+            current += size;
+        }
+        if current != self.heap_end {
+            error_print!("Heap corruption: size mismatch. Expected end 0x{:x}, got 0x{:x}", self.heap_end, current);
+            return Err(AllocError::InternalError);
+        }
+        if free_blocks != self.stats.free_count {
+            error_print!("Buddy free lists hold {} blocks, the heap {}", self.stats.free_count, free_blocks);
+            return Err(AllocError::InternalError);
+        }
+        Ok(())
+    }
+
+    /// 准备接管信息
+    pub fn prepare_handover(&mut self) -> Option<advanced::EarlyBox<HandoverInfo>> {
+        allocator::collect_handover(self.heap_start, self.heap_end, self.stats())
+    }
+
+    /// 冻结分配器
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// 设置分配用途
+    pub fn set_purpose(&mut self, ptr: NonNull<u8>, purpose: AllocPurpose) -> Result<(), AllocError> {
+        let header = self.header_of(ptr).map_err(|e| match e {
+            AllocError::DoubleFree => AllocError::InvalidPointer,
+            e => e,
+        })?;
+        unsafe { (*header).set_purpose(purpose) };
+        Ok(())
+    }
+
+    /// 用户数据在块内的偏移
+    fn data_offset(align: usize) -> usize {
+        HEADER_SIZE.next_multiple_of(align.max(16))
+    }
+
+    /// 能容纳`size`字节 (含块头) 的最小阶
+    fn order_for(size: usize) -> Option<usize> {
+        let order = Self::order_of(size.max(MIN_BLOCK).checked_next_power_of_two()?);
+        (order <= MAX_ORDER).then_some(order)
+    }
+
+    fn order_of(block_size: usize) -> usize {
+        (block_size / MIN_BLOCK).trailing_zeros() as usize
+    }
+
+    /// 找回已分配指针所在块的块头，块已空闲时返回`DoubleFree`
+    fn header_of(&self, ptr: NonNull<u8>) -> Result<*mut BlockHeader, AllocError> {
+        let user = ptr.as_ptr() as usize;
+        if user < self.heap_start + HEADER_SIZE || user >= self.heap_end {
+            return Err(AllocError::InvalidPointer);
+        }
+        let offset = user - self.heap_start;
+        // 默认对齐的数据紧跟块头；更大的对齐使数据位于块内一个2的幂偏移处，
+        // 而块按自身大小对齐，所以该偏移就是相对堆起点偏移的最低位
+        let block = if offset % MIN_BLOCK == HEADER_SIZE % MIN_BLOCK {
+            user - HEADER_SIZE
+        } else {
+            let lowest = offset & offset.wrapping_neg();
+            if lowest < MIN_BLOCK {
+                return Err(AllocError::InvalidPointer);
+            }
+            user - lowest
+        };
+        let header = block as *mut BlockHeader;
+        unsafe {
+            if !(*header).validate() {
+                return Err(AllocError::CorruptedHeader);
+            }
+            if (*header).status != BlockStatus::Allocated {
+                return Err(AllocError::DoubleFree);
+            }
+            if user >= block + (*header).total_size() {
+                return Err(AllocError::InvalidPointer);
+            }
+        }
+        Ok(header)
+    }
+
+    fn push(&mut self, order: usize, block: usize) {
+        let node = (block + HEADER_SIZE) as *mut FreeBlock;
+        let head = self.free_lists[order];
+        unsafe {
+            (*node).prev = ptr::null_mut();
+            (*node).next = head;
+            if !head.is_null() {
+                (*head).prev = node;
+            }
+        }
+        self.free_lists[order] = node;
+    }
+
+    fn pop(&mut self, order: usize) -> usize {
+        let node = self.free_lists[order];
+        let block = node as usize - HEADER_SIZE;
+        self.remove(block);
+        block
+    }
+
+    fn remove(&mut self, block: usize) {
+        let node = (block + HEADER_SIZE) as *mut FreeBlock;
+        let order = Self::order_of(unsafe { (*(block as *const BlockHeader)).total_size() });
+        unsafe {
+            if (*node).prev.is_null() {
+                self.free_lists[order] = (*node).next;
+            } else {
+                (*(*node).prev).next = (*node).next;
+            }
+            if !(*node).next.is_null() {
+                (*(*node).next).prev = (*node).prev;
+            }
+        }
+    }
+
+    fn update_max_free(&mut self) {
+        self.stats.max_free_block_size = (0..=MAX_ORDER)
+            .rev()
+            .find(|&order| !self.free_lists[order].is_null())
+            .map_or(0, |order| MIN_BLOCK << order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const HEAP_SIZE: usize = 64 * 1024;
+
+    /// 在宿主机内存上建立分配器，返回的缓冲区必须比分配器活得更久
+    fn heap(size: usize) -> (Vec<u128>, BuddyAllocator) {
+        let mut buffer = alloc::vec![0u128; (size + BASE_ALIGN) / mem::size_of::<u128>()];
+        let start = (buffer.as_mut_ptr() as usize).next_multiple_of(BASE_ALIGN);
+        let allocator = BuddyAllocator::new(start, size).unwrap();
+        (buffer, allocator)
+    }
+
+    #[test]
+    fn splits_and_merges_back() {
+        let (_buffer, mut allocator) = heap(HEAP_SIZE);
+        assert_eq!(allocator.stats().free_count, 1);
+        let blocks: Vec<_> = (0..32).map(|i| allocator.alloc(16 + i * 40).unwrap()).collect();
+        allocator.integrity_check().unwrap();
+        assert_eq!(allocator.stats().alloc_count, 32);
+
+        for ptr in blocks.iter().skip(1).step_by(2).chain(blocks.iter().step_by(2)) {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        allocator.integrity_check().unwrap();
+        let stats = allocator.stats();
+        assert_eq!((stats.alloc_count, stats.free_count, stats.free_size), (0, 1, HEAP_SIZE));
+        assert_eq!(stats.max_free_block_size, HEAP_SIZE);
+        assert!(allocator.alloc(HEAP_SIZE - HEADER_SIZE).is_some());
+    }
+
+    #[test]
+    fn power_of_two_sizes_do_not_fragment() {
+        let (_buffer, mut allocator) = heap(HEAP_SIZE);
+        // 交替释放一半块时伙伴都还在用，全部释放后又合并为整个堆
+        let blocks: Vec<_> = (0..64).map(|_| allocator.alloc(1024 - HEADER_SIZE).unwrap()).collect();
+        assert!(allocator.alloc(1).is_none());
+        for ptr in blocks.iter().step_by(2) {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        assert_eq!(allocator.stats().max_free_block_size, 1024);
+        for ptr in blocks.iter().skip(1).step_by(2) {
+            allocator.dealloc(*ptr).unwrap();
+        }
+        assert_eq!(allocator.stats().max_free_block_size, HEAP_SIZE);
+        assert_eq!(allocator.stats().fragmentation_estimate(), 0);
+    }
+
+    #[test]
+    fn uneven_heap_is_carved_into_blocks() {
+        let (_buffer, mut allocator) = heap(3 * 4096 + 100);
+        // 3 * 4096 = 8192 + 4096，末尾不足MIN_BLOCK的部分不使用
+        let stats = allocator.stats();
+        assert_eq!((stats.total_size, stats.free_count), (3 * 4096 + 64, 3));
+        allocator.integrity_check().unwrap();
+        let big = allocator.alloc(8000).unwrap();
+        let small = allocator.alloc(4000).unwrap();
+        assert!(allocator.alloc(4000).is_none());
+        allocator.dealloc(big).unwrap();
+        allocator.dealloc(small).unwrap();
+        allocator.integrity_check().unwrap();
+        assert_eq!(allocator.stats().free_count, 3);
+    }
+
+    #[test]
+    fn aligned_allocations() {
+        let (_buffer, mut allocator) = heap(HEAP_SIZE);
+        let mut blocks = Vec::new();
+        for shift in 3..=12 {
+            let align = 1 << shift;
+            let ptr = allocator.alloc_aligned(100, align).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % align, 0, "alignment {}", align);
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0xa5, 100) };
+            blocks.push(ptr);
+        }
+        allocator.integrity_check().unwrap();
+        allocator.set_purpose(blocks[9], AllocPurpose::PageTable).unwrap();
+        // 4096对齐的块为8KB，数据前有4KB的空隙
+        allocator.resize_in_place(blocks[9], 4096).unwrap();
+        assert_eq!(allocator.resize_in_place(blocks[9], 4097), Err(AllocError::OutOfMemory));
+        for ptr in blocks {
+            allocator.dealloc(ptr).unwrap();
+        }
+        allocator.integrity_check().unwrap();
+        assert_eq!(allocator.stats().free_size, HEAP_SIZE);
+        assert!(allocator.alloc_aligned(16, 2 * BASE_ALIGN).is_none());
+    }
+
+    #[test]
+    fn detects_double_free_and_corruption() {
+        let (_buffer, mut allocator) = heap(HEAP_SIZE);
+        let ptr = allocator.alloc(64).unwrap();
+        let keep = allocator.alloc(64).unwrap();
+        allocator.dealloc(ptr).unwrap();
+        assert_eq!(allocator.dealloc(ptr), Err(AllocError::DoubleFree));
+        let mut outside = 0u64;
+        assert_eq!(allocator.dealloc(NonNull::from(&mut outside).cast()), Err(AllocError::InvalidPointer));
+
+        let header = (keep.as_ptr() as usize - HEADER_SIZE) as *mut BlockHeader;
+        unsafe { (*header).magic ^= 1 };
+        assert_eq!(allocator.integrity_check(), Err(AllocError::CorruptedHeader));
+        assert_eq!(allocator.dealloc(keep), Err(AllocError::CorruptedHeader));
+        assert_eq!(allocator.stats().double_free_attempts, 1);
+    }
+}
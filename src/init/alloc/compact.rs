@@ -0,0 +1,168 @@
+// 堆整理：把可重定位的已分配块向低地址滑动，合并散落的空闲块
+//
+// 只有所有者用`set_purpose`标记为`AllocPurpose::Relocatable`的块会被搬动，其余用途的块
+// (页帧、页表、被设备引用的缓冲区等) 原地不动。
+// 整理按地址顺序遍历块：空闲块之后紧跟一个可重定位的已分配块时，
+// 把该块连同块头整体搬到空闲块的位置，空闲块随之移到其后并与后面的空闲块合并。
+// 搬动不改变块的对齐：新地址的对齐至少要与原地址相同 (最多按页计)，否则该块不动。
+//
+// 每次搬动记入重定位表。标记`Relocatable`的所有者先用`register_fixup`登记修正函数，
+// 每次整理后 (已释放分配器的锁) 修正函数收到这次的表，用`RelocationTable::translate`
+// 换算自己保存的指针；直接调用`compact`的调用者也拿到同一张表。表只对这一次整理有效：
+// 旧地址在整理中可能被别的块占用，不能拿之前的表换算。
+// 重定位表容量固定 (整理时持有分配器的锁，不能分配内存)，表满后本次整理停止。
+// 整理默认关闭，由`set_enabled`打开后在`maintenance`中进行，也可以直接调用`compact`。
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::allocator::AllocError;
+use super::handover::AllocPurpose;
+use super::GLOBAL_EARLY_ALLOCATOR;
+use crate::util::arrayvec::ArrayVec;
+use spin::Mutex;
+
+/// 一次整理最多搬动的块数
+pub const MAX_RELOCATIONS: usize = 64;
+
+/// 保持对齐时考虑的最大对齐，更大的巧合对齐不必保持
+pub const MAX_KEPT_ALIGN: usize = 4096;
+
+/// 最多登记的修正函数数
+pub const MAX_FIXUPS: usize = 8;
+
+/// 整理后修正所有者保存的指针
+pub type Fixup = fn(&RelocationTable);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 持有可重定位块的所有者登记的修正函数
+static FIXUPS: Mutex<ArrayVec<Fixup, MAX_FIXUPS>> = Mutex::new(ArrayVec::new());
+
+/// 一个被搬动的块
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relocation {
+    /// 原用户数据地址
+    pub old: usize,
+    /// 新用户数据地址
+    pub new: usize,
+    /// 用户数据的字节数
+    pub size: usize,
+    pub purpose: AllocPurpose,
+}
+
+/// 一次整理中搬动的块
+#[derive(Debug, Clone, Default)]
+pub struct RelocationTable {
+    entries: ArrayVec<Relocation, MAX_RELOCATIONS>,
+}
+
+impl RelocationTable {
+    pub const fn new() -> Self {
+        Self { entries: ArrayVec::new() }
+    }
+
+    pub fn entries(&self) -> &[Relocation] {
+        &self.entries
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.is_full()
+    }
+
+    /// 记录一次搬动，表满时返回false
+    pub fn record(&mut self, relocation: Relocation) -> bool {
+        self.entries.push(relocation).is_ok()
+    }
+
+    /// 把指向被搬动块内部的地址换算为新地址，不在任何被搬动的块内时返回None
+    ///
+    /// 只能换算这次整理之前保存的地址。
+    pub fn translate(&self, addr: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|r| (r.old..r.old + r.size).contains(&addr))
+            .map(|r| r.new + (addr - r.old))
+    }
+}
+
+/// 一次整理的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub moved_blocks: usize,
+    pub moved_bytes: usize,
+    pub free_blocks_before: usize,
+    pub free_blocks_after: usize,
+    pub largest_free_before: usize,
+    pub largest_free_after: usize,
+}
+
+impl CompactionReport {
+    /// 回收的字节数：最大空闲块 (一次能满足的最大分配) 增长的部分
+    pub fn recovered(&self) -> usize {
+        self.largest_free_after.saturating_sub(self.largest_free_before)
+    }
+}
+
+/// 判断搬到`new`后是否保持`old`的对齐
+pub(super) fn keeps_alignment(old: usize, new: usize) -> bool {
+    let align = (old & old.wrapping_neg()).min(MAX_KEPT_ALIGN);
+    new.is_multiple_of(align)
+}
+
+/// 打开或关闭`maintenance`中的整理
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Release);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// 登记修正函数，之后的每次整理都会调用它；登记已满时返回`OutOfMemory`
+pub fn register_fixup(fixup: Fixup) -> Result<(), AllocError> {
+    FIXUPS.lock().push(fixup).map_err(|_| AllocError::OutOfMemory)
+}
+
+/// 整理早期堆，把重定位表交给登记的修正函数后返回给调用者
+pub fn compact() -> Result<(CompactionReport, RelocationTable), AllocError> {
+    crate::cov!("compact");
+    if !super::is_initialized() {
+        return Err(AllocError::NotInitialized);
+    }
+    let mut relocations = RelocationTable::new();
+    let report = GLOBAL_EARLY_ALLOCATOR.compact(&mut relocations)?;
+    if !relocations.entries().is_empty() {
+        let fixups = FIXUPS.lock().clone();
+        for fixup in fixups.iter() {
+            fixup(&relocations);
+        }
+    }
+    Ok((report, relocations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_interior_pointers() {
+        let mut table = RelocationTable::new();
+        assert!(table.record(Relocation { old: 0x2000, new: 0x1000, size: 0x100, purpose: AllocPurpose::TempBuffer }));
+        assert_eq!(table.translate(0x2000), Some(0x1000));
+        assert_eq!(table.translate(0x20ff), Some(0x10ff));
+        assert_eq!(table.translate(0x2100), None);
+        for i in 1..MAX_RELOCATIONS {
+            assert!(table.record(Relocation { old: i * 0x10000, new: 0, size: 1, purpose: AllocPurpose::Testing }));
+        }
+        assert!(table.is_full());
+        assert!(!table.record(Relocation { old: 0, new: 0, size: 1, purpose: AllocPurpose::Testing }));
+    }
+
+    #[test]
+    fn alignment_is_kept() {
+        assert!(keeps_alignment(0x1010, 0x1020));
+        assert!(!keeps_alignment(0x1040, 0x1010));
+        assert!(keeps_alignment(0x1040, 0x1080));
+        // 页以上的对齐按页计
+        assert!(keeps_alignment(0x10000, 0x3000));
+    }
+}
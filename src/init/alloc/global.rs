@@ -3,7 +3,8 @@
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::{self, NonNull};
-use super::allocator::{ThreadSafeEarlyAllocator, AllocError};
+use super::allocator::{ThreadSafeEarlyAllocator, AllocError, AllocPolicy, Backend, RegionInfo, MAX_REGIONS};
+use crate::util::arrayvec::ArrayVec;
 use super::handover::{AllocPurpose, HandoverInfo};
 use crate::{error_print, warn_print, debug_print};
 
@@ -19,6 +20,17 @@ pub struct EarlyGlobalAllocator {
 // 全局分配器实例 - 内部使用
 static ALLOCATOR_INSTANCE: ThreadSafeEarlyAllocator = ThreadSafeEarlyAllocator::new();
 
+/// 分配内存；失败时调用内存压力回收器释放内存后重试一次
+fn alloc_or_reclaim(size: usize, align: usize) -> Option<NonNull<u8>> {
+    ALLOCATOR_INSTANCE.alloc_aligned(size, align).or_else(|| {
+        if super::pressure::reclaim(size) > 0 {
+            ALLOCATOR_INSTANCE.alloc_aligned(size, align)
+        } else {
+            None
+        }
+    })
+}
+
 impl EarlyGlobalAllocator {
     /// 创建新的全局分配器
     pub const fn new() -> Self {
@@ -29,6 +41,41 @@ impl EarlyGlobalAllocator {
     pub fn init(&self, heap_start: usize, heap_size: usize) -> Result<(), AllocError> {
         ALLOCATOR_INSTANCE.init(heap_start, heap_size)
     }
+
+    /// 以指定的后端初始化全局分配器
+    pub fn init_with_backend(&self, heap_start: usize, heap_size: usize, backend: Backend) -> Result<(), AllocError> {
+        ALLOCATOR_INSTANCE.init_with_backend(heap_start, heap_size, backend)
+    }
+
+    /// 当前使用的后端
+    pub fn backend(&self) -> Option<Backend> {
+        ALLOCATOR_INSTANCE.backend()
+    }
+
+    /// 当前的分配策略
+    pub fn policy(&self) -> Option<AllocPolicy> {
+        ALLOCATOR_INSTANCE.policy()
+    }
+
+    /// 切换分配策略
+    pub fn set_policy(&self, policy: AllocPolicy) -> Result<(), AllocError> {
+        ALLOCATOR_INSTANCE.set_policy(policy)
+    }
+
+    /// 加入一个堆区域，返回区域编号
+    pub fn add_region(&self, start: usize, size: usize) -> Result<usize, AllocError> {
+        ALLOCATOR_INSTANCE.add_region(start, size)
+    }
+
+    /// 各区域的范围与统计信息
+    pub fn regions(&self) -> ArrayVec<RegionInfo, MAX_REGIONS> {
+        ALLOCATOR_INSTANCE.regions()
+    }
+
+    /// 逐个区域执行完整性检查
+    pub fn check_regions(&self) -> Result<(), (usize, AllocError)> {
+        ALLOCATOR_INSTANCE.check_regions()
+    }
     
     /// 设置分配用途
     pub fn set_purpose(&self, ptr: *mut u8, purpose: AllocPurpose) -> Result<(), AllocError> {
@@ -49,6 +96,11 @@ impl EarlyGlobalAllocator {
         ALLOCATOR_INSTANCE.prepare_handover()
     }
     
+    /// 整理堆
+    pub fn compact(&self, relocations: &mut super::compact::RelocationTable) -> Result<super::compact::CompactionReport, AllocError> {
+        ALLOCATOR_INSTANCE.compact(relocations)
+    }
+    
     /// 冻结分配器
     pub fn freeze(&self) -> Result<(), AllocError> {
         ALLOCATOR_INSTANCE.freeze()
@@ -73,7 +125,7 @@ impl EarlyGlobalAllocator {
             return Err(AllocError::InvalidParameter);
         }
         
-        match ALLOCATOR_INSTANCE.alloc_aligned(layout.size(), layout.align()) {
+        match alloc_or_reclaim(layout.size(), layout.align()) {
             Some(ptr) => Ok(ptr),
             None => Err(AllocError::OutOfMemory),
         }
@@ -86,7 +138,7 @@ impl EarlyGlobalAllocator {
     
     /// 对齐分配内存（原始接口）
     pub fn alloc_aligned_raw(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
-        ALLOCATOR_INSTANCE.alloc_aligned(size, align)
+        alloc_or_reclaim(size, align)
     }
     
     /// 释放内存（原始接口）
@@ -118,7 +170,7 @@ impl EarlyGlobalAllocator {
         }
     }
     
-    /// 重新分配（简单实现）
+    /// 重新分配：先尝试原地改变大小，不行再分配、复制、释放
     pub fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         if ptr.is_null() {
             return unsafe { 
@@ -133,6 +185,12 @@ impl EarlyGlobalAllocator {
             return ptr::null_mut();
         }
         
+        if let Some(non_null_ptr) = NonNull::new(ptr) {
+            if ALLOCATOR_INSTANCE.resize_in_place(non_null_ptr, new_size).is_ok() {
+                return ptr;
+            }
+        }
+        
         let new_layout = match Layout::from_size_align(new_size, layout.align()) {
             Ok(l) => l,
             Err(_) => return ptr::null_mut(),
@@ -158,7 +216,7 @@ impl EarlyGlobalAllocator {
 
 unsafe impl GlobalAlloc for EarlyGlobalAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match ALLOCATOR_INSTANCE.alloc_aligned(layout.size(), layout.align()) {
+        match alloc_or_reclaim(layout.size(), layout.align()) {
             Some(ptr) => ptr.as_ptr(),
             None => ptr::null_mut(),
         }
@@ -190,6 +248,8 @@ unsafe impl GlobalAlloc for EarlyGlobalAllocator {
     }
 }
 
+// 宿主机测试使用std的分配错误处理
+#[cfg(not(feature = "host-test"))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
     error_print!("Memory allocation error!");
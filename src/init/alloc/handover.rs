@@ -2,11 +2,19 @@
 // 用于将早期分配的内存信息安全传递给完整的内存管理系统
 
 use super::metadata::AllocStats;
+use crate::time;
+use crate::trap::collections::RangeMap;
 use crate::{println, warn_print, error_print, info_print};
 
 // 最大可跟踪的已分配块数量
 pub const MAX_TRACKED_BLOCKS: usize = 512;
 
+// 存在超过该时长（微秒）的块被视为可能泄漏
+pub const LEAK_AGE_US: u64 = 10_000_000;
+
+// 临时缓冲区与测试数据的泄漏阈值（微秒），它们本应很快释放
+pub const TEMP_LEAK_AGE_US: u64 = 1_000_000;
+
 // 接管协议版本
 pub const HANDOVER_PROTOCOL_VERSION: u32 = 1;
 
@@ -38,6 +46,7 @@ pub enum AllocPurpose {
     SystemCall = 17,         // 系统调用相关
     Debugging = 18,          // 调试信息
     Testing = 19,            // 测试数据
+    Relocatable = 20,        // 可重定位（所有者能处理整理中的搬动）
 }
 
 impl AllocPurpose {
@@ -76,6 +85,14 @@ impl AllocPurpose {
         }
     }
     
+    /// 判断堆整理能否搬动该用途的内存
+    ///
+    /// 只有所有者明确标记为`Relocatable`的块才会被搬动：其他用途的内存 (例如用户页帧)
+    /// 可能被页表或设备按物理地址引用，搬动后无人修正。
+    pub fn is_relocatable(&self) -> bool {
+        matches!(self, AllocPurpose::Relocatable)
+    }
+    
     /// 判断该用途的内存是否需要特殊对齐
     pub fn requires_special_alignment(&self) -> bool {
         match self {
@@ -115,6 +132,7 @@ impl AllocPurpose {
             AllocPurpose::SharedMemory => 80,
             AllocPurpose::UserData => 90,
             AllocPurpose::CacheBuffer => 100,
+            AllocPurpose::Relocatable => 110,
             AllocPurpose::Debugging => 200,
             AllocPurpose::TempBuffer => 240,
             AllocPurpose::Testing => 250,
@@ -145,6 +163,7 @@ impl AllocPurpose {
             AllocPurpose::SystemCall => "System Call",
             AllocPurpose::Debugging => "Debugging Info",
             AllocPurpose::Testing => "Testing Data",
+            AllocPurpose::Relocatable => "Relocatable",
         }
     }
     
@@ -171,6 +190,7 @@ impl AllocPurpose {
             AllocPurpose::SystemCall => "SYS",
             AllocPurpose::Debugging => "DBG",
             AllocPurpose::Testing => "TST",
+            AllocPurpose::Relocatable => "RLC",
         }
     }
 }
@@ -191,7 +211,7 @@ pub struct AllocatedBlock {
     /// 分配ID（用于追踪）
     pub alloc_id: u64,
     
-    /// 分配时间戳
+    /// 分配时间戳（启动以来的微秒数）
     pub timestamp: u64,
     
     /// 访问权限标志
@@ -212,7 +232,7 @@ impl AllocatedBlock {
             size,
             purpose,
             alloc_id,
-            timestamp: get_timestamp(),
+            timestamp: time::monotonic_us(),
             permissions: MemoryPermissions::READ_WRITE,
             alignment: 8,
             reserved: [0; 2],
@@ -234,19 +254,19 @@ impl AllocatedBlock {
         self.addr < other.end_addr() && other.addr < self.end_addr()
     }
     
-    /// 获取块的年龄（相对时间）
+    /// 获取块的年龄（微秒）
     pub fn age(&self) -> u64 {
-        get_timestamp().saturating_sub(self.timestamp)
+        time::monotonic_us().saturating_sub(self.timestamp)
     }
     
-    /// 检查是否是古老的块
+    /// 检查块是否已存在超过 `threshold` 微秒
     pub fn is_old(&self, threshold: u64) -> bool {
         self.age() > threshold
     }
     
     /// 打印块信息
     pub fn print_info(&self) {
-        println!("Block #{}: addr=0x{:x}, size={} bytes, purpose={} ({}), age={}", 
+        println!("Block #{}: addr=0x{:x}, size={} bytes, purpose={} ({}), age={} us", 
                  self.alloc_id, self.addr, self.size, 
                  self.purpose.short_name(), self.purpose.description(), self.age());
     }
@@ -261,7 +281,7 @@ impl AllocatedBlock {
         println!("Priority: {}", self.purpose.priority());
         println!("Alignment: {} bytes", self.alignment);
         println!("Permissions: {:?}", self.permissions);
-        println!("Age: {} ticks", self.age());
+        println!("Age: {} us", self.age());
         println!("Critical: {}", self.purpose.is_critical());
         println!("Reclaimable: {}", self.purpose.is_reclaimable());
         println!("Movable: {}", self.purpose.is_movable());
@@ -334,7 +354,7 @@ pub struct HandoverInfo {
     /// 分配器状态快照
     pub allocator_state: AllocatorState,
     
-    /// 接管时间戳
+    /// 接管时间戳（启动以来的微秒数）
     pub handover_timestamp: u64,
     
     /// 校验和
@@ -412,7 +432,7 @@ impl HandoverInfo {
                     max_consecutive_failures: 0,
                 },
             },
-            handover_timestamp: get_timestamp(),
+            handover_timestamp: time::monotonic_us(),
             checksum: 0,
         };
         
@@ -473,7 +493,7 @@ impl HandoverInfo {
     }
     
     /// 按用途分组统计 - 扩展版本
-    pub fn group_by_purpose(&self) -> [(AllocPurpose, usize, usize); 20] {
+    pub fn group_by_purpose(&self) -> [(AllocPurpose, usize, usize); 21] {
         let mut groups = [
             (AllocPurpose::Unknown, 0, 0),
             (AllocPurpose::InterruptTable, 0, 0),
@@ -495,6 +515,7 @@ impl HandoverInfo {
             (AllocPurpose::SystemCall, 0, 0),
             (AllocPurpose::Debugging, 0, 0),
             (AllocPurpose::Testing, 0, 0),
+            (AllocPurpose::Relocatable, 0, 0),
         ];
         
         for i in 0..self.allocated_count {
@@ -562,7 +583,6 @@ impl HandoverInfo {
             leak_score: 0,
         };
         
-        let age_threshold = 10000; // 假设的阈值
         let size_threshold = 1024 * 1024; // 1MB
         
         for i in 0..self.allocated_count {
@@ -570,7 +590,7 @@ impl HandoverInfo {
             let mut suspicious = false;
             
             // 检查古老的块
-            if block.is_old(age_threshold) {
+            if block.is_old(LEAK_AGE_US) {
                 suspicious = true;
                 result.oldest_block_age = result.oldest_block_age.max(block.age());
             }
@@ -582,7 +602,7 @@ impl HandoverInfo {
             
             // 检查临时或测试数据
             if matches!(block.purpose, AllocPurpose::TempBuffer | AllocPurpose::Testing) 
-               && block.is_old(1000) {
+               && block.is_old(TEMP_LEAK_AGE_US) {
                 suspicious = true;
             }
             
@@ -690,7 +710,7 @@ impl HandoverInfo {
             println!("  Suspicious blocks: {}", leak_result.suspicious_count);
             println!("  Suspicious size: {} KB", leak_result.total_suspicious_size / 1024);
             println!("  Leak score: {}%", leak_result.leak_score);
-            println!("  Oldest block age: {} ticks", leak_result.oldest_block_age);
+            println!("  Oldest block age: {} ms", leak_result.oldest_block_age / 1000);
         }
         
         println!("===============================");
@@ -731,15 +751,11 @@ impl HandoverInfo {
             }
         }
         
-        // 检查块是否重叠
-        for i in 0..self.allocated_count {
-            for j in (i + 1)..self.allocated_count {
-                let block1 = &self.allocated_blocks[i];
-                let block2 = &self.allocated_blocks[j];
-                
-                if block1.overlaps_with(block2) {
-                    return Err("Overlapping blocks detected");
-                }
+        // 检查块是否重叠：逐个插入区间映射，与已插入的块重叠即失败 (空块不占内存)
+        let mut ranges = RangeMap::new();
+        for block in self.allocated_blocks[..self.allocated_count].iter().filter(|b| b.size > 0) {
+            if ranges.insert(block.addr..block.end_addr(), ()).is_err() {
+                return Err("Overlapping blocks detected");
             }
         }
         
@@ -965,8 +981,73 @@ pub mod handover_utils {
     }
 }
 
-/// 获取时间戳（简化实现）
-fn get_timestamp() -> u64 {
-    static COUNTER: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
-    COUNTER.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAP_START: usize = 0x8040_0000;
+    const HEAP_END: usize = HEAP_START + 0x10000;
+
+    /// 由给定的 (偏移, 大小) 构造接管信息，统计与校验和都与块一致
+    fn handover(blocks: &[(usize, usize)]) -> HandoverInfo {
+        let mut info = HandoverInfo::new(HEAP_START, HEAP_END, AllocStats::new(HEAP_END - HEAP_START));
+        for (i, &(offset, size)) in blocks.iter().enumerate() {
+            let block = AllocatedBlock::new(HEAP_START + offset, size, AllocPurpose::Testing, i as u64 + 1);
+            info.allocated_blocks[i] = block;
+        }
+        info.allocated_count = blocks.len();
+        info.statistics.used_size = info.allocated_size();
+        info.update_checksum();
+        info
+    }
+
+    #[test]
+    fn valid_handover() {
+        let info = handover(&[(0x40, 0x100), (0x200, 0x80), (0x1000, 0x800)]);
+        assert_eq!(info.validate(), Ok(()));
+        assert_eq!(info.heap_size(), 0x10000);
+        assert_eq!(info.allocated_size(), 0x980);
+        assert_eq!(handover(&[]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn bad_magic_and_version() {
+        let mut info = handover(&[(0x40, 0x100)]);
+        info.magic = !HANDOVER_MAGIC;
+        info.update_checksum();
+        assert_eq!(info.validate(), Err("Invalid handover magic"));
+
+        let mut info = handover(&[(0x40, 0x100)]);
+        info.version += 1;
+        info.update_checksum();
+        assert_eq!(info.validate(), Err("Unsupported protocol version"));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut info = handover(&[(0x40, 0x100)]);
+        info.allocated_blocks[0].addr += 0x10;
+        assert_eq!(info.validate(), Err("Checksum validation failed"));
+    }
+
+    #[test]
+    fn overlapping_blocks() {
+        let info = handover(&[(0x40, 0x100), (0x400, 0x40), (0x100, 0x80)]);
+        assert_eq!(info.validate(), Err("Overlapping blocks detected"));
+        // 首尾相接不算重叠
+        assert_eq!(handover(&[(0x40, 0x100), (0x140, 0x80)]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn block_outside_heap() {
+        let info = handover(&[(0x40, 0x100), (0xff00, 0x200)]);
+        assert_eq!(info.validate(), Err("Block outside heap range"));
+    }
+
+    #[test]
+    fn statistics_mismatch() {
+        let mut info = handover(&[(0x40, 0x100)]);
+        info.statistics.used_size += 1;
+        assert_eq!(info.validate(), Err("Statistics mismatch"));
+    }
+}
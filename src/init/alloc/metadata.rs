@@ -1,7 +1,9 @@
 // 生产级早期分配器元数据管理
 // 定义完善的块头、统计信息等数据结构
 
+use super::allocator::AllocPolicy;
 use super::handover::AllocPurpose;
+use crate::time;
 use core::mem;
 
 // 块头魔数
@@ -34,18 +36,19 @@ pub struct BlockHeader {
     /// 分配用途
     pub purpose: AllocPurpose,
     
-    /// 分配时间戳（相对时间，用于LRU等算法）
+    /// 分配时间戳（启动以来的微秒数，用于LRU等算法）
     pub timestamp: u64,
     
     /// 校验和（简单的完整性检查）
     pub checksum: u32,
     
-    /// 填充字节，确保头部大小为16字节的倍数
-    #[cfg(target_pointer_width = "64")]
-    pub padding: [u8; 4],
+    /// 边界标记：物理上前一个块的总大小（含头部），堆中第一个块为0
+    /// 释放时据此直接找到前一个块；单个堆区域不超过1GB，u32足够
+    pub prev_size: u32,
     
+    /// 填充字节，确保头部大小为16字节的倍数
     #[cfg(target_pointer_width = "32")]
-    pub padding: [u8; 8],
+    pub padding: [u8; 4],
 }
 
 impl BlockHeader {
@@ -57,12 +60,11 @@ impl BlockHeader {
             magic: BLOCK_MAGIC,
             alloc_id: 0,
             purpose: AllocPurpose::Unknown,
-            timestamp: get_timestamp(),
+            timestamp: time::monotonic_us(),
             checksum: 0, // 校验和初始为0
-            #[cfg(target_pointer_width = "64")]
-            padding: [0; 4],
+            prev_size: 0,
             #[cfg(target_pointer_width = "32")]
-            padding: [0; 8],
+            padding: [0; 4],
         };
         
         // 基于其他字段的值计算并填充校验和
@@ -96,6 +98,7 @@ impl BlockHeader {
         checksum = checksum.wrapping_add(self.purpose as u32);
         checksum = checksum.wrapping_add(self.timestamp as u32);
         checksum = checksum.wrapping_add((self.timestamp >> 32) as u32);
+        checksum = checksum.wrapping_add(self.prev_size);
         // 注意：这里没有包含 self.checksum 自身
         checksum
     }
@@ -110,6 +113,17 @@ impl BlockHeader {
         self.size + mem::size_of::<BlockHeader>()
     }
     
+    /// 物理上前一个块的总大小（含头部），没有前一个块时为0
+    pub fn prev_size(&self) -> usize {
+        self.prev_size as usize
+    }
+    
+    /// 设置边界标记
+    pub fn set_prev_size(&mut self, prev_size: usize) {
+        self.prev_size = prev_size as u32;
+        self.update_checksum();
+    }
+    
     /// 获取用户数据起始地址
     pub fn user_data_addr(&self) -> usize {
         (self as *const BlockHeader as usize) + mem::size_of::<BlockHeader>()
@@ -129,17 +143,47 @@ impl BlockHeader {
     
     /// 更新时间戳
     pub fn update_timestamp(&mut self) {
-        self.timestamp = get_timestamp();
+        self.timestamp = time::monotonic_us();
         self.update_checksum();
     }
     
-    /// 检查块是否过期（用于调试泄漏检测）
+    /// 检查块是否已存在超过 `threshold` 微秒（用于调试泄漏检测）
     pub fn is_old(&self, threshold: u64) -> bool {
-        let current_time = get_timestamp();
+        let current_time = time::monotonic_us();
         current_time.saturating_sub(self.timestamp) > threshold
     }
 }
 
+/// 一种分配策略查找空闲块的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// 查找次数
+    pub searches: u64,
+    /// 所有查找检查过的空闲块数
+    pub steps: u64,
+    /// 一次查找最多检查的空闲块数
+    pub longest: u64,
+}
+
+impl SearchStats {
+    pub fn record(&mut self, steps: usize) {
+        self.searches += 1;
+        self.steps += steps as u64;
+        self.longest = self.longest.max(steps as u64);
+    }
+
+    /// 平均每次查找检查的空闲块数
+    pub fn average(&self) -> u64 {
+        self.steps.checked_div(self.searches).unwrap_or(0)
+    }
+
+    pub fn merge(&mut self, other: &SearchStats) {
+        self.searches += other.searches;
+        self.steps += other.steps;
+        self.longest = self.longest.max(other.longest);
+    }
+}
+
 /// 增强的分配器统计信息
 #[derive(Debug, Clone)]
 pub struct AllocStats {
@@ -162,6 +206,8 @@ pub struct AllocStats {
     pub peak_used_size: usize,
     pub max_free_block_size: usize,
     pub fragmentation_percent: u8,
+    /// 按分配策略 (`AllocPolicy::index`) 分开的查找统计
+    pub search: [SearchStats; AllocPolicy::ALL.len()],
 }
 
 impl AllocStats {
@@ -186,6 +232,7 @@ impl AllocStats {
             peak_used_size: 0,
             max_free_block_size: total_size,
             fragmentation_percent: 0,
+            search: [SearchStats::default(); AllocPolicy::ALL.len()],
         }
     }
     
@@ -207,6 +254,13 @@ impl AllocStats {
         self.alloc_count = self.alloc_count.saturating_sub(1);
     }
 
+    /// 已分配块原地改变大小
+    pub fn record_resize(&mut self, old_size: usize, new_size: usize) {
+        self.used_size = self.used_size - old_size + new_size;
+        self.max_alloc_size = self.max_alloc_size.max(new_size);
+        self.peak_used_size = self.peak_used_size.max(self.used_size);
+    }
+
     pub fn record_merge(&mut self) {
         self.merge_count += 1;
         self.coalesce_count += 1;
@@ -216,6 +270,15 @@ impl AllocStats {
         self.split_count += 1;
     }
     
+    /// 以`policy`查找空闲块，检查了`steps`个空闲块
+    pub fn record_search(&mut self, policy: AllocPolicy, steps: usize) {
+        self.search[policy.index()].record(steps);
+    }
+
+    pub fn search_stats(&self, policy: AllocPolicy) -> SearchStats {
+        self.search[policy.index()]
+    }
+
     pub fn record_alloc_failure(&mut self) { self.failed_allocs += 1; }
     pub fn record_double_free(&mut self) { self.double_free_attempts += 1; }
     pub fn record_corruption(&mut self) { self.corrupted_blocks += 1; }
@@ -262,6 +325,13 @@ impl AllocStats {
         println!("  Block splits: {}", self.split_count);
         println!("  Coalesce operations: {}", self.coalesce_count);
         println!("  Fragmentation: {}%", self.fragmentation_estimate());
+        for policy in AllocPolicy::ALL {
+            let search = self.search_stats(policy);
+            if search.searches > 0 {
+                println!("  Search ({}): {} searches, avg {} / max {} blocks",
+                         policy.name(), search.searches, search.average(), search.longest);
+            }
+        }
         println!("Error Statistics:");
         println!("  Double free attempts: {}", self.double_free_attempts);
         println!("  Corrupted blocks: {}", self.corrupted_blocks);
@@ -367,8 +437,3 @@ impl BlockValidator {
         Ok(())
     }
 }
-
-fn get_timestamp() -> u64 {
-    static COUNTER: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
-    COUNTER.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
-}
\ No newline at end of file
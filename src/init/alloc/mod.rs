@@ -2,25 +2,37 @@
 // 用于内核启动早期的内存分配，在完整的内存管理系统初始化前使用
 
 pub mod allocator;
+pub mod buddy;
 pub mod metadata;
 pub mod handover;
 pub mod global;
+pub mod pressure;
+pub mod subheap;
+pub mod slab;
+pub mod compact;
 
 use core::sync::atomic::{AtomicBool, Ordering};
 use crate::{error_print, warn_print, info_print, debug_print, println};
 use crate::init::alloc::global::advanced;
+use crate::time;
+use alloc::vec::Vec;
 
 // 从子模块导出类型
-pub use self::allocator::{EarlyAllocator, AllocError, ThreadSafeEarlyAllocator};
+pub use self::allocator::{EarlyAllocator, AllocError, AllocPolicy, Backend, RegionInfo, ThreadSafeEarlyAllocator, MAX_REGIONS};
+pub use self::buddy::BuddyAllocator;
 pub use self::global::{GLOBAL_EARLY_ALLOCATOR, EarlyGlobalAllocator};
-pub use self::metadata::{AllocStats, BlockHeader, BlockStatus, HealthStatus};
+pub use self::metadata::{AllocStats, BlockHeader, BlockStatus, HealthStatus, SearchStats};
 pub use self::handover::{HandoverInfo, AllocatedBlock, AllocPurpose, HandoverProtocol};
+pub use self::pressure::{Shrinker, PressureStats, register_shrinker, unregister_shrinker};
+pub use self::subheap::{SubHeap, SubHeapInfo};
+pub use self::slab::{SlabBox, SlabCache, SlabStats};
+pub use self::compact::{CompactionReport, Relocation, RelocationTable};
 
 // 全局状态管理
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 static ENABLED: AtomicBool = AtomicBool::new(true);
 
-/// 初始化早期分配器
+/// 初始化早期分配器，使用首次适配后端
 /// 
 /// # 参数
 /// * `heap_start` - 堆起始地址
@@ -29,6 +41,11 @@ static ENABLED: AtomicBool = AtomicBool::new(true);
 /// # 返回值
 /// 成功返回Ok(())，失败返回错误
 pub fn init(heap_start: usize, heap_size: usize) -> Result<(), AllocError> {
+    init_with_backend(heap_start, heap_size, Backend::FirstFit)
+}
+
+/// 以指定的后端初始化早期分配器，参数与返回值同`init`
+pub fn init_with_backend(heap_start: usize, heap_size: usize, backend: Backend) -> Result<(), AllocError> {
     // 检查是否已经初始化
     if INITIALIZED.load(Ordering::Acquire) {
         warn_print!("Early allocator already initialized");
@@ -71,10 +88,10 @@ pub fn init(heap_start: usize, heap_size: usize) -> Result<(), AllocError> {
     }
     
     // 初始化全局分配器
-    match GLOBAL_EARLY_ALLOCATOR.init(heap_start, heap_size) {
+    match GLOBAL_EARLY_ALLOCATOR.init_with_backend(heap_start, heap_size, backend) {
         Ok(_) => {
             INITIALIZED.store(true, Ordering::Release);
-            info_print!("Early allocator initialized successfully");
+            info_print!("Early allocator initialized successfully ({} backend)", backend.name());
             info_print!("  Start: 0x{:x}", heap_start);
             info_print!("  Size:  {} KB ({} bytes)", heap_size / 1024, heap_size);
             info_print!("  End:   0x{:x}", heap_end);
@@ -101,6 +118,39 @@ pub fn init(heap_start: usize, heap_size: usize) -> Result<(), AllocError> {
     }
 }
 
+/// 为早期堆加入一段不与已有区域相连的内存 (如保留的DMA窗口)，返回区域编号
+///
+/// 区域使用`init`时选定的后端，有独立的统计信息与完整性检查；
+/// 分配在前面的区域无法满足时落到后面的区域。
+pub fn add_region(start: usize, size: usize) -> Result<usize, AllocError> {
+    crate::cov!("add_region");
+    if !is_initialized() {
+        return Err(AllocError::NotInitialized);
+    }
+    if start == 0 || start & 0xF != 0 {
+        error_print!("Invalid heap region start: 0x{:x}", start);
+        return Err(AllocError::InvalidAlignment);
+    }
+    match GLOBAL_EARLY_ALLOCATOR.add_region(start, size) {
+        Ok(index) => {
+            info_print!("Heap region {} added: 0x{:x} - 0x{:x} ({} KB)", index, start, start + size, size / 1024);
+            Ok(index)
+        }
+        Err(e) => {
+            error_print!("Failed to add heap region 0x{:x} ({} bytes): {:?}", start, size, e);
+            Err(e)
+        }
+    }
+}
+
+/// 各堆区域的范围与统计信息，按加入顺序
+pub fn regions() -> Vec<RegionInfo> {
+    if !is_initialized() {
+        return Vec::new();
+    }
+    GLOBAL_EARLY_ALLOCATOR.regions().iter().cloned().collect()
+}
+
 /// 检查分配器是否已初始化
 pub fn is_initialized() -> bool {
     INITIALIZED.load(Ordering::Acquire)
@@ -131,17 +181,20 @@ pub fn disable() {
 /// # 返回值
 /// 成功返回内存地址，失败返回None
 pub fn alloc(size: usize) -> Option<*mut u8> {
+    crate::cov!("alloc");
     if !is_initialized() {
         error_print!("Early allocator not initialized");
         return None;
     }
     
     if !is_enabled() {
+        crate::cov!("alloc", "disabled");
         debug_print!("Allocation attempt while allocator disabled (size: {})", size);
         return None;
     }
     
     if size == 0 {
+        crate::cov!("alloc", "zero_size");
         debug_print!("Zero-size allocation request");
         return None;
     }
@@ -149,6 +202,7 @@ pub fn alloc(size: usize) -> Option<*mut u8> {
     match GLOBAL_EARLY_ALLOCATOR.alloc_aligned_raw(size, 8) {
         Some(ptr) => Some(ptr.as_ptr()),
         None => {
+            crate::cov!("alloc", "exhausted");
             debug_print!("Allocation failed: size: {}", size);
             None
         }
@@ -164,6 +218,7 @@ pub fn alloc(size: usize) -> Option<*mut u8> {
 /// # 返回值
 /// 成功返回内存地址，失败返回None
 pub fn alloc_aligned(size: usize, align: usize) -> Option<*mut u8> {
+    crate::cov!("alloc_aligned");
     if !is_initialized() {
         error_print!("Early allocator not initialized");
         return None;
@@ -175,6 +230,7 @@ pub fn alloc_aligned(size: usize, align: usize) -> Option<*mut u8> {
     }
     
     if size == 0 || !align.is_power_of_two() {
+        crate::cov!("alloc_aligned", "invalid");
         debug_print!("Invalid aligned allocation parameters: size={}, align={}", size, align);
         return None;
     }
@@ -182,6 +238,7 @@ pub fn alloc_aligned(size: usize, align: usize) -> Option<*mut u8> {
     match GLOBAL_EARLY_ALLOCATOR.alloc_aligned_raw(size, align) {
         Some(ptr) => Some(ptr.as_ptr()),
         None => {
+            crate::cov!("alloc_aligned", "exhausted");
             debug_print!("Aligned allocation failed: size: {}, align: {}", size, align);
             None
         }
@@ -196,6 +253,7 @@ pub fn alloc_aligned(size: usize, align: usize) -> Option<*mut u8> {
 /// # 返回值
 /// 成功返回内存地址，失败返回None
 pub fn alloc_zeroed(size: usize) -> Option<*mut u8> {
+    crate::cov!("alloc_zeroed");
     if let Some(ptr) = alloc(size) {
         unsafe {
             core::ptr::write_bytes(ptr, 0, size);
@@ -211,18 +269,21 @@ pub fn alloc_zeroed(size: usize) -> Option<*mut u8> {
 /// # 参数
 /// * `ptr` - 要释放的内存地址
 pub fn dealloc(ptr: *mut u8) {
+    crate::cov!("dealloc");
     if !is_initialized() {
         error_print!("Early allocator not initialized");
         return;
     }
     
     if ptr.is_null() {
+        crate::cov!("dealloc", "null");
         warn_print!("Attempt to deallocate null pointer");
         return;
     }
     
     if let Some(non_null_ptr) = core::ptr::NonNull::new(ptr) {
         if let Err(e) = GLOBAL_EARLY_ALLOCATOR.dealloc_raw(non_null_ptr) {
+            crate::cov!("dealloc", "rejected");
             error_print!("Deallocation failed: {:?}, ptr=0x{:x}", e, ptr as usize);
         }
     }
@@ -237,6 +298,7 @@ pub fn dealloc(ptr: *mut u8) {
 /// # 返回值
 /// 成功返回Ok(())，失败返回错误
 pub fn dealloc_safe(ptr: *mut u8, _size: usize) -> Result<(), AllocError> {
+    crate::cov!("dealloc_safe");
     if !is_initialized() {
         return Err(AllocError::NotInitialized);
     }
@@ -254,19 +316,21 @@ pub fn dealloc_safe(ptr: *mut u8, _size: usize) -> Result<(), AllocError> {
 
 /// 设置分配用途
 /// 
+/// 用途记录在块头中，接管信息按用途分组时使用。块释放后用途恢复为`Unknown`。
+/// 
 /// # 参数
-/// * `ptr` - 内存地址
+/// * `ptr` - 内存地址，必须是分配器返回的、尚未释放的指针
 /// * `purpose` - 分配用途
 /// 
 /// # 返回值
 /// 成功返回Ok(())，失败返回错误
-pub fn set_purpose(_ptr: *mut u8, _purpose: AllocPurpose) -> Result<(), AllocError> {
+pub fn set_purpose(ptr: *mut u8, purpose: AllocPurpose) -> Result<(), AllocError> {
+    crate::cov!("set_purpose");
     if !is_initialized() {
         return Err(AllocError::NotInitialized);
     }
     
-    // 简化实现：不实际设置用途
-    Ok(())
+    GLOBAL_EARLY_ALLOCATOR.set_purpose(ptr, purpose)
 }
 
 /// 获取分配器统计信息
@@ -278,13 +342,55 @@ pub fn stats() -> Option<AllocStats> {
     GLOBAL_EARLY_ALLOCATOR.stats()
 }
 
+/// 获取早期堆使用的后端
+pub fn backend() -> Option<Backend> {
+    if !is_initialized() {
+        return None;
+    }
+
+    GLOBAL_EARLY_ALLOCATOR.backend()
+}
+
+/// 获取首次适配后端的分配策略，伙伴系统后端没有策略
+pub fn policy() -> Option<AllocPolicy> {
+    if !is_initialized() {
+        return None;
+    }
+
+    GLOBAL_EARLY_ALLOCATOR.policy()
+}
+
+/// 切换首次适配后端查找空闲块的策略，对所有区域生效
+///
+/// 各策略的查找统计分开记录 (`AllocStats::search_stats`)，可在同一负载下比较。
+pub fn set_policy(policy: AllocPolicy) -> Result<(), AllocError> {
+    crate::cov!("set_policy");
+    if !is_initialized() {
+        return Err(AllocError::NotInitialized);
+    }
+    match GLOBAL_EARLY_ALLOCATOR.set_policy(policy) {
+        Ok(()) => {
+            info_print!("Early allocator policy: {}", policy.name());
+            Ok(())
+        }
+        Err(e) => {
+            warn_print!("Cannot use {} policy with the {:?} backend", policy.name(), backend());
+            Err(e)
+        }
+    }
+}
+
 /// 执行完整性检查
 pub fn integrity_check() -> Result<(), AllocError> {
     if !is_initialized() {
         return Err(AllocError::NotInitialized);
     }
     
-    GLOBAL_EARLY_ALLOCATOR.integrity_check()
+    // 逐个区域检查，报告出错的区域
+    GLOBAL_EARLY_ALLOCATOR.check_regions().map_err(|(index, e)| {
+        error_print!("Heap region {} failed its integrity check: {:?}", index, e);
+        e
+    })
 }
 
 /// 打印分配器状态
@@ -297,6 +403,12 @@ pub fn print_status() {
     info_print!("Early Allocator Status:");
     info_print!("  Initialized: {}", is_initialized());
     info_print!("  Enabled: {}", is_enabled());
+    if let Some(backend) = backend() {
+        info_print!("  Backend: {}", backend.name());
+    }
+    if let Some(policy) = policy() {
+        info_print!("  Policy: {}", policy.name());
+    }
     
     if let Some(stats) = stats() {
         stats.print_summary();
@@ -315,6 +427,21 @@ pub fn print_status() {
             error_print!("Integrity check: FAILED ({:?})", e);
         }
     }
+    
+    let regions = regions();
+    if regions.len() > 1 {
+        for region in &regions {
+            info_print!("  Region {}: 0x{:x} ({} KB), used {} KB, free {} KB, {} live",
+                        region.index, region.start, region.size / 1024, region.stats.used_size / 1024,
+                        region.stats.free_size / 1024, region.stats.alloc_count);
+        }
+    }
+    
+    subheap::print_report();
+    slab::print_report();
+    if let Err((name, e)) = subheap::check_all() {
+        error_print!("Sub-heap '{}' integrity check: FAILED ({:?})", name, e);
+    }
 }
 
 /// 打印详细调试信息
@@ -352,6 +479,7 @@ pub fn health_check() -> Option<HealthStatus> {
 /// # 返回值
 /// 返回接管信息，如果分配器未初始化则返回None
 pub fn prepare_handover() -> Option<advanced::EarlyBox<HandoverInfo>> {
+    crate::cov!("prepare_handover");
     if !is_initialized() {
         warn_print!("Cannot prepare handover: allocator not initialized");
         return None;
@@ -399,6 +527,7 @@ pub fn prepare_handover() -> Option<advanced::EarlyBox<HandoverInfo>> {
 /// # 返回值
 /// 成功返回Ok(())，失败返回错误
 pub fn freeze() -> Result<(), AllocError> {
+    crate::cov!("freeze");
     if !is_initialized() {
         return Err(AllocError::NotInitialized);
     }
@@ -434,6 +563,7 @@ pub fn usage_summary() -> Option<(usize, usize, usize)> {
 /// 紧急回收内存
 /// 尝试回收所有可回收的内存
 pub fn emergency_reclaim() -> usize {
+    crate::cov!("emergency_reclaim");
     if !is_initialized() {
         error_print!("Cannot perform emergency reclaim: allocator not initialized");
         return 0;
@@ -444,10 +574,19 @@ pub fn emergency_reclaim() -> usize {
     // 获取当前状态
     let stats_before = stats();
     
+    // 先让回收器释放缓存等可丢弃的内存
+    let shrunk = pressure::reclaim(usize::MAX);
+    if shrunk > 0 {
+        crate::cov!("emergency_reclaim", "shrinkers");
+        warn_print!("Shrinkers released {} KB", shrunk / 1024);
+        return shrunk;
+    }
+    
     // 准备接管信息以获取可回收块的信息
     if let Some(handover) = GLOBAL_EARLY_ALLOCATOR.prepare_handover() {
         let reclaimable_size = handover.reclaimable_size();
         if reclaimable_size > 0 {
+            crate::cov!("emergency_reclaim", "reclaimable");
             warn_print!("Found {} KB of potentially reclaimable memory", reclaimable_size / 1024);
             
             // 在实际实现中，这里会回收临时缓冲区等
@@ -461,7 +600,12 @@ pub fn emergency_reclaim() -> usize {
 }
 
 /// 运行自动维护任务
+///
+/// 启用整理时只搬动所有者用`set_purpose`标为`AllocPurpose::Relocatable`的块；
+/// 目前内核里还没有这样的所有者，整理不会搬动任何块。要让块可搬动，所有者须先用
+/// `compact::register_fixup`登记修正函数，按收到的`RelocationTable`改写它持有的指针。
 pub fn maintenance() -> Result<(), AllocError> {
+    crate::cov!("maintenance");
     if !is_initialized() {
         return Err(AllocError::NotInitialized);
     }
@@ -470,17 +614,32 @@ pub fn maintenance() -> Result<(), AllocError> {
     
     // 执行完整性检查
     integrity_check()?;
+    // 子堆的损坏按名称报告，归属到创建它的子系统
+    if let Err((name, e)) = subheap::check_all() {
+        error_print!("Maintenance: sub-heap '{}' failed its integrity check: {:?}", name, e);
+        return Err(e);
+    }
     
     // 检查健康状态
     if let Some(health) = health_check() {
         if !health.is_healthy() {
+            crate::cov!("maintenance", "unhealthy");
             warn_print!("Maintenance: health issues detected");
             health.print_report();
         }
     }
     
+    // 只整理所有者标记过的块，登记的修正函数收到这次的重定位表
+    if compact::is_enabled() {
+        let (report, _) = compact::compact()?;
+        if report.moved_blocks > 0 {
+            info_print!("Maintenance: compaction moved {} blocks ({} bytes), free blocks {} -> {}, recovered {} bytes",
+                        report.moved_blocks, report.moved_bytes, report.free_blocks_before,
+                        report.free_blocks_after, report.recovered());
+        }
+    }
+    
     // 在实际实现中，这里可能会执行：
-    // - 内存碎片整理
     // - 清理过期的临时分配
     // - 更新统计信息
     // - 优化空闲链表
@@ -491,6 +650,7 @@ pub fn maintenance() -> Result<(), AllocError> {
 
 /// 创建内存快照（用于调试）
 pub fn create_snapshot() -> Option<MemorySnapshot> {
+    crate::cov!("create_snapshot");
     if !is_initialized() {
         return None;
     }
@@ -499,7 +659,7 @@ pub fn create_snapshot() -> Option<MemorySnapshot> {
     let handover = GLOBAL_EARLY_ALLOCATOR.prepare_handover()?;
     
     Some(MemorySnapshot {
-        timestamp: get_timestamp(),
+        timestamp: time::monotonic_us(),
         statistics: stats,
         handover_info: handover,
     })
@@ -528,7 +688,7 @@ impl MemorySnapshot {
     
     /// 打印快照信息
     pub fn print(&self) {
-        println!("=== Memory Snapshot (t={}) ===", self.timestamp);
+        println!("=== Memory Snapshot (t={} us) ===", self.timestamp);
         self.statistics.print_summary();
         println!("Allocated blocks: {}", self.handover_info.allocated_count);
         println!("============================");
@@ -549,7 +709,7 @@ impl SnapshotComparison {
     /// 打印比较结果
     pub fn print(&self) {
         println!("=== Snapshot Comparison ===");
-        println!("Time delta: {} ticks", self.time_delta);
+        println!("Time delta: {} us", self.time_delta);
         println!("Allocations: +{}", self.alloc_delta);
         println!("Deallocations: +{}", self.dealloc_delta);
         println!("Size change: {:+} bytes", self.size_delta);
@@ -567,12 +727,6 @@ impl SnapshotComparison {
     }
 }
 
-/// 获取简单时间戳
-fn get_timestamp() -> u64 {
-    static COUNTER: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
-    COUNTER.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
-}
-
 /// 便捷宏定义
 #[macro_export]
 macro_rules! alloc_with_purpose {
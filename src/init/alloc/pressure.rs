@@ -0,0 +1,99 @@
+// 内存压力回调
+// 子系统 (如块缓冲缓存) 注册回收器；分配失败时依次调用回收器释放可回收的内存，然后重试分配。
+// 回收器在分配路径上被调用，因此不得阻塞等待I/O，也不应再分配内存。
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use super::AllocError;
+use crate::debug_print;
+
+/// 最多可注册的回收器数量
+const MAX_SHRINKERS: usize = 8;
+
+/// 内存回收器
+pub struct Shrinker {
+    /// 名称，用于注销和打印
+    pub name: &'static str,
+    /// 返回当前可回收的字节数
+    pub count: fn() -> usize,
+    /// 尝试回收至少指定字节数的内存，返回实际回收的字节数
+    pub scan: fn(usize) -> usize,
+}
+
+static SHRINKERS: Mutex<[Option<&'static Shrinker>; MAX_SHRINKERS]> = Mutex::new([None; MAX_SHRINKERS]);
+/// 防止回收器内部的分配失败再次触发回收
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+static RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+/// 内存压力统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PressureStats {
+    /// 触发回收的次数
+    pub events: usize,
+    /// 累计回收的字节数
+    pub reclaimed: usize,
+}
+
+/// 注册回收器
+pub fn register_shrinker(shrinker: &'static Shrinker) -> Result<(), AllocError> {
+    let mut shrinkers = SHRINKERS.lock();
+    if shrinkers.iter().flatten().any(|s| s.name == shrinker.name) {
+        return Err(AllocError::InvalidParameter);
+    }
+    let slot = shrinkers.iter_mut().find(|s| s.is_none()).ok_or(AllocError::OutOfMemory)?;
+    *slot = Some(shrinker);
+    Ok(())
+}
+
+/// 按名称注销回收器
+pub fn unregister_shrinker(name: &str) -> Result<(), AllocError> {
+    let mut shrinkers = SHRINKERS.lock();
+    let slot = shrinkers
+        .iter_mut()
+        .find(|s| s.is_some_and(|s| s.name == name))
+        .ok_or(AllocError::InvalidParameter)?;
+    *slot = None;
+    Ok(())
+}
+
+/// 所有回收器当前可回收的字节数之和
+pub fn reclaimable() -> usize {
+    let shrinkers = *SHRINKERS.lock();
+    shrinkers.iter().flatten().map(|s| (s.count)()).sum()
+}
+
+/// 依次调用回收器，直到回收了至少`bytes`字节或所有回收器都已调用
+///
+/// # 返回值
+/// 实际回收的字节数；回收进行中时 (重入) 返回0
+pub fn reclaim(bytes: usize) -> usize {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    // 复制出列表后释放锁，回收器可以注册或注销回收器
+    let shrinkers = *SHRINKERS.lock();
+    let mut freed = 0;
+    for shrinker in shrinkers.iter().flatten() {
+        if freed >= bytes {
+            break;
+        }
+        let got = (shrinker.scan)(bytes - freed);
+        if got > 0 {
+            debug_print!("Memory pressure: {} released {} bytes", shrinker.name, got);
+        }
+        freed += got;
+    }
+    EVENTS.fetch_add(1, Ordering::Relaxed);
+    RECLAIMED.fetch_add(freed, Ordering::Relaxed);
+    RECLAIMING.store(false, Ordering::Release);
+    freed
+}
+
+/// 获取内存压力统计
+pub fn stats() -> PressureStats {
+    PressureStats {
+        events: EVENTS.load(Ordering::Relaxed),
+        reclaimed: RECLAIMED.load(Ordering::Relaxed),
+    }
+}
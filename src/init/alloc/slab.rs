@@ -0,0 +1,558 @@
+// 对象缓存 (slab)：固定大小内核对象的分配
+//
+// 每个`SlabCache<T>`只分配一种类型的对象。缓存从堆上按页申请slab，每个slab开头是`SlabHeader`，
+// 其后切分为等大的对象槽，空闲槽串成slab内的单链表。有空闲槽的slab在缓存的部分链表上，
+// 分配取链表头slab的第一个空闲槽，释放由对象地址按页对齐找回所在slab，两者都是O(1)，
+// 不经过主堆的空闲链表，也不在主堆上留下大量小块。
+//
+// 全空的slab留在缓存中供后续分配复用，由`shrink`或内存压力回收器归还给主堆。
+// 用`register`登记的缓存出现在`caches`、状态打印与debugfs中，并参与内存压力回收。
+
+use core::alloc::Layout;
+use core::any::type_name;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicU32, Ordering};
+use super::allocator::AllocError;
+use super::handover::AllocPurpose;
+use super::pressure::{self, Shrinker};
+use crate::{error_print, println};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// slab的大小，也是其对齐
+pub const SLAB_SIZE: usize = 4096;
+/// 每个slab至少容纳的对象数，更大的对象不适合用slab缓存
+pub const MIN_OBJECTS: usize = 8;
+
+/// 缓存编号，写入slab头以识别外来指针；0表示尚未分配slab
+static NEXT_CACHE_ID: AtomicU32 = AtomicU32::new(1);
+
+/// 已登记的缓存
+static CACHES: Mutex<Vec<&'static dyn Cache>> = Mutex::new(Vec::new());
+
+static SLAB_SHRINKER: Shrinker = Shrinker {
+    name: "slab",
+    count: reclaimable,
+    scan: reclaim,
+};
+
+/// 位于每个slab开头
+#[repr(C)]
+struct SlabHeader {
+    next: *mut SlabHeader,
+    prev: *mut SlabHeader,
+    /// slab内空闲槽的单链表
+    free: *mut FreeSlot,
+    in_use: usize,
+    cache_id: u32,
+}
+
+/// 空闲槽的链表节点，占用槽的开头
+struct FreeSlot {
+    next: *mut FreeSlot,
+}
+
+/// 缓存的统计信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    /// 对象的类型名
+    pub name: &'static str,
+    /// 每个对象槽的字节数
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    /// 没有对象的slab，可以归还给主堆
+    pub empty_slabs: usize,
+    pub in_use: usize,
+    pub peak_in_use: usize,
+    pub total_allocs: u64,
+    pub total_frees: u64,
+    pub failed_allocs: u64,
+    /// 归还给主堆的slab数
+    pub released_slabs: u64,
+}
+
+impl SlabStats {
+    /// 缓存占用的堆内存
+    pub fn bytes(&self) -> usize {
+        self.slabs * SLAB_SIZE
+    }
+}
+
+struct SlabInner {
+    /// 有空闲槽的slab (包括全空的slab)
+    partial: *mut SlabHeader,
+    /// 没有空闲槽的slab
+    full: *mut SlabHeader,
+    cache_id: u32,
+    slabs: usize,
+    empty_slabs: usize,
+    in_use: usize,
+    peak_in_use: usize,
+    total_allocs: u64,
+    total_frees: u64,
+    failed_allocs: u64,
+    released_slabs: u64,
+}
+
+// slab链表只在持有缓存的锁时访问
+unsafe impl Send for SlabInner {}
+
+impl SlabInner {
+    const fn new() -> Self {
+        Self {
+            partial: ptr::null_mut(),
+            full: ptr::null_mut(),
+            cache_id: 0,
+            slabs: 0,
+            empty_slabs: 0,
+            in_use: 0,
+            peak_in_use: 0,
+            total_allocs: 0,
+            total_frees: 0,
+            failed_allocs: 0,
+            released_slabs: 0,
+        }
+    }
+
+    unsafe fn push(list: &mut *mut SlabHeader, slab: *mut SlabHeader) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = *list;
+        if !(*list).is_null() {
+            (**list).prev = slab;
+        }
+        *list = slab;
+    }
+
+    unsafe fn unlink(list: &mut *mut SlabHeader, slab: *mut SlabHeader) {
+        if (*slab).prev.is_null() {
+            *list = (*slab).next;
+        } else {
+            (*(*slab).prev).next = (*slab).next;
+        }
+        if !(*slab).next.is_null() {
+            (*(*slab).next).prev = (*slab).prev;
+        }
+    }
+
+    /// 从部分链表摘下全空的slab，最多`max`个
+    fn take_empty(&mut self, max: usize) -> Vec<*mut SlabHeader> {
+        let mut empty = Vec::new();
+        let mut slab = self.partial;
+        while !slab.is_null() && empty.len() < max {
+            unsafe {
+                let next = (*slab).next;
+                if (*slab).in_use == 0 {
+                    Self::unlink(&mut self.partial, slab);
+                    empty.push(slab);
+                }
+                slab = next;
+            }
+        }
+        self.slabs -= empty.len();
+        self.empty_slabs -= empty.len();
+        self.released_slabs += empty.len() as u64;
+        empty
+    }
+}
+
+/// 类型`T`的对象缓存
+pub struct SlabCache<T> {
+    inner: Mutex<SlabInner>,
+    _marker: PhantomData<T>,
+}
+
+// 缓存只交出`T`的所有权，不共享`T`
+unsafe impl<T: Send> Sync for SlabCache<T> {}
+unsafe impl<T: Send> Send for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+    /// 对象槽的对齐
+    const SLOT_ALIGN: usize = if mem::align_of::<T>() > mem::align_of::<FreeSlot>() {
+        mem::align_of::<T>()
+    } else {
+        mem::align_of::<FreeSlot>()
+    };
+    /// 对象槽的大小，至少容纳空闲链表节点
+    const SLOT_SIZE: usize = {
+        let size = if mem::size_of::<T>() > mem::size_of::<FreeSlot>() {
+            mem::size_of::<T>()
+        } else {
+            mem::size_of::<FreeSlot>()
+        };
+        size.next_multiple_of(Self::SLOT_ALIGN)
+    };
+    /// 第一个对象槽在slab内的偏移
+    const FIRST_SLOT: usize = mem::size_of::<SlabHeader>().next_multiple_of(Self::SLOT_ALIGN);
+    const OBJECTS_PER_SLAB: usize = (SLAB_SIZE - Self::FIRST_SLOT) / Self::SLOT_SIZE;
+
+    /// 创建空缓存，不分配内存
+    pub const fn new() -> Self {
+        const {
+            assert!(
+                Self::FIRST_SLOT < SLAB_SIZE && Self::OBJECTS_PER_SLAB >= MIN_OBJECTS,
+                "type is too large for a slab cache"
+            )
+        };
+        Self { inner: Mutex::new(SlabInner::new()), _marker: PhantomData }
+    }
+
+    /// 缓存的名称，即对象的类型名
+    pub fn name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    /// 分配一个对象并放入`value`
+    pub fn alloc(&self, value: T) -> Result<SlabBox<'_, T>, AllocError> {
+        let ptr = self.alloc_raw().ok_or(AllocError::OutOfMemory)?;
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(SlabBox { cache: self, ptr })
+    }
+
+    /// 分配一个未初始化的对象槽
+    pub fn alloc_raw(&self) -> Option<NonNull<T>> {
+        let mut inner = self.inner.lock();
+        if inner.partial.is_null() && !self.grow(&mut inner) {
+            inner.failed_allocs += 1;
+            return None;
+        }
+        let slab = inner.partial;
+        unsafe {
+            let slot = (*slab).free;
+            (*slab).free = (*slot).next;
+            if (*slab).in_use == 0 {
+                inner.empty_slabs -= 1;
+            }
+            (*slab).in_use += 1;
+            if (*slab).free.is_null() {
+                SlabInner::unlink(&mut inner.partial, slab);
+                SlabInner::push(&mut inner.full, slab);
+            }
+            inner.in_use += 1;
+            inner.peak_in_use = inner.peak_in_use.max(inner.in_use);
+            inner.total_allocs += 1;
+            NonNull::new(slot.cast())
+        }
+    }
+
+    /// 把对象槽还给缓存，不运行`T`的析构
+    ///
+    /// 不属于本缓存的指针返回`InvalidPointer`。
+    ///
+    /// # Safety
+    /// `ptr`所在的页必须可读 (指针来自任一缓存即满足)；来自本缓存时必须尚未释放。
+    pub unsafe fn free_raw(&self, ptr: NonNull<T>) -> Result<(), AllocError> {
+        let addr = ptr.as_ptr() as usize;
+        let slab = (addr & !(SLAB_SIZE - 1)) as *mut SlabHeader;
+        let offset = addr - slab as usize;
+        let mut inner = self.inner.lock();
+        if inner.cache_id == 0
+            || (*slab).cache_id != inner.cache_id
+            || offset < Self::FIRST_SLOT
+            || !(offset - Self::FIRST_SLOT).is_multiple_of(Self::SLOT_SIZE)
+            || (offset - Self::FIRST_SLOT) / Self::SLOT_SIZE >= Self::OBJECTS_PER_SLAB
+        {
+            return Err(AllocError::InvalidPointer);
+        }
+        if (*slab).free.is_null() {
+            SlabInner::unlink(&mut inner.full, slab);
+            SlabInner::push(&mut inner.partial, slab);
+        }
+        let slot = addr as *mut FreeSlot;
+        (*slot).next = (*slab).free;
+        (*slab).free = slot;
+        (*slab).in_use -= 1;
+        if (*slab).in_use == 0 {
+            inner.empty_slabs += 1;
+        }
+        inner.in_use -= 1;
+        inner.total_frees += 1;
+        Ok(())
+    }
+
+    /// 统计信息
+    pub fn stats(&self) -> SlabStats {
+        let inner = self.inner.lock();
+        self.stats_of(&inner)
+    }
+
+    /// 把全空的slab归还给主堆，返回释放的字节数
+    pub fn shrink(&self) -> usize {
+        let empty = self.inner.lock().take_empty(usize::MAX);
+        Self::release(empty)
+    }
+
+    /// 登记缓存，使其出现在`caches`中并参与内存压力回收
+    pub fn register(&'static self) -> Result<(), AllocError>
+    where
+        T: Send,
+    {
+        let mut caches = CACHES.lock();
+        let cache: &'static dyn Cache = self;
+        if caches.iter().any(|c| ptr::addr_eq(*c, cache)) {
+            return Err(AllocError::AlreadyInitialized);
+        }
+        if caches.is_empty() {
+            // 回收器可能已由先前的登记注册
+            let _ = pressure::register_shrinker(&SLAB_SHRINKER);
+        }
+        caches.push(cache);
+        Ok(())
+    }
+
+    fn stats_of(&self, inner: &SlabInner) -> SlabStats {
+        SlabStats {
+            name: self.name(),
+            object_size: Self::SLOT_SIZE,
+            objects_per_slab: Self::OBJECTS_PER_SLAB,
+            slabs: inner.slabs,
+            empty_slabs: inner.empty_slabs,
+            in_use: inner.in_use,
+            peak_in_use: inner.peak_in_use,
+            total_allocs: inner.total_allocs,
+            total_frees: inner.total_frees,
+            failed_allocs: inner.failed_allocs,
+            released_slabs: inner.released_slabs,
+        }
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    /// 申请一个新slab放到部分链表上
+    fn grow(&self, inner: &mut SlabInner) -> bool {
+        let base = unsafe { alloc::alloc::alloc(Self::layout()) };
+        if base.is_null() {
+            return false;
+        }
+        // 宿主机测试中早期分配器未初始化，用途无处记录
+        let _ = super::set_purpose(base, AllocPurpose::KernelHeap);
+        if inner.cache_id == 0 {
+            inner.cache_id = NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let slab = base as *mut SlabHeader;
+        unsafe {
+            // 空闲槽按地址顺序串起，先分配的对象靠前
+            let mut free = ptr::null_mut();
+            for index in (0..Self::OBJECTS_PER_SLAB).rev() {
+                let slot = base.add(Self::FIRST_SLOT + index * Self::SLOT_SIZE) as *mut FreeSlot;
+                (*slot).next = free;
+                free = slot;
+            }
+            slab.write(SlabHeader {
+                next: ptr::null_mut(),
+                prev: ptr::null_mut(),
+                free,
+                in_use: 0,
+                cache_id: inner.cache_id,
+            });
+            SlabInner::push(&mut inner.partial, slab);
+        }
+        inner.slabs += 1;
+        inner.empty_slabs += 1;
+        true
+    }
+
+    fn release(slabs: Vec<*mut SlabHeader>) -> usize {
+        for &slab in &slabs {
+            unsafe { alloc::alloc::dealloc(slab.cast(), Self::layout()) };
+        }
+        slabs.len() * SLAB_SIZE
+    }
+}
+
+impl<T> Default for SlabCache<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for SlabCache<T> {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        if inner.in_use > 0 {
+            // 对象仍在使用，slab不能归还
+            error_print!("Slab cache {} dropped with {} live objects", type_name::<T>(), inner.in_use);
+            return;
+        }
+        let empty = inner.take_empty(usize::MAX);
+        Self::release(empty);
+    }
+}
+
+/// 已登记缓存的公共接口，供统计与回收遍历
+trait Cache: Sync {
+    fn stats(&self) -> SlabStats;
+    /// 可立即归还的字节数；缓存正被使用时为0
+    fn reclaimable(&self) -> usize;
+    /// 归还全空的slab直到至少`bytes`字节，缓存正被使用时不等待
+    fn reclaim(&self, bytes: usize) -> usize;
+}
+
+impl<T: Send> Cache for SlabCache<T> {
+    fn stats(&self) -> SlabStats {
+        SlabCache::stats(self)
+    }
+
+    fn reclaimable(&self) -> usize {
+        self.inner.try_lock().map_or(0, |inner| inner.empty_slabs * SLAB_SIZE)
+    }
+
+    fn reclaim(&self, bytes: usize) -> usize {
+        // 回收发生在分配失败的路径上，可能正是本缓存在申请slab
+        let Some(mut inner) = self.inner.try_lock() else {
+            return 0;
+        };
+        let empty = inner.take_empty(bytes.div_ceil(SLAB_SIZE));
+        drop(inner);
+        Self::release(empty)
+    }
+}
+
+/// 缓存分配的对象，离开作用域时析构并归还
+pub struct SlabBox<'a, T> {
+    cache: &'a SlabCache<T>,
+    ptr: NonNull<T>,
+}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            let _ = self.cache.free_raw(self.ptr);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// 列出已登记缓存的统计信息，按登记顺序
+pub fn caches() -> Vec<SlabStats> {
+    let caches: Vec<&'static dyn Cache> = CACHES.lock().clone();
+    caches.into_iter().map(|cache| cache.stats()).collect()
+}
+
+/// 已登记缓存中可归还的字节数
+fn reclaimable() -> usize {
+    CACHES.try_lock().map_or(0, |caches| caches.iter().map(|cache| cache.reclaimable()).sum())
+}
+
+/// 内存压力回收：依次归还各缓存全空的slab
+fn reclaim(bytes: usize) -> usize {
+    let Some(caches) = CACHES.try_lock() else {
+        return 0;
+    };
+    let mut freed = 0;
+    for cache in caches.iter() {
+        if freed >= bytes {
+            break;
+        }
+        freed += cache.reclaim(bytes - freed);
+    }
+    freed
+}
+
+/// 打印已登记缓存的使用情况
+pub fn print_report() {
+    let caches = caches();
+    if caches.is_empty() {
+        return;
+    }
+    println!("=== Slab caches ===");
+    for cache in caches {
+        println!("  {:<32} {:>4} B x {:>3}/slab, {:>3} slabs ({} empty), {} in use, peak {}, {} failed",
+                 cache.name, cache.object_size, cache.objects_per_slab, cache.slabs, cache.empty_slabs,
+                 cache.in_use, cache.peak_in_use, cache.failed_allocs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Node {
+        key: u64,
+        value: [u32; 10],
+    }
+
+    #[test]
+    fn objects_fill_slabs_and_come_back() {
+        let cache = SlabCache::<Node>::new();
+        let per_slab = cache.stats().objects_per_slab;
+        assert_eq!(cache.stats().object_size, 48);
+        let nodes: Vec<_> = (0..per_slab as u64 + 1)
+            .map(|key| cache.alloc(Node { key, value: [key as u32; 10] }).unwrap())
+            .collect();
+        let stats = cache.stats();
+        assert_eq!((stats.slabs, stats.empty_slabs, stats.in_use), (2, 0, per_slab + 1));
+        assert!(nodes.iter().enumerate().all(|(i, node)| node.key == i as u64));
+        assert_eq!(&*nodes[0] as *const Node as usize % SLAB_SIZE, mem::size_of::<SlabHeader>().next_multiple_of(8));
+
+        drop(nodes);
+        let stats = cache.stats();
+        assert_eq!((stats.in_use, stats.empty_slabs, stats.peak_in_use), (0, 2, per_slab + 1));
+        assert_eq!((stats.total_allocs, stats.total_frees), (per_slab as u64 + 1, per_slab as u64 + 1));
+        assert_eq!(cache.shrink(), 2 * SLAB_SIZE);
+        assert_eq!(cache.stats().slabs, 0);
+        assert_eq!(cache.stats().released_slabs, 2);
+    }
+
+    #[test]
+    fn freed_slots_are_reused_first() {
+        let cache = SlabCache::<u64>::new();
+        let a = cache.alloc_raw().unwrap();
+        let b = cache.alloc_raw().unwrap();
+        unsafe { cache.free_raw(a).unwrap() };
+        assert_eq!(cache.alloc_raw(), Some(a));
+        unsafe {
+            cache.free_raw(a).unwrap();
+            cache.free_raw(b).unwrap();
+        }
+        assert_eq!(cache.stats().slabs, 1);
+    }
+
+    #[test]
+    fn foreign_pointers_are_rejected() {
+        let cache = SlabCache::<u64>::new();
+        let other = SlabCache::<u64>::new();
+        let mut outside = alloc::boxed::Box::new([0u64; 1024]);
+        let value = cache.alloc(7).unwrap();
+        let foreign = other.alloc_raw().unwrap();
+        unsafe {
+            assert_eq!(cache.free_raw(foreign), Err(AllocError::InvalidPointer));
+            let inside = NonNull::from(&*value).cast::<u8>().add(4).cast::<u64>();
+            assert_eq!(cache.free_raw(inside), Err(AllocError::InvalidPointer));
+            // 缓冲区为8KB，其中必有一个页边界，其后的“slab头”全为0
+            let page = (outside.as_mut_ptr() as usize).next_multiple_of(SLAB_SIZE);
+            assert_eq!(cache.free_raw(NonNull::new((page + 64) as *mut u64).unwrap()), Err(AllocError::InvalidPointer));
+            other.free_raw(foreign).unwrap();
+        }
+        assert_eq!(*value, 7);
+        assert_eq!(cache.stats().in_use, 1);
+    }
+}
@@ -0,0 +1,247 @@
+// 子堆：从主堆划出的独立命名堆
+//
+// 子系统可以用`create`从主分配器划出一块固定大小的区域，在其中运行一个独立的`EarlyAllocator`。
+// 区域的大小就是子堆的配额：子堆用尽时只有该子系统的分配失败，不会挤占主堆与其他子系统。
+// 每个子堆有自己的统计信息与完整性检查，块头损坏只会波及同一子堆，
+// 泄漏与损坏都能按子堆名称归属到子系统。
+//
+// 子堆实现`core::alloc::Allocator`，集合以`Vec::new_in(heap)`、`BTreeMap::new_in(heap)`
+// 把元素放进子堆。子堆创建后一直存在，登记在全局列表中，供`heaps`与`check_all`遍历。
+
+use core::alloc::{AllocError as LayoutAllocError, Allocator, Layout};
+use core::ptr::{self, NonNull};
+use super::allocator::{AllocError, EarlyAllocator};
+use super::handover::AllocPurpose;
+use super::metadata::AllocStats;
+use super::GLOBAL_EARLY_ALLOCATOR;
+use crate::{error_print, info_print, println};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// 子堆区域的对齐，与主堆相同
+const REGION_ALIGN: usize = 16;
+
+/// 已创建的子堆
+static HEAPS: Mutex<Vec<&'static SubHeap>> = Mutex::new(Vec::new());
+
+/// 一个命名的子堆
+pub struct SubHeap {
+    name: &'static str,
+    base: usize,
+    size: usize,
+    allocator: Mutex<EarlyAllocator>,
+}
+
+/// 子堆的概况，见`heaps`
+#[derive(Debug, Clone)]
+pub struct SubHeapInfo {
+    pub name: &'static str,
+    pub base: usize,
+    pub size: usize,
+    pub stats: AllocStats,
+}
+
+impl SubHeap {
+    /// 在`[base, base + size)`上建立子堆，这段内存此后归子堆所有
+    ///
+    /// # Safety
+    /// 内存必须可写、按`REGION_ALIGN`对齐，且在子堆存在期间不作他用。
+    pub unsafe fn from_region(name: &'static str, base: usize, size: usize) -> Result<Self, AllocError> {
+        if !base.is_multiple_of(REGION_ALIGN) {
+            return Err(AllocError::InvalidAlignment);
+        }
+        let allocator = EarlyAllocator::new(base, size)?;
+        Ok(Self { name, base, size, allocator: Mutex::new(allocator) })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// 子堆的配额，即区域的大小
+    pub fn quota(&self) -> usize {
+        self.size
+    }
+
+    /// 判断`ptr`是否落在子堆的区域内
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        (self.base..self.base + self.size).contains(&(ptr as usize))
+    }
+
+    /// 分配`layout`描述的内存，配额用尽时返回None
+    pub fn alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.allocator.lock().alloc_aligned(layout.size(), layout.align())
+    }
+
+    /// 释放子堆分配的内存
+    pub fn dealloc(&self, ptr: NonNull<u8>) -> Result<(), AllocError> {
+        if !self.contains(ptr.as_ptr()) {
+            return Err(AllocError::InvalidPointer);
+        }
+        self.allocator.lock().dealloc(ptr)
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        self.allocator.lock().stats()
+    }
+
+    /// 检查子堆内全部块头
+    pub fn integrity_check(&self) -> Result<(), AllocError> {
+        self.allocator.lock().integrity_check()
+    }
+
+    pub fn info(&self) -> SubHeapInfo {
+        SubHeapInfo { name: self.name, base: self.base, size: self.size, stats: self.stats() }
+    }
+}
+
+unsafe impl Allocator for SubHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, LayoutAllocError> {
+        if layout.size() == 0 {
+            let dangling = NonNull::new(ptr::without_provenance_mut(layout.align())).ok_or(LayoutAllocError)?;
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+        let ptr = self.alloc(layout).ok_or(LayoutAllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        if let Err(e) = self.dealloc(ptr) {
+            error_print!("Sub-heap '{}': deallocation failed: {:?}, ptr=0x{:x}, size={}",
+                         self.name, e, ptr.as_ptr() as usize, layout.size());
+        }
+    }
+}
+
+/// 从主堆划出`quota`字节，创建名为`name`的子堆
+///
+/// 名称已被使用时返回`AlreadyInitialized`，主堆无法提供区域时返回`OutOfMemory`。
+pub fn create(name: &'static str, quota: usize) -> Result<&'static SubHeap, AllocError> {
+    if !super::is_initialized() {
+        return Err(AllocError::NotInitialized);
+    }
+    let mut heaps = HEAPS.lock();
+    if heaps.iter().any(|heap| heap.name == name) {
+        return Err(AllocError::AlreadyInitialized);
+    }
+    let quota = (quota + REGION_ALIGN - 1) & !(REGION_ALIGN - 1);
+    let region = GLOBAL_EARLY_ALLOCATOR.alloc_aligned_raw(quota, REGION_ALIGN).ok_or(AllocError::OutOfMemory)?;
+    // 区域在主堆看来是一个普通的已分配块，以用途区分
+    let _ = GLOBAL_EARLY_ALLOCATOR.set_purpose(region.as_ptr(), AllocPurpose::KernelHeap);
+    let heap = match unsafe { SubHeap::from_region(name, region.as_ptr() as usize, quota) } {
+        Ok(heap) => heap,
+        Err(e) => {
+            let _ = GLOBAL_EARLY_ALLOCATOR.dealloc_raw(region);
+            return Err(e);
+        }
+    };
+    let heap: &'static SubHeap = Box::leak(Box::new(heap));
+    heaps.push(heap);
+    info_print!("Sub-heap '{}' created at 0x{:x} ({} KB)", name, heap.base, quota / 1024);
+    Ok(heap)
+}
+
+/// 按名称查找子堆
+pub fn get(name: &str) -> Option<&'static SubHeap> {
+    HEAPS.lock().iter().find(|heap| heap.name == name).copied()
+}
+
+/// 返回指针所在的子堆，用于把损坏或泄漏的块归属到子系统
+pub fn owner_of(ptr: *const u8) -> Option<&'static SubHeap> {
+    HEAPS.lock().iter().find(|heap| heap.contains(ptr)).copied()
+}
+
+/// 列出全部子堆，按创建顺序
+pub fn heaps() -> Vec<SubHeapInfo> {
+    let heaps: Vec<&'static SubHeap> = HEAPS.lock().clone();
+    heaps.into_iter().map(SubHeap::info).collect()
+}
+
+/// 检查全部子堆，返回第一个未通过检查的子堆名称与错误
+pub fn check_all() -> Result<(), (&'static str, AllocError)> {
+    let heaps: Vec<&'static SubHeap> = HEAPS.lock().clone();
+    for heap in heaps {
+        heap.integrity_check().map_err(|e| (heap.name, e))?;
+    }
+    Ok(())
+}
+
+/// 打印各子堆的配额与使用情况
+pub fn print_report() {
+    let heaps = heaps();
+    if heaps.is_empty() {
+        return;
+    }
+    println!("=== Sub-heaps ===");
+    for heap in heaps {
+        println!("  {:<12} quota {:>6} KB, used {:>6} KB, peak {:>6} KB, {} live, {} failed",
+                 heap.name, heap.size / 1024, heap.stats.used_size / 1024, heap.stats.peak_used_size / 1024,
+                 heap.stats.alloc_count, heap.stats.failed_allocs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use core::mem;
+
+    const HEAP_SIZE: usize = 16 * 1024;
+
+    /// 在宿主机内存上建立子堆，返回的缓冲区必须比子堆活得更久
+    fn heap(name: &'static str) -> (Vec<u128>, SubHeap) {
+        let mut buffer = alloc::vec![0u128; HEAP_SIZE / mem::size_of::<u128>()];
+        let heap = unsafe { SubHeap::from_region(name, buffer.as_mut_ptr() as usize, HEAP_SIZE) }.unwrap();
+        (buffer, heap)
+    }
+
+    #[test]
+    fn collections_live_in_the_sub_heap() {
+        let (_buffer, heap) = heap("trap");
+        {
+            let mut map = BTreeMap::new_in(&heap);
+            for i in 0..64u32 {
+                map.insert(i, i * 2);
+            }
+            let mut list = Vec::with_capacity_in(32, &heap);
+            list.extend(0..32u64);
+            assert!(heap.contains(list.as_ptr().cast()));
+            assert_eq!(map.get(&10), Some(&20));
+            assert!(heap.stats().alloc_count > 1);
+            heap.integrity_check().unwrap();
+        }
+        let stats = heap.stats();
+        assert_eq!(stats.alloc_count, 0);
+        assert_eq!(stats.free_size, HEAP_SIZE);
+    }
+
+    #[test]
+    fn quota_is_independent() {
+        let (_buffer, heap) = heap("small");
+        let mut list: Vec<u8, &SubHeap> = Vec::new_in(&heap);
+        assert!(list.try_reserve_exact(HEAP_SIZE).is_err());
+        assert!(list.try_reserve_exact(HEAP_SIZE / 2).is_ok());
+        assert_eq!(heap.stats().failed_allocs, 1);
+        let mut outside = 0u64;
+        assert_eq!(heap.dealloc(NonNull::from(&mut outside).cast()), Err(AllocError::InvalidPointer));
+    }
+
+    #[test]
+    fn corruption_stays_in_its_heap() {
+        let (_a_buffer, a) = heap("a");
+        let (_b_buffer, b) = heap("b");
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = a.alloc(layout).unwrap();
+        let kept = b.alloc(layout).unwrap();
+        // 越过分配的末尾写坏下一个块头
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0xee, 64 + 16) };
+        assert_eq!(a.integrity_check(), Err(AllocError::CorruptedHeader));
+        b.integrity_check().unwrap();
+        b.dealloc(kept).unwrap();
+        assert_eq!(a.name(), "a");
+    }
+}
@@ -0,0 +1,236 @@
+// nt_rustos/src/init/initcall.rs
+
+//! # Initcalls
+//!
+//! Subsystems register their initialization with `initcall!` instead of
+//! being called from one long boot function. Each initcall is a static
+//! `Initcall` placed in a linker section named after its level and order,
+//! `.initcall.<level>.<order>`; the linker script sorts these sections by
+//! name and collects them between `sinitcall` and `einitcall`.
+//!
+//! `run_all` runs the levels in order:
+//!
+//! - `early`: platform description, clocks and the heap. Nothing may
+//!   allocate before the heap initcall has run.
+//! - `arch`: paging and traps.
+//! - `subsys`: core subsystems such as tasks, filesystems and the network
+//!   stack.
+//! - `device`: device probing and what needs the probed devices.
+//! - `late`: reports and self-checks once everything is up.
+//!
+//! Within a level, initcalls run by increasing order, a two-digit number
+//! from 10 to 99, and in link order when orders are equal. Each call is
+//! timed; a failing initcall is reported and the boot goes on with the
+//! next one.
+
+use crate::{error_print, info_print, println, time};
+use alloc::string::String;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// What an initcall returns: an error message if it failed.
+pub type InitResult = Result<(), String>;
+
+/// Initcall levels, in the order they run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Early,
+    Arch,
+    Subsys,
+    Device,
+    Late,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [Level::Early, Level::Arch, Level::Subsys, Level::Device, Level::Late];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Early => "early",
+            Level::Arch => "arch",
+            Level::Subsys => "subsys",
+            Level::Device => "device",
+            Level::Late => "late",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Outcome of an initcall.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    NotRun,
+    Done,
+    Failed,
+}
+
+const STATE_NOT_RUN: u8 = 0;
+const STATE_DONE: u8 = 1;
+const STATE_FAILED: u8 = 2;
+
+/// A registered initialization function, with its outcome once run.
+pub struct Initcall {
+    name: &'static str,
+    level: Level,
+    func: fn() -> InitResult,
+    state: AtomicU8,
+    duration_us: AtomicU64,
+}
+
+impl Initcall {
+    /// Used by `initcall!`.
+    pub const fn new(name: &'static str, level: Level, func: fn() -> InitResult) -> Self {
+        Self {
+            name,
+            level,
+            func,
+            state: AtomicU8::new(STATE_NOT_RUN),
+            duration_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the path of the initcall's function.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn state(&self) -> State {
+        match self.state.load(Ordering::Acquire) {
+            STATE_DONE => State::Done,
+            STATE_FAILED => State::Failed,
+            _ => State::NotRun,
+        }
+    }
+
+    /// Returns how long the call took, in microseconds.
+    pub fn duration_us(&self) -> u64 {
+        self.duration_us.load(Ordering::Relaxed)
+    }
+
+    fn run(&self) -> InitResult {
+        let start = time::monotonic_us();
+        let result = (self.func)();
+        self.duration_us.store(time::monotonic_us().saturating_sub(start), Ordering::Relaxed);
+        let state = if result.is_ok() { STATE_DONE } else { STATE_FAILED };
+        self.state.store(state, Ordering::Release);
+        result
+    }
+}
+
+/// Registers `$func`, a `fn() -> InitResult`, to run at `$level` with the
+/// two-digit order `$order`.
+///
+/// ```ignore
+/// crate::initcall!(subsys, 30, register_syscalls);
+/// ```
+#[macro_export]
+macro_rules! initcall {
+    (early, $order:literal, $func:path) => {
+        $crate::initcall!(@register "0", Early, $order, $func);
+    };
+    (arch, $order:literal, $func:path) => {
+        $crate::initcall!(@register "1", Arch, $order, $func);
+    };
+    (subsys, $order:literal, $func:path) => {
+        $crate::initcall!(@register "2", Subsys, $order, $func);
+    };
+    (device, $order:literal, $func:path) => {
+        $crate::initcall!(@register "3", Device, $order, $func);
+    };
+    (late, $order:literal, $func:path) => {
+        $crate::initcall!(@register "4", Late, $order, $func);
+    };
+    (@register $section:literal, $level:ident, $order:literal, $func:path) => {
+        const _: () = {
+            // Section names sort as strings, so orders need two digits.
+            assert!($order >= 10 && $order <= 99, "initcall order must be from 10 to 99");
+
+            #[used]
+            #[link_section = concat!(".initcall.", $section, ".", $order)]
+            static INITCALL: $crate::init::initcall::Initcall = $crate::init::initcall::Initcall::new(
+                concat!(module_path!(), "::", stringify!($func)),
+                $crate::init::initcall::Level::$level,
+                $func,
+            );
+        };
+    };
+}
+
+/// Returns every registered initcall, in the order they run within a
+/// level.
+#[cfg(not(feature = "host-test"))]
+pub fn initcalls() -> &'static [Initcall] {
+    extern "C" {
+        static sinitcall: u8;
+        static einitcall: u8;
+    }
+    unsafe {
+        let start = core::ptr::addr_of!(sinitcall).cast::<Initcall>();
+        let end = core::ptr::addr_of!(einitcall).cast::<Initcall>();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Host tests have no linker script to collect the initcalls, and run none.
+#[cfg(feature = "host-test")]
+pub fn initcalls() -> &'static [Initcall] {
+    &[]
+}
+
+/// Initcalls run and failed, and the time they took.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub run: usize,
+    pub failed: usize,
+    pub duration_us: u64,
+}
+
+/// Runs the initcalls of `level` that have not run yet.
+pub fn run_level(level: Level) -> Summary {
+    let mut summary = Summary::default();
+    for call in initcalls().iter().filter(|c| c.level == level && c.state() == State::NotRun) {
+        let result = call.run();
+        summary.run += 1;
+        summary.duration_us += call.duration_us();
+        if let Err(e) = result {
+            summary.failed += 1;
+            error_print!("initcall {} ({}) failed after {} us: {}", call.name, level, call.duration_us(), e);
+        }
+    }
+    summary
+}
+
+/// Runs every level in order.
+pub fn run_all() -> Summary {
+    let mut total = Summary::default();
+    for level in Level::ALL {
+        let summary = run_level(level);
+        total.run += summary.run;
+        total.failed += summary.failed;
+        total.duration_us += summary.duration_us;
+    }
+    info_print!("Initcalls: {} run, {} failed, {} ms.", total.run, total.failed, total.duration_us / 1000);
+    total
+}
+
+/// Prints every initcall with its level, outcome and duration.
+pub fn print_report() {
+    println!("Level   State    Time(us)  Initcall");
+    for call in initcalls() {
+        let state = match call.state() {
+            State::NotRun => "-",
+            State::Done => "ok",
+            State::Failed => "FAILED",
+        };
+        println!("{:<7} {:<7} {:>9}  {}", call.level, state, call.duration_us(), call.name);
+    }
+}
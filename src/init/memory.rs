@@ -0,0 +1,164 @@
+// 早期堆的内存布局：由设备树给出的RAM区间决定堆放在哪里、有多大
+//
+// 设备树的`memory`节点 (见 platform) 给出若干RAM区间，其中有内核映像、固件保留的内存 (见 mm::guard)
+// 和引导程序放置的initrd。`plan_heap`从RAM区间中扣除这些范围，得到的每一段成为早期堆的一个区域：
+// 内核之后的第一段是首个区域 (`init`)，其余按RAM区间的顺序加入 (`add_region`)。
+//
+// 规划在堆建立之前进行，不能分配内存，所以结果是定长数组。
+
+use core::ops::Range;
+use super::alloc::MAX_REGIONS;
+use crate::util::arrayvec::ArrayVec;
+
+/// 一个堆区域的最小字节数，与`init`的下限一致
+pub const MIN_REGION_SIZE: usize = 64 * 1024;
+
+/// 一个堆区域的最大字节数，与`init`的上限一致；更大的RAM段拆成多个区域
+pub const MAX_REGION_SIZE: usize = 1024 * 1024 * 1024;
+
+/// 堆区域起点的对齐
+const REGION_ALIGN: usize = 16;
+
+/// 规划早期堆的区域
+///
+/// # 参数
+/// - `banks`: RAM区间，按设备树的顺序
+/// - `kernel`: 内核映像 (连同堆起点的随机偏移)，其后的第一段成为首个区域
+/// - `reserved`: 不能用作堆的范围
+/// - `limit`: 内核能访问的最高地址，之上的RAM不用
+/// - `budget`: 堆的总字节数上限
+///
+/// 不足`MIN_REGION_SIZE`的段被舍弃。内核之后的一段总占一个区域，其余的段超过`MAX_REGIONS - 1`个时不用。
+pub fn plan_heap(
+    banks: &[Range<usize>],
+    kernel: Range<usize>,
+    reserved: &[Range<usize>],
+    limit: usize,
+    budget: usize,
+) -> ArrayVec<Range<usize>, MAX_REGIONS> {
+    let mut pieces: ArrayVec<Range<usize>, MAX_REGIONS> = ArrayVec::new();
+    let mut primary = None;
+
+    for bank in banks {
+        let end = bank.end.min(limit);
+        let mut cursor = bank.start;
+        while cursor < end {
+            // 跳过覆盖当前位置的保留范围
+            if let Some(r) = core::iter::once(&kernel).chain(reserved).find(|r| r.contains(&cursor)) {
+                cursor = r.end;
+                continue;
+            }
+            // 到下一个保留范围为止
+            let stop = core::iter::once(&kernel)
+                .chain(reserved)
+                .map(|r| r.start)
+                .filter(|&s| s > cursor)
+                .fold(end, usize::min);
+            let mut start = cursor.next_multiple_of(REGION_ALIGN);
+            cursor = stop;
+            let stop = stop & !(REGION_ALIGN - 1);
+            while stop.saturating_sub(start) >= MIN_REGION_SIZE {
+                let piece = start..stop.min(start + MAX_REGION_SIZE);
+                start = piece.end;
+                if primary.is_none() && bank.contains(&kernel.end) && piece.start >= kernel.end {
+                    primary = Some(piece);
+                } else if pieces.len() < MAX_REGIONS - 1 {
+                    // 留一个区域给内核之后的一段
+                    let _ = pieces.push(piece);
+                }
+            }
+        }
+    }
+
+    // 内核之后的一段放在最前
+    if let Some(piece) = primary {
+        let _ = pieces.insert(0, piece);
+    }
+
+    // 按预算截断
+    let mut remaining = budget;
+    let mut kept = 0;
+    for piece in pieces.iter_mut() {
+        if remaining < MIN_REGION_SIZE {
+            break;
+        }
+        piece.end = piece.start + piece.len().min(remaining);
+        remaining -= piece.len();
+        kept += 1;
+    }
+    pieces.truncate(kept);
+    pieces
+}
+
+/// 解析字节数，接受十进制或0x开头的十六进制，可带K、M、G后缀 (不区分大小写)
+pub fn parse_size(text: &str) -> Option<usize> {
+    let (digits, shift) = match text.as_bytes().last()? {
+        b'k' | b'K' => (&text[..text.len() - 1], 10),
+        b'm' | b'M' => (&text[..text.len() - 1], 20),
+        b'g' | b'G' => (&text[..text.len() - 1], 30),
+        _ => (text, 0),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    value.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    #[test]
+    fn heap_avoids_kernel_and_reservations() {
+        let banks = [0x8000_0000..0x8000_0000 + 128 * MB, 0x1_0000_0000..0x1_0000_0000 + 64 * MB];
+        let kernel = 0x8020_0000..0x8040_0010;
+        // 固件在内核之前，initrd在内核之后
+        let reserved = [0x8000_0000..0x8020_0000, 0x8100_0000..0x8110_0000];
+
+        let plan = plan_heap(&banks, kernel.clone(), &reserved, usize::MAX, usize::MAX);
+        assert_eq!(plan.as_slice(), &[
+            0x8040_0010..0x8100_0000,
+            0x8110_0000..0x8800_0000,
+            0x1_0000_0000..0x1_0400_0000,
+        ]);
+
+        // 只用能访问的内存，总量不超过预算
+        let plan = plan_heap(&banks, kernel.clone(), &reserved, 0x1_0000_0000, 2 * MB);
+        assert_eq!(plan.as_slice(), &[0x8040_0010..0x8060_0010]);
+
+        // 太小的段被舍弃
+        let reserved = [0x8000_0000..0x8020_0000, 0x8040_1000..0x8800_0000];
+        let plan = plan_heap(&banks, kernel, &reserved, usize::MAX, usize::MAX);
+        assert_eq!(plan.as_slice(), &[0x1_0000_0000..0x1_0400_0000]);
+    }
+
+    #[test]
+    fn kernel_piece_survives_many_banks_before_it() {
+        // 内核之前有10个RAM区间
+        let banks: [Range<usize>; 11] = core::array::from_fn(|i| match i {
+            10 => 0x8000_0000..0x8000_0000 + 128 * MB,
+            _ => 0x4000_0000 + i * 0x400_0000..0x4000_0000 + i * 0x400_0000 + 16 * MB,
+        });
+        let kernel = 0x8020_0000..0x8040_0000;
+
+        let plan = plan_heap(&banks, kernel, &[], usize::MAX, usize::MAX);
+        assert_eq!(plan.len(), MAX_REGIONS);
+        assert_eq!(plan[0], 0x8040_0000..0x8800_0000);
+        assert_eq!(plan[1], 0x4000_0000..0x4100_0000);
+        assert_eq!(plan[MAX_REGIONS - 1], 0x5800_0000..0x5900_0000);
+    }
+
+    #[test]
+    fn sizes_parse_with_suffixes() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("0x1000"), Some(4096));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("16m"), Some(16 * MB));
+        assert_eq!(parse_size("1G"), Some(1024 * MB));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("ten"), None);
+    }
+}
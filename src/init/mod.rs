@@ -1,5 +1,7 @@
 
 // 系统初始化模块
-// 包含早期分配器等初始化子系统
+// 包含早期分配器及其内存布局等初始化子系统，以及按级别运行各子系统初始化函数的initcall框架
 
-pub mod alloc;
\ No newline at end of file
+pub mod alloc;
+pub mod initcall;
+pub mod memory;
\ No newline at end of file
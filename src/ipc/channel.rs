@@ -0,0 +1,212 @@
+// nt_rustos/src/ipc/channel.rs
+
+//! # Channels
+//!
+//! A bounded message queue shared by any number of senders and one
+//! receiver, usually through an `Arc`. Items are stored in an `MpscQueue`;
+//! tasks blocked on a full or empty channel wait on one of the channel's two
+//! wait queues. Every state change happens before the matching wait queue is
+//! woken, which is what the wait queues need to rule out lost wake-ups.
+//!
+//! Closing a channel wakes everyone: blocked senders get their item back,
+//! and the receiver drains what is left before seeing `Closed`.
+//!
+//! The blocking operations must be called from thread context.
+
+use crate::task::{self, WaitQueue};
+use crate::trap::collections::MpscQueue;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Error of a blocking `send`: the channel was closed. Holds the item.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// Error of a blocking `recv`: the channel is closed and empty.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecvError;
+
+/// Error of `try_send`. Holds the item that could not be sent.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full.
+    Full(T),
+    /// The channel has been closed.
+    Closed(T),
+}
+
+/// Error of `try_recv`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing to receive yet.
+    Empty,
+    /// The channel is closed and empty.
+    Closed,
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending on a closed channel")
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "receiving on a closed and empty channel")
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => write!(f, "sending on a full channel"),
+            Self::Closed(_) => write!(f, "sending on a closed channel"),
+        }
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "receiving on an empty channel"),
+            Self::Closed => write!(f, "receiving on a closed and empty channel"),
+        }
+    }
+}
+
+/// A bounded message queue between kernel threads.
+pub struct Channel<T> {
+    queue: MpscQueue<T>,
+    closed: AtomicBool,
+    /// Senders waiting for space.
+    not_full: WaitQueue,
+    /// The receiver waiting for an item, directly or through `select`.
+    not_empty: WaitQueue,
+}
+
+impl<T> Channel<T> {
+    /// Creates an open channel buffering up to `capacity` items.
+    ///
+    /// # Panics
+    /// Panics if the capacity is 0.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: MpscQueue::with_capacity(capacity),
+            closed: AtomicBool::new(false),
+            not_full: WaitQueue::new(),
+            not_empty: WaitQueue::new(),
+        }
+    }
+
+    /// Sends `item` without blocking.
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(item));
+        }
+        self.queue.push(item).map_err(TrySendError::Full)?;
+        self.not_empty.wake_one();
+        Ok(())
+    }
+
+    /// Sends `item`, blocking while the channel is full.
+    pub fn send(&self, mut item: T) -> Result<(), SendError<T>> {
+        loop {
+            match self.try_send(item) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(back)) => return Err(SendError(back)),
+                Err(TrySendError::Full(back)) => item = back,
+            }
+            self.not_full.wait_until(|| self.is_closed() || !self.queue.is_full());
+        }
+    }
+
+    /// Receives an item without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.queue.pop() {
+            Some(item) => {
+                self.not_full.wake_one();
+                Ok(item)
+            }
+            None if self.is_closed() => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Receives an item, blocking while the channel is empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(item) => return Ok(item),
+                Err(TryRecvError::Closed) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            self.not_empty.wait_until(|| self.is_recv_ready());
+        }
+    }
+
+    /// Closes the channel and wakes every blocked sender and receiver.
+    /// Items already queued can still be received.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.not_full.wake_all();
+        self.not_empty.wake_all();
+    }
+
+    /// Returns `true` once the channel has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of queued items.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if no item is queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns the maximum number of queued items.
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    /// Returns `true` if `recv` would not block.
+    fn is_recv_ready(&self) -> bool {
+        self.is_closed() || !self.queue.is_empty()
+    }
+}
+
+/// A source `select` can wait on.
+pub trait Selectable {
+    /// Returns `true` if receiving would not block.
+    fn is_ready(&self) -> bool;
+    /// The queue woken whenever `is_ready` may have become `true`.
+    fn ready_queue(&self) -> &WaitQueue;
+}
+
+impl<T> Selectable for Channel<T> {
+    fn is_ready(&self) -> bool {
+        self.is_recv_ready()
+    }
+
+    fn ready_queue(&self) -> &WaitQueue {
+        &self.not_empty
+    }
+}
+
+/// Blocks until one of `sources` is ready to receive from and returns its
+/// index; the lowest index wins if several are ready. A closed channel
+/// counts as ready.
+///
+/// Returns `None` if `sources` is empty.
+pub fn select(sources: &[&dyn Selectable]) -> Option<usize> {
+    // Fast path: no need to touch the wait queues.
+    if let Some(i) = sources.iter().position(|s| s.is_ready()) {
+        return Some(i);
+    }
+    let queues: Vec<&WaitQueue> = sources.iter().map(|s| s.ready_queue()).collect();
+    task::wait_any(&queues, |i| sources[i].is_ready())
+}
@@ -0,0 +1,11 @@
+// nt_rustos/src/ipc/mod.rs
+
+//! # Inter-Process Communication
+//!
+//! Message passing between kernel threads. A `Channel<T>` is a bounded
+//! queue with blocking and non-blocking send and receive; `select` waits
+//! until any of several channels has something to receive.
+
+pub mod channel;
+
+pub use self::channel::{select, Channel, RecvError, Selectable, SendError, TryRecvError, TrySendError};
@@ -1,8 +1,10 @@
 // nt_rustos/src/lib.rs
 
-#![no_std]
+#![cfg_attr(not(feature = "host-test"), no_std)]
 #![feature(panic_info_message)]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
+#![feature(btreemap_alloc)]
 
 // 导入alloc crate以支持动态数据结构
 extern crate alloc;
@@ -15,29 +17,124 @@ pub use alloc::vec;    // 确保 vec! 宏可用
 pub use alloc::boxed::Box; // 确保 Box 可用
 
 // 声明内核模块
+// 宿主机测试 (host-test) 只编译控制台、分配器、trap等不依赖硬件的模块，
+// 控制台后端、时钟与底层陷阱操作换成host模块中的模拟实现
+#[cfg(not(feature = "host-test"))]
+pub mod boot;
 pub mod console;
+#[cfg(not(feature = "host-test"))]
 pub mod util;
+#[cfg(feature = "host-test")]
+pub mod util {
+    // 宿主机测试只需要符号表 (陷阱转储用它解析地址) 与不依赖硬件的定长容器
+    pub mod ksyms;
+    pub mod arrayvec;
+}
 pub mod init;
+#[cfg(not(feature = "host-test"))]
 pub mod test;
 pub mod trap; // 新增：声明 trap 子系统模块
-
+#[cfg(not(feature = "host-test"))]
+pub mod task;
+#[cfg(not(feature = "host-test"))]
+pub mod syscall;
+#[cfg(not(feature = "host-test"))]
+pub mod loader;
+#[cfg(not(feature = "host-test"))]
+pub mod mm;
+#[cfg(not(feature = "host-test"))]
+pub mod ipc;
+#[cfg(not(feature = "host-test"))]
+pub mod profiler;
+#[cfg(not(feature = "host-test"))]
+pub mod debug;
+#[cfg(not(feature = "host-test"))]
+pub mod fdt;
+#[cfg(not(feature = "host-test"))]
+pub mod driver;
+#[cfg(not(feature = "host-test"))]
+pub mod block;
+#[cfg(not(feature = "host-test"))]
+pub mod fs;
+#[cfg(not(feature = "host-test"))]
+pub mod config;
+#[cfg(not(feature = "host-test"))]
+pub mod net;
+#[cfg(not(feature = "host-test"))]
+pub mod platform;
+#[cfg(not(feature = "host-test"))]
+pub mod pm;
+#[cfg(not(feature = "host-test"))]
+pub mod time;
+#[cfg(feature = "host-test")]
+#[path = "host/time.rs"]
+pub mod time;
+#[cfg(not(feature = "host-test"))]
+pub mod cpuinfo;
+#[cfg(not(feature = "host-test"))]
+pub mod smp;
+#[cfg(not(feature = "host-test"))]
+pub mod cmdline;
+#[cfg(not(feature = "host-test"))]
+pub mod sysctl;
+#[cfg(not(feature = "host-test"))]
+pub mod crashdump;
+#[cfg(not(feature = "host-test"))]
+pub mod panic;
+#[cfg(not(feature = "host-test"))]
+pub mod report;
+pub mod coverage;
+#[cfg(feature = "host-test")]
+pub mod host;
+
+#[cfg(not(feature = "host-test"))]
 use core::panic::PanicInfo;
+#[cfg(not(feature = "host-test"))]
 use core::arch::asm;
+#[cfg(not(feature = "host-test"))]
+use core::ops::Range;
+#[cfg(not(feature = "host-test"))]
+use util::arrayvec::ArrayVec;
 
-// 设置全局分配器
+// 设置全局分配器；宿主机测试使用std的分配器，内核分配器在测试自备的内存上初始化
+#[cfg(not(feature = "host-test"))]
 #[global_allocator]
 static GLOBAL_ALLOCATOR: init::alloc::global::EarlyGlobalAllocator = init::alloc::global::GLOBAL_EARLY_ALLOCATOR;
 
 /// 启动栈大小 (16KB)
 pub const STACK_SIZE: usize = 4096 * 4;
 
+/// 启用`qemu-exit`时QEMU的退出状态：全部测试通过
+pub const EXIT_TESTS_PASSED: u16 = 0;
+/// 有测试失败，或没有运行任何测试
+pub const EXIT_TESTS_FAILED: u16 = 1;
+/// 内核panic，测试未能运行完
+pub const EXIT_PANIC: u16 = 3;
+
 /// Panic处理器 - 当发生panic时调用
+#[cfg(not(feature = "host-test"))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     // 尝试禁用中断，防止嵌套Panic或进一步错误
     unsafe { asm!("csrci sstatus, 1 << 1") };
 
+    // 报告过程本身panic时 (如控制台或分配器状态损坏) 不再重复报告，
+    // 只经SBI打印位置后停机，避免无限递归
+    match panic::enter() {
+        0 => {}
+        1 => panic::nested(info),
+        _ => panic::halt(),
+    }
+
+    // 先让其他hart停下并保存现场，以免它们在报告期间继续改动状态；
+    // 已有其他hart在报告panic时，本hart只打印位置后停下
+    if !panic::stop_other_harts() {
+        panic::park_hart(info);
+    }
+
     error_print!("KERNEL PANIC!");
+    // 墙上时间只读原子量和time CSR，不加锁，panic中读取安全
+    error_print!("  Time: {} ({} ms since boot)", time::now(), time::monotonic_ms());
 
     if let Some(location) = info.location() {
         error_print!("  Location: {}:{}", location.file(), location.line());
@@ -49,6 +146,37 @@ fn panic(info: &PanicInfo) -> ! {
         error_print!("  No panic message available.");
     }
 
+    // kassert!失败时已从断言处打印过回溯
+    let backtrace = util::backtrace::capture();
+    if !util::backtrace::take_reported() {
+        backtrace.print();
+    }
+
+    // 其他hart停下时的寄存器，用于诊断多核死锁与竞争
+    for (hart, stop) in panic::other_harts() {
+        error_print!("  Hart {}: {}", hart, stop);
+    }
+
+    // 写入崩溃转储区域，下次启动时报告 (见 crashdump)
+    crashdump::write(|dump| {
+        use core::fmt::Write;
+        writeln!(dump, "KERNEL PANIC at {} ({} ms since boot)", time::now(), time::monotonic_ms())?;
+        if let Some(location) = info.location() {
+            writeln!(dump, "Location: {}:{}", location.file(), location.line())?;
+        }
+        if let Some(message) = info.message() {
+            writeln!(dump, "Message: {}", message)?;
+        }
+        writeln!(dump, "Backtrace:")?;
+        for (index, &pc) in backtrace.frames().iter().enumerate() {
+            writeln!(dump, "  #{:<2} {}", index, util::ksyms::WithSymbol(pc))?;
+        }
+        for (hart, stop) in panic::other_harts() {
+            writeln!(dump, "Hart {}: {}", hart, stop)?;
+        }
+        Ok(())
+    });
+
     // 如果错误处理系统（特别是ErrorManager的panic_mode）已经初始化，则利用它
     // 这需要trap系统已经初始化
     if trap::infrastructure::di::is_initialized() {
@@ -81,16 +209,15 @@ fn panic(info: &PanicInfo) -> ! {
         error_print!("  Allocator not initialized. Cannot report memory state.");
     }
 
-    error_print!("System halted.");
-    // 无限循环，停止系统
-    loop {
-        unsafe {
-            asm!("wfi"); // 等待中断，降低功耗
-        }
-    }
+    // 停机前让各子系统保存状态或使硬件静止 (见 panic::register_hook)
+    panic::run_hooks(info);
+
+    // 按panic策略停机、关机或延时重启 (见 panic)
+    panic::finish()
 }
 
 /// 安全地清空BSS段，但跳过指定的栈区域
+#[cfg(not(feature = "host-test"))]
 pub unsafe fn clear_bss(stack_bottom: usize, stack_top: usize) {
     extern "C" {
         fn sbss();
@@ -116,55 +243,96 @@ pub unsafe fn clear_bss(stack_bottom: usize, stack_top: usize) {
 }
 
 
-/// 系统初始化
+/// 堆起点随机偏移的16字节槽位数
+#[cfg(not(feature = "host-test"))]
+const HEAP_SLIDE_SLOTS: u64 = 256;
+
+/// 系统初始化：按级别 (early、arch、subsys、device、late) 运行各子系统注册的initcall，
+/// 见 init::initcall
+#[cfg(not(feature = "host-test"))]
 pub fn init() {
     info_print!("NT RustOS Initializing...");
+    init::initcall::run_all();
+    info_print!("System Core Initialization Completed.");
+}
 
-    // 1. 初始化早期分配器 (必须首先完成)
+/// 初始化早期分配器：按设备树给出的RAM建立堆 (见 init::memory)，此前的initcall都不能分配内存
+///
+/// 内核结束之后的一段是首个堆区域，其余RAM依次作为后续区域加入。
+/// 命令行的`heap_size=` (可带K、M、G后缀) 限制堆的总大小，默认使用全部可用的RAM。
+#[cfg(not(feature = "host-test"))]
+fn init_heap() -> init::initcall::InitResult {
     extern "C" {
+        fn skernel(); // 链接器提供的内核起始地址
         fn end(); // 链接器提供的内核结束地址
     }
 
-    let heap_start = unsafe { end as usize };
-    let heap_start_aligned = (heap_start + 0xF) & !0xF; // 16字节对齐
-    let heap_size = 2 * 1024 * 1024; // 2MB
-
-    match init::alloc::init(heap_start_aligned, heap_size) {
-        Ok(_) => {
-            info_print!("Early Allocator initialized at 0x{:x} (Size: {} KB).", heap_start_aligned, heap_size / 1024);
-            if let Some(stats) = init::alloc::stats() {
-                info_print!("  Initial Heap: Total: {} KB, Free: {} KB, Overhead: {} bytes",
-                            stats.total_size / 1024,
-                            stats.free_size / 1024,
-                            stats.total_size - stats.free_size);
-            }
-        }
-        Err(e) => {
-            // Panic时控制台可能还未完全可用，尝试基础输出
-            crate::console::print_str("FATAL: Failed to initialize early allocator: ");
-            // 无法使用复杂的打印，因为分配器失败
-            // crate::console::print_str(format!("{:?}", e).as_str()); // format! 需要分配
-            crate::console::print_str("Halting.\n");
-            loop { unsafe { asm!("wfi"); } } // 系统无法继续
-        }
+    let _timing = boot::timing::measure(boot::timing::Phase::Heap);
+    let platform = platform::get();
+    let kernel_end = unsafe { end as usize };
+    // 在内核结束后的一页内随机偏移堆起点 (此时只有基于计数器抖动的种子)
+    let heap_slide = util::rand::rand_below(HEAP_SLIDE_SLOTS) as usize * 16;
+    let heap_floor = ((kernel_end + 0xF) & !0xF) + heap_slide;
+
+    // 不覆盖固件保留的内存 (见 mm::guard)，也不覆盖引导程序放置的initrd
+    let mut reserved: ArrayVec<Range<usize>, { mm::guard::MAX_RESERVED + 1 }> = ArrayVec::new();
+    for r in mm::guard::reserved() {
+        let _ = reserved.push(r.region.base..r.region.end());
     }
-
-    // 2. 初始化 Trap 子系统 (依赖分配器)
-    // 使用 Direct 模式，因为 Vectored 模式需要更复杂的硬件支持和设置
-    trap::init(trap::TrapMode::Direct);
-    info_print!("Trap Subsystem initialized.");
-
-    // 3. 测试动态数据结构 (依赖分配器和trap系统错误处理)
-    test_dynamic_structures();
-
-    // 4. 显示SBI系统信息 (可选，但有助于调试)
-    util::sbi::info::print_sbi_info();
-
-    info_print!("System Core Initialization Completed.");
+    if let Some(initrd) = platform.initrd {
+        let _ = reserved.push(initrd.base..initrd.end());
+    }
+    let mut banks: ArrayVec<Range<usize>, { platform::MAX_RAM_BANKS }> = ArrayVec::new();
+    for bank in platform.ram_banks() {
+        let _ = banks.push(bank.base..bank.end());
+    }
+    let budget = match cmdline::value("heap_size") {
+        Some(text) => init::memory::parse_size(text).unwrap_or_else(|| {
+            warn_print!("Invalid heap_size={}, using all available RAM", text);
+            usize::MAX
+        }),
+        None => usize::MAX,
+    };
+    // 内核只映射了低4GB (见 mm::address_space)
+    let limit = mm::address_space::KERNEL_ROOT_ENTRIES << 30;
+    let plan = init::memory::plan_heap(&banks, (skernel as usize)..heap_floor, &reserved, limit, budget);
+
+    // heap=buddy选用伙伴系统后端，默认为首次适配
+    let backend = cmdline::value("heap")
+        .and_then(init::alloc::Backend::parse)
+        .unwrap_or(init::alloc::Backend::FirstFit);
+    let initialized = plan
+        .first()
+        .is_some_and(|primary| init::alloc::init_with_backend(primary.start, primary.len(), backend).is_ok());
+    if !initialized {
+        // 没有堆就无法报告错误 (错误信息需要分配)，只能停机
+        crate::console::print_str("FATAL: Failed to initialize early allocator. Halting.\n");
+        loop { unsafe { asm!("wfi"); } } // 系统无法继续
+    }
+    // heap_policy=best或next切换首次适配后端查找空闲块的策略
+    if let Some(policy) = cmdline::value("heap_policy").and_then(init::alloc::AllocPolicy::parse) {
+        let _ = init::alloc::set_policy(policy);
+    }
+    info_print!("Early Allocator initialized at 0x{:x} (Size: {} KB).", plan[0].start, plan[0].len() / 1024);
+    for region in &plan[1..] {
+        // 失败已由add_region报告，堆仍可使用已有的区域
+        let _ = init::alloc::add_region(region.start, region.len());
+    }
+    if let Some(stats) = init::alloc::stats() {
+        info_print!("  Initial Heap: Total: {} KB in {} region(s), Free: {} KB, Overhead: {} bytes",
+                    stats.total_size / 1024,
+                    init::alloc::regions().len(),
+                    stats.free_size / 1024,
+                    stats.total_size - stats.free_size);
+    }
+    Ok(())
 }
 
-/// 测试动态数据结构支持
-fn test_dynamic_structures() {
+#[cfg(not(feature = "host-test"))]
+initcall!(early, 50, init_heap);
+
+/// 测试动态数据结构支持 (依赖分配器和trap系统错误处理)
+fn test_dynamic_structures() -> init::initcall::InitResult {
     info_print!("Testing dynamic data structures...");
 
     let mut test_vec = Vec::new();
@@ -197,35 +365,47 @@ fn test_dynamic_structures() {
     }
 
     info_print!("Dynamic structures test completed.");
+    Ok(())
 }
 
+initcall!(late, 20, test_dynamic_structures);
+
 /// 主循环 - 系统的核心循环
+#[cfg(not(feature = "host-test"))]
 pub fn main_loop() -> ! {
     info_print!("Entering main operating loop...");
 
     // 运行所有测试
     info_print!("Running comprehensive test suites...");
-    test::run_all_tests();
+    let passed = {
+        let _timing = boot::timing::measure(boot::timing::Phase::Tests);
+        test::run_all_tests()
+    };
     info_print!("All test suites completed.");
+    boot::timing::report().print();
+
+    // 自动化测试运行：以测试结果作为QEMU的退出状态
+    if cfg!(feature = "qemu-exit") {
+        exit(if passed { EXIT_TESTS_PASSED } else { EXIT_TESTS_FAILED });
+    }
 
     // 打印最终内存状态
     init::alloc::print_status();
 
     info_print!("System ready. Entering idle loop.");
     loop {
-        unsafe {
-            // 等待中断，如果没有中断发生，wfi 将使处理器进入低功耗状态
-            // 直到下一个中断到达。
-            asm!("wfi");
-        }
-        // 当中断发生并处理完毕后，会从这里继续执行。
-        // 在一个更复杂的内核中，这里可能会检查调度队列等。
+        // 关中断进入空闲状态，直到有中断挂起；恢复中断后在此处理该中断。
+        let was_enabled = trap::disable_interrupts();
+        pm::idle(task::timer::armed_deadline());
+        trap::restore_interrupts(was_enabled);
     }
 }
 
 /// 系统关闭
+#[cfg(not(feature = "host-test"))]
 pub fn shutdown() -> ! {
     info_print!("System Shutting Down...");
+    report::emit_at_shutdown();
 
     if init::alloc::is_initialized() {
         if let Some(handover) = init::alloc::prepare_handover() {
@@ -238,11 +418,28 @@ pub fn shutdown() -> ! {
         init::alloc::print_status();
     }
 
-    info_print!("Shutdown sequence completed. Calling SBI shutdown.");
-    util::sbi::system::shutdown();
+    // 固件不支持SRST时通过测试设备关机 (QEMU退出状态为0)
+    if util::sbi::info::is_extension_available(util::sbi::extension_ids::SRST) {
+        info_print!("Shutdown sequence completed. Calling SBI shutdown.");
+        util::sbi::system::shutdown();
+    }
+    info_print!("Shutdown sequence completed. SBI SRST unavailable, using the test finisher.");
+    driver::sifive_test::exit(0);
+}
+
+/// 以指定状态退出QEMU (0表示成功)，用于自动化测试
+#[cfg(not(feature = "host-test"))]
+pub fn exit(code: u16) -> ! {
+    info_print!("Exiting with status {}.", code);
+    report::emit_at_shutdown();
+    if init::alloc::is_initialized() {
+        init::alloc::print_status();
+    }
+    driver::sifive_test::exit(code);
 }
 
 /// 系统重启
+#[cfg(not(feature = "host-test"))]
 pub fn reboot() -> ! {
     info_print!("System Rebooting...");
     if init::alloc::is_initialized() {
@@ -250,8 +447,12 @@ pub fn reboot() -> ! {
             warn_print!("Failed to freeze allocator before reboot: {:?}", e);
         }
     }
-    info_print!("Reboot sequence initiated. Calling SBI reboot.");
-    util::sbi::system::reboot();
+    if util::sbi::info::is_extension_available(util::sbi::extension_ids::SRST) {
+        info_print!("Reboot sequence initiated. Calling SBI reboot.");
+        util::sbi::system::reboot();
+    }
+    info_print!("Reboot sequence initiated. SBI SRST unavailable, using the test finisher.");
+    driver::sifive_test::reset();
 }
 
 /// 获取系统内存信息摘要
@@ -0,0 +1,330 @@
+// nt_rustos/src/loader/elf.rs
+
+//! # ELF64 Executable Parser
+//!
+//! Parses and validates ELF64 little-endian RISC-V executables from a byte
+//! slice. All fields are decoded byte-wise, so the input does not need any
+//! particular alignment.
+
+use core::fmt;
+use core::ops::Range;
+
+/// `\x7FELF`
+pub const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+/// Executable file.
+pub const ET_EXEC: u16 = 2;
+/// RISC-V machine type.
+pub const EM_RISCV: u16 = 243;
+
+/// Size of the ELF64 file header.
+pub const EHDR_SIZE: usize = 64;
+/// Size of an ELF64 program header.
+pub const PHDR_SIZE: usize = 56;
+/// Upper bound on program headers, to reject absurd files early.
+pub const MAX_PROGRAM_HEADERS: usize = 64;
+
+/// `e_flags`: the image uses compressed instructions.
+pub const EF_RISCV_RVC: u32 = 0x1;
+/// `e_flags`: the floating-point calling convention.
+pub const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+pub const EF_RISCV_FLOAT_ABI_SINGLE: u32 = 0x2;
+pub const EF_RISCV_FLOAT_ABI_DOUBLE: u32 = 0x4;
+pub const EF_RISCV_FLOAT_ABI_QUAD: u32 = 0x6;
+
+/// Loadable segment.
+pub const PT_LOAD: u32 = 1;
+/// Program interpreter (dynamic linking), not supported.
+pub const PT_INTERP: u32 = 3;
+/// Location of the program header table itself.
+pub const PT_PHDR: u32 = 6;
+
+/// Segment permission bits from `p_flags`.
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// Errors produced while parsing or loading an ELF image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// The input is shorter than a structure it should contain.
+    Truncated,
+    /// The magic number is missing.
+    BadMagic,
+    /// Not a 64-bit little-endian ELF.
+    UnsupportedClass,
+    /// Not a RISC-V executable.
+    UnsupportedMachine,
+    /// Not an `ET_EXEC` file.
+    UnsupportedType,
+    /// The image requires a dynamic loader.
+    DynamicNotSupported,
+    /// A header field is inconsistent (sizes, counts, alignment).
+    InvalidHeader,
+    /// A segment lies outside the file or the user address range.
+    InvalidSegment,
+    /// The image has no loadable segment.
+    NoLoadableSegment,
+    /// The entry point is not inside an executable segment.
+    InvalidEntry,
+    /// The image needs an extension the harts lack.
+    UnsupportedIsa,
+    /// The target address space could not map or write memory.
+    MapFailed,
+    /// Arguments do not fit in the initial stack.
+    StackOverflow,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "ELF image is truncated"),
+            Self::BadMagic => write!(f, "not an ELF image"),
+            Self::UnsupportedClass => write!(f, "only ELF64 little-endian is supported"),
+            Self::UnsupportedMachine => write!(f, "not a RISC-V executable"),
+            Self::UnsupportedType => write!(f, "not an executable (ET_EXEC) image"),
+            Self::DynamicNotSupported => write!(f, "dynamically linked images are not supported"),
+            Self::InvalidHeader => write!(f, "inconsistent ELF header"),
+            Self::InvalidSegment => write!(f, "segment outside of file or user range"),
+            Self::NoLoadableSegment => write!(f, "no PT_LOAD segment"),
+            Self::InvalidEntry => write!(f, "entry point outside executable segments"),
+            Self::UnsupportedIsa => write!(f, "image needs an ISA extension the harts lack"),
+            Self::MapFailed => write!(f, "failed to map segment"),
+            Self::StackOverflow => write!(f, "arguments exceed initial stack"),
+        }
+    }
+}
+
+/// Permissions requested by a segment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SegmentPermissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl SegmentPermissions {
+    /// Decodes `p_flags`.
+    pub fn from_flags(flags: u32) -> Self {
+        Self {
+            read: flags & PF_R != 0,
+            write: flags & PF_W != 0,
+            execute: flags & PF_X != 0,
+        }
+    }
+}
+
+/// The decoded ELF file header fields the loader needs.
+#[derive(Debug, Copy, Clone)]
+pub struct ElfHeader {
+    pub entry: usize,
+    pub phoff: usize,
+    pub phentsize: usize,
+    pub phnum: usize,
+    /// `e_flags`, the `EF_RISCV_*` bits.
+    pub flags: u32,
+}
+
+impl ElfHeader {
+    /// Returns the single-letter extensions the flags say the image needs:
+    /// `c` for compressed instructions, `f`, `d` or `q` for its float ABI.
+    pub fn required_extensions(&self) -> impl Iterator<Item = char> {
+        let rvc = (self.flags & EF_RISCV_RVC != 0).then_some('c');
+        let float = match self.flags & EF_RISCV_FLOAT_ABI {
+            EF_RISCV_FLOAT_ABI_SINGLE => Some('f'),
+            EF_RISCV_FLOAT_ABI_DOUBLE => Some('d'),
+            EF_RISCV_FLOAT_ABI_QUAD => Some('q'),
+            _ => None,
+        };
+        rvc.into_iter().chain(float)
+    }
+}
+
+/// A decoded program header.
+#[derive(Debug, Copy, Clone)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub file_size: usize,
+    pub mem_size: usize,
+    pub align: usize,
+}
+
+impl ProgramHeader {
+    /// Returns the permissions of this segment.
+    pub fn permissions(&self) -> SegmentPermissions {
+        SegmentPermissions::from_flags(self.flags)
+    }
+
+    /// Returns the end of the segment in memory (exclusive).
+    pub fn vaddr_end(&self) -> usize {
+        self.vaddr + self.mem_size
+    }
+}
+
+fn read_u16(data: &[u8], off: usize) -> Result<u16, ElfError> {
+    let b = data.get(off..off + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], off: usize) -> Result<u32, ElfError> {
+    let b = data.get(off..off + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(data: &[u8], off: usize) -> Result<u64, ElfError> {
+    let b = data.get(off..off + 8).ok_or(ElfError::Truncated)?;
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(b);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// A validated view of an ELF64 image.
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: ElfHeader,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parses and validates the file header and program header table.
+    ///
+    /// Every loadable segment must fit inside `user_range`.
+    pub fn parse(data: &'a [u8], user_range: Range<usize>) -> Result<Self, ElfError> {
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if data[0..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB || data[6] != EV_CURRENT {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if read_u16(data, 16)? != ET_EXEC {
+            return Err(ElfError::UnsupportedType);
+        }
+        if read_u16(data, 18)? != EM_RISCV {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        let header = ElfHeader {
+            entry: read_u64(data, 24)? as usize,
+            phoff: read_u64(data, 32)? as usize,
+            phentsize: read_u16(data, 54)? as usize,
+            phnum: read_u16(data, 56)? as usize,
+            flags: read_u32(data, 48)?,
+        };
+
+        if header.phentsize != PHDR_SIZE || header.phnum == 0 || header.phnum > MAX_PROGRAM_HEADERS {
+            return Err(ElfError::InvalidHeader);
+        }
+        let table_end = header
+            .phnum
+            .checked_mul(PHDR_SIZE)
+            .and_then(|len| len.checked_add(header.phoff))
+            .ok_or(ElfError::InvalidHeader)?;
+        if table_end > data.len() {
+            return Err(ElfError::Truncated);
+        }
+
+        let elf = Self { data, header };
+        elf.validate_segments(&user_range)?;
+        Ok(elf)
+    }
+
+    fn validate_segments(&self, user_range: &Range<usize>) -> Result<(), ElfError> {
+        let mut loadable = 0;
+        let mut entry_ok = false;
+
+        for ph in self.program_headers() {
+            let ph = ph?;
+            match ph.p_type {
+                PT_INTERP => return Err(ElfError::DynamicNotSupported),
+                PT_LOAD => {}
+                _ => continue,
+            }
+            loadable += 1;
+
+            if ph.file_size > ph.mem_size {
+                return Err(ElfError::InvalidSegment);
+            }
+            let file_end = ph.offset.checked_add(ph.file_size).ok_or(ElfError::InvalidSegment)?;
+            if file_end > self.data.len() {
+                return Err(ElfError::InvalidSegment);
+            }
+            let mem_end = ph.vaddr.checked_add(ph.mem_size).ok_or(ElfError::InvalidSegment)?;
+            if ph.vaddr < user_range.start || mem_end > user_range.end {
+                return Err(ElfError::InvalidSegment);
+            }
+            if ph.align > 1 && (!ph.align.is_power_of_two() || (ph.vaddr % ph.align) != (ph.offset % ph.align)) {
+                return Err(ElfError::InvalidSegment);
+            }
+            if ph.permissions().execute && self.header.entry >= ph.vaddr && self.header.entry < mem_end {
+                entry_ok = true;
+            }
+        }
+
+        if loadable == 0 {
+            return Err(ElfError::NoLoadableSegment);
+        }
+        if !entry_ok {
+            return Err(ElfError::InvalidEntry);
+        }
+        Ok(())
+    }
+
+    /// Returns the decoded file header.
+    pub fn header(&self) -> &ElfHeader {
+        &self.header
+    }
+
+    /// Returns the program entry point.
+    pub fn entry(&self) -> usize {
+        self.header.entry
+    }
+
+    /// Iterates over all program headers.
+    pub fn program_headers(&self) -> impl Iterator<Item = Result<ProgramHeader, ElfError>> + '_ {
+        (0..self.header.phnum).map(move |i| self.program_header(i))
+    }
+
+    /// Decodes program header `index`.
+    pub fn program_header(&self, index: usize) -> Result<ProgramHeader, ElfError> {
+        let base = self.header.phoff + index * PHDR_SIZE;
+        Ok(ProgramHeader {
+            p_type: read_u32(self.data, base)?,
+            flags: read_u32(self.data, base + 4)?,
+            offset: read_u64(self.data, base + 8)? as usize,
+            vaddr: read_u64(self.data, base + 16)? as usize,
+            file_size: read_u64(self.data, base + 32)? as usize,
+            mem_size: read_u64(self.data, base + 40)? as usize,
+            align: read_u64(self.data, base + 48)? as usize,
+        })
+    }
+
+    /// Returns the file contents backing a segment.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        &self.data[ph.offset..ph.offset + ph.file_size]
+    }
+
+    /// Returns the virtual address of the program header table, if the image maps it.
+    pub fn phdr_vaddr(&self) -> Option<usize> {
+        for ph in self.program_headers().flatten() {
+            if ph.p_type == PT_PHDR {
+                return Some(ph.vaddr);
+            }
+        }
+        // Fall back to the load segment that covers the table's file offset.
+        self.program_headers().flatten().find_map(|ph| {
+            let off = self.header.phoff;
+            if ph.p_type == PT_LOAD && off >= ph.offset && off < ph.offset + ph.file_size {
+                Some(ph.vaddr + (off - ph.offset))
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -0,0 +1,183 @@
+// nt_rustos/src/loader/mod.rs
+
+//! # Program Loader
+//!
+//! Loads ELF64 executables into a target address space and prepares the
+//! initial user stack (argc, argv, envp and auxiliary vector).
+//!
+//! The loader is decoupled from the paging implementation through the
+//! `ImageMapper` trait, which any address space implements.
+
+pub mod elf;
+
+pub use self::elf::{ElfError, ElfFile, ProgramHeader, SegmentPermissions};
+
+use crate::cpuinfo;
+use alloc::vec::Vec;
+
+pub use crate::mm::{PAGE_SIZE, USER_SPACE_END, USER_SPACE_START};
+
+/// Initial user stack top. One guard page is left below `USER_SPACE_END`.
+pub const USER_STACK_TOP: usize = USER_SPACE_END - PAGE_SIZE;
+/// Size of the initial user stack (64KB).
+pub const USER_STACK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// Auxiliary vector keys.
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_ENTRY: usize = 9;
+
+/// The interface the loader needs from an address space.
+pub trait ImageMapper {
+    /// Maps `[vaddr, vaddr + mem_size)` with `perms`, copies `data` to its
+    /// start, and zero-fills the remainder. `vaddr` need not be page aligned.
+    fn map_segment(
+        &mut self,
+        vaddr: usize,
+        mem_size: usize,
+        perms: SegmentPermissions,
+        data: &[u8],
+    ) -> Result<(), ElfError>;
+
+    /// Maps a zeroed, read-write user stack occupying `[top - size, top)`.
+    fn map_stack(&mut self, top: usize, size: usize) -> Result<(), ElfError>;
+
+    /// Copies `data` into already mapped memory at `vaddr`.
+    fn write_bytes(&mut self, vaddr: usize, data: &[u8]) -> Result<(), ElfError>;
+}
+
+/// The result of loading an executable.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadedImage {
+    /// The program entry point (`sepc` for the first return to user mode).
+    pub entry: usize,
+    /// The initial user stack pointer, pointing at `argc`.
+    pub stack_pointer: usize,
+    /// Number of arguments.
+    pub argc: usize,
+    /// User address of the `argv` array.
+    pub argv: usize,
+    /// User address of the `envp` array.
+    pub envp: usize,
+    /// Page-aligned end of the highest loaded segment (initial program break).
+    pub brk: usize,
+}
+
+const fn page_round_up(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Parses `image`, maps its loadable segments into `mapper` and builds the
+/// initial stack holding `argv`.
+pub fn load_elf<M: ImageMapper>(
+    image: &[u8],
+    argv: &[&str],
+    mapper: &mut M,
+) -> Result<LoadedImage, ElfError> {
+    let elf = ElfFile::parse(image, USER_SPACE_START..USER_STACK_TOP - USER_STACK_SIZE)?;
+    if !elf.header().required_extensions().all(cpuinfo::has_letter) {
+        return Err(ElfError::UnsupportedIsa);
+    }
+
+    let mut brk = 0;
+    for ph in elf.program_headers() {
+        let ph = ph?;
+        if ph.p_type != elf::PT_LOAD || ph.mem_size == 0 {
+            continue;
+        }
+        mapper.map_segment(ph.vaddr, ph.mem_size, ph.permissions(), elf.segment_data(&ph))?;
+        brk = brk.max(page_round_up(ph.vaddr_end()));
+    }
+
+    mapper.map_stack(USER_STACK_TOP, USER_STACK_SIZE)?;
+    let layout = build_initial_stack(&elf, argv, mapper)?;
+
+    Ok(LoadedImage {
+        entry: elf.entry(),
+        stack_pointer: layout.stack_pointer,
+        argc: argv.len(),
+        argv: layout.argv,
+        envp: layout.envp,
+        brk,
+    })
+}
+
+struct StackLayout {
+    stack_pointer: usize,
+    argv: usize,
+    envp: usize,
+}
+
+/// Writes the argument strings and the System V style pointer block:
+///
+/// ```text
+///   sp -> argc
+///         argv[0..argc], NULL
+///         envp: NULL
+///         auxv pairs, AT_NULL
+///         ... argument strings ...
+/// USER_STACK_TOP
+/// ```
+fn build_initial_stack<M: ImageMapper>(
+    elf: &ElfFile<'_>,
+    argv: &[&str],
+    mapper: &mut M,
+) -> Result<StackLayout, ElfError> {
+    let stack_bottom = USER_STACK_TOP - USER_STACK_SIZE;
+    let mut sp = USER_STACK_TOP;
+
+    // Copy the strings first, highest address first.
+    let mut arg_ptrs = Vec::with_capacity(argv.len());
+    for arg in argv.iter() {
+        let len = arg.len() + 1;
+        sp = sp.checked_sub(len).ok_or(ElfError::StackOverflow)?;
+        if sp < stack_bottom {
+            return Err(ElfError::StackOverflow);
+        }
+        mapper.write_bytes(sp, arg.as_bytes())?;
+        mapper.write_bytes(sp + arg.len(), &[0])?;
+        arg_ptrs.push(sp);
+    }
+
+    let mut auxv = Vec::with_capacity(12);
+    if let Some(phdr) = elf.phdr_vaddr() {
+        auxv.extend_from_slice(&[AT_PHDR, phdr]);
+    }
+    auxv.extend_from_slice(&[
+        AT_PHENT, elf::PHDR_SIZE,
+        AT_PHNUM, elf.header().phnum,
+        AT_PAGESZ, PAGE_SIZE,
+        AT_ENTRY, elf.entry(),
+        AT_NULL, 0,
+    ]);
+
+    // argc + argv + NULL + envp NULL + auxv
+    let words = 1 + arg_ptrs.len() + 1 + 1 + auxv.len();
+    sp = (sp & !0xF).checked_sub(words * 8).ok_or(ElfError::StackOverflow)? & !0xF;
+    if sp < stack_bottom {
+        return Err(ElfError::StackOverflow);
+    }
+
+    let mut block = Vec::with_capacity(words);
+    block.push(argv.len());
+    block.extend_from_slice(&arg_ptrs);
+    block.push(0);
+    block.push(0);
+    block.extend_from_slice(&auxv);
+
+    let mut bytes = Vec::with_capacity(words * 8);
+    for word in block.iter() {
+        bytes.extend_from_slice(&(*word as u64).to_le_bytes());
+    }
+    mapper.write_bytes(sp, &bytes)?;
+
+    let argv_addr = sp + 8;
+    Ok(StackLayout {
+        stack_pointer: sp,
+        argv: argv_addr,
+        envp: argv_addr + (arg_ptrs.len() + 1) * 8,
+    })
+}
@@ -3,36 +3,59 @@
 #![no_std]
 #![no_main]
 
-use core::arch::asm;
+use core::arch::global_asm;
+use nt_rustos::boot::timing::Phase;
 use nt_rustos::{STACK_SIZE, clear_bss, init, main_loop, MemoryInfo, get_memory_info, println, info_print, error_print, debug_print};
 
 // 用于存放栈的内存区域
+#[no_mangle]
 #[link_section = ".bss.stack"]
 static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
-/// 程序入口点 - 汇编代码会跳转到这里
-#[no_mangle]
-#[link_section = ".text.entry"]
-fn _start() -> ! {
-    // 关键：首先设置栈指针，这样我们才能执行Rust代码
-    // 栈向下增长，所以sp指向高地址
+// 程序入口点：OpenSBI跳转到这里时 a0 = hartid，a1 = DTB物理地址。
+// 入口只设置栈指针，不触碰 a0/a1，它们作为参数原样传给 start_rust。
+// 固件可能同时启动多个hart：先抽签，只有第一个到达的hart继续，其余hart在
+// __boot_park 停放 (见 boot::park)，以免并发清理BSS、初始化分配器。
+global_asm!(
+    ".section .text.entry",
+    ".globl _start",
+    "_start:",
+    "    la t0, __boot_lottery",
+    "    li t1, 1",
+    "    amoadd.w t1, t1, (t0)",
+    "    beqz t1, 1f",
+    "    la t0, __boot_park",
+    "    jr t0",
+    "1:",
+    "    li s0, 0", // 帧指针链在此结束 (见 util::backtrace)
+    "    la sp, {stack}",
+    "    li t0, {stack_size}",
+    "    add sp, sp, t0",
+    "    call {start}",
+    stack = sym STACK,
+    stack_size = const STACK_SIZE,
+    start = sym start_rust,
+);
+
+/// 栈设置完成后的入口：清理BSS并记录启动参数
+extern "C" fn start_rust(hart_id: usize, dtb_addr: usize) -> ! {
     let stack_top = unsafe { STACK.as_ptr().add(STACK_SIZE) as usize };
-    unsafe {
-        asm!(
-            "mv sp, {0}",
-            in(reg) stack_top,
-            options(nostack) // 确保编译器不在此处插入栈操作
-        );
-    }
-
     // 获取栈底，用于BSS清理
     let stack_bottom = unsafe { STACK.as_ptr() as usize };
 
+    // 入口时刻的计数器读数，BSS清理后才能保存
+    let entry_ticks = nt_rustos::time::now_ticks();
+
     // 关键：安全地清空BSS段，同时绕过栈区域。
     unsafe {
         clear_bss(stack_bottom, stack_top);
     }
 
+    // 启动参数与启动计时保存在BSS中，必须在清理之后记录
+    nt_rustos::boot::record(hart_id, dtb_addr);
+    nt_rustos::boot::timing::record_entry(entry_ticks);
+    nt_rustos::boot::timing::record(Phase::BssClear, entry_ticks, nt_rustos::time::now_ticks());
+
     // 调用Rust主函数
     rust_main();
 }
@@ -70,6 +93,13 @@ fn early_printk_banner() {
     console::print_str(" (");
     console::print_num(STACK_SIZE / 1024);
     console::print_str(" KB)\n");
+    if let Some(info) = nt_rustos::boot::boot_info() {
+        console::print_str("Boot hart: ");
+        console::print_num(info.hart_id);
+        console::print_str(", DTB at 0x");
+        console::print_hex(info.dtb_addr);
+        console::print_str("\n");
+    }
 
     // 获取内核边界信息
     extern "C" {
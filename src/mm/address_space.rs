@@ -0,0 +1,377 @@
+// nt_rustos/src/mm/address_space.rs
+
+//! # Address Spaces
+//!
+//! An `AddressSpace` owns an Sv39 root table, the intermediate tables below
+//! it and every user frame it maps. The kernel half (the first four 1GB
+//! entries of the root, identity mapping MMIO and RAM) is copied from the
+//! kernel template into every user address space, together with the root
+//! entry of the kernel stack area, so kernel code keeps running unchanged
+//! after `satp` is switched.
+//!
+//! User mappings live in `[USER_SPACE_START, USER_SPACE_END)` and are
+//! always created with 4KB pages. A page is either backed by a frame the
+//! address space owns, or by a page of a `SharedMemory` object, which it
+//! keeps alive while mapped.
+
+use super::asid::{self, KERNEL_ASID};
+use super::frame::PhysFrame;
+use super::kstack::KSTACK_ROOT_INDEX;
+use super::page_table::{self, PageTableEntry, PteFlags};
+use super::shm::SharedMemory;
+use super::{MmError, PAGE_SIZE, USER_SPACE_END, USER_SPACE_START};
+use crate::init::alloc::AllocPurpose;
+use crate::loader::{ElfError, ImageMapper, SegmentPermissions};
+use crate::platform;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Number of root entries forming the shared kernel half (0..4GB).
+pub const KERNEL_ROOT_ENTRIES: usize = 4;
+
+/// `satp.MODE` value for Sv39.
+const SATP_MODE_SV39: usize = 8 << 60;
+
+/// The memory behind a user page.
+enum Backing {
+    /// A private frame owned by the address space.
+    Owned(PhysFrame),
+    /// Page `index` of a shared memory object.
+    Shared(Arc<SharedMemory>, usize),
+}
+
+/// A user page and the permissions it was mapped with.
+struct UserPage {
+    backing: Backing,
+    flags: PteFlags,
+}
+
+impl UserPage {
+    fn addr(&self) -> usize {
+        match &self.backing {
+            Backing::Owned(frame) => frame.addr(),
+            Backing::Shared(object, index) => object.frame_addr(*index),
+        }
+    }
+
+    fn ppn(&self) -> usize {
+        self.addr() / PAGE_SIZE
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.addr() as *const u8, PAGE_SIZE) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.addr() as *mut u8, PAGE_SIZE) }
+    }
+}
+
+/// A set of virtual-to-physical mappings identified by one `satp` value.
+pub struct AddressSpace {
+    root: PhysFrame,
+    tables: Vec<PhysFrame>,
+    pages: BTreeMap<usize, UserPage>,
+    asid: u16,
+}
+
+const fn page_round_down(addr: usize) -> usize {
+    addr & !(PAGE_SIZE - 1)
+}
+
+const fn page_round_up(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+impl PteFlags {
+    /// Converts ELF segment permissions into leaf flags.
+    pub fn from_segment(perms: SegmentPermissions) -> Self {
+        let mut flags = PteFlags::empty();
+        if perms.read {
+            flags |= PteFlags::READ;
+        }
+        if perms.write {
+            flags |= PteFlags::READ | PteFlags::WRITE;
+        }
+        if perms.execute {
+            flags |= PteFlags::EXECUTE;
+        }
+        flags
+    }
+}
+
+impl AddressSpace {
+    /// Builds the kernel template: the low 4GB identity mapped, RAM with
+    /// read-write-execute 2MB pages below level-1 tables and MMIO with
+    /// read-write gigapages, and the table of the kernel stack area.
+    ///
+    /// The level-1 tables of RAM are shared with every user space through
+    /// the copied root entries, so a kernel page later split and protected
+    /// by `kprotect` is protected whatever `satp` is active.
+    pub(super) fn new_kernel() -> Result<Self, MmError> {
+        let root = PhysFrame::alloc(AllocPurpose::PageTable)?;
+        let table = unsafe { page_table::table_at(root.addr()) };
+        let common = PteFlags::VALID | PteFlags::GLOBAL | PteFlags::ACCESSED | PteFlags::DIRTY;
+        let platform = platform::get();
+        let mut tables = Vec::new();
+        for (i, entry) in table.iter_mut().take(KERNEL_ROOT_ENTRIES).enumerate() {
+            let (start, end) = (i << 30, (i + 1) << 30);
+            if platform.ram_banks().any(|ram| start < ram.end() && ram.base < end) {
+                let level1 = PhysFrame::alloc(AllocPurpose::PageTable)?;
+                let megapages = unsafe { page_table::table_at(level1.addr()) };
+                for (j, megapage) in megapages.iter_mut().enumerate() {
+                    let flags = common | PteFlags::READ | PteFlags::WRITE | PteFlags::EXECUTE;
+                    *megapage = PageTableEntry::new((i << 18) | (j << 9), flags);
+                }
+                *entry = PageTableEntry::new(level1.ppn(), PteFlags::VALID);
+                tables.push(level1);
+            } else {
+                *entry = PageTableEntry::new(i << 18, common | PteFlags::READ | PteFlags::WRITE);
+            }
+        }
+        // Created up front so that user spaces can share the table.
+        let stacks = PhysFrame::alloc(AllocPurpose::PageTable)?;
+        table[KSTACK_ROOT_INDEX] = PageTableEntry::new(stacks.ppn(), PteFlags::VALID);
+        tables.push(stacks);
+        Ok(Self { root, tables, pages: BTreeMap::new(), asid: KERNEL_ASID })
+    }
+
+    /// Creates an empty user address space sharing the kernel half.
+    pub fn new_user() -> Result<Self, MmError> {
+        let kernel_root = super::kernel_root().ok_or(MmError::NotInitialized)?;
+        let root = PhysFrame::alloc(AllocPurpose::PageTable)?;
+        let src = unsafe { page_table::table_at(kernel_root) };
+        let dst = unsafe { page_table::table_at(root.addr()) };
+        dst[..KERNEL_ROOT_ENTRIES].copy_from_slice(&src[..KERNEL_ROOT_ENTRIES]);
+        dst[KSTACK_ROOT_INDEX] = src[KSTACK_ROOT_INDEX];
+        Ok(Self { root, tables: Vec::new(), pages: BTreeMap::new(), asid: asid::alloc() })
+    }
+
+    /// Returns the `satp` value that activates this address space.
+    pub fn satp(&self) -> usize {
+        SATP_MODE_SV39 | ((self.asid as usize) << 44) | self.root.ppn()
+    }
+
+    /// Returns the ASID tagging this address space's translations.
+    pub fn asid(&self) -> u16 {
+        self.asid
+    }
+
+    /// Returns the physical address of the root table.
+    pub fn root_addr(&self) -> usize {
+        self.root.addr()
+    }
+
+    /// Returns the number of mapped user pages.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn check_user_range(start: usize, len: usize) -> Result<(), MmError> {
+        let end = start.checked_add(len).ok_or(MmError::InvalidAddress)?;
+        if start < USER_SPACE_START || end > USER_SPACE_END {
+            return Err(MmError::InvalidAddress);
+        }
+        Ok(())
+    }
+
+    /// Maps one zeroed user page at `vaddr` with `flags`.
+    ///
+    /// If the page is already mapped, the new permissions are merged into the
+    /// existing mapping instead, so segments sharing a page keep both.
+    pub fn map_page(&mut self, vaddr: usize, flags: PteFlags) -> Result<(), MmError> {
+        if vaddr % PAGE_SIZE != 0 {
+            return Err(MmError::Misaligned);
+        }
+        Self::check_user_range(vaddr, PAGE_SIZE)?;
+
+        let leaf = flags | PteFlags::VALID | PteFlags::USER | PteFlags::ACCESSED | PteFlags::DIRTY;
+        let vpn = vaddr / PAGE_SIZE;
+        if let Some(page) = self.pages.get_mut(&vpn) {
+            // Shared pages keep the permissions checked at map time.
+            if let Backing::Shared(..) = page.backing {
+                return Err(MmError::AlreadyMapped);
+            }
+            page.flags |= leaf;
+            let entry = page_table::walk_create(self.root.addr(), vaddr, &mut self.tables)?;
+            *entry = PageTableEntry::new(page.ppn(), page.flags);
+            asid::flush(self.asid);
+            return Ok(());
+        }
+
+        let frame = PhysFrame::alloc(AllocPurpose::UserData)?;
+        self.insert_page(vaddr, UserPage { backing: Backing::Owned(frame), flags: leaf })
+    }
+
+    /// Installs a leaf for `page` at the unmapped address `vaddr`.
+    fn insert_page(&mut self, vaddr: usize, page: UserPage) -> Result<(), MmError> {
+        let entry = page_table::walk_create(self.root.addr(), vaddr, &mut self.tables)?;
+        *entry = PageTableEntry::new(page.ppn(), page.flags);
+        self.pages.insert(vaddr / PAGE_SIZE, page);
+        Ok(())
+    }
+
+    /// Maps all of `object` at `vaddr` with `flags`.
+    ///
+    /// Fails with `PermissionDenied` if `flags` exceeds the object's
+    /// permissions, and with `AlreadyMapped` if any page of the target range
+    /// is in use. The address space holds a reference to the object until the
+    /// pages are unmapped.
+    pub fn map_shared(
+        &mut self,
+        vaddr: usize,
+        object: &Arc<SharedMemory>,
+        flags: PteFlags,
+    ) -> Result<(), MmError> {
+        if vaddr % PAGE_SIZE != 0 {
+            return Err(MmError::Misaligned);
+        }
+        Self::check_user_range(vaddr, object.size())?;
+        object.check_permissions(flags)?;
+        let first = vaddr / PAGE_SIZE;
+        if self.pages.range(first..first + object.page_count()).next().is_some() {
+            return Err(MmError::AlreadyMapped);
+        }
+
+        let leaf = flags | PteFlags::VALID | PteFlags::USER | PteFlags::ACCESSED | PteFlags::DIRTY;
+        for index in 0..object.page_count() {
+            let page = UserPage { backing: Backing::Shared(Arc::clone(object), index), flags: leaf };
+            if let Err(e) = self.insert_page(vaddr + index * PAGE_SIZE, page) {
+                let _ = self.unmap_range(vaddr, index * PAGE_SIZE);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps every page overlapping `[start, start + len)`.
+    pub fn map_range(&mut self, start: usize, len: usize, flags: PteFlags) -> Result<(), MmError> {
+        let end = page_round_up(start.checked_add(len).ok_or(MmError::InvalidAddress)?);
+        let mut vaddr = page_round_down(start);
+        while vaddr < end {
+            self.map_page(vaddr, flags)?;
+            vaddr += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Unmaps every page overlapping `[start, start + len)` and releases the
+    /// backing frames, or the references to shared objects. Unmapped holes
+    /// in the range are ignored.
+    pub fn unmap_range(&mut self, start: usize, len: usize) -> Result<(), MmError> {
+        let end = page_round_up(start.checked_add(len).ok_or(MmError::InvalidAddress)?);
+        let mut vaddr = page_round_down(start);
+        let mut removed = Vec::new();
+        while vaddr < end {
+            if let Some(page) = self.pages.remove(&(vaddr / PAGE_SIZE)) {
+                if let Some((entry, _)) = page_table::walk(self.root.addr(), vaddr) {
+                    entry.clear();
+                }
+                removed.push(page);
+            }
+            vaddr += PAGE_SIZE;
+        }
+        asid::flush(self.asid);
+        // Frames are released only once no stale translation can reach them.
+        drop(removed);
+        Ok(())
+    }
+
+    /// Creates a new user address space holding an eager copy of every
+    /// private page, with the same permissions. Shared pages are mapped to
+    /// the same objects instead of being copied.
+    pub fn try_clone(&self) -> Result<Self, MmError> {
+        let mut child = Self::new_user()?;
+        for (vpn, page) in self.pages.iter() {
+            let vaddr = vpn * PAGE_SIZE;
+            match &page.backing {
+                Backing::Owned(frame) => {
+                    child.map_page(vaddr, page.flags)?;
+                    child.copy_to(vaddr, frame.as_bytes())?;
+                }
+                Backing::Shared(object, index) => {
+                    let shared = UserPage {
+                        backing: Backing::Shared(Arc::clone(object), *index),
+                        flags: page.flags,
+                    };
+                    child.insert_page(vaddr, shared)?;
+                }
+            }
+        }
+        Ok(child)
+    }
+
+    /// Translates a user virtual address to its physical address.
+    pub fn translate(&self, vaddr: usize) -> Option<usize> {
+        let page = self.pages.get(&(vaddr / PAGE_SIZE))?;
+        Some(page.addr() + vaddr % PAGE_SIZE)
+    }
+
+    /// Returns the leaf flags of the user page containing `vaddr`.
+    pub fn flags(&self, vaddr: usize) -> Option<PteFlags> {
+        self.pages.get(&(vaddr / PAGE_SIZE)).map(|page| page.flags)
+    }
+
+    /// Copies `data` into mapped user memory at `vaddr`, ignoring page
+    /// permissions.
+    pub fn copy_to(&mut self, vaddr: usize, data: &[u8]) -> Result<(), MmError> {
+        let mut done = 0;
+        while done < data.len() {
+            let addr = vaddr.checked_add(done).ok_or(MmError::InvalidAddress)?;
+            let offset = addr % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - offset).min(data.len() - done);
+            let page = self.pages.get_mut(&(addr / PAGE_SIZE)).ok_or(MmError::NotMapped)?;
+            page.bytes_mut()[offset..offset + chunk].copy_from_slice(&data[done..done + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Copies mapped user memory at `vaddr` into `buf`.
+    pub fn copy_from(&self, vaddr: usize, buf: &mut [u8]) -> Result<(), MmError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let addr = vaddr.checked_add(done).ok_or(MmError::InvalidAddress)?;
+            let offset = addr % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - offset).min(buf.len() - done);
+            let page = self.pages.get(&(addr / PAGE_SIZE)).ok_or(MmError::NotMapped)?;
+            buf[done..done + chunk].copy_from_slice(&page.bytes()[offset..offset + chunk]);
+            done += chunk;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // Stale translations must not outlive the frames they point to.
+        asid::flush(self.asid);
+        asid::free(self.asid);
+    }
+}
+
+impl ImageMapper for AddressSpace {
+    fn map_segment(
+        &mut self,
+        vaddr: usize,
+        mem_size: usize,
+        perms: SegmentPermissions,
+        data: &[u8],
+    ) -> Result<(), ElfError> {
+        self.map_range(vaddr, mem_size, PteFlags::from_segment(perms))
+            .map_err(|_| ElfError::MapFailed)?;
+        // Frames start zeroed, so only the file-backed part needs copying.
+        self.copy_to(vaddr, data).map_err(|_| ElfError::MapFailed)
+    }
+
+    fn map_stack(&mut self, top: usize, size: usize) -> Result<(), ElfError> {
+        let start = top.checked_sub(size).ok_or(ElfError::MapFailed)?;
+        self.map_range(start, size, PteFlags::READ | PteFlags::WRITE)
+            .map_err(|_| ElfError::MapFailed)
+    }
+
+    fn write_bytes(&mut self, vaddr: usize, data: &[u8]) -> Result<(), ElfError> {
+        self.copy_to(vaddr, data).map_err(|_| ElfError::MapFailed)
+    }
+}
@@ -0,0 +1,90 @@
+// nt_rustos/src/mm/asid.rs
+
+//! # ASID Management
+//!
+//! Allocates address space identifiers for user address spaces and issues
+//! the matching TLB shootdowns. ASID 0 is reserved for the kernel address
+//! space; when the hardware implements no ASID bits, or all ASIDs are in use,
+//! address spaces fall back to ASID 0 and flushes become global.
+
+use crate::util::sbi::rfence;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// ASID shared by the kernel and by address spaces that could not get one.
+pub const KERNEL_ASID: u16 = 0;
+
+struct AsidAllocator {
+    /// Largest usable ASID (0 if ASIDs are not implemented).
+    max: u16,
+    next: u16,
+    free: Vec<u16>,
+}
+
+static ALLOCATOR: Mutex<AsidAllocator> = Mutex::new(AsidAllocator { max: 0, next: 1, free: Vec::new() });
+
+/// Harts that have enabled paging and may cache translations. Bit 0 is the
+/// boot hart.
+static ACTIVE_HARTS: AtomicUsize = AtomicUsize::new(1);
+
+/// Sets the largest ASID the hardware supports. Called once by `mm::init`.
+pub(super) fn set_max_asid(max: u16) {
+    ALLOCATOR.lock().max = max;
+}
+
+/// Returns the largest usable ASID.
+pub fn max_asid() -> u16 {
+    ALLOCATOR.lock().max
+}
+
+/// Allocates a fresh ASID, or `KERNEL_ASID` if none is available.
+pub fn alloc() -> u16 {
+    let mut allocator = ALLOCATOR.lock();
+    if let Some(asid) = allocator.free.pop() {
+        return asid;
+    }
+    if allocator.next != 0 && allocator.next <= allocator.max {
+        let asid = allocator.next;
+        allocator.next = allocator.next.wrapping_add(1);
+        return asid;
+    }
+    KERNEL_ASID
+}
+
+/// Returns `asid` to the allocator. The caller must have flushed it first.
+pub fn free(asid: u16) {
+    if asid != KERNEL_ASID {
+        ALLOCATOR.lock().free.push(asid);
+    }
+}
+
+/// Marks `hartid` as running with paging enabled, so it receives shootdowns.
+pub fn mark_hart_active(hartid: usize) {
+    ACTIVE_HARTS.fetch_or(1 << hartid, Ordering::SeqCst);
+}
+
+/// Flushes every translation tagged with `asid` on all active harts.
+///
+/// Flushing `KERNEL_ASID` flushes all non-global translations, because
+/// address spaces without a private ASID share it.
+pub fn flush(asid: u16) {
+    unsafe {
+        if asid == KERNEL_ASID {
+            asm!("sfence.vma zero, zero");
+        } else {
+            asm!("sfence.vma zero, {}", in(reg) asid as usize);
+        }
+    }
+
+    // The current hart is assumed to be hart 0 until per-hart state exists.
+    let others = ACTIVE_HARTS.load(Ordering::SeqCst) & !1;
+    if others != 0 {
+        let _ = if asid == KERNEL_ASID {
+            rfence::remote_sfence_vma(others, 0, usize::MAX)
+        } else {
+            rfence::remote_sfence_vma_asid(others, 0, usize::MAX, asid as usize)
+        };
+    }
+}
@@ -0,0 +1,57 @@
+// nt_rustos/src/mm/frame.rs
+
+//! # Physical Frames
+//!
+//! Page-sized, page-aligned physical frames carved out of the early heap.
+//! The kernel runs identity mapped, so a frame's physical address is also
+//! directly dereferenceable by kernel code.
+
+use super::{guard, MmError, PAGE_SIZE};
+use crate::init::alloc::{self, AllocPurpose};
+
+/// An owned physical frame, returned to the heap on drop.
+#[derive(Debug)]
+pub struct PhysFrame {
+    addr: usize,
+}
+
+impl PhysFrame {
+    /// Allocates a zeroed frame tagged with `purpose`.
+    pub fn alloc(purpose: AllocPurpose) -> Result<Self, MmError> {
+        let ptr = alloc::alloc_aligned(PAGE_SIZE, PAGE_SIZE).ok_or(MmError::OutOfMemory)?;
+        // Firmware memory is neither zeroed nor handed back to the heap,
+        // which would give it out again.
+        guard::check(ptr as usize, PAGE_SIZE, "page frame").map_err(|_| MmError::Reserved)?;
+        unsafe {
+            core::ptr::write_bytes(ptr, 0, PAGE_SIZE);
+        }
+        let _ = alloc::set_purpose(ptr, purpose);
+        Ok(Self { addr: ptr as usize })
+    }
+
+    /// Returns the physical address of the frame.
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    /// Returns the physical page number of the frame.
+    pub fn ppn(&self) -> usize {
+        self.addr / PAGE_SIZE
+    }
+
+    /// Returns the frame contents as a byte slice.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.addr as *const u8, PAGE_SIZE) }
+    }
+
+    /// Returns the frame contents as a mutable byte slice.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.addr as *mut u8, PAGE_SIZE) }
+    }
+}
+
+impl Drop for PhysFrame {
+    fn drop(&mut self) {
+        alloc::dealloc(self.addr as *mut u8);
+    }
+}
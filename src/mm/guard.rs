@@ -0,0 +1,217 @@
+// nt_rustos/src/mm/guard.rs
+
+//! # Firmware Memory Guard
+//!
+//! S-mode cannot read the PMP registers, so the kernel does not learn from
+//! the hardware which RAM the firmware protects; touching it raises access
+//! faults, or worse, silently corrupts the firmware if its PMP is open.
+//! Instead the guard collects the ranges the firmware announces as its own:
+//!
+//! - the memory reservation block of the device tree (`/memreserve/`);
+//! - the `reg` ranges of the children of `/reserved-memory`, where OpenSBI
+//!   lists the regions it protects with PMP (`mmode_resv*`);
+//! - the device tree blob itself;
+//! - RAM below the kernel image, where the firmware was loaded;
+//! - the crash dump region at the end of RAM (see `crashdump`), which is
+//!   the kernel's own but must survive into the next boot.
+//!
+//! The early heap is placed clear of these ranges with `clip`, and frame
+//! allocations are verified with `check`, which reports an overlap as an
+//! `ErrorSource::Memory` error through the trap system.
+//!
+//! The ranges are collected before the heap exists, so the table is a fixed
+//! array; ranges beyond `MAX_RESERVED` are counted but not guarded.
+
+use crate::boot;
+use crate::fdt::Fdt;
+use crate::init::initcall::InitResult;
+use crate::platform::{self, Region};
+use crate::{error_print, info_print, println, time, trap};
+use alloc::format;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+
+/// Reserved ranges the guard keeps track of.
+pub const MAX_RESERVED: usize = 16;
+
+/// Error code of a guard violation within `ErrorSource::Memory`.
+const VIOLATION_CODE: u16 = 1;
+
+/// A range of physical memory the kernel must not allocate from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub region: Region,
+    /// Where the range comes from: a node name, or `memreserve`, `dtb`,
+    /// `firmware` or `crashdump`.
+    pub name: &'static str,
+}
+
+struct Table {
+    entries: [Reservation; MAX_RESERVED],
+    len: usize,
+    /// Ranges that did not fit.
+    dropped: usize,
+}
+
+impl Table {
+    const EMPTY: Reservation = Reservation { region: Region { base: 0, size: 0 }, name: "" };
+
+    fn push(&mut self, base: u64, size: u64, name: &'static str) {
+        if size == 0 {
+            return;
+        }
+        if self.len == MAX_RESERVED {
+            self.dropped += 1;
+            return;
+        }
+        self.entries[self.len] = Reservation { region: Region { base: base as usize, size: size as usize }, name };
+        self.len += 1;
+    }
+}
+
+static TABLE: Once<Table> = Once::new();
+static VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// A range that overlaps reserved memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub base: usize,
+    pub size: usize,
+    /// The first reservation the range overlaps.
+    pub reservation: Reservation,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = Region { base: self.base, size: self.size };
+        write!(f, "{} overlaps reserved {} ({})", range, self.reservation.region, self.reservation.name)
+    }
+}
+
+fn collect() -> Table {
+    let mut table = Table { entries: [Table::EMPTY; MAX_RESERVED], len: 0, dropped: 0 };
+    extern "C" {
+        fn skernel(); // 链接器提供的内核起始地址
+    }
+    let ram = platform::get().ram;
+    let kernel_start = skernel as usize;
+    if ram.contains(kernel_start) {
+        table.push(ram.base as u64, (kernel_start - ram.base) as u64, "firmware");
+    }
+    if let Some(region) = crate::crashdump::region() {
+        table.push(region.base as u64, region.size as u64, "crashdump");
+    }
+
+    let Some(info) = boot::boot_info() else {
+        return table;
+    };
+    let Ok(fdt) = (unsafe { Fdt::from_addr(info.dtb_addr) }) else {
+        return table;
+    };
+    table.push(info.dtb_addr as u64, fdt.total_size() as u64, "dtb");
+    for entry in fdt.memory_reservations() {
+        table.push(entry.address, entry.size, "memreserve");
+    }
+    // The children of `/reserved-memory` follow it at the next depth.
+    let mut parent_depth = None;
+    for node in fdt.nodes().filter_map(Result::ok) {
+        match parent_depth {
+            Some(depth) if node.depth > depth => {
+                if node.depth == depth + 1 {
+                    for entry in node.reg() {
+                        table.push(entry.address, entry.size, node.name);
+                    }
+                }
+                continue;
+            }
+            _ => parent_depth = None,
+        }
+        if node.depth == 1 && node.base_name() == "reserved-memory" {
+            parent_depth = Some(node.depth);
+        }
+    }
+    table
+}
+
+/// Collects the reserved ranges. Only the first call has an effect; it must
+/// follow `platform::init`.
+pub fn init() {
+    TABLE.call_once(collect);
+}
+
+/// Returns the reserved ranges; empty before `init`.
+pub fn reserved() -> &'static [Reservation] {
+    TABLE.get().map_or(&[], |table| &table.entries[..table.len])
+}
+
+/// Returns the first reservation overlapping `base..base + size`.
+pub fn overlapping(base: usize, size: usize) -> Option<Reservation> {
+    let end = base.saturating_add(size);
+    reserved().iter().copied().find(|r| r.region.base < end && base < r.region.end())
+}
+
+/// Returns the largest part of `base..base + size` that overlaps no
+/// reservation and starts at the lowest possible address.
+pub fn clip(base: usize, size: usize) -> Region {
+    let end = base.saturating_add(size);
+    let mut start = base;
+    // Move past every reservation covering the start.
+    while let Some(r) = reserved().iter().find(|r| start < end && r.region.contains(start)) {
+        start = r.region.end().min(end);
+    }
+    // Stop at the first reservation after it.
+    let end = reserved()
+        .iter()
+        .map(|r| r.region.base)
+        .filter(|&b| b > start)
+        .fold(end, usize::min);
+    Region { base: start, size: end.saturating_sub(start) }
+}
+
+/// Checks that `base..base + size`, allocated for `what`, overlaps no
+/// reservation. A violation is counted and reported as an
+/// `ErrorSource::Memory` error.
+pub fn check(base: usize, size: usize, what: &str) -> Result<(), Violation> {
+    let Some(reservation) = overlapping(base, size) else {
+        return Ok(());
+    };
+    let violation = Violation { base, size, reservation };
+    VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    let code = trap::ErrorCode::new(trap::ErrorSource::Memory, trap::ErrorLevel::Error, VIOLATION_CODE);
+    let message = format!("{}: {}", what, violation);
+    let error = trap::create_system_error(code, message, Some(base), 0, time::monotonic_us());
+    if trap::report_system_error(error) == trap::ErrorResult::Unhandled {
+        error_print!("Memory guard: {}: {}", what, violation);
+    }
+    Err(violation)
+}
+
+/// Returns how many allocations `check` has rejected.
+pub fn violations() -> u64 {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
+
+/// Prints the reserved ranges.
+pub fn print() {
+    println!("Reserved memory:");
+    for r in reserved() {
+        println!("  {} {}", r.region, r.name);
+    }
+    if let Some(table) = TABLE.get().filter(|t| t.dropped > 0) {
+        println!("  ({} more not guarded)", table.dropped);
+    }
+    println!("  {} violation(s)", violations());
+}
+
+/// Collects the reserved ranges before the heap is placed.
+fn collect_at_boot() -> InitResult {
+    init();
+    info_print!("Memory guard: {} reserved range(s).", reserved().len());
+    if let Some(table) = TABLE.get().filter(|t| t.dropped > 0) {
+        error_print!("Memory guard: {} reserved range(s) beyond {} not guarded.", table.dropped, MAX_RESERVED);
+    }
+    Ok(())
+}
+
+crate::initcall!(early, 45, collect_at_boot);
@@ -0,0 +1,97 @@
+// nt_rustos/src/mm/kprotect.rs
+
+//! # Kernel Page Protection
+//!
+//! Changes the permissions of single 4KB pages of the kernel's identity
+//! mapping of RAM. RAM is mapped with 2MB pages below level-1 tables that
+//! every address space shares (see `AddressSpace::new_kernel`); `split`
+//! replaces the 2MB page covering an address by a table of 4KB pages with
+//! the same permissions, after which `set_writable` can change one of
+//! them, through whatever `satp` is active.
+//!
+//! Split tables are kept for good. `set_writable` only rewrites an entry
+//! and flushes its translation, so it may be called from trap handlers.
+
+use super::frame::PhysFrame;
+use super::page_table::{self, PageTableEntry, PteFlags, ENTRIES_PER_TABLE};
+use super::{MmError, PAGE_SIZE};
+use crate::init::alloc::AllocPurpose;
+use crate::platform;
+use crate::smp;
+use crate::task::scheduler::current_hart;
+use crate::util::sbi::rfence;
+use alloc::vec::Vec;
+use core::arch::asm;
+use spin::Mutex;
+
+/// Level of 2MB entries in a walk (0 = 1GB, 2 = 4KB).
+const MEGAPAGE_LEVEL: usize = 1;
+const PAGE_LEVEL: usize = 2;
+
+/// Tables created by `split`.
+static SPLIT_TABLES: Mutex<Vec<PhysFrame>> = Mutex::new(Vec::new());
+
+/// Flushes the translations of `[start, start + len)` on every online hart.
+fn flush(start: usize, len: usize) {
+    unsafe {
+        if len == PAGE_SIZE {
+            asm!("sfence.vma {}, zero", in(reg) start);
+        } else {
+            asm!("sfence.vma zero, zero");
+        }
+    }
+    let others = smp::online_mask() & !(1 << current_hart());
+    if others != 0 {
+        let _ = rfence::remote_sfence_vma(others as usize, start, len);
+    }
+}
+
+/// Maps the 4KB page at `vaddr`, which must lie in RAM, with a page of its
+/// own, splitting the 2MB page that covers it if needed.
+pub fn split(vaddr: usize) -> Result<(), MmError> {
+    let root = super::kernel_root().ok_or(MmError::NotInitialized)?;
+    if !platform::get().in_ram(vaddr) {
+        return Err(MmError::InvalidAddress);
+    }
+    let mut tables = SPLIT_TABLES.lock();
+    let (entry, level) = page_table::walk(root, vaddr).ok_or(MmError::NotMapped)?;
+    match level {
+        PAGE_LEVEL => return Ok(()),
+        MEGAPAGE_LEVEL if entry.is_leaf() => {}
+        _ => return Err(MmError::AlreadyMapped),
+    }
+    let frame = PhysFrame::alloc(AllocPurpose::PageTable)?;
+    let pages = unsafe { page_table::table_at(frame.addr()) };
+    for (i, page) in pages.iter_mut().enumerate() {
+        *page = PageTableEntry::new(entry.ppn() + i, entry.flags());
+    }
+    *entry = PageTableEntry::new(frame.ppn(), PteFlags::VALID);
+    tables.push(frame);
+    let megapage = vaddr & !(ENTRIES_PER_TABLE * PAGE_SIZE - 1);
+    flush(megapage, ENTRIES_PER_TABLE * PAGE_SIZE);
+    Ok(())
+}
+
+/// Makes the kernel page at `vaddr` writable or read-only. The page must
+/// have been split.
+pub fn set_writable(vaddr: usize, writable: bool) -> Result<(), MmError> {
+    let root = super::kernel_root().ok_or(MmError::NotInitialized)?;
+    let (entry, level) = page_table::walk(root, vaddr).ok_or(MmError::NotMapped)?;
+    if level != PAGE_LEVEL {
+        return Err(MmError::AlreadyMapped);
+    }
+    let flags = match writable {
+        true => entry.flags() | PteFlags::WRITE,
+        false => PteFlags::from_bits(entry.flags().bits() & !PteFlags::WRITE.bits()),
+    };
+    *entry = PageTableEntry::new(entry.ppn(), flags);
+    flush(vaddr & !(PAGE_SIZE - 1), PAGE_SIZE);
+    Ok(())
+}
+
+/// Whether the kernel page at `vaddr` is mapped writable.
+pub fn is_writable(vaddr: usize) -> bool {
+    super::kernel_root()
+        .and_then(|root| page_table::translate(root, vaddr))
+        .is_some_and(|(_, flags)| flags.contains(PteFlags::WRITE))
+}
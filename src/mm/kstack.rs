@@ -0,0 +1,130 @@
+// nt_rustos/src/mm/kstack.rs
+
+//! # Kernel Stack Area
+//!
+//! Kernel thread stacks are mapped into a dedicated 1GB area at the top of
+//! the Sv39 address space instead of being carved out of the identity
+//! mapped heap, where they could not be given guard pages: RAM is mapped
+//! with large pages.
+//!
+//! Each stack takes a region of the area sized to it: an unmapped guard
+//! page followed by the stack, so running off the bottom of a stack faults
+//! instead of corrupting a neighbour. The regions in use are kept in a
+//! `RangeMap`, and a new stack goes into the lowest gap it fits. The
+//! area's level-1 table is created with the kernel template and shared by
+//! every user address space, so a stack is reachable whatever `satp` is
+//! active.
+
+use super::frame::PhysFrame;
+use super::page_table::{self, PageTableEntry, PteFlags};
+use super::{MmError, PAGE_SIZE};
+use crate::init::alloc::AllocPurpose;
+use crate::trap::collections::RangeMap;
+use alloc::vec::Vec;
+use core::arch::asm;
+use spin::Mutex;
+
+/// Lowest address of the kernel stack area (root entry 511).
+pub const KSTACK_AREA_START: usize = 0xFFFF_FFFF_C000_0000;
+/// Root table index covering the kernel stack area.
+pub(super) const KSTACK_ROOT_INDEX: usize = 511;
+/// Size of the area in bytes.
+const AREA_SIZE: usize = 1 << 30;
+
+/// Regions in use and the page tables created below the area's root entry.
+struct Area {
+    /// Regions handed out, as offsets into the area.
+    regions: RangeMap<()>,
+    tables: Vec<PhysFrame>,
+}
+
+static AREA: Mutex<Area> = Mutex::new(Area { regions: RangeMap::new(), tables: Vec::new() });
+
+/// A kernel stack mapped in the stack area, with an unmapped guard page
+/// below it. Unmapped and returned to the area on drop.
+pub struct GuardedStack {
+    /// Offset of the stack's region (its guard page) in the area.
+    region: usize,
+    top: usize,
+    frames: Vec<PhysFrame>,
+}
+
+impl GuardedStack {
+    /// Maps a new stack of `size` bytes (rounded up to whole pages).
+    pub fn alloc(size: usize) -> Result<Self, MmError> {
+        let pages = size.div_ceil(PAGE_SIZE);
+        if pages == 0 || pages >= AREA_SIZE / PAGE_SIZE {
+            return Err(MmError::InvalidSize);
+        }
+        let root = super::kernel_root().ok_or(MmError::NotInitialized)?;
+
+        let len = (pages + 1) * PAGE_SIZE;
+        let region = {
+            let mut area = AREA.lock();
+            // Not the last page: the top of a stack there would be 2^64.
+            let bounds = 0..AREA_SIZE - PAGE_SIZE;
+            let region = area.regions.find_gap(len, PAGE_SIZE, bounds).ok_or(MmError::OutOfMemory)?;
+            area.regions.insert(region..region + len, ()).map_err(|_| MmError::OutOfMemory)?;
+            region
+        };
+        // Mapped from the top down, so `bottom` always matches the pages
+        // mapped so far and a failure unmaps exactly those.
+        let top = KSTACK_AREA_START + region + len;
+        let mut stack = Self { region, top, frames: Vec::with_capacity(pages) };
+        let flags = PteFlags::VALID
+            | PteFlags::READ
+            | PteFlags::WRITE
+            | PteFlags::GLOBAL
+            | PteFlags::ACCESSED
+            | PteFlags::DIRTY;
+        for _ in 0..pages {
+            let frame = PhysFrame::alloc(AllocPurpose::KernelStack)?;
+            let vaddr = stack.bottom() - PAGE_SIZE;
+            let mut area = AREA.lock();
+            let entry = page_table::walk_create(root, vaddr, &mut area.tables)?;
+            *entry = PageTableEntry::new(frame.ppn(), flags);
+            stack.frames.push(frame);
+        }
+        Ok(stack)
+    }
+
+    /// Returns the lowest mapped address of the stack.
+    pub fn bottom(&self) -> usize {
+        self.top() - self.size()
+    }
+
+    /// Returns the address one past the highest byte of the stack.
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    /// Returns the size of the mapped stack in bytes.
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// Returns the address of the unmapped page right below the stack.
+    pub fn guard_page(&self) -> usize {
+        self.bottom() - PAGE_SIZE
+    }
+}
+
+impl Drop for GuardedStack {
+    fn drop(&mut self) {
+        if let Some(root) = super::kernel_root() {
+            let mut page = self.bottom();
+            while page < self.top() {
+                if let Some((entry, _)) = page_table::walk(root, page) {
+                    entry.clear();
+                }
+                // The mapping is global, so it is flushed from every ASID.
+                unsafe {
+                    asm!("sfence.vma {}, zero", in(reg) page);
+                }
+                page += PAGE_SIZE;
+            }
+        }
+        AREA.lock().regions.remove(self.region);
+        // `frames` are released after their translations are gone.
+    }
+}
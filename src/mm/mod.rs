@@ -0,0 +1,162 @@
+// nt_rustos/src/mm/mod.rs
+
+//! # Memory Management
+//!
+//! Sv39 paging for the kernel and per-process user address spaces.
+//!
+//! The kernel keeps running at its physical addresses: `init` builds a
+//! kernel template that identity maps the low 4GB with global pages (2MB
+//! pages for RAM, gigapages for MMIO) and enables translation. Every user `AddressSpace` shares that kernel half
+//! and adds its own mappings above `USER_SPACE_START`. Kernel thread stacks
+//! are mapped separately, with guard pages, at the top of the address
+//! space.
+
+pub mod address_space;
+pub mod asid;
+pub mod frame;
+pub mod guard;
+pub mod kprotect;
+pub mod kstack;
+pub mod page_table;
+pub mod shm;
+pub mod uaccess;
+
+pub use self::address_space::AddressSpace;
+pub use self::frame::PhysFrame;
+pub use self::kstack::GuardedStack;
+pub use self::page_table::{PageTableEntry, PteFlags};
+pub use self::shm::SharedMemory;
+pub use self::uaccess::{copy_from_user, copy_to_user, strncpy_from_user};
+
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use alloc::format;
+use core::arch::asm;
+use core::fmt;
+use spin::Once;
+
+/// Size of a base page.
+pub const PAGE_SIZE: usize = 4096;
+/// Lowest user virtual address; everything below belongs to the kernel half.
+pub const USER_SPACE_START: usize = 0x1_0000_0000;
+/// Exclusive upper bound of user virtual addresses (lower half of Sv39).
+pub const USER_SPACE_END: usize = 0x40_0000_0000;
+
+/// Errors produced by the memory management subsystem.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MmError {
+    /// Paging has not been set up yet.
+    NotInitialized,
+    /// No frame could be allocated.
+    OutOfMemory,
+    /// The address is not page aligned.
+    Misaligned,
+    /// The address lies outside the user range.
+    InvalidAddress,
+    /// The address is covered by a conflicting mapping.
+    AlreadyMapped,
+    /// The address is not mapped.
+    NotMapped,
+    /// A user access faulted or lacked the required permission.
+    Fault,
+    /// The requested size is zero or too large.
+    InvalidSize,
+    /// The mapping asks for more than the object allows.
+    PermissionDenied,
+    /// A shared memory object with this name already exists.
+    NameInUse,
+    /// No shared memory object has this name.
+    NoSuchObject,
+    /// The memory is reserved by the firmware.
+    Reserved,
+}
+
+impl fmt::Display for MmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInitialized => write!(f, "paging not initialized"),
+            Self::OutOfMemory => write!(f, "out of physical frames"),
+            Self::Misaligned => write!(f, "address not page aligned"),
+            Self::InvalidAddress => write!(f, "address outside user range"),
+            Self::AlreadyMapped => write!(f, "address already mapped"),
+            Self::NotMapped => write!(f, "address not mapped"),
+            Self::Fault => write!(f, "bad user memory access"),
+            Self::InvalidSize => write!(f, "invalid size"),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::NameInUse => write!(f, "shared memory name in use"),
+            Self::NoSuchObject => write!(f, "no such shared memory object"),
+            Self::Reserved => write!(f, "memory reserved by firmware"),
+        }
+    }
+}
+
+static KERNEL_SPACE: Once<AddressSpace> = Once::new();
+
+/// Builds the kernel address space, enables Sv39 and probes the ASID width.
+pub fn init() -> Result<(), MmError> {
+    if KERNEL_SPACE.is_completed() {
+        return Ok(());
+    }
+    let space = AddressSpace::new_kernel()?;
+    let space = KERNEL_SPACE.call_once(|| space);
+
+    // Write all-ones into satp.ASID and read back which bits stuck.
+    let probe = space.satp() | (0xFFFF << 44);
+    let asid_bits = unsafe {
+        asm!("csrw satp, {}", "sfence.vma", in(reg) probe);
+        let readback: usize;
+        asm!("csrr {}, satp", out(reg) readback);
+        (readback >> 44) & 0xFFFF
+    };
+    asid::set_max_asid(asid_bits as u16);
+
+    unsafe {
+        asm!("csrw satp, {}", "sfence.vma", in(reg) space.satp());
+        // Let the kernel dereference user pointers (sstatus.SUM).
+        asm!("csrs sstatus, {}", in(reg) 1usize << 18);
+    }
+    Ok(())
+}
+
+/// Enables paging; the heap provides the page table frames.
+fn enable_paging() -> InitResult {
+    init().map_err(|e| format!("failed to enable paging: {}", e))?;
+    info_print!("Sv39 paging enabled (max ASID: {}).", asid::max_asid());
+    Ok(())
+}
+
+crate::initcall!(arch, 10, enable_paging);
+
+/// Returns `true` once the kernel address space is active.
+pub fn is_initialized() -> bool {
+    KERNEL_SPACE.is_completed()
+}
+
+/// Returns the `satp` value of the kernel address space, or 0 (bare) before
+/// `init`.
+pub fn kernel_satp() -> usize {
+    KERNEL_SPACE.get().map(|space| space.satp()).unwrap_or(0)
+}
+
+/// Returns the physical address of the kernel root table.
+pub(crate) fn kernel_root() -> Option<usize> {
+    KERNEL_SPACE.get().map(|space| space.root_addr())
+}
+
+/// Switches the current hart to `satp`.
+///
+/// Address spaces that share `KERNEL_ASID` cannot be told apart by the TLB,
+/// so switching between them flushes all non-global translations.
+pub fn activate(satp: usize) {
+    unsafe {
+        let current: usize;
+        asm!("csrr {}, satp", out(reg) current);
+        if current == satp {
+            return;
+        }
+        asm!("csrw satp, {}", in(reg) satp);
+        if (satp >> 44) & 0xFFFF == asid::KERNEL_ASID as usize {
+            asm!("sfence.vma zero, zero");
+        }
+    }
+}
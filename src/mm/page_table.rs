@@ -0,0 +1,186 @@
+// nt_rustos/src/mm/page_table.rs
+
+//! # Sv39 Page Tables
+//!
+//! Page table entry encoding and three-level table walks for the Sv39
+//! translation scheme.
+
+use super::frame::PhysFrame;
+use super::{MmError, PAGE_SIZE};
+use crate::init::alloc::AllocPurpose;
+use alloc::vec::Vec;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+
+/// Number of entries in a page table.
+pub const ENTRIES_PER_TABLE: usize = 512;
+/// Number of translation levels in Sv39.
+pub const LEVELS: usize = 3;
+
+/// Page table entry permission and status bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PteFlags(u64);
+
+impl PteFlags {
+    pub const VALID: Self = Self(1 << 0);
+    pub const READ: Self = Self(1 << 1);
+    pub const WRITE: Self = Self(1 << 2);
+    pub const EXECUTE: Self = Self(1 << 3);
+    pub const USER: Self = Self(1 << 4);
+    pub const GLOBAL: Self = Self(1 << 5);
+    pub const ACCESSED: Self = Self(1 << 6);
+    pub const DIRTY: Self = Self(1 << 7);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits & 0xFF)
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    /// Returns `true` if any of R/W/X is set, i.e. the entry is a leaf.
+    pub const fn is_leaf(&self) -> bool {
+        self.0 & (Self::READ.0 | Self::WRITE.0 | Self::EXECUTE.0) != 0
+    }
+}
+
+impl BitOr for PteFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for PteFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for PteFlags {
+    type Output = Self;
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+/// A single Sv39 page table entry.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Creates an entry pointing at `ppn` with `flags`.
+    pub const fn new(ppn: usize, flags: PteFlags) -> Self {
+        Self(((ppn as u64) << 10) | flags.bits())
+    }
+
+    pub const fn ppn(&self) -> usize {
+        ((self.0 >> 10) & ((1 << 44) - 1)) as usize
+    }
+
+    pub const fn flags(&self) -> PteFlags {
+        PteFlags::from_bits(self.0)
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        self.0 & PteFlags::VALID.bits() != 0
+    }
+
+    pub const fn is_leaf(&self) -> bool {
+        self.is_valid() && self.flags().is_leaf()
+    }
+
+    /// Returns the physical address the entry points to.
+    pub const fn addr(&self) -> usize {
+        self.ppn() * PAGE_SIZE
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+}
+
+/// A page table occupying exactly one frame.
+pub type PageTable = [PageTableEntry; ENTRIES_PER_TABLE];
+
+/// Splits a virtual address into its `[vpn2, vpn1, vpn0]` indices.
+pub const fn vpn_indices(vaddr: usize) -> [usize; LEVELS] {
+    let vpn = vaddr >> 12;
+    [(vpn >> 18) & 0x1FF, (vpn >> 9) & 0x1FF, vpn & 0x1FF]
+}
+
+/// Returns the table stored at physical address `pa`.
+///
+/// # Safety
+/// `pa` must be the address of a live, identity-mapped page table frame.
+pub unsafe fn table_at(pa: usize) -> &'static mut PageTable {
+    &mut *(pa as *mut PageTable)
+}
+
+/// Walks from `root_pa` to the leaf entry for `vaddr`, allocating missing
+/// intermediate tables. Newly allocated tables are pushed onto `tables`.
+pub fn walk_create(
+    root_pa: usize,
+    vaddr: usize,
+    tables: &mut Vec<PhysFrame>,
+) -> Result<&'static mut PageTableEntry, MmError> {
+    let idx = vpn_indices(vaddr);
+    let mut table = unsafe { table_at(root_pa) };
+    for level in 0..LEVELS - 1 {
+        let entry = &mut table[idx[level]];
+        if !entry.is_valid() {
+            let frame = PhysFrame::alloc(AllocPurpose::PageTable)?;
+            *entry = PageTableEntry::new(frame.ppn(), PteFlags::VALID);
+            tables.push(frame);
+        } else if entry.is_leaf() {
+            // A huge page already covers this address.
+            return Err(MmError::AlreadyMapped);
+        }
+        table = unsafe { table_at(entry.addr()) };
+    }
+    Ok(&mut table[idx[LEVELS - 1]])
+}
+
+/// Walks from `root_pa` to the leaf entry for `vaddr` without allocating.
+///
+/// Returns the entry and the level it was found at (0 = 1GB, 2 = 4KB).
+pub fn walk(root_pa: usize, vaddr: usize) -> Option<(&'static mut PageTableEntry, usize)> {
+    let idx = vpn_indices(vaddr);
+    let mut table = unsafe { table_at(root_pa) };
+    for level in 0..LEVELS {
+        let entry = &mut table[idx[level]];
+        if !entry.is_valid() {
+            return None;
+        }
+        if entry.is_leaf() || level == LEVELS - 1 {
+            return Some((entry, level));
+        }
+        table = unsafe { table_at(entry.addr()) };
+    }
+    None
+}
+
+/// Translates `vaddr` through the table rooted at `root_pa`.
+pub fn translate(root_pa: usize, vaddr: usize) -> Option<(usize, PteFlags)> {
+    let (entry, level) = walk(root_pa, vaddr)?;
+    if !entry.is_leaf() {
+        return None;
+    }
+    // Offset bits covered by the leaf: 30 for 1GB, 21 for 2MB, 12 for 4KB.
+    let shift = 12 + 9 * (LEVELS - 1 - level);
+    let offset = vaddr & ((1 << shift) - 1);
+    Some((((entry.ppn() << 12) & !((1 << shift) - 1)) + offset, entry.flags()))
+}
@@ -0,0 +1,172 @@
+// nt_rustos/src/mm/shm.rs
+
+//! # Shared Memory Objects
+//!
+//! Named, page-granular memory that several address spaces can map at the
+//! same time. Frames are allocated once, at creation, and tagged
+//! `AllocPurpose::SharedMemory`.
+//!
+//! An object is reference counted: every `Arc` handle and every mapped page
+//! keeps it alive. When the last one goes away the frames are freed and the
+//! name is released, so an object created, mapped and then unmapped
+//! everywhere disappears on its own.
+//!
+//! Each object carries the maximum permissions it may be mapped with;
+//! `AddressSpace::map_shared` refuses anything broader.
+
+use super::frame::PhysFrame;
+use super::{MmError, PteFlags, PAGE_SIZE};
+use crate::init::alloc::AllocPurpose;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Names of live objects. Holds weak references only, so the registry never
+/// keeps an object alive by itself.
+static REGISTRY: Mutex<BTreeMap<String, Weak<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+/// A named shared memory object.
+pub struct SharedMemory {
+    name: String,
+    frames: Vec<PhysFrame>,
+    permissions: PteFlags,
+}
+
+impl SharedMemory {
+    /// Returns the name the object was created with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the size in bytes, a whole number of pages.
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// Returns the number of pages.
+    pub fn page_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the maximum permissions the object may be mapped with.
+    pub fn permissions(&self) -> PteFlags {
+        self.permissions
+    }
+
+    /// Returns the physical address of page `index`.
+    pub(super) fn frame_addr(&self, index: usize) -> usize {
+        self.frames[index].addr()
+    }
+
+    /// Checks that `flags` only asks for permissions the object grants.
+    pub(super) fn check_permissions(&self, flags: PteFlags) -> Result<(), MmError> {
+        let requested = flags & (PteFlags::READ | PteFlags::WRITE | PteFlags::EXECUTE);
+        if !self.permissions.contains(requested) {
+            return Err(MmError::PermissionDenied);
+        }
+        Ok(())
+    }
+
+    /// Copies object contents at `offset` into `buf`.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), MmError> {
+        self.check_bounds(offset, buf.len())?;
+        let src = self.frames.iter().map(|f| f.as_bytes());
+        copy_pages(offset, buf.len(), src, |page, range, done| {
+            buf[done..done + range.len()].copy_from_slice(&page[range]);
+        });
+        Ok(())
+    }
+
+    /// Copies `data` into the object at `offset`.
+    pub fn write(&self, offset: usize, data: &[u8]) -> Result<(), MmError> {
+        self.check_bounds(offset, data.len())?;
+        // Shared frames are written concurrently by design; the object only
+        // hands out raw views of them.
+        let dst = self.frames.iter().map(|f| unsafe {
+            core::slice::from_raw_parts_mut(f.addr() as *mut u8, PAGE_SIZE)
+        });
+        copy_pages(offset, data.len(), dst, |page, range, done| {
+            let len = range.len();
+            page[range].copy_from_slice(&data[done..done + len]);
+        });
+        Ok(())
+    }
+
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<(), MmError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size() => Ok(()),
+            _ => Err(MmError::InvalidAddress),
+        }
+    }
+}
+
+/// Walks `[offset, offset + len)` page by page, calling `f` with each page,
+/// the byte range inside it and the number of bytes already processed.
+fn copy_pages<P, I, F>(offset: usize, len: usize, pages: I, mut f: F)
+where
+    I: Iterator<Item = P>,
+    F: FnMut(P, core::ops::Range<usize>, usize),
+{
+    let mut done = 0;
+    for page in pages.skip(offset / PAGE_SIZE) {
+        if done == len {
+            break;
+        }
+        let start = (offset + done) % PAGE_SIZE;
+        let chunk = (PAGE_SIZE - start).min(len - done);
+        f(page, start..start + chunk, done);
+        done += chunk;
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        // Release the name, unless it was already reused by a newer object.
+        let mut registry = REGISTRY.lock();
+        if let Some(weak) = registry.get(&self.name) {
+            if core::ptr::eq(weak.as_ptr(), self) {
+                registry.remove(&self.name);
+            }
+        }
+    }
+}
+
+/// Creates a zeroed object of at least `size` bytes named `name`, mappable
+/// with at most `permissions`.
+pub fn create(name: &str, size: usize, permissions: PteFlags) -> Result<Arc<SharedMemory>, MmError> {
+    if size == 0 {
+        return Err(MmError::InvalidSize);
+    }
+    let pages = size.div_ceil(PAGE_SIZE);
+    let mut frames = Vec::with_capacity(pages);
+    for _ in 0..pages {
+        frames.push(PhysFrame::alloc(AllocPurpose::SharedMemory)?);
+    }
+    let permissions = permissions & (PteFlags::READ | PteFlags::WRITE | PteFlags::EXECUTE);
+    let object = Arc::new(SharedMemory { name: String::from(name), frames, permissions });
+
+    let mut registry = REGISTRY.lock();
+    // Dying objects (no strong references left) no longer own their name.
+    if registry.get(name).is_some_and(|weak| weak.strong_count() > 0) {
+        drop(registry);
+        return Err(MmError::NameInUse);
+    }
+    registry.insert(String::from(name), Arc::downgrade(&object));
+    Ok(object)
+}
+
+/// Opens the live object named `name`.
+pub fn open(name: &str) -> Result<Arc<SharedMemory>, MmError> {
+    REGISTRY
+        .lock()
+        .get(name)
+        .and_then(Weak::upgrade)
+        .ok_or(MmError::NoSuchObject)
+}
+
+/// Returns the number of live objects.
+pub fn object_count() -> usize {
+    REGISTRY.lock().values().filter(|weak| weak.strong_count() > 0).count()
+}
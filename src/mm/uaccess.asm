@@ -0,0 +1,65 @@
+# nt_rustos/src/mm/uaccess.asm
+# 用户内存访问例程
+# 每条可能访问用户地址的指令都在修复表中登记，缺页时跳转到对应的修复入口
+
+.section .text
+.globl __copy_user
+.globl __strncpy_user
+.align 4
+
+# __copy_user(dst, src, len) -> 未复制的字节数
+#   a0: 目标地址
+#   a1: 源地址
+#   a2: 长度
+__copy_user:
+    beqz a2, 2f
+1:
+.Lcopy_load:
+    lb t0, 0(a1)
+.Lcopy_store:
+    sb t0, 0(a0)
+    addi a0, a0, 1
+    addi a1, a1, 1
+    addi a2, a2, -1
+    bnez a2, 1b
+2:
+    mv a0, a2
+    ret
+.Lcopy_fault:
+    # a2 仍为剩余字节数
+    mv a0, a2
+    ret
+
+# __strncpy_user(dst, src, max) -> 字符串长度(不含NUL)；未在max内结束时返回max；出错返回-1
+#   a0: 内核缓冲区
+#   a1: 用户字符串地址
+#   a2: 最大复制长度
+__strncpy_user:
+    li t1, 0
+1:
+    beq t1, a2, 2f
+.Lstr_load:
+    lb t0, 0(a1)
+    sb t0, 0(a0)
+    beqz t0, 2f
+    addi a0, a0, 1
+    addi a1, a1, 1
+    addi t1, t1, 1
+    j 1b
+2:
+    mv a0, t1
+    ret
+.Lstr_fault:
+    li a0, -1
+    ret
+
+# 修复表：(出错指令地址, 修复入口地址)
+.section .rodata
+.balign 8
+.globl __uaccess_fixups
+.globl __uaccess_fixups_end
+__uaccess_fixups:
+    .dword .Lcopy_load, .Lcopy_fault
+    .dword .Lcopy_store, .Lcopy_fault
+    .dword .Lstr_load, .Lstr_fault
+__uaccess_fixups_end:
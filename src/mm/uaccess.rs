@@ -0,0 +1,154 @@
+// nt_rustos/src/mm/uaccess.rs
+
+//! # User Memory Access
+//!
+//! Copies data between kernel buffers and the current task's user address
+//! space. Ranges are first checked against the task's mappings; the copy
+//! itself runs in small assembly routines whose user loads and stores are
+//! listed in a fixup table. If one of them still faults, the page fault
+//! handler resumes at the routine's fixup entry and the copy fails with
+//! `MmError::Fault` instead of panicking the kernel.
+
+use super::{MmError, PteFlags, PAGE_SIZE, USER_SPACE_END, USER_SPACE_START};
+use crate::init::initcall::InitResult;
+use crate::task;
+use crate::trap::{
+    self, Exception, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use alloc::format;
+use core::arch::global_asm;
+
+global_asm!(include_str!("uaccess.asm"));
+
+extern "C" {
+    fn __copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn __strncpy_user(dst: *mut u8, src: *const u8, max: usize) -> isize;
+    static __uaccess_fixups: [FixupEntry; 0];
+    static __uaccess_fixups_end: [FixupEntry; 0];
+}
+
+/// One entry of the fixup table emitted by `uaccess.asm`.
+#[repr(C)]
+struct FixupEntry {
+    /// Address of an instruction that may fault on a user address.
+    insn: usize,
+    /// Address to resume at if it does.
+    fixup: usize,
+}
+
+/// Priority of the fixup handler; it must see kernel faults before anyone else.
+const FIXUP_HANDLER_PRIORITY: u8 = 0;
+
+/// `sstatus.SPP`: the trap was taken from S-mode.
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// Registers the page fault fixup handler with the trap subsystem.
+///
+/// Must be called after `trap::init`.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    for trap_type in [TrapType::LoadPageFault, TrapType::StorePageFault] {
+        trap::register_trap_handler(
+            trap_type,
+            uaccess_fault_handler,
+            FIXUP_HANDLER_PRIORITY,
+            "User Access Fixup",
+            ProtectionLevel::Kernel,
+            KERNEL_REGISTRAR_ID,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Registers the fixup handler, which must see kernel page faults first.
+fn register_fixups() -> InitResult {
+    init().map_err(|e| format!("failed to register user access fixup handler: {}", e))?;
+    crate::info_print!("User access fixup handler registered.");
+    Ok(())
+}
+
+crate::initcall!(subsys, 50, register_fixups);
+
+/// Returns the fixup address for a faulting instruction, if it has one.
+fn search_fixup(sepc: usize) -> Option<usize> {
+    let entries = unsafe {
+        let start = __uaccess_fixups.as_ptr();
+        let end = __uaccess_fixups_end.as_ptr();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+    entries.iter().find(|e| e.insn == sepc).map(|e| e.fixup)
+}
+
+fn uaccess_fault_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    let code = context.cause().code();
+    if code != Exception::LoadPageFault as usize && code != Exception::StorePageFault as usize {
+        return TrapHandlerResult::Pass;
+    }
+    if context.sstatus & SSTATUS_SPP == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    match search_fixup(context.sepc) {
+        Some(fixup) => {
+            context.sepc = fixup;
+            TrapHandlerResult::Handled
+        }
+        None => TrapHandlerResult::Pass,
+    }
+}
+
+/// Checks that `[addr, addr + len)` lies in user space.
+fn check_range(addr: usize, len: usize) -> Result<(), MmError> {
+    let end = addr.checked_add(len).ok_or(MmError::InvalidAddress)?;
+    if addr < USER_SPACE_START || end > USER_SPACE_END {
+        return Err(MmError::InvalidAddress);
+    }
+    Ok(())
+}
+
+/// Checks that every page of `[addr, addr + len)` is mapped in the current
+/// address space with at least `required` permissions.
+fn check_mapped(addr: usize, len: usize, required: PteFlags) -> Result<(), MmError> {
+    check_range(addr, len)?;
+    if len == 0 {
+        return Ok(());
+    }
+    let space = task::current_address_space().ok_or(MmError::InvalidAddress)?;
+    let space = space.lock();
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < addr + len {
+        match space.flags(page) {
+            Some(flags) if flags.contains(required | PteFlags::USER) => {}
+            Some(_) => return Err(MmError::Fault),
+            None => return Err(MmError::NotMapped),
+        }
+        page += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+/// Copies `dst.len()` bytes from user address `src`.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), MmError> {
+    check_mapped(src, dst.len(), PteFlags::READ)?;
+    let left = unsafe { __copy_user(dst.as_mut_ptr(), src as *const u8, dst.len()) };
+    if left == 0 { Ok(()) } else { Err(MmError::Fault) }
+}
+
+/// Copies `src` to user address `dst`.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), MmError> {
+    check_mapped(dst, src.len(), PteFlags::WRITE)?;
+    let left = unsafe { __copy_user(dst as *mut u8, src.as_ptr(), src.len()) };
+    if left == 0 { Ok(()) } else { Err(MmError::Fault) }
+}
+
+/// Copies a NUL-terminated string from user address `src` into `dst`.
+///
+/// Returns the string length without the terminator. A return value equal
+/// to `dst.len()` means no terminator was found within `dst.len()` bytes.
+pub fn strncpy_from_user(dst: &mut [u8], src: usize) -> Result<usize, MmError> {
+    // Only the first byte is known to be needed; bytes past the terminator
+    // may legitimately be unmapped, so the rest is left to the fixup path.
+    check_mapped(src, dst.len().min(1), PteFlags::READ)?;
+    let max = dst.len().min(USER_SPACE_END - src);
+    let len = unsafe { __strncpy_user(dst.as_mut_ptr(), src as *const u8, max) };
+    if len < 0 { Err(MmError::Fault) } else { Ok(len as usize) }
+}
@@ -0,0 +1,250 @@
+// nt_rustos/src/net/arp.rs
+
+//! ARP (RFC 826) for IPv4 over Ethernet.
+//!
+//! The cache maps the IPv4 addresses of neighbours on each interface to
+//! their hardware addresses. It learns a mapping from every request or
+//! reply addressed to us, and from any ARP packet of a neighbour it already
+//! knows. Entries go stale after `ENTRY_TIMEOUT_MS`.
+//!
+//! A packet for a neighbour not in the cache is held while a request is
+//! broadcast, and sent when the reply comes in. Requests are repeated at
+//! most once per `REQUEST_INTERVAL_MS`; unanswered packets are dropped
+//! when a newer one pushes them out of the queue.
+
+use super::{EtherType, Frame, Interface, Ipv4Addr, MacAddr, NetError};
+use crate::println;
+use crate::time;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Length of an ARP packet for IPv4 over Ethernet.
+pub const PACKET_LEN: usize = 28;
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+/// Lifetime of a cache entry.
+pub const ENTRY_TIMEOUT_MS: u64 = 60_000;
+/// Shortest time between two requests for the same address.
+pub const REQUEST_INTERVAL_MS: u64 = 1_000;
+/// Most entries kept; the oldest is evicted beyond this.
+const MAX_ENTRIES: usize = 64;
+/// Most packets held for one unresolved address.
+const MAX_PENDING: usize = 4;
+
+/// A decoded ARP packet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Decodes an Ethernet/IPv4 ARP packet, ignoring trailing padding.
+    pub fn parse(bytes: &[u8]) -> Result<Self, NetError> {
+        if bytes.len() < PACKET_LEN {
+            return Err(NetError::Malformed);
+        }
+        let be16 = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        if be16(0) != HTYPE_ETHERNET || be16(2) != EtherType::IPV4.0 || bytes[4] != 6 || bytes[5] != 4 {
+            return Err(NetError::NotSupported);
+        }
+        let mac = |i: usize| MacAddr(bytes[i..i + 6].try_into().unwrap());
+        let ip = |i: usize| Ipv4Addr(bytes[i..i + 4].try_into().unwrap());
+        Ok(Self {
+            op: be16(6),
+            sender_mac: mac(8),
+            sender_ip: ip(14),
+            target_mac: mac(18),
+            target_ip: ip(24),
+        })
+    }
+
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        let mut bytes = [0u8; PACKET_LEN];
+        bytes[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        bytes[2..4].copy_from_slice(&EtherType::IPV4.0.to_be_bytes());
+        bytes[4] = 6;
+        bytes[5] = 4;
+        bytes[6..8].copy_from_slice(&self.op.to_be_bytes());
+        bytes[8..14].copy_from_slice(&self.sender_mac.0);
+        bytes[14..18].copy_from_slice(&self.sender_ip.0);
+        bytes[18..24].copy_from_slice(&self.target_mac.0);
+        bytes[24..28].copy_from_slice(&self.target_ip.0);
+        bytes
+    }
+}
+
+/// A cache entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighbour {
+    pub iface: String,
+    pub ip: Ipv4Addr,
+    pub mac: MacAddr,
+    /// When the mapping was last confirmed, in timer ticks.
+    pub updated: u64,
+}
+
+/// Packets waiting for an address to resolve.
+struct Pending {
+    iface: String,
+    ip: Ipv4Addr,
+    packets: Vec<Vec<u8>>,
+    requested: u64,
+}
+
+static CACHE: Mutex<Vec<Neighbour>> = Mutex::new(Vec::new());
+static PENDING: Mutex<Vec<Pending>> = Mutex::new(Vec::new());
+
+/// Returns the hardware address of `ip` on `iface`, if cached and fresh.
+pub fn lookup(iface: &Interface, ip: Ipv4Addr) -> Option<MacAddr> {
+    let now = time::now_ticks();
+    CACHE
+        .lock()
+        .iter()
+        .find(|n| n.ip == ip && n.iface == iface.name())
+        .filter(|n| now.saturating_sub(n.updated) < time::ms_to_ticks(ENTRY_TIMEOUT_MS))
+        .map(|n| n.mac)
+}
+
+/// Records that `ip` is at `mac` on `iface`. Only refreshes an existing
+/// entry unless `create` is set. Returns the packets that were waiting.
+fn learn(iface: &Interface, ip: Ipv4Addr, mac: MacAddr, create: bool) -> Vec<Vec<u8>> {
+    let now = time::now_ticks();
+    {
+        let mut cache = CACHE.lock();
+        match cache.iter_mut().find(|n| n.ip == ip && n.iface == iface.name()) {
+            Some(entry) => {
+                entry.mac = mac;
+                entry.updated = now;
+            }
+            None if create => {
+                if cache.len() >= MAX_ENTRIES {
+                    let oldest = (0..cache.len()).min_by_key(|&i| cache[i].updated).unwrap();
+                    cache.remove(oldest);
+                }
+                cache.push(Neighbour { iface: String::from(iface.name()), ip, mac, updated: now });
+            }
+            None => return Vec::new(),
+        }
+    }
+    let mut pending = PENDING.lock();
+    match pending.iter().position(|p| p.ip == ip && p.iface == iface.name()) {
+        Some(index) => pending.remove(index).packets,
+        None => Vec::new(),
+    }
+}
+
+/// Broadcasts a request for the hardware address of `ip`.
+pub fn request(iface: &Interface, ip: Ipv4Addr) -> Result<(), NetError> {
+    let config = iface.ipv4().ok_or(NetError::NoRoute)?;
+    let packet = ArpPacket {
+        op: OP_REQUEST,
+        sender_mac: iface.mac(),
+        sender_ip: config.addr,
+        target_mac: MacAddr::ZERO,
+        target_ip: ip,
+    };
+    iface.send(MacAddr::BROADCAST, EtherType::ARP, &packet.encode())
+}
+
+/// Sends the IPv4 `packet` to the neighbour `next_hop`, first resolving
+/// its hardware address if need be.
+pub fn send_ipv4(iface: &Interface, next_hop: Ipv4Addr, packet: Vec<u8>) -> Result<(), NetError> {
+    let config = iface.ipv4().ok_or(NetError::NoRoute)?;
+    if next_hop.is_broadcast() || next_hop == config.broadcast() {
+        return iface.send(MacAddr::BROADCAST, EtherType::IPV4, &packet);
+    }
+    if iface.device().is_loopback() {
+        return iface.send(iface.mac(), EtherType::IPV4, &packet);
+    }
+    if let Some(mac) = lookup(iface, next_hop) {
+        return iface.send(mac, EtherType::IPV4, &packet);
+    }
+
+    let now = time::now_ticks();
+    let due = {
+        let mut pending = PENDING.lock();
+        match pending.iter_mut().find(|p| p.ip == next_hop && p.iface == iface.name()) {
+            Some(entry) => {
+                if entry.packets.len() >= MAX_PENDING {
+                    entry.packets.remove(0);
+                }
+                entry.packets.push(packet);
+                let due = now.saturating_sub(entry.requested) >= time::ms_to_ticks(REQUEST_INTERVAL_MS);
+                if due {
+                    entry.requested = now;
+                }
+                due
+            }
+            None => {
+                let iface = String::from(iface.name());
+                pending.push(Pending { iface, ip: next_hop, packets: alloc::vec![packet], requested: now });
+                true
+            }
+        }
+    };
+    if due {
+        request(iface, next_hop)?;
+    }
+    Ok(())
+}
+
+/// Handles a received ARP frame.
+pub fn receive(iface: &Arc<Interface>, frame: &Frame) {
+    let Some(config) = iface.ipv4() else {
+        return;
+    };
+    let Ok(packet) = ArpPacket::parse(frame.payload) else {
+        iface.count_dropped();
+        return;
+    };
+    let for_us = packet.target_ip == config.addr;
+    let waiting = if packet.sender_ip != Ipv4Addr::UNSPECIFIED {
+        learn(iface, packet.sender_ip, packet.sender_mac, for_us)
+    } else {
+        Vec::new()
+    };
+
+    if for_us && packet.op == OP_REQUEST {
+        let reply = ArpPacket {
+            op: OP_REPLY,
+            sender_mac: iface.mac(),
+            sender_ip: config.addr,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        let _ = iface.send(packet.sender_mac, EtherType::ARP, &reply.encode());
+    }
+    for held in waiting {
+        let _ = iface.send(packet.sender_mac, EtherType::IPV4, &held);
+    }
+}
+
+/// Drops the entries and held packets of `iface`.
+pub fn forget(iface: &Interface) {
+    CACHE.lock().retain(|n| n.iface != iface.name());
+    PENDING.lock().retain(|p| p.iface != iface.name());
+}
+
+/// Returns the cache entries.
+pub fn neighbours() -> Vec<Neighbour> {
+    CACHE.lock().clone()
+}
+
+/// Prints the cache.
+pub fn print_cache() {
+    let now = time::now_ticks();
+    let neighbours = neighbours();
+    println!("ARP cache ({} entries):", neighbours.len());
+    for n in neighbours {
+        println!("  {:<15} {} on {}, {} ms old", n.ip, n.mac, n.iface, time::ticks_to_ms(now.saturating_sub(n.updated)));
+    }
+}
@@ -0,0 +1,312 @@
+// nt_rustos/src/net/buf.rs
+
+//! Packet buffers.
+//!
+//! A `PacketBuf` holds one frame on its way between a device and the
+//! stack. The frame occupies a window of the buffer: the space before it,
+//! the headroom, lets each layer prepend its header in place with `push`,
+//! and `pull` strips headers on the way up, so a packet is built and parsed
+//! without being copied from layer to layer. `put` and `trim` grow and
+//! shrink the window at the tail.
+//!
+//! Buffers are reference counted. Cloning one shares its data, each clone
+//! with its own window; only a buffer nobody else holds can be written,
+//! and `unshare` copies a shared one.
+//!
+//! Buffers come from a pool of `POOL_BUFFERS` buffers of `BUFFER_SIZE`
+//! bytes, allocated once at boot (`AllocPurpose::NetworkBuffer`), so the
+//! send and receive paths do not go to the heap for every packet. A buffer
+//! larger than that, such as a loopback frame, or one asked for while the
+//! pool is empty, is allocated from the heap instead, and counted.
+//! Allocating and freeing take no locks and may happen in interrupt
+//! handlers.
+
+use super::NetError;
+use crate::init::alloc::{self, AllocPurpose};
+use crate::init::initcall::InitResult;
+use core::fmt;
+use core::mem;
+use core::ptr::NonNull;
+use core::slice;
+use core::sync::atomic::{self as atomic, AtomicU64, AtomicUsize, Ordering};
+use spin::Once;
+
+/// Bytes a pooled buffer holds: a full Ethernet frame with room to spare
+/// for headers.
+pub const BUFFER_SIZE: usize = 2048;
+/// Buffers in the pool, one bit each in the free mask.
+pub const POOL_BUFFERS: usize = 64;
+/// Headroom of `alloc`: enough for the Ethernet, IPv4 and UDP headers.
+pub const DEFAULT_HEADROOM: usize = 64;
+
+/// Bytes in front of the data for the `Shared` header, keeping the data
+/// aligned to a cache line.
+const DATA_OFFSET: usize = 64;
+const ALIGN: usize = 64;
+const STRIDE: usize = DATA_OFFSET + BUFFER_SIZE;
+/// `Shared::slot` of a buffer from the heap.
+const NOT_POOLED: usize = usize::MAX;
+
+const _: () = assert!(mem::size_of::<Shared>() <= DATA_OFFSET && POOL_BUFFERS > 0 && POOL_BUFFERS <= 64);
+
+/// Header in front of every buffer's data.
+struct Shared {
+    refs: AtomicUsize,
+    capacity: usize,
+    slot: usize,
+}
+
+struct Pool {
+    base: usize,
+}
+
+static POOL: Once<Option<Pool>> = Once::new();
+/// Free pool buffers, bit `n` for buffer `n`.
+static FREE: AtomicU64 = AtomicU64::new(0);
+static POOLED: AtomicU64 = AtomicU64::new(0);
+static FALLBACKS: AtomicU64 = AtomicU64::new(0);
+
+/// Pool counters.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers in the pool, 0 if it could not be allocated.
+    pub buffers: usize,
+    pub free: usize,
+    /// Buffers handed out from the pool.
+    pub pooled: u64,
+    /// Buffers allocated from the heap because they were too large or the
+    /// pool was empty.
+    pub fallbacks: u64,
+}
+
+pub fn pool_stats() -> PoolStats {
+    let buffers = if matches!(POOL.get(), Some(Some(_))) { POOL_BUFFERS } else { 0 };
+    PoolStats {
+        buffers,
+        free: FREE.load(Ordering::Relaxed).count_ones() as usize,
+        pooled: POOLED.load(Ordering::Relaxed),
+        fallbacks: FALLBACKS.load(Ordering::Relaxed),
+    }
+}
+
+/// Takes a buffer from the pool.
+fn take_pooled() -> Option<NonNull<Shared>> {
+    let Some(Some(pool)) = POOL.get() else {
+        return None;
+    };
+    let mask = FREE.fetch_update(Ordering::Acquire, Ordering::Relaxed, |m| (m != 0).then(|| m & (m - 1))).ok()?;
+    let slot = mask.trailing_zeros() as usize;
+    let shared = (pool.base + slot * STRIDE) as *mut Shared;
+    unsafe {
+        shared.write(Shared { refs: AtomicUsize::new(1), capacity: BUFFER_SIZE, slot });
+    }
+    POOLED.fetch_add(1, Ordering::Relaxed);
+    NonNull::new(shared)
+}
+
+fn alloc_heap(capacity: usize) -> Option<NonNull<Shared>> {
+    let base = alloc::alloc_aligned(DATA_OFFSET + capacity, ALIGN)?;
+    let _ = alloc::set_purpose(base, AllocPurpose::NetworkBuffer);
+    let shared = base as *mut Shared;
+    unsafe {
+        shared.write(Shared { refs: AtomicUsize::new(1), capacity, slot: NOT_POOLED });
+    }
+    FALLBACKS.fetch_add(1, Ordering::Relaxed);
+    NonNull::new(shared)
+}
+
+/// A reference-counted packet buffer.
+pub struct PacketBuf {
+    shared: NonNull<Shared>,
+    /// The window holding the packet, as offsets into the data.
+    start: usize,
+    end: usize,
+}
+
+// The data is only written through a buffer nobody else holds.
+unsafe impl Send for PacketBuf {}
+unsafe impl Sync for PacketBuf {}
+
+impl PacketBuf {
+    /// Returns an empty buffer with `headroom` bytes in front of the
+    /// window and room for `size` bytes after it.
+    pub fn with_headroom(headroom: usize, size: usize) -> Result<Self, NetError> {
+        let capacity = headroom.checked_add(size).ok_or(NetError::TooLong)?;
+        let pooled = if capacity <= BUFFER_SIZE { take_pooled() } else { None };
+        let shared = pooled.or_else(|| alloc_heap(capacity.max(1))).ok_or(NetError::NoBuffers)?;
+        Ok(Self { shared, start: headroom, end: headroom })
+    }
+
+    /// Returns an empty buffer with `DEFAULT_HEADROOM` and room for `size`
+    /// bytes.
+    pub fn alloc(size: usize) -> Result<Self, NetError> {
+        Self::with_headroom(DEFAULT_HEADROOM, size)
+    }
+
+    /// Returns a buffer holding a copy of `bytes` and no headroom, as a
+    /// device hands up a received frame.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, NetError> {
+        let mut buf = Self::with_headroom(0, bytes.len())?;
+        buf.end = bytes.len();
+        buf.data_mut().ok_or(NetError::NoBuffers)?.copy_from_slice(bytes);
+        Ok(buf)
+    }
+
+    fn shared(&self) -> &Shared {
+        unsafe { self.shared.as_ref() }
+    }
+
+    fn base(&self) -> *mut u8 {
+        unsafe { (self.shared.as_ptr() as *mut u8).add(DATA_OFFSET) }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared().capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Bytes free in front of the packet.
+    pub fn headroom(&self) -> usize {
+        self.start
+    }
+
+    /// Bytes free after the packet.
+    pub fn tailroom(&self) -> usize {
+        self.capacity() - self.end
+    }
+
+    /// Returns `true` if another buffer shares the data.
+    pub fn is_shared(&self) -> bool {
+        self.shared().refs.load(Ordering::Acquire) > 1
+    }
+
+    /// Returns `true` if the buffer came from the pool.
+    pub fn is_pooled(&self) -> bool {
+        self.shared().slot != NOT_POOLED
+    }
+
+    pub fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.base().add(self.start), self.len()) }
+    }
+
+    /// Returns the packet for writing, or `None` if the data is shared.
+    pub fn data_mut(&mut self) -> Option<&mut [u8]> {
+        if self.is_shared() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts_mut(self.base().add(self.start), self.len()) })
+    }
+
+    /// Extends the packet `len` bytes into the headroom and returns them,
+    /// zeroed, for a header. Returns `None` if the headroom is too small or
+    /// the data is shared.
+    pub fn push(&mut self, len: usize) -> Option<&mut [u8]> {
+        if len > self.start || self.is_shared() {
+            return None;
+        }
+        self.start -= len;
+        let header = &mut self.data_mut()?[..len];
+        header.fill(0);
+        Some(header)
+    }
+
+    /// Strips `len` bytes from the front of the packet and returns them.
+    /// Returns `None` if the packet is shorter.
+    pub fn pull(&mut self, len: usize) -> Option<&[u8]> {
+        if len > self.len() {
+            return None;
+        }
+        self.start += len;
+        Some(unsafe { slice::from_raw_parts(self.base().add(self.start - len), len) })
+    }
+
+    /// Extends the packet `len` bytes into the tailroom and returns them,
+    /// zeroed. Returns `None` if the tailroom is too small or the data is
+    /// shared.
+    pub fn put(&mut self, len: usize) -> Option<&mut [u8]> {
+        if len > self.tailroom() || self.is_shared() {
+            return None;
+        }
+        let old_len = self.len();
+        self.end += len;
+        let tail = &mut self.data_mut()?[old_len..];
+        tail.fill(0);
+        Some(tail)
+    }
+
+    /// Shortens the packet to `len` bytes, if it is longer.
+    pub fn trim(&mut self, len: usize) {
+        self.end = self.end.min(self.start + len);
+    }
+
+    /// Gives the buffer its own copy of the data if it is shared, keeping
+    /// the headroom and tailroom.
+    pub fn unshare(&mut self) -> Result<(), NetError> {
+        if !self.is_shared() {
+            return Ok(());
+        }
+        let mut copy = Self::with_headroom(self.headroom(), self.len() + self.tailroom())?;
+        copy.put(self.len()).ok_or(NetError::NoBuffers)?.copy_from_slice(self.data());
+        *self = copy;
+        Ok(())
+    }
+}
+
+impl Clone for PacketBuf {
+    /// Shares the data; the clone has its own window.
+    fn clone(&self) -> Self {
+        self.shared().refs.fetch_add(1, Ordering::Relaxed);
+        Self { shared: self.shared, start: self.start, end: self.end }
+    }
+}
+
+impl Drop for PacketBuf {
+    fn drop(&mut self) {
+        if self.shared().refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+        match self.shared().slot {
+            NOT_POOLED => alloc::dealloc(self.shared.as_ptr() as *mut u8),
+            slot => {
+                FREE.fetch_or(1 << slot, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PacketBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketBuf")
+            .field("len", &self.len())
+            .field("headroom", &self.headroom())
+            .field("tailroom", &self.tailroom())
+            .field("shared", &self.is_shared())
+            .finish()
+    }
+}
+
+/// Allocates the pool before the network stack and its drivers start.
+fn init_pool() -> InitResult {
+    let pool = POOL.call_once(|| {
+        let base = alloc::alloc_aligned(POOL_BUFFERS * STRIDE, ALIGN)?;
+        let _ = alloc::set_purpose(base, AllocPurpose::NetworkBuffer);
+        Some(Pool { base: base as usize })
+    });
+    if pool.is_none() {
+        crate::warn_print!("No memory for the packet buffer pool; packet buffers come from the heap.");
+        return Ok(());
+    }
+    FREE.store(u64::MAX >> (64 - POOL_BUFFERS), Ordering::Release);
+    crate::info_print!("Packet buffer pool: {} buffers of {} bytes.", POOL_BUFFERS, BUFFER_SIZE);
+    Ok(())
+}
+
+crate::initcall!(subsys, 85, init_pool);
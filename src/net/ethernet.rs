@@ -0,0 +1,119 @@
+// nt_rustos/src/net/ethernet.rs
+
+//! Ethernet II framing.
+//!
+//! A frame is the destination and source MAC addresses and an EtherType
+//! naming the payload's protocol, followed by the payload. Frames shorter
+//! than `MIN_FRAME_LEN` are padded with zeros; the frame check sequence is
+//! left to the device.
+
+use super::{NetError, PacketBuf};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Destination, source and EtherType.
+pub const HEADER_LEN: usize = 14;
+/// Shortest frame on the wire, without the frame check sequence.
+pub const MIN_FRAME_LEN: usize = 60;
+
+/// A hardware address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: Self = Self([0xff; 6]);
+    pub const ZERO: Self = Self([0; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Group addresses, broadcast included, have the low bit of the
+    /// first byte set.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+/// The protocol of a frame's payload.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EtherType(pub u16);
+
+impl EtherType {
+    pub const IPV4: Self = Self(0x0800);
+    pub const ARP: Self = Self(0x0806);
+    pub const IPV6: Self = Self(0x86DD);
+}
+
+impl fmt::Display for EtherType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#06x}", self.0)
+    }
+}
+
+/// A decoded frame, borrowing its payload.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ether_type: EtherType,
+    /// The payload, including any padding.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Decodes `bytes`, which must hold at least a header.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, NetError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(NetError::Malformed);
+        }
+        let mac = |offset: usize| {
+            let mut addr = [0u8; 6];
+            addr.copy_from_slice(&bytes[offset..offset + 6]);
+            MacAddr(addr)
+        };
+        Ok(Self {
+            dst: mac(0),
+            src: mac(6),
+            ether_type: EtherType(u16::from_be_bytes([bytes[12], bytes[13]])),
+            payload: &bytes[HEADER_LEN..],
+        })
+    }
+}
+
+/// Encodes a frame carrying `payload`, padded to `MIN_FRAME_LEN`.
+pub fn encode(dst: MacAddr, src: MacAddr, ether_type: EtherType, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity((HEADER_LEN + payload.len()).max(MIN_FRAME_LEN));
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.extend_from_slice(&ether_type.0.to_be_bytes());
+    frame.extend_from_slice(payload);
+    if frame.len() < MIN_FRAME_LEN {
+        frame.resize(MIN_FRAME_LEN, 0);
+    }
+    frame
+}
+
+/// Pads the payload in `packet` to `MIN_FRAME_LEN` and prepends the header,
+/// copying the packet first if it has no headroom for it or is shared.
+pub fn push_header(packet: &mut PacketBuf, dst: MacAddr, src: MacAddr, ether_type: EtherType) -> Result<(), NetError> {
+    let padding = (MIN_FRAME_LEN - HEADER_LEN).saturating_sub(packet.len());
+    if packet.headroom() < HEADER_LEN || packet.tailroom() < padding || packet.is_shared() {
+        let mut copy = PacketBuf::with_headroom(HEADER_LEN, packet.len() + padding)?;
+        copy.put(packet.len()).ok_or(NetError::NoBuffers)?.copy_from_slice(packet.data());
+        *packet = copy;
+    }
+    packet.put(padding).ok_or(NetError::NoBuffers)?;
+    let header = packet.push(HEADER_LEN).ok_or(NetError::NoBuffers)?;
+    header[..6].copy_from_slice(&dst.0);
+    header[6..12].copy_from_slice(&src.0);
+    header[12..].copy_from_slice(&ether_type.0.to_be_bytes());
+    Ok(())
+}
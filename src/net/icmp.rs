@@ -0,0 +1,216 @@
+// nt_rustos/src/net/icmp.rs
+
+//! ICMP echo (RFC 792).
+//!
+//! Echo requests addressed to one of our interfaces are answered with an
+//! echo reply carrying the same identifier, sequence number and data.
+//! Requests sent to a broadcast address are ignored, as Linux does by
+//! default.
+//!
+//! `echo` sends one request and waits for its reply; `ping` is the `ping`
+//! command built on it, printing a line per reply and a summary. The
+//! kernel has no shell yet, so `ping` is called directly for now.
+
+use super::ipv4::{self, Ipv4Addr, Packet, PROTO_ICMP};
+use super::{Interface, NetError};
+use crate::println;
+use crate::task;
+use crate::time;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use spin::Mutex;
+
+/// Type, code, checksum, identifier and sequence number.
+pub const HEADER_LEN: usize = 8;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// Data bytes in a `ping` request, as in the usual `ping` command.
+pub const PING_DATA_LEN: usize = 56;
+/// How long `ping` waits for each reply.
+pub const PING_TIMEOUT_MS: u64 = 1_000;
+/// Time between two `ping` requests.
+const PING_INTERVAL_MS: u64 = 1_000;
+
+/// Encodes an echo request or reply with its checksum.
+pub fn encode_echo(kind: u8, ident: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + data.len());
+    message.extend_from_slice(&[kind, 0, 0, 0]);
+    message.extend_from_slice(&ident.to_be_bytes());
+    message.extend_from_slice(&seq.to_be_bytes());
+    message.extend_from_slice(data);
+    let sum = ipv4::checksum(&message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+/// A received echo reply.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EchoReply {
+    pub from: Ipv4Addr,
+    /// ICMP message length, header included.
+    pub bytes: usize,
+    pub ttl: u8,
+    /// Round-trip time in microseconds.
+    pub rtt_us: u64,
+}
+
+/// An echo request waiting for its reply.
+struct Outstanding {
+    ident: u16,
+    seq: u16,
+    dst: Ipv4Addr,
+    sent: u64,
+    reply: Option<EchoReply>,
+}
+
+static OUTSTANDING: Mutex<Vec<Outstanding>> = Mutex::new(Vec::new());
+static NEXT_IDENT: AtomicU16 = AtomicU16::new(1);
+
+/// Handles a received ICMP packet.
+pub fn receive(iface: &Arc<Interface>, packet: &Packet) {
+    let message = packet.payload;
+    if message.len() < HEADER_LEN || ipv4::checksum(message) != 0 {
+        iface.count_dropped();
+        return;
+    }
+    let ident = u16::from_be_bytes([message[4], message[5]]);
+    let seq = u16::from_be_bytes([message[6], message[7]]);
+    match message[0] {
+        TYPE_ECHO_REQUEST => {
+            // `ipv4` only passes on packets for us or for a broadcast address.
+            let broadcast = packet.dst.is_broadcast() || iface.ipv4().is_some_and(|c| packet.dst == c.broadcast());
+            if !broadcast {
+                let reply = encode_echo(TYPE_ECHO_REPLY, ident, seq, &message[HEADER_LEN..]);
+                let _ = ipv4::send_from(Some(packet.dst), packet.src, PROTO_ICMP, &reply);
+            }
+        }
+        TYPE_ECHO_REPLY => {
+            let now = time::now_ticks();
+            let mut outstanding = OUTSTANDING.lock();
+            let waiting = outstanding
+                .iter_mut()
+                .find(|o| o.ident == ident && o.seq == seq && o.dst == packet.src && o.reply.is_none());
+            match waiting {
+                Some(o) => {
+                    o.reply = Some(EchoReply {
+                        from: packet.src,
+                        bytes: message.len(),
+                        ttl: packet.ttl,
+                        rtt_us: time::ticks_to_us(now.saturating_sub(o.sent)),
+                    })
+                }
+                None => iface.count_dropped(),
+            }
+        }
+        _ => iface.count_dropped(),
+    }
+}
+
+/// Sends an echo request with `data_len` bytes of data to `dst` and waits
+/// up to `timeout_ms` for the reply.
+pub fn echo(dst: Ipv4Addr, ident: u16, seq: u16, data_len: usize, timeout_ms: u64) -> Result<EchoReply, NetError> {
+    let data: Vec<u8> = (0..data_len).map(|i| i as u8).collect();
+    let request = encode_echo(TYPE_ECHO_REQUEST, ident, seq, &data);
+    let sent = time::now_ticks();
+    OUTSTANDING.lock().push(Outstanding { ident, seq, dst, sent, reply: None });
+
+    let take = || {
+        let mut outstanding = OUTSTANDING.lock();
+        let index = outstanding.iter().position(|o| o.ident == ident && o.seq == seq && o.dst == dst)?;
+        match outstanding[index].reply {
+            Some(_) => outstanding.remove(index).reply,
+            None => None,
+        }
+    };
+    let result = match ipv4::send(dst, PROTO_ICMP, &request) {
+        Ok(()) => {
+            let deadline = sent.saturating_add(time::ms_to_ticks(timeout_ms));
+            loop {
+                if let Some(reply) = take() {
+                    break Ok(reply);
+                }
+                if time::now_ticks() >= deadline {
+                    break Err(NetError::TimedOut);
+                }
+                task::sleep_ms(1);
+            }
+        }
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        OUTSTANDING.lock().retain(|o| !(o.ident == ident && o.seq == seq && o.dst == dst));
+    }
+    result
+}
+
+/// Summary of a `ping` run.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PingStats {
+    pub transmitted: u16,
+    pub received: u16,
+    pub min_rtt_us: u64,
+    pub max_rtt_us: u64,
+    pub total_rtt_us: u64,
+}
+
+/// The `ping <ip>` command: sends `count` echo requests to `target`, one
+/// per second, and prints each reply and a summary.
+pub fn ping(target: &str, count: u16) -> Result<PingStats, NetError> {
+    let dst: Ipv4Addr = target.trim().parse()?;
+    let ident = NEXT_IDENT.fetch_add(1, Ordering::Relaxed);
+    println!("PING {}: {} data bytes", dst, PING_DATA_LEN);
+
+    let mut stats = PingStats::default();
+    for seq in 1..=count {
+        let started = time::now_ticks();
+        stats.transmitted += 1;
+        match echo(dst, ident, seq, PING_DATA_LEN, PING_TIMEOUT_MS) {
+            Ok(reply) => {
+                println!(
+                    "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms",
+                    reply.bytes,
+                    reply.from,
+                    seq,
+                    reply.ttl,
+                    reply.rtt_us / 1000,
+                    reply.rtt_us % 1000
+                );
+                if stats.received == 0 || reply.rtt_us < stats.min_rtt_us {
+                    stats.min_rtt_us = reply.rtt_us;
+                }
+                stats.max_rtt_us = stats.max_rtt_us.max(reply.rtt_us);
+                stats.total_rtt_us += reply.rtt_us;
+                stats.received += 1;
+            }
+            Err(NetError::TimedOut) => println!("Request timeout for icmp_seq {}", seq),
+            Err(e) => {
+                println!("ping: {}: {}", dst, e);
+                return Err(e);
+            }
+        }
+        if seq < count {
+            let elapsed_ms = time::ticks_to_ms(time::now_ticks().saturating_sub(started));
+            task::sleep_ms(PING_INTERVAL_MS.saturating_sub(elapsed_ms));
+        }
+    }
+
+    let loss = (stats.transmitted - stats.received) as u32 * 100 / stats.transmitted.max(1) as u32;
+    println!("--- {} ping statistics ---", dst);
+    println!("{} packets transmitted, {} received, {}% packet loss", stats.transmitted, stats.received, loss);
+    if stats.received > 0 {
+        let avg = stats.total_rtt_us / stats.received as u64;
+        println!(
+            "rtt min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms",
+            stats.min_rtt_us / 1000,
+            stats.min_rtt_us % 1000,
+            avg / 1000,
+            avg % 1000,
+            stats.max_rtt_us / 1000,
+            stats.max_rtt_us % 1000
+        );
+    }
+    Ok(stats)
+}
@@ -0,0 +1,300 @@
+// nt_rustos/src/net/ipv4.rs
+
+//! IPv4.
+//!
+//! Each interface may have one address with its subnet and an optional
+//! default gateway. `send` picks the interface whose subnet holds the
+//! destination, or else one with a gateway, and hands the packet to ARP to
+//! find the next hop's hardware address. Packets for our own addresses go
+//! through the loopback interface. Received packets addressed to the
+//! interface, or broadcast, are checked and passed to their protocol.
+//!
+//! Packets are never fragmented: `send` refuses payloads that do not fit
+//! the interface's MTU, and received fragments are dropped.
+
+use super::{arp, icmp, udp, Interface, NetError};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Header without options.
+pub const HEADER_LEN: usize = 20;
+/// Time to live of sent packets.
+pub const DEFAULT_TTL: u8 = 64;
+
+/// Don't-fragment flag, in the flags and fragment offset field.
+const FLAG_DF: u16 = 0x4000;
+/// More-fragments flag.
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1FFF;
+
+/// Protocol numbers of the payload.
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_UDP: u8 = 17;
+
+/// An IPv4 address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Self = Self([0; 4]);
+    pub const BROADCAST: Self = Self([255; 4]);
+    pub const LOCALHOST: Self = Self([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub const fn from_u32(value: u32) -> Self {
+        Self(value.to_be_bytes())
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+impl FromStr for Ipv4Addr {
+    type Err = NetError;
+
+    /// Parses dotted-quad notation such as `10.0.2.2`.
+    fn from_str(s: &str) -> Result<Self, NetError> {
+        let mut octets = [0u8; 4];
+        let mut parts = s.split('.');
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or(NetError::InvalidArgument)?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(NetError::InvalidArgument);
+            }
+            *octet = part.parse().map_err(|_| NetError::InvalidArgument)?;
+        }
+        if parts.next().is_some() {
+            return Err(NetError::InvalidArgument);
+        }
+        Ok(Self(octets))
+    }
+}
+
+/// The IPv4 configuration of an interface.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Ipv4Config {
+    pub addr: Ipv4Addr,
+    /// Length of the subnet prefix, 0 to 32.
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl Ipv4Config {
+    pub fn netmask(&self) -> u32 {
+        match self.prefix_len {
+            0 => 0,
+            len => u32::MAX << (32 - len.min(32) as u32),
+        }
+    }
+
+    /// Returns whether `addr` lies in the subnet.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        (addr.to_u32() ^ self.addr.to_u32()) & self.netmask() == 0
+    }
+
+    /// Returns the subnet's broadcast address.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.addr.to_u32() | !self.netmask())
+    }
+}
+
+impl fmt::Display for Ipv4Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)?;
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {}", gateway)?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the Internet checksum (RFC 1071) of `data`: the one's
+/// complement of the one's complement sum of its 16-bit words.
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum_words(0, data))
+}
+
+/// Adds the big-endian 16-bit words of `data` to `sum`, padding an odd
+/// last byte with zero.
+pub fn sum_words(sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = chunks.by_ref().fold(sum, |sum, w| sum + u16::from_be_bytes([w[0], w[1]]) as u32);
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// Folds the carries of a 32-bit sum into 16 bits.
+pub fn fold(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// A decoded packet, borrowing its payload.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Decodes and checks `bytes`. Trailing bytes beyond the total length,
+    /// such as Ethernet padding, are ignored.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, NetError> {
+        if bytes.len() < HEADER_LEN || bytes[0] >> 4 != 4 {
+            return Err(NetError::Malformed);
+        }
+        let header_len = (bytes[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        if header_len < HEADER_LEN || total_len < header_len || total_len > bytes.len() {
+            return Err(NetError::Malformed);
+        }
+        if checksum(&bytes[..header_len]) != 0 {
+            return Err(NetError::Malformed);
+        }
+        let fragment = u16::from_be_bytes([bytes[6], bytes[7]]);
+        if fragment & (FLAG_MF | FRAGMENT_OFFSET) != 0 {
+            return Err(NetError::NotSupported);
+        }
+        Ok(Self {
+            src: Ipv4Addr([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            dst: Ipv4Addr([bytes[16], bytes[17], bytes[18], bytes[19]]),
+            protocol: bytes[9],
+            ttl: bytes[8],
+            payload: &bytes[header_len..total_len],
+        })
+    }
+}
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Encodes a packet carrying `payload`, with a header checksum.
+pub fn encode(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, ttl: u8, payload: &[u8]) -> Vec<u8> {
+    let total_len = (HEADER_LEN + payload.len()) as u16;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.extend_from_slice(&[ttl, protocol, 0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Where to send a packet.
+#[derive(Clone)]
+pub struct Route {
+    pub iface: Arc<Interface>,
+    /// The neighbour to hand the packet to: the destination itself, or a
+    /// gateway.
+    pub next_hop: Ipv4Addr,
+    /// The source address to send from.
+    pub src: Ipv4Addr,
+}
+
+/// Returns whether `addr` is the address of one of our interfaces.
+pub fn is_local(addr: Ipv4Addr) -> bool {
+    super::interfaces().iter().any(|i| i.ipv4().is_some_and(|c| c.addr == addr))
+}
+
+/// Returns the route to `dst`. Our own addresses are reached through the
+/// loopback interface, if there is one.
+pub fn route(dst: Ipv4Addr) -> Result<Route, NetError> {
+    let interfaces = super::interfaces();
+    let configured = || interfaces.iter().filter_map(|i| i.ipv4().map(|c| (i, c)));
+    let via = |iface: &Arc<Interface>, next_hop: Ipv4Addr, src: Ipv4Addr| Route { iface: iface.clone(), next_hop, src };
+    if configured().any(|(_, c)| c.addr == dst) {
+        if let Some((lo, _)) = configured().find(|(i, _)| i.device().is_loopback()) {
+            return Ok(via(lo, dst, dst));
+        }
+    }
+    if let Some((iface, c)) = configured().find(|(_, c)| c.contains(dst) || dst == c.addr) {
+        return Ok(via(iface, dst, c.addr));
+    }
+    if dst.is_broadcast() {
+        if let Some((iface, c)) = configured().find(|(i, _)| !i.device().is_loopback()) {
+            return Ok(via(iface, dst, c.addr));
+        }
+    }
+    configured()
+        .find_map(|(i, c)| c.gateway.map(|gateway| via(i, gateway, c.addr)))
+        .ok_or(NetError::NoRoute)
+}
+
+/// Sends `payload` to `dst` as protocol `protocol`. A packet waiting for
+/// ARP to resolve the next hop counts as sent.
+pub fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), NetError> {
+    send_from(None, dst, protocol, payload)
+}
+
+/// Like `send`, from the source address `src` rather than the route's.
+/// Replies use it to come from the address the request went to.
+pub fn send_from(src: Option<Ipv4Addr>, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), NetError> {
+    let route = route(dst)?;
+    if HEADER_LEN + payload.len() > route.iface.mtu() {
+        return Err(NetError::TooLong);
+    }
+    let packet = encode(src.unwrap_or(route.src), dst, protocol, DEFAULT_TTL, payload);
+    arp::send_ipv4(&route.iface, route.next_hop, packet)
+}
+
+/// Handles a received IPv4 frame.
+pub fn receive(iface: &Arc<Interface>, frame: &super::Frame) {
+    let Some(config) = iface.ipv4() else {
+        return;
+    };
+    let Ok(packet) = Packet::parse(frame.payload) else {
+        iface.count_dropped();
+        return;
+    };
+    // The loopback interface takes the whole loopback subnet and every
+    // address routed to it.
+    let loopback = iface.device().is_loopback() && (config.contains(packet.dst) || is_local(packet.dst));
+    let broadcast = packet.dst.is_broadcast() || packet.dst == config.broadcast();
+    if packet.dst != config.addr && !loopback && !broadcast {
+        iface.count_dropped();
+        return;
+    }
+    match packet.protocol {
+        PROTO_ICMP => icmp::receive(iface, &packet),
+        PROTO_UDP => udp::receive(iface, &packet),
+        _ => iface.count_dropped(),
+    }
+}
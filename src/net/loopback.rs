@@ -0,0 +1,109 @@
+// nt_rustos/src/net/loopback.rs
+
+//! The loopback device.
+//!
+//! Every frame transmitted on `lo` comes back in on its receive path, so
+//! the stack can talk to itself without any network hardware. The interface
+//! has the address 127.0.0.1/8 and answers for the whole subnet, and
+//! `ipv4` routes packets for the kernel's own addresses through it.
+//!
+//! Frames are delivered one at a time from a queue rather than from inside
+//! `transmit`: a reply sent by a protocol handler is queued and delivered
+//! after the handler returns, so request/reply exchanges never recurse.
+//! Whoever finds the queue idle delivers until it is empty. The transmitted
+//! buffer itself is queued and handed to the receive path, never copied.
+
+use super::{Interface, Ipv4Addr, Ipv4Config, MacAddr, NetDevice, NetError, PacketBuf, ReceiveHandler};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+/// Name of the loopback interface.
+pub const NAME: &str = "lo";
+/// Largest payload, the most an IPv4 packet can hold.
+pub const MTU: usize = 65535;
+/// Frames queued before transmitting fails with `Busy`.
+const QUEUE_LEN: usize = 256;
+
+/// A device that receives every frame it transmits.
+pub struct Loopback {
+    queue: Mutex<VecDeque<PacketBuf>>,
+    delivering: AtomicBool,
+    handler: Mutex<Option<ReceiveHandler>>,
+}
+
+impl Loopback {
+    pub fn new() -> Self {
+        Self { queue: Mutex::new(VecDeque::new()), delivering: AtomicBool::new(false), handler: Mutex::new(None) }
+    }
+
+    /// Delivers queued frames until the queue is empty, unless another
+    /// caller already is.
+    fn deliver(&self) {
+        while self.delivering.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            loop {
+                let Some(frame) = self.queue.lock().pop_front() else {
+                    break;
+                };
+                let handler = self.handler.lock().clone();
+                if let Some(handler) = handler {
+                    handler(frame);
+                }
+            }
+            self.delivering.store(false, Ordering::Release);
+            // A frame queued after the last check but before the flag was
+            // cleared would otherwise wait for the next transmit.
+            if self.queue.lock().is_empty() {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetDevice for Loopback {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn mac(&self) -> MacAddr {
+        MacAddr::ZERO
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn transmit(&self, frame: PacketBuf) -> Result<(), NetError> {
+        {
+            let mut queue = self.queue.lock();
+            if queue.len() >= QUEUE_LEN {
+                return Err(NetError::Busy);
+            }
+            queue.push_back(frame);
+        }
+        self.deliver();
+        Ok(())
+    }
+
+    fn set_receive_handler(&self, handler: Option<ReceiveHandler>) {
+        *self.handler.lock() = handler;
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+}
+
+/// Registers `lo` with the address 127.0.0.1/8.
+pub fn register() -> Result<Arc<Interface>, NetError> {
+    let iface = super::register(Arc::new(Loopback::new()))?;
+    iface.set_ipv4(Some(Ipv4Config { addr: Ipv4Addr::LOCALHOST, prefix_len: 8, gateway: None }));
+    Ok(iface)
+}
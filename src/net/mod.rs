@@ -0,0 +1,376 @@
+// nt_rustos/src/net/mod.rs
+
+//! # Network Layer
+//!
+//! A `NetDevice` sends and receives Ethernet frames. Drivers register their
+//! devices by name; each registered device becomes an `Interface`, which
+//! keeps its statistics, encodes outgoing frames (see `ethernet`) and
+//! decodes incoming ones. The device hands received frames to the receive
+//! handler the interface installs at registration, and the interface
+//! passes each frame addressed to it on to the protocol registered for the
+//! frame's EtherType. Protocols thus see only frames and interfaces, never
+//! the driver behind them. Frames travel in both directions as `PacketBuf`s
+//! from a preallocated pool (see `buf`), which drivers and the stack share.
+//!
+//! Protocol handlers are called on the receive path, possibly from an
+//! interrupt handler, and with no locks of this layer held: they may send
+//! frames but must not block.
+//!
+//! On top of this sits a minimal IPv4 stack: `arp` resolves next hops,
+//! `ipv4` routes and checks packets, `icmp` answers echo requests and
+//! implements `ping`, and `udp` provides sockets. `init` registers it with
+//! the layer, along with the `loopback` interface. With the `smoltcp`
+//! feature, `smol` offers smoltcp as an alternative for devices not
+//! registered here.
+
+pub mod arp;
+pub mod buf;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod loopback;
+#[cfg(feature = "smoltcp")]
+pub mod smol;
+pub mod udp;
+
+pub use self::buf::PacketBuf;
+pub use self::ethernet::{EtherType, Frame, MacAddr};
+pub use self::ipv4::{Ipv4Addr, Ipv4Config};
+
+use crate::init::initcall::InitResult;
+use crate::println;
+use alloc::format;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// Errors returned by the network layer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetError {
+    /// A device or protocol with the same name or EtherType is already
+    /// registered.
+    AlreadyRegistered,
+    /// No device or protocol has this name or EtherType.
+    NotFound,
+    /// The payload is longer than the interface's MTU.
+    TooLong,
+    /// A received frame or packet is truncated or inconsistent.
+    Malformed,
+    /// The device has no room to queue the frame; try again later.
+    Busy,
+    /// The device failed to send the frame.
+    Io,
+    /// No interface reaches the destination address.
+    NoRoute,
+    /// No reply came in time.
+    TimedOut,
+    /// An address or argument could not be parsed.
+    InvalidArgument,
+    /// The packet uses a feature the stack lacks, such as fragmentation.
+    NotSupported,
+    /// The port is already bound, or no ephemeral port is free.
+    AddrInUse,
+    /// No memory for a packet buffer.
+    NoBuffers,
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyRegistered => write!(f, "already registered"),
+            Self::NotFound => write!(f, "no such device or protocol"),
+            Self::TooLong => write!(f, "payload exceeds the MTU"),
+            Self::Malformed => write!(f, "malformed frame"),
+            Self::Busy => write!(f, "device busy"),
+            Self::Io => write!(f, "network device I/O error"),
+            Self::NoRoute => write!(f, "no route to host"),
+            Self::TimedOut => write!(f, "timed out"),
+            Self::InvalidArgument => write!(f, "invalid argument"),
+            Self::NotSupported => write!(f, "not supported"),
+            Self::AddrInUse => write!(f, "address in use"),
+            Self::NoBuffers => write!(f, "no packet buffers"),
+        }
+    }
+}
+
+/// Called by a device with each frame it receives.
+pub type ReceiveHandler = Arc<dyn Fn(PacketBuf) + Send + Sync>;
+
+/// A device sending and receiving Ethernet frames.
+pub trait NetDevice: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn mac(&self) -> MacAddr;
+
+    /// Largest payload of a frame, 1500 for standard Ethernet.
+    fn mtu(&self) -> usize;
+
+    /// Sends `frame`, a complete Ethernet frame without the frame check
+    /// sequence. The device may keep the buffer until the frame is sent.
+    fn transmit(&self, frame: PacketBuf) -> Result<(), NetError>;
+
+    /// Sets the handler to call with received frames. The device calls it
+    /// only after this, and stops when `None` is set.
+    fn set_receive_handler(&self, handler: Option<ReceiveHandler>);
+
+    /// Whether the device loops frames back rather than sending them on a
+    /// link, so neighbours need no resolving.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+}
+
+/// Interface counters.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    /// Frames for another host or with no protocol registered.
+    pub rx_dropped: u64,
+    /// Frames too short to decode.
+    pub rx_errors: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_dropped: AtomicU64,
+    rx_errors: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_errors: AtomicU64,
+}
+
+fn bump(counter: &AtomicU64, by: u64) {
+    counter.fetch_add(by, Ordering::Relaxed);
+}
+
+/// A registered network device.
+pub struct Interface {
+    device: Arc<dyn NetDevice>,
+    counters: Counters,
+    ipv4: Mutex<Option<Ipv4Config>>,
+}
+
+impl Interface {
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn mac(&self) -> MacAddr {
+        self.device.mac()
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+
+    /// Returns the underlying device.
+    pub fn device(&self) -> &Arc<dyn NetDevice> {
+        &self.device
+    }
+
+    /// Returns the interface's IPv4 configuration, if it has one.
+    pub fn ipv4(&self) -> Option<Ipv4Config> {
+        *self.ipv4.lock()
+    }
+
+    /// Sets or clears the interface's IPv4 configuration.
+    pub fn set_ipv4(&self, config: Option<Ipv4Config>) {
+        *self.ipv4.lock() = config;
+    }
+
+    /// Counts a received frame that a protocol discarded.
+    pub(crate) fn count_dropped(&self) {
+        bump(&self.counters.rx_dropped, 1);
+    }
+
+    /// Sends `payload` to `dst` in a frame of type `ether_type`.
+    pub fn send(&self, dst: MacAddr, ether_type: EtherType, payload: &[u8]) -> Result<(), NetError> {
+        if payload.len() > self.mtu() {
+            return Err(NetError::TooLong);
+        }
+        let mut packet = PacketBuf::with_headroom(ethernet::HEADER_LEN, payload.len())?;
+        packet.put(payload.len()).ok_or(NetError::NoBuffers)?.copy_from_slice(payload);
+        self.send_buf(dst, ether_type, packet)
+    }
+
+    /// Sends the payload in `packet` to `dst` in a frame of type
+    /// `ether_type`, adding the Ethernet header in the packet's headroom
+    /// if it has room.
+    pub fn send_buf(&self, dst: MacAddr, ether_type: EtherType, mut packet: PacketBuf) -> Result<(), NetError> {
+        if packet.len() > self.mtu() {
+            return Err(NetError::TooLong);
+        }
+        ethernet::push_header(&mut packet, dst, self.mac(), ether_type)?;
+        let len = packet.len();
+        match self.device.transmit(packet) {
+            Ok(()) => {
+                bump(&self.counters.tx_packets, 1);
+                bump(&self.counters.tx_bytes, len as u64);
+                Ok(())
+            }
+            Err(e) => {
+                bump(&self.counters.tx_errors, 1);
+                Err(e)
+            }
+        }
+    }
+
+    /// Decodes a received frame and passes it to its protocol.
+    fn receive(self: &Arc<Self>, packet: PacketBuf) {
+        let bytes = packet.data();
+        let frame = match Frame::parse(bytes) {
+            Ok(frame) => frame,
+            Err(_) => {
+                bump(&self.counters.rx_errors, 1);
+                return;
+            }
+        };
+        bump(&self.counters.rx_packets, 1);
+        bump(&self.counters.rx_bytes, bytes.len() as u64);
+        if frame.dst != self.mac() && !frame.dst.is_multicast() {
+            bump(&self.counters.rx_dropped, 1);
+            return;
+        }
+        let handler = PROTOCOLS.lock().iter().find(|(t, _)| *t == frame.ether_type).map(|(_, h)| *h);
+        match handler {
+            Some(handler) => handler(self, &frame),
+            None => bump(&self.counters.rx_dropped, 1),
+        }
+    }
+
+    pub fn stats(&self) -> InterfaceStats {
+        let c = &self.counters;
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        InterfaceStats {
+            rx_packets: get(&c.rx_packets),
+            rx_bytes: get(&c.rx_bytes),
+            rx_dropped: get(&c.rx_dropped),
+            rx_errors: get(&c.rx_errors),
+            tx_packets: get(&c.tx_packets),
+            tx_bytes: get(&c.tx_bytes),
+            tx_errors: get(&c.tx_errors),
+        }
+    }
+}
+
+/// Handles a received frame of the protocol's EtherType.
+pub type ProtocolHandler = fn(&Arc<Interface>, &Frame);
+
+static INTERFACES: Mutex<Vec<Arc<Interface>>> = Mutex::new(Vec::new());
+static PROTOCOLS: Mutex<Vec<(EtherType, ProtocolHandler)>> = Mutex::new(Vec::new());
+
+/// Registers `device` under its name, starts delivering its received
+/// frames and returns its interface.
+pub fn register(device: Arc<dyn NetDevice>) -> Result<Arc<Interface>, NetError> {
+    let iface = {
+        let mut interfaces = INTERFACES.lock();
+        if interfaces.iter().any(|i| i.name() == device.name()) {
+            return Err(NetError::AlreadyRegistered);
+        }
+        let iface = Arc::new(Interface { device, counters: Counters::default(), ipv4: Mutex::new(None) });
+        interfaces.push(iface.clone());
+        iface
+    };
+    // The device holds its handler, so it refers to the interface weakly.
+    let weak: Weak<Interface> = Arc::downgrade(&iface);
+    iface.device.set_receive_handler(Some(Arc::new(move |frame: PacketBuf| {
+        if let Some(iface) = weak.upgrade() {
+            iface.receive(frame);
+        }
+    })));
+    Ok(iface)
+}
+
+/// Removes the interface called `name` and stops its receive path.
+pub fn unregister(name: &str) -> Result<(), NetError> {
+    let iface = {
+        let mut interfaces = INTERFACES.lock();
+        let index = interfaces.iter().position(|i| i.name() == name).ok_or(NetError::NotFound)?;
+        interfaces.remove(index)
+    };
+    iface.device.set_receive_handler(None);
+    arp::forget(&iface);
+    Ok(())
+}
+
+/// Returns the interface called `name`.
+pub fn find(name: &str) -> Option<Arc<Interface>> {
+    INTERFACES.lock().iter().find(|i| i.name() == name).cloned()
+}
+
+/// Returns the registered interfaces, in registration order.
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock().clone()
+}
+
+/// Routes received frames of type `ether_type` to `handler`.
+pub fn register_protocol(ether_type: EtherType, handler: ProtocolHandler) -> Result<(), NetError> {
+    let mut protocols = PROTOCOLS.lock();
+    if protocols.iter().any(|(t, _)| *t == ether_type) {
+        return Err(NetError::AlreadyRegistered);
+    }
+    protocols.push((ether_type, handler));
+    Ok(())
+}
+
+/// Stops routing frames of type `ether_type`.
+pub fn unregister_protocol(ether_type: EtherType) -> Result<(), NetError> {
+    let mut protocols = PROTOCOLS.lock();
+    let index = protocols.iter().position(|(t, _)| *t == ether_type).ok_or(NetError::NotFound)?;
+    protocols.remove(index);
+    Ok(())
+}
+
+/// Registers the IPv4 stack: ARP and IPv4 frames go to `arp` and `ipv4`.
+/// Also registers the loopback interface.
+pub fn init() -> Result<(), NetError> {
+    register_protocol(EtherType::ARP, arp::receive)?;
+    register_protocol(EtherType::IPV4, ipv4::receive)?;
+    loopback::register().map(|_| ())
+}
+
+/// Registers the IPv4 stack before the network drivers probe and add
+/// their interfaces.
+fn init_stack() -> InitResult {
+    init().map_err(|e| format!("failed to register the IPv4 stack: {}", e))?;
+    crate::info_print!("IPv4 stack ready (ARP, ICMP echo, UDP), loopback interface lo up.");
+    Ok(())
+}
+
+crate::initcall!(subsys, 90, init_stack);
+
+/// Prints the registered interfaces with their addresses and counters.
+pub fn print_interfaces() {
+    let interfaces = interfaces();
+    println!("Network interfaces ({}):", interfaces.len());
+    for iface in interfaces {
+        let stats = iface.stats();
+        println!(
+            "  {:<8} {} mtu {}, rx {} packets ({} B, {} dropped, {} errors), tx {} packets ({} B, {} errors)",
+            iface.name(),
+            iface.mac(),
+            iface.mtu(),
+            stats.rx_packets,
+            stats.rx_bytes,
+            stats.rx_dropped,
+            stats.rx_errors,
+            stats.tx_packets,
+            stats.tx_bytes,
+            stats.tx_errors
+        );
+        if let Some(config) = iface.ipv4() {
+            println!("           inet {}", config);
+        }
+    }
+    let pool = buf::pool_stats();
+    println!("  packet buffers: {}/{} free, {} from the pool, {} from the heap", pool.free, pool.buffers, pool.pooled,
+        pool.fallbacks);
+}
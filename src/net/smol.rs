@@ -0,0 +1,253 @@
+// nt_rustos/src/net/smol.rs
+
+//! smoltcp adapter, enabled by the `smoltcp` feature.
+//!
+//! An alternative to the hand-rolled stack for protocols it lacks, TCP
+//! above all. `attach` takes a `NetDevice` that is *not* registered with
+//! this layer, since its received frames go to smoltcp instead, and wraps
+//! it in a `SmolStack`: the device adapter implementing smoltcp's `Device`,
+//! a smoltcp interface with the given IPv4 configuration, and a socket set.
+//!
+//! Received frames are queued by the device's receive handler, which may run
+//! in an interrupt, and consumed by polling. A kernel thread polls every
+//! attached stack and then sleeps until the earliest deadline smoltcp asks
+//! for, at most `MAX_POLL_INTERVAL_MS`, using the tickless timer. Sockets
+//! are added to a stack and reached through their handles with
+//! `with_socket`, which holds the stack lock; poll the stack afterwards to
+//! send what the closure queued without waiting for the thread.
+
+use super::{Ipv4Config, NetDevice, NetError, PacketBuf};
+use crate::task;
+use crate::time;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::socket::{tcp, udp, AnySocket};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, Ipv4Address};
+use spin::{Mutex, Once};
+
+/// Received frames kept until the next poll.
+const RX_QUEUE_LEN: usize = 128;
+/// Longest sleep of the poll thread.
+const MAX_POLL_INTERVAL_MS: u64 = 10;
+/// Buffer size of sockets made by `add_udp_socket` and `add_tcp_socket`.
+const SOCKET_BUFFER: usize = 8192;
+/// Datagrams a UDP socket made by `add_udp_socket` can hold.
+const UDP_PACKETS: usize = 16;
+
+/// The current time in smoltcp's terms.
+fn now() -> Instant {
+    Instant::from_millis(time::monotonic_ms() as i64)
+}
+
+/// `NetDevice` as a smoltcp `Device`.
+struct SmolDevice {
+    device: Arc<dyn NetDevice>,
+    rx: Arc<Mutex<VecDeque<PacketBuf>>>,
+}
+
+struct RxToken(PacketBuf);
+
+struct TxToken<'a>(&'a Arc<dyn NetDevice>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(mut self, f: F) -> R {
+        match self.0.data_mut() {
+            Some(frame) => f(frame),
+            None => f(&mut self.0.data().to_vec()),
+        }
+    }
+}
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+        let Ok(mut frame) = PacketBuf::with_headroom(0, len) else {
+            // Dropped like a frame lost on the link.
+            return f(&mut vec![0u8; len]);
+        };
+        let result = f(frame.put(len).unwrap_or_default());
+        // smoltcp has no way to learn of the failure; it retransmits or
+        // times out as on a lossy link.
+        let _ = self.0.transmit(frame);
+        result
+    }
+}
+
+impl phy::Device for SmolDevice {
+    type RxToken<'a> = RxToken where Self: 'a;
+    type TxToken<'a> = TxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(RxToken, TxToken<'_>)> {
+        let frame = self.rx.lock().pop_front()?;
+        Some((RxToken(frame), TxToken(&self.device)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<TxToken<'_>> {
+        Some(TxToken(&self.device))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        // smoltcp counts the Ethernet header in the MTU.
+        caps.max_transmission_unit = self.device.mtu() + super::ethernet::HEADER_LEN;
+        caps
+    }
+}
+
+struct Inner {
+    device: SmolDevice,
+    iface: Interface,
+    sockets: SocketSet<'static>,
+}
+
+/// A smoltcp interface over a `NetDevice`, with its sockets.
+pub struct SmolStack {
+    name: String,
+    inner: Mutex<Inner>,
+}
+
+static STACKS: Mutex<Vec<Arc<SmolStack>>> = Mutex::new(Vec::new());
+static POLL_THREAD: Once<()> = Once::new();
+
+/// Hands `device` to smoltcp with the address `config`, and starts the
+/// poll thread if it is not running yet.
+pub fn attach(device: Arc<dyn NetDevice>, config: Ipv4Config) -> Result<Arc<SmolStack>, NetError> {
+    let name = String::from(device.name());
+    if super::find(&name).is_some() || find(&name).is_some() {
+        return Err(NetError::AlreadyRegistered);
+    }
+    let rx = Arc::new(Mutex::new(VecDeque::new()));
+    let queue = rx.clone();
+    device.set_receive_handler(Some(Arc::new(move |frame: PacketBuf| {
+        let mut queue = queue.lock();
+        if queue.len() < RX_QUEUE_LEN {
+            queue.push_back(frame);
+        }
+    })));
+
+    let mut device = SmolDevice { device, rx };
+    let hardware = HardwareAddress::Ethernet(EthernetAddress(device.device.mac().0));
+    let mut iface = Interface::new(Config::new(hardware), &mut device, now());
+    iface.update_ip_addrs(|addrs| {
+        let cidr = IpCidr::new(IpAddress::Ipv4(Ipv4Address(config.addr.0)), config.prefix_len);
+        let _ = addrs.push(cidr);
+    });
+    if let Some(gateway) = config.gateway {
+        iface.routes_mut().add_default_ipv4_route(Ipv4Address(gateway.0)).map_err(|_| NetError::NoRoute)?;
+    }
+
+    let stack = Arc::new(SmolStack {
+        name,
+        inner: Mutex::new(Inner { device, iface, sockets: SocketSet::new(Vec::new()) }),
+    });
+    STACKS.lock().push(stack.clone());
+    POLL_THREAD.call_once(|| {
+        if task::spawn_kernel_thread("smoltcp-poll", poll_thread).is_err() {
+            crate::println!("[net] failed to start the smoltcp poll thread; call SmolStack::poll directly");
+        }
+    });
+    Ok(stack)
+}
+
+/// Detaches the stack called `name` and gives its device back.
+pub fn detach(name: &str) -> Result<Arc<dyn NetDevice>, NetError> {
+    let stack = {
+        let mut stacks = STACKS.lock();
+        let index = stacks.iter().position(|s| s.name == name).ok_or(NetError::NotFound)?;
+        stacks.remove(index)
+    };
+    let device = stack.inner.lock().device.device.clone();
+    device.set_receive_handler(None);
+    Ok(device)
+}
+
+/// Returns the stack called `name`.
+pub fn find(name: &str) -> Option<Arc<SmolStack>> {
+    STACKS.lock().iter().find(|s| s.name == name).cloned()
+}
+
+impl SmolStack {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Processes queued frames and socket timers. Returns whether any
+    /// socket changed state.
+    pub fn poll(&self) -> bool {
+        let mut inner = self.inner.lock();
+        let Inner { device, iface, sockets } = &mut *inner;
+        iface.poll(now(), device, sockets)
+    }
+
+    /// Returns how long the stack can go without polling, in milliseconds.
+    pub fn poll_delay_ms(&self) -> Option<u64> {
+        let mut inner = self.inner.lock();
+        let Inner { iface, sockets, .. } = &mut *inner;
+        iface.poll_delay(now(), sockets).map(|d| d.total_millis())
+    }
+
+    /// Adds `socket` and returns its handle.
+    pub fn add_socket<S: AnySocket<'static>>(&self, socket: S) -> SocketHandle {
+        self.inner.lock().sockets.add(socket)
+    }
+
+    /// Adds a UDP socket bound to `port`.
+    pub fn add_udp_socket(&self, port: u16) -> Result<SocketHandle, NetError> {
+        let buffer = || {
+            udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0; SOCKET_BUFFER])
+        };
+        let mut socket = udp::Socket::new(buffer(), buffer());
+        socket.bind(port).map_err(|_| NetError::AddrInUse)?;
+        Ok(self.add_socket(socket))
+    }
+
+    /// Adds an unconnected TCP socket; connect or listen through
+    /// `with_socket`.
+    pub fn add_tcp_socket(&self) -> SocketHandle {
+        let buffer = || tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER]);
+        self.add_socket(tcp::Socket::new(buffer(), buffer()))
+    }
+
+    /// Calls `f` with the socket `handle`, which must be of type `S`.
+    pub fn with_socket<S: AnySocket<'static>, R>(&self, handle: SocketHandle, f: impl FnOnce(&mut S) -> R) -> R {
+        f(self.inner.lock().sockets.get_mut::<S>(handle))
+    }
+
+    /// Calls `f` with the socket `handle` and the interface's context, as
+    /// `tcp::Socket::connect` needs.
+    pub fn with_socket_and_context<S: AnySocket<'static>, R>(
+        &self,
+        handle: SocketHandle,
+        f: impl FnOnce(&mut S, &mut smoltcp::iface::Context) -> R,
+    ) -> R {
+        let mut inner = self.inner.lock();
+        let Inner { iface, sockets, .. } = &mut *inner;
+        f(sockets.get_mut::<S>(handle), iface.context())
+    }
+
+    /// Removes the socket `handle`.
+    pub fn remove_socket(&self, handle: SocketHandle) {
+        self.inner.lock().sockets.remove(handle);
+    }
+}
+
+/// Polls every attached stack, then sleeps until one needs polling again.
+fn poll_thread() {
+    loop {
+        let stacks = STACKS.lock().clone();
+        let mut delay = MAX_POLL_INTERVAL_MS;
+        for stack in &stacks {
+            stack.poll();
+            if let Some(ms) = stack.poll_delay_ms() {
+                delay = delay.min(ms);
+            }
+        }
+        task::sleep_ms(delay.max(1));
+    }
+}
@@ -0,0 +1,227 @@
+// nt_rustos/src/net/udp.rs
+
+//! UDP (RFC 768).
+//!
+//! A `UdpSocket` is bound to a local port on every interface. Datagrams
+//! received for the port are queued on the socket, up to `QUEUE_LEN`, and
+//! readers block on the socket's wait queue until one arrives. Datagrams for
+//! a port nobody has bound are dropped. Dropping the socket frees its port.
+//!
+//! `netcat` is a small `nc`-like command on top of the socket API, for
+//! exchanging data with the host; the kernel has no shell yet, so it is
+//! called directly for now.
+
+use super::ipv4::{self, Ipv4Addr, Packet, PROTO_UDP};
+use super::{Interface, NetError};
+use crate::println;
+use crate::task::WaitQueue;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Source port, destination port, length and checksum.
+pub const HEADER_LEN: usize = 8;
+/// Datagrams queued on a socket before newer ones are dropped.
+pub const QUEUE_LEN: usize = 64;
+/// Ports handed out when binding to port 0: 49152 to 65535.
+const EPHEMERAL_FIRST: u16 = 49152;
+const EPHEMERAL_COUNT: u32 = 16384;
+
+/// A received datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    pub data: Vec<u8>,
+}
+
+struct Socket {
+    port: u16,
+    queue: Mutex<VecDeque<Datagram>>,
+    readers: WaitQueue,
+}
+
+static SOCKETS: Mutex<Vec<Weak<Socket>>> = Mutex::new(Vec::new());
+static NEXT_EPHEMERAL: Mutex<u32> = Mutex::new(0);
+
+fn find(sockets: &[Weak<Socket>], port: u16) -> Option<Arc<Socket>> {
+    sockets.iter().filter_map(Weak::upgrade).find(|s| s.port == port)
+}
+
+/// Computes the checksum of a UDP datagram over the IPv4 pseudo-header.
+/// A result of 0 is sent as 0xFFFF, since 0 means "no checksum".
+pub fn checksum(src: Ipv4Addr, dst: Ipv4Addr, datagram: &[u8]) -> u16 {
+    let mut sum = ipv4::sum_words(0, &src.0);
+    sum = ipv4::sum_words(sum, &dst.0);
+    sum += PROTO_UDP as u32 + datagram.len() as u32;
+    match !ipv4::fold(ipv4::sum_words(sum, datagram)) {
+        0 => 0xFFFF,
+        sum => sum,
+    }
+}
+
+/// Encodes a datagram from `src:src_port` to `dst:dst_port`.
+pub fn encode(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_LEN + data.len());
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst_port.to_be_bytes());
+    datagram.extend_from_slice(&((HEADER_LEN + data.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    let sum = checksum(src, dst, &datagram);
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+/// A socket bound to a local UDP port.
+pub struct UdpSocket {
+    socket: Arc<Socket>,
+}
+
+impl UdpSocket {
+    /// Binds `port`, or a free ephemeral port if `port` is 0.
+    pub fn bind(port: u16) -> Result<Self, NetError> {
+        let mut sockets = SOCKETS.lock();
+        sockets.retain(|s| s.strong_count() > 0);
+        let port = match port {
+            0 => {
+                let mut next = NEXT_EPHEMERAL.lock();
+                let offset = (0..EPHEMERAL_COUNT)
+                    .map(|i| (*next + i) % EPHEMERAL_COUNT)
+                    .find(|&i| find(&sockets, EPHEMERAL_FIRST + i as u16).is_none())
+                    .ok_or(NetError::AddrInUse)?;
+                *next = (offset + 1) % EPHEMERAL_COUNT;
+                EPHEMERAL_FIRST + offset as u16
+            }
+            port if find(&sockets, port).is_some() => return Err(NetError::AddrInUse),
+            port => port,
+        };
+        let socket = Arc::new(Socket { port, queue: Mutex::new(VecDeque::new()), readers: WaitQueue::new() });
+        sockets.push(Arc::downgrade(&socket));
+        Ok(Self { socket })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.socket.port
+    }
+
+    /// Sends `data` to `dst:port`.
+    pub fn send_to(&self, data: &[u8], dst: Ipv4Addr, port: u16) -> Result<(), NetError> {
+        let route = ipv4::route(dst)?;
+        if ipv4::HEADER_LEN + HEADER_LEN + data.len() > route.iface.mtu() {
+            return Err(NetError::TooLong);
+        }
+        ipv4::send(dst, PROTO_UDP, &encode(route.src, self.socket.port, dst, port, data))
+    }
+
+    /// Returns the oldest queued datagram without blocking.
+    pub fn try_recv(&self) -> Option<Datagram> {
+        self.socket.queue.lock().pop_front()
+    }
+
+    /// Blocks until a datagram arrives and returns it. Must be called from
+    /// thread context.
+    pub fn recv(&self) -> Datagram {
+        let mut received = None;
+        self.socket.readers.wait_until(|| {
+            received = self.try_recv();
+            received.is_some()
+        });
+        received.unwrap()
+    }
+
+    /// Like `recv`, copying the data into `buf`. Returns the number of
+    /// bytes copied, and the sender; the rest of a longer datagram is lost.
+    pub fn recv_from(&self, buf: &mut [u8]) -> (usize, Ipv4Addr, u16) {
+        let datagram = self.recv();
+        let len = datagram.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram.data[..len]);
+        (len, datagram.src, datagram.src_port)
+    }
+
+    /// Returns the number of queued datagrams.
+    pub fn pending(&self) -> usize {
+        self.socket.queue.lock().len()
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let port = self.socket.port;
+        SOCKETS.lock().retain(|s| s.upgrade().is_some_and(|s| s.port != port));
+    }
+}
+
+/// Handles a received UDP packet.
+pub fn receive(iface: &Arc<Interface>, packet: &Packet) {
+    let bytes = packet.payload;
+    if bytes.len() < HEADER_LEN {
+        iface.count_dropped();
+        return;
+    }
+    let len = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+    let sum = u16::from_be_bytes([bytes[6], bytes[7]]);
+    if len < HEADER_LEN || len > bytes.len() {
+        iface.count_dropped();
+        return;
+    }
+    let datagram = &bytes[..len];
+    // Summing the datagram with its checksum in place gives 0, which
+    // `checksum` turns into 0xFFFF.
+    if sum != 0 && checksum(packet.src, packet.dst, datagram) != 0xFFFF {
+        iface.count_dropped();
+        return;
+    }
+    let src_port = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let dst_port = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let Some(socket) = find(&SOCKETS.lock(), dst_port) else {
+        iface.count_dropped();
+        return;
+    };
+    {
+        let mut queue = socket.queue.lock();
+        if queue.len() >= QUEUE_LEN {
+            drop(queue);
+            iface.count_dropped();
+            return;
+        }
+        queue.push_back(Datagram { src: packet.src, src_port, data: datagram[HEADER_LEN..].to_vec() });
+    }
+    socket.readers.wake_one();
+}
+
+/// The `netcat` command.
+///
+/// * `netcat <ip> <port> <text>` sends `text` in one datagram.
+/// * `netcat -l <port>` waits for one datagram on `port` and prints it.
+pub fn netcat(args: &str) -> Result<(), NetError> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let port = |arg: &str| arg.parse::<u16>().map_err(|_| NetError::InvalidArgument);
+    match args.as_slice() {
+        ["-l", local] => {
+            let socket = UdpSocket::bind(port(local)?)?;
+            println!("netcat: listening on UDP port {}", socket.local_port());
+            let datagram = socket.recv();
+            println!(
+                "netcat: {} bytes from {}:{}: {}",
+                datagram.data.len(),
+                datagram.src,
+                datagram.src_port,
+                String::from_utf8_lossy(&datagram.data)
+            );
+            Ok(())
+        }
+        [dst, remote, text @ ..] if !text.is_empty() => {
+            let dst: Ipv4Addr = dst.parse()?;
+            let remote = port(remote)?;
+            let socket = UdpSocket::bind(0)?;
+            socket.send_to(text.join(" ").as_bytes(), dst, remote)
+        }
+        _ => {
+            println!("usage: netcat <ip> <port> <text> | netcat -l <port>");
+            Err(NetError::InvalidArgument)
+        }
+    }
+}
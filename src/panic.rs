@@ -0,0 +1,506 @@
+// nt_rustos/src/panic.rs
+
+//! # Panic Handling
+//!
+//! Support for the panic handler. Its last step follows the panic policy,
+//! what to do once the report has been printed and the crash dump
+//! written: halt in place, which keeps the machine for a debugger
+//! and is the default; shut the machine down; or reboot it after a delay,
+//! so an unattended test machine comes back by itself and reports the
+//! crash on the next boot (see `crashdump`).
+//!
+//! The policy is set with `set_policy`, or through the tunables
+//! `kernel.panic` (`halt`, `shutdown` or `reboot`) and
+//! `kernel.panic_reboot_delay_ms`, which the command line can set as
+//! `sysctl.kernel.panic=reboot`. It is kept in atomics, so the panic
+//! handler reads it without taking locks.
+//!
+//! With the `qemu-exit` feature, automated test runs exit QEMU with
+//! `EXIT_PANIC` whatever the policy.
+//!
+//! Subsystems that must flush state or quiesce hardware before the machine
+//! stops register a hook with `register_hook`. The handler calls the hooks
+//! in registration order after the report, before following the policy.
+//! They run with interrupts disabled and cannot be interrupted, so they
+//! share a time budget: a hook finishing past it is named, and the hooks
+//! after it are skipped.
+//!
+//! The report is built with the console, the allocator statistics and
+//! other shared state, any of which may be what failed. A panic raised
+//! while the hart is already in the handler is therefore not reported the
+//! same way again: `enter` detects it, and `nested` prints its location
+//! with bare SBI calls and halts. A panic within that halts silently.
+//!
+//! Before reporting, the panicking hart stops the others so they do not
+//! run on, and change the state being reported, meanwhile. It sends them
+//! an IPI; the trap entry code hands each one to a stop hook that saves
+//! the context the hart was interrupted in and parks it. The report then
+//! shows every hart's registers, so deadlocks and races between harts can
+//! be diagnosed. A hart spinning with interrupts disabled never takes the
+//! IPI and is reported as not stopping. A hart that panics while another
+//! reports only prints its panic's location, with bare SBI calls, and
+//! parks.
+
+use crate::boot::park;
+use crate::init::initcall::InitResult;
+use crate::smp;
+use crate::sysctl::{self, Tunable, Value};
+use crate::task::scheduler::current_hart;
+use crate::task::MAX_HARTS;
+use crate::trap::{
+    self, Interrupt, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use crate::util::arrayvec::ArrayVec;
+use crate::util::ksyms::WithSymbol;
+use crate::util::sbi;
+use crate::{driver, error_print, time, EXIT_PANIC};
+use alloc::format;
+use alloc::string::String;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Milliseconds a reboot waits by default, so the report can be read.
+pub const DEFAULT_REBOOT_DELAY_MS: u64 = 5000;
+
+/// Most hooks that can be registered.
+pub const MAX_HOOKS: usize = 8;
+
+/// Milliseconds all hooks together may take.
+pub const HOOK_BUDGET_MS: u64 = 500;
+
+/// Milliseconds to wait for the other harts to stop.
+const STOP_TIMEOUT_MS: u64 = 100;
+
+/// `sie.SSIE`: supervisor software interrupt enable.
+const SIE_SSIE: usize = 1 << 1;
+/// `sip.SSIP`: supervisor software interrupt pending.
+const SIP_SSIP: usize = 1 << 1;
+
+const POLICY_TUNABLE: &str = "kernel.panic";
+const DELAY_TUNABLE: &str = "kernel.panic_reboot_delay_ms";
+
+/// What to do after a panic has been reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Stop the panicking hart in a `wfi` loop.
+    Halt,
+    /// Power the machine off.
+    Shutdown,
+    /// Reset the machine after `delay_ms` milliseconds.
+    Reboot { delay_ms: u64 },
+}
+
+impl PanicPolicy {
+    /// Returns the name the `kernel.panic` tunable uses for the policy.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Halt => "halt",
+            Self::Shutdown => "shutdown",
+            Self::Reboot { .. } => "reboot",
+        }
+    }
+}
+
+const HALT: u8 = 0;
+const SHUTDOWN: u8 = 1;
+const REBOOT: u8 = 2;
+
+static POLICY: AtomicU8 = AtomicU8::new(HALT);
+static REBOOT_DELAY_MS: AtomicU64 = AtomicU64::new(DEFAULT_REBOOT_DELAY_MS);
+
+/// Returns the policy the panic handler follows.
+pub fn policy() -> PanicPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        SHUTDOWN => PanicPolicy::Shutdown,
+        REBOOT => PanicPolicy::Reboot { delay_ms: REBOOT_DELAY_MS.load(Ordering::Relaxed) },
+        _ => PanicPolicy::Halt,
+    }
+}
+
+fn store(policy: PanicPolicy) {
+    let code = match policy {
+        PanicPolicy::Halt => HALT,
+        PanicPolicy::Shutdown => SHUTDOWN,
+        PanicPolicy::Reboot { delay_ms } => {
+            REBOOT_DELAY_MS.store(delay_ms, Ordering::Relaxed);
+            REBOOT
+        }
+    };
+    POLICY.store(code, Ordering::Relaxed);
+}
+
+/// Sets the policy the panic handler follows, and the tunables showing it.
+pub fn set_policy(policy: PanicPolicy) {
+    store(policy);
+    // Before the tunables are registered there is nothing to keep in step.
+    if let PanicPolicy::Reboot { delay_ms } = policy {
+        let _ = sysctl::set(DELAY_TUNABLE, Value::Int(delay_ms.min(i64::MAX as u64) as i64));
+    }
+    let _ = sysctl::set(POLICY_TUNABLE, Value::Str(String::from(policy.name())));
+}
+
+/// How deep each hart is in the panic handler.
+static DEPTH: [AtomicU8; MAX_HARTS] = [const { AtomicU8::new(0) }; MAX_HARTS];
+
+/// Marks the calling hart as in the panic handler. Returns how many times
+/// it already was: 0 for a first panic, 1 if the handler itself panicked
+/// and more if `nested` did.
+pub fn enter() -> u8 {
+    let depth = &DEPTH[current_hart()];
+    let previous = depth.load(Ordering::Relaxed);
+    depth.store(previous.saturating_add(1), Ordering::Relaxed);
+    previous
+}
+
+/// Prints `title` and the location of the panic through SBI directly:
+/// the message may be what panicked, and the console may be in any state.
+fn raw_report(title: &str, info: &PanicInfo) {
+    let _ = sbi::console::puts("\n");
+    let _ = sbi::console::puts(title);
+    if let Some(location) = info.location() {
+        let _ = sbi::console::puts(" at ");
+        let _ = sbi::console::puts(location.file());
+        let _ = sbi::console::puts(":");
+        let _ = sbi::console::putnum(location.line() as usize, 10);
+    }
+    let _ = sbi::console::puts("\n");
+}
+
+/// Reports a panic raised inside the panic handler and halts. Only its
+/// location is printed.
+pub fn nested(info: &PanicInfo) -> ! {
+    raw_report("NESTED KERNEL PANIC", info);
+    let _ = sbi::console::puts("System halted.\n");
+    halt()
+}
+
+/// No hart is reporting a panic.
+const NO_HART: usize = usize::MAX;
+
+/// The hart reporting a panic, once one is.
+static REPORTER: AtomicUsize = AtomicUsize::new(NO_HART);
+/// Harts sent the stop IPI, bit `n` for hart `n`.
+static STOP_SENT: AtomicU64 = AtomicU64::new(0);
+/// Harts that panicked while another one reported.
+static ALSO_PANICKED: AtomicU64 = AtomicU64::new(0);
+
+/// The context a hart was stopped in.
+struct Saved {
+    ready: AtomicBool,
+    context: UnsafeCell<TrapContext>,
+}
+
+// Each hart writes only its own slot, once, before setting `ready`.
+unsafe impl Sync for Saved {}
+
+static SAVED: [Saved; MAX_HARTS] =
+    [const { Saved { ready: AtomicBool::new(false), context: UnsafeCell::new(TrapContext::new()) } }; MAX_HARTS];
+
+fn is_stopped(hart: usize) -> bool {
+    SAVED.get(hart).is_some_and(|saved| saved.ready.load(Ordering::Acquire))
+}
+
+/// Makes the calling hart the one reporting the panic and stops the other
+/// online harts, waiting up to `STOP_TIMEOUT_MS` for them. Returns `false`
+/// if another hart is already reporting one; the caller should then call
+/// `park_hart`.
+pub fn stop_other_harts() -> bool {
+    let hart = current_hart();
+    if REPORTER.compare_exchange(NO_HART, hart, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return false;
+    }
+    // Parked harts run no kernel code and have nothing to save.
+    let others = smp::online_mask() & !park::parked_mask() & !(1 << hart);
+    if others == 0 {
+        return true;
+    }
+    STOP_SENT.store(others, Ordering::Release);
+    let _ = sbi::ipi::send_ipi(others as usize);
+    let pending = |hart: usize| {
+        others & (1 << hart) != 0 && !is_stopped(hart) && ALSO_PANICKED.load(Ordering::Acquire) & (1 << hart) == 0
+    };
+    let deadline = time::deadline_after_ms(STOP_TIMEOUT_MS);
+    while (0..park::MAX_PARKED).any(pending) && !time::reached(deadline) {
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// Reports a panic on a hart other than the one already reporting one, by
+/// its location only, and parks the hart.
+pub fn park_hart(info: &PanicInfo) -> ! {
+    let hart = current_hart();
+    if hart < park::MAX_PARKED {
+        ALSO_PANICKED.fetch_or(1 << hart, Ordering::AcqRel);
+    }
+    raw_report("KERNEL PANIC on another hart while one is reported", info);
+    halt()
+}
+
+/// Stops the calling hart if another one is reporting a panic, saving the
+/// context it was interrupted in. Runs first on every trap.
+fn stop_on_trap(context: &mut TrapContext) {
+    let reporter = REPORTER.load(Ordering::Acquire);
+    if reporter == NO_HART {
+        return;
+    }
+    let hart = current_hart();
+    if reporter == hart {
+        return;
+    }
+    let saved = &SAVED[hart];
+    if !saved.ready.load(Ordering::Relaxed) {
+        unsafe { *saved.context.get() = *context };
+        saved.ready.store(true, Ordering::Release);
+    }
+    halt()
+}
+
+/// Takes the stop IPI when no panic is being reported, so a stray one
+/// does not trap again and again.
+fn stop_ipi_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    let cause = context.cause();
+    if !cause.is_interrupt() || cause.code() != Interrupt::SupervisorSoft as usize {
+        return TrapHandlerResult::Pass;
+    }
+    unsafe {
+        asm!("csrc sip, {}", in(reg) SIP_SSIP);
+    }
+    TrapHandlerResult::Handled
+}
+
+/// What became of another hart when a panic stopped the harts.
+#[derive(Debug, Clone, Copy)]
+pub enum HartStop {
+    /// Stopped on the IPI, in this context.
+    Stopped(TrapContext),
+    /// Waiting in the park loop, running no kernel code.
+    Parked,
+    /// Panicked itself.
+    Panicked,
+    /// Did not take the IPI in time, for instance because it spins with
+    /// interrupts disabled.
+    NotStopped,
+}
+
+impl fmt::Display for HartStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stopped(context) => write!(f, "stopped\n{}", Registers(context)),
+            Self::Parked => write!(f, "parked"),
+            Self::Panicked => write!(f, "panicked"),
+            Self::NotStopped => write!(f, "did not stop"),
+        }
+    }
+}
+
+/// Returns what became of each present hart other than the one reporting
+/// the panic, in hart order. Harts taken offline are left out.
+pub fn other_harts() -> impl Iterator<Item = (usize, HartStop)> {
+    let reporter = REPORTER.load(Ordering::Acquire);
+    let present = smp::present_mask();
+    let sent = STOP_SENT.load(Ordering::Acquire);
+    let panicked = ALSO_PANICKED.load(Ordering::Acquire);
+    (0..park::MAX_PARKED).filter(move |&hart| hart != reporter && present & (1 << hart) != 0).filter_map(move |hart| {
+        let stop = if is_stopped(hart) {
+            HartStop::Stopped(unsafe { *SAVED[hart].context.get() })
+        } else if panicked & (1 << hart) != 0 {
+            HartStop::Panicked
+        } else if park::is_parked(hart) {
+            HartStop::Parked
+        } else if sent & (1 << hart) != 0 {
+            HartStop::NotStopped
+        } else {
+            return None;
+        };
+        Some((hart, stop))
+    })
+}
+
+/// ABI names of `x1` to `x31`.
+const REGISTER_NAMES: [&str; 31] = [
+    "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5", "a6",
+    "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// Formats a saved context as indented lines: the program counter and
+/// return address with their symbols, then every register.
+pub struct Registers<'a>(pub &'a TrapContext);
+
+impl fmt::Display for Registers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = self.0;
+        writeln!(f, "    pc: {}", WithSymbol(context.sepc))?;
+        writeln!(f, "    ra: {}", WithSymbol(context.x[1]))?;
+        write!(f, "    sstatus: {:#x}  scause: {:#x}  stval: {:#x}", context.sstatus, context.scause, context.stval)?;
+        for (index, name) in REGISTER_NAMES.iter().enumerate() {
+            if index % 4 == 0 {
+                write!(f, "\n   ")?;
+            }
+            write!(f, " {:>3}: {:016x}", name, context.x[index + 1])?;
+        }
+        Ok(())
+    }
+}
+
+/// Flushes state or quiesces hardware after a panic. Hooks must not
+/// block, and should not allocate or take locks other code may hold.
+pub type PanicHook = fn(&PanicInfo);
+
+/// Registered hooks, in registration order.
+static HOOKS: Mutex<ArrayVec<PanicHook, MAX_HOOKS>> = Mutex::new(ArrayVec::new());
+
+/// Registers `hook` to be called on a panic. Returns `false` if it is
+/// already registered or `MAX_HOOKS` are.
+pub fn register_hook(hook: PanicHook) -> bool {
+    let mut hooks = HOOKS.lock();
+    !hooks.iter().any(|&h| ptr::fn_addr_eq(h, hook)) && hooks.push(hook).is_ok()
+}
+
+/// Removes `hook`. Returns `false` if it was not registered.
+pub fn unregister_hook(hook: PanicHook) -> bool {
+    let mut hooks = HOOKS.lock();
+    match hooks.iter().position(|&h| ptr::fn_addr_eq(h, hook)) {
+        Some(index) => {
+            hooks.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Calls the registered hooks in order, skipping the rest once they have
+/// taken `HOOK_BUDGET_MS`. Called by the panic handler.
+pub fn run_hooks(info: &PanicInfo) {
+    // The panic may have struck while a hook was being registered.
+    let Some(hooks) = HOOKS.try_lock().map(|hooks| hooks.clone()) else {
+        error_print!("  Panic hooks locked, none run.");
+        return;
+    };
+    let start = time::monotonic_ms();
+    for (index, &hook) in hooks.iter().enumerate() {
+        hook(info);
+        let elapsed = time::monotonic_ms() - start;
+        if elapsed > HOOK_BUDGET_MS {
+            error_print!("  Panic hook {} ran over the {} ms budget; skipping {} more.",
+                         WithSymbol(hook as usize), HOOK_BUDGET_MS, hooks.len() - index - 1);
+            return;
+        }
+    }
+}
+
+/// Carries out the policy. Called last by the panic handler.
+pub fn finish() -> ! {
+    // Automated test runs must not stop at a panic. Write the test device
+    // directly (or shut down through SBI without it), not through `exit`,
+    // which may take locks.
+    if cfg!(feature = "qemu-exit") {
+        error_print!("Exiting with status {}.", EXIT_PANIC);
+        driver::sifive_test::exit(EXIT_PANIC);
+    }
+    match policy() {
+        PanicPolicy::Halt => {}
+        PanicPolicy::Shutdown => {
+            error_print!("Shutting down.");
+            driver::sifive_test::exit(EXIT_PANIC);
+        }
+        PanicPolicy::Reboot { delay_ms } => {
+            error_print!("Rebooting in {} ms.", delay_ms);
+            time::mdelay(delay_ms);
+            driver::sifive_test::reset();
+        }
+    }
+    error_print!("System halted.");
+    halt()
+}
+
+/// Stops the calling hart for good.
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+fn parse_policy(value: &Value) -> Option<PanicPolicy> {
+    match value.as_str()? {
+        "halt" => Some(PanicPolicy::Halt),
+        "shutdown" => Some(PanicPolicy::Shutdown),
+        "reboot" => Some(PanicPolicy::Reboot { delay_ms: REBOOT_DELAY_MS.load(Ordering::Relaxed) }),
+        _ => None,
+    }
+}
+
+fn check_policy(value: &Value) -> Result<(), &'static str> {
+    parse_policy(value).map(|_| ()).ok_or("policy is halt, shutdown or reboot")
+}
+
+fn apply_policy(value: &Value) {
+    if let Some(policy) = parse_policy(value) {
+        store(policy);
+    }
+}
+
+fn check_delay(value: &Value) -> Result<(), &'static str> {
+    match value.as_int() {
+        Some(delay) if delay >= 0 => Ok(()),
+        _ => Err("delay is 0 or more"),
+    }
+}
+
+fn apply_delay(value: &Value) {
+    if let Some(delay) = value.as_int() {
+        REBOOT_DELAY_MS.store(delay as u64, Ordering::Relaxed);
+    }
+}
+
+/// Lets the other harts stop this one on a panic: installs the stop hook
+/// and takes the stop IPI on the boot hart.
+fn init_stop() -> InitResult {
+    trap::set_trap_stop_hook(stop_on_trap).map_err(|e| format!("failed to install the panic stop hook: {}", e))?;
+    trap::register_trap_handler(
+        TrapType::SoftwareInterrupt,
+        stop_ipi_handler,
+        u8::MAX,
+        "Panic Stop IPI",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )
+    .map_err(|e| format!("failed to register the panic stop IPI handler: {}", e))?;
+    unsafe {
+        asm!("csrs sie, {}", in(reg) SIE_SSIE);
+    }
+    Ok(())
+}
+
+crate::initcall!(subsys, 15, init_stop);
+
+/// Registers the tunables; the delay first, so a `reboot` policy given on
+/// the command line picks up a delay given with it.
+fn register_tunables() -> InitResult {
+    sysctl::register(Tunable {
+        name: DELAY_TUNABLE,
+        description: "Milliseconds a reboot after a panic waits",
+        default: Value::Int(DEFAULT_REBOOT_DELAY_MS as i64),
+        validate: Some(check_delay),
+        changed: Some(apply_delay),
+    })
+    .map_err(|e| format!("failed to register {}: {}", DELAY_TUNABLE, e))?;
+    sysctl::register(Tunable {
+        name: POLICY_TUNABLE,
+        description: "After a panic: halt, shutdown or reboot",
+        default: Value::Str(String::from(policy().name())),
+        validate: Some(check_policy),
+        changed: Some(apply_policy),
+    })
+    .map_err(|e| format!("failed to register {}: {}", POLICY_TUNABLE, e))
+}
+
+crate::initcall!(early, 65, register_tunables);
@@ -0,0 +1,268 @@
+// nt_rustos/src/platform.rs
+
+//! # Platform Description
+//!
+//! The memory map and clock of the machine the kernel runs on, for
+//! subsystems that need them before (or without) a driver: the RAM range,
+//! the timebase, and the fixed devices of QEMU's `virt` machine.
+//!
+//! `init` reads the values from the boot device tree. Without a usable
+//! device tree every value falls back to the `virt` defaults below. With
+//! one, the RAM range and timebase fall back individually if the tree lacks
+//! them, while devices the tree does not describe are taken to be absent.
+//!
+//! RAM may come in several banks: every `reg` entry of every `memory` node
+//! is one, up to `MAX_RAM_BANKS`. `ram` is the first of them, the bank the
+//! kernel is loaded into on `virt`.
+
+use crate::boot;
+use crate::cpuinfo::{Extension, Isa};
+use crate::fdt::{Fdt, Node};
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use core::fmt;
+use spin::Once;
+
+/// RAM banks the description keeps; further banks are ignored.
+pub const MAX_RAM_BANKS: usize = 8;
+
+/// `virt` defaults, from QEMU's `hw/riscv/virt.c`.
+mod fallback {
+    use super::Region;
+
+    /// RAM starts at 2GB; QEMU's default size is 128MB.
+    pub const RAM: Region = Region { base: 0x8000_0000, size: 128 * 1024 * 1024 };
+    /// The `time` CSR counts at 10MHz.
+    pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+    pub const HART_COUNT: usize = 1;
+    pub const TEST_DEVICE: Region = Region { base: 0x10_0000, size: 0x1000 };
+    pub const CLINT: Region = Region { base: 0x200_0000, size: 0x1_0000 };
+    pub const PLIC: Region = Region { base: 0xc00_0000, size: 0x400_0000 };
+    pub const UART: Region = Region { base: 0x1000_0000, size: 0x100 };
+    pub const UART_IRQ: u32 = 10;
+    pub const PCIE_ECAM: Region = Region { base: 0x3000_0000, size: 0x1000_0000 };
+}
+
+/// A physical address range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub base: usize,
+    pub size: usize,
+}
+
+impl Region {
+    /// Returns the first address past the region.
+    pub fn end(&self) -> usize {
+        self.base + self.size
+    }
+
+    /// Returns `true` if `addr` lies in the region.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08x}-0x{:08x}", self.base, self.end())
+    }
+}
+
+/// Where the platform description came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Source {
+    DeviceTree,
+    /// No usable device tree; every value is a `virt` default.
+    Fallback,
+}
+
+/// The machine's memory map and clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Platform {
+    pub source: Source,
+    /// The first RAM range.
+    pub ram: Region,
+    /// Every RAM range, `ram` first, in device tree order.
+    pub ram_banks: [Option<Region>; MAX_RAM_BANKS],
+    /// Frequency of the `time` CSR in Hz.
+    pub timebase_frequency: u64,
+    pub hart_count: usize,
+    /// Every hart has the Sstc extension, so the kernel can program its
+    /// timer through `stimecmp` without calling SBI.
+    pub sstc: bool,
+    /// The 16550 UART and its interrupt.
+    pub uart: Option<(Region, u32)>,
+    pub plic: Option<Region>,
+    pub clint: Option<Region>,
+    /// The test finisher that powers the machine off.
+    pub test_device: Option<Region>,
+    /// The PCIe configuration space window.
+    pub pcie_ecam: Option<Region>,
+    /// The initial ramdisk the boot loader placed in RAM, from `/chosen`.
+    pub initrd: Option<Region>,
+}
+
+impl Platform {
+    /// The `virt` machine as QEMU builds it by default.
+    pub const FALLBACK: Platform = Platform {
+        source: Source::Fallback,
+        ram: fallback::RAM,
+        ram_banks: {
+            let mut banks = [None; MAX_RAM_BANKS];
+            banks[0] = Some(fallback::RAM);
+            banks
+        },
+        timebase_frequency: fallback::TIMEBASE_FREQUENCY,
+        hart_count: fallback::HART_COUNT,
+        sstc: false,
+        uart: Some((fallback::UART, fallback::UART_IRQ)),
+        plic: Some(fallback::PLIC),
+        clint: Some(fallback::CLINT),
+        test_device: Some(fallback::TEST_DEVICE),
+        pcie_ecam: Some(fallback::PCIE_ECAM),
+        initrd: None,
+    };
+
+    /// Reads the description from `fdt`.
+    pub fn from_fdt(fdt: &Fdt) -> Self {
+        let find = |compatible: &[&str]| {
+            fdt.nodes()
+                .filter_map(Result::ok)
+                .find(|n| n.is_enabled() && n.compatible().any(|c| compatible.contains(&c)))
+        };
+        let mut ram_banks = [None; MAX_RAM_BANKS];
+        let banks = fdt
+            .nodes()
+            .filter_map(Result::ok)
+            .filter(|n| n.is_enabled() && n.property("device_type").and_then(|p| p.as_str()) == Some("memory"))
+            .flat_map(|n| n.reg())
+            .filter(|r| r.size != 0)
+            .map(|r| Region { base: r.address as usize, size: r.size as usize });
+        for (slot, bank) in ram_banks.iter_mut().zip(banks) {
+            *slot = Some(bank);
+        }
+        if ram_banks[0].is_none() {
+            ram_banks[0] = Some(fallback::RAM);
+        }
+        let ram = ram_banks[0].unwrap_or(fallback::RAM);
+        let cpus = fdt.find_node("cpus");
+        let timebase_frequency = cpus
+            .and_then(|n| n.property("timebase-frequency"))
+            .and_then(|p| match p.value.len() {
+                4 => p.as_u32().map(u64::from),
+                8 => Some(p.cells().fold(0u64, |acc, cell| (acc << 32) | cell as u64)),
+                _ => None,
+            })
+            .filter(|&f| f != 0)
+            .unwrap_or(fallback::TIMEBASE_FREQUENCY);
+        let harts = || {
+            fdt.nodes()
+                .filter_map(Result::ok)
+                .filter(|n| n.is_enabled() && n.property("device_type").and_then(|p| p.as_str()) == Some("cpu"))
+        };
+        let hart_count = harts().count().max(1);
+        let sstc = harts().count() > 0 && harts().all(|n| Isa::from_node(&n).has(Extension::Sstc));
+        // `/chosen` gives the initrd bounds as one or two cells each.
+        let chosen = fdt.find_node("chosen");
+        let chosen_addr = |name: &str| {
+            let value = chosen?.property(name)?;
+            match value.value.len() {
+                4 | 8 => Some(value.cells().fold(0u64, |acc, cell| (acc << 32) | cell as u64) as usize),
+                _ => None,
+            }
+        };
+        let initrd = match (chosen_addr("linux,initrd-start"), chosen_addr("linux,initrd-end")) {
+            (Some(start), Some(end)) if end > start => Some(Region { base: start, size: end - start }),
+            _ => None,
+        };
+        let uart = find(&["ns16550a"])
+            .and_then(|n| Some((first_region(&n)?, n.interrupts().next().unwrap_or(fallback::UART_IRQ))));
+
+        Self {
+            source: Source::DeviceTree,
+            ram,
+            ram_banks,
+            timebase_frequency,
+            hart_count,
+            sstc,
+            uart,
+            plic: find(&["riscv,plic0", "sifive,plic-1.0.0"]).and_then(|n| first_region(&n)),
+            clint: find(&["riscv,clint0", "sifive,clint0"]).and_then(|n| first_region(&n)),
+            test_device: find(&["sifive,test0", "sifive,test1"]).and_then(|n| first_region(&n)),
+            pcie_ecam: find(&["pci-host-ecam-generic"]).and_then(|n| first_region(&n)),
+            initrd,
+        }
+    }
+
+    /// Iterates over the RAM banks, `ram` first.
+    pub fn ram_banks(&self) -> impl Iterator<Item = Region> + '_ {
+        self.ram_banks.iter().flatten().copied()
+    }
+
+    /// Returns `true` if `addr` lies in any RAM bank.
+    pub fn in_ram(&self, addr: usize) -> bool {
+        self.ram_banks().any(|bank| bank.contains(addr))
+    }
+
+    /// Returns the number of `time` ticks per millisecond.
+    pub fn ticks_per_ms(&self) -> u64 {
+        (self.timebase_frequency / 1000).max(1)
+    }
+
+    /// Prints the description.
+    pub fn print(&self) {
+        let show = |name: &str, region: Option<Region>| match region {
+            Some(region) => crate::println!("  {:<8} {}", name, region),
+            None => crate::println!("  {:<8} -", name),
+        };
+        crate::println!("Platform ({:?}):", self.source);
+        for bank in self.ram_banks() {
+            show("RAM", Some(bank));
+        }
+        crate::println!("  {:<8} {} Hz", "Timebase", self.timebase_frequency);
+        crate::println!("  {:<8} {}", "Harts", self.hart_count);
+        crate::println!("  {:<8} {}", "Sstc", if self.sstc { "yes" } else { "no" });
+        match self.uart {
+            Some((region, irq)) => crate::println!("  {:<8} {} irq {}", "UART", region, irq),
+            None => show("UART", None),
+        }
+        show("PLIC", self.plic);
+        show("CLINT", self.clint);
+        show("Test", self.test_device);
+        show("PCIe", self.pcie_ecam);
+        show("Initrd", self.initrd);
+    }
+}
+
+/// Returns the node's first `reg` entry.
+fn first_region(node: &Node) -> Option<Region> {
+    node.reg()
+        .next()
+        .map(|r| Region { base: r.address as usize, size: r.size as usize })
+}
+
+static PLATFORM: Once<Platform> = Once::new();
+
+/// Reads the platform description from the boot device tree. Only the first
+/// call has an effect; it must follow `boot::record`.
+pub fn init() -> &'static Platform {
+    PLATFORM.call_once(|| {
+        let fdt = boot::boot_info().and_then(|info| unsafe { Fdt::from_addr(info.dtb_addr) }.ok());
+        fdt.map_or(Platform::FALLBACK, |fdt| Platform::from_fdt(&fdt))
+    })
+}
+
+/// Reads the platform description; every later initcall depends on it.
+fn init_platform() -> InitResult {
+    let platform = init();
+    info_print!("Platform: RAM {}, timebase {} Hz, {} hart(s) ({:?}).",
+                platform.ram, platform.timebase_frequency, platform.hart_count, platform.source);
+    Ok(())
+}
+
+crate::initcall!(early, 10, init_platform);
+
+/// Returns the platform description; before `init` this is the fallback.
+pub fn get() -> &'static Platform {
+    PLATFORM.get().unwrap_or(&Platform::FALLBACK)
+}
@@ -0,0 +1,377 @@
+// nt_rustos/src/pm.rs
+
+//! # CPU Idle Power Management
+//!
+//! An idle hart enters one of a list of idle states, ordered from the
+//! shallowest to the deepest. Deeper states save more power but take
+//! longer to leave and only pay off if the hart stays idle long enough, so
+//! each state declares its exit latency and target residency. A governor
+//! picks the state from the time until the next timer deadline and the
+//! latency limit set with `set_latency_limit_us`.
+//!
+//! Built-in states are `wfi` and, on SBI implementations with HSM
+//! `hart_suspend`, a default retentive suspend. Built-in governors are
+//! `menu`, which picks the deepest state that fits, and `shallow`, which
+//! always picks the first state. Residency statistics are kept per hart and
+//! state.
+//!
+//! `idle` is called with interrupts disabled, and returns once an interrupt
+//! is pending without taking it.
+
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use crate::println;
+use crate::task::{scheduler, MAX_HARTS};
+use crate::time;
+use crate::util::sbi::{base, extension_ids, hsm, info};
+use alloc::format;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::RwLock;
+
+/// Idle states that can be registered.
+pub const MAX_STATES: usize = 4;
+
+/// Lowest SBI specification version with HSM `hart_suspend` (v0.3).
+const SBI_VERSION_HART_SUSPEND: usize = 3;
+
+/// A way for an idle hart to wait for an interrupt.
+pub trait IdleState: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Time it takes to resume after an interrupt, in microseconds.
+    fn exit_latency_us(&self) -> u64;
+
+    /// Shortest idle period for which the state saves power over the
+    /// shallower ones, in microseconds.
+    fn target_residency_us(&self) -> u64;
+
+    /// Returns `false` if the state cannot be entered on this machine.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Waits until an interrupt is pending. Called with interrupts
+    /// disabled; must return without taking the interrupt.
+    fn enter(&self);
+}
+
+/// What a governor knows about an idle period.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IdleBudget {
+    /// Time until the next timer deadline, in microseconds, or `None` if
+    /// no deadline is pending.
+    pub predicted_us: Option<u64>,
+    /// Largest acceptable exit latency, in microseconds.
+    pub latency_limit_us: u64,
+}
+
+/// A policy choosing the idle state.
+pub trait Governor: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the index into `states` of the state to enter. `states` is
+    /// never empty and holds only available states, shallowest first.
+    fn select(&self, states: &[&'static dyn IdleState], budget: IdleBudget) -> usize;
+}
+
+/// Errors returned by the power management interface.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PmError {
+    /// A state or governor with the same name is already registered.
+    AlreadyRegistered,
+    /// `MAX_STATES` states are registered.
+    TooManyStates,
+    /// No governor with that name is registered.
+    UnknownGovernor,
+}
+
+impl fmt::Display for PmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlreadyRegistered => write!(f, "already registered"),
+            Self::TooManyStates => write!(f, "too many idle states"),
+            Self::UnknownGovernor => write!(f, "unknown governor"),
+        }
+    }
+}
+
+/// Waits in `wfi`.
+pub struct WfiState;
+
+impl IdleState for WfiState {
+    fn name(&self) -> &'static str {
+        "wfi"
+    }
+
+    fn exit_latency_us(&self) -> u64 {
+        0
+    }
+
+    fn target_residency_us(&self) -> u64 {
+        0
+    }
+
+    fn enter(&self) {
+        unsafe { asm!("wfi", options(nomem, nostack)) }
+    }
+}
+
+/// Suspends the hart through SBI HSM, letting the firmware pick a deeper
+/// platform state than `wfi`. The suspend is retentive: the hart resumes
+/// after the call once an interrupt is pending.
+pub struct HsmSuspendState {
+    /// Cleared if the firmware turns out not to implement the call.
+    supported: AtomicBool,
+}
+
+impl IdleState for HsmSuspendState {
+    fn name(&self) -> &'static str {
+        "hsm-suspend"
+    }
+
+    fn exit_latency_us(&self) -> u64 {
+        10
+    }
+
+    fn target_residency_us(&self) -> u64 {
+        100
+    }
+
+    fn is_available(&self) -> bool {
+        self.supported.load(Ordering::Relaxed)
+    }
+
+    fn enter(&self) {
+        if hsm::hart_suspend(hsm::SUSPEND_TYPE_DEFAULT_RETENTIVE, 0, 0).is_err() {
+            self.supported.store(false, Ordering::Relaxed);
+            WFI.enter();
+        }
+    }
+}
+
+/// Picks the deepest state whose target residency fits before the next
+/// deadline and whose exit latency is within the limit.
+pub struct MenuGovernor;
+
+impl Governor for MenuGovernor {
+    fn name(&self) -> &'static str {
+        "menu"
+    }
+
+    fn select(&self, states: &[&'static dyn IdleState], budget: IdleBudget) -> usize {
+        let predicted = budget.predicted_us.unwrap_or(u64::MAX);
+        states
+            .iter()
+            .rposition(|s| s.target_residency_us() <= predicted && s.exit_latency_us() <= budget.latency_limit_us)
+            .unwrap_or(0)
+    }
+}
+
+/// Always picks the shallowest state.
+pub struct ShallowGovernor;
+
+impl Governor for ShallowGovernor {
+    fn name(&self) -> &'static str {
+        "shallow"
+    }
+
+    fn select(&self, _states: &[&'static dyn IdleState], _budget: IdleBudget) -> usize {
+        0
+    }
+}
+
+pub static WFI: WfiState = WfiState;
+pub static HSM_SUSPEND: HsmSuspendState = HsmSuspendState { supported: AtomicBool::new(true) };
+pub static MENU: MenuGovernor = MenuGovernor;
+pub static SHALLOW: ShallowGovernor = ShallowGovernor;
+
+static STATES: RwLock<Vec<&'static dyn IdleState>> = RwLock::new(Vec::new());
+static GOVERNORS: RwLock<Vec<&'static dyn Governor>> = RwLock::new(Vec::new());
+static GOVERNOR: RwLock<&'static dyn Governor> = RwLock::new(&MENU as &dyn Governor);
+static LATENCY_LIMIT_US: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Residency counters of one state on one hart.
+struct Residency {
+    entries: AtomicU64,
+    ticks: AtomicU64,
+    /// Entries that ended before the state's target residency.
+    early_exits: AtomicU64,
+}
+
+const NO_RESIDENCY: Residency =
+    Residency { entries: AtomicU64::new(0), ticks: AtomicU64::new(0), early_exits: AtomicU64::new(0) };
+const NO_RESIDENCIES: [Residency; MAX_STATES] = [NO_RESIDENCY; MAX_STATES];
+
+static RESIDENCY: [[Residency; MAX_STATES]; MAX_HARTS] = [NO_RESIDENCIES; MAX_HARTS];
+
+/// Residency statistics of one state on one hart.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StateStats {
+    pub name: &'static str,
+    pub entries: u64,
+    /// Time spent in the state, in timer ticks.
+    pub ticks: u64,
+    pub early_exits: u64,
+}
+
+/// Adds `state` as the deepest idle state.
+pub fn register_state(state: &'static dyn IdleState) -> Result<(), PmError> {
+    let mut states = STATES.write();
+    if states.iter().any(|s| s.name() == state.name()) {
+        return Err(PmError::AlreadyRegistered);
+    }
+    if states.len() == MAX_STATES {
+        return Err(PmError::TooManyStates);
+    }
+    states.push(state);
+    Ok(())
+}
+
+/// Makes a governor available to `set_governor`.
+pub fn register_governor(governor: &'static dyn Governor) -> Result<(), PmError> {
+    let mut governors = GOVERNORS.write();
+    if governors.iter().any(|g| g.name() == governor.name()) {
+        return Err(PmError::AlreadyRegistered);
+    }
+    governors.push(governor);
+    Ok(())
+}
+
+/// Switches to the registered governor called `name`.
+pub fn set_governor(name: &str) -> Result<(), PmError> {
+    let governor = GOVERNORS
+        .read()
+        .iter()
+        .copied()
+        .find(|g| g.name() == name)
+        .ok_or(PmError::UnknownGovernor)?;
+    *GOVERNOR.write() = governor;
+    Ok(())
+}
+
+/// Returns the name of the current governor.
+pub fn governor_name() -> &'static str {
+    GOVERNOR.read().name()
+}
+
+/// Limits the exit latency of the states governors pick; `u64::MAX`
+/// removes the limit.
+pub fn set_latency_limit_us(limit: u64) {
+    LATENCY_LIMIT_US.store(limit, Ordering::Relaxed);
+}
+
+/// Registers the built-in states and governors.
+pub fn init() -> Result<(), PmError> {
+    register_governor(&MENU)?;
+    register_governor(&SHALLOW)?;
+    register_state(&WFI)?;
+    let hart_suspend = info::is_extension_available(extension_ids::HSM)
+        && base::get_spec_version().map_or(false, |v| v >= SBI_VERSION_HART_SUSPEND);
+    if hart_suspend {
+        register_state(&HSM_SUSPEND)?;
+    }
+    Ok(())
+}
+
+/// Registers the idle states the scheduler enters when nothing runs.
+fn register_idle() -> InitResult {
+    init().map_err(|e| format!("failed to register CPU idle states: {}", e))?;
+    info_print!("CPU idle states registered (governor: {}).", governor_name());
+    Ok(())
+}
+
+crate::initcall!(subsys, 20, register_idle);
+
+/// Idles the current hart until an interrupt is pending, in the state the
+/// governor picks for an idle period ending at `deadline` (in timer ticks)
+/// at the latest. Must be called with interrupts disabled.
+pub fn idle(deadline: Option<u64>) {
+    let hart = scheduler::current_hart();
+    let now = time::now_ticks();
+    let budget = IdleBudget {
+        predicted_us: deadline.map(|d| time::ticks_to_us(d.saturating_sub(now))),
+        latency_limit_us: LATENCY_LIMIT_US.load(Ordering::Relaxed),
+    };
+
+    // The governor sees available states only; map its pick back to the
+    // registered index for the statistics.
+    let picked = {
+        let states = STATES.read();
+        let mut available = [&WFI as &'static dyn IdleState; MAX_STATES];
+        let mut indices = [0; MAX_STATES];
+        let mut count = 0;
+        for (index, state) in states.iter().enumerate().filter(|(_, s)| s.is_available()) {
+            available[count] = *state;
+            indices[count] = index;
+            count += 1;
+        }
+        (count > 0).then(|| {
+            let choice = GOVERNOR.read().select(&available[..count], budget).min(count - 1);
+            (indices[choice], available[choice])
+        })
+    };
+    let Some((index, state)) = picked else {
+        // Before `init`.
+        WFI.enter();
+        return;
+    };
+
+    state.enter();
+
+    let resident = time::now_ticks().saturating_sub(now);
+    let counters = &RESIDENCY[hart % MAX_HARTS][index];
+    counters.entries.fetch_add(1, Ordering::Relaxed);
+    counters.ticks.fetch_add(resident, Ordering::Relaxed);
+    if resident < time::us_to_ticks(state.target_residency_us()) {
+        counters.early_exits.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the residency statistics of `hart`, one entry per registered
+/// state.
+pub fn stats(hart: usize) -> Vec<StateStats> {
+    let states = STATES.read();
+    states
+        .iter()
+        .zip(RESIDENCY[hart % MAX_HARTS].iter())
+        .map(|(state, counters)| StateStats {
+            name: state.name(),
+            entries: counters.entries.load(Ordering::Relaxed),
+            ticks: counters.ticks.load(Ordering::Relaxed),
+            early_exits: counters.early_exits.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// Prints the states and the residency statistics of harts that idled.
+pub fn print_stats() {
+    println!("Idle states (governor: {}):", governor_name());
+    for state in STATES.read().iter() {
+        println!(
+            "  {:<12} latency {} us, residency {} us{}",
+            state.name(),
+            state.exit_latency_us(),
+            state.target_residency_us(),
+            if state.is_available() { "" } else { " (unavailable)" }
+        );
+    }
+    for hart in 0..MAX_HARTS {
+        let stats = stats(hart);
+        if stats.iter().all(|s| s.entries == 0) {
+            continue;
+        }
+        println!("  hart {}:", hart);
+        for s in stats {
+            println!(
+                "    {:<12} {} entries, {} ms, {} early exits",
+                s.name,
+                s.entries,
+                time::ticks_to_ms(s.ticks),
+                s.early_exits
+            );
+        }
+    }
+}
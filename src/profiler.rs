@@ -0,0 +1,400 @@
+// nt_rustos/src/profiler.rs
+
+//! # Sampling Profiler
+//!
+//! Records the interrupted program counter (`sepc`) at regular intervals
+//! and aggregates the samples into a flat profile of hot PCs.
+//!
+//! Samples are taken either on timer interrupts, with the tickless timer
+//! asked to also fire at the sampling period, or on counter overflow
+//! interrupts of a PMU counter (SBI PMU extension plus Sscofpmf), which
+//! samples every N occurrences of a hardware event: cycles, retired
+//! instructions, cache accesses or misses, branch mispredictions, or a raw
+//! event of the platform. Only the programmable `hpmcounter3..31` raise
+//! overflow interrupts, so the fixed `cycle` and `instret` counters are
+//! never picked, and counting is inhibited in M-mode so firmware work is
+//! not charged to the interrupted kernel PC. Handlers append to a fixed-size
+//! buffer of the current hart and never allocate; samples that find the
+//! buffer full or locked are counted as lost. Thread-context readers drain
+//! the buffers into the aggregated profile.
+//!
+//! Reports are symbolized with `util::ksyms` when the image carries a
+//! symbol table or one has been installed.
+
+use crate::init::initcall::InitResult;
+use crate::println;
+use crate::task::{scheduler, timer, MAX_HARTS};
+use crate::trap::{
+    self, Interrupt, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use crate::util::arrayvec::ArrayVec;
+use crate::util::ksyms::Symbolized;
+use crate::util::sbi::{base, extension_ids, pmu};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Samples each hart buffers between two drains.
+const SAMPLE_CAPACITY: usize = 1024;
+
+/// Priority of the sampling handlers: ahead of the scheduler timer, which
+/// consumes timer interrupts.
+const SAMPLE_HANDLER_PRIORITY: u8 = 40;
+
+/// `sie.LCOFIE` / `sip.LCOFIP`: local counter overflow interrupt.
+const SIE_LCOFIE: usize = 1 << 13;
+const SIP_LCOFIP: usize = 1 << 13;
+
+/// `PMU_COUNTER` value meaning no counter is in use.
+const NO_COUNTER: usize = usize::MAX;
+
+/// CSRs of the programmable counters, `hpmcounter3` to `hpmcounter31`.
+const CSR_HPMCOUNTER3: usize = 0xc03;
+const CSR_HPMCOUNTER31: usize = 0xc1f;
+
+/// A hardware event a PMU counter can sample on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PmuEvent {
+    Cycles,
+    Instructions,
+    CacheReferences,
+    CacheMisses,
+    BranchMisses,
+    /// Read misses in the L1 data cache.
+    L1DataReadMisses,
+    /// A platform-specific event, by its raw event code.
+    Raw(u64),
+}
+
+impl PmuEvent {
+    /// The SBI event index.
+    fn event_idx(&self) -> usize {
+        match self {
+            PmuEvent::Cycles => pmu::EVENT_HW_CPU_CYCLES,
+            PmuEvent::Instructions => pmu::EVENT_HW_INSTRUCTIONS,
+            PmuEvent::CacheReferences => pmu::EVENT_HW_CACHE_REFERENCES,
+            PmuEvent::CacheMisses => pmu::EVENT_HW_CACHE_MISSES,
+            PmuEvent::BranchMisses => pmu::EVENT_HW_BRANCH_MISSES,
+            PmuEvent::L1DataReadMisses => {
+                pmu::cache_event(pmu::CACHE_L1D, pmu::CACHE_OP_READ, pmu::CACHE_RESULT_MISS)
+            }
+            PmuEvent::Raw(_) => pmu::EVENT_HW_RAW,
+        }
+    }
+
+    /// The event data passed along with the index.
+    fn event_data(&self) -> u64 {
+        match self {
+            PmuEvent::Raw(code) => *code,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for PmuEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PmuEvent::Cycles => write!(f, "cycles"),
+            PmuEvent::Instructions => write!(f, "instructions"),
+            PmuEvent::CacheReferences => write!(f, "cache references"),
+            PmuEvent::CacheMisses => write!(f, "cache misses"),
+            PmuEvent::BranchMisses => write!(f, "branch misses"),
+            PmuEvent::L1DataReadMisses => write!(f, "L1 data read misses"),
+            PmuEvent::Raw(code) => write!(f, "raw event {:#x}", code),
+        }
+    }
+}
+
+/// What triggers a sample.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SampleSource {
+    /// A timer interrupt every `period` timer ticks.
+    Timer { period: u64 },
+    /// A counter overflow every `period` occurrences of `event`.
+    Pmu { event: PmuEvent, period: u64 },
+}
+
+impl fmt::Display for SampleSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleSource::Timer { period } => write!(f, "every {} timer ticks", period),
+            SampleSource::Pmu { event, period } => write!(f, "every {} {}", period, event),
+        }
+    }
+}
+
+/// Errors returned by the profiler.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProfilerError {
+    /// `init` has not registered the sampling handlers.
+    NotInitialized,
+    AlreadyRunning,
+    NotRunning,
+    /// The sampling period is zero.
+    InvalidPeriod,
+    /// No PMU counter can sample the requested event.
+    PmuUnavailable,
+}
+
+impl fmt::Display for ProfilerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInitialized => write!(f, "profiler not initialized"),
+            Self::AlreadyRunning => write!(f, "profiler already running"),
+            Self::NotRunning => write!(f, "profiler not running"),
+            Self::InvalidPeriod => write!(f, "sampling period must be non-zero"),
+            Self::PmuUnavailable => write!(f, "no PMU counter available for the event"),
+        }
+    }
+}
+
+/// A program counter and the number of samples that hit it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    pub pc: usize,
+    pub samples: u64,
+}
+
+/// Samples taken on each hart since the last drain.
+static BUFFERS: [Mutex<ArrayVec<usize, SAMPLE_CAPACITY>>; MAX_HARTS] =
+    [const { Mutex::new(ArrayVec::new()) }; MAX_HARTS];
+
+/// Drained samples, by program counter.
+static PROFILE: Mutex<BTreeMap<usize, u64>> = Mutex::new(BTreeMap::new());
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// Set while sampling on timer interrupts.
+static TIMER_SAMPLING: AtomicBool = AtomicBool::new(false);
+static LOST: AtomicU64 = AtomicU64::new(0);
+/// The PMU counter sampling, and the value it restarts from after an
+/// overflow.
+static PMU_COUNTER: AtomicUsize = AtomicUsize::new(NO_COUNTER);
+static PMU_RELOAD: AtomicU64 = AtomicU64::new(0);
+/// The source of the last run, for reports.
+static SOURCE: Mutex<Option<SampleSource>> = Mutex::new(None);
+
+/// Registers the sampling handlers. Must be called after `task::init`.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    trap::register_trap_handler(
+        TrapType::TimerInterrupt,
+        timer_sample_handler,
+        SAMPLE_HANDLER_PRIORITY,
+        "Profiler Timer Sample",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    trap::register_trap_handler(
+        TrapType::CounterOverflowInterrupt,
+        pmu_sample_handler,
+        SAMPLE_HANDLER_PRIORITY,
+        "Profiler PMU Sample",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    INITIALIZED.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Registers the sample handlers; timer sampling needs the task timer.
+fn register_handlers() -> InitResult {
+    init().map_err(|e| format!("failed to register profiler sample handlers: {}", e))?;
+    crate::info_print!("Profiler sample handlers registered.");
+    Ok(())
+}
+
+crate::initcall!(subsys, 40, register_handlers);
+
+fn record(pc: usize) {
+    let recorded = BUFFERS[scheduler::current_hart()].try_lock().is_some_and(|mut buffer| buffer.push(pc).is_ok());
+    if !recorded {
+        LOST.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn timer_sample_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if TIMER_SAMPLING.load(Ordering::Acquire) {
+        record(context.sepc);
+    }
+    // The scheduler timer still has to see the interrupt.
+    TrapHandlerResult::Pass
+}
+
+fn pmu_sample_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    let cause = context.cause();
+    if !cause.is_interrupt() || cause.code() != Interrupt::CounterOverflow as usize {
+        return TrapHandlerResult::Pass;
+    }
+    unsafe {
+        asm!("csrc sip, {}", in(reg) SIP_LCOFIP);
+    }
+    let counter = PMU_COUNTER.load(Ordering::Acquire);
+    if counter == NO_COUNTER {
+        return TrapHandlerResult::Handled;
+    }
+    record(context.sepc);
+    // Restarting with an initial value also clears the overflow flag.
+    let _ = pmu::counter_stop(counter, 1, 0);
+    let _ = pmu::counter_start(counter, 1, pmu::START_FLAG_SET_INIT_VALUE, PMU_RELOAD.load(Ordering::Relaxed));
+    TrapHandlerResult::Handled
+}
+
+/// Starts sampling from `source`.
+pub fn start(source: SampleSource) -> Result<(), ProfilerError> {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        return Err(ProfilerError::NotInitialized);
+    }
+    let period = match source {
+        SampleSource::Timer { period } | SampleSource::Pmu { period, .. } => period,
+    };
+    if period == 0 {
+        return Err(ProfilerError::InvalidPeriod);
+    }
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        return Err(ProfilerError::AlreadyRunning);
+    }
+    match source {
+        SampleSource::Timer { period } => {
+            TIMER_SAMPLING.store(true, Ordering::Release);
+            timer::set_sample_period(Some(period));
+        }
+        SampleSource::Pmu { event, period } => {
+            if let Err(e) = start_pmu(event, period) {
+                RUNNING.store(false, Ordering::Release);
+                return Err(e);
+            }
+        }
+    }
+    *SOURCE.lock() = Some(source);
+    Ok(())
+}
+
+/// The mask of counters that can raise overflow interrupts.
+fn overflow_counters() -> usize {
+    let count = pmu::get_num_counters().unwrap_or(0).min(usize::BITS as usize);
+    (0..count)
+        .filter(|&counter| {
+            pmu::counter_info(counter)
+                .is_ok_and(|info| !info.firmware && (CSR_HPMCOUNTER3..=CSR_HPMCOUNTER31).contains(&info.csr))
+        })
+        .fold(0, |mask, counter| mask | 1 << counter)
+}
+
+/// Configures a counter for `event` that overflows every `period` events.
+fn start_pmu(event: PmuEvent, period: u64) -> Result<(), ProfilerError> {
+    if base::probe_extension(extension_ids::PMU).unwrap_or(0) == 0 {
+        return Err(ProfilerError::PmuUnavailable);
+    }
+    let mask = overflow_counters();
+    if mask == 0 {
+        return Err(ProfilerError::PmuUnavailable);
+    }
+    let flags = pmu::CFG_FLAG_CLEAR_VALUE | pmu::CFG_FLAG_SET_MINH;
+    let counter = pmu::counter_config_matching(0, mask, flags, event.event_idx(), event.event_data())
+        .map_err(|_| ProfilerError::PmuUnavailable)?;
+    let reload = period.wrapping_neg();
+    PMU_RELOAD.store(reload, Ordering::Relaxed);
+    PMU_COUNTER.store(counter, Ordering::Release);
+    if pmu::counter_start(counter, 1, pmu::START_FLAG_SET_INIT_VALUE, reload).is_err() {
+        PMU_COUNTER.store(NO_COUNTER, Ordering::Release);
+        let _ = pmu::counter_stop(counter, 1, pmu::STOP_FLAG_RESET);
+        return Err(ProfilerError::PmuUnavailable);
+    }
+    unsafe {
+        asm!("csrs sie, {}", in(reg) SIE_LCOFIE);
+    }
+    Ok(())
+}
+
+/// Stops sampling. Samples taken so far are kept.
+pub fn stop() -> Result<(), ProfilerError> {
+    if !RUNNING.swap(false, Ordering::AcqRel) {
+        return Err(ProfilerError::NotRunning);
+    }
+    if TIMER_SAMPLING.swap(false, Ordering::AcqRel) {
+        timer::set_sample_period(None);
+    }
+    let counter = PMU_COUNTER.swap(NO_COUNTER, Ordering::AcqRel);
+    if counter != NO_COUNTER {
+        unsafe {
+            asm!("csrc sie, {}", in(reg) SIE_LCOFIE);
+        }
+        let _ = pmu::counter_stop(counter, 1, pmu::STOP_FLAG_RESET);
+    }
+    Ok(())
+}
+
+/// Returns `true` while sampling.
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// Moves buffered samples into the aggregated profile.
+fn drain(profile: &mut BTreeMap<usize, u64>) {
+    for buffer in BUFFERS.iter() {
+        let mut buffer = buffer.lock();
+        for pc in buffer.iter() {
+            *profile.entry(*pc).or_insert(0) += 1;
+        }
+        buffer.clear();
+    }
+}
+
+/// Returns the profile gathered so far, hottest PCs first.
+pub fn profile() -> Vec<HotSpot> {
+    let mut profile = PROFILE.lock();
+    drain(&mut profile);
+    let mut spots: Vec<HotSpot> = profile.iter().map(|(&pc, &samples)| HotSpot { pc, samples }).collect();
+    spots.sort_by(|a, b| b.samples.cmp(&a.samples).then(a.pc.cmp(&b.pc)));
+    spots
+}
+
+/// Returns the number of samples taken so far.
+pub fn sample_count() -> u64 {
+    let mut profile = PROFILE.lock();
+    drain(&mut profile);
+    profile.values().sum()
+}
+
+/// Returns the number of samples dropped because a buffer was full or busy.
+pub fn lost_samples() -> u64 {
+    LOST.load(Ordering::Relaxed)
+}
+
+/// Discards all samples taken so far.
+pub fn reset() {
+    let mut profile = PROFILE.lock();
+    drain(&mut profile);
+    profile.clear();
+    LOST.store(0, Ordering::Relaxed);
+}
+
+/// Prints the `top` hottest PCs with their share of all samples.
+pub fn print_report(top: usize) {
+    let spots = profile();
+    let total: u64 = spots.iter().map(|s| s.samples).sum();
+    match *SOURCE.lock() {
+        Some(source) => println!("Profile: {} samples, {} lost, sampling {}", total, lost_samples(), source),
+        None => println!("Profile: {} samples, {} lost", total, lost_samples()),
+    }
+    if total == 0 {
+        return;
+    }
+    println!("  {:>6}  {:>8}  location", "share", "samples");
+    for spot in spots.iter().take(top) {
+        let permille = spot.samples * 1000 / total;
+        println!(
+            "  {:>3}.{}%  {:>8}  {}",
+            permille / 10,
+            permille % 10,
+            spot.samples,
+            Symbolized(spot.pc)
+        );
+    }
+}
@@ -0,0 +1,219 @@
+// nt_rustos/src/report.rs
+
+//! # Metrics Reports
+//!
+//! Serializes kernel metrics for external tooling that tracks them across
+//! runs: the memory summary of `get_memory_info`, the allocator's
+//! `AllocStats`, trap counts, the error statistics of the error manager
+//! and the summary of the last test run. Each
+//! section gives one or more records that start with `section=<name>`,
+//! written as `name=value` lines or as JSON Lines with the formats of
+//! `debugfs`, so a line can be told apart from other console output and
+//! parsed on its own.
+//!
+//! `command` takes the arguments of a `report` shell command. The kernel
+//! also emits a report of every section once on the way down, from
+//! `shutdown` and `exit`, before the allocator is frozen. The command line
+//! selects its format with `report_format=json` (key=value otherwise) and
+//! turns it off with `report=off`.
+
+use crate::cmdline;
+use crate::fs::debugfs::{self, Format, Record};
+use crate::trap::{self, ErrorLevel, TrapType};
+use crate::{print, println, test, time};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A group of metrics in a report.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Section {
+    /// `MemoryInfo`, with the uptime.
+    Memory,
+    /// The allocator's `AllocStats`.
+    Alloc,
+    /// One record per trap type taken at least once.
+    Traps,
+    /// One record per error source that reported errors.
+    Errors,
+    /// The counts of the last test run, if tests ran.
+    Tests,
+}
+
+impl Section {
+    pub const ALL: [Section; 5] = [Section::Memory, Section::Alloc, Section::Traps, Section::Errors, Section::Tests];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Memory => "memory",
+            Section::Alloc => "alloc",
+            Section::Traps => "traps",
+            Section::Errors => "errors",
+            Section::Tests => "tests",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|section| section.name() == name)
+    }
+
+    fn records(self, out: &mut Vec<Record>) {
+        let record = Record::new().field("section", self.name());
+        match self {
+            Section::Memory => {
+                let info = crate::get_memory_info();
+                out.push(
+                    record
+                        .field("uptime_ms", time::monotonic_ms())
+                        .field("total", info.total_size)
+                        .field("used", info.used_size)
+                        .field("free", info.free_size)
+                        .field("usage_percent", info.usage_percent())
+                        .field("allocator_initialized", info.allocator_initialized)
+                        .field("allocator_enabled", info.allocator_enabled)
+                        .field("allocs", info.allocation_count)
+                        .field("deallocs", info.deallocation_count),
+                );
+            }
+            Section::Alloc => {
+                let Some(stats) = crate::init::alloc::stats() else {
+                    return;
+                };
+                out.push(
+                    record
+                        .field("total", stats.total_size)
+                        .field("used", stats.used_size)
+                        .field("free", stats.free_size)
+                        .field("peak_used", stats.peak_used_size)
+                        .field("largest_free", stats.max_free_block_size)
+                        .field("live_allocs", stats.alloc_count)
+                        .field("free_blocks", stats.free_count)
+                        .field("allocs", stats.total_allocs)
+                        .field("frees", stats.total_frees)
+                        .field("failed_allocs", stats.failed_allocs)
+                        .field("double_frees", stats.double_free_attempts)
+                        .field("corrupted_blocks", stats.corrupted_blocks)
+                        .field("avg_alloc_size", stats.avg_alloc_size)
+                        .field("max_alloc_size", stats.max_alloc_size)
+                        .field("splits", stats.split_count)
+                        .field("merges", stats.merge_count)
+                        .field("fragmentation_percent", stats.fragmentation_percent),
+                );
+            }
+            Section::Traps => {
+                for trap_type in (0..TrapType::COUNT).filter_map(TrapType::from_index) {
+                    let count = trap::trap_count(trap_type);
+                    if count > 0 {
+                        out.push(record.clone().field("trap", format!("{:?}", trap_type)).field("count", count));
+                    }
+                }
+            }
+            Section::Errors => {
+                let window = trap::error_rate_limit().map_or(0, |limit| limit.window_ticks);
+                for stats in trap::error_stats() {
+                    out.push(
+                        record
+                            .clone()
+                            .field("source", format!("{:?}", stats.source))
+                            .field("fatal", stats.count(ErrorLevel::Fatal))
+                            .field("critical", stats.count(ErrorLevel::Critical))
+                            .field("error", stats.count(ErrorLevel::Error))
+                            .field("warning", stats.count(ErrorLevel::Warning))
+                            .field("info", stats.count(ErrorLevel::Info))
+                            .field("first_ticks", stats.first_ticks)
+                            .field("last_ticks", stats.last_ticks)
+                            .field("recent", stats.recent)
+                            .field("window_ticks", window),
+                    );
+                }
+            }
+            Section::Tests => {
+                let Some(summary) = test::last_summary() else {
+                    return;
+                };
+                out.push(
+                    record
+                        .field("total", summary.total)
+                        .field("passed", summary.passed)
+                        .field("failed", summary.failed)
+                        .field("skipped", summary.skipped)
+                        .field("filtered", summary.filtered)
+                        .field("duration_us", summary.elapsed.as_micros() as u64),
+                );
+            }
+        }
+    }
+}
+
+/// Errors of the `report` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportError {
+    /// No section has the name.
+    UnknownSection(String),
+    /// `format=` names neither `json` nor `kv`.
+    UnknownFormat(String),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSection(name) => write!(f, "unknown section '{}'", name),
+            Self::UnknownFormat(name) => write!(f, "unknown format '{}'", name),
+        }
+    }
+}
+
+/// Serializes `sections`, in order, in `format`.
+pub fn report(sections: &[Section], format: Format) -> String {
+    let mut records = Vec::new();
+    for section in sections {
+        section.records(&mut records);
+    }
+    debugfs::format(&records, format)
+}
+
+fn parse_format(name: &str) -> Option<Format> {
+    match name {
+        "json" => Some(Format::Json),
+        "kv" => Some(Format::KeyValue),
+        _ => None,
+    }
+}
+
+/// Runs a `report` command with arguments `args`: prints the named
+/// sections (all of them if none is named) as key=value lines, or as JSON
+/// Lines given `format=json`.
+pub fn command(args: &str) -> Result<(), ReportError> {
+    let mut format = Format::KeyValue;
+    let mut sections = Vec::new();
+    for (name, value) in cmdline::parse(args) {
+        match (name, value) {
+            ("format", Some(value)) => {
+                format = parse_format(value).ok_or_else(|| ReportError::UnknownFormat(String::from(value)))?;
+            }
+            (name, None) => sections.push(Section::parse(name).ok_or_else(|| ReportError::UnknownSection(String::from(name)))?),
+            (name, Some(_)) => return Err(ReportError::UnknownSection(String::from(name))),
+        }
+    }
+    if sections.is_empty() {
+        sections.extend(Section::ALL);
+    }
+    print!("{}", report(&sections, format));
+    Ok(())
+}
+
+static EMITTED: AtomicBool = AtomicBool::new(false);
+
+/// Prints a report of every section, once, unless the command line has
+/// `report=off`. Called on the way to shutdown and exit.
+pub fn emit_at_shutdown() {
+    if cmdline::value("report") == Some("off") || EMITTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let format = cmdline::value("report_format").and_then(parse_format).unwrap_or(Format::KeyValue);
+    println!("=== Metrics Report ===");
+    print!("{}", report(&Section::ALL, format));
+    println!("======================");
+}
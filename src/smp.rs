@@ -0,0 +1,231 @@
+// nt_rustos/src/smp.rs
+
+//! # Hart Hotplug
+//!
+//! `offline_hart` takes a secondary hart down for power management or
+//! debugging, and `online_hart` brings it back:
+//!
+//! - Going offline, the hart first leaves the online mask, which interrupt
+//!   routing consults, then the scheduler, whose tasks queued on it move to
+//!   other harts. A parked hart is then released to a stub that calls the
+//!   SBI HSM `hart_stop`, and the call returns once the firmware reports the
+//!   hart stopped. A hart running other code cannot be stopped from outside
+//!   and is left alone.
+//! - Coming online, a stopped hart is started at `_start` with HSM
+//!   `hart_start`, where it loses the boot lottery and parks (see
+//!   `boot::park`). It rejoins the online mask, and the scheduler if it was
+//!   scheduling before it went down.
+//!
+//! The boot hart runs the kernel and cannot be taken offline. Hotplug
+//! operations are serialized.
+
+use crate::boot::{self, park};
+use crate::cpuinfo;
+use crate::info_print;
+use crate::task::{self, TaskError, MAX_HARTS};
+use crate::time;
+use crate::util::sbi::{hsm, SbiError};
+use alloc::vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+/// How long to wait for a hart to stop or park.
+const HSM_TIMEOUT_MS: u64 = 100;
+/// Size of the stack a parked hart calls `hart_stop` on, in 16-byte words.
+const STOP_STACK_WORDS: usize = 256;
+
+/// Harts taken offline, bit `n` for hart `n`.
+static OFFLINE: AtomicU64 = AtomicU64::new(0);
+/// Offline harts the scheduler used before they went down.
+static WAS_SCHEDULING: AtomicU64 = AtomicU64::new(0);
+/// Serializes hotplug operations.
+static HOTPLUG: Mutex<()> = Mutex::new(());
+
+/// Why a hart could not be taken offline or brought online.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SmpError {
+    /// The hart is not described, or its ID has no bit in the masks.
+    InvalidHart,
+    /// The hart runs the kernel itself.
+    BootHart,
+    /// The hart runs code other than the park loop, or is changing state.
+    Busy,
+    /// The firmware rejected the HSM call.
+    Sbi(SbiError),
+    /// The hart did not stop or park in time.
+    Timeout,
+    /// The scheduler refused to take the hart in or out.
+    Scheduler(TaskError),
+}
+
+impl fmt::Display for SmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmpError::InvalidHart => write!(f, "no such hart"),
+            SmpError::BootHart => write!(f, "the boot hart cannot go offline"),
+            SmpError::Busy => write!(f, "hart is busy"),
+            SmpError::Sbi(e) => write!(f, "SBI HSM call failed: {:?}", e),
+            SmpError::Timeout => write!(f, "hart did not respond in time"),
+            SmpError::Scheduler(e) => write!(f, "scheduler refused: {:?}", e),
+        }
+    }
+}
+
+/// What a hart is doing, as far as the kernel can tell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HartState {
+    /// Stopped by the firmware.
+    Stopped,
+    /// Waiting in `__boot_park`.
+    Parked,
+    /// Started, and running the kernel or released code.
+    Running,
+    /// Between started and stopped, or suspended.
+    Transitioning,
+    /// The firmware has no HSM extension or does not know the hart.
+    Unknown,
+}
+
+/// Returns the state of `hart`.
+pub fn hart_state(hart: usize) -> HartState {
+    match hsm::hart_get_status(hart) {
+        Ok(hsm::HART_STATE_STOPPED) => HartState::Stopped,
+        Ok(hsm::HART_STATE_STARTED) if park::is_parked(hart) => HartState::Parked,
+        Ok(hsm::HART_STATE_STARTED) => HartState::Running,
+        Ok(_) => HartState::Transitioning,
+        Err(_) => HartState::Unknown,
+    }
+}
+
+fn boot_hart() -> usize {
+    boot::boot_info().map_or(0, |info| info.hart_id)
+}
+
+/// Returns the mask of harts the kernel knows of: the enabled harts of
+/// `cpuinfo`, or only the boot hart if none are described.
+pub fn present_mask() -> u64 {
+    let described = cpuinfo::get().map_or(0, |info| {
+        info.harts()
+            .filter(|h| h.enabled && h.hart_id < park::MAX_PARKED)
+            .fold(0, |mask, h| mask | (1 << h.hart_id))
+    });
+    if described != 0 {
+        described
+    } else {
+        1 << (boot_hart() % park::MAX_PARKED)
+    }
+}
+
+/// Returns the mask of present harts not taken offline; only these may be
+/// sent interrupts or work.
+pub fn online_mask() -> u64 {
+    present_mask() & !OFFLINE.load(Ordering::Acquire)
+}
+
+pub fn is_online(hart: usize) -> bool {
+    hart < park::MAX_PARKED && online_mask() & (1 << hart) != 0
+}
+
+/// Waits up to `HSM_TIMEOUT_MS` for `done`.
+fn wait_for(done: impl Fn() -> bool) -> bool {
+    let deadline = time::deadline_after_ms(HSM_TIMEOUT_MS);
+    while !done() {
+        if time::reached(deadline) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Where a parked hart is released to when it goes offline.
+extern "C" fn stop_entry(_hart_id: usize, _arg: usize) -> ! {
+    loop {
+        let _ = hsm::hart_stop();
+    }
+}
+
+/// Checks that `hart` is present and not the boot hart.
+fn check_secondary(hart: usize) -> Result<(), SmpError> {
+    if hart >= park::MAX_PARKED || present_mask() & (1 << hart) == 0 {
+        return Err(SmpError::InvalidHart);
+    }
+    if hart == boot_hart() {
+        return Err(SmpError::BootHart);
+    }
+    Ok(())
+}
+
+/// Takes `hart` out of interrupt routing and scheduling and stops it.
+pub fn offline_hart(hart: usize) -> Result<(), SmpError> {
+    check_secondary(hart)?;
+    let _hotplug = HOTPLUG.lock();
+    let state = hart_state(hart);
+    if !matches!(state, HartState::Stopped | HartState::Parked) {
+        return Err(SmpError::Busy);
+    }
+
+    let bit = 1 << hart;
+    OFFLINE.fetch_or(bit, Ordering::AcqRel);
+    let scheduled = if hart < MAX_HARTS { task::set_hart_online(hart, false) } else { Ok(false) };
+    let was_scheduling = match scheduled {
+        Ok(was_online) => was_online,
+        Err(TaskError::NotInitialized) => false,
+        Err(e) => {
+            OFFLINE.fetch_and(!bit, Ordering::AcqRel);
+            return Err(SmpError::Scheduler(e));
+        }
+    };
+    if was_scheduling {
+        WAS_SCHEDULING.fetch_or(bit, Ordering::AcqRel);
+    }
+
+    if state == HartState::Parked {
+        let stack = vec![0u128; STOP_STACK_WORDS];
+        let stack_top = stack.as_ptr_range().end as usize;
+        if unsafe { park::release(hart, stop_entry, stack_top, 0) } == Err(park::ParkError::NotParked) {
+            // Released by someone else in the meantime: undo.
+            if was_scheduling {
+                WAS_SCHEDULING.fetch_and(!bit, Ordering::AcqRel);
+                let _ = task::set_hart_online(hart, true);
+            }
+            OFFLINE.fetch_and(!bit, Ordering::AcqRel);
+            return Err(SmpError::Busy);
+        }
+        if !wait_for(|| hart_state(hart) == HartState::Stopped) {
+            // The hart may still be on the stack.
+            core::mem::forget(stack);
+            return Err(SmpError::Timeout);
+        }
+    }
+    info_print!("Hart {} offline.", hart);
+    Ok(())
+}
+
+/// Starts `hart` if it is stopped, waits for it to park and puts it back
+/// into interrupt routing, and into scheduling if it was scheduling before.
+pub fn online_hart(hart: usize) -> Result<(), SmpError> {
+    check_secondary(hart)?;
+    let _hotplug = HOTPLUG.lock();
+    match hart_state(hart) {
+        HartState::Stopped => {
+            extern "C" {
+                fn _start();
+            }
+            hsm::hart_start(hart, _start as usize, 0).map_err(SmpError::Sbi)?;
+            if !wait_for(|| park::is_parked(hart)) {
+                return Err(SmpError::Timeout);
+            }
+        }
+        HartState::Parked | HartState::Running => {}
+        HartState::Transitioning | HartState::Unknown => return Err(SmpError::Busy),
+    }
+
+    let bit = 1 << hart;
+    if WAS_SCHEDULING.fetch_and(!bit, Ordering::AcqRel) & bit != 0 {
+        task::set_hart_online(hart, true).map_err(SmpError::Scheduler)?;
+    }
+    OFFLINE.fetch_and(!bit, Ordering::AcqRel);
+    info_print!("Hart {} online.", hart);
+    Ok(())
+}
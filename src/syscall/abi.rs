@@ -0,0 +1,247 @@
+// nt_rustos/src/syscall/abi.rs
+
+//! # System Call ABI
+//!
+//! Defines the stable contract between user code and the kernel's system call
+//! dispatcher. Both the dispatcher (`crate::syscall`) and the user-side helpers
+//! (`crate::syscall::usys`) are built on the definitions in this file, so the
+//! two sides cannot drift apart.
+//!
+//! ## Calling convention
+//! * The system call number is passed in `a7`.
+//! * Up to six arguments are passed in `a0`-`a5`.
+//! * The call is issued with `ecall` from U-mode.
+//! * The result is returned in `a0`. Values in the range `[-4095, -1]`
+//!   (interpreted as `isize`) are negated `SyscallError` codes; every other
+//!   value is a successful return.
+//!
+//! Numbers follow the Linux RISC-V table where the semantics match, which keeps
+//! user tooling (disassemblers, tracers) readable.
+
+use core::fmt;
+
+/// Writes a buffer to a file descriptor. `(fd, buf, len) -> written`.
+pub const SYS_WRITE: usize = 64;
+/// Terminates the calling process. `(exit_code) -> !`.
+pub const SYS_EXIT: usize = 93;
+/// Waits on or wakes a futex word. `(addr, op, val) -> 0 | woken`.
+///
+/// `op` is `FUTEX_WAIT` (block while `*addr == val`) or `FUTEX_WAKE` (wake up
+/// to `val` waiters).
+pub const SYS_FUTEX: usize = 98;
+/// Suspends the calling task for a number of milliseconds. `(ms) -> 0`.
+///
+/// Unlike Linux `nanosleep`, the duration is passed by value in `a0`.
+pub const SYS_SLEEP: usize = 101;
+/// Gives up the CPU to another runnable task. `() -> 0`.
+pub const SYS_YIELD: usize = 124;
+/// Posts a signal to a process. `(pid, signo) -> 0`.
+pub const SYS_KILL: usize = 129;
+/// Sets the action for a signal. `(signo, handler, restorer) -> 0`.
+///
+/// `handler` is `SIG_DFL`, `SIG_IGN` or the address of a user handler, which
+/// is entered with `ra = restorer`.
+pub const SYS_SIGACTION: usize = 134;
+/// Returns from a signal handler to the interrupted code. `() -> !`.
+pub const SYS_SIGRETURN: usize = 139;
+/// Returns the process ID of the caller. `() -> pid`.
+pub const SYS_GETPID: usize = 172;
+/// Duplicates the calling process. `() -> child pid` in the parent, `0` in
+/// the child.
+///
+/// Uses the Linux `clone` number, but takes no arguments: the child always
+/// gets an eager copy of the parent's address space.
+pub const SYS_FORK: usize = 220;
+
+/// `SYS_SIGACTION` handler value selecting the default action.
+pub const SIG_DFL: usize = 0;
+/// `SYS_SIGACTION` handler value ignoring the signal.
+pub const SIG_IGN: usize = 1;
+
+/// `SYS_FUTEX` operation blocking while the word holds the given value.
+pub const FUTEX_WAIT: usize = 0;
+/// `SYS_FUTEX` operation waking waiters.
+pub const FUTEX_WAKE: usize = 1;
+
+/// Maximum number of arguments a system call can take.
+pub const MAX_SYSCALL_ARGS: usize = 6;
+
+/// Standard file descriptor for console output.
+pub const FD_STDOUT: usize = 1;
+/// Standard file descriptor for console error output.
+pub const FD_STDERR: usize = 2;
+
+/// Register index of `a0` in `TrapContext::x`.
+pub const REG_A0: usize = 10;
+/// Register index of `a7` in `TrapContext::x`.
+pub const REG_A7: usize = 17;
+
+/// The set of system calls known to the dispatcher.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Syscall {
+    Write = SYS_WRITE,
+    Exit = SYS_EXIT,
+    Futex = SYS_FUTEX,
+    Sleep = SYS_SLEEP,
+    Yield = SYS_YIELD,
+    Kill = SYS_KILL,
+    SigAction = SYS_SIGACTION,
+    SigReturn = SYS_SIGRETURN,
+    GetPid = SYS_GETPID,
+    Fork = SYS_FORK,
+}
+
+impl Syscall {
+    /// Converts a raw system call number into a `Syscall`.
+    pub fn from_number(number: usize) -> Option<Self> {
+        match number {
+            SYS_WRITE => Some(Syscall::Write),
+            SYS_EXIT => Some(Syscall::Exit),
+            SYS_FUTEX => Some(Syscall::Futex),
+            SYS_SLEEP => Some(Syscall::Sleep),
+            SYS_YIELD => Some(Syscall::Yield),
+            SYS_KILL => Some(Syscall::Kill),
+            SYS_SIGACTION => Some(Syscall::SigAction),
+            SYS_SIGRETURN => Some(Syscall::SigReturn),
+            SYS_GETPID => Some(Syscall::GetPid),
+            SYS_FORK => Some(Syscall::Fork),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw system call number.
+    pub const fn number(&self) -> usize {
+        *self as usize
+    }
+
+    /// Returns a short, human-readable name for tracing.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syscall::Write => "write",
+            Syscall::Exit => "exit",
+            Syscall::Futex => "futex",
+            Syscall::Sleep => "sleep",
+            Syscall::Yield => "yield",
+            Syscall::Kill => "kill",
+            Syscall::SigAction => "sigaction",
+            Syscall::SigReturn => "sigreturn",
+            Syscall::GetPid => "getpid",
+            Syscall::Fork => "fork",
+        }
+    }
+}
+
+/// Error codes returned by system calls, negated in `a0`.
+/// The values match the Linux errno numbers of the same name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(isize)]
+pub enum SyscallError {
+    /// Operation not permitted.
+    EPERM = 1,
+    /// No such file or directory.
+    ENOENT = 2,
+    /// No such process.
+    ESRCH = 3,
+    /// Interrupted system call.
+    EINTR = 4,
+    /// I/O error.
+    EIO = 5,
+    /// Bad file descriptor.
+    EBADF = 9,
+    /// Resource temporarily unavailable.
+    EAGAIN = 11,
+    /// Out of memory.
+    ENOMEM = 12,
+    /// Bad address.
+    EFAULT = 14,
+    /// Device or resource busy.
+    EBUSY = 16,
+    /// File exists.
+    EEXIST = 17,
+    /// Invalid argument.
+    EINVAL = 22,
+    /// Function not implemented.
+    ENOSYS = 38,
+}
+
+impl SyscallError {
+    /// Converts a positive errno value into a `SyscallError`.
+    pub fn from_errno(errno: isize) -> Self {
+        match errno {
+            1 => SyscallError::EPERM,
+            2 => SyscallError::ENOENT,
+            3 => SyscallError::ESRCH,
+            4 => SyscallError::EINTR,
+            5 => SyscallError::EIO,
+            9 => SyscallError::EBADF,
+            11 => SyscallError::EAGAIN,
+            12 => SyscallError::ENOMEM,
+            14 => SyscallError::EFAULT,
+            16 => SyscallError::EBUSY,
+            17 => SyscallError::EEXIST,
+            22 => SyscallError::EINVAL,
+            _ => SyscallError::ENOSYS,
+        }
+    }
+
+    /// Returns the positive errno value.
+    pub const fn errno(&self) -> isize {
+        *self as isize
+    }
+}
+
+impl fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} (errno {})", self, self.errno())
+    }
+}
+
+/// The result type of a system call implementation.
+pub type SyscallResult = Result<usize, SyscallError>;
+
+/// The largest errno value that can be encoded in a return register.
+const MAX_ERRNO: usize = 4095;
+
+/// Encodes a `SyscallResult` into the raw value placed in `a0`.
+pub fn encode_result(result: SyscallResult) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(e) => (-e.errno()) as usize,
+    }
+}
+
+/// Decodes the raw value returned in `a0` into a `SyscallResult`.
+pub fn decode_result(raw: usize) -> SyscallResult {
+    if raw > usize::MAX - MAX_ERRNO {
+        Err(SyscallError::from_errno(-(raw as isize)))
+    } else {
+        Ok(raw)
+    }
+}
+
+/// The decoded arguments of a single system call.
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallArgs {
+    /// The system call number (`a7`).
+    pub number: usize,
+    /// The raw arguments (`a0`-`a5`).
+    pub args: [usize; MAX_SYSCALL_ARGS],
+}
+
+impl SyscallArgs {
+    /// Extracts the system call number and arguments from a trap context.
+    pub fn from_context(context: &crate::trap::TrapContext) -> Self {
+        let mut args = [0usize; MAX_SYSCALL_ARGS];
+        args.copy_from_slice(&context.x[REG_A0..REG_A0 + MAX_SYSCALL_ARGS]);
+        Self {
+            number: context.x[REG_A7],
+            args,
+        }
+    }
+
+    /// Returns argument `index`, or 0 if out of range.
+    pub fn arg(&self, index: usize) -> usize {
+        self.args.get(index).copied().unwrap_or(0)
+    }
+}
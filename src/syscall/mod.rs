@@ -0,0 +1,187 @@
+// nt_rustos/src/syscall/mod.rs
+
+//! # System Call Subsystem
+//!
+//! Receives `ecall` traps from U-mode, decodes them according to the ABI in
+//! `abi`, and forwards them to the individual system call implementations.
+//! The dispatcher is registered with the trap subsystem as a kernel-protected
+//! `TrapType::SystemCall` handler.
+
+pub mod abi;
+pub mod usys;
+
+pub use self::abi::{Syscall, SyscallArgs, SyscallError, SyscallResult};
+
+use crate::init::initcall::InitResult;
+use crate::trap::{
+    self, Exception, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use crate::task::{Signal, SignalAction};
+use crate::{console, mm, task};
+use alloc::format;
+
+/// Priority of the system call dispatcher. Nothing should run before it.
+const SYSCALL_HANDLER_PRIORITY: u8 = 0;
+
+/// Size of the bounce buffer `sys_write` copies user data through.
+const WRITE_CHUNK_SIZE: usize = 256;
+
+/// Registers the system call dispatcher with the trap subsystem.
+///
+/// Must be called after `trap::init`.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    trap::register_trap_handler(
+        TrapType::SystemCall,
+        syscall_trap_handler,
+        SYSCALL_HANDLER_PRIORITY,
+        "System Call Dispatcher",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )
+    .map(|_| ())
+}
+
+/// Registers the system call dispatcher with the trap subsystem.
+fn register_dispatcher() -> InitResult {
+    init().map_err(|e| format!("failed to register syscall dispatcher: {}", e))?;
+    crate::info_print!("Syscall dispatcher registered.");
+    Ok(())
+}
+
+crate::initcall!(subsys, 30, register_dispatcher);
+
+/// The trap handler for environment calls.
+///
+/// Only U-mode `ecall`s are handled here; S-mode `ecall`s go to the SBI
+/// firmware and never reach the kernel's trap vector.
+fn syscall_trap_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.cause().code() != Exception::UserEnvCall as usize {
+        return TrapHandlerResult::Pass;
+    }
+
+    // Return to the instruction following the `ecall`.
+    context.advance_sepc();
+
+    let args = SyscallArgs::from_context(context);
+    let result = dispatch(&args, context);
+    context.set_return_value(abi::encode_result(result));
+    TrapHandlerResult::Handled
+}
+
+/// Routes a decoded system call to its implementation.
+///
+/// `context` is the caller's trap frame, with `sepc` already past the `ecall`.
+pub fn dispatch(args: &SyscallArgs, context: &mut TrapContext) -> SyscallResult {
+    let syscall = match Syscall::from_number(args.number) {
+        Some(s) => s,
+        None => return Err(SyscallError::ENOSYS),
+    };
+
+    match syscall {
+        Syscall::Write => sys_write(args.arg(0), args.arg(1), args.arg(2)),
+        Syscall::Exit => sys_exit(args.arg(0) as i32),
+        Syscall::Futex => sys_futex(args.arg(0), args.arg(1), args.arg(2)),
+        Syscall::Sleep => sys_sleep(args.arg(0)),
+        Syscall::Yield => sys_yield(),
+        Syscall::Kill => sys_kill(args.arg(0), args.arg(1)),
+        Syscall::SigAction => sys_sigaction(args.arg(0), args.arg(1), args.arg(2)),
+        Syscall::SigReturn => sys_sigreturn(context),
+        Syscall::GetPid => sys_getpid(),
+        Syscall::Fork => sys_fork(context),
+    }
+}
+
+fn sys_write(fd: usize, buf: usize, len: usize) -> SyscallResult {
+    if fd != abi::FD_STDOUT && fd != abi::FD_STDERR {
+        return Err(SyscallError::EBADF);
+    }
+
+    // Copy through a bounce buffer so a bad pointer fails with EFAULT.
+    let mut chunk = [0u8; WRITE_CHUNK_SIZE];
+    let mut written = 0;
+    while written < len {
+        let n = (len - written).min(WRITE_CHUNK_SIZE);
+        let addr = buf.checked_add(written).ok_or(SyscallError::EFAULT)?;
+        mm::copy_from_user(&mut chunk[..n], addr).map_err(|_| SyscallError::EFAULT)?;
+        match core::str::from_utf8(&chunk[..n]) {
+            Ok(s) => console::print_str(s),
+            Err(_) => {
+                for &b in &chunk[..n] {
+                    console::print_char(b as char);
+                }
+            }
+        }
+        written += n;
+    }
+    Ok(len)
+}
+
+fn sys_exit(code: i32) -> SyscallResult {
+    // The task is switched away from on trap exit and never resumed.
+    task::exit_current(code);
+    Ok(0)
+}
+
+fn sys_futex(addr: usize, op: usize, val: usize) -> SyscallResult {
+    let result = match op {
+        abi::FUTEX_WAIT => task::futex::wait(addr, val as u32).map(|_| 0),
+        abi::FUTEX_WAKE => task::futex::wake(addr, val),
+        _ => return Err(SyscallError::EINVAL),
+    };
+    result.map_err(|e| match e {
+        task::TaskError::WouldBlock => SyscallError::EAGAIN,
+        task::TaskError::BadAddress => SyscallError::EFAULT,
+        _ => SyscallError::EINVAL,
+    })
+}
+
+fn sys_sleep(ms: usize) -> SyscallResult {
+    task::sleep_ms(ms as u64);
+    Ok(0)
+}
+
+fn sys_yield() -> SyscallResult {
+    task::yield_now();
+    Ok(0)
+}
+
+fn sys_kill(pid: usize, signo: usize) -> SyscallResult {
+    let signal = Signal::from_number(signo).ok_or(SyscallError::EINVAL)?;
+    match task::signal::post(pid as task::Pid, signal) {
+        Ok(()) => Ok(0),
+        Err(task::TaskError::NoSuchTask) => Err(SyscallError::ESRCH),
+        Err(_) => Err(SyscallError::EPERM),
+    }
+}
+
+fn sys_sigaction(signo: usize, handler: usize, restorer: usize) -> SyscallResult {
+    let signal = Signal::from_number(signo).ok_or(SyscallError::EINVAL)?;
+    let action = match handler {
+        abi::SIG_DFL => SignalAction::Default,
+        abi::SIG_IGN => SignalAction::Ignore,
+        entry => SignalAction::Handler { entry, restorer },
+    };
+    task::signal::set_action(signal, action)
+        .map(|_| 0)
+        .map_err(|_| SyscallError::EINVAL)
+}
+
+fn sys_sigreturn(context: &mut TrapContext) -> SyscallResult {
+    task::signal::sigreturn(context).map_err(|_| SyscallError::EINVAL)?;
+    // The dispatcher writes the result to a0; hand back the restored value.
+    Ok(context.x[abi::REG_A0])
+}
+
+fn sys_getpid() -> SyscallResult {
+    Ok(task::current_pid() as usize)
+}
+
+fn sys_fork(context: &TrapContext) -> SyscallResult {
+    match task::fork_current(context) {
+        Ok(pid) => Ok(pid as usize),
+        Err(task::TaskError::NotUserTask) => Err(SyscallError::EINVAL),
+        Err(task::TaskError::NoFreePid) => Err(SyscallError::EAGAIN),
+        Err(_) => Err(SyscallError::ENOMEM),
+    }
+}
@@ -0,0 +1,106 @@
+// nt_rustos/src/syscall/usys.rs
+
+//! # User-side System Call Helpers
+//!
+//! Thin wrappers that issue `ecall` following the ABI in `super::abi`.
+//! These are intended to be linked into user programs (or run from U-mode
+//! test payloads). An `ecall` executed from S-mode traps to the SBI firmware,
+//! not to the kernel, so calling these helpers from kernel code is an error.
+
+use super::abi::{self, SyscallResult};
+use core::arch::asm;
+
+/// Issues a raw system call with up to three arguments.
+#[inline(always)]
+pub fn syscall3(number: usize, a0: usize, a1: usize, a2: usize) -> usize {
+    let ret: usize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") a0 => ret,
+            in("a1") a1,
+            in("a2") a2,
+            in("a7") number,
+        );
+    }
+    ret
+}
+
+/// Issues a raw system call with up to six arguments.
+#[inline(always)]
+pub fn syscall6(number: usize, args: [usize; abi::MAX_SYSCALL_ARGS]) -> usize {
+    let ret: usize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => ret,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a7") number,
+        );
+    }
+    ret
+}
+
+/// Writes `buf` to the file descriptor `fd`.
+pub fn write(fd: usize, buf: &[u8]) -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_WRITE, fd, buf.as_ptr() as usize, buf.len()))
+}
+
+/// Terminates the calling process with `code`.
+pub fn exit(code: i32) -> ! {
+    syscall3(abi::SYS_EXIT, code as usize, 0, 0);
+    unreachable!("sys_exit returned");
+}
+
+/// Blocks while the word at `addr` equals `val`. Fails with `EAGAIN` if it
+/// already differs.
+pub fn futex_wait(addr: &u32, val: u32) -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_FUTEX, addr as *const u32 as usize, abi::FUTEX_WAIT, val as usize))
+}
+
+/// Wakes up to `count` tasks waiting on the word at `addr`. Returns the
+/// number woken.
+pub fn futex_wake(addr: &u32, count: usize) -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_FUTEX, addr as *const u32 as usize, abi::FUTEX_WAKE, count))
+}
+
+/// Gives up the CPU to another runnable task.
+pub fn yield_now() -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_YIELD, 0, 0, 0))
+}
+
+/// Suspends the calling task for at least `ms` milliseconds.
+pub fn sleep(ms: usize) -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_SLEEP, ms, 0, 0))
+}
+
+/// Returns the process ID of the caller.
+pub fn getpid() -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_GETPID, 0, 0, 0))
+}
+
+/// Duplicates the calling process. Returns the child's PID in the parent and
+/// 0 in the child.
+pub fn fork() -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_FORK, 0, 0, 0))
+}
+
+/// Posts signal `signo` to process `pid`.
+pub fn kill(pid: usize, signo: usize) -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_KILL, pid, signo, 0))
+}
+
+/// Sets the action for signal `signo`. `handler` is `SIG_DFL`, `SIG_IGN` or
+/// a handler address; `restorer` must call `sigreturn`.
+pub fn sigaction(signo: usize, handler: usize, restorer: usize) -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_SIGACTION, signo, handler, restorer))
+}
+
+/// Returns from a signal handler. Only valid inside a handler.
+pub fn sigreturn() -> SyscallResult {
+    abi::decode_result(syscall3(abi::SYS_SIGRETURN, 0, 0, 0))
+}
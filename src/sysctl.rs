@@ -0,0 +1,382 @@
+// nt_rustos/src/sysctl.rs
+
+//! # Runtime Tunables
+//!
+//! A registry of named settings that subsystems expose for changing while
+//! the kernel runs, in the manner of `sysctl`. Names are dot-separated
+//! paths such as `kernel.log_level`; the parts before the last dot group
+//! related tunables into directories, so a name is either a tunable or a
+//! directory, never both.
+//!
+//! A subsystem registers each tunable with its type, default value, an
+//! optional validation callback that can refuse a value, and an optional
+//! callback told of every accepted change, which applies it. `set` checks
+//! the type and asks the validator before storing a value; `get` and
+//! `list` read the current values.
+//!
+//! An argument `sysctl.<name>=<value>` on the kernel command line gives the
+//! tunable its initial value when it is registered; a value that does not
+//! parse or is refused is reported and the default kept. `command` takes
+//! the arguments of a `sysctl` shell command: nothing or a directory lists
+//! tunables, `name` prints one and `name=value` changes it.
+//!
+//! Callbacks run while changes are serialized: they may read tunables but
+//! must not register or set them.
+
+use crate::cmdline;
+use crate::console;
+use crate::init::initcall::InitResult;
+use crate::{println, warn_print};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+/// Longest name in bytes.
+pub const MAX_NAME: usize = 64;
+
+/// Prefix of command line arguments setting tunables.
+const CMDLINE_PREFIX: &str = "sysctl.";
+
+/// The type of a tunable.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Bool,
+    Int,
+    Str,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool => write!(f, "bool"),
+            Self::Int => write!(f, "int"),
+            Self::Str => write!(f, "string"),
+        }
+    }
+}
+
+/// The value of a tunable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::Bool(_) => Kind::Bool,
+            Self::Int(_) => Kind::Int,
+            Self::Str(_) => Kind::Str,
+        }
+    }
+
+    /// Parses `text` as a value of type `kind`. Booleans are `1`, `0`,
+    /// `true`, `false`, `on` or `off`; integers are decimal or `0x`
+    /// hexadecimal, optionally negative.
+    pub fn parse(kind: Kind, text: &str) -> Result<Self, SysctlError> {
+        match kind {
+            Kind::Bool => match text {
+                "1" | "true" | "on" => Ok(Self::Bool(true)),
+                "0" | "false" | "off" => Ok(Self::Bool(false)),
+                _ => Err(SysctlError::Parse(kind)),
+            },
+            Kind::Int => {
+                let (negative, digits) = match text.strip_prefix('-') {
+                    Some(rest) => (true, rest),
+                    None => (false, text),
+                };
+                let magnitude = match digits.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => digits.parse::<i64>(),
+                };
+                let magnitude = magnitude.map_err(|_| SysctlError::Parse(kind))?;
+                Ok(Self::Int(if negative { -magnitude } else { magnitude }))
+            }
+            Kind::Str => Ok(Self::Str(String::from(text))),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Str(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{}", *value as u8),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Errors returned by the tunable registry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SysctlError {
+    /// Names are 1 to `MAX_NAME` bytes of dot-separated, non-empty parts
+    /// made of lowercase letters, digits and underscores.
+    InvalidName,
+    /// A tunable with the name is already registered.
+    AlreadyRegistered,
+    /// The name is a directory of other tunables, or lies below a tunable.
+    Conflict,
+    /// No tunable or directory has the name.
+    NotFound,
+    /// The value has another type than the tunable.
+    WrongType(Kind),
+    /// The text is not a value of the type.
+    Parse(Kind),
+    /// The validation callback refused the value, for the reason given.
+    Rejected(&'static str),
+}
+
+impl fmt::Display for SysctlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName => write!(f, "invalid tunable name"),
+            Self::AlreadyRegistered => write!(f, "tunable already registered"),
+            Self::Conflict => write!(f, "name clashes with a tunable directory"),
+            Self::NotFound => write!(f, "no such tunable"),
+            Self::WrongType(kind) => write!(f, "tunable takes a {}", kind),
+            Self::Parse(kind) => write!(f, "not a valid {}", kind),
+            Self::Rejected(reason) => write!(f, "value refused: {}", reason),
+        }
+    }
+}
+
+/// Checks a proposed value, returning why it is refused.
+pub type Validator = fn(&Value) -> Result<(), &'static str>;
+
+/// Applies an accepted value.
+pub type Notifier = fn(&Value);
+
+/// Describes a tunable to `register`.
+pub struct Tunable {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: Value,
+    pub validate: Option<Validator>,
+    pub changed: Option<Notifier>,
+}
+
+/// A registered tunable as returned by `list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub value: Value,
+    pub default: Value,
+}
+
+struct Registered {
+    tunable: Tunable,
+    value: Value,
+}
+
+/// Registered tunables by name.
+static TUNABLES: Mutex<BTreeMap<&'static str, Registered>> = Mutex::new(BTreeMap::new());
+
+/// Serializes changes, so callbacks see them in order without holding
+/// `TUNABLES`.
+static CHANGES: Mutex<()> = Mutex::new(());
+
+fn check_name(name: &str) -> Result<(), SysctlError> {
+    let valid_part = |part: &str| {
+        !part.is_empty() && part.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+    };
+    if name.len() > MAX_NAME || !name.split('.').all(valid_part) {
+        return Err(SysctlError::InvalidName);
+    }
+    Ok(())
+}
+
+/// Whether `name` is `dir` or lies below it.
+fn is_within(name: &str, dir: &str) -> bool {
+    dir.is_empty() || name == dir || name.strip_prefix(dir).is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Checks `value` against the type and validator of `tunable`.
+fn check_value(tunable: &Tunable, value: &Value) -> Result<(), SysctlError> {
+    if value.kind() != tunable.default.kind() {
+        return Err(SysctlError::WrongType(tunable.default.kind()));
+    }
+    match tunable.validate {
+        Some(validate) => validate(value).map_err(SysctlError::Rejected),
+        None => Ok(()),
+    }
+}
+
+/// Registers a tunable. Its value is the default unless the command line
+/// sets it, in which case the change callback is told of it.
+pub fn register(tunable: Tunable) -> Result<(), SysctlError> {
+    check_name(tunable.name)?;
+    check_value(&tunable, &tunable.default).map_err(|_| SysctlError::Rejected("invalid default"))?;
+    let _changes = CHANGES.lock();
+    let mut value = tunable.default.clone();
+    let mut from_cmdline = false;
+    if let Some(text) = cmdline::value(&format!("{}{}", CMDLINE_PREFIX, tunable.name)) {
+        match Value::parse(tunable.default.kind(), text).and_then(|v| check_value(&tunable, &v).map(|_| v)) {
+            Ok(v) => {
+                value = v;
+                from_cmdline = true;
+            }
+            Err(e) => warn_print!("sysctl: ignoring {}{}={}: {}", CMDLINE_PREFIX, tunable.name, text, e),
+        }
+    }
+    let changed = tunable.changed;
+    {
+        let mut tunables = TUNABLES.lock();
+        if tunables.contains_key(tunable.name) {
+            return Err(SysctlError::AlreadyRegistered);
+        }
+        if tunables.keys().any(|name| is_within(name, tunable.name) || is_within(tunable.name, name)) {
+            return Err(SysctlError::Conflict);
+        }
+        tunables.insert(tunable.name, Registered { tunable, value: value.clone() });
+    }
+    if let (true, Some(changed)) = (from_cmdline, changed) {
+        changed(&value);
+    }
+    Ok(())
+}
+
+/// Removes a tunable, returning its last value.
+pub fn unregister(name: &str) -> Result<Value, SysctlError> {
+    let _changes = CHANGES.lock();
+    TUNABLES.lock().remove(name).map(|entry| entry.value).ok_or(SysctlError::NotFound)
+}
+
+/// Returns the value of a tunable.
+pub fn get(name: &str) -> Result<Value, SysctlError> {
+    TUNABLES.lock().get(name).map(|entry| entry.value.clone()).ok_or(SysctlError::NotFound)
+}
+
+/// Changes a tunable after checking its type and asking its validator,
+/// then tells its change callback.
+pub fn set(name: &str, value: Value) -> Result<(), SysctlError> {
+    let _changes = CHANGES.lock();
+    let (validate, changed, kind) = {
+        let tunables = TUNABLES.lock();
+        let entry = tunables.get(name).ok_or(SysctlError::NotFound)?;
+        (entry.tunable.validate, entry.tunable.changed, entry.tunable.default.kind())
+    };
+    if value.kind() != kind {
+        return Err(SysctlError::WrongType(kind));
+    }
+    if let Some(validate) = validate {
+        validate(&value).map_err(SysctlError::Rejected)?;
+    }
+    // Changes are serialized, so the entry is still there.
+    if let Some(entry) = TUNABLES.lock().get_mut(name) {
+        entry.value = value.clone();
+    }
+    if let Some(changed) = changed {
+        changed(&value);
+    }
+    Ok(())
+}
+
+/// Parses `text` as a value of the tunable's type and sets it.
+pub fn set_str(name: &str, text: &str) -> Result<(), SysctlError> {
+    let kind = TUNABLES.lock().get(name).map(|entry| entry.tunable.default.kind()).ok_or(SysctlError::NotFound)?;
+    set(name, Value::parse(kind, text)?)
+}
+
+/// Restores the default value of a tunable.
+pub fn reset(name: &str) -> Result<(), SysctlError> {
+    let default = TUNABLES.lock().get(name).map(|entry| entry.tunable.default.clone()).ok_or(SysctlError::NotFound)?;
+    set(name, default)
+}
+
+/// Lists the tunables in directory `dir`, or `dir` itself if it is a
+/// tunable, sorted by name; an empty `dir` lists all of them.
+pub fn list(dir: &str) -> Vec<Entry> {
+    TUNABLES
+        .lock()
+        .values()
+        .filter(|entry| is_within(entry.tunable.name, dir))
+        .map(|entry| Entry {
+            name: entry.tunable.name,
+            description: entry.tunable.description,
+            value: entry.value.clone(),
+            default: entry.tunable.default.clone(),
+        })
+        .collect()
+}
+
+/// Runs a `sysctl` command with arguments `args`: prints the tunables
+/// below a directory (all of them if `args` is empty), or sets each
+/// `name=value` argument and prints the new value.
+pub fn command(args: &str) -> Result<(), SysctlError> {
+    let mut args = cmdline::parse(args).peekable();
+    if args.peek().is_none() {
+        return print(&list(""));
+    }
+    for (name, value) in args {
+        if let Some(text) = value {
+            set_str(name, text)?;
+        }
+        print(&list(name))?;
+    }
+    Ok(())
+}
+
+fn print(entries: &[Entry]) -> Result<(), SysctlError> {
+    if entries.is_empty() {
+        return Err(SysctlError::NotFound);
+    }
+    for entry in entries {
+        println!("{} = {}", entry.name, entry.value);
+    }
+    Ok(())
+}
+
+fn check_log_level(value: &Value) -> Result<(), &'static str> {
+    match value.as_int() {
+        Some(level) if (console::LOG_ERROR as i64..=console::LOG_INFO as i64).contains(&level) => Ok(()),
+        _ => Err("log level is 1 (errors) to 3 (info)"),
+    }
+}
+
+fn apply_log_level(value: &Value) {
+    if let Some(level) = value.as_int() {
+        console::set_log_level(level as u8);
+    }
+}
+
+/// Registers the tunables of the kernel core.
+fn register_kernel() -> InitResult {
+    register(Tunable {
+        name: "kernel.log_level",
+        description: "Console messages shown: 1 errors, 2 warnings, 3 information",
+        default: Value::Int(console::LOG_INFO as i64),
+        validate: Some(check_log_level),
+        changed: Some(apply_log_level),
+    })
+    .map_err(|e| format!("failed to register kernel.log_level: {}", e))
+}
+
+crate::initcall!(early, 60, register_kernel);
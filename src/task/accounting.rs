@@ -0,0 +1,105 @@
+// nt_rustos/src/task/accounting.rs
+
+//! # CPU Time Accounting
+//!
+//! Every hart keeps a timestamp of the last point its time was charged.
+//! At each accounting event the time elapsed since then is charged to one
+//! bucket, and the timestamp moves forward:
+//!
+//! * trap entry charges the interrupted code: `user` time if the trap came
+//!   from U-mode, `system` time otherwise;
+//! * trap exit charges the trap itself: `irq` time for interrupts, `system`
+//!   time for exceptions and system calls;
+//! * a scheduling decision charges the outgoing task's kernel code as
+//!   `system` time, or the idle loop as `idle` time.
+//!
+//! Task buckets live in the control block; the system-wide totals below
+//! include idle time, which belongs to no task. Times are in timer ticks.
+
+use super::run_queue::MAX_HARTS;
+use super::task::TaskControlBlock;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// CPU time consumed by one task, in timer ticks.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    /// Time spent executing in U-mode.
+    pub user: u64,
+    /// Time spent in the kernel on the task's behalf.
+    pub system: u64,
+    /// Time spent handling interrupts that arrived while the task ran.
+    pub irq: u64,
+}
+
+impl CpuTimes {
+    pub const fn new() -> Self {
+        Self { user: 0, system: 0, irq: 0 }
+    }
+
+    /// Returns the sum of all buckets.
+    pub fn total(&self) -> u64 {
+        self.user + self.system + self.irq
+    }
+}
+
+/// System-wide CPU time breakdown, in timer ticks.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SystemCpuTimes {
+    pub user: u64,
+    pub system: u64,
+    pub irq: u64,
+    /// Time harts spent idle, waiting for work.
+    pub idle: u64,
+}
+
+/// The bucket an interval is charged to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum Charge {
+    User,
+    System,
+    Irq,
+    Idle,
+}
+
+/// Per-hart tick of the last charge.
+static LAST_CHARGE: [AtomicU64; MAX_HARTS] = [const { AtomicU64::new(0) }; MAX_HARTS];
+
+static TOTAL_USER: AtomicU64 = AtomicU64::new(0);
+static TOTAL_SYSTEM: AtomicU64 = AtomicU64::new(0);
+static TOTAL_IRQ: AtomicU64 = AtomicU64::new(0);
+static TOTAL_IDLE: AtomicU64 = AtomicU64::new(0);
+
+/// Starts accounting on `hart` at `now`.
+pub(super) fn start(hart: usize, now: u64) {
+    LAST_CHARGE[hart].store(now, Ordering::Release);
+}
+
+/// Charges the time `hart` spent since the previous charge to `kind`, on
+/// `task`'s account unless it is idle time.
+pub(super) fn charge(hart: usize, task: &mut TaskControlBlock, kind: Charge, now: u64) {
+    let last = LAST_CHARGE[hart].swap(now, Ordering::AcqRel);
+    let elapsed = now.saturating_sub(last);
+    if elapsed == 0 {
+        return;
+    }
+    let (bucket, total) = match kind {
+        Charge::User => (Some(&mut task.cpu_times.user), &TOTAL_USER),
+        Charge::System => (Some(&mut task.cpu_times.system), &TOTAL_SYSTEM),
+        Charge::Irq => (Some(&mut task.cpu_times.irq), &TOTAL_IRQ),
+        Charge::Idle => (None, &TOTAL_IDLE),
+    };
+    if let Some(bucket) = bucket {
+        *bucket += elapsed;
+    }
+    total.fetch_add(elapsed, Ordering::Relaxed);
+}
+
+/// Returns the system-wide breakdown.
+pub fn system_times() -> SystemCpuTimes {
+    SystemCpuTimes {
+        user: TOTAL_USER.load(Ordering::Relaxed),
+        system: TOTAL_SYSTEM.load(Ordering::Relaxed),
+        irq: TOTAL_IRQ.load(Ordering::Relaxed),
+        idle: TOTAL_IDLE.load(Ordering::Relaxed),
+    }
+}
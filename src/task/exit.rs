@@ -0,0 +1,87 @@
+// nt_rustos/src/task/exit.rs
+
+//! # Task Exit and Resource Cleanup
+//!
+//! Releases everything an exited task owns. Resources are released in a
+//! fixed order, from the ones other code may still reach to the ones only
+//! the task itself used:
+//!
+//! 1. trap handlers registered with the task's PID as context,
+//! 2. the user address space (frames, page tables and ASID), once the last
+//!    thread sharing it is gone,
+//! 3. purpose-tagged kernel allocations,
+//! 4. the kernel stack.
+//!
+//! Cleanup runs on another task's stack, after the exited task has been
+//! switched away from for the last time and outside the scheduler lock.
+//! Debug builds verify afterwards that nothing owned by the task remains.
+
+use super::task::{OwnedAllocation, TaskControlBlock};
+use crate::init::alloc::{self as heap, AllocPurpose};
+use crate::trap;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+/// Releases the resources of an exited task.
+pub(super) fn release_task(mut tcb: Box<TaskControlBlock>) {
+    let pid = tcb.pid;
+
+    // 1. Context-bound trap handlers.
+    let _ = trap::unregister_context_handlers(pid);
+
+    // 2. User address space. Other threads may still share it.
+    let space = tcb.address_space.take().map(|space| Arc::downgrade(&space));
+    tcb.satp = 0;
+
+    // 3. Purpose-tagged allocations.
+    for owned in tcb.allocations.drain(..) {
+        heap::dealloc(owned.ptr as *mut u8);
+    }
+
+    // 4. Kernel stack.
+    tcb.kernel_stack = None;
+
+    #[cfg(debug_assertions)]
+    assert_released(&tcb, space.as_ref().map_or(false, |weak| weak.strong_count() > 0));
+    #[cfg(not(debug_assertions))]
+    let _ = space;
+
+    // 5. The PID, once nothing of the task is left to be found by it.
+    super::free_pid(pid);
+}
+
+/// Debug-build leak check: fails if anything owned by the task survived.
+#[cfg(debug_assertions)]
+fn assert_released(tcb: &TaskControlBlock, space_shared: bool) {
+    let handlers = trap::context_handler_count(tcb.pid);
+    crate::kassert!(handlers == 0, "task {}: {} trap handlers leaked", tcb.pid, handlers);
+    crate::kassert!(tcb.allocations.is_empty(), "task {}: allocations leaked", tcb.pid);
+    crate::kassert!(tcb.kernel_stack.is_none(), "task {}: kernel stack leaked", tcb.pid);
+    if space_shared {
+        crate::debug_print!("task {}: address space still shared by another thread", tcb.pid);
+    }
+}
+
+/// Allocates `size` bytes tagged with `purpose`, owned by `tcb`.
+pub(super) fn alloc_owned(
+    tcb: &mut TaskControlBlock,
+    size: usize,
+    purpose: AllocPurpose,
+) -> Option<*mut u8> {
+    let ptr = heap::alloc(size)?;
+    let _ = heap::set_purpose(ptr, purpose);
+    tcb.allocations.push(OwnedAllocation { ptr: ptr as usize, size, purpose });
+    Some(ptr)
+}
+
+/// Frees an allocation previously made with `alloc_owned`.
+pub(super) fn free_owned(tcb: &mut TaskControlBlock, ptr: *mut u8) -> bool {
+    match tcb.allocations.iter().position(|a| a.ptr == ptr as usize) {
+        Some(index) => {
+            tcb.allocations.swap_remove(index);
+            heap::dealloc(ptr);
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,126 @@
+// nt_rustos/src/task/futex.rs
+
+//! # Futexes
+//!
+//! Blocking on a 32-bit word in memory. `wait` blocks the current task only
+//! while the word still holds the expected value; `wake` wakes tasks blocked
+//! on the word. Lock-free fast paths stay in user space (or in kernel code)
+//! and the kernel is entered only on contention.
+//!
+//! Waiters are keyed by the *physical* address of the word, so tasks sharing
+//! memory through different virtual addresses meet on the same futex. Keys
+//! are hashed into a fixed table of buckets; the value check and the
+//! enqueueing happen under the bucket lock, which `wake` also takes, so a
+//! wake-up issued after the word was changed is never lost.
+
+use super::scheduler;
+use super::task::{Pid, TaskError};
+use crate::mm::{self, USER_SPACE_END, USER_SPACE_START};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+/// Number of hash buckets. Must be a power of two.
+const BUCKET_COUNT: usize = 64;
+
+/// A task blocked on the futex at physical address `key`.
+struct Waiter {
+    key: usize,
+    pid: Pid,
+}
+
+static BUCKETS: [Mutex<Vec<Waiter>>; BUCKET_COUNT] = [const { Mutex::new(Vec::new()) }; BUCKET_COUNT];
+
+fn bucket(key: usize) -> &'static Mutex<Vec<Waiter>> {
+    // Fibonacci hashing of the word index.
+    let hash = (key >> 2).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    &BUCKETS[hash >> (usize::BITS - BUCKET_COUNT.trailing_zeros())]
+}
+
+fn is_user_address(addr: usize) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&addr)
+}
+
+/// Resolves `addr` to the physical address used as the futex key.
+///
+/// User addresses are translated through the current address space; kernel
+/// addresses are identity-mapped.
+fn futex_key(addr: usize) -> Result<usize, TaskError> {
+    if addr % core::mem::size_of::<u32>() != 0 {
+        return Err(TaskError::InvalidArgument);
+    }
+    if !is_user_address(addr) {
+        return Ok(addr);
+    }
+    let space = super::current_address_space().ok_or(TaskError::BadAddress)?;
+    let translated = space.lock().translate(addr);
+    translated.ok_or(TaskError::BadAddress)
+}
+
+/// Reads the futex word at `addr`.
+fn load_word(addr: usize) -> Result<u32, TaskError> {
+    if is_user_address(addr) {
+        let mut bytes = [0u8; 4];
+        mm::copy_from_user(&mut bytes, addr).map_err(|_| TaskError::BadAddress)?;
+        Ok(u32::from_ne_bytes(bytes))
+    } else {
+        Ok(unsafe { (*(addr as *const AtomicU32)).load(Ordering::SeqCst) })
+    }
+}
+
+/// Blocks the current task on the word at `addr` if it still equals
+/// `expected`.
+///
+/// Returns `TaskError::WouldBlock` without blocking if the value differs.
+/// Returning `Ok` does not imply the value changed: callers re-check the
+/// word and wait again if needed. From a trap handler the task is switched
+/// out on trap exit.
+pub fn wait(addr: usize, expected: u32) -> Result<(), TaskError> {
+    if !scheduler::is_initialized() {
+        return Err(TaskError::NotInitialized);
+    }
+    let key = futex_key(addr)?;
+
+    super::without_interrupts(|| {
+        let mut waiters = bucket(key).lock();
+        if load_word(addr)? != expected {
+            return Err(TaskError::WouldBlock);
+        }
+        let pid = scheduler::block_current().ok_or(TaskError::NotInitialized)?;
+        waiters.push(Waiter { key, pid });
+        Ok(())
+    })?;
+    super::reschedule();
+    Ok(())
+}
+
+/// Wakes up to `count` tasks blocked on the word at `addr`, oldest first.
+///
+/// Returns the number of tasks woken.
+pub fn wake(addr: usize, count: usize) -> Result<usize, TaskError> {
+    let key = futex_key(addr)?;
+
+    Ok(super::without_interrupts(|| {
+        let mut waiters = bucket(key).lock();
+        let mut woken = 0;
+        let mut i = 0;
+        while i < waiters.len() && woken < count {
+            if waiters[i].key != key {
+                i += 1;
+                continue;
+            }
+            // Tasks that already left (woken by a signal, or exited) are
+            // dropped without counting.
+            if scheduler::wake(waiters.remove(i).pid) {
+                woken += 1;
+            }
+        }
+        woken
+    }))
+}
+
+/// Returns the number of tasks queued on the word at `addr`.
+pub fn waiter_count(addr: usize) -> Result<usize, TaskError> {
+    let key = futex_key(addr)?;
+    Ok(bucket(key).lock().iter().filter(|w| w.key == key).count())
+}
@@ -0,0 +1,338 @@
+// nt_rustos/src/task/mod.rs
+
+//! # Task Management Subsystem
+//!
+//! Provides kernel threads, user processes, a round-robin scheduler, and the
+//! task-level services (yield, sleep, exit, getpid, fork) used by the system
+//! call layer. Tasks block on `WaitQueue`s or, keyed by memory address, on
+//! futexes.
+//!
+//! Operations that give up the CPU can be called both from thread context and
+//! from trap handlers. In a trap handler the switch is deferred until trap exit,
+//! because the trap system holds its locks while handlers run. Kernel code
+//! can hold off such involuntary switches with `preempt_disable`.
+
+pub mod task;
+pub mod scheduler;
+pub mod signal;
+pub mod futex;
+pub mod wait_queue;
+pub mod timer;
+pub mod accounting;
+mod exit;
+mod preempt;
+mod run_queue;
+mod stack_guard;
+mod switch;
+
+pub use self::task::{
+    OwnedAllocation, Pid, TaskControlBlock, TaskError, TaskState, KERNEL_PID, KERNEL_STACK_SIZE,
+};
+pub use self::accounting::{CpuTimes, SystemCpuTimes};
+pub use self::preempt::{preempt_count, preempt_disable, preempt_enable, preemptible};
+pub use self::run_queue::{HartLoad, ALL_HARTS, MAX_HARTS};
+pub use self::scheduler::{now_ticks, schedule, ticks_per_ms};
+pub use self::signal::{Signal, SignalAction, SignalState};
+pub use self::wait_queue::{wait_any, WaitQueue};
+
+use crate::init::alloc::AllocPurpose;
+use crate::init::initcall::InitResult;
+use crate::loader;
+use crate::mm::AddressSpace;
+use crate::trap::collections::IdAllocator;
+use crate::trap::{self, TrapContext};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::arch::asm;
+use spin::Mutex;
+
+/// `sstatus` bits used when building a user trap frame.
+const SSTATUS_SIE: usize = 1 << 1;
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SPP: usize = 1 << 8;
+const SSTATUS_SUM: usize = 1 << 18;
+
+/// Initializes the task subsystem and adopts the boot flow as `KERNEL_PID`.
+///
+/// Must be called after `trap::init`, since reschedules requested from trap
+/// handlers are carried out by a trap exit hook.
+pub fn init() -> Result<(), trap::TrapApiError> {
+    scheduler::init(Box::new(TaskControlBlock::new_boot()));
+    trap::set_trap_entry_hook(scheduler::on_trap_entry)?;
+    trap::set_trap_exit_hook(scheduler::on_trap_exit)?;
+    timer::init()?;
+    stack_guard::init()?;
+    signal::init()
+}
+
+/// Adopts the boot flow as a kernel task; needs the trap subsystem.
+fn init_tasks() -> InitResult {
+    init().map_err(|e| format!("failed to initialize task subsystem: {}", e))?;
+    crate::info_print!("Task Subsystem initialized (timer via {}).", timer::path());
+    Ok(())
+}
+
+crate::initcall!(subsys, 10, init_tasks);
+
+/// PIDs are below this, as on Linux by default.
+const PID_MAX: Pid = 32768;
+
+/// PIDs in use. `KERNEL_PID` belongs to the boot task and is never handed
+/// out; the PIDs of exited tasks are reused once the rest have been.
+static PIDS: Mutex<IdAllocator> = Mutex::new(IdAllocator::new(KERNEL_PID + 1..PID_MAX));
+
+/// Allocates a new, unique PID.
+fn alloc_pid() -> Result<Pid, TaskError> {
+    // PIDs are freed as tasks are released, which may be on trap exit.
+    let _irq = IrqRestore::disable();
+    PIDS.lock().alloc().ok_or(TaskError::NoFreePid)
+}
+
+/// Frees the PID of a task that was released or never started.
+pub(super) fn free_pid(pid: Pid) {
+    let _irq = IrqRestore::disable();
+    PIDS.lock().free(pid);
+}
+
+/// Creates a kernel thread running `entry` and makes it runnable.
+pub fn spawn_kernel_thread(name: &'static str, entry: fn()) -> Result<Pid, TaskError> {
+    if !scheduler::is_initialized() {
+        return Err(TaskError::NotInitialized);
+    }
+    let pid = alloc_pid()?;
+    let tcb = TaskControlBlock::new_kernel_thread(
+        pid,
+        name,
+        entry,
+        kernel_thread_trampoline as usize,
+    )
+    .inspect_err(|_| free_pid(pid))?;
+    if scheduler::add_task(Box::new(tcb)) {
+        Ok(pid)
+    } else {
+        free_pid(pid);
+        Err(TaskError::NotInitialized)
+    }
+}
+
+/// The first code executed by every kernel thread.
+extern "C" fn kernel_thread_trampoline() -> ! {
+    scheduler::finish_switch();
+    trap::enable_interrupts();
+
+    let entry = scheduler::with_current(|t| t.entry).flatten();
+    if let Some(entry) = entry {
+        entry();
+    }
+
+    exit_current(0);
+    unreachable!("exited kernel thread was scheduled again");
+}
+
+/// Loads `elf` into a fresh address space and starts it as a user process
+/// with `argv`. The process is named after `argv[0]`.
+pub fn spawn_process(elf: &[u8], argv: &[&str]) -> Result<Pid, TaskError> {
+    if !scheduler::is_initialized() {
+        return Err(TaskError::NotInitialized);
+    }
+    let mut space = AddressSpace::new_user()?;
+    let image = loader::load_elf(elf, argv, &mut space).map_err(TaskError::LoadFailed)?;
+
+    let mut frame = TrapContext::new();
+    frame.sstatus = user_sstatus();
+    frame.sepc = image.entry;
+    frame.x[2] = image.stack_pointer;
+    frame.x[10] = image.argc;
+    frame.x[11] = image.argv;
+    frame.x[12] = image.envp;
+
+    let name = String::from(argv.first().copied().unwrap_or("user"));
+    start_user_task(name, Arc::new(Mutex::new(space)), &frame, SignalState::new())
+}
+
+/// Duplicates the calling user process.
+///
+/// The child gets an eager copy of the parent's address space and resumes
+/// from `parent_frame` with `a0` set to 0. Returns the child's PID.
+pub fn fork_current(parent_frame: &TrapContext) -> Result<Pid, TaskError> {
+    let (space, name, signals, affinity) = scheduler::with_current(|t| {
+        (t.address_space.clone(), t.name.clone(), t.signals.inherit(), t.affinity)
+    })
+    .ok_or(TaskError::NotInitialized)?;
+    let space = space.ok_or(TaskError::NotUserTask)?;
+    let child_space = space.lock().try_clone()?;
+
+    let mut frame = *parent_frame;
+    frame.set_return_value(0);
+    let pid = start_user_task(name, Arc::new(Mutex::new(child_space)), &frame, signals)?;
+    if affinity != ALL_HARTS {
+        // The parent's mask already allows an online hart.
+        let _ = scheduler::set_affinity(pid, affinity);
+    }
+    Ok(pid)
+}
+
+fn start_user_task(
+    name: String,
+    space: Arc<Mutex<AddressSpace>>,
+    frame: &TrapContext,
+    signals: SignalState,
+) -> Result<Pid, TaskError> {
+    let pid = alloc_pid()?;
+    let mut tcb = TaskControlBlock::new_user_task(pid, name, space, frame, switch::user_entry())
+        .inspect_err(|_| free_pid(pid))?;
+    tcb.signals = signals;
+    if scheduler::add_task(Box::new(tcb)) {
+        Ok(pid)
+    } else {
+        free_pid(pid);
+        Err(TaskError::NotInitialized)
+    }
+}
+
+/// Returns the `sstatus` value a new user task is entered with: previous
+/// mode U, interrupts enabled after `sret`, kernel access to user pages kept.
+fn user_sstatus() -> usize {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrr {}, sstatus", out(reg) sstatus);
+    }
+    (sstatus & !(SSTATUS_SPP | SSTATUS_SIE)) | SSTATUS_SPIE | SSTATUS_SUM
+}
+
+/// Called by `__user_entry` before a new user task returns to U-mode
+/// through `frame`.
+#[no_mangle]
+extern "C" fn user_task_entry(frame: &mut TrapContext) {
+    scheduler::finish_switch();
+    signal::deliver_pending(frame);
+}
+
+/// Returns the PID of the running task.
+pub fn current_pid() -> Pid {
+    scheduler::with_current(|t| t.pid).unwrap_or(KERNEL_PID)
+}
+
+/// Returns the address space of the running task, or `None` for kernel threads.
+pub fn current_address_space() -> Option<Arc<Mutex<AddressSpace>>> {
+    scheduler::with_current(|t| t.address_space.clone()).flatten()
+}
+
+/// Gives up the CPU to the next runnable task.
+pub fn yield_now() {
+    reschedule();
+}
+
+/// Suspends the current task for at least `ms` milliseconds.
+pub fn sleep_ms(ms: u64) {
+    let wake_at = now_ticks().saturating_add(crate::time::ms_to_ticks(ms));
+    let marked = scheduler::with_current(|t| {
+        t.wake_at = wake_at;
+        t.state = TaskState::Sleeping;
+    });
+    if marked.is_some() {
+        reschedule();
+    }
+}
+
+/// Returns the CPU times charged to task `pid`.
+pub fn cpu_times(pid: Pid) -> Option<CpuTimes> {
+    scheduler::cpu_times(pid)
+}
+
+/// Returns the system-wide CPU time breakdown, including idle time.
+pub fn cpu_stats() -> SystemCpuTimes {
+    accounting::system_times()
+}
+
+/// Restricts task `pid` to the harts whose bits are set in `mask`.
+pub fn set_affinity(pid: Pid, mask: u64) -> Result<(), TaskError> {
+    scheduler::set_affinity(pid, mask)
+}
+
+/// Takes `hart` in or out of scheduling, moving the tasks queued on it to
+/// other harts when it goes out. Returns whether it was in.
+pub fn set_hart_online(hart: usize, online: bool) -> Result<bool, TaskError> {
+    scheduler::set_hart_online(hart, online)
+}
+
+/// Returns the affinity mask of task `pid`.
+pub fn affinity(pid: Pid) -> Option<u64> {
+    scheduler::affinity(pid)
+}
+
+/// Allocates `size` bytes tagged with `purpose` on behalf of the current
+/// task. Whatever the task has not freed is released when it exits.
+pub fn alloc_owned(size: usize, purpose: AllocPurpose) -> Option<*mut u8> {
+    scheduler::with_current(|t| exit::alloc_owned(t, size, purpose)).flatten()
+}
+
+/// Frees an allocation made with `alloc_owned` by the current task.
+pub fn free_owned(ptr: *mut u8) -> bool {
+    scheduler::with_current(|t| exit::free_owned(t, ptr)).unwrap_or(false)
+}
+
+/// Terminates the current task with `code`.
+///
+/// From thread context this never returns. From a trap handler the task is
+/// marked as exited and switched away from on trap exit. Its resources are
+/// released by the next task to run, see `exit`.
+pub fn exit_current(code: i32) {
+    let pid = current_pid();
+    if pid == KERNEL_PID {
+        panic!("the boot task cannot exit (code {})", code);
+    }
+    scheduler::with_current(|t| {
+        t.exit_code = Some(code);
+        t.state = TaskState::Zombie;
+    });
+    reschedule();
+}
+
+/// Disables interrupts on this hart until dropped, then restores the
+/// previous state.
+///
+/// Works on `sstatus.SIE` directly rather than through `trap`, whose
+/// interrupt control takes the trap system lock and so cannot be used from
+/// trap handlers.
+pub(super) struct IrqRestore {
+    was_enabled: bool,
+}
+
+impl IrqRestore {
+    pub(super) fn disable() -> Self {
+        let sstatus: usize;
+        unsafe {
+            asm!("csrrci {}, sstatus, 1 << 1", out(reg) sstatus);
+        }
+        Self { was_enabled: sstatus & SSTATUS_SIE != 0 }
+    }
+}
+
+impl Drop for IrqRestore {
+    fn drop(&mut self) {
+        if self.was_enabled {
+            unsafe {
+                asm!("csrsi sstatus, 1 << 1");
+            }
+        }
+    }
+}
+
+/// Runs `f` with interrupts disabled, so no task switch can happen while it
+/// holds a lock that other tasks take.
+pub(super) fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let _irq = IrqRestore::disable();
+    f()
+}
+
+/// Switches tasks now, or on trap exit when running inside a trap handler.
+fn reschedule() {
+    if trap::in_trap_context() {
+        scheduler::request_resched();
+    } else {
+        schedule();
+    }
+}
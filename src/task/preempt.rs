@@ -0,0 +1,50 @@
+// nt_rustos/src/task/preempt.rs
+
+//! # Preemption Control
+//!
+//! `preempt_disable` and `preempt_enable` bracket short critical sections
+//! that must not be switched out by the timer-driven scheduler but can still
+//! take interrupts. Each hart keeps a nesting count; while it is non-zero,
+//! a reschedule requested from a trap handler stays pending instead of
+//! being carried out on trap exit, and the outermost `preempt_enable`
+//! performs it.
+//!
+//! The count only suppresses involuntary switches. Code that blocks or
+//! yields with preemption disabled still switches, taking the count of the
+//! hart with it, and is a bug.
+
+use super::scheduler::{self, current_hart};
+use super::run_queue::MAX_HARTS;
+use crate::trap;
+use core::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
+
+static PREEMPT_COUNT: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Disables preemption on the current hart. Calls nest.
+pub fn preempt_disable() {
+    PREEMPT_COUNT[current_hart()].fetch_add(1, Ordering::Relaxed);
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Re-enables preemption disabled by a matching `preempt_disable`.
+///
+/// Leaving the outermost section switches tasks if a reschedule became due
+/// meanwhile, unless called from a trap handler, where trap exit does it.
+pub fn preempt_enable() {
+    compiler_fence(Ordering::SeqCst);
+    let previous = PREEMPT_COUNT[current_hart()].fetch_sub(1, Ordering::Relaxed);
+    debug_assert!(previous > 0, "preempt_enable without preempt_disable");
+    if previous == 1 && scheduler::resched_pending() && !trap::in_trap_context() {
+        scheduler::schedule();
+    }
+}
+
+/// Returns the preemption nesting count of the current hart.
+pub fn preempt_count() -> usize {
+    PREEMPT_COUNT[current_hart()].load(Ordering::Relaxed)
+}
+
+/// Returns `true` if the current hart may be preempted.
+pub fn preemptible() -> bool {
+    preempt_count() == 0
+}
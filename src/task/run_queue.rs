@@ -0,0 +1,101 @@
+// nt_rustos/src/task/run_queue.rs
+
+//! # Per-Hart Run Queues
+//!
+//! Each hart has its own FIFO of ready tasks and tracks how busy it has
+//! been recently. The scheduler places woken tasks on the least loaded hart
+//! their affinity allows, and periodically (or when a hart runs dry) moves
+//! queued tasks from the busiest hart to the least loaded one.
+//!
+//! A hart's load combines its queue length with its recent utilization, so
+//! between two harts with equally long queues the one that has been idle
+//! more is preferred.
+
+use super::task::Pid;
+use alloc::collections::VecDeque;
+
+/// Maximum number of harts the scheduler keeps queues for.
+pub const MAX_HARTS: usize = 8;
+
+/// Affinity mask allowing every hart.
+pub const ALL_HARTS: u64 = u64::MAX;
+
+/// Length of a utilization window, in milliseconds.
+pub const BALANCE_INTERVAL_MS: u64 = 10;
+
+/// Load units contributed by one queued task; utilization adds 0..=100.
+const LOAD_PER_TASK: u32 = 100;
+
+/// Scheduling state of one hart.
+pub(super) struct HartQueue {
+    /// Tasks ready to run on this hart, in FIFO order.
+    pub ready: VecDeque<Pid>,
+    /// The task executing on this hart.
+    pub current: Pid,
+    /// Whether the hart takes part in scheduling.
+    pub online: bool,
+    /// Start of the current utilization window.
+    window_start: u64,
+    /// Ticks spent idle in the current window.
+    idle_ticks: u64,
+    /// Smoothed busy percentage (0..=100) of past windows.
+    utilization: u32,
+}
+
+impl HartQueue {
+    pub fn new(current: Pid, online: bool, now: u64) -> Self {
+        Self {
+            ready: VecDeque::new(),
+            current,
+            online,
+            window_start: now,
+            idle_ticks: 0,
+            utilization: 0,
+        }
+    }
+
+    /// Accounts `ticks` of idle time to the current window.
+    pub fn add_idle(&mut self, ticks: u64) {
+        self.idle_ticks = self.idle_ticks.saturating_add(ticks);
+    }
+
+    /// Closes the utilization window if it is over, folding it into the
+    /// smoothed value (half old, half new).
+    pub fn update_utilization(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed < crate::time::ms_to_ticks(BALANCE_INTERVAL_MS) {
+            return;
+        }
+        let idle = self.idle_ticks.min(elapsed);
+        let busy = ((elapsed - idle) * 100 / elapsed) as u32;
+        self.utilization = (self.utilization + busy) / 2;
+        self.window_start = now;
+        self.idle_ticks = 0;
+    }
+
+    /// Returns the smoothed busy percentage.
+    pub fn utilization(&self) -> u32 {
+        self.utilization
+    }
+
+    /// Returns the load used for balancing decisions.
+    pub fn load(&self) -> u32 {
+        self.ready.len() as u32 * LOAD_PER_TASK + self.utilization
+    }
+}
+
+/// Load snapshot of one hart, as reported by `scheduler::hart_loads`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HartLoad {
+    pub hart: usize,
+    pub online: bool,
+    /// Number of tasks waiting in the hart's run queue.
+    pub queued: usize,
+    /// Smoothed busy percentage (0..=100).
+    pub utilization: u32,
+}
+
+/// Returns `true` if `mask` allows hart `hart`.
+pub fn allows(mask: u64, hart: usize) -> bool {
+    hart < 64 && mask & (1 << hart) != 0
+}
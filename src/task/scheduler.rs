@@ -0,0 +1,599 @@
+// nt_rustos/src/task/scheduler.rs
+
+//! # Round-Robin Scheduler
+//!
+//! A round-robin scheduler with one run queue per hart, balanced as
+//! described in `run_queue`. Tasks are switched
+//! cooperatively through `schedule()`; traps request a reschedule that is
+//! carried out on trap exit, after the trap system's locks have been released.
+//! Switching to a task also installs its address space. Each decision
+//! programs the timer for the next sleeper deadline; see `timer`.
+
+use super::run_queue::{self, HartLoad, HartQueue, BALANCE_INTERVAL_MS, MAX_HARTS};
+use super::task::{Pid, TaskControlBlock, TaskError, TaskState};
+use super::accounting::{self, Charge};
+use super::{exit, preempt, signal, stack_guard, switch, timer};
+use crate::mm;
+use crate::pm;
+use crate::time;
+use crate::trap::{self, TaskContext};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, MutexGuard};
+
+// The scheduler works in raw timer ticks; the clock lives in `time`.
+pub use crate::time::{now_ticks, ticks_per_ms};
+
+/// `sstatus.SPP`: the trap was taken from S-mode.
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// The global scheduler instance.
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+/// Holds the scheduler lock with interrupts disabled on this hart, so an
+/// interrupt whose exit path reschedules can never find the lock taken by
+/// the code it interrupted. Interrupts are restored after the lock is
+/// released.
+struct SchedulerGuard {
+    guard: MutexGuard<'static, Option<Scheduler>>,
+    _irq: super::IrqRestore,
+}
+
+impl core::ops::Deref for SchedulerGuard {
+    type Target = Option<Scheduler>;
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl core::ops::DerefMut for SchedulerGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// Takes the scheduler lock; see `SchedulerGuard`.
+fn lock() -> SchedulerGuard {
+    let irq = super::IrqRestore::disable();
+    SchedulerGuard { guard: SCHEDULER.lock(), _irq: irq }
+}
+
+/// Set when the current task should be switched out at the next opportunity.
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+/// The outcome of a scheduling decision.
+enum Pick {
+    /// Keep running the current task.
+    Stay,
+    /// Switch from the first context to the second, under the given `satp`.
+    Switch(*mut TaskContext, *const TaskContext, usize),
+    /// Nothing is runnable; wait for a sleeper to become ready.
+    Idle,
+}
+
+pub struct Scheduler {
+    /// All live tasks, boxed so their contexts never move.
+    tasks: BTreeMap<Pid, Box<TaskControlBlock>>,
+    /// Run queue and current task of each hart.
+    harts: Vec<HartQueue>,
+    /// Tick of the last periodic balancing pass.
+    last_balance: u64,
+}
+
+/// Returns the hart executing the caller.
+///
+/// Secondary harts do not run tasks yet, so this is always the boot hart.
+pub fn current_hart() -> usize {
+    boot_hart()
+}
+
+/// The hart that runs the boot flow; hart 0 if its ID is too large for a
+/// run queue.
+fn boot_hart() -> usize {
+    crate::boot::boot_info()
+        .map(|info| info.hart_id)
+        .filter(|&hart| hart < MAX_HARTS)
+        .unwrap_or(0)
+}
+
+impl Scheduler {
+    fn new(boot: Box<TaskControlBlock>) -> Self {
+        let current = boot.pid;
+        let now = now_ticks();
+        let mut tasks = BTreeMap::new();
+        tasks.insert(current, boot);
+        let harts = (0..MAX_HARTS)
+            .map(|hart| HartQueue::new(current, hart == boot_hart(), now))
+            .collect();
+        Self {
+            tasks,
+            harts,
+            last_balance: now,
+        }
+    }
+
+    fn add(&mut self, mut tcb: Box<TaskControlBlock>) {
+        let pid = tcb.pid;
+        tcb.state = TaskState::Ready;
+        self.tasks.insert(pid, tcb);
+        self.enqueue(pid);
+    }
+
+    fn current(&self) -> Pid {
+        self.harts[current_hart()].current
+    }
+
+    fn current_mut(&mut self) -> &mut TaskControlBlock {
+        let current = self.current();
+        self.tasks
+            .get_mut(&current)
+            .expect("current task missing from task table")
+    }
+
+    /// Picks the hart a task allowed on `mask` should be queued on: the
+    /// least loaded online hart, preferring `last` on ties.
+    fn select_hart(&self, mask: u64, last: usize) -> usize {
+        let mut best: Option<(usize, u32)> = None;
+        for (hart, queue) in self.harts.iter().enumerate() {
+            if !queue.online || !run_queue::allows(mask, hart) {
+                continue;
+            }
+            let load = queue.load();
+            let better = match best {
+                None => true,
+                Some((_, best_load)) => load < best_load || (load == best_load && hart == last),
+            };
+            if better {
+                best = Some((hart, load));
+            }
+        }
+        // An affinity mask without online harts falls back to the boot hart.
+        best.map_or(boot_hart(), |(hart, _)| hart)
+    }
+
+    /// Puts a ready task on the run queue of the hart it fits best.
+    fn enqueue(&mut self, pid: Pid) {
+        let (mask, last) = match self.tasks.get(&pid) {
+            Some(t) => (t.affinity, t.last_hart),
+            None => return,
+        };
+        let hart = self.select_hart(mask, last);
+        self.harts[hart].ready.push_back(pid);
+        if let Some(t) = self.tasks.get_mut(&pid) {
+            t.last_hart = hart;
+        }
+    }
+
+    /// Removes every run queue entry of `pid`.
+    fn dequeue(&mut self, pid: Pid) {
+        for queue in self.harts.iter_mut() {
+            queue.ready.retain(|p| *p != pid);
+        }
+    }
+
+    /// Moves sleepers whose deadline has passed back to the run queue.
+    fn wake_expired(&mut self, now: u64) {
+        let expired: Vec<Pid> = self
+            .tasks
+            .iter()
+            .filter(|(_, t)| t.state == TaskState::Sleeping && t.wake_at <= now)
+            .map(|(pid, _)| *pid)
+            .collect();
+        for pid in expired {
+            if let Some(t) = self.tasks.get_mut(&pid) {
+                t.state = TaskState::Ready;
+            }
+            self.enqueue(pid);
+        }
+    }
+
+    /// Moves one queued task from the busiest online hart to `target`.
+    /// Returns `false` if no task could move.
+    fn pull_one(&mut self, target: usize) -> bool {
+        let source = self
+            .harts
+            .iter()
+            .enumerate()
+            .filter(|(hart, q)| *hart != target && q.online && !q.ready.is_empty())
+            .max_by_key(|(_, q)| q.load())
+            .map(|(hart, _)| hart);
+        match source {
+            Some(source) => self.migrate(source, target),
+            None => false,
+        }
+    }
+
+    /// Moves one queued task from `source` to `target`. Returns `false` if
+    /// no queued task of `source` may run on `target`.
+    fn migrate(&mut self, source: usize, target: usize) -> bool {
+        // Take the most recently queued task allowed on the target; it has
+        // the least chance of still being cache-warm on the source.
+        let tasks = &self.tasks;
+        let index = self.harts[source].ready.iter().rposition(|pid| {
+            tasks.get(pid).map_or(false, |t| run_queue::allows(t.affinity, target))
+        });
+        match index.and_then(|i| self.harts[source].ready.remove(i)) {
+            Some(pid) => {
+                self.harts[target].ready.push_back(pid);
+                if let Some(t) = self.tasks.get_mut(&pid) {
+                    t.last_hart = target;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Periodic balancing: evens out queue lengths between the busiest and
+    /// the least loaded online harts.
+    fn balance(&mut self, now: u64) {
+        if now.saturating_sub(self.last_balance) < time::ms_to_ticks(BALANCE_INTERVAL_MS) {
+            return;
+        }
+        self.last_balance = now;
+        for queue in self.harts.iter_mut() {
+            queue.update_utilization(now);
+        }
+        // Each move narrows the gap, so this terminates; the bound only
+        // keeps a single pass short.
+        for _ in 0..self.tasks.len() {
+            let online = self.harts.iter().enumerate().filter(|(_, q)| q.online);
+            let idlest = online.clone().min_by_key(|(_, q)| q.load()).map(|(h, _)| h);
+            let busiest = online.max_by_key(|(_, q)| q.ready.len()).map(|(h, _)| h);
+            let (idlest, busiest) = match (idlest, busiest) {
+                (Some(i), Some(b)) if i != b => (i, b),
+                _ => return,
+            };
+            if self.harts[busiest].ready.len() <= self.harts[idlest].ready.len() + 1 {
+                return;
+            }
+            if !self.migrate(busiest, idlest) {
+                return;
+            }
+        }
+    }
+
+    /// Returns the earliest wake-up deadline among sleeping tasks.
+    fn next_wakeup(&self) -> Option<u64> {
+        self.tasks
+            .values()
+            .filter(|t| t.state == TaskState::Sleeping)
+            .map(|t| t.wake_at)
+            .min()
+    }
+
+    /// Returns `true` if some task waits to be woken by another task or an
+    /// interrupt rather than by a deadline.
+    fn has_blocked(&self) -> bool {
+        self.tasks.values().any(|t| t.state == TaskState::Blocked)
+    }
+
+    /// Removes zombies not running on any hart, whose stacks may still be
+    /// in use, and hands them back for cleanup.
+    fn take_zombies(&mut self) -> Vec<Box<TaskControlBlock>> {
+        let harts = &self.harts;
+        let zombies: Vec<Pid> = self
+            .tasks
+            .iter()
+            .filter(|(pid, t)| t.state == TaskState::Zombie && !harts.iter().any(|q| q.current == **pid))
+            .map(|(pid, _)| *pid)
+            .collect();
+        zombies.iter().filter_map(|pid| self.tasks.remove(pid)).collect()
+    }
+
+    fn pick_next(&mut self) -> Pick {
+        let hart = current_hart();
+        let current = self.current();
+        if self.harts[hart].ready.is_empty() {
+            // Pull work from a busier hart before giving up the CPU.
+            self.pull_one(hart);
+        }
+        if self.current_mut().state == TaskState::Running {
+            if self.harts[hart].ready.is_empty() {
+                return Pick::Stay;
+            }
+            self.current_mut().state = TaskState::Ready;
+            self.enqueue(current);
+        }
+
+        let next = loop {
+            match self.harts[hart].ready.pop_front() {
+                // Skip stale entries of tasks that exited while queued.
+                Some(pid) => match self.tasks.get(&pid) {
+                    Some(t) if t.state == TaskState::Ready => break pid,
+                    _ => continue,
+                },
+                None => return Pick::Idle,
+            }
+        };
+
+        self.harts[hart].current = next;
+        self.current_mut().state = TaskState::Running;
+        self.current_mut().last_hart = hart;
+        if next == current {
+            return Pick::Stay;
+        }
+
+        stack_guard::check_canary(&self.tasks[&current]);
+        let cur_ctx = &mut self.tasks.get_mut(&current).unwrap().context as *mut TaskContext;
+        let next_task = self.tasks.get(&next).unwrap();
+        stack_guard::switch_in(next_task);
+        let next_ctx = &next_task.context as *const TaskContext;
+        Pick::Switch(cur_ctx, next_ctx, next_task.satp())
+    }
+}
+
+/// Installs the boot flow as the first task.
+pub(super) fn init(boot: Box<TaskControlBlock>) {
+    accounting::start(current_hart(), now_ticks());
+    *lock() = Some(Scheduler::new(boot));
+}
+
+/// Returns `true` once `init` has been called.
+pub fn is_initialized() -> bool {
+    lock().is_some()
+}
+
+/// Adds a new task to the run queue.
+pub(super) fn add_task(tcb: Box<TaskControlBlock>) -> bool {
+    match lock().as_mut() {
+        Some(s) => {
+            s.add(tcb);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Runs `f` with the current task's control block.
+pub(super) fn with_current<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut TaskControlBlock) -> R,
+{
+    lock().as_mut().map(|s| f(s.current_mut()))
+}
+
+/// Runs `f` with the control block of task `pid`.
+pub(super) fn with_task<F, R>(pid: Pid, f: F) -> Option<R>
+where
+    F: FnOnce(&mut TaskControlBlock) -> R,
+{
+    lock().as_mut().and_then(|s| s.tasks.get_mut(&pid).map(|t| f(t)))
+}
+
+/// Makes a sleeping or blocked task runnable. Returns `false` if the task
+/// was not waiting.
+pub(super) fn wake(pid: Pid) -> bool {
+    if let Some(s) = lock().as_mut() {
+        if let Some(t) = s.tasks.get_mut(&pid) {
+            if matches!(t.state, TaskState::Sleeping | TaskState::Blocked) {
+                t.state = TaskState::Ready;
+                s.enqueue(pid);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Marks the current task as blocked. It stays off the run queue until
+/// `wake` is called for it; the caller still has to reschedule.
+pub(super) fn block_current() -> Option<Pid> {
+    lock().as_mut().map(|s| {
+        s.current_mut().state = TaskState::Blocked;
+        s.current()
+    })
+}
+
+/// Undoes `block_current` for a task that found its condition satisfied
+/// before switching away. Handles a wake-up that already arrived.
+pub(super) fn cancel_block() {
+    if let Some(s) = lock().as_mut() {
+        let pid = s.current();
+        let state = s.current_mut().state;
+        if state == TaskState::Ready {
+            s.dequeue(pid);
+        }
+        if matches!(state, TaskState::Blocked | TaskState::Ready) {
+            s.current_mut().state = TaskState::Running;
+        }
+    }
+}
+
+/// Returns the earliest deadline among sleeping tasks, in ticks.
+pub fn next_wakeup() -> Option<u64> {
+    lock().as_ref().and_then(|s| s.next_wakeup())
+}
+
+/// Restricts task `pid` to the harts in `mask`.
+///
+/// Fails with `InvalidArgument` if `mask` allows no online hart. A queued
+/// task is moved to an allowed hart right away; a running one on its next
+/// switch.
+pub(super) fn set_affinity(pid: Pid, mask: u64) -> Result<(), TaskError> {
+    let mut guard = lock();
+    let s = guard.as_mut().ok_or(TaskError::NotInitialized)?;
+    let usable = s.harts.iter().enumerate().any(|(hart, q)| q.online && run_queue::allows(mask, hart));
+    if !usable {
+        return Err(TaskError::InvalidArgument);
+    }
+    let t = s.tasks.get_mut(&pid).ok_or(TaskError::NoSuchTask)?;
+    t.affinity = mask;
+    let requeue = t.state == TaskState::Ready && !run_queue::allows(mask, t.last_hart);
+    if requeue {
+        s.dequeue(pid);
+        s.enqueue(pid);
+    }
+    Ok(())
+}
+
+/// Takes `hart` in or out of scheduling and returns whether it was in.
+///
+/// A hart taken out gets no more tasks, and the tasks queued on it move to
+/// the other online harts. The calling hart cannot be taken out.
+pub(super) fn set_hart_online(hart: usize, online: bool) -> Result<bool, TaskError> {
+    let mut guard = lock();
+    let s = guard.as_mut().ok_or(TaskError::NotInitialized)?;
+    if hart >= MAX_HARTS || (!online && hart == current_hart()) {
+        return Err(TaskError::InvalidArgument);
+    }
+    let was_online = core::mem::replace(&mut s.harts[hart].online, online);
+    if !online {
+        let queued: Vec<Pid> = s.harts[hart].ready.drain(..).collect();
+        for pid in queued {
+            s.enqueue(pid);
+        }
+    }
+    Ok(was_online)
+}
+
+/// Returns the affinity mask of task `pid`.
+pub(super) fn affinity(pid: Pid) -> Option<u64> {
+    lock().as_ref().and_then(|s| s.tasks.get(&pid).map(|t| t.affinity))
+}
+
+/// Returns a load snapshot of every hart.
+pub fn hart_loads() -> Vec<HartLoad> {
+    match lock().as_ref() {
+        Some(s) => s
+            .harts
+            .iter()
+            .enumerate()
+            .map(|(hart, q)| HartLoad {
+                hart,
+                online: q.online,
+                queued: q.ready.len(),
+                utilization: q.utilization(),
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the CPU times charged to task `pid`.
+pub(super) fn cpu_times(pid: Pid) -> Option<accounting::CpuTimes> {
+    lock().as_ref().and_then(|s| s.tasks.get(&pid).map(|t| t.cpu_times))
+}
+
+/// Returns the number of live tasks.
+pub fn task_count() -> usize {
+    lock().as_ref().map_or(0, |s| s.tasks.len())
+}
+
+/// Asks for the current task to be switched out on the next trap exit.
+pub fn request_resched() {
+    NEED_RESCHED.store(true, Ordering::Release);
+}
+
+/// Returns `true` if a reschedule has been requested but not carried out.
+pub fn resched_pending() -> bool {
+    NEED_RESCHED.load(Ordering::Acquire)
+}
+
+/// Trap entry hook: charges the interrupted code to the current task.
+pub(super) fn on_trap_entry(context: &trap::TrapContext) {
+    let kind = if context.sstatus & SSTATUS_SPP == 0 { Charge::User } else { Charge::System };
+    if let Some(s) = lock().as_mut() {
+        accounting::charge(current_hart(), s.current_mut(), kind, now_ticks());
+    }
+}
+
+/// Trap exit hook: charges the trap to the current task, performs a deferred
+/// reschedule outside the trap system's locks, then delivers signals pending
+/// for the task about to resume.
+pub(super) fn on_trap_exit(context: &mut trap::TrapContext) {
+    let kind = if context.cause().is_interrupt() { Charge::Irq } else { Charge::System };
+    if let Some(s) = lock().as_mut() {
+        accounting::charge(current_hart(), s.current_mut(), kind, now_ticks());
+    }
+    // With preemption disabled the request stays pending for
+    // `preempt_enable`.
+    if preempt::preemptible() && NEED_RESCHED.swap(false, Ordering::AcqRel) {
+        schedule();
+    }
+    signal::deliver_pending(context);
+}
+
+/// Picks the next runnable task and switches to it.
+///
+/// Returns when the calling task is scheduled again. If the calling task is
+/// no longer runnable and nothing else is ready, the hart idles (see `pm`)
+/// until the earliest sleeper's deadline or an interrupt.
+pub fn schedule() {
+    let was_enabled = trap::disable_interrupts();
+    NEED_RESCHED.store(false, Ordering::Release);
+    let mut idle_since: Option<u64> = None;
+
+    loop {
+        let mut guard = lock();
+        let sched = match guard.as_mut() {
+            Some(s) => s,
+            None => break,
+        };
+        let now = now_ticks();
+        let hart = current_hart();
+        let kind = match idle_since.take() {
+            Some(since) => {
+                sched.harts[hart].add_idle(now.saturating_sub(since));
+                Charge::Idle
+            }
+            None => Charge::System,
+        };
+        accounting::charge(hart, sched.current_mut(), kind, now);
+        sched.wake_expired(now);
+        sched.balance(now);
+
+        let pick = sched.pick_next();
+        let deadline = sched.next_wakeup();
+        match pick {
+            Pick::Stay => {
+                drop(guard);
+                timer::program(deadline);
+                break;
+            }
+            Pick::Switch(cur, next, satp) => {
+                drop(guard);
+                timer::program(deadline);
+                if mm::is_initialized() {
+                    mm::activate(satp);
+                }
+                unsafe { switch::switch_to(cur, next) };
+                break;
+            }
+            Pick::Idle => {
+                let blocked = sched.has_blocked();
+                drop(guard);
+                if deadline.is_none() && !blocked {
+                    panic!("scheduler: no runnable task and no pending wake-up");
+                }
+                // Sleep until the next deadline, or until an interrupt
+                // handler wakes a blocked task.
+                idle_since = Some(now);
+                timer::program(deadline);
+                pm::idle(deadline);
+                // `wfi` returns on a pending interrupt without taking it
+                // while interrupts are masked; let its handler run before
+                // looking at the queues again.
+                trap::enable_interrupts();
+                trap::disable_interrupts();
+            }
+        }
+    }
+
+    finish_switch();
+    trap::restore_interrupts(was_enabled);
+}
+
+/// Housekeeping performed by a task right after it has been switched to.
+pub(super) fn finish_switch() {
+    let zombies = match lock().as_mut() {
+        Some(s) => s.take_zombies(),
+        None => return,
+    };
+    // Released outside the scheduler lock: cleanup takes the trap system lock.
+    for tcb in zombies {
+        exit::release_task(tcb);
+    }
+}
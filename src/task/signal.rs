@@ -0,0 +1,309 @@
+// nt_rustos/src/task/signal.rs
+
+//! # Signals
+//!
+//! A minimal asynchronous event mechanism. The kernel, or another task, posts
+//! a signal to a task; the signal stays pending until the task next returns
+//! to user mode, where it is delivered according to the task's action for
+//! it: the default action (terminate or ignore), ignore, or a call into a
+//! user handler.
+//!
+//! A user handler is entered with the signal number in `a0` and `ra` set to
+//! the restorer registered alongside it, which must issue `sigreturn`. The
+//! interrupted trap frame is kept in the kernel until then; handlers do not
+//! nest.
+//!
+//! Faults taken in user mode are turned into signals here, so a bad user
+//! program is terminated instead of bringing down the kernel.
+
+use super::scheduler;
+use super::task::{Pid, TaskError};
+use crate::trap::{
+    self, Exception, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+
+/// Number of signal slots; valid signal numbers are `1..NSIG`.
+pub const NSIG: usize = 32;
+
+/// Exit code offset for tasks terminated by a signal (`128 + signo`).
+pub const SIGNAL_EXIT_BASE: i32 = 128;
+
+/// `sstatus.SPP`: the trap was taken from S-mode.
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// Priority of the user fault handler, after fixups and default handlers.
+const USER_FAULT_HANDLER_PRIORITY: u8 = 200;
+
+/// Signals understood by the kernel. Numbers follow Linux.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Signal {
+    Hup = 1,
+    Int = 2,
+    Ill = 4,
+    Trap = 5,
+    Bus = 7,
+    Kill = 9,
+    Usr1 = 10,
+    Segv = 11,
+    Usr2 = 12,
+    Alrm = 14,
+    Term = 15,
+    Chld = 17,
+}
+
+/// What happens to a signal whose action is `SignalAction::Default`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+}
+
+impl Signal {
+    /// Converts a raw signal number into a `Signal`.
+    pub fn from_number(number: usize) -> Option<Self> {
+        match number {
+            1 => Some(Signal::Hup),
+            2 => Some(Signal::Int),
+            4 => Some(Signal::Ill),
+            5 => Some(Signal::Trap),
+            7 => Some(Signal::Bus),
+            9 => Some(Signal::Kill),
+            10 => Some(Signal::Usr1),
+            11 => Some(Signal::Segv),
+            12 => Some(Signal::Usr2),
+            14 => Some(Signal::Alrm),
+            15 => Some(Signal::Term),
+            17 => Some(Signal::Chld),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw signal number.
+    pub const fn number(&self) -> usize {
+        *self as usize
+    }
+
+    /// Returns the action taken when no handler is installed.
+    pub fn default_action(&self) -> DefaultAction {
+        match self {
+            Signal::Chld => DefaultAction::Ignore,
+            _ => DefaultAction::Terminate,
+        }
+    }
+
+    /// Returns `true` if the action for this signal cannot be changed.
+    pub fn is_fixed(&self) -> bool {
+        *self == Signal::Kill
+    }
+}
+
+/// The disposition of a signal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Apply the signal's `DefaultAction`.
+    Default,
+    /// Discard the signal.
+    Ignore,
+    /// Call a user handler; `restorer` is placed in `ra`.
+    Handler { entry: usize, restorer: usize },
+}
+
+/// Per-task signal bookkeeping.
+#[derive(Debug, Clone)]
+pub struct SignalState {
+    /// Bit `n` is set while signal `n` is pending.
+    pending: u32,
+    actions: [SignalAction; NSIG],
+    /// The frame interrupted by the running user handler, if any.
+    saved_frame: Option<TrapContext>,
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            actions: [SignalAction::Default; NSIG],
+            saved_frame: None,
+        }
+    }
+
+    /// Returns the state a forked child starts with: the parent's actions,
+    /// with nothing pending.
+    pub fn inherit(&self) -> Self {
+        Self {
+            pending: 0,
+            actions: self.actions,
+            saved_frame: None,
+        }
+    }
+
+    /// Marks `signal` as pending.
+    pub fn post(&mut self, signal: Signal) {
+        self.pending |= 1 << signal.number();
+    }
+
+    /// Returns `true` if any signal is pending.
+    pub fn has_pending(&self) -> bool {
+        self.pending != 0
+    }
+
+    /// Returns the action for `signal`.
+    pub fn action(&self, signal: Signal) -> SignalAction {
+        self.actions[signal.number()]
+    }
+
+    /// Sets the action for `signal`, returning the previous one.
+    pub fn set_action(&mut self, signal: Signal, action: SignalAction) -> Result<SignalAction, TaskError> {
+        if signal.is_fixed() {
+            return Err(TaskError::InvalidArgument);
+        }
+        Ok(core::mem::replace(&mut self.actions[signal.number()], action))
+    }
+
+    /// Removes and returns the lowest pending signal that can be delivered
+    /// now. While a handler runs, signals that would need another handler
+    /// stay pending.
+    fn take_next(&mut self) -> Option<(Signal, SignalAction)> {
+        for number in 1..NSIG {
+            if self.pending & (1 << number) == 0 {
+                continue;
+            }
+            let signal = match Signal::from_number(number) {
+                Some(signal) => signal,
+                None => {
+                    self.pending &= !(1 << number);
+                    continue;
+                }
+            };
+            let action = self.actions[number];
+            if self.saved_frame.is_some() && matches!(action, SignalAction::Handler { .. }) {
+                continue;
+            }
+            self.pending &= !(1 << number);
+            return Some((signal, action));
+        }
+        None
+    }
+}
+
+/// Registers the handler turning user-mode faults into signals.
+pub(super) fn init() -> Result<(), trap::TrapApiError> {
+    const FAULTS: [TrapType; 10] = [
+        TrapType::InstructionPageFault,
+        TrapType::LoadPageFault,
+        TrapType::StorePageFault,
+        TrapType::InstructionAccessFault,
+        TrapType::LoadAccessFault,
+        TrapType::StoreAccessFault,
+        TrapType::IllegalInstruction,
+        TrapType::Breakpoint,
+        TrapType::LoadMisaligned,
+        TrapType::StoreMisaligned,
+    ];
+    for trap_type in FAULTS {
+        trap::register_trap_handler(
+            trap_type,
+            user_fault_handler,
+            USER_FAULT_HANDLER_PRIORITY,
+            "User Fault Signal",
+            ProtectionLevel::Kernel,
+            KERNEL_REGISTRAR_ID,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn fault_signal(code: usize) -> Signal {
+    match code {
+        c if c == Exception::IllegalInstruction as usize => Signal::Ill,
+        c if c == Exception::Breakpoint as usize => Signal::Trap,
+        c if c == Exception::LoadMisaligned as usize || c == Exception::StoreMisaligned as usize => Signal::Bus,
+        _ => Signal::Segv,
+    }
+}
+
+fn user_fault_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.sstatus & SSTATUS_SPP != 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let signal = fault_signal(context.cause().code());
+    let posted = scheduler::with_current(|t| {
+        if t.is_user() {
+            t.signals.post(signal);
+            true
+        } else {
+            false
+        }
+    });
+    if posted == Some(true) {
+        TrapHandlerResult::Handled
+    } else {
+        TrapHandlerResult::Pass
+    }
+}
+
+/// Posts `signal` to task `pid`, waking it if it sleeps.
+pub fn post(pid: Pid, signal: Signal) -> Result<(), TaskError> {
+    let posted = scheduler::with_task(pid, |t| {
+        if t.is_user() {
+            t.signals.post(signal);
+            Ok(())
+        } else {
+            Err(TaskError::NotUserTask)
+        }
+    })
+    .ok_or(TaskError::NoSuchTask)?;
+    posted?;
+    scheduler::wake(pid);
+    Ok(())
+}
+
+/// Sets the current task's action for `signal`.
+pub fn set_action(signal: Signal, action: SignalAction) -> Result<SignalAction, TaskError> {
+    scheduler::with_current(|t| t.signals.set_action(signal, action))
+        .ok_or(TaskError::NotInitialized)?
+}
+
+/// Delivers pending signals to the current task, which is about to return
+/// to user mode through `frame`.
+pub(super) fn deliver_pending(frame: &mut TrapContext) {
+    if frame.sstatus & SSTATUS_SPP != 0 {
+        return;
+    }
+    loop {
+        let next = scheduler::with_current(|t| t.signals.take_next()).flatten();
+        let (signal, action) = match next {
+            Some(next) => next,
+            None => return,
+        };
+        match action {
+            SignalAction::Ignore => continue,
+            SignalAction::Default => match signal.default_action() {
+                DefaultAction::Ignore => continue,
+                DefaultAction::Terminate => {
+                    super::exit_current(SIGNAL_EXIT_BASE + signal.number() as i32);
+                    return;
+                }
+            },
+            SignalAction::Handler { entry, restorer } => {
+                let saved = *frame;
+                scheduler::with_current(|t| t.signals.saved_frame = Some(saved));
+                frame.sepc = entry;
+                frame.x[1] = restorer;
+                frame.set_return_value(signal.number());
+                return;
+            }
+        }
+    }
+}
+
+/// Restores the frame interrupted by the current signal handler into `frame`.
+pub fn sigreturn(frame: &mut TrapContext) -> Result<(), TaskError> {
+    let saved = scheduler::with_current(|t| t.signals.saved_frame.take())
+        .flatten()
+        .ok_or(TaskError::InvalidArgument)?;
+    *frame = saved;
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// nt_rustos/src/task/stack_guard.rs
+
+//! # Kernel Stack Overflow Detection
+//!
+//! Two mechanisms catch a kernel thread running off the bottom of its
+//! stack:
+//!
+//! * the canary words at the bottom of every `KernelStack` are verified
+//!   whenever the task is switched out;
+//! * the unmapped guard page below a mapped stack makes the overflowing
+//!   access fault. The trap entry code notices the stack pointer is below
+//!   the current stack's limit and handles the trap on a separate overflow
+//!   stack, where the handler below reports it.
+//!
+//! Either way the kernel panics with the name of the offending thread
+//! rather than with whatever corruption the overflow would have caused.
+
+use super::task::{Pid, TaskControlBlock};
+use crate::mm::PAGE_SIZE;
+use crate::trap::{
+    self, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Priority of the guard page handler: right after the uaccess fixups.
+const GUARD_HANDLER_PRIORITY: u8 = 1;
+
+/// `sstatus.SPP`: the trap was taken from S-mode.
+const SSTATUS_SPP: usize = 1 << 8;
+
+/// Register index of `sp` in `TrapContext::x`.
+const REG_SP: usize = 2;
+
+/// Bytes of the thread name kept for the diagnostic.
+const NAME_LEN: usize = 32;
+
+/// The stack of the task running on this hart.
+static CURRENT_PID: AtomicU64 = AtomicU64::new(0);
+static STACK_BOTTOM: AtomicUsize = AtomicUsize::new(0);
+static GUARD_PAGE: AtomicUsize = AtomicUsize::new(0);
+/// Copied at switch time: the fault handler must not allocate or take the
+/// scheduler lock.
+static CURRENT_NAME: Mutex<([u8; NAME_LEN], usize)> = Mutex::new(([0; NAME_LEN], 0));
+
+/// Registers the guard page fault handler.
+pub(super) fn init() -> Result<(), trap::TrapApiError> {
+    for trap_type in [TrapType::LoadPageFault, TrapType::StorePageFault] {
+        trap::register_trap_handler(
+            trap_type,
+            guard_fault_handler,
+            GUARD_HANDLER_PRIORITY,
+            "Kernel Stack Guard",
+            ProtectionLevel::Kernel,
+            KERNEL_REGISTRAR_ID,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Records `task` as the one about to run and arms the trap entry check
+/// for its stack. Called with interrupts disabled.
+pub(super) fn switch_in(task: &TaskControlBlock) {
+    let stack = task.kernel_stack.as_ref();
+    let bottom = stack.map_or(0, |s| s.bottom());
+    CURRENT_PID.store(task.pid, Ordering::Relaxed);
+    STACK_BOTTOM.store(bottom, Ordering::Relaxed);
+    GUARD_PAGE.store(stack.and_then(|s| s.guard_page()).unwrap_or(0), Ordering::Relaxed);
+    if let Some(mut current) = CURRENT_NAME.try_lock() {
+        let len = task.name.len().min(NAME_LEN);
+        current.0[..len].copy_from_slice(&task.name.as_bytes()[..len]);
+        current.1 = len;
+    }
+    trap::set_kernel_stack_bottom(bottom);
+}
+
+/// Panics if `task` has overwritten the canary of its kernel stack.
+pub(super) fn check_canary(task: &TaskControlBlock) {
+    if task.kernel_stack.as_ref().is_some_and(|s| !s.canary_intact()) {
+        panic!("stack overflow in thread {} (pid {}): canary overwritten", task.name, task.pid);
+    }
+}
+
+fn overflow_panic(pid: Pid, addr: usize) -> ! {
+    match CURRENT_NAME.try_lock() {
+        Some(current) => {
+            let name = core::str::from_utf8(&current.0[..current.1]).unwrap_or("?");
+            panic!("stack overflow in thread {} (pid {}) at {:#x}", name, pid, addr);
+        }
+        None => panic!("stack overflow in thread pid {} at {:#x}", pid, addr),
+    }
+}
+
+fn guard_fault_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.sstatus & SSTATUS_SPP == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let bottom = STACK_BOTTOM.load(Ordering::Relaxed);
+    if bottom == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    let guard = GUARD_PAGE.load(Ordering::Relaxed);
+    let in_guard = guard != 0 && (guard..guard + PAGE_SIZE).contains(&context.stval);
+    if in_guard || context.x[REG_SP] < bottom {
+        overflow_panic(CURRENT_PID.load(Ordering::Relaxed), context.stval);
+    }
+    TrapHandlerResult::Pass
+}
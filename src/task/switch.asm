@@ -0,0 +1,58 @@
+# nt_rustos/src/task/switch.asm
+# 任务上下文切换汇编代码
+# 保存当前任务的 ra/sp/s0-s11，并恢复下一个任务的对应寄存器
+# 布局必须与 trap::ds::context::TaskContext 保持一致
+
+.section .text
+.globl __switch
+.align 4
+
+# __switch(current: *mut TaskContext, next: *const TaskContext)
+#   a0: 当前任务上下文指针
+#   a1: 下一个任务上下文指针
+__switch:
+    # 保存当前任务的被调用者保存寄存器
+    sd ra, 0(a0)
+    sd sp, 8(a0)
+    sd s0, 16(a0)
+    sd s1, 24(a0)
+    sd s2, 32(a0)
+    sd s3, 40(a0)
+    sd s4, 48(a0)
+    sd s5, 56(a0)
+    sd s6, 64(a0)
+    sd s7, 72(a0)
+    sd s8, 80(a0)
+    sd s9, 88(a0)
+    sd s10, 96(a0)
+    sd s11, 104(a0)
+
+    # 恢复下一个任务的寄存器
+    ld ra, 0(a1)
+    ld sp, 8(a1)
+    ld s0, 16(a1)
+    ld s1, 24(a1)
+    ld s2, 32(a1)
+    ld s3, 40(a1)
+    ld s4, 48(a1)
+    ld s5, 56(a1)
+    ld s6, 64(a1)
+    ld s7, 72(a1)
+    ld s8, 80(a1)
+    ld s9, 88(a1)
+    ld s10, 96(a1)
+    ld s11, 104(a1)
+
+    # 跳转到下一个任务的 ra
+    ret
+
+.globl __user_entry
+.align 4
+
+# 新用户任务首次被调度时的入口
+#   sp: 指向内核栈上预先构造好的 TrapContext
+__user_entry:
+    mv a0, sp
+    call user_task_entry
+    # 经由陷入返回路径进入用户态
+    tail __trap_return
@@ -0,0 +1,36 @@
+// nt_rustos/src/task/switch.rs
+
+//! # Context Switch Primitive
+//!
+//! Wraps the `__switch` assembly routine that swaps callee-saved register
+//! state between two `TaskContext`s, and the entry stub of user tasks.
+
+use crate::trap::TaskContext;
+use core::arch::global_asm;
+
+global_asm!(include_str!("switch.asm"));
+
+extern "C" {
+    /// Saves the callee-saved registers into `current` and restores them from `next`.
+    fn __switch(current: *mut TaskContext, next: *const TaskContext);
+    /// First code run by a user task: finishes the switch and `sret`s through
+    /// the `TrapContext` that `sp` points to.
+    fn __user_entry();
+}
+
+/// Returns the address user tasks start at when first scheduled.
+pub fn user_entry() -> usize {
+    __user_entry as usize
+}
+
+/// Switches execution from the task owning `current` to the task owning `next`.
+///
+/// Returns when some other task switches back to `current`.
+///
+/// # Safety
+/// Both pointers must reference live `TaskContext`s that stay at a stable
+/// address until the switch back, and `next` must describe a valid stack and
+/// return address.
+pub unsafe fn switch_to(current: *mut TaskContext, next: *const TaskContext) {
+    __switch(current, next);
+}
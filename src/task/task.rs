@@ -0,0 +1,314 @@
+// nt_rustos/src/task/task.rs
+
+//! # Task Control Block
+//!
+//! Defines the per-task bookkeeping structure used by the scheduler.
+
+use super::accounting::CpuTimes;
+use super::run_queue::ALL_HARTS;
+use super::signal::SignalState;
+use crate::init::alloc::AllocPurpose;
+use crate::loader::ElfError;
+use crate::mm::{self, AddressSpace, MmError};
+use crate::trap::{TaskContext, TrapContext};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// A process / task identifier.
+pub type Pid = u64;
+
+/// The PID of the boot flow, which becomes the kernel's first task.
+pub const KERNEL_PID: Pid = 0;
+
+/// Size of the kernel stack allocated for each spawned task (16KB).
+pub const KERNEL_STACK_SIZE: usize = 4096 * 4;
+
+/// The scheduling state of a task.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    /// Waiting in the run queue.
+    Ready,
+    /// Currently executing on a hart.
+    Running,
+    /// Waiting for a deadline to pass.
+    Sleeping,
+    /// Waiting on a wait queue or futex until explicitly woken.
+    Blocked,
+    /// Finished; waiting to be reaped.
+    Zombie,
+}
+
+/// Errors reported by task management operations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskError {
+    /// `task::init` has not been called yet.
+    NotInitialized,
+    /// The kernel stack or control block could not be allocated.
+    OutOfMemory,
+    /// No task with the given PID exists.
+    NoSuchTask,
+    /// The executable image could not be loaded.
+    LoadFailed(ElfError),
+    /// The operation requires a task with a user address space.
+    NotUserTask,
+    /// An argument was out of range or not allowed in the current state.
+    InvalidArgument,
+    /// The operation would block and blocking was not requested.
+    WouldBlock,
+    /// A memory address could not be accessed.
+    BadAddress,
+    /// Every PID is in use.
+    NoFreePid,
+}
+
+impl From<MmError> for TaskError {
+    fn from(_: MmError) -> Self {
+        TaskError::OutOfMemory
+    }
+}
+
+/// Value filling the canary words at the bottom of every kernel stack.
+const STACK_CANARY: u64 = 0x57AC_C0DE_CA4A_12E5;
+/// Number of canary words at the bottom of a kernel stack.
+const STACK_CANARY_WORDS: usize = 8;
+
+/// The memory behind a kernel stack.
+enum StackMemory {
+    /// Mapped in the kernel stack area, with a guard page below it.
+    Guarded(mm::GuardedStack),
+    /// Taken from the heap, used before paging is enabled.
+    Heap(Vec<u8>),
+}
+
+/// A kernel stack.
+///
+/// The lowest words of the stack hold a canary that the scheduler checks
+/// whenever the task is switched out, which catches overflows that skipped
+/// the guard page, or stacks that have none.
+pub struct KernelStack {
+    memory: StackMemory,
+}
+
+impl KernelStack {
+    /// Allocates a new kernel stack of `size` bytes.
+    pub fn new(size: usize) -> Result<Self, TaskError> {
+        let memory = if mm::is_initialized() {
+            StackMemory::Guarded(mm::GuardedStack::alloc(size)?)
+        } else {
+            let mut memory = Vec::new();
+            memory
+                .try_reserve_exact(size)
+                .map_err(|_| TaskError::OutOfMemory)?;
+            memory.resize(size, 0);
+            StackMemory::Heap(memory)
+        };
+        let stack = Self { memory };
+        for word in stack.canary_words() {
+            unsafe { word.write_volatile(STACK_CANARY) };
+        }
+        Ok(stack)
+    }
+
+    /// Returns the lowest address of the stack.
+    pub fn bottom(&self) -> usize {
+        match &self.memory {
+            StackMemory::Guarded(stack) => stack.bottom(),
+            StackMemory::Heap(memory) => memory.as_ptr() as usize,
+        }
+    }
+
+    /// Returns the 16-byte aligned initial stack pointer.
+    pub fn top(&self) -> usize {
+        (self.bottom() + self.size()) & !0xF
+    }
+
+    /// Returns the size of the stack in bytes.
+    pub fn size(&self) -> usize {
+        match &self.memory {
+            StackMemory::Guarded(stack) => stack.size(),
+            StackMemory::Heap(memory) => memory.len(),
+        }
+    }
+
+    /// Returns the address of the unmapped page below the stack, if it has
+    /// one.
+    pub fn guard_page(&self) -> Option<usize> {
+        match &self.memory {
+            StackMemory::Guarded(stack) => Some(stack.guard_page()),
+            StackMemory::Heap(_) => None,
+        }
+    }
+
+    fn canary_words(&self) -> impl Iterator<Item = *mut u64> {
+        let base = (self.bottom() + 7) & !7;
+        (0..STACK_CANARY_WORDS).map(move |i| (base as *mut u64).wrapping_add(i))
+    }
+
+    /// Returns `false` if the stack has overwritten its canary.
+    pub fn canary_intact(&self) -> bool {
+        self.canary_words().all(|word| unsafe { word.read_volatile() } == STACK_CANARY)
+    }
+}
+
+/// # Task Control Block
+///
+/// Holds everything the scheduler needs to suspend and resume a task.
+/// Control blocks are boxed by the scheduler so that the embedded
+/// `TaskContext` keeps a stable address across context switches.
+pub struct TaskControlBlock {
+    /// The task's identifier.
+    pub pid: Pid,
+    /// A human-readable name for diagnostics.
+    pub name: String,
+    /// The current scheduling state.
+    pub state: TaskState,
+    /// Saved callee-saved registers while the task is switched out.
+    pub context: TaskContext,
+    /// The kernel stack owned by the task. The boot task runs on the boot stack.
+    pub kernel_stack: Option<KernelStack>,
+    /// The function a kernel thread starts in.
+    pub entry: Option<fn()>,
+    /// Wake-up deadline (in timer ticks) while `Sleeping`.
+    pub wake_at: u64,
+    /// The exit code once the task has become a `Zombie`.
+    pub exit_code: Option<i32>,
+    /// The user address space, or `None` for kernel threads.
+    pub address_space: Option<Arc<Mutex<AddressSpace>>>,
+    /// Cached `satp` of `address_space` (0 selects the kernel address space).
+    pub satp: usize,
+    /// Purpose-tagged kernel allocations owned by the task.
+    pub allocations: Vec<OwnedAllocation>,
+    /// Pending signals and their actions.
+    pub signals: SignalState,
+    /// Bit `n` allows the task to run on hart `n`.
+    pub affinity: u64,
+    /// The hart whose run queue the task was last placed on.
+    pub last_hart: usize,
+    /// CPU time charged to the task so far.
+    pub cpu_times: CpuTimes,
+}
+
+/// A kernel heap block owned by a task and freed when it exits.
+#[derive(Debug, Copy, Clone)]
+pub struct OwnedAllocation {
+    pub ptr: usize,
+    pub size: usize,
+    pub purpose: AllocPurpose,
+}
+
+impl TaskControlBlock {
+    /// Creates the control block describing the already running boot flow.
+    pub fn new_boot() -> Self {
+        Self {
+            pid: KERNEL_PID,
+            name: String::from("kernel"),
+            state: TaskState::Running,
+            context: TaskContext::new(),
+            kernel_stack: None,
+            entry: None,
+            wake_at: 0,
+            exit_code: None,
+            address_space: None,
+            satp: 0,
+            allocations: Vec::new(),
+            signals: SignalState::new(),
+            affinity: ALL_HARTS,
+            last_hart: 0,
+            cpu_times: CpuTimes::new(),
+        }
+    }
+
+    /// Creates a kernel thread that will start executing at `trampoline`
+    /// on a freshly allocated stack and then call `entry`.
+    pub fn new_kernel_thread(
+        pid: Pid,
+        name: &'static str,
+        entry: fn(),
+        trampoline: usize,
+    ) -> Result<Self, TaskError> {
+        let stack = KernelStack::new(KERNEL_STACK_SIZE)?;
+        let context = TaskContext::new_for_task(trampoline, stack.top());
+        Ok(Self {
+            pid,
+            name: String::from(name),
+            state: TaskState::Ready,
+            context,
+            kernel_stack: Some(stack),
+            entry: Some(entry),
+            wake_at: 0,
+            exit_code: None,
+            address_space: None,
+            satp: 0,
+            allocations: Vec::new(),
+            signals: SignalState::new(),
+            affinity: ALL_HARTS,
+            last_hart: 0,
+            cpu_times: CpuTimes::new(),
+        })
+    }
+
+    /// Creates a user task that enters user mode through `frame` the first
+    /// time it is scheduled. `user_entry` is the assembly stub that returns
+    /// through the trap frame.
+    pub fn new_user_task(
+        pid: Pid,
+        name: String,
+        space: Arc<Mutex<AddressSpace>>,
+        frame: &TrapContext,
+        user_entry: usize,
+    ) -> Result<Self, TaskError> {
+        let stack = KernelStack::new(KERNEL_STACK_SIZE)?;
+        // The trap frame sits at the top of the kernel stack, exactly where
+        // `__trap_entry` puts it for traps from user mode.
+        let frame_addr = stack.top() - core::mem::size_of::<TrapContext>();
+        unsafe {
+            (frame_addr as *mut TrapContext).write(*frame);
+        }
+        let mut tcb = Self {
+            pid,
+            name,
+            state: TaskState::Ready,
+            context: TaskContext::new_for_task(user_entry, frame_addr),
+            kernel_stack: Some(stack),
+            entry: None,
+            wake_at: 0,
+            exit_code: None,
+            address_space: None,
+            satp: 0,
+            allocations: Vec::new(),
+            signals: SignalState::new(),
+            affinity: ALL_HARTS,
+            last_hart: 0,
+            cpu_times: CpuTimes::new(),
+        };
+        tcb.attach_address_space(space);
+        Ok(tcb)
+    }
+
+    /// Returns `true` if the task runs in its own user address space.
+    pub fn is_user(&self) -> bool {
+        self.address_space.is_some()
+    }
+
+    /// Attaches a user address space, activated whenever the task runs.
+    pub fn attach_address_space(&mut self, space: Arc<Mutex<AddressSpace>>) {
+        self.satp = space.lock().satp();
+        self.address_space = Some(space);
+    }
+
+    /// Returns the `satp` value to install when switching to this task.
+    pub fn satp(&self) -> usize {
+        if self.satp != 0 {
+            self.satp
+        } else {
+            mm::kernel_satp()
+        }
+    }
+
+    /// Returns `true` if the task can be picked by the scheduler.
+    pub fn is_runnable(&self) -> bool {
+        matches!(self.state, TaskState::Ready | TaskState::Running)
+    }
+}
@@ -0,0 +1,195 @@
+// nt_rustos/src/task/timer.rs
+
+//! # Tickless Timer
+//!
+//! There is no periodic tick. The timer is programmed to the next
+//! scheduler event, the earliest sleeper deadline, every time the scheduler
+//! makes a decision, and disarmed when no deadline is pending. An idle hart
+//! waits in an idle state (see `pm`) until that deadline or another
+//! interrupt arrives.
+//!
+//! The interrupt only disarms the timer and requests a reschedule; expired
+//! sleepers are moved to the run queue by the scheduler itself. The last
+//! programmed deadline can be read back with `armed_deadline`, which keeps
+//! timer behavior observable in tests.
+//!
+//! A sampling period can be set on top of that, for the profiler: the timer
+//! then also fires at every period, and such interrupts reschedule only if
+//! the scheduler deadline has passed as well.
+//!
+//! On harts with the Sstc extension the deadline is written to `stimecmp`
+//! directly; otherwise every reprogramming is an SBI call, which traps to
+//! the firmware. `stats` tells which path is in use and how often the
+//! hardware was reprogrammed.
+
+use super::scheduler;
+use crate::platform;
+use crate::trap::{
+    self, Interrupt, ProtectionLevel, TrapContext, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+};
+use crate::util::sbi::timer as sbi_timer;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Deadline value meaning "disarmed".
+const DISARMED: u64 = u64::MAX;
+
+/// `sie.STIE`: supervisor timer interrupt enable.
+const SIE_STIE: usize = 1 << 5;
+
+/// Priority of the timer handler.
+const TIMER_HANDLER_PRIORITY: u8 = 50;
+
+/// The scheduler deadline.
+static ARMED_DEADLINE: AtomicU64 = AtomicU64::new(DISARMED);
+/// The sampling period in ticks, or 0 when not sampling.
+static SAMPLE_PERIOD: AtomicU64 = AtomicU64::new(0);
+/// The next sampling tick.
+static NEXT_SAMPLE: AtomicU64 = AtomicU64::new(DISARMED);
+/// The value the hardware timer is currently programmed for.
+static HARDWARE_DEADLINE: AtomicU64 = AtomicU64::new(DISARMED);
+/// Whether the timer is programmed through `stimecmp`.
+static USE_SSTC: AtomicBool = AtomicBool::new(false);
+static INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static REPROGRAMS: AtomicU64 = AtomicU64::new(0);
+
+/// How the timer is programmed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimerPath {
+    /// Writes to the `stimecmp` CSR (Sstc extension).
+    Sstc,
+    /// `sbi::timer::set_timer` calls.
+    Sbi,
+}
+
+impl fmt::Display for TimerPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sstc => "stimecmp (Sstc)",
+            Self::Sbi => "SBI set_timer",
+        })
+    }
+}
+
+/// Timer counters since boot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimerStats {
+    pub path: TimerPath,
+    /// Timer interrupts handled.
+    pub interrupts: u64,
+    /// Writes of a new deadline to the hardware.
+    pub reprograms: u64,
+}
+
+/// Picks the timer path, registers the timer interrupt handler and enables
+/// timer interrupts.
+pub(super) fn init() -> Result<(), trap::TrapApiError> {
+    USE_SSTC.store(platform::get().sstc, Ordering::Release);
+    trap::register_trap_handler(
+        TrapType::TimerInterrupt,
+        timer_interrupt_handler,
+        TIMER_HANDLER_PRIORITY,
+        "Scheduler Timer",
+        ProtectionLevel::Kernel,
+        KERNEL_REGISTRAR_ID,
+        None,
+    )?;
+    program(None);
+    unsafe {
+        asm!("csrs sie, {}", in(reg) SIE_STIE);
+    }
+    Ok(())
+}
+
+fn timer_interrupt_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    let cause = context.cause();
+    if !cause.is_interrupt() || cause.code() != Interrupt::SupervisorTimer as usize {
+        return TrapHandlerResult::Pass;
+    }
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    let now = scheduler::now_ticks();
+    let period = SAMPLE_PERIOD.load(Ordering::Acquire);
+    if period != 0 && NEXT_SAMPLE.load(Ordering::Acquire) <= now {
+        NEXT_SAMPLE.store(now + period, Ordering::Release);
+    }
+    if ARMED_DEADLINE.load(Ordering::Acquire) <= now {
+        ARMED_DEADLINE.store(DISARMED, Ordering::Release);
+        scheduler::request_resched();
+    }
+    rearm();
+    TrapHandlerResult::Handled
+}
+
+/// Programs the hardware timer for the earlier of the scheduler deadline
+/// and the next sampling tick.
+fn rearm() {
+    let target = ARMED_DEADLINE
+        .load(Ordering::Acquire)
+        .min(NEXT_SAMPLE.load(Ordering::Acquire));
+    if HARDWARE_DEADLINE.swap(target, Ordering::AcqRel) != target {
+        REPROGRAMS.fetch_add(1, Ordering::Relaxed);
+        if USE_SSTC.load(Ordering::Acquire) {
+            // `stimecmp` is CSR 0x14d; older assemblers lack the name.
+            unsafe { asm!("csrw 0x14d, {}", in(reg) target) };
+        } else {
+            let _ = sbi_timer::set_timer(target);
+        }
+    }
+}
+
+/// Programs the timer to fire at `deadline` (in ticks), or disarms it.
+///
+/// Reprogramming also clears a pending timer interrupt, as long as the new
+/// deadline lies in the future.
+pub fn program(deadline: Option<u64>) {
+    ARMED_DEADLINE.store(deadline.unwrap_or(DISARMED), Ordering::Release);
+    rearm();
+}
+
+/// Makes the timer also fire every `period` ticks, or stops doing so.
+pub fn set_sample_period(period: Option<u64>) {
+    match period.filter(|p| *p != 0) {
+        Some(period) => {
+            SAMPLE_PERIOD.store(period, Ordering::Release);
+            NEXT_SAMPLE.store(scheduler::now_ticks() + period, Ordering::Release);
+        }
+        None => {
+            SAMPLE_PERIOD.store(0, Ordering::Release);
+            NEXT_SAMPLE.store(DISARMED, Ordering::Release);
+        }
+    }
+    rearm();
+}
+
+/// Returns the deadline the timer is armed for, or `None` if disarmed.
+pub fn armed_deadline() -> Option<u64> {
+    match ARMED_DEADLINE.load(Ordering::Acquire) {
+        DISARMED => None,
+        deadline => Some(deadline),
+    }
+}
+
+/// Returns how the timer is programmed.
+pub fn path() -> TimerPath {
+    if USE_SSTC.load(Ordering::Acquire) {
+        TimerPath::Sstc
+    } else {
+        TimerPath::Sbi
+    }
+}
+
+/// Returns the timer counters.
+pub fn stats() -> TimerStats {
+    TimerStats {
+        path: path(),
+        interrupts: INTERRUPTS.load(Ordering::Relaxed),
+        reprograms: REPROGRAMS.load(Ordering::Relaxed),
+    }
+}
+
+/// Prints the timer counters.
+pub fn print_stats() {
+    let stats = stats();
+    crate::println!("Timer: {}, {} interrupts, {} reprograms", stats.path, stats.interrupts, stats.reprograms);
+}
@@ -0,0 +1,163 @@
+// nt_rustos/src/task/wait_queue.rs
+
+//! # Wait Queues
+//!
+//! A FIFO of tasks blocked until some condition changes. Waiters register
+//! themselves and block under the queue lock, and wakers take the same lock
+//! after changing the condition, so a wake-up can never fall between a
+//! waiter's condition check and its blocking.
+
+use super::scheduler;
+use super::task::Pid;
+use crate::trap;
+use alloc::collections::VecDeque;
+use spin::Mutex;
+
+/// A queue of blocked tasks.
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<Pid>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { waiters: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Blocks the current task until `condition` returns `true`.
+    ///
+    /// Must be called from thread context: inside a trap handler the switch
+    /// is deferred, so the condition could not be re-checked after waking.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut condition: F) {
+        debug_assert!(!trap::in_trap_context(), "WaitQueue::wait_until called from a trap handler");
+        loop {
+            let blocked = super::without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if condition() {
+                    return false;
+                }
+                match scheduler::block_current() {
+                    Some(pid) => {
+                        waiters.push_back(pid);
+                        true
+                    }
+                    None => false,
+                }
+            });
+            if !blocked {
+                return;
+            }
+            super::schedule();
+        }
+    }
+
+    /// Queues the current task and blocks it once, without re-checking any
+    /// condition. In a trap handler the task is switched out on trap exit.
+    pub fn wait_once(&self) {
+        let blocked = super::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            scheduler::block_current().map(|pid| waiters.push_back(pid)).is_some()
+        });
+        if blocked {
+            super::reschedule();
+        }
+    }
+
+    /// Wakes the longest waiting task. Returns `false` if none was waiting.
+    pub fn wake_one(&self) -> bool {
+        super::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            while let Some(pid) = waiters.pop_front() {
+                // Entries of tasks that were woken by other means are skipped.
+                if scheduler::wake(pid) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    /// Wakes every waiting task and returns how many were woken.
+    pub fn wake_all(&self) -> usize {
+        super::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            let mut woken = 0;
+            while let Some(pid) = waiters.pop_front() {
+                if scheduler::wake(pid) {
+                    woken += 1;
+                }
+            }
+            woken
+        })
+    }
+
+    /// Removes every entry of `pid` from the queue.
+    fn remove(&self, pid: Pid) {
+        self.waiters.lock().retain(|p| *p != pid);
+    }
+
+    /// Returns the number of queued waiters.
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len()
+    }
+
+    /// Returns `true` if nobody is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+/// Blocks the current task until `ready(i)` returns `true` for one of
+/// `queues`, and returns the index of the first such queue.
+///
+/// `ready(i)` is checked under the lock of `queues[i]`, so wakers must change
+/// the condition before waking that queue. The task is queued on every
+/// queue while blocked and removed from all of them before returning, so it
+/// never absorbs a wake-up meant for another waiter. Returns `None` if
+/// `queues` is empty. Must be called from thread context.
+pub fn wait_any<F: FnMut(usize) -> bool>(queues: &[&WaitQueue], mut ready: F) -> Option<usize> {
+    debug_assert!(!trap::in_trap_context(), "wait_any called from a trap handler");
+    if queues.is_empty() {
+        return None;
+    }
+    loop {
+        let found = super::without_interrupts(|| {
+            let pid = scheduler::block_current()?;
+            for (i, queue) in queues.iter().enumerate() {
+                let mut waiters = queue.waiters.lock();
+                if ready(i) {
+                    drop(waiters);
+                    scheduler::cancel_block();
+                    return Some((pid, Some(i)));
+                }
+                waiters.push_back(pid);
+            }
+            Some((pid, None))
+        });
+        let (pid, index) = match found {
+            Some(found) => found,
+            // No scheduler yet: nothing could wake us, so only poll.
+            None => match (0..queues.len()).find(|&i| ready(i)) {
+                Some(i) => return Some(i),
+                None => {
+                    core::hint::spin_loop();
+                    continue;
+                }
+            },
+        };
+        if index.is_none() {
+            super::schedule();
+        }
+        for queue in queues {
+            queue.remove(pid);
+        }
+        if index.is_some() {
+            return index;
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,9 +1,13 @@
 // 生产级早期内存分配器功能测试模块
 
-use super::{TestCase, TestResult, TestRunner};
+use super::{parse_number, test_rng, test_seed, TestResult, TestSuite};
 use crate::{init::alloc, println, debug_print, warn_print};
 use crate::{alloc_with_purpose, alloc_zeroed_with_purpose};
-use crate::{Vec, String};
+use crate::{format, Vec, String};
+use crate::cmdline;
+use crate::time;
+use core::alloc::Layout;
+use spin::Mutex;
 
 /// 测试单次分配与释放
 fn test_single_alloc_dealloc() -> TestResult {
@@ -192,8 +196,15 @@ fn test_purpose_allocation() -> TestResult {
             }
         }
         
-        if found_purposes < purposes.len() {
-            println!("  FAIL: Not all purposes found in handover info");
+        // 每个块在接管信息中都带着分配时设置的用途
+        let recorded = allocated.iter().zip(purposes.iter()).all(|((ptr, _), (purpose, _))| {
+            handover.allocated_blocks[..handover.allocated_count]
+                .iter()
+                .any(|block| block.addr == *ptr as usize && block.purpose == *purpose)
+        });
+        
+        if found_purposes < purposes.len() || !recorded {
+            println!("  FAIL: Not all purposes found in handover info (per block: {})", recorded);
             // 清理
             for (p, s) in allocated {
                 alloc::dealloc_safe(p, s).ok();
@@ -443,6 +454,24 @@ fn test_leak_detection() -> TestResult {
     TestResult::Pass
 }
 
+/// 测试块年龄按单调时钟计时（微秒），而非按调用次数递增
+fn test_block_age() -> TestResult {
+    println!("  Testing block ages against the monotonic clock...");
+
+    let block = alloc::AllocatedBlock::new(0x1000, 64, alloc::AllocPurpose::Testing, 0);
+    let header = alloc::BlockHeader::new(64, alloc::BlockStatus::Allocated);
+    time::mdelay(2);
+    let age = block.age();
+
+    if age < 2000 || !block.is_old(1000) || block.is_old(age + 1_000_000) || !header.is_old(1000) {
+        println!("  FAIL: age={} us after a 2 ms delay", age);
+        return TestResult::Fail;
+    }
+
+    println!("  PASS: Block age {} us after a 2 ms delay", age);
+    TestResult::Pass
+}
+
 /// 测试完整性检查
 fn test_integrity_check() -> TestResult {
     println!("  Testing allocator integrity check...");
@@ -544,15 +573,19 @@ fn test_stress_allocation() -> TestResult {
     const ITERATIONS: usize = 100;
     const MAX_ALLOCS: usize = 50;
     let mut active_allocs = Vec::new();
+
+    // 随机序列由运行种子决定，以相同的test_seed=即可复现
+    let mut rng = test_rng();
+    let mut next = move || rng.next_u64() as usize;
     
     for iteration in 0..ITERATIONS {
         // 随机分配或释放
-        let action = iteration % 3;
+        let action = next() % 3;
         
         match action {
             0 | 1 => { // 分配
                 if active_allocs.len() < MAX_ALLOCS {
-                    let size = 64 + (iteration % 10) * 128;
+                    let size = 64 + (next() % 10) * 128;
                     if let Some(ptr) = alloc::alloc(size) {
                         // 写入测试数据
                         unsafe {
@@ -566,7 +599,7 @@ fn test_stress_allocation() -> TestResult {
             }
             2 => { // 释放
                 if !active_allocs.is_empty() {
-                    let index = iteration % active_allocs.len();
+                    let index = next() % active_allocs.len();
                     let (ptr, size, pattern) = active_allocs.remove(index);
                     
                     // 验证数据完整性
@@ -620,77 +653,348 @@ fn test_stress_allocation() -> TestResult {
     }
 }
 
-/// 内存分配器测试用例列表 - 增强版本
-const ALLOC_TESTS: &[TestCase] = &[
-    TestCase {
-        name: "single_alloc_dealloc",
-        func: test_single_alloc_dealloc,
-        description: "Test a single allocation and deallocation with data integrity",
-    },
-    TestCase {
-        name: "multiple_allocs",
-        func: test_multiple_allocs,
-        description: "Test multiple allocations with different sizes and data verification",
-    },
-    TestCase {
-        name: "aligned_allocation",
-        func: test_aligned_allocation,
-        description: "Test memory alignment requirements",
-    },
-    TestCase {
-        name: "purpose_allocation",
-        func: test_purpose_allocation,
-        description: "Test purpose-based memory allocation",
-    },
-    TestCase {
-        name: "dynamic_vec",
-        func: test_dynamic_vec,
-        description: "Test standard library Vec operations",
-    },
-    TestCase {
-        name: "dynamic_string",
-        func: test_dynamic_string,
-        description: "Test standard library String operations",
-    },
-    TestCase {
-        name: "early_box",
-        func: test_early_box,
-        description: "Test EarlyBox smart pointer",
-    },
-    TestCase {
-        name: "early_vec",
-        func: test_early_vec,
-        description: "Test EarlyVec custom vector implementation",
-    },
-    TestCase {
-        name: "leak_detection",
-        func: test_leak_detection,
-        description: "Test memory leak detection capabilities",
-    },
-    TestCase {
-        name: "integrity_check",
-        func: test_integrity_check,
-        description: "Test allocator integrity verification",
-    },
-    TestCase {
-        name: "health_check",
-        func: test_health_check,
-        description: "Test allocator health monitoring",
-    },
-    TestCase {
-        name: "double_free_detection",
-        func: test_double_free_detection,
-        description: "Test double free detection and prevention",
-    },
-    TestCase {
-        name: "stress_allocation",
-        func: test_stress_allocation,
-        description: "Stress test with random allocation/deallocation patterns",
-    },
-];
-
-/// 运行所有内存分配器测试
-pub fn run_alloc_tests(runner: &mut TestRunner) {
+/// 模糊测试默认的操作次数，命令行`fuzz_ops=<次数>`可改为长时间运行
+const FUZZ_DEFAULT_OPS: usize = 2000;
+/// 同时存活的块数上限
+const FUZZ_MAX_LIVE: usize = 64;
+/// 每隔多少次操作校验全部存活块的内容
+const FUZZ_FULL_CHECK_INTERVAL: usize = 64;
+
+/// 影子记录：模糊测试认为某个块应有的状态
+struct FuzzBlock {
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    /// 内容模式，第i字节为`tag + 31 * i`
+    tag: u8,
+}
+
+impl FuzzBlock {
+    fn expected(&self, offset: usize) -> u8 {
+        self.tag.wrapping_add((offset as u8).wrapping_mul(31))
+    }
+
+    /// 从`from`起按模式填充
+    fn fill(&self, from: usize) {
+        for offset in from..self.size {
+            unsafe { self.ptr.add(offset).write(self.expected(offset)) };
+        }
+    }
+
+    /// 检查前`len`字节，返回第一个不符的偏移
+    fn verify(&self, len: usize) -> Option<usize> {
+        (0..len.min(self.size)).find(|&offset| unsafe { self.ptr.add(offset).read() } != self.expected(offset))
+    }
+
+    fn overlaps(&self, ptr: *mut u8, size: usize) -> bool {
+        let (start, end) = (ptr as usize, ptr as usize + size);
+        let (block_start, block_end) = (self.ptr as usize, self.ptr as usize + self.size);
+        start < block_end && block_start < end
+    }
+}
+
+/// 分配器模糊测试：随机分配、对齐分配、realloc与释放，影子记录每个块的
+/// 大小、对齐与内容，每次操作后检查分配器完整性。失败时报告种子，
+/// 以相同的`test_seed=`与`fuzz_ops=`重新运行即可复现。
+fn test_alloc_fuzz() -> TestResult {
+    let ops = cmdline::value("fuzz_ops").and_then(parse_number).map_or(FUZZ_DEFAULT_OPS, |n| n as usize);
+    println!("  {} operations", ops);
+    let mut rng = test_rng();
+    let mut next = move || rng.next_u64() as usize;
+
+    let mut live: Vec<FuzzBlock> = Vec::new();
+    let mut counts = [0usize; 4];
+    let mut failure = None;
+    for op in 0..ops {
+        // 多数块较小，偶尔分配较大的块
+        let size = if next() % 8 == 0 { 1 + next() % 8192 } else { 1 + next() % 256 };
+        let kind = if live.is_empty() { 0 } else if live.len() >= FUZZ_MAX_LIVE { 3 } else { next() % 4 };
+        let result: Result<(), String> = match kind {
+            // 分配与对齐分配
+            0 | 1 => {
+                let align = if kind == 0 { 8 } else { 1 << (3 + next() % 10) };
+                let ptr = if kind == 0 { alloc::alloc(size) } else { alloc::alloc_aligned(size, align) };
+                match ptr {
+                    Some(ptr) if ptr as usize % align != 0 => Err(format!("{:p} not aligned to {}", ptr, align)),
+                    Some(ptr) => match live.iter().find(|b| b.overlaps(ptr, size)) {
+                        Some(other) => Err(format!("{:p}+{} overlaps live {:p}+{}", ptr, size, other.ptr, other.size)),
+                        None => {
+                            let block = FuzzBlock { ptr, size, align, tag: next() as u8 };
+                            block.fill(0);
+                            live.push(block);
+                            Ok(())
+                        }
+                    },
+                    // 内存不足不算错误
+                    None => Ok(()),
+                }
+            }
+            // realloc：保留原有内容，新增部分重新填充
+            2 => {
+                let index = next() % live.len();
+                let (old, old_size, align) = (live[index].ptr, live[index].size, live[index].align);
+                let new = match Layout::from_size_align(old_size, align) {
+                    Ok(layout) => alloc::GLOBAL_EARLY_ALLOCATOR.realloc(old, layout, size),
+                    Err(_) => core::ptr::null_mut(),
+                };
+                if new.is_null() {
+                    Ok(())
+                } else if let Some((_, other)) =
+                    live.iter().enumerate().find(|&(i, b)| i != index && b.overlaps(new, size))
+                {
+                    Err(format!("realloc to {:p}+{} overlaps live {:p}+{}", new, size, other.ptr, other.size))
+                } else {
+                    let block = &mut live[index];
+                    block.ptr = new;
+                    block.size = size;
+                    match block.verify(old_size) {
+                        Some(offset) => Err(format!("realloc {} -> {} lost byte {}", old_size, size, offset)),
+                        None if new as usize % align != 0 => Err(format!("realloc lost alignment {}", align)),
+                        None => {
+                            block.fill(old_size);
+                            Ok(())
+                        }
+                    }
+                }
+            }
+            // 释放前校验全部内容
+            _ => {
+                let block = live.swap_remove(next() % live.len());
+                let corrupt = block.verify(block.size);
+                alloc::dealloc(block.ptr);
+                match corrupt {
+                    Some(offset) => Err(format!("{:p}+{} corrupted at byte {}", block.ptr, block.size, offset)),
+                    None => Ok(()),
+                }
+            }
+        };
+        counts[kind] += 1;
+
+        let result = result
+            .and_then(|_| alloc::integrity_check().map_err(|e| format!("integrity check failed: {:?}", e)))
+            .and_then(|_| {
+                if op % FUZZ_FULL_CHECK_INTERVAL != 0 {
+                    return Ok(());
+                }
+                match live.iter().find_map(|b| b.verify(b.size).map(|offset| (b, offset))) {
+                    Some((b, offset)) => Err(format!("{:p}+{} corrupted at byte {}", b.ptr, b.size, offset)),
+                    None => Ok(()),
+                }
+            });
+        if let Err(message) = result {
+            failure = Some((op, message));
+            break;
+        }
+    }
+
+    for block in live {
+        alloc::dealloc(block.ptr);
+    }
+    match failure {
+        None => {
+            let [allocs, aligned, reallocs, frees] = counts;
+            println!("  {} allocs, {} aligned allocs, {} reallocs, {} frees", allocs, aligned, reallocs, frees);
+            TestResult::Pass
+        }
+        Some((op, message)) => {
+            println!("  FAIL: operation {}: {}", op, message);
+            println!("  Reproduce with test_seed={:#x} test=alloc_fuzz fuzz_ops={}", test_seed(), ops);
+            TestResult::Fail
+        }
+    }
+}
+
+/// 测试子堆：集合的元素落在子堆内，配额独立于主堆，统计与完整性检查按子堆进行
+fn test_sub_heap() -> TestResult {
+    use alloc::subheap;
+    use alloc::AllocError;
+    use ::alloc::collections::BTreeMap;
+
+    const QUOTA: usize = 32 * 1024;
+    // 子堆创建后一直存在，重复运行测试时取用已有的
+    let heap = match subheap::get("test") {
+        Some(heap) => heap,
+        None => match subheap::create("test", QUOTA) {
+            Ok(heap) => heap,
+            Err(e) => {
+                println!("  FAIL: Sub-heap creation failed: {:?}", e);
+                return TestResult::Fail;
+            }
+        },
+    };
+    let duplicate = subheap::create("test", QUOTA).err();
+
+    let mut map = BTreeMap::new_in(heap);
+    for i in 0..100u32 {
+        map.insert(i, i * 3);
+    }
+    let mut list: Vec<u64, _> = Vec::new_in(heap);
+    list.extend(0..256u64);
+    let inside = heap.contains(list.as_ptr().cast())
+        && subheap::owner_of(list.as_ptr().cast()).map(|h| h.name()) == Some("test");
+    let live = heap.stats().alloc_count;
+    // 超出配额的请求只在子堆内失败
+    let over_quota = list.try_reserve_exact(QUOTA).is_err();
+    let checked = subheap::check_all();
+    let listed = subheap::heaps().iter().any(|info| info.name == "test" && info.size == QUOTA);
+    drop(map);
+    drop(list);
+    let drained = heap.stats().alloc_count == 0;
+
+    if duplicate == Some(AllocError::AlreadyInitialized) && inside && live >= 2 && over_quota
+        && checked.is_ok() && listed && drained {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: duplicate={:?}, inside={}, live={}, over quota={}, checked={:?}, listed={}, drained={}",
+                 duplicate, inside, live, over_quota, checked, listed, drained);
+        TestResult::Fail
+    }
+}
+
+/// 测试对象缓存：对象落在按页对齐的slab中，统计按缓存计，全空的slab可归还主堆
+fn test_slab_cache() -> TestResult {
+    use alloc::slab::{self, SlabCache, SLAB_SIZE};
+    use alloc::AllocError;
+
+    struct Entry {
+        id: u64,
+        payload: [u8; 56],
+    }
+    static CACHE: SlabCache<Entry> = SlabCache::new();
+
+    // 缓存登记后一直存在，重复运行测试时已登记
+    let registered = matches!(CACHE.register(), Ok(()) | Err(AllocError::AlreadyInitialized));
+    let before = CACHE.stats();
+    let count = before.objects_per_slab * 3;
+    let mut entries = Vec::new();
+    for id in 0..count as u64 {
+        match CACHE.alloc(Entry { id, payload: [id as u8; 56] }) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                println!("  FAIL: Slab allocation {} failed: {:?}", id, e);
+                return TestResult::Fail;
+            }
+        }
+    }
+    let intact = entries.iter().enumerate().all(|(i, e)| e.id == i as u64 && e.payload[55] == i as u8);
+    let stats = CACHE.stats();
+    let in_slabs = stats.slabs >= 3 && stats.in_use == before.in_use + count;
+    let listed = slab::caches().iter().any(|c| c.name == CACHE.name() && c.in_use == stats.in_use);
+    let checked = alloc::integrity_check();
+    drop(entries);
+    let emptied = CACHE.stats().in_use == before.in_use;
+    let released = CACHE.shrink();
+
+    if registered && intact && in_slabs && listed && checked.is_ok() && emptied
+        && released >= 3 * SLAB_SIZE && CACHE.stats().slabs == 0 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: registered={}, intact={}, stats={:?}, listed={}, checked={:?}, emptied={}, released={}",
+                 registered, intact, stats, listed, checked, emptied, released);
+        TestResult::Fail
+    }
+}
+
+/// 分配策略：每种策略都能服务分配，查找统计按策略分开记录；伙伴系统后端没有策略
+fn test_alloc_policy() -> TestResult {
+    let Some(original) = alloc::policy() else {
+        let rejected = alloc::set_policy(alloc::AllocPolicy::BestFit).is_err();
+        return if rejected { TestResult::Pass } else { TestResult::Fail };
+    };
+    let mut ok = true;
+    for policy in alloc::AllocPolicy::ALL {
+        let before = alloc::stats().map(|s| s.search_stats(policy)).unwrap_or_default();
+        if alloc::set_policy(policy).is_err() || alloc::policy() != Some(policy) {
+            println!("  FAIL: Cannot switch to {}", policy.name());
+            ok = false;
+            continue;
+        }
+        let blocks: Vec<Vec<u8>> = (1..=8).map(|i| crate::vec![i as u8; i * 40]).collect();
+        let intact = blocks.iter().enumerate().all(|(i, b)| b.len() == (i + 1) * 40 && b[0] == (i + 1) as u8);
+        drop(blocks);
+        let after = alloc::stats().map(|s| s.search_stats(policy)).unwrap_or_default();
+        if !intact || after.searches < before.searches + 8 {
+            println!("  FAIL: {}: intact={}, searches {} -> {}", policy.name(), intact, before.searches, after.searches);
+            ok = false;
+        }
+    }
+    let restored = alloc::set_policy(original).is_ok();
+
+    if ok && restored && alloc::integrity_check().is_ok() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 堆布局：每个堆区域都在设备树给出的某个RAM区间内，不与保留的内存重叠，首个区域在内核之后
+fn test_heap_layout() -> TestResult {
+    extern "C" {
+        fn end(); // 链接器提供的内核结束地址
+    }
+
+    let platform = crate::platform::get();
+    let regions = alloc::regions();
+    let in_ram = regions.iter().all(|r| {
+        platform.ram_banks().any(|bank| bank.contains(r.start) && r.start + r.size <= bank.end())
+    });
+    let clear = regions.iter().all(|r| crate::mm::guard::overlapping(r.start, r.size).is_none());
+    let after_kernel = regions.first().is_some_and(|r| r.start >= end as usize);
+
+    if in_ram && clear && after_kernel {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: regions={:?}, in_ram={}, clear={}, after_kernel={}", regions, in_ram, clear, after_kernel);
+        TestResult::Fail
+    }
+}
+
+/// 内存分配器测试套件 - 增强版本
+static SUITE: TestSuite = TestSuite {
+    setup: Some(alloc_suite_setup),
+    teardown: Some(alloc_suite_teardown),
+    ..TestSuite::new("Enhanced Allocator", 30)
+};
+
+crate::kernel_test!(SUITE, "single_alloc_dealloc", test_single_alloc_dealloc,
+    "Test a single allocation and deallocation with data integrity");
+crate::kernel_test!(SUITE, "multiple_allocs", test_multiple_allocs,
+    "Test multiple allocations with different sizes and data verification");
+crate::kernel_test!(SUITE, "aligned_allocation", test_aligned_allocation, "Test memory alignment requirements");
+crate::kernel_test!(SUITE, "purpose_allocation", test_purpose_allocation, "Test purpose-based memory allocation");
+crate::kernel_test!(SUITE, "dynamic_vec", test_dynamic_vec, "Test standard library Vec operations");
+crate::kernel_test!(SUITE, "dynamic_string", test_dynamic_string, "Test standard library String operations");
+crate::kernel_test!(SUITE, "early_box", test_early_box, "Test EarlyBox smart pointer");
+crate::kernel_test!(SUITE, "early_vec", test_early_vec, "Test EarlyVec custom vector implementation");
+crate::kernel_test!(SUITE, "leak_detection", test_leak_detection, "Test memory leak detection capabilities");
+crate::kernel_test!(SUITE, "block_age", test_block_age, "Test that block ages measure elapsed time");
+crate::kernel_test!(SUITE, "integrity_check", test_integrity_check, "Test allocator integrity verification");
+crate::kernel_test!(SUITE, "health_check", test_health_check, "Test allocator health monitoring");
+crate::kernel_test!(SUITE, "double_free_detection", test_double_free_detection,
+    "Test double free detection and prevention");
+crate::kernel_test!(SUITE, "sub_heap", test_sub_heap,
+    "Sub-heaps hold collections within their own quota, stats and integrity checks", allow_leak);
+crate::kernel_test!(SUITE, "slab_cache", test_slab_cache,
+    "Slab caches hand out objects in O(1) and give empty slabs back to the heap", allow_leak);
+crate::kernel_test!(SUITE, "alloc_policy", test_alloc_policy,
+    "First-, best- and next-fit serve allocations and keep separate search statistics");
+crate::kernel_test!(SUITE, "heap_layout", test_heap_layout,
+    "Heap regions come from device tree RAM banks and avoid reserved memory");
+crate::kernel_test!(SUITE, "stress_allocation", test_stress_allocation,
+    "Stress test with random allocation/deallocation patterns");
+crate::kernel_test!(SUITE, "alloc_fuzz", test_alloc_fuzz,
+    "Randomized alloc/realloc/free against a shadow record, reproducible by seed");
+
+/// 套件开始前的快照，供套件结束时比较
+struct Snapshot(alloc::MemorySnapshot);
+
+// 快照只在运行测试的线程中创建和使用
+unsafe impl Send for Snapshot {}
+
+static SNAPSHOT_BEFORE: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+/// 打印测试前的内存状态并创建快照
+fn alloc_suite_setup() -> bool {
     println!("Starting comprehensive allocator test suite...");
     
     // 打印测试前的内存状态
@@ -700,13 +1004,15 @@ pub fn run_alloc_tests(runner: &mut TestRunner) {
     }
     
     // 创建测试前快照
-    let snapshot_before = alloc::create_snapshot();
-    
-    // 运行测试套件
-    runner.run_suite("Enhanced Allocator", ALLOC_TESTS);
-    
+    *SNAPSHOT_BEFORE.lock() = alloc::create_snapshot().map(Snapshot);
+    true
+}
+
+/// 比较测试前后的快照，检查完整性并打印最终内存状态
+fn alloc_suite_teardown() {
     // 创建测试后快照并比较
-    if let (Some(before), Some(after)) = (snapshot_before, alloc::create_snapshot()) {
+    let snapshot_before = SNAPSHOT_BEFORE.lock().take();
+    if let (Some(Snapshot(before)), Some(after)) = (snapshot_before, alloc::create_snapshot()) {
         println!("Memory usage during tests:");
         let comparison = before.compare(&after);
         comparison.print();
@@ -0,0 +1,319 @@
+// 块设备层测试模块
+
+use super::{requirement, TestResult, TestSuite};
+use crate::block::{self, cache, BlockError, Op, RamDisk, Request};
+use crate::init::alloc::pressure;
+use crate::println;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+const BLOCK: usize = 512;
+
+/// setup：注册一个盘、经缓存读一块后注销，建立设备表与块缓存的索引。这些在注销后保留，
+/// 单独运行的测试 (`test=`) 也不会因首次使用它们而被判为泄漏。
+fn prepare_tables() -> bool {
+    let Ok(disk) = block::register(Arc::new(RamDisk::new("test-setup", BLOCK, 1))) else {
+        return false;
+    };
+    let cached = cache::get(&disk, 0).is_ok();
+    block::unregister("test-setup").is_ok() && cached
+}
+
+/// 按名称注册与查找，重复注册被拒绝
+fn test_block_register() -> TestResult {
+    let first = block::register(Arc::new(RamDisk::new("test-reg", BLOCK, 8)));
+    let again = block::register(Arc::new(RamDisk::new("test-reg", BLOCK, 8))).err();
+    let found = block::find("test-reg").map(|d| d.block_count());
+    let removed = block::unregister("test-reg");
+    let gone = block::find("test-reg").is_none();
+    let missing = block::unregister("test-reg");
+    if first.is_ok() && again == Some(BlockError::AlreadyRegistered) && found == Some(8)
+        && removed.is_ok() && gone && missing == Err(BlockError::NotFound) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: again={:?}, found={:?}, removed={:?}", again, found, removed);
+        TestResult::Fail
+    }
+}
+
+/// 同步读写往返，越界、非整块与只读写入报错
+fn test_block_read_write() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::new("test-rw", BLOCK, 16))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let pattern: Vec<u8> = (0..2 * BLOCK).map(|i| (i % 251) as u8).collect();
+    let written = disk.write(3, &pattern);
+    let mut readback = vec![0u8; 2 * BLOCK];
+    let read = disk.read(3, &mut readback);
+    let out_of_range = disk.read(15, &mut readback);
+    let misaligned = disk.write(0, &pattern[..100]);
+    let flushed = disk.flush();
+    let _ = block::unregister("test-rw");
+
+    let ro = match block::register(Arc::new(RamDisk::from_image("test-ro", BLOCK, vec![0xAA; 100], true))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let ro_write = ro.write(0, &pattern[..BLOCK]);
+    let mut ro_block = vec![0u8; BLOCK];
+    let ro_read = ro.read(0, &mut ro_block);
+    let _ = block::unregister("test-ro");
+
+    if written.is_ok() && read.is_ok() && readback == pattern && out_of_range == Err(BlockError::OutOfRange)
+        && misaligned == Err(BlockError::Misaligned) && flushed.is_ok()
+        && ro_write == Err(BlockError::ReadOnly) && ro_read.is_ok()
+        && ro_block[..100].iter().all(|&b| b == 0xAA) && ro_block[100..].iter().all(|&b| b == 0) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: written={:?}, read={:?}, range={:?}, misaligned={:?}, ro={:?}",
+                 written, read, out_of_range, misaligned, ro_write);
+        TestResult::Fail
+    }
+}
+
+/// 相邻请求在队列中合并 (向后与向前)，每个请求仍各自完成回调
+fn test_block_merge() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::new("test-merge", BLOCK, 16))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+    COMPLETED.store(0, Ordering::SeqCst);
+    let submit = |op: Op, lba: u64, fill: u8| {
+        disk.submit(Request::new(op, lba, vec![fill; BLOCK], move |c| {
+            let ok = c.result.is_ok() && c.lba == lba && (op == Op::Write || c.data.iter().all(|&b| b == 4 + lba as u8));
+            if ok {
+                COMPLETED.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+    };
+    // 写入块5、6 (向后合并)、4 (向前合并)
+    submit(Op::Write, 5, 9);
+    submit(Op::Write, 6, 10);
+    submit(Op::Write, 4, 8);
+    let queued_writes = disk.queue().len();
+    disk.queue().run();
+    // 读回同样的三块，也合并为一个请求
+    submit(Op::Read, 4, 0);
+    submit(Op::Read, 5, 0);
+    submit(Op::Read, 6, 0);
+    let queued_reads = disk.queue().len();
+    disk.queue().run();
+
+    let stats = disk.queue().stats();
+    let _ = block::unregister("test-merge");
+    if queued_writes == 1 && queued_reads == 1 && COMPLETED.load(Ordering::SeqCst) == 6
+        && stats.writes == 1 && stats.reads == 1 && stats.merges == 4 && stats.blocks_written == 3 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: queued={}/{}, completed={}, stats={:?}",
+                 queued_writes, queued_reads, COMPLETED.load(Ordering::SeqCst), stats);
+        TestResult::Fail
+    }
+}
+
+/// 电梯调度：同一批请求按块号排序 (从上次位置起单向扫描、回绕一次) 并合并相邻请求，
+/// 重叠的写请求保持提交顺序，队列达到深度上限时自动运行
+fn test_block_elevator() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::new("test-elevator", BLOCK, 16))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    static ORDER: Mutex<Vec<(Op, u64)>> = Mutex::new(Vec::new());
+    ORDER.lock().clear();
+    let submit = |op: Op, lba: u64, fill: u8| {
+        disk.submit(Request::new(op, lba, vec![fill; BLOCK], move |c| {
+            if c.result.is_ok() {
+                ORDER.lock().push((op, lba));
+            }
+        }));
+    };
+    disk.queue().set_depth_limit(16);
+    submit(Op::Write, 9, 1);
+    submit(Op::Write, 2, 2);
+    submit(Op::Write, 7, 3);
+    submit(Op::Write, 3, 4);
+    submit(Op::Read, 8, 0);
+    // 与块2的写冲突：必须在前一次写之后执行
+    submit(Op::Write, 2, 5);
+    let queued = disk.queue().len();
+    disk.queue().run();
+    let sorted = *ORDER.lock() == [(Op::Write, 2), (Op::Write, 3), (Op::Write, 7), (Op::Read, 8),
+                                   (Op::Write, 9), (Op::Write, 2)];
+    let mut raw = vec![0u8; BLOCK];
+    let last_write_won = disk.read(2, &mut raw).is_ok() && raw[0] == 5;
+
+    // 上次扫描停在块3之后：块5先于块1
+    ORDER.lock().clear();
+    submit(Op::Write, 1, 6);
+    submit(Op::Write, 5, 7);
+    disk.queue().run();
+    let wrapped = *ORDER.lock() == [(Op::Write, 5), (Op::Write, 1)];
+
+    disk.queue().set_depth_limit(2);
+    submit(Op::Write, 12, 8);
+    submit(Op::Write, 14, 9);
+    let ran_when_full = disk.queue().is_empty();
+    let stats = disk.queue().stats();
+    let _ = block::unregister("test-elevator");
+    // 不留下记录的缓冲区
+    let order = core::mem::take(&mut *ORDER.lock());
+
+    if queued == 6 && sorted && last_write_won && wrapped && ran_when_full && stats.elevator_merges == 1
+        && stats.max_depth == 6 && stats.full_runs == 1 && stats.writes == 8 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: queued={}, order={:?}, last_write_won={}, wrapped={}, full={}, stats={:?}",
+                 queued, order, last_write_won, wrapped, ran_when_full, stats);
+        TestResult::Fail
+    }
+}
+
+/// 缓存命中不再访问设备，写入在同步前只存在于缓存中
+fn test_cache_hit_write_back() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::new("test-cache", BLOCK, 16))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let before = cache::stats();
+    let first = cache::get(&disk, 7).map(|b| b.lock()[0]);
+    let buffer = match cache::get(&disk, 7) {
+        Ok(buffer) => buffer,
+        Err(_) => return TestResult::Fail,
+    };
+    let after = cache::stats();
+    let device_reads = disk.queue().stats().reads;
+
+    buffer.lock()[0] = 0x5A;
+    buffer.mark_dirty();
+    let mut raw = vec![0u8; BLOCK];
+    let _ = disk.read(7, &mut raw);
+    let on_disk_before = raw[0];
+    let synced = cache::sync(&disk);
+    let _ = disk.read(7, &mut raw);
+    let on_disk_after = raw[0];
+    let dirty_after = buffer.is_dirty();
+    drop(buffer);
+    let out_of_range = cache::get(&disk, 16).err();
+    let _ = block::unregister("test-cache");
+
+    if first == Ok(0) && after.misses == before.misses + 1 && after.hits == before.hits + 1
+        && device_reads == 1 && on_disk_before == 0 && synced.is_ok() && on_disk_after == 0x5A
+        && !dirty_after && out_of_range == Some(BlockError::OutOfRange) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: first={:?}, misses={}->{}, hits={}->{}, reads={}, disk={}/{}, synced={:?}",
+                 first, before.misses, after.misses, before.hits, after.hits, device_reads,
+                 on_disk_before, on_disk_after, synced);
+        TestResult::Fail
+    }
+}
+
+/// 顺序未命中触发预读，预读块与未命中块合并为一次设备读取
+fn test_cache_read_ahead() -> TestResult {
+    let image: Vec<u8> = (0..32 * BLOCK).map(|i| (i / BLOCK) as u8).collect();
+    let disk = match block::register(Arc::new(RamDisk::from_image("test-ahead", BLOCK, image, false))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let before = cache::stats();
+    let mut contents_ok = true;
+    for lba in 0..(2 + cache::READ_AHEAD) {
+        match cache::get(&disk, lba) {
+            Ok(buffer) => contents_ok &= buffer.lock().iter().all(|&b| b == lba as u8),
+            Err(_) => contents_ok = false,
+        }
+    }
+    let after = cache::stats();
+    let device_reads = disk.queue().stats().reads;
+    let _ = block::unregister("test-ahead");
+
+    // 块0、1未命中 (块1为顺序未命中，预读其后的块)，其余命中
+    if contents_ok && after.misses - before.misses == 2 && after.read_ahead - before.read_ahead == cache::READ_AHEAD
+        && after.hits - before.hits == cache::READ_AHEAD && device_reads == 2 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: contents={}, misses={}, read_ahead={}, hits={}, device reads={}",
+                 contents_ok, after.misses - before.misses, after.read_ahead - before.read_ahead,
+                 after.hits - before.hits, device_reads);
+        TestResult::Fail
+    }
+}
+
+/// 内存压力回调丢弃未使用的干净缓冲区并降低上限，保留正在使用与脏的缓冲区
+fn test_cache_shrink() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::new("test-shrink", BLOCK, 16))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let held = cache::get(&disk, 0);
+    let dirty = cache::get_for_overwrite(&disk, 1);
+    if let Ok(dirty) = &dirty {
+        dirty.lock().fill(0x77);
+        dirty.mark_dirty();
+    }
+    for lba in 2..8 {
+        let _ = cache::get(&disk, lba);
+    }
+    let dirty_lba = dirty.as_ref().ok().map(|b| b.lba());
+    drop(dirty);
+    let before = cache::stats();
+    let reclaimed = pressure::reclaim(usize::MAX);
+    let after = cache::stats();
+    // 正在使用的块0仍命中，脏块1仍在缓存中，被丢弃的块需要重新读取
+    let held_hit = cache::get(&disk, 0).is_ok() && cache::stats().hits == after.hits + 1;
+    let dirty_kept = cache::get(&disk, 1).map(|b| b.is_dirty() && b.lock()[0] == 0x77);
+    drop(held);
+    let unregistered = block::unregister("test-shrink");
+    let mut raw = vec![0u8; BLOCK];
+    let written = disk.read(1, &mut raw).is_ok() && raw[0] == 0x77;
+
+    if reclaimed >= 6 * BLOCK && after.bytes + reclaimed == before.bytes && after.shrinks == before.shrinks + 1
+        && after.limit == after.bytes.max(cache::MIN_BYTES) && held_hit && dirty_kept == Ok(true)
+        && dirty_lba == Some(1) && unregistered.is_ok() && written {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: reclaimed={}, bytes={}->{}, limit={}, held={}, dirty={:?}, written={}",
+                 reclaimed, before.bytes, after.bytes, after.limit, held_hit, dirty_kept, written);
+        TestResult::Fail
+    }
+}
+
+/// virtio-blk设备：读取第一个块
+fn test_virtio_blk() -> TestResult {
+    let Some(disk) = block::find("vda") else {
+        println!("  FAIL: vda disappeared");
+        return TestResult::Fail;
+    };
+    block::print_disks();
+    let mut first = vec![0u8; disk.block_size()];
+    match disk.read(0, &mut first) {
+        Ok(()) => TestResult::Pass,
+        Err(e) => {
+            println!("  FAIL: {}", e);
+            TestResult::Fail
+        }
+    }
+}
+
+/// 块设备测试套件；设备表与块缓存的索引由setup预先建立
+static SUITE: TestSuite = TestSuite { setup: Some(prepare_tables), ..TestSuite::new("Block", 110) };
+
+crate::kernel_test!(SUITE, "block_register", test_block_register, "Block devices register and are found by name");
+crate::kernel_test!(SUITE, "block_read_write", test_block_read_write,
+    "Synchronous I/O round-trips and bad requests fail");
+crate::kernel_test!(SUITE, "block_merge", test_block_merge,
+    "Contiguous queued requests merge and complete individually");
+crate::kernel_test!(SUITE, "block_elevator", test_block_elevator,
+    "Queued requests are sorted and merged without reordering conflicts");
+crate::kernel_test!(SUITE, "cache_hit_write_back", test_cache_hit_write_back,
+    "Cached blocks hit and dirty blocks reach the disk on sync");
+crate::kernel_test!(SUITE, "cache_read_ahead", test_cache_read_ahead,
+    "A sequential miss reads ahead in one device request");
+crate::kernel_test!(SUITE, "cache_shrink", test_cache_shrink, "Memory pressure drops clean, unused cached blocks");
+crate::kernel_test!(SUITE, "virtio_blk", test_virtio_blk, "A virtio block device reads its first block",
+    requires [requirement::VIRTIO_BLK]);
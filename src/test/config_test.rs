@@ -0,0 +1,179 @@
+// 持久化配置存储测试模块
+
+use super::{TestResult, TestSuite};
+use crate::block::{self, BlockError, RamDisk};
+use crate::config::{ConfigError, DiskRegion, FileStorage, Storage, Store, MAX_VALUE};
+use crate::fs;
+use crate::println;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const BLOCK: usize = 512;
+
+/// 测试用内存存储：可限制剩余可写字节数以模拟写入中途掉电
+struct MemStorage {
+    data: Arc<Mutex<Vec<u8>>>,
+    /// 剩余可写字节数，None为不限
+    budget: Arc<Mutex<Option<usize>>>,
+}
+
+impl Storage for MemStorage {
+    fn size(&self) -> u64 {
+        self.data.lock().len() as u64
+    }
+
+    fn unit(&self) -> usize {
+        BLOCK
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<(), ConfigError> {
+        let start = offset as usize;
+        buf.copy_from_slice(&self.data.lock()[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<(), ConfigError> {
+        let start = offset as usize;
+        let mut budget = self.budget.lock();
+        let len = budget.map_or(buf.len(), |left| left.min(buf.len()));
+        self.data.lock()[start..start + len].copy_from_slice(&buf[..len]);
+        if let Some(left) = budget.as_mut() {
+            *left -= len;
+        }
+        if len < buf.len() {
+            return Err(ConfigError::Block(BlockError::Io));
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        String::from("memory")
+    }
+}
+
+/// 在磁盘区域上格式化、写入、删除，重新打开后内容与代数保持
+fn test_config_persist() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::new("test-kv", BLOCK, 16))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let region = || DiskRegion::new(disk.clone(), 0, 16).map(|r| Box::new(r) as Box<dyn Storage>);
+    let blank = region().and_then(Store::open).err();
+
+    let mut changes = Vec::new();
+    let mut store = match region().and_then(Store::format) {
+        Ok(store) => store,
+        Err(_) => {
+            let _ = block::unregister("test-kv");
+            return TestResult::Fail;
+        }
+    };
+    changes.push(store.set("log.level", b"debug"));
+    changes.push(store.set("panic.policy", b"reboot"));
+    changes.push(store.remove("panic.policy").map(|_| ()));
+    // 无效键与超长值被拒绝
+    let bad_key = store.set("has space", b"x").err();
+    let too_long = store.set("big", &vec![0u8; MAX_VALUE + 1]).err();
+    // 写满后拒绝，已有内容不变
+    let mut full = None;
+    for i in 0..8 {
+        if let Err(e) = store.set(&alloc::format!("fill.{}", i), &[i as u8; MAX_VALUE]) {
+            full = Some((i, e));
+            break;
+        }
+    }
+    let generation = store.generation();
+
+    let reopened = region().and_then(Store::open);
+    let loaded = reopened.as_ref().ok().map(|s| {
+        (s.get_str("log.level").map(String::from), s.get("panic.policy").is_some(), s.keys().count(), s.generation())
+    });
+    let _ = block::unregister("test-kv");
+
+    let expected = Some((Some(String::from("debug")), false, 4, generation));
+    if blank == Some(ConfigError::NotFormatted) && changes.iter().all(Result::is_ok)
+        && bad_key == Some(ConfigError::InvalidKey) && too_long == Some(ConfigError::ValueTooLong)
+        && full == Some((3, ConfigError::NoSpace)) && generation == 7 && loaded == expected {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: blank={:?}, changes={:?}, bad_key={:?}, too_long={:?}, full={:?}, generation={}, loaded={:?}",
+                 blank, changes, bad_key, too_long, full, generation, loaded);
+        TestResult::Fail
+    }
+}
+
+/// 写入中途失败或最新快照损坏时，回退到上一快照
+fn test_config_torn_write() -> TestResult {
+    let data = Arc::new(Mutex::new(vec![0u8; 4 * BLOCK]));
+    let budget = Arc::new(Mutex::new(None));
+    let storage = || Box::new(MemStorage { data: data.clone(), budget: budget.clone() }) as Box<dyn Storage>;
+
+    let mut store = match Store::format(storage()) {
+        Ok(store) => store,
+        Err(_) => return TestResult::Fail,
+    };
+    let first = store.set("boot.tests", b"all");
+    // 新快照只写入了头部的一部分
+    *budget.lock() = Some(10);
+    let torn = store.set("boot.tests", b"none").err();
+    let in_memory = store.get_str("boot.tests").map(String::from);
+    *budget.lock() = None;
+    let after_torn = Store::open(storage()).map(|s| (s.get_str("boot.tests").map(String::from), s.generation()));
+
+    // 翻转最新快照 (第0槽) 中的一个字节：回退到第1槽的上一快照
+    let newest = store.set("boot.tests", b"none").map(|_| store.generation());
+    data.lock()[30] ^= 0xFF;
+    let fallback = Store::open(storage()).map(|s| (s.keys().count(), s.generation()));
+    // 两个槽都损坏
+    data.lock()[BLOCK * 2 + 30] ^= 0xFF;
+    let both = Store::open(storage()).err();
+
+    let all = Some(String::from("all"));
+    if first.is_ok() && torn == Some(ConfigError::Block(BlockError::Io)) && in_memory == all
+        && after_torn == Ok((all.clone(), 2)) && newest == Ok(3) && fallback == Ok((1, 2))
+        && both == Some(ConfigError::Corrupted) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: first={:?}, torn={:?}, in_memory={:?}, after_torn={:?}, newest={:?}, fallback={:?}, both={:?}",
+                 first, torn, in_memory, after_torn, newest, fallback, both);
+        TestResult::Fail
+    }
+}
+
+/// 以文件为存储
+fn test_config_file() -> TestResult {
+    const PATH: &str = "/tmp/config-test.kv";
+    let written = FileStorage::open(PATH, 2048)
+        .and_then(|f| Store::format(Box::new(f)))
+        .and_then(|mut store| store.set("console.color", b"off"));
+    let size = fs::stat(PATH).map(|m| m.size);
+    let loaded = FileStorage::open(PATH, 2048)
+        .and_then(|f| Store::open(Box::new(f)))
+        .map(|s| s.get_str("console.color").map(String::from));
+    let cleanup = fs::unlink(PATH);
+
+    if written.is_ok() && size == Ok(2048) && loaded == Ok(Some(String::from("off"))) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: written={:?}, size={:?}, loaded={:?}, cleanup={:?}", written, size, loaded, cleanup);
+        TestResult::Fail
+    }
+}
+
+/// 配置存储测试套件
+static SUITE: TestSuite = TestSuite::new("Config Store", 130);
+
+crate::kernel_test!(SUITE, "config_persist", test_config_persist,
+    "Settings written to a disk region are there after reopening");
+crate::kernel_test!(SUITE, "config_torn_write", test_config_torn_write,
+    "A torn or corrupted snapshot falls back to the previous one");
+// 存储文件留在ramfs中
+crate::kernel_test!(SUITE, "config_file", test_config_file, "A store kept in a file round-trips", allow_leak);
@@ -1,7 +1,10 @@
 // 控制台功能测试模块
 
-use super::{TestCase, TestResult, TestRunner};
+use super::{TestResult, TestSuite};
 use crate::{console, println, debug_print};
+use crate::console::ConsoleDriver;
+use alloc::string::String;
+use spin::Mutex;
 
 /// 测试基本字符输出
 fn test_basic_char_output() -> TestResult {
@@ -77,51 +80,75 @@ fn test_debug_output() -> TestResult {
     TestResult::Pass
 }
 
-/// 控制台测试用例列表
-const CONSOLE_TESTS: &[TestCase] = &[
-    TestCase {
-        name: "basic_char_output",
-        func: test_basic_char_output,
-        description: "Test basic character output functionality"
-    },
-    TestCase {
-        name: "string_output", 
-        func: test_string_output,
-        description: "Test string output functionality"
-    },
-    TestCase {
-        name: "decimal_output",
-        func: test_decimal_output,
-        description: "Test decimal number output"
-    },
-    TestCase {
-        name: "hex_output",
-        func: test_hex_output,
-        description: "Test hexadecimal number output"
-    },
-    TestCase {
-        name: "octal_output",
-        func: test_octal_output,
-        description: "Test octal number output"
-    },
-    TestCase {
-        name: "format_macros",
-        func: test_format_macros,
-        description: "Test formatting macros (println!)"
-    },
-    TestCase {
-        name: "color_output",
-        func: test_color_output,
-        description: "Test colored output macros"
-    },
-    TestCase {
-        name: "debug_output",
-        func: test_debug_output,
-        description: "Test debug output with file/line info"
-    },
-];
-
-/// 运行所有控制台测试
-pub fn run_console_tests(runner: &mut TestRunner) {
-    runner.run_suite("Console", CONSOLE_TESTS);
-}
\ No newline at end of file
+/// 记录输出内容的测试后端
+struct CaptureConsole;
+
+static CAPTURED: Mutex<String> = Mutex::new(String::new());
+static CAPTURE_CONSOLE: CaptureConsole = CaptureConsole;
+
+impl ConsoleDriver for CaptureConsole {
+    fn name(&self) -> &'static str {
+        "capture"
+    }
+
+    fn write_str(&self, s: &str) {
+        CAPTURED.lock().push_str(s);
+    }
+}
+
+/// 测试控制台后端切换
+fn test_backend_switch() -> TestResult {
+    let previous = console::backend();
+    CAPTURED.lock().clear();
+
+    console::register_backend(&CAPTURE_CONSOLE);
+    let name = console::backend_name();
+    println!("captured {}", 42);
+    console::print_hex(0xbeef);
+    console::register_backend(previous);
+
+    let captured = core::mem::take(&mut *CAPTURED.lock());
+    if name == "capture" && captured == "captured 42\nbeef" && console::backend_name() == previous.name() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: name={}, captured={:?}", name, captured);
+        TestResult::Fail
+    }
+}
+
+/// 测试附加输出：与后端一起收到输出，注销后不再收到，同名不能重复注册
+fn test_console_sink() -> TestResult {
+    CAPTURED.lock().clear();
+
+    let registered = console::register_sink(&CAPTURE_CONSOLE);
+    let duplicate = console::register_sink(&CAPTURE_CONSOLE);
+    let listed = console::sink_names().any(|name| name == "capture");
+    console::print_str("to every sink\n");
+    console::unregister_sink("capture");
+    console::print_str("backend only\n");
+
+    let captured = core::mem::take(&mut *CAPTURED.lock());
+    if registered && !duplicate && listed && captured == "to every sink\n"
+        && !console::sink_names().any(|name| name == "capture") {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: registered={}, duplicate={}, listed={}, captured={:?}",
+                 registered, duplicate, listed, captured);
+        TestResult::Fail
+    }
+}
+
+/// 控制台测试套件
+static SUITE: TestSuite = TestSuite::new("Console", 10);
+
+crate::kernel_test!(SUITE, "basic_char_output", test_basic_char_output, "Test basic character output functionality");
+crate::kernel_test!(SUITE, "string_output", test_string_output, "Test string output functionality");
+crate::kernel_test!(SUITE, "decimal_output", test_decimal_output, "Test decimal number output");
+crate::kernel_test!(SUITE, "hex_output", test_hex_output, "Test hexadecimal number output");
+crate::kernel_test!(SUITE, "octal_output", test_octal_output, "Test octal number output");
+crate::kernel_test!(SUITE, "format_macros", test_format_macros, "Test formatting macros (println!)");
+crate::kernel_test!(SUITE, "color_output", test_color_output, "Test colored output macros");
+crate::kernel_test!(SUITE, "debug_output", test_debug_output, "Test debug output with file/line info");
+crate::kernel_test!(SUITE, "backend_switch", test_backend_switch,
+    "Test routing console output through a registered backend");
+crate::kernel_test!(SUITE, "console_sink", test_console_sink, "Test mirroring console output to an additional sink");
@@ -0,0 +1,216 @@
+// 内核调试设施测试模块
+
+use super::{Requirement, TestResult, TestSuite};
+use crate::debug::kprobe::{self, KprobeError};
+use crate::debug::pagewatch;
+use crate::debug::watchpoint::{self, WatchError, WatchHit, WatchKind, WatchMode};
+use crate::mm::{self, kprotect};
+use crate::println;
+use crate::trap::TrapContext;
+use core::arch::global_asm;
+use core::hint::black_box;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// 以auipc开头的函数，与pc相关的指令不能在别处单步执行
+global_asm!(
+    ".section .text.kprobe_test, \"ax\"",
+    ".globl kprobe_test_auipc",
+    "kprobe_test_auipc:",
+    "auipc a0, 0",
+    "ret",
+);
+
+extern "C" {
+    fn kprobe_test_auipc() -> usize;
+}
+
+static PRE_HITS: AtomicU64 = AtomicU64::new(0);
+static POST_HITS: AtomicU64 = AtomicU64::new(0);
+/// pre回调看到的第一个参数 (a0)
+static PRE_ARG: AtomicUsize = AtomicUsize::new(0);
+/// post回调看到的恢复地址
+static POST_PC: AtomicUsize = AtomicUsize::new(0);
+
+fn record_pre(_addr: usize, context: &mut TrapContext) {
+    PRE_HITS.fetch_add(1, Ordering::Relaxed);
+    PRE_ARG.store(context.x[10], Ordering::Relaxed);
+}
+
+fn record_post(_addr: usize, context: &mut TrapContext) {
+    POST_HITS.fetch_add(1, Ordering::Relaxed);
+    POST_PC.store(context.sepc, Ordering::Relaxed);
+}
+
+#[inline(never)]
+fn probed_target(value: usize) -> usize {
+    black_box(value).wrapping_mul(3) + 1
+}
+
+/// 测试探针：被探测的函数结果不变，pre回调看到参数，post回调在被探测指令之后，注销后不再触发
+fn test_kprobe_hits() -> TestResult {
+    let addr = probed_target as usize;
+    PRE_HITS.store(0, Ordering::Relaxed);
+    POST_HITS.store(0, Ordering::Relaxed);
+    if let Err(e) = kprobe::register(addr, Some(record_pre), Some(record_post)) {
+        println!("  FAIL: register: {}", e);
+        return TestResult::Fail;
+    }
+    let first = probed_target(black_box(7));
+    let second = probed_target(black_box(11));
+    let listed = kprobe::probes().iter().any(|p| p.addr == addr && p.hits == 2);
+    let removed = kprobe::unregister(addr);
+    let after = probed_target(black_box(5));
+    let (pre, post) = (PRE_HITS.load(Ordering::Relaxed), POST_HITS.load(Ordering::Relaxed));
+    let arg = PRE_ARG.load(Ordering::Relaxed);
+    let resumed = POST_PC.load(Ordering::Relaxed);
+    if first == 22 && second == 34 && after == 16 && listed && removed == Ok(2)
+        && pre == 2 && post == 2 && arg == 11 && (resumed == addr + 2 || resumed == addr + 4) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: results=({}, {}, {}), listed={}, removed={:?}, pre={}, post={}, arg={}, resumed={:#x}",
+                 first, second, after, listed, removed, pre, post, arg, resumed);
+        TestResult::Fail
+    }
+}
+
+/// 测试探针的拒绝条件：内核代码之外、奇数地址、重复探测、与pc相关的指令与未探测的地址
+fn test_kprobe_rejects() -> TestResult {
+    let addr = probed_target as usize;
+    let outside = kprobe::register(0x1000, None, None);
+    let odd = kprobe::register(addr + 1, None, None);
+    let auipc = kprobe::register(kprobe_test_auipc as usize, None, None);
+    let first = kprobe::register(addr, None, None);
+    let duplicate = kprobe::register(addr, None, None);
+    let removed = kprobe::unregister(addr);
+    let again = kprobe::unregister(addr);
+    let value = unsafe { kprobe_test_auipc() };
+    if outside == Err(KprobeError::OutsideText) && odd == Err(KprobeError::Misaligned)
+        && auipc == Err(KprobeError::Unsupported) && first.is_ok()
+        && duplicate == Err(KprobeError::AlreadyProbed) && removed == Ok(0)
+        && again == Err(KprobeError::NotProbed) && value == kprobe_test_auipc as usize {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: outside={:?}, odd={:?}, auipc={:?}, first={:?}, duplicate={:?}, removed={:?}, again={:?}",
+                 outside, odd, auipc, first, duplicate, removed, again);
+        TestResult::Fail
+    }
+}
+
+/// 被监视的内存，按其大小对齐
+#[repr(align(16))]
+struct Watched([u64; 2]);
+
+static mut WATCHED: Watched = Watched([0; 2]);
+static WATCH_HITS: AtomicU64 = AtomicU64::new(0);
+/// 监视点处理函数看到的访问类型与地址
+static WATCH_STORE: AtomicU64 = AtomicU64::new(0);
+static WATCH_ACCESS: AtomicUsize = AtomicUsize::new(0);
+
+fn record_hit(hit: &WatchHit, _context: &mut TrapContext) {
+    WATCH_HITS.fetch_add(1, Ordering::Relaxed);
+    WATCH_STORE.store((hit.kind == Some(WatchKind::Store)) as u64, Ordering::Relaxed);
+    WATCH_ACCESS.store(hit.access, Ordering::Relaxed);
+}
+
+/// 测试硬件监视点：写入被监视的内存时命中，写入照常完成，读取与相邻内存不触发
+fn test_watchpoint_store() -> TestResult {
+    let base = unsafe { ptr::addr_of_mut!(WATCHED.0) } as *mut u64;
+    let addr = base as usize;
+    WATCH_HITS.store(0, Ordering::Relaxed);
+    if let Err(e) = watchpoint::set(addr, 8, WatchKind::Store, Some(record_hit)) {
+        println!("  FAIL: set: {}", e);
+        return TestResult::Fail;
+    }
+    unsafe {
+        ptr::write_volatile(base, 0x5a5a);
+        ptr::write_volatile(base.add(1), 1);
+    }
+    let read = unsafe { ptr::read_volatile(base) };
+    let cleared = watchpoint::clear(addr);
+    unsafe { ptr::write_volatile(base, 0) };
+    let hits = WATCH_HITS.load(Ordering::Relaxed);
+    let store = WATCH_STORE.load(Ordering::Relaxed) == 1;
+    let access = WATCH_ACCESS.load(Ordering::Relaxed);
+    if read == 0x5a5a && cleared == Ok(1) && hits == 1 && store && access == addr {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: read={:#x}, cleared={:?}, hits={}, store={}, access={:#x}", read, cleared, hits, store, access);
+        TestResult::Fail
+    }
+}
+
+/// 独占一页的被监视内存，陷阱路径不会写入这一页
+#[repr(align(4096))]
+struct WatchedPage([u64; 512]);
+
+static mut WATCHED_PAGE: WatchedPage = WatchedPage([0; 512]);
+
+/// 测试页保护监视点：写入监视范围时命中，同页其它位置的写入不报告，两者都照常完成，清除后页恢复可写
+fn test_pagewatch_store() -> TestResult {
+    let base = unsafe { ptr::addr_of_mut!(WATCHED_PAGE.0) } as *mut u64;
+    let watched = unsafe { base.add(8) };
+    let addr = watched as usize;
+    WATCH_HITS.store(0, Ordering::Relaxed);
+    let stray_before = pagewatch::stray_faults();
+    if let Err(e) = pagewatch::set(addr, 8, Some(record_hit)) {
+        println!("  FAIL: set: {}", e);
+        return TestResult::Fail;
+    }
+    let protected = !kprotect::is_writable(addr);
+    let listed = watchpoint::watchpoints().iter().any(|w| w.addr == addr && w.mode == WatchMode::Page);
+    unsafe {
+        ptr::write_volatile(watched, 0x77);
+        ptr::write_volatile(base, 1);
+    }
+    let cleared = pagewatch::clear(addr);
+    let writable = kprotect::is_writable(addr);
+    let values = unsafe { (ptr::read_volatile(watched), ptr::read_volatile(base)) };
+    unsafe {
+        ptr::write_volatile(watched, 0);
+        ptr::write_volatile(base, 0);
+    }
+    let hits = WATCH_HITS.load(Ordering::Relaxed);
+    let stray = pagewatch::stray_faults() - stray_before;
+    let access = WATCH_ACCESS.load(Ordering::Relaxed);
+    if protected && listed && values == (0x77, 1) && cleared == Ok(1) && hits == 1 && stray == 1
+        && access == addr && writable {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: protected={}, listed={}, values={:x?}, cleared={:?}, hits={}, stray={}, access={:#x}, writable={}",
+                 protected, listed, values, cleared, hits, stray, access, writable);
+        TestResult::Fail
+    }
+}
+
+/// 测试监视点的参数检查：长度须为2的幂且地址按长度对齐
+fn test_watchpoint_rejects() -> TestResult {
+    let addr = unsafe { ptr::addr_of!(WATCHED.0) } as usize;
+    let empty = watchpoint::set(addr, 0, WatchKind::Store, None);
+    let odd_len = watchpoint::set(addr, 3, WatchKind::Store, None);
+    let misaligned = watchpoint::set(addr + 4, 8, WatchKind::Access, None);
+    let unknown = watchpoint::clear(addr);
+    if empty == Err(WatchError::InvalidRange) && odd_len == Err(WatchError::InvalidRange)
+        && misaligned == Err(WatchError::InvalidRange) && unknown == Err(WatchError::NotWatched) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: empty={:?}, odd_len={:?}, misaligned={:?}, unknown={:?}", empty, odd_len, misaligned, unknown);
+        TestResult::Fail
+    }
+}
+
+/// 调试设施测试套件
+static SUITE: TestSuite = TestSuite::new("Debug", 85);
+
+crate::kernel_test!(SUITE, "kprobe_hits", test_kprobe_hits,
+    "Probes run pre and post callbacks around the probed instruction until removed");
+crate::kernel_test!(SUITE, "kprobe_rejects", test_kprobe_rejects,
+    "Probes outside text, misaligned, duplicated or on pc-relative instructions are refused");
+crate::kernel_test!(SUITE, "watchpoint_store", test_watchpoint_store,
+    "A store watchpoint reports writes to the watched word and lets them complete",
+    requires [Requirement::Probe("RISC-V trigger module", watchpoint::is_available)]);
+crate::kernel_test!(SUITE, "watchpoint_rejects", test_watchpoint_rejects,
+    "Watchpoints on empty, non-power-of-two or misaligned ranges are refused");
+crate::kernel_test!(SUITE, "pagewatch_store", test_pagewatch_store,
+    "A page-protection watchpoint logs stores to its range and lets every store to the page complete",
+    requires [Requirement::Probe("paging", mm::is_initialized)]);
@@ -0,0 +1,484 @@
+// 设备树与驱动模型测试模块
+
+use super::{Requirement, TestResult, TestSuite};
+use crate::boot;
+use crate::cpuinfo::{self, Extension, Isa};
+use crate::driver::mmio::{self, ReadOnly, ReadWrite, WriteOnly};
+use crate::driver::fb::{self, font, Framebuffer, TextConsole};
+use crate::driver::dma::{self, Direction, DmaBuffer};
+use crate::driver::{self, irq, pci, sifive_test, virtio, Device, Driver, DriverError, MmioRegion};
+use crate::fdt::{Fdt, FdtError};
+use crate::mm::{PAGE_SIZE, USER_SPACE_START};
+use crate::platform::{self, Platform, Source};
+use crate::println;
+use crate::util::rand::{self, SeedSource};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// 测试用设备树：/soc (1个地址单元、1个大小单元) 下有一个启用的
+/// dev@1000 (reg <0x1000 0x100>，中断 <7 8>) 和一个禁用的 off@2000
+static TEST_DTB: [u8; 372] = [
+    0xd0, 0x0d, 0xfe, 0xed, 0x00, 0x00, 0x01, 0x74, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x01, 0x38,
+    0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x01, 0x73, 0x6f, 0x63, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x64, 0x65, 0x76, 0x40,
+    0x31, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x17,
+    0x00, 0x00, 0x00, 0x1b, 0x6e, 0x74, 0x2c, 0x74, 0x65, 0x73, 0x74, 0x2d, 0x64, 0x65, 0x76, 0x00,
+    0x6e, 0x74, 0x2c, 0x67, 0x65, 0x6e, 0x65, 0x72, 0x69, 0x63, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x26, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, 0x07,
+    0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x6f, 0x66, 0x66, 0x40,
+    0x32, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0c,
+    0x00, 0x00, 0x00, 0x1b, 0x6e, 0x74, 0x2c, 0x74, 0x65, 0x73, 0x74, 0x2d, 0x64, 0x65, 0x76, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x35, 0x64, 0x69, 0x73, 0x61,
+    0x62, 0x6c, 0x65, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x09, 0x23, 0x61, 0x64, 0x64, 0x72, 0x65, 0x73, 0x73,
+    0x2d, 0x63, 0x65, 0x6c, 0x6c, 0x73, 0x00, 0x23, 0x73, 0x69, 0x7a, 0x65, 0x2d, 0x63, 0x65, 0x6c,
+    0x6c, 0x73, 0x00, 0x63, 0x6f, 0x6d, 0x70, 0x61, 0x74, 0x69, 0x62, 0x6c, 0x65, 0x00, 0x72, 0x65,
+    0x67, 0x00, 0x69, 0x6e, 0x74, 0x65, 0x72, 0x72, 0x75, 0x70, 0x74, 0x73, 0x00, 0x73, 0x74, 0x61,
+    0x74, 0x75, 0x73, 0x00,
+];
+
+/// 解析测试设备树，根节点有两个地址单元
+fn test_fdt_parse() -> TestResult {
+    let fdt = match Fdt::from_bytes(&TEST_DTB) {
+        Ok(fdt) => fdt,
+        Err(e) => {
+            println!("  FAIL: {:?}", e);
+            return TestResult::Fail;
+        }
+    };
+    let dev = fdt.find_node("dev");
+    let reg = dev.and_then(|n| n.reg().next());
+    let compatible = fdt.find_compatible("nt,generic").map(|n| n.name);
+    let disabled = fdt.find_node("off@2000").map(|n| n.is_enabled());
+    let bad = Fdt::from_bytes(&TEST_DTB[4..]).err();
+    if reg.map(|r| (r.address, r.size)) == Some((0x1000, 0x100)) && compatible == Some("dev@1000")
+        && disabled == Some(false) && bad == Some(FdtError::BadMagic) && fdt.total_size() == TEST_DTB.len() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: reg={:?}, compatible={:?}, disabled={:?}, bad={:?}", reg, compatible, disabled, bad);
+        TestResult::Fail
+    }
+}
+
+/// 启动时传入的设备树应能解析，并包含virt平台的串口
+fn test_boot_fdt() -> TestResult {
+    let Some(info) = boot::boot_info() else {
+        return TestResult::Skip;
+    };
+    let fdt = match unsafe { Fdt::from_addr(info.dtb_addr) } {
+        Ok(fdt) => fdt,
+        Err(e) => {
+            println!("  FAIL: {:?}", e);
+            return TestResult::Fail;
+        }
+    };
+    let nodes = fdt.nodes().count();
+    let errors = fdt.nodes().filter(|n| n.is_err()).count();
+    let uart = fdt.find_compatible("ns16550a").and_then(|n| n.reg().next());
+    println!("  {} nodes, uart at {:?}", nodes, uart.map(|r| r.address));
+    if errors == 0 && uart.is_some() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 平台描述：设备树缺少的内存与时基取默认值，未描述的设备视为不存在；
+/// 启动时的描述应覆盖内核自身所在的内存
+fn test_platform() -> TestResult {
+    let fdt = match Fdt::from_bytes(&TEST_DTB) {
+        Ok(fdt) => fdt,
+        Err(_) => return TestResult::Fail,
+    };
+    let parsed = Platform::from_fdt(&fdt);
+    let parsed_ok = parsed.source == Source::DeviceTree
+        && parsed.ram == Platform::FALLBACK.ram && parsed.ram_banks == Platform::FALLBACK.ram_banks
+        && parsed.timebase_frequency == Platform::FALLBACK.timebase_frequency
+        && parsed.hart_count == 1 && !parsed.sstc
+        && parsed.uart.is_none() && parsed.plic.is_none() && parsed.test_device.is_none();
+
+    let boot = platform::get();
+    boot.print();
+    let kernel_in_ram = boot.ram.contains(test_platform as usize) && boot.ram_banks().next() == Some(boot.ram);
+    let ticks_ok = boot.ticks_per_ms() * 1000 == boot.timebase_frequency;
+    if parsed_ok && kernel_in_ram && ticks_ok {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: parsed={:?}, kernel_in_ram={}, ticks_ok={}", parsed, kernel_in_ram, ticks_ok);
+        TestResult::Fail
+    }
+}
+
+/// ISA字符串解析 (版本号、g 展开、多字母扩展)；启动hart应有描述且具备基本整数指令集
+fn test_cpuinfo() -> TestResult {
+    let isa = Isa::parse("rv64i2p1m2p0afdc_zicsr_zba1p0_sstc");
+    let parsed_ok = isa.xlen == 64 && isa.has_letter('c') && isa.has_letter('d') && !isa.has_letter('v')
+        && isa.has(Extension::Sstc) && isa.has(Extension::Zba) && !isa.has(Extension::Zifencei);
+    let expanded = Isa::parse("rv64gc");
+    let expanded_ok = expanded.has_letter('f') && expanded.has(Extension::Zifencei)
+        && format!("{}", expanded) == "rv64imafdc_zicsr_zifencei";
+
+    let info = cpuinfo::init();
+    info.print();
+    let boot_hart = boot::boot_info().map_or(0, |b| b.hart_id);
+    let described = boot::boot_info().is_none() || info.hart(boot_hart).is_some_and(|h| h.isa.has_letter('i'));
+    let common_ok = cpuinfo::has_letter('i') && cpuinfo::has_extension(Extension::Sstc) == platform::get().sstc;
+    if parsed_ok && expanded_ok && described && common_ok {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: parsed={}, expanded={}, described={}, common={}", isa, expanded, described, common_ok);
+        TestResult::Fail
+    }
+}
+
+static TEST_PROBES: AtomicUsize = AtomicUsize::new(0);
+
+fn test_probe(device: &Device) -> Result<(), DriverError> {
+    TEST_PROBES.fetch_add(1, Ordering::SeqCst);
+    if device.region()? == (MmioRegion { base: 0x1000, size: 0x100 }) && device.irqs == [7, 8] {
+        Ok(())
+    } else {
+        Err(DriverError::Unsupported)
+    }
+}
+
+static TEST_IRQS: AtomicUsize = AtomicUsize::new(0);
+static TEST_REMOVES: AtomicUsize = AtomicUsize::new(0);
+
+fn test_irq_handler(_irq: u32, _device: &Device) {
+    TEST_IRQS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn test_remove(_device: &Device) {
+    TEST_REMOVES.fetch_add(1, Ordering::SeqCst);
+}
+
+static TEST_DRIVER: Driver = Driver {
+    name: "nt-test",
+    compatible: &["nt,generic"],
+    probe: test_probe,
+    irq_handler: Some(test_irq_handler),
+    remove: Some(test_remove),
+};
+
+/// 按compatible匹配驱动：禁用节点不探测，重复探测不会重复绑定
+fn test_driver_probe() -> TestResult {
+    let fdt = match Fdt::from_bytes(&TEST_DTB) {
+        Ok(fdt) => fdt,
+        Err(_) => return TestResult::Fail,
+    };
+    let registered = driver::register_driver(&TEST_DRIVER);
+    let again = driver::register_driver(&TEST_DRIVER);
+    let first = driver::probe_all(&fdt);
+    let second = driver::probe_all(&fdt);
+    let bound = driver::find_device("/soc/dev@1000");
+
+    let bound_ok = bound.as_ref().map_or(false, |b| b.driver == "nt-test" && b.device.compatible == "nt,generic");
+    if registered.is_ok() && again == Err(DriverError::AlreadyRegistered)
+        && first.map(|s| s.bound) == Ok(1) && second.map(|s| s.bound) == Ok(0)
+        && TEST_PROBES.load(Ordering::SeqCst) == 1 && bound_ok {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: registered={:?}, first={:?}, second={:?}, probes={}",
+                 registered, first, second, TEST_PROBES.load(Ordering::SeqCst));
+        TestResult::Fail
+    }
+}
+
+/// 总线驱动发现的设备 (无设备树节点) 同样按compatible匹配驱动
+fn test_dynamic_probe() -> TestResult {
+    let region = MmioRegion { base: 0x1000, size: 0x100 };
+    let path = String::from("/test-bus/dev0");
+    let before = TEST_PROBES.load(Ordering::SeqCst);
+    let bound = driver::probe_device(path.clone(), &["nt,unknown", "nt,generic"], vec![region], vec![7, 8]);
+    let again = driver::probe_device(path.clone(), &["nt,generic"], vec![region], vec![7, 8]);
+    let unmatched = driver::probe_device(String::from("/test-bus/dev1"), &["nt,unknown"], vec![], vec![]);
+    let device = driver::find_device(&path);
+
+    let device_ok = device.as_ref().map_or(false, |b| {
+        b.device.node.is_none() && b.device.name() == "dev0" && b.device.depth() == 2 && b.driver == "nt-test"
+    });
+    if bound == Ok(true) && again == Ok(false) && unmatched == Ok(false) && device_ok
+        && TEST_PROBES.load(Ordering::SeqCst) == before + 1 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: bound={:?}, again={:?}, unmatched={:?}", bound, again, unmatched);
+        TestResult::Fail
+    }
+}
+
+/// 探测成功后设备的中断自动绑定到驱动的处理函数，解绑设备时释放中断并调用remove
+/// (测试设备没有中断父节点，不经PLIC路由)
+fn test_irq_binding() -> TestResult {
+    let tree_path = "/soc/dev@1000";
+    let bus_path = "/test-bus/dev0";
+    let irqs_of = |path: &str| -> Vec<u32> {
+        irq::bindings().into_iter().filter(|b| b.path == path).map(|b| b.irq).collect()
+    };
+    let bound = irqs_of(tree_path) == [7, 8] && irqs_of(bus_path) == [7, 8];
+    let unrouted = irq::bindings().iter().filter(|b| b.driver == "nt-test").all(|b| !b.routed);
+
+    let before = TEST_IRQS.load(Ordering::SeqCst);
+    let shared = irq::dispatch(8) && TEST_IRQS.load(Ordering::SeqCst) == before + 2;
+    let removed = driver::unbind(bus_path);
+    let removes = TEST_REMOVES.load(Ordering::SeqCst);
+    let released = irqs_of(bus_path).is_empty() && driver::find_device(bus_path).is_none();
+    let before = TEST_IRQS.load(Ordering::SeqCst);
+    let single = irq::dispatch(8) && TEST_IRQS.load(Ordering::SeqCst) == before + 1;
+    let again = driver::unbind(bus_path).err();
+    irq::print();
+
+    if bound && unrouted && shared && removed.as_ref().map(|b| b.driver) == Ok("nt-test") && removes == 1
+        && released && single && again == Some(DriverError::NotBound) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: bound={}, unrouted={}, shared={}, removed={:?}, removes={}, released={}, single={}",
+                 bound, unrouted, shared, removed.map(|b| b.device.path), removes, released, single);
+        TestResult::Fail
+    }
+}
+
+/// PCIe枚举：virt平台的主桥位于00:00.0，已分配的BAR按大小对齐
+fn test_pci_enumeration() -> TestResult {
+    let functions = pci::functions();
+    if functions.is_empty() {
+        return TestResult::Skip;
+    }
+    pci::print_functions();
+    let host_bridge = functions
+        .iter()
+        .find(|f| f.address == pci::PciAddress { bus: 0, device: 0, function: 0 })
+        .map_or(false, |f| f.class >> 16 == 0x06);
+    let aligned = functions
+        .iter()
+        .flat_map(|f| f.bars.iter().flatten())
+        .all(|bar| bar.size.is_power_of_two() && bar.bus_addr % bar.size == 0);
+    let lookup = functions.iter().all(|f| pci::function(&f.path).map(|g| g.address) == Some(f.address));
+    if host_bridge && aligned && lookup {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: host_bridge={}, aligned={}, lookup={}", host_bridge, aligned, lookup);
+        TestResult::Fail
+    }
+}
+
+/// DMA映射：缓冲区经内核页表得到总线地址，不可映射的地址被拒绝
+fn test_dma_mapping() -> TestResult {
+    let mut ring = match DmaBuffer::new(3 * PAGE_SIZE, PAGE_SIZE) {
+        Ok(ring) => ring,
+        Err(e) => {
+            println!("  FAIL: DmaBuffer::new: {:?}", e);
+            return TestResult::Fail;
+        }
+    };
+    let buffer_ok = ring.addr() % PAGE_SIZE == 0 && ring.as_slice().iter().all(|&b| b == 0)
+        && dma::bus_address(ring.addr()) == Some(ring.bus_addr());
+    ring.as_mut_slice()[PAGE_SIZE] = 0x5a;
+    let part = ring.map(PAGE_SIZE..PAGE_SIZE + 16, Direction::ToDevice);
+    let part_ok = part.bus_addr == ring.bus_addr() + PAGE_SIZE && part.len == 16 && !part.device_writes();
+
+    let mut data = vec![0u8; 64];
+    let mapped = dma::map_for_device(data.as_mut_ptr() as usize, data.len(), Direction::FromDevice);
+    let mapped_ok = mapped.map_or(false, |m| {
+        let buffer = virtio::Buffer::from(m);
+        dma::sync_for_cpu(&m);
+        Some(m.bus_addr) == dma::bus_address(data.as_ptr() as usize) && buffer.writable && buffer.len == 64
+    });
+    let unmapped = dma::map_for_device(USER_SPACE_START, 16, Direction::ToDevice);
+    let overflow = dma::map_for_device(ring.addr(), usize::MAX, Direction::ToDevice);
+
+    if buffer_ok && part_ok && mapped_ok && unmapped == Err(DriverError::Unsupported)
+        && overflow == Err(DriverError::Unsupported) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: buffer={}, part={}, mapped={}, unmapped={:?}, overflow={:?}",
+                 buffer_ok, part_ok, mapped_ok, unmapped, overflow);
+        TestResult::Fail
+    }
+}
+
+/// 测试设备：绑定的寄存器地址与平台描述一致 (写入会结束QEMU，此处不写)
+fn test_sifive_test() -> TestResult {
+    let Some(region) = platform::get().test_device else {
+        return TestResult::Skip;
+    };
+    let bound = driver::devices().into_iter().find(|b| b.driver == sifive_test::SIFIVE_TEST_DRIVER.name);
+    let addr = sifive_test::register_addr();
+    println!("  finisher at {:x?}, bound: {}", addr, bound.is_some());
+    if sifive_test::is_present() && addr == Some(region.base)
+        && bound.map_or(true, |b| b.device.region() == Ok(MmioRegion { base: region.base, size: region.size })) {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+crate::mmio_registers! {
+    /// 测试用寄存器块
+    struct TestRegs {
+        0x0 => id: ReadOnly<u32>,
+        0x4 => control: ReadWrite<u32>,
+        0x8 => doorbell: WriteOnly<u16>,
+    }
+}
+
+/// 类型化寄存器访问：在普通内存上模拟一个寄存器块
+fn test_mmio_registers() -> TestResult {
+    let mut block = [0x1234_5678u32, 0x10, 0, 0];
+    let regs = unsafe { TestRegs::new(block.as_mut_ptr() as usize) };
+    let id = regs.id().read();
+    regs.control().modify(|v| v | 0x3);
+    let control = regs.control().read();
+    regs.doorbell().write(0xbeef);
+    mmio::wmb();
+    if id == 0x1234_5678 && control == 0x13 && block[2] & 0xffff == 0xbeef && regs.base() == block.as_ptr() as usize {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: id={:#x}, control={:#x}, block={:x?}", id, control, block);
+        TestResult::Fail
+    }
+}
+
+/// 内核随机数生成器：输出互不相同，有界取值不越界
+fn test_kernel_rand() -> TestResult {
+    let mut a = [0u8; 48];
+    let mut b = [0u8; 48];
+    rand::fill_bytes(&mut a);
+    rand::fill_bytes(&mut b);
+    let bounded = (0..100).all(|_| rand::rand_below(7) < 7);
+    let distinct = rand::rand_u64() != rand::rand_u64();
+    if a != b && a != [0u8; 48] && bounded && distinct && rand::rand_below(0) == 0 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: bounded={}, distinct={}", bounded, distinct);
+        TestResult::Fail
+    }
+}
+
+/// virtio熵设备：读取随机字节并为内核随机数生成器提供种子
+fn test_virtio_rng() -> TestResult {
+    let mut bytes = [0u8; 32];
+    let read = virtio::rng::read(&mut bytes);
+    let reseed = virtio::rng::reseed();
+    let source = rand::seed_source();
+    if read == Ok(bytes.len()) && bytes != [0u8; 32] && reseed.is_ok()
+        && source == SeedSource::Device("virtio-rng") {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: read={:?}, reseed={:?}, source={:?}", read, reseed, source);
+        TestResult::Fail
+    }
+}
+
+/// virtio控制台：控制台端口可写，向未连接的普通端口写入会被丢弃
+fn test_virtio_console() -> TestResult {
+    let ports = virtio::console::ports();
+    let Some(id) = virtio::console::console_port() else {
+        println!("  FAIL: no console port among {:?}", ports);
+        return TestResult::Fail;
+    };
+    let written = virtio::console::write(id, b"virtio-console test\n");
+    let missing = virtio::console::write(u32::MAX, b"x");
+    let mut input = [0u8; 16];
+    let read = virtio::console::read(id, &mut input);
+    if written == Ok(20) && missing == Err(DriverError::NotPresent) && read.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: ports={:?}, written={:?}, read={:?}", ports, written, read);
+        TestResult::Fail
+    }
+}
+
+/// 第 `col` 列第 `row` 行的字符格是否以 `fg` 色显示 `c`
+fn cell_shows(picture: &Framebuffer, col: usize, row: usize, c: char, fg: u32) -> bool {
+    let glyph = font::glyph(c);
+    (0..font::HEIGHT).all(|y| {
+        (0..font::WIDTH).all(|x| {
+            let color = if glyph[y] & (1 << x) != 0 { fg } else { fb::BLACK };
+            picture.pixel(col * font::WIDTH + x, row * font::HEIGHT + y) == Some(color)
+        })
+    })
+}
+
+/// 帧缓冲文本控制台：按字模绘制、换行与滚屏，转义序列只改变颜色不绘制
+fn test_fb_console() -> TestResult {
+    // 4列3行
+    let mut console = TextConsole::new(Framebuffer::new(4 * font::WIDTH, 3 * font::HEIGHT));
+    console.take_dirty();
+    console.write_str("\x1b[31mA\x1b[0m");
+    let drawn = cell_shows(console.framebuffer(), 0, 0, 'A', 0xAA_0000);
+    let first_dirty = console.take_dirty();
+    let after_a = console.cursor();
+
+    // 第一行写满后自动换行；写到第三行后再换行，画面上移一行
+    console.write_str("BCDE\nF\nG");
+    let scrolled_cursor = console.cursor();
+    let scrolled = cell_shows(console.framebuffer(), 0, 0, 'E', fb::LIGHT_GRAY);
+    let dirty = console.take_dirty();
+
+    if drawn && first_dirty == Some((0, font::HEIGHT)) && after_a == (1, 0) && scrolled_cursor == (1, 2)
+        && scrolled && dirty == Some((0, 3 * font::HEIGHT)) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: drawn={}, first_dirty={:?}, after_a={:?}, cursor={:?}, dirty={:?}",
+                 drawn, first_dirty, after_a, scrolled_cursor, dirty);
+        TestResult::Fail
+    }
+}
+
+/// virtio-gpu设备的帧缓冲应已显示，并可绘制
+fn test_virtio_gpu() -> TestResult {
+    let resolution = virtio::gpu::resolution();
+    let drawn = virtio::gpu::draw(|fb| {
+        fb.fill_rect(fb.width() - 8, 0, 8, 8, 0x00_AA00);
+        fb.pixel(fb.width() - 1, 0)
+    });
+    if resolution.is_some_and(|(w, h)| w > 0 && h > 0) && drawn == Ok(Some(0x00_AA00)) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: resolution={:?}, drawn={:?}", resolution, drawn);
+        TestResult::Fail
+    }
+}
+
+/// 驱动测试套件
+///
+/// 注册的测试驱动与它探测到的设备保留到解绑为止：探测驱动的测试不做泄漏检查。
+static SUITE: TestSuite = TestSuite::new("Driver", 100);
+
+crate::kernel_test!(SUITE, "fdt_parse", test_fdt_parse, "The device tree parser decodes nodes, reg and status");
+crate::kernel_test!(SUITE, "boot_fdt", test_boot_fdt, "The boot device tree parses and describes the UART");
+crate::kernel_test!(SUITE, "platform", test_platform,
+    "The platform memory map comes from the device tree with fallbacks");
+crate::kernel_test!(SUITE, "cpuinfo", test_cpuinfo, "ISA strings are parsed and every hart's ISA is recorded");
+crate::kernel_test!(SUITE, "driver_probe", test_driver_probe, "Drivers are probed once for enabled compatible nodes",
+    allow_leak);
+crate::kernel_test!(SUITE, "dynamic_probe", test_dynamic_probe,
+    "Devices without a device tree node are matched and probed", allow_leak);
+crate::kernel_test!(SUITE, "irq_binding", test_irq_binding,
+    "Probed devices get their interrupts bound and released on unbind");
+crate::kernel_test!(SUITE, "pci_enumeration", test_pci_enumeration, "PCIe functions are found and their BARs assigned");
+crate::kernel_test!(SUITE, "dma_mapping", test_dma_mapping,
+    "DMA mappings translate buffers to bus addresses and reject unmapped ones");
+crate::kernel_test!(SUITE, "sifive_test", test_sifive_test, "The test finisher is found for exiting QEMU");
+crate::kernel_test!(SUITE, "mmio_registers", test_mmio_registers,
+    "Register blocks give typed volatile access at fixed offsets");
+crate::kernel_test!(SUITE, "kernel_rand", test_kernel_rand, "The kernel RNG produces fresh bounded output");
+crate::kernel_test!(SUITE, "virtio_rng", test_virtio_rng, "The virtio entropy device seeds the kernel RNG",
+    requires [Requirement::Probe("virtio-rng device", virtio::rng::is_present)]);
+crate::kernel_test!(SUITE, "virtio_console", test_virtio_console,
+    "The virtio console accepts output on its console port",
+    requires [Requirement::Probe("virtio-console device", virtio::console::is_present)]);
+crate::kernel_test!(SUITE, "fb_console", test_fb_console, "The framebuffer console draws glyphs, wraps and scrolls");
+crate::kernel_test!(SUITE, "virtio_gpu", test_virtio_gpu, "The virtio GPU framebuffer can be drawn on",
+    requires [Requirement::Probe("virtio-gpu device", virtio::gpu::is_present)]);
@@ -0,0 +1,711 @@
+// 虚拟文件系统测试模块
+
+use super::{TestResult, TestSuite};
+use crate::fs::debugfs::{self, Format, Output, Record};
+use crate::fs::{self, initramfs, path, DirEntry, Ext2Fs, Fat32Fs, FileSystem, FileType, FsError, Inode, Metadata, OpenFlags, RamFs, SeekFrom};
+use crate::block::{self, RamDisk};
+use crate::println;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const HELLO: &[u8] = b"hello, vfs\n";
+
+/// 测试用只读文件系统：节点为静态的 (名称, 内容或子节点) 树
+enum Node {
+    File(u64, &'static [u8]),
+    Dir(u64, &'static [(&'static str, Node)]),
+}
+
+static TREE: Node = Node::Dir(1, &[
+    ("hello", Node::File(2, HELLO)),
+    ("dir", Node::Dir(3, &[("nested", Node::File(4, b"deep"))])),
+]);
+
+struct StaticInode(&'static Node);
+
+impl Inode for StaticInode {
+    fn metadata(&self) -> Metadata {
+        let (ino, file_type, size) = match self.0 {
+            Node::File(ino, data) => (*ino, FileType::Regular, data.len() as u64),
+            Node::Dir(ino, entries) => (*ino, FileType::Directory, entries.len() as u64),
+        };
+        Metadata { ino, file_type, size, nlink: 1, mode: 0o444, mtime: 0 }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        let Node::File(_, data) = self.0 else {
+            return Err(FsError::IsADirectory);
+        };
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, FsError> {
+        let Node::Dir(_, entries) = self.0 else {
+            return Err(FsError::NotADirectory);
+        };
+        let (_, node) = entries.iter().find(|(n, _)| *n == name).ok_or(FsError::NotFound)?;
+        Ok(Arc::new(StaticInode(node)))
+    }
+
+    fn readdir(&self) -> Result<Vec<DirEntry>, FsError> {
+        let Node::Dir(_, entries) = self.0 else {
+            return Err(FsError::NotADirectory);
+        };
+        Ok(entries
+            .iter()
+            .map(|(name, node)| {
+                let meta = StaticInode(node).metadata();
+                DirEntry { name: String::from(*name), ino: meta.ino, file_type: meta.file_type }
+            })
+            .collect())
+    }
+}
+
+struct StaticFs;
+
+impl FileSystem for StaticFs {
+    fn name(&self) -> &str {
+        "staticfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(StaticInode(&TREE))
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// 挂载测试文件系统的位置，位于根ramfs中
+const MOUNT_POINT: &str = "/mnt/vfs-test";
+
+/// 路径规范化：`.`、`..`与重复的`/`
+fn test_fs_path() -> TestResult {
+    let cases: [(&str, Result<&str, FsError>); 5] = [
+        ("/", Ok("/")),
+        ("//a//b/", Ok("/a/b")),
+        ("/a/./b/../c", Ok("/a/c")),
+        ("/../..", Ok("/")),
+        ("relative", Err(FsError::InvalidPath)),
+    ];
+    let mut ok = true;
+    for (input, expected) in cases {
+        let got = path::normalize(input);
+        if got != expected.map(String::from) {
+            println!("  FAIL: normalize({:?}) = {:?}", input, got);
+            ok = false;
+        }
+    }
+    let long = vec![b'x'; path::NAME_MAX + 1];
+    let long = core::str::from_utf8(&long).unwrap_or("");
+    ok &= path::normalize(&alloc::format!("/{}", long)) == Err(FsError::NameTooLong);
+    ok &= path::split("/a/b") == Ok((String::from("/a"), String::from("b")));
+    ok &= path::split("/") == Err(FsError::InvalidPath);
+    ok &= path::is_under("/a/b", "/a") && !path::is_under("/ab", "/a") && path::is_under("/x", "/");
+    if ok { TestResult::Pass } else { TestResult::Fail }
+}
+
+/// 挂载后解析路径、读取文件与目录；错误路径与只读挂载报错
+fn test_fs_mount_resolve() -> TestResult {
+    let root = MOUNT_POINT;
+    if fs::mkdir(root).is_err() || fs::mount(root, Arc::new(StaticFs)).is_err() {
+        println!("  FAIL: mount on {} failed", root);
+        return TestResult::Fail;
+    }
+    let at = |p: &str| if root == "/" { String::from(p) } else { alloc::format!("{}{}", root, p) };
+
+    let names: Vec<String> = fs::readdir(&at("/")).map(|e| e.into_iter().map(|d| d.name).collect()).unwrap_or_default();
+    let nested = fs::stat(&at("/dir/./nested")).map(|m| (m.ino, m.size));
+    let mut buf = [0u8; 32];
+    let read = fs::open(&at("/hello"), OpenFlags::READ).and_then(|f| {
+        let n = f.read(&mut buf)?;
+        let end = f.read(&mut buf[n..])?;
+        f.seek(SeekFrom::Start(7))?;
+        let tail = f.read(&mut buf[20..])?;
+        Ok((n, end, tail))
+    });
+    let whole = fs::read_file(&at("/dir/../hello"));
+    let missing = fs::stat(&at("/nope")).err();
+    let through_file = fs::stat(&at("/hello/x")).err();
+    let read_dir = fs::open(&at("/dir"), OpenFlags::READ).and_then(|f| f.read(&mut buf)).err();
+    let write_ro = fs::open(&at("/hello"), OpenFlags::WRITE).err();
+    let create_ro = fs::mkdir(&at("/new")).err();
+    let no_write = fs::open(&at("/hello"), OpenFlags::READ).and_then(|f| f.write(b"x")).err();
+    let again = fs::mount(root, Arc::new(StaticFs)).err();
+
+    // 打开的文件使挂载保持忙碌
+    let held = fs::open(&at("/hello"), OpenFlags::READ);
+    let busy = fs::unmount(root).err();
+    let unlink_busy = fs::unlink(root).err();
+    drop(held);
+    let unmounted = fs::unmount(root);
+    let removed = fs::unlink(root);
+
+    if names == ["dir", "hello"] && nested == Ok((4, 4)) && read == Ok((HELLO.len(), 0, 4))
+        && &buf[20..24] == b"vfs\n" && whole.as_deref() == Ok(HELLO)
+        && missing == Some(FsError::NotFound) && through_file == Some(FsError::NotADirectory)
+        && read_dir == Some(FsError::IsADirectory) && write_ro == Some(FsError::ReadOnly)
+        && create_ro == Some(FsError::ReadOnly) && no_write == Some(FsError::NotPermitted)
+        && again == Some(FsError::Busy) && busy == Some(FsError::Busy) && unlink_busy == Some(FsError::Busy)
+        && unmounted.is_ok() && removed.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: names={:?}, nested={:?}, read={:?}, missing={:?}, through={:?}, dir={:?}",
+                 names, nested, read, missing, through_file, read_dir);
+        println!("        write={:?}, create={:?}, no_write={:?}, again={:?}, busy={:?}/{:?}, unmount={:?}/{:?}",
+                 write_ro, create_ro, no_write, again, busy, unlink_busy, unmounted, removed);
+        TestResult::Fail
+    }
+}
+
+/// 根ramfs：创建、写入、追加、截断与读回文件
+fn test_ramfs_files() -> TestResult {
+    let dir = "/tmp/ramfs-files";
+    if fs::mkdir(dir).is_err() {
+        return TestResult::Fail;
+    }
+    let written = fs::write_file("/tmp/ramfs-files/a", b"hello");
+    let appended = fs::open("/tmp/ramfs-files/a", OpenFlags::WRITE | OpenFlags::APPEND).and_then(|f| f.write(b", world"));
+    let content = fs::read_file("/tmp/ramfs-files/a");
+    let exclusive = fs::open("/tmp/ramfs-files/a", OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE).err();
+    // 越过文件末尾写入，中间以零填充
+    let sparse = fs::open("/tmp/ramfs-files/b", OpenFlags::READ_WRITE | OpenFlags::CREATE).and_then(|f| {
+        f.seek(SeekFrom::Start(4))?;
+        f.write(b"x")?;
+        f.seek(SeekFrom::Start(0))?;
+        let mut buf = [0xFFu8; 8];
+        let n = f.read(&mut buf)?;
+        Ok((n, buf))
+    });
+    let truncated = fs::write_file("/tmp/ramfs-files/a", b"hi").and_then(|_| fs::read_file("/tmp/ramfs-files/a"));
+    let listing: Vec<(String, FileType)> = fs::readdir(dir)
+        .map(|e| e.into_iter().map(|d| (d.name, d.file_type)).collect())
+        .unwrap_or_default();
+    let dir_stat = fs::stat(dir).map(|m| (m.file_type, m.size));
+    let write_dir = fs::open(dir, OpenFlags::WRITE).err();
+    let cleanup = fs::unlink("/tmp/ramfs-files/a").and(fs::unlink("/tmp/ramfs-files/b")).and(fs::unlink(dir));
+
+    if written.is_ok() && appended == Ok(7) && content.as_deref() == Ok(&b"hello, world"[..])
+        && exclusive == Some(FsError::AlreadyExists) && sparse == Ok((5, [0, 0, 0, 0, b'x', 0xFF, 0xFF, 0xFF]))
+        && truncated.as_deref() == Ok(&b"hi"[..])
+        && listing == [(String::from("a"), FileType::Regular), (String::from("b"), FileType::Regular)]
+        && dir_stat == Ok((FileType::Directory, 2)) && write_dir == Some(FsError::IsADirectory) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: written={:?}, appended={:?}, content={:?}, excl={:?}, sparse={:?}",
+                 written, appended, content, exclusive, sparse);
+        println!("        truncated={:?}, listing={:?}, stat={:?}, cleanup={:?}", truncated, listing, dir_stat, cleanup);
+        TestResult::Fail
+    }
+}
+
+/// 目录操作：非空目录不能删除，重命名替换文件并在目录间移动
+fn test_ramfs_namespace() -> TestResult {
+    let setup = fs::mkdir("/tmp/ns")
+        .and(fs::mkdir("/tmp/ns/a"))
+        .and(fs::mkdir("/tmp/ns/b"))
+        .and(fs::write_file("/tmp/ns/a/f", b"first"))
+        .and(fs::write_file("/tmp/ns/a/g", b"second"));
+    let not_empty = fs::unlink("/tmp/ns/a").err();
+    let exists = fs::mkdir("/tmp/ns/a").err();
+    // 同目录重命名替换已有文件
+    let replaced = fs::rename("/tmp/ns/a/f", "/tmp/ns/a/g").and_then(|_| fs::read_file("/tmp/ns/a/g"));
+    let old_gone = fs::stat("/tmp/ns/a/f").err();
+    // 目录移动到另一目录下，其中的文件随之移动
+    let moved = fs::rename("/tmp/ns/a", "/tmp/ns/b/a").and_then(|_| fs::read_file("/tmp/ns/b/a/g"));
+    let into_self = fs::rename("/tmp/ns/b", "/tmp/ns/b/a/x").err();
+    let dir_over_file = fs::write_file("/tmp/ns/file", b"").and(fs::rename("/tmp/ns/b", "/tmp/ns/file")).err();
+    let nlink = fs::stat("/tmp/ns").map(|m| m.nlink);
+    let cleanup = fs::unlink("/tmp/ns/b/a/g")
+        .and(fs::unlink("/tmp/ns/b/a"))
+        .and(fs::unlink("/tmp/ns/b"))
+        .and(fs::unlink("/tmp/ns/file"))
+        .and(fs::unlink("/tmp/ns"));
+
+    if setup.is_ok() && not_empty == Some(FsError::NotEmpty) && exists == Some(FsError::AlreadyExists)
+        && replaced.as_deref() == Ok(&b"first"[..]) && old_gone == Some(FsError::NotFound)
+        && moved.as_deref() == Ok(&b"first"[..]) && into_self == Some(FsError::InvalidArgument)
+        && dir_over_file == Some(FsError::NotADirectory) && nlink == Ok(3) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: setup={:?}, not_empty={:?}, exists={:?}, replaced={:?}, old={:?}, moved={:?}",
+                 setup, not_empty, exists, replaced, old_gone, moved);
+        println!("        into_self={:?}, dir_over_file={:?}, nlink={:?}, cleanup={:?}",
+                 into_self, dir_over_file, nlink, cleanup);
+        TestResult::Fail
+    }
+}
+
+/// 容量限制与跨文件系统重命名
+fn test_ramfs_capacity() -> TestResult {
+    let small = Arc::new(RamFs::new(64));
+    if fs::mkdir("/tmp/small").and(fs::mount("/tmp/small", small.clone())).is_err() {
+        return TestResult::Fail;
+    }
+    let fits = fs::write_file("/tmp/small/a", &[1u8; 48]);
+    let full = fs::write_file("/tmp/small/b", &[2u8; 32]).err();
+    let used = small.used();
+    let cross = fs::rename("/tmp/small/a", "/tmp/a").err();
+    // 删除文件后空间被释放
+    let freed = fs::unlink("/tmp/small/a").map(|_| small.used());
+    let cleanup = fs::unlink("/tmp/small/b").and(fs::unmount("/tmp/small")).and(fs::unlink("/tmp/small"));
+
+    if fits.is_ok() && full == Some(FsError::NoSpace) && used == 48 && cross == Some(FsError::CrossDevice)
+        && freed == Ok(0) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: fits={:?}, full={:?}, used={}, cross={:?}, freed={:?}, cleanup={:?}",
+                 fits, full, used, cross, freed, cleanup);
+        TestResult::Fail
+    }
+}
+
+/// 向newc格式的cpio归档追加一个成员
+fn cpio_member(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let header = alloc::format!(
+        "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+        0, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, name.len() + 1, 0
+    );
+    archive.extend_from_slice(header.as_bytes());
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(4), 0);
+}
+
+/// 解析cpio归档并解包到ramfs，缺失的父目录被自动创建
+fn test_initramfs_unpack() -> TestResult {
+    let mut archive = Vec::new();
+    cpio_member(&mut archive, ".", 0o040755, b"");
+    cpio_member(&mut archive, "./bin", 0o040755, b"");
+    cpio_member(&mut archive, "./bin/init", 0o100755, b"\x7fELF-not-really");
+    cpio_member(&mut archive, "etc/motd", 0o100644, b"welcome");
+    cpio_member(&mut archive, "etc/link", 0o120777, b"motd");
+    cpio_member(&mut archive, "TRAILER!!!", 0, b"");
+    // 归档末尾的填充不属于任何成员
+    archive.extend_from_slice(&[0u8; 16]);
+
+    let names: Vec<&str> = initramfs::Archive::new(&archive).filter_map(Result::ok).map(|e| e.name).collect();
+    let stats = fs::mkdir("/tmp/initramfs").and_then(|_| initramfs::unpack(&archive, "/tmp/initramfs"));
+    let init = fs::read_file("/tmp/initramfs/bin/init");
+    let motd = fs::read_file("/tmp/initramfs/etc/motd");
+    let link = fs::stat("/tmp/initramfs/etc/link").err();
+
+    // 截断的归档与错误的魔数报告为损坏
+    let truncated = initramfs::unpack(&archive[..archive.len() / 2], "/tmp/initramfs").err();
+    let mut bad_magic = archive.clone();
+    bad_magic[5] = b'9';
+    let bad = initramfs::Archive::new(&bad_magic).next();
+
+    let cleanup = fs::unlink("/tmp/initramfs/bin/init")
+        .and(fs::unlink("/tmp/initramfs/bin"))
+        .and(fs::unlink("/tmp/initramfs/etc/motd"))
+        .and(fs::unlink("/tmp/initramfs/etc"))
+        .and(fs::unlink("/tmp/initramfs"));
+
+    let expected = initramfs::UnpackStats { dirs: 2, files: 2, bytes: 22, skipped: 1 };
+    if names == [".", "./bin", "./bin/init", "etc/motd", "etc/link"] && stats == Ok(expected)
+        && init.as_deref() == Ok(&b"\x7fELF-not-really"[..]) && motd.as_deref() == Ok(&b"welcome"[..])
+        && link == Some(FsError::NotFound) && truncated == Some(FsError::Corrupted)
+        && matches!(bad, Some(Err(FsError::Corrupted))) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: names={:?}, stats={:?}, init={:?}, motd={:?}, link={:?}, truncated={:?}, cleanup={:?}",
+                 names, stats, init, motd, link, truncated, cleanup);
+        TestResult::Fail
+    }
+}
+
+const FAT_MOUNT: &str = "/mnt/fat-test";
+const FAT_SECTOR: usize = 512;
+/// 保留扇区之后是单扇区的FAT，然后是数据区 (每簇一个扇区)
+const FAT_RESERVED: usize = 4;
+const FAT_DATA: usize = FAT_RESERVED + 1;
+const FAT_CLUSTERS: usize = 16;
+/// 2024-01-02 03:04:06 的FAT日期与时间
+const FAT_DATE: u16 = (44 << 9) | (1 << 5) | 2;
+const FAT_TIME: u16 = (3 << 11) | (4 << 5) | 3;
+
+/// 返回簇`cluster`的数据
+fn fat_cluster(image: &mut [u8], cluster: usize) -> &mut [u8] {
+    let start = (FAT_DATA + cluster - 2) * FAT_SECTOR;
+    &mut image[start..start + FAT_SECTOR]
+}
+
+/// 写入目录的第`index`项 (8.3格式)
+fn fat_dirent(dir: &mut [u8], index: usize, name: &[u8; 11], attr: u8, case: u8, cluster: u32, size: u32) {
+    let entry = &mut dir[index * 32..(index + 1) * 32];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[12] = case;
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[22..24].copy_from_slice(&FAT_TIME.to_le_bytes());
+    entry[24..26].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+/// 从第`index`项起写入`short`的长文件名项 (最后一段在前)，返回其后的项号
+fn fat_long_name(dir: &mut [u8], index: usize, name: &str, short: &[u8; 11]) -> usize {
+    const OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let pieces = units.len().div_ceil(13);
+    let checksum = short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+    for piece in 0..pieces {
+        let sequence = pieces - piece;
+        let entry = &mut dir[(index + piece) * 32..(index + piece + 1) * 32];
+        entry[0] = sequence as u8 | if piece == 0 { 0x40 } else { 0 };
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+        for (i, &offset) in OFFSETS.iter().enumerate() {
+            // 名称以0结尾，其余位置填充0xFFFF
+            let pos = (sequence - 1) * 13 + i;
+            let unit = units.get(pos).copied().unwrap_or(if pos == units.len() { 0 } else { 0xFFFF });
+            entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    index + pieces
+}
+
+/// 构造一个小的FAT32映像：长文件名、不连续的簇链、子目录和一个成环的簇链
+fn fat32_image() -> Vec<u8> {
+    let mut image = vec![0u8; (FAT_DATA + FAT_CLUSTERS) * FAT_SECTOR];
+    let boot = &mut image[..FAT_SECTOR];
+    boot[..11].copy_from_slice(b"\xEB\x58\x90MSWIN4.1");
+    boot[11..13].copy_from_slice(&(FAT_SECTOR as u16).to_le_bytes());
+    boot[13] = 1;
+    boot[14..16].copy_from_slice(&(FAT_RESERVED as u16).to_le_bytes());
+    boot[16] = 1;
+    boot[21] = 0xF8;
+    boot[32..36].copy_from_slice(&((FAT_DATA + FAT_CLUSTERS) as u32).to_le_bytes());
+    boot[36..40].copy_from_slice(&1u32.to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[66] = 0x29;
+    boot[71..82].copy_from_slice(b"NTOS TEST  ");
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // 簇3接簇5，簇8指向自身
+    let fat: [u32; 9] = [0x0FFF_FFF8, 0x0FFF_FFFF, 0x0FFF_FFFF, 5, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 0x0FFF_FFFF, 8];
+    for (i, entry) in fat.iter().enumerate() {
+        let start = FAT_RESERVED * FAT_SECTOR + i * 4;
+        image[start..start + 4].copy_from_slice(&entry.to_le_bytes());
+    }
+
+    let root = fat_cluster(&mut image, 2);
+    fat_dirent(root, 0, b"NTOS TEST  ", 0x08, 0, 0, 0);
+    let next = fat_long_name(root, 1, "A long file name.txt", b"ALONGF~1TXT");
+    fat_dirent(root, next, b"ALONGF~1TXT", 0x20, 0, 3, 600);
+    fat_dirent(root, next + 1, b"\xE5ELETED TXT", 0x20, 0, 9, 1);
+    fat_dirent(root, next + 2, b"README  TXT", 0x20, 0x18, 4, 11);
+    fat_dirent(root, next + 3, b"SUBDIR     ", 0x10, 0, 6, 0);
+    fat_dirent(root, next + 4, b"LOOP    BIN", 0x20, 0, 8, 1024);
+    let subdir = fat_cluster(&mut image, 6);
+    fat_dirent(subdir, 0, b".          ", 0x10, 0, 6, 0);
+    fat_dirent(subdir, 1, b"..         ", 0x10, 0, 0, 0);
+    fat_dirent(subdir, 2, b"NESTED  BIN", 0x20, 0, 7, 3);
+
+    let long: Vec<u8> = (0..600).map(|i| (i % 251) as u8).collect();
+    fat_cluster(&mut image, 3).copy_from_slice(&long[..FAT_SECTOR]);
+    fat_cluster(&mut image, 5)[..600 - FAT_SECTOR].copy_from_slice(&long[FAT_SECTOR..]);
+    fat_cluster(&mut image, 4)[..11].copy_from_slice(b"hello, fat\n");
+    fat_cluster(&mut image, 7)[..3].copy_from_slice(b"abc");
+    image
+}
+
+/// 挂载FAT32映像，列目录并按长文件名和8.3别名读取文件
+fn test_fat32_read() -> TestResult {
+    let disk = match block::register(Arc::new(RamDisk::from_image("test-fat", FAT_SECTOR, fat32_image(), true))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    let not_fat = block::register(Arc::new(RamDisk::new("test-nofat", FAT_SECTOR, 4)))
+        .map(|d| Fat32Fs::new(d).err());
+    let fat = match Fat32Fs::new(disk) {
+        Ok(fat) => fat,
+        Err(e) => {
+            println!("  FAIL: probe: {}", e);
+            let _ = block::unregister("test-fat").and(block::unregister("test-nofat"));
+            return TestResult::Fail;
+        }
+    };
+    let label = String::from(fat.label());
+    let mounted = fs::mkdir(FAT_MOUNT).and(fs::mount(FAT_MOUNT, Arc::new(fat)));
+
+    let names: Option<Vec<String>> = fs::readdir(FAT_MOUNT).ok().map(|e| e.into_iter().map(|e| e.name).collect());
+    let long = fs::read_file("/mnt/fat-test/a LONG file name.TXT");
+    let expected: Vec<u8> = (0..600).map(|i| (i % 251) as u8).collect();
+    // 8.3别名同样可以查找；时间戳换算为Unix时间
+    let alias = fs::stat("/mnt/fat-test/alongf~1.txt").map(|m| (m.size, m.mtime));
+    let readme = fs::read_file("/mnt/fat-test/readme.txt");
+    let nested = fs::read_file("/mnt/fat-test/SUBDIR/nested.bin");
+    let looped = fs::read_file("/mnt/fat-test/loop.bin").err();
+    let read_only = fs::write_file("/mnt/fat-test/new.txt", b"x").err();
+
+    let cleanup = fs::unmount(FAT_MOUNT)
+        .and(fs::unlink(FAT_MOUNT))
+        .and(block::unregister("test-fat").map_err(FsError::from))
+        .and(block::unregister("test-nofat").map_err(FsError::from));
+
+    let expected_names = ["A long file name.txt", "LOOP.BIN", "SUBDIR", "readme.txt"];
+    if not_fat == Ok(Some(FsError::NotSupported)) && label == "NTOS TEST" && mounted.is_ok()
+        && names.as_deref().is_some_and(|n| n == expected_names) && long.as_ref() == Ok(&expected)
+        && alias == Ok((600, 1_704_164_646)) && readme.as_deref() == Ok(&b"hello, fat\n"[..])
+        && nested.as_deref() == Ok(&b"abc"[..]) && looped == Some(FsError::Corrupted)
+        && read_only == Some(FsError::ReadOnly) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: not_fat={:?}, label={:?}, mounted={:?}, names={:?}, long={:?}, alias={:?}, readme={:?}",
+                 not_fat, label, mounted, names, long.map(|d| d.len()), alias, readme);
+        println!("        nested={:?}, looped={:?}, read_only={:?}, cleanup={:?}", nested, looped, read_only, cleanup);
+        TestResult::Fail
+    }
+}
+
+const EXT2_MOUNT: &str = "/mnt/ext2-test";
+const EXT2_BLOCK: usize = 1024;
+const EXT2_BLOCKS: usize = 24;
+/// 块1为超级块，块2为组描述符表，块5起为inode表
+const EXT2_INODE_TABLE: usize = 5;
+/// 大文件：12个直接块，一级间接块中第一项指向块23，第二项为空洞
+const EXT2_BIG_SIZE: usize = 13 * EXT2_BLOCK + 100;
+
+fn put16(image: &mut [u8], offset: usize, value: u16) {
+    image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// 写入inode `ino`：类型与权限、大小、修改时间和块指针
+fn ext2_inode(image: &mut [u8], ino: usize, mode: u16, size: u32, mtime: u32, blocks: &[u32]) {
+    let base = EXT2_INODE_TABLE * EXT2_BLOCK + (ino - 1) * 128;
+    put16(image, base, mode);
+    put32(image, base + 4, size);
+    put32(image, base + 16, mtime);
+    put16(image, base + 26, if mode & 0o040000 != 0 { 2 } else { 1 });
+    put32(image, base + 28, (blocks.iter().filter(|&&b| b != 0).count() * 2) as u32);
+    for (i, &block) in blocks.iter().enumerate() {
+        put32(image, base + 40 + i * 4, block);
+    }
+}
+
+/// 向目录块写入各项 (inode, 类型, 名称)，最后一项延伸到块尾
+fn ext2_dir(image: &mut [u8], block: usize, entries: &[(u32, u8, &str)]) {
+    let mut pos = block * EXT2_BLOCK;
+    for (i, &(ino, file_type, name)) in entries.iter().enumerate() {
+        let rec_len = if i + 1 == entries.len() {
+            (block + 1) * EXT2_BLOCK - pos
+        } else {
+            (8 + name.len()).next_multiple_of(4)
+        };
+        put32(image, pos, ino);
+        put16(image, pos + 4, rec_len as u16);
+        image[pos + 6] = name.len() as u8;
+        image[pos + 7] = file_type;
+        image[pos + 8..pos + 8 + name.len()].copy_from_slice(name.as_bytes());
+        pos += rec_len;
+    }
+}
+
+/// 大文件第`i`个字节的内容 (空洞处为0)
+fn ext2_big_byte(i: usize) -> u8 {
+    if i >= 13 * EXT2_BLOCK { 0 } else { (i % 251) as u8 }
+}
+
+/// 构造一个1 KB块的ext2映像：子目录、间接块与空洞、内联符号链接和一个已删除的目录项
+fn ext2_image() -> Vec<u8> {
+    let mut image = vec![0u8; EXT2_BLOCKS * EXT2_BLOCK];
+    let sb = EXT2_BLOCK;
+    put32(&mut image, sb, 16);
+    put32(&mut image, sb + 4, EXT2_BLOCKS as u32);
+    put32(&mut image, sb + 20, 1);
+    put32(&mut image, sb + 32, 8192);
+    put32(&mut image, sb + 40, 16);
+    put16(&mut image, sb + 56, 0xEF53);
+    put16(&mut image, sb + 58, 1);
+    put32(&mut image, sb + 76, 1);
+    put32(&mut image, sb + 84, 11);
+    put16(&mut image, sb + 88, 128);
+    put32(&mut image, sb + 96, 0x0002);
+    image[sb + 120..sb + 129].copy_from_slice(b"ntos-ext2");
+    put32(&mut image, 2 * EXT2_BLOCK, 3);
+    put32(&mut image, 2 * EXT2_BLOCK + 4, 4);
+    put32(&mut image, 2 * EXT2_BLOCK + 8, EXT2_INODE_TABLE as u32);
+
+    let big_blocks: Vec<u32> = (10..22).chain([22]).collect();
+    ext2_inode(&mut image, 2, 0o040755, EXT2_BLOCK as u32, 0, &[7]);
+    ext2_inode(&mut image, 11, 0o100644, 11, 1_700_000_000, &[9]);
+    ext2_inode(&mut image, 12, 0o040755, EXT2_BLOCK as u32, 0, &[8]);
+    ext2_inode(&mut image, 13, 0o100600, EXT2_BIG_SIZE as u32, 0, &big_blocks);
+    ext2_inode(&mut image, 15, 0o100644, 0, 0, &[]);
+    // 内联符号链接：目标直接存放在块指针中
+    ext2_inode(&mut image, 14, 0o120777, 9, 0, &[]);
+    let link = 5 * EXT2_BLOCK + 13 * 128 + 40;
+    image[link..link + 9].copy_from_slice(b"hello.txt");
+    put32(&mut image, 22 * EXT2_BLOCK, 23);
+
+    ext2_dir(&mut image, 7, &[
+        (2, 2, "."), (2, 2, ".."), (11, 1, "hello.txt"), (0, 1, "gone"),
+        (12, 2, "docs"), (13, 1, "big.bin"), (14, 7, "link"),
+    ]);
+    ext2_dir(&mut image, 8, &[(12, 2, "."), (2, 2, ".."), (15, 1, "readme")]);
+    image[9 * EXT2_BLOCK..9 * EXT2_BLOCK + 11].copy_from_slice(b"hello, ext\n");
+    for i in 0..13 * EXT2_BLOCK {
+        let block = if i < 12 * EXT2_BLOCK { 10 + i / EXT2_BLOCK } else { 23 };
+        image[block * EXT2_BLOCK + i % EXT2_BLOCK] = ext2_big_byte(i);
+    }
+    image
+}
+
+/// 挂载ext2映像，遍历目录并读取经间接块映射的文件
+fn test_ext2_read() -> TestResult {
+    let image = ext2_image();
+    // 使用extents的卷不受支持
+    let mut extents = image.clone();
+    put32(&mut extents, EXT2_BLOCK + 96, 0x0042);
+    let unsupported = block::register(Arc::new(RamDisk::from_image("test-ext4", EXT2_BLOCK, extents, true)))
+        .map(|d| Ext2Fs::new(d).err());
+    let disk = match block::register(Arc::new(RamDisk::from_image("test-ext2", 512, image, true))) {
+        Ok(disk) => disk,
+        Err(_) => return TestResult::Fail,
+    };
+    // 自动探测应识别为ext2
+    let probed = fs::probe(&disk).map(|f| String::from(f.name()));
+    let ext2 = match Ext2Fs::new(disk) {
+        Ok(ext2) => ext2,
+        Err(e) => {
+            println!("  FAIL: probe: {}", e);
+            let _ = block::unregister("test-ext2").and(block::unregister("test-ext4"));
+            return TestResult::Fail;
+        }
+    };
+    let label = String::from(ext2.label());
+    let mounted = fs::mkdir(EXT2_MOUNT).and(fs::mount(EXT2_MOUNT, Arc::new(ext2)));
+
+    let names: Option<Vec<String>> = fs::readdir(EXT2_MOUNT).ok().map(|e| e.into_iter().map(|e| e.name).collect());
+    let hello = fs::read_file("/mnt/ext2-test/hello.txt");
+    let hello_stat = fs::stat("/mnt/ext2-test/hello.txt").map(|m| (m.mode, m.mtime));
+    let big = fs::read_file("/mnt/ext2-test/big.bin");
+    let expected: Vec<u8> = (0..EXT2_BIG_SIZE).map(ext2_big_byte).collect();
+    let link = fs::stat("/mnt/ext2-test/link").map(|m| m.file_type);
+    let target = fs::read_file("/mnt/ext2-test/link");
+    let readme = fs::read_file("/mnt/ext2-test/docs/readme");
+    let gone = fs::stat("/mnt/ext2-test/gone").err();
+    let read_only = fs::mkdir("/mnt/ext2-test/new").err();
+
+    let cleanup = fs::unmount(EXT2_MOUNT)
+        .and(fs::unlink(EXT2_MOUNT))
+        .and(block::unregister("test-ext2").map_err(FsError::from))
+        .and(block::unregister("test-ext4").map_err(FsError::from));
+
+    if unsupported == Ok(Some(FsError::NotSupported)) && probed.as_deref() == Ok("ext2") && label == "ntos-ext2"
+        && mounted.is_ok() && names.as_deref().is_some_and(|n| n == ["big.bin", "docs", "hello.txt", "link"])
+        && hello.as_deref() == Ok(&b"hello, ext\n"[..]) && hello_stat == Ok((0o644, 1_700_000_000))
+        && big.as_ref() == Ok(&expected) && link == Ok(FileType::Symlink) && target.as_deref() == Ok(&b"hello.txt"[..])
+        && readme.as_deref() == Ok(&b""[..]) && gone == Some(FsError::NotFound)
+        && read_only == Some(FsError::ReadOnly) && cleanup.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: unsupported={:?}, probed={:?}, label={:?}, mounted={:?}, names={:?}, hello={:?}, stat={:?}",
+                 unsupported, probed, label, mounted, names, hello, hello_stat);
+        println!("        big={:?}, link={:?}, target={:?}, readme={:?}, gone={:?}, read_only={:?}, cleanup={:?}",
+                 big.map(|d| d.len()), link, target, readme, gone, read_only, cleanup);
+        TestResult::Fail
+    }
+}
+
+fn render_test_single(out: &mut Output) {
+    out.push(Record::new().field("count", 3u64).field("name", "a\"b"));
+}
+
+fn render_test_table(out: &mut Output) {
+    out.push(Record::new().field("id", 1u64).field("state", "ready"));
+    out.push(Record::new().field("id", 22u64).field("state", "idle"));
+}
+
+/// debugfs：注册的文件在/debug下可读，目录由路径隐含，按文本、JSON Lines与key=value输出，只读
+fn test_debugfs_files() -> TestResult {
+    let registered = debugfs::register("/test/dbg/single", render_test_single)
+        .and(debugfs::register("/test/dbg/table", render_test_table));
+    let duplicate = debugfs::register("/test/dbg/single", render_test_single).err();
+    let over_file = debugfs::register("/test/dbg/single/x", render_test_single).err();
+    let single = fs::read_file("/debug/test/dbg/single");
+    let table = fs::read_file("/debug/test/dbg/table");
+    let json = debugfs::render("/test/dbg/single", Format::Json);
+    let key_value = debugfs::render("/test/dbg/table", Format::KeyValue);
+    let size = fs::stat("/debug/test/dbg/table").map(|m| m.size);
+    let listing: Vec<(String, FileType)> = fs::readdir("/debug/test/dbg")
+        .map(|e| e.into_iter().map(|d| (d.name, d.file_type)).collect())
+        .unwrap_or_default();
+    let dir = debugfs::render("/test/dbg", Format::Text).err();
+    let read_only = fs::write_file("/debug/test/dbg/single", b"x").err();
+    let core = fs::read_file("/debug/alloc/stats").map(|data| data.starts_with(b"total:"));
+    let removed = debugfs::unregister("/test/dbg/single").and(debugfs::unregister("/test/dbg/table"));
+    let gone = fs::stat("/debug/test").err();
+
+    if registered.is_ok() && duplicate == Some(FsError::AlreadyExists) && over_file == Some(FsError::AlreadyExists)
+        && single.as_deref() == Ok(&b"count:  3\nname:   a\"b\n"[..])
+        && table.as_deref() == Ok(&b"id  state\n1   ready\n22  idle\n"[..])
+        && json.as_deref() == Ok("{\"count\":3,\"name\":\"a\\\"b\"}\n")
+        && key_value.as_deref() == Ok("id=1 state=ready\nid=22 state=idle\n")
+        && size == Ok(29)
+        && listing == [(String::from("single"), FileType::Regular), (String::from("table"), FileType::Regular)]
+        && dir == Some(FsError::IsADirectory) && read_only == Some(FsError::ReadOnly) && core == Ok(true)
+        && removed.is_ok() && gone == Some(FsError::NotFound) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: registered={:?}, duplicate={:?}, over_file={:?}, single={:?}, table={:?}",
+                 registered, duplicate, over_file, single.map(String::from_utf8), table.map(String::from_utf8));
+        println!("        json={:?}, key_value={:?}, size={:?}, listing={:?}, dir={:?}, read_only={:?}, core={:?}",
+                 json, key_value, size, listing, dir, read_only, core);
+        println!("        removed={:?}, gone={:?}", removed, gone);
+        TestResult::Fail
+    }
+}
+
+/// setup：挂载后卸载一次测试文件系统，在/tmp与debugfs中各建后删一项，并读一个内核的debugfs文件，
+/// 建立挂载表、/tmp与debugfs的目录表和测试查找的父目录的目录项缓存。这些在测试后保留，
+/// 单独运行的测试 (`test=`) 也不会因首次使用它们而被判为泄漏。
+fn prepare_tables() -> bool {
+    let mounted = fs::mkdir(MOUNT_POINT).is_ok() && fs::mount(MOUNT_POINT, Arc::new(StaticFs)).is_ok();
+    let resolved = fs::stat(&alloc::format!("{}/dir/nested", MOUNT_POINT)).is_ok();
+    let unmounted = fs::unmount(MOUNT_POINT).is_ok() && fs::unlink(MOUNT_POINT).is_ok();
+    let tmp = fs::mkdir("/tmp/fs-setup").is_ok()
+        && fs::write_file("/tmp/fs-setup/a", b"x").is_ok()
+        && fs::unlink("/tmp/fs-setup/a").is_ok()
+        && fs::unlink("/tmp/fs-setup").is_ok();
+    let debug = debugfs::register("/test/setup/single", render_test_single).is_ok()
+        && fs::read_file("/debug/test/setup/single").is_ok()
+        && debugfs::unregister("/test/setup/single").is_ok()
+        && fs::read_file("/debug/alloc/stats").is_ok();
+    mounted && resolved && unmounted && tmp && debug
+}
+
+/// 文件系统测试套件；挂载表、目录表与目录项缓存由setup预先建立
+static SUITE: TestSuite = TestSuite { setup: Some(prepare_tables), ..TestSuite::new("Filesystem", 120) };
+
+crate::kernel_test!(SUITE, "fs_path", test_fs_path, "Paths normalize lexically and split into parent and name");
+crate::kernel_test!(SUITE, "fs_mount_resolve", test_fs_mount_resolve,
+    "Paths resolve across a mount and files read through the VFS");
+crate::kernel_test!(SUITE, "ramfs_files", test_ramfs_files,
+    "Root ramfs files are created, written, appended and truncated");
+crate::kernel_test!(SUITE, "ramfs_namespace", test_ramfs_namespace,
+    "Non-empty directories stay and renames replace and move entries");
+crate::kernel_test!(SUITE, "ramfs_capacity", test_ramfs_capacity,
+    "A full ramfs refuses writes and renames stay on one filesystem");
+crate::kernel_test!(SUITE, "initramfs_unpack", test_initramfs_unpack, "A newc cpio archive unpacks into ramfs");
+crate::kernel_test!(SUITE, "fat32_read", test_fat32_read,
+    "A FAT32 image mounts read-only with long names and cluster chains");
+crate::kernel_test!(SUITE, "ext2_read", test_ext2_read,
+    "An ext2 image mounts read-only with indirect blocks, holes and symlinks");
+crate::kernel_test!(SUITE, "debugfs_files", test_debugfs_files,
+    "Registered debugfs files read under /debug as text, render as JSON Lines or key=value and stay read-only");
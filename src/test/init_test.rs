@@ -0,0 +1,292 @@
+// 初始化框架测试模块
+
+use super::{TestFilter, TestResult, TestSuite};
+use crate::boot::timing::{self, Phase};
+use crate::cmdline;
+use crate::coverage;
+use crate::crashdump;
+use crate::init::initcall::{self, Level, State};
+use crate::fs::debugfs::Format;
+use crate::panic::{self, PanicPolicy};
+use crate::report::{self, ReportError, Section};
+use crate::sysctl::{self, SysctlError, Value};
+use crate::trap::{self, TrapApiError, TrapContext};
+use crate::println;
+use crate::test;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+/// 测试initcall注册：链接器按级别排序，启动时全部运行
+fn test_initcall_order() -> TestResult {
+    let calls = initcall::initcalls();
+    let sorted = calls.windows(2).all(|pair| pair[0].level() <= pair[1].level());
+    let not_run = calls.iter().filter(|c| c.state() == State::NotRun).count();
+    // 平台描述最先读取，堆在所有arch级initcall之前建立
+    let first_platform = calls.first().is_some_and(|c| c.name().ends_with("::init_platform"));
+    let heap = calls.iter().position(|c| c.name().ends_with("::init_heap"));
+    let first_arch = calls.iter().position(|c| c.level() == Level::Arch);
+    let heap_early = matches!((heap, first_arch), (Some(h), Some(a)) if h < a);
+
+    if !calls.is_empty() && sorted && not_run == 0 && first_platform && heap_early {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: {} initcalls, sorted={}, not run={}, platform first={}, heap early={}",
+                 calls.len(), sorted, not_run, first_platform, heap_early);
+        initcall::print_report();
+        TestResult::Fail
+    }
+}
+
+/// 测试重复运行：已运行过的initcall不再运行
+fn test_initcall_rerun() -> TestResult {
+    let reruns: usize = Level::ALL.iter().map(|&level| initcall::run_level(level).run).sum();
+    let failed = initcall::initcalls().iter().filter(|c| c.state() == State::Failed).count();
+    println!("  {} initcalls, {} failed at boot", initcall::initcalls().len(), failed);
+    if reruns == 0 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: {} initcalls ran again", reruns);
+        TestResult::Fail
+    }
+}
+
+/// 测试命令行解析与按套件名、测试名过滤测试
+fn test_cmdline_filter() -> TestResult {
+    let args: Vec<_> = cmdline::parse(" console=ttyS0  test=alloc,fs quiet skip= a=b=c ").collect();
+    let parsed = args == [
+        ("console", Some("ttyS0")),
+        ("test", Some("alloc,fs")),
+        ("quiet", None),
+        ("skip", Some("")),
+        ("a", Some("b=c")),
+    ];
+
+    let filter = TestFilter { include: Some("alloc_purpose,network"), exclude: Some("ping") };
+    let included = filter.selects("Enhanced Allocator", "alloc_purpose") && filter.selects("Network", "udp_socket");
+    let excluded = !filter.selects("Network", "icmp_ping") && !filter.selects("Block", "block_elevator");
+    let skip_only = TestFilter { include: None, exclude: Some("NET,,") };
+    let case_insensitive = !skip_only.selects("Network", "loopback") && skip_only.selects("Block", "block_io");
+    let everything = !TestFilter::default().is_active() && TestFilter::default().selects("Init", "initcall_order");
+    println!("  command line: \"{}\"", cmdline::get());
+
+    if parsed && included && excluded && case_insensitive && everything {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: args={:?}, included={}, excluded={}, case insensitive={}, everything={}",
+                 args, included, excluded, case_insensitive, everything);
+        TestResult::Fail
+    }
+}
+
+/// 覆盖计数测试用的插桩函数
+fn coverage_probe(odd: bool) {
+    crate::cov!("coverage_probe");
+    if odd {
+        crate::cov!("coverage_probe", "odd");
+    }
+}
+
+/// 测试覆盖计数：cov!的计数点都登记在链接段中，未到达的分支计数为0
+fn test_coverage_counters() -> TestResult {
+    if !coverage::is_enabled() {
+        println!("  Kernel built without the coverage feature");
+        return TestResult::Skip;
+    }
+    for i in 0..5 {
+        coverage_probe(i % 2 == 1);
+    }
+    let probe = |branch| {
+        coverage::points()
+            .iter()
+            .find(|p| p.function() == "coverage_probe" && p.branch() == branch)
+            .map(|p| p.hits())
+    };
+    let (entry, odd) = (probe(None), probe(Some("odd")));
+    let summary = coverage::summary();
+    println!("  {} points, entry hits {:?}, branch hits {:?}", coverage::points().len(), entry, odd);
+
+    if entry == Some(5) && odd == Some(2) && summary.functions_hit >= 1 && summary.branches_hit >= 1 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: summary {:?}", summary);
+        TestResult::Fail
+    }
+}
+
+/// 测试崩溃转储：写入的报告可按下次启动的方式读回，截断的报告仍然有效，丢弃后不再读到
+fn test_crashdump_roundtrip() -> TestResult {
+    use core::fmt::Write;
+    if crashdump::region().is_none() {
+        println!("  RAM too small for a crash dump region");
+        return TestResult::Skip;
+    }
+    crashdump::write(|dump| writeln!(dump, "test report {}", 42));
+    let short = crashdump::stored().map(|report| report.text);
+    crashdump::write(|dump| {
+        for _ in 0..crashdump::CAPACITY / 8 + 1 {
+            dump.write_str("overflow")?;
+        }
+        Ok(())
+    });
+    let truncated = crashdump::stored().is_some_and(|report| report.text.ends_with("[truncated]\n"));
+    crashdump::discard();
+    let discarded = crashdump::stored().is_none();
+
+    if short.as_deref() == Some("test report 42\n") && truncated && discarded {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: short={:?}, truncated={}, discarded={}", short, truncated, discarded);
+        TestResult::Fail
+    }
+}
+
+/// 测试panic策略：可由sysctl或API设置，两者保持一致，无效的策略被拒绝
+fn test_panic_policy() -> TestResult {
+    let before = panic::policy();
+    let before_delay = sysctl::get("kernel.panic_reboot_delay_ms");
+    let delay = sysctl::set_str("kernel.panic_reboot_delay_ms", "250");
+    let reboot = sysctl::set_str("kernel.panic", "reboot").map(|_| panic::policy());
+    let refused = sysctl::set_str("kernel.panic", "explode");
+    panic::set_policy(PanicPolicy::Shutdown);
+    let shown = sysctl::get("kernel.panic");
+    if let Ok(value) = before_delay {
+        let _ = sysctl::set("kernel.panic_reboot_delay_ms", value);
+    }
+    panic::set_policy(before);
+
+    if delay.is_ok() && reboot == Ok(PanicPolicy::Reboot { delay_ms: 250 })
+        && matches!(refused, Err(SysctlError::Rejected(_)))
+        && shown == Ok(Value::Str("shutdown".into())) && panic::policy() == before {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: delay={:?}, reboot={:?}, refused={:?}, shown={:?}, restored={:?}",
+                 delay, reboot, refused, shown, panic::policy());
+        TestResult::Fail
+    }
+}
+
+fn first_hook(_: &PanicInfo) {}
+fn second_hook(_: &PanicInfo) {}
+
+/// 测试panic钩子注册：同一钩子不能重复注册，注销后可再次注册
+fn test_panic_hooks() -> TestResult {
+    let first = panic::register_hook(first_hook);
+    let second = panic::register_hook(second_hook);
+    let duplicate = panic::register_hook(first_hook);
+    let removed = panic::unregister_hook(first_hook);
+    let removed_twice = panic::unregister_hook(first_hook);
+    let again = panic::register_hook(first_hook);
+    let cleaned = panic::unregister_hook(first_hook) && panic::unregister_hook(second_hook);
+
+    if first && second && !duplicate && removed && !removed_twice && again && cleaned {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: first={}, second={}, duplicate={}, removed={}, removed twice={}, again={}, cleaned={}",
+                 first, second, duplicate, removed, removed_twice, again, cleaned);
+        TestResult::Fail
+    }
+}
+
+fn other_stop_hook(_: &mut TrapContext) {}
+
+/// 测试panic时停止其他hart的准备：停止钩子与IPI处理器已安装，寄存器按ABI名格式化
+fn test_panic_stop_setup() -> TestResult {
+    let hook_taken = trap::set_trap_stop_hook(other_stop_hook) == Err(TrapApiError::RegistrationFailed);
+    let ipi_handler = trap::handlers().iter().any(|h| h.description == "Panic Stop IPI");
+    let mut context = TrapContext::new();
+    context.x[2] = 0x8020_0000;
+    context.x[31] = 0x1f;
+    let text = format!("{}", panic::Registers(&context));
+    let formatted = text.lines().count() == 11
+        && text.contains(" sp: 0000000080200000")
+        && text.ends_with(" t6: 000000000000001f");
+
+    if hook_taken && ipi_handler && formatted {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: hook taken={}, IPI handler={}, registers:\n{}", hook_taken, ipi_handler, text);
+        TestResult::Fail
+    }
+}
+
+/// 测试启动计时：启动阶段按顺序记录且互不重叠，测试阶段仍在进行，重复计时不覆盖首次记录
+fn test_boot_timing() -> TestResult {
+    let before = timing::report();
+    drop(timing::measure(Phase::Heap));
+    let after = timing::report();
+    let finished: Vec<_> = [Phase::BssClear, Phase::Heap, Phase::Traps]
+        .iter()
+        .filter_map(|&phase| before.get(phase))
+        .collect();
+    let ordered = finished.len() == 3
+        && finished.windows(2).all(|pair| match pair[0].duration_us {
+            Some(duration) => pair[0].start_us + duration <= pair[1].start_us,
+            None => false,
+        });
+    let tests_running = before.get(Phase::Tests).is_some_and(|t| t.duration_us.is_none());
+    let last_end = finished.last().and_then(|t| t.duration_us.map(|d| t.start_us + d)).unwrap_or(u64::MAX);
+    before.print();
+
+    if ordered && tests_running && before.get(Phase::Heap) == after.get(Phase::Heap) && before.total_us >= last_end {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: finished={:?}, ordered={}, tests running={}, heap after={:?}, total={}",
+                 finished, ordered, tests_running, after.get(Phase::Heap), before.total_us);
+        TestResult::Fail
+    }
+}
+
+/// 指标报告：每条记录以section开头，key=value与JSON Lines两种格式一致，命令拒绝未知的分节与格式
+fn test_metrics_report() -> TestResult {
+    let parsed = Section::ALL.iter().all(|&section| Section::parse(section.name()) == Some(section));
+    let key_value = report::report(&[Section::Memory, Section::Traps], Format::KeyValue);
+    let json = report::report(&[Section::Memory, Section::Traps], Format::Json);
+    let kv_lines: Vec<&str> = key_value.lines().collect();
+    let json_lines: Vec<&str> = json.lines().collect();
+    let tagged = kv_lines.first().is_some_and(|line| line.starts_with("section=memory uptime_ms="))
+        && kv_lines[1..].iter().all(|line| line.starts_with("section=traps trap="))
+        && json_lines.len() == kv_lines.len()
+        && json_lines.iter().all(|line| line.starts_with("{\"section\":\""));
+    // 首次运行测试时还没有总结，tests分节为空
+    let tests = report::report(&[Section::Tests], Format::KeyValue);
+    let tests_ok = tests.is_empty() == test::last_summary().is_none();
+    let unknown_section = report::command("memory bogus").err();
+    let unknown_format = report::command("format=xml").err();
+
+    if parsed && tagged && tests_ok
+        && unknown_section == Some(ReportError::UnknownSection(String::from("bogus")))
+        && unknown_format == Some(ReportError::UnknownFormat(String::from("xml"))) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: parsed={}, tests={:?}, unknown section={:?}, unknown format={:?}",
+                 parsed, tests, unknown_section, unknown_format);
+        println!("        key_value={:?}", key_value);
+        println!("        json={:?}", json);
+        TestResult::Fail
+    }
+}
+
+/// 初始化框架测试套件
+static SUITE: TestSuite = TestSuite::new("Init", 160);
+
+crate::kernel_test!(SUITE, "initcall_order", test_initcall_order, "Initcalls are sorted by level and all ran at boot");
+crate::kernel_test!(SUITE, "initcall_rerun", test_initcall_rerun,
+    "Running a level again skips initcalls that already ran");
+crate::kernel_test!(SUITE, "cmdline_filter", test_cmdline_filter,
+    "The command line parses and test=/skip= select tests by suite and name");
+crate::kernel_test!(SUITE, "coverage_counters", test_coverage_counters,
+    "cov! points are collected from their linker section and count hits");
+crate::kernel_test!(SUITE, "crashdump_roundtrip", test_crashdump_roundtrip,
+    "Crash reports written to the reserved region read back as the next boot sees them");
+crate::kernel_test!(SUITE, "panic_policy", test_panic_policy,
+    "The panic policy is set through kernel.panic or the API, which stay in step");
+crate::kernel_test!(SUITE, "panic_hooks", test_panic_hooks,
+    "Panic hooks register once each and can be registered again after removal");
+crate::kernel_test!(SUITE, "panic_stop_setup", test_panic_stop_setup,
+    "Harts can be stopped on a panic, and their saved registers are formatted by ABI name");
+crate::kernel_test!(SUITE, "metrics_report", test_metrics_report,
+    "Metrics reports tag every record with its section in both key=value and JSON Lines");
+crate::kernel_test!(SUITE, "boot_timing", test_boot_timing,
+    "Boot phases are timed in order without overlapping and are recorded only once");
@@ -0,0 +1,63 @@
+// IPC消息队列测试模块
+
+use super::{TestResult, TestSuite};
+use crate::ipc::{self, Channel, Selectable, TryRecvError, TrySendError};
+use crate::println;
+
+/// 测试非阻塞收发保持FIFO顺序，满时拒绝并归还消息
+fn test_channel_try_ops() -> TestResult {
+    let channel = Channel::new(2);
+    let first = channel.try_send(1u32);
+    let second = channel.try_send(2);
+    let full = channel.try_send(3);
+    let a = channel.try_recv();
+    let b = channel.try_recv();
+    let empty = channel.try_recv();
+    if first.is_ok() && second.is_ok() && full == Err(TrySendError::Full(3))
+        && a == Ok(1) && b == Ok(2) && empty == Err(TryRecvError::Empty) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: full={:?}, a={:?}, b={:?}, empty={:?}", full, a, b, empty);
+        TestResult::Fail
+    }
+}
+
+/// 测试关闭后发送失败，剩余消息仍可接收
+fn test_channel_close() -> TestResult {
+    let channel = Channel::new(4);
+    let _ = channel.try_send(7u32);
+    channel.close();
+    let send = channel.try_send(8);
+    let drained = channel.recv();
+    let after = channel.try_recv();
+    if send == Err(TrySendError::Closed(8)) && drained == Ok(7) && after == Err(TryRecvError::Closed) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: send={:?}, drained={:?}, after={:?}", send, drained, after);
+        TestResult::Fail
+    }
+}
+
+/// 测试select返回第一个可接收的队列
+fn test_channel_select_ready() -> TestResult {
+    let idle: Channel<u32> = Channel::new(1);
+    let busy: Channel<u32> = Channel::new(1);
+    let _ = busy.try_send(5);
+    let sources: [&dyn Selectable; 2] = [&idle, &busy];
+    let picked = ipc::select(&sources);
+    if picked == Some(1) && ipc::select(&[]).is_none() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: picked={:?}", picked);
+        TestResult::Fail
+    }
+}
+
+/// IPC测试套件
+static SUITE: TestSuite = TestSuite::new("IPC", 70);
+
+crate::kernel_test!(SUITE, "channel_try_ops", test_channel_try_ops,
+    "try_send/try_recv are FIFO and reject sends on a full channel");
+crate::kernel_test!(SUITE, "channel_close", test_channel_close, "Closed channels reject sends but drain queued items");
+crate::kernel_test!(SUITE, "channel_select_ready", test_channel_select_ready,
+    "select returns the first channel with an item");
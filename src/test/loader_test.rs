@@ -0,0 +1,160 @@
+// ELF加载器测试模块
+
+use super::{TestResult, TestSuite};
+use crate::cpuinfo;
+use crate::loader::{self, elf, ElfError, ImageMapper, SegmentPermissions};
+use crate::{println, Vec};
+
+/// 测试镜像代码段的加载基址
+const TEXT_BASE: u64 = loader::USER_SPACE_START as u64 + 0x10000;
+
+/// 记录映射操作的模拟地址空间
+struct MockMapper {
+    segments: Vec<(usize, usize, SegmentPermissions)>,
+    stack: Option<(usize, usize)>,
+    written: usize,
+}
+
+impl MockMapper {
+    fn new() -> Self {
+        Self { segments: Vec::new(), stack: None, written: 0 }
+    }
+}
+
+impl ImageMapper for MockMapper {
+    fn map_segment(&mut self, vaddr: usize, mem_size: usize, perms: SegmentPermissions, _data: &[u8]) -> Result<(), ElfError> {
+        self.segments.push((vaddr, mem_size, perms));
+        Ok(())
+    }
+
+    fn map_stack(&mut self, top: usize, size: usize) -> Result<(), ElfError> {
+        self.stack = Some((top, size));
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, vaddr: usize, data: &[u8]) -> Result<(), ElfError> {
+        match self.stack {
+            Some((top, size)) if vaddr >= top - size && vaddr + data.len() <= top => {
+                self.written += data.len();
+                Ok(())
+            }
+            _ => Err(ElfError::MapFailed),
+        }
+    }
+}
+
+/// 构造一个只含一个可执行PT_LOAD段的最小ELF镜像
+fn build_test_image(entry: u64) -> Vec<u8> {
+    let code_offset = elf::EHDR_SIZE + elf::PHDR_SIZE;
+    let code = [0x13u8, 0x00, 0x00, 0x00]; // nop
+    let mut img = Vec::new();
+    img.resize(code_offset + code.len(), 0);
+
+    img[0..4].copy_from_slice(&elf::ELF_MAGIC);
+    img[4] = 2; // ELFCLASS64
+    img[5] = 1; // ELFDATA2LSB
+    img[6] = 1; // EV_CURRENT
+    img[16..18].copy_from_slice(&elf::ET_EXEC.to_le_bytes());
+    img[18..20].copy_from_slice(&elf::EM_RISCV.to_le_bytes());
+    img[24..32].copy_from_slice(&entry.to_le_bytes());
+    img[32..40].copy_from_slice(&(elf::EHDR_SIZE as u64).to_le_bytes());
+    img[54..56].copy_from_slice(&(elf::PHDR_SIZE as u16).to_le_bytes());
+    img[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+    let ph = elf::EHDR_SIZE;
+    img[ph..ph + 4].copy_from_slice(&elf::PT_LOAD.to_le_bytes());
+    img[ph + 4..ph + 8].copy_from_slice(&(elf::PF_R | elf::PF_X).to_le_bytes());
+    img[ph + 8..ph + 16].copy_from_slice(&(code_offset as u64).to_le_bytes());
+    img[ph + 16..ph + 24].copy_from_slice(&(TEXT_BASE + code_offset as u64).to_le_bytes());
+    img[ph + 32..ph + 40].copy_from_slice(&(code.len() as u64).to_le_bytes());
+    img[ph + 40..ph + 48].copy_from_slice(&0x1000u64.to_le_bytes());
+    img[ph + 48..ph + 56].copy_from_slice(&0x1000u64.to_le_bytes());
+
+    img[code_offset..].copy_from_slice(&code);
+    img
+}
+
+/// 测试加载合法镜像
+fn test_load_valid_image() -> TestResult {
+    let entry = TEXT_BASE + (elf::EHDR_SIZE + elf::PHDR_SIZE) as u64;
+    let image = build_test_image(entry);
+    let mut mapper = MockMapper::new();
+
+    match loader::load_elf(&image, &["init", "-v"], &mut mapper) {
+        Ok(loaded) => {
+            println!("  entry=0x{:x}, sp=0x{:x}, brk=0x{:x}", loaded.entry, loaded.stack_pointer, loaded.brk);
+            let seg_ok = mapper.segments.len() == 1 && mapper.segments[0].2.execute && !mapper.segments[0].2.write;
+            if loaded.entry as u64 == entry && loaded.argc == 2 && seg_ok
+                && loaded.stack_pointer % 16 == 0 && mapper.written > 0 {
+                TestResult::Pass
+            } else {
+                println!("  FAIL: unexpected load result");
+                TestResult::Fail
+            }
+        }
+        Err(e) => {
+            println!("  FAIL: load failed: {}", e);
+            TestResult::Fail
+        }
+    }
+}
+
+/// 测试拒绝错误魔数
+fn test_reject_bad_magic() -> TestResult {
+    let mut image = build_test_image(TEXT_BASE + 0x78);
+    image[0] = 0;
+    match elf::ElfFile::parse(&image, loader::USER_SPACE_START..loader::USER_SPACE_END) {
+        Err(ElfError::BadMagic) => TestResult::Pass,
+        _ => TestResult::Fail,
+    }
+}
+
+/// 测试拒绝入口不在可执行段内的镜像
+fn test_reject_bad_entry() -> TestResult {
+    let image = build_test_image(0xdead_0000);
+    match elf::ElfFile::parse(&image, loader::USER_SPACE_START..loader::USER_SPACE_END) {
+        Err(ElfError::InvalidEntry) => TestResult::Pass,
+        _ => TestResult::Fail,
+    }
+}
+
+/// 测试拒绝被截断的镜像
+fn test_reject_truncated() -> TestResult {
+    let image = build_test_image(TEXT_BASE + 0x78);
+    match elf::ElfFile::parse(&image[..elf::EHDR_SIZE + 8], loader::USER_SPACE_START..loader::USER_SPACE_END) {
+        Err(ElfError::Truncated) => TestResult::Pass,
+        _ => TestResult::Fail,
+    }
+}
+
+/// 测试 e_flags 要求的扩展 (压缩指令、浮点ABI) 须为各hart共有
+fn test_isa_requirements() -> TestResult {
+    let entry = TEXT_BASE + (elf::EHDR_SIZE + elf::PHDR_SIZE) as u64;
+    let load = |flags: u32| {
+        let mut image = build_test_image(entry);
+        image[48..52].copy_from_slice(&flags.to_le_bytes());
+        loader::load_elf(&image, &["init"], &mut MockMapper::new()).map(|_| ())
+    };
+    let expect = |letters: &[char]| {
+        if letters.iter().all(|&c| cpuinfo::has_letter(c)) { Ok(()) } else { Err(ElfError::UnsupportedIsa) }
+    };
+    let gc = load(elf::EF_RISCV_RVC | elf::EF_RISCV_FLOAT_ABI_DOUBLE);
+    let quad = load(elf::EF_RISCV_FLOAT_ABI_QUAD);
+    if gc == expect(&['c', 'd']) && quad == expect(&['q']) && load(0).is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: gc={:?}, quad={:?}, common ISA {}", gc, quad, cpuinfo::common());
+        TestResult::Fail
+    }
+}
+
+/// 加载器测试套件
+static SUITE: TestSuite = TestSuite::new("ELF Loader", 40);
+
+crate::kernel_test!(SUITE, "elf_load_valid", test_load_valid_image,
+    "Load a minimal ELF64 image and build the initial stack");
+crate::kernel_test!(SUITE, "elf_bad_magic", test_reject_bad_magic, "Reject images without the ELF magic");
+crate::kernel_test!(SUITE, "elf_bad_entry", test_reject_bad_entry, "Reject entry points outside executable segments");
+crate::kernel_test!(SUITE, "elf_truncated", test_reject_truncated, "Reject truncated program header tables");
+crate::kernel_test!(SUITE, "elf_isa_requirements", test_isa_requirements,
+    "Reject images needing extensions the harts lack");
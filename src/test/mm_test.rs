@@ -0,0 +1,278 @@
+// 地址空间测试模块
+
+use super::{TestResult, TestSuite};
+use crate::init::alloc::AllocPurpose;
+use crate::mm::{self, guard, shm, AddressSpace, MmError, PhysFrame, PteFlags, PAGE_SIZE, USER_SPACE_START};
+use crate::println;
+
+/// 测试映射、地址转换与数据拷贝
+fn test_map_and_copy() -> TestResult {
+    let mut space = match AddressSpace::new_user() {
+        Ok(space) => space,
+        Err(e) => {
+            println!("  FAIL: cannot create address space: {}", e);
+            return TestResult::Fail;
+        }
+    };
+
+    let base = USER_SPACE_START + 0x2000;
+    if space.map_range(base, 2 * PAGE_SIZE, PteFlags::READ | PteFlags::WRITE).is_err() {
+        println!("  FAIL: map_range failed");
+        return TestResult::Fail;
+    }
+
+    // 跨页写入再读回
+    let data = [0x5Au8; 64];
+    let addr = base + PAGE_SIZE - 32;
+    let mut readback = [0u8; 64];
+    if space.copy_to(addr, &data).is_err() || space.copy_from(addr, &mut readback).is_err() {
+        println!("  FAIL: copy across page boundary failed");
+        return TestResult::Fail;
+    }
+
+    let phys_ok = space.translate(base + 8).map_or(false, |pa| pa % PAGE_SIZE == 8);
+    if readback == data && phys_ok && space.page_count() == 2 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: unexpected mapping state");
+        TestResult::Fail
+    }
+}
+
+/// 测试拒绝映射内核区域和未对齐地址
+fn test_reject_kernel_range() -> TestResult {
+    let mut space = match AddressSpace::new_user() {
+        Ok(space) => space,
+        Err(_) => return TestResult::Fail,
+    };
+
+    let kernel = space.map_page(0x8020_0000, PteFlags::READ);
+    let misaligned = space.map_page(USER_SPACE_START + 1, PteFlags::READ);
+    if kernel == Err(MmError::InvalidAddress) && misaligned == Err(MmError::Misaligned) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: kernel={:?}, misaligned={:?}", kernel, misaligned);
+        TestResult::Fail
+    }
+}
+
+/// 测试取消映射后地址不可访问
+fn test_unmap() -> TestResult {
+    let mut space = match AddressSpace::new_user() {
+        Ok(space) => space,
+        Err(_) => return TestResult::Fail,
+    };
+
+    let page = USER_SPACE_START;
+    if space.map_page(page, PteFlags::READ).is_err() || space.unmap_range(page, PAGE_SIZE).is_err() {
+        return TestResult::Fail;
+    }
+    if space.translate(page).is_none() && space.copy_to(page, &[1]) == Err(MmError::NotMapped) {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试不同地址空间获得不同的satp
+fn test_distinct_satp() -> TestResult {
+    let (a, b) = match (AddressSpace::new_user(), AddressSpace::new_user()) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return TestResult::Fail,
+    };
+
+    println!("  ASIDs: {} / {} (max {})", a.asid(), b.asid(), mm::asid::max_asid());
+    if a.satp() != b.satp() && a.satp() != mm::kernel_satp() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试复制地址空间为深拷贝
+fn test_clone_eager_copy() -> TestResult {
+    let mut parent = match AddressSpace::new_user() {
+        Ok(space) => space,
+        Err(_) => return TestResult::Fail,
+    };
+    let page = USER_SPACE_START;
+    if parent.map_page(page, PteFlags::READ | PteFlags::WRITE).is_err() || parent.copy_to(page, &[7]).is_err() {
+        return TestResult::Fail;
+    }
+
+    let child = match parent.try_clone() {
+        Ok(child) => child,
+        Err(e) => {
+            println!("  FAIL: clone failed: {}", e);
+            return TestResult::Fail;
+        }
+    };
+
+    // 修改父进程页面后子进程不受影响
+    let _ = parent.copy_to(page, &[9]);
+    let mut byte = [0u8; 1];
+    let copied = child.copy_from(page, &mut byte).is_ok() && byte[0] == 7;
+    if copied && child.translate(page) != parent.translate(page) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: child shares or lost parent data");
+        TestResult::Fail
+    }
+}
+
+/// 测试用户内存访问拒绝内核地址
+fn test_uaccess_rejects_kernel_pointer() -> TestResult {
+    let mut buf = [0u8; 16];
+    let from = mm::copy_from_user(&mut buf, 0x8020_0000);
+    let to = mm::copy_to_user(0x8020_0000, &buf);
+    let overflow = mm::copy_from_user(&mut buf, usize::MAX - 4);
+    if from == Err(MmError::InvalidAddress) && to == Err(MmError::InvalidAddress)
+        && overflow == Err(MmError::InvalidAddress) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: from={:?}, to={:?}, overflow={:?}", from, to, overflow);
+        TestResult::Fail
+    }
+}
+
+/// 测试共享内存在两个地址空间之间可见
+fn test_shm_share() -> TestResult {
+    let object = match shm::create("test_shm_share", PAGE_SIZE, PteFlags::READ | PteFlags::WRITE) {
+        Ok(object) => object,
+        Err(e) => {
+            println!("  FAIL: cannot create object: {}", e);
+            return TestResult::Fail;
+        }
+    };
+    let (mut a, mut b) = match (AddressSpace::new_user(), AddressSpace::new_user()) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return TestResult::Fail,
+    };
+    let va = USER_SPACE_START + 0x10000;
+    let vb = USER_SPACE_START + 0x40000;
+    if a.map_shared(va, &object, PteFlags::READ | PteFlags::WRITE).is_err()
+        || b.map_shared(vb, &object, PteFlags::READ).is_err() {
+        println!("  FAIL: map_shared failed");
+        return TestResult::Fail;
+    }
+
+    let mut readback = [0u8; 4];
+    let copied = a.copy_to(va + 8, b"ping").is_ok() && b.copy_from(vb + 8, &mut readback).is_ok();
+    let same_frame = a.translate(va) == b.translate(vb);
+    if copied && same_frame && &readback == b"ping" {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: copied={}, same_frame={}", copied, same_frame);
+        TestResult::Fail
+    }
+}
+
+/// 测试共享内存的权限控制与重名检查
+fn test_shm_permissions() -> TestResult {
+    let object = match shm::create("test_shm_ro", PAGE_SIZE, PteFlags::READ) {
+        Ok(object) => object,
+        Err(_) => return TestResult::Fail,
+    };
+    let mut space = match AddressSpace::new_user() {
+        Ok(space) => space,
+        Err(_) => return TestResult::Fail,
+    };
+    let base = USER_SPACE_START + 0x10000;
+    let write = space.map_shared(base, &object, PteFlags::READ | PteFlags::WRITE);
+    let duplicate = shm::create("test_shm_ro", PAGE_SIZE, PteFlags::READ).map(|_| ());
+    if write == Err(MmError::PermissionDenied) && duplicate == Err(MmError::NameInUse) && space.page_count() == 0 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: write={:?}, duplicate={:?}", write, duplicate);
+        TestResult::Fail
+    }
+}
+
+/// 测试最后一个映射解除后共享内存对象被销毁
+fn test_shm_lifetime() -> TestResult {
+    let mut space = match AddressSpace::new_user() {
+        Ok(space) => space,
+        Err(_) => return TestResult::Fail,
+    };
+    let base = USER_SPACE_START + 0x10000;
+    let mapped = shm::create("test_shm_lifetime", 2 * PAGE_SIZE, PteFlags::READ)
+        .and_then(|object| space.map_shared(base, &object, PteFlags::READ));
+    // 句柄已释放，映射仍保持对象存活
+    let alive = shm::open("test_shm_lifetime").is_ok();
+    let _ = space.unmap_range(base, 2 * PAGE_SIZE);
+    let gone = shm::open("test_shm_lifetime").map(|_| ());
+    if mapped.is_ok() && alive && gone == Err(MmError::NoSuchObject) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: mapped={:?}, alive={}, gone={:?}", mapped, alive, gone);
+        TestResult::Fail
+    }
+}
+
+/// 测试固件保留区：内核镜像之下的固件内存已登记，页帧不会落在保留区内
+fn test_guard_reservations() -> TestResult {
+    let firmware = guard::reserved().iter().any(|r| r.name == "firmware");
+    let frame = match PhysFrame::alloc(AllocPurpose::TempBuffer) {
+        Ok(frame) => frame,
+        Err(e) => {
+            println!("  FAIL: frame allocation failed: {}", e);
+            return TestResult::Fail;
+        }
+    };
+    if firmware && guard::overlapping(frame.addr(), PAGE_SIZE).is_none() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: firmware={}, frame=0x{:x}", firmware, frame.addr());
+        guard::print();
+        TestResult::Fail
+    }
+}
+
+/// 测试与保留区重叠的范围被拒绝并计数，clip 跳过保留区
+fn test_guard_rejects_reserved() -> TestResult {
+    let Some(reservation) = guard::reserved().first().copied() else {
+        println!("  FAIL: no reserved ranges");
+        return TestResult::Fail;
+    };
+    let region = reservation.region;
+    let before = guard::violations();
+    let rejected = guard::check(region.base, PAGE_SIZE, "test").is_err();
+    let counted = guard::violations() == before + 1;
+    let clipped = guard::clip(region.base, region.size + 2 * PAGE_SIZE);
+    let clear = clipped.base >= region.end() && guard::overlapping(clipped.base, clipped.size).is_none();
+    if rejected && counted && clear {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: rejected={}, counted={}, clipped={}", rejected, counted, clipped);
+        TestResult::Fail
+    }
+}
+
+/// 地址空间测试套件
+static SUITE: TestSuite = TestSuite { setup: Some(paging_enabled), ..TestSuite::new("Address Space", 50) };
+
+/// 未启用分页时跳过地址空间测试
+fn paging_enabled() -> bool {
+    if !mm::is_initialized() {
+        println!("Paging not initialized, skipping address space tests");
+    }
+    mm::is_initialized()
+}
+
+crate::kernel_test!(SUITE, "mm_map_and_copy", test_map_and_copy, "Map user pages and copy data across a page boundary");
+crate::kernel_test!(SUITE, "mm_reject_kernel_range", test_reject_kernel_range,
+    "Reject mappings in the kernel half and misaligned addresses");
+crate::kernel_test!(SUITE, "mm_unmap", test_unmap, "Unmapped pages are no longer translated");
+crate::kernel_test!(SUITE, "mm_distinct_satp", test_distinct_satp, "Each address space gets its own satp value");
+crate::kernel_test!(SUITE, "mm_clone_eager_copy", test_clone_eager_copy,
+    "Cloned address spaces own private copies of user pages");
+crate::kernel_test!(SUITE, "uaccess_rejects_kernel_pointer", test_uaccess_rejects_kernel_pointer,
+    "User copy helpers refuse kernel and overflowing addresses");
+crate::kernel_test!(SUITE, "shm_share", test_shm_share, "A shared object mapped twice is backed by the same frames");
+crate::kernel_test!(SUITE, "shm_permissions", test_shm_permissions,
+    "Mappings cannot exceed object permissions; names are unique");
+crate::kernel_test!(SUITE, "shm_lifetime", test_shm_lifetime, "Objects disappear when their last mapping is removed");
+crate::kernel_test!(SUITE, "guard_reservations", test_guard_reservations,
+    "Firmware memory is reserved and frames stay clear of it");
+crate::kernel_test!(SUITE, "guard_rejects_reserved", test_guard_rejects_reserved,
+    "Ranges overlapping reserved memory are rejected and clipped");
@@ -1,10 +1,67 @@
 // 测试模块入口
+//
+// 测试用例用`kernel_test!`注册到`.kernel_test`链接段，`run_all_tests`从该段收集全部测试，
+// 按套件顺序分组运行。在任何模块中注册的测试都会自动运行，无需维护测试列表。
+//
+// 命令行的`test_output=tap`或`test_output=json`让结果以TAP或JSON Lines输出，供CI解析，
+// 见`OutputFormat`。每个测试的耗时 (单调时钟) 随结果输出，总结中列出最慢的几个测试，
+// 便于在CI历史中发现分配器或陷阱路径的性能退化。
+//
+// 需要随机输入的测试用`test_rng()`取得伪随机数生成器。每次运行有一个种子，在每个套件开始时打印，
+// 以`test_seed=<种子>`重新运行即可复现；每个测试的生成器由种子与测试的全名导出，
+// 因此只用`test=`运行其中一个测试时得到的序列也相同。
+//
+// 套件可以给出钩子：`setup`/`teardown`在整个套件前后各调用一次，`before_each`/`after_each`
+// 在每个测试前后调用，用于快照与清理等各测试共同的工作，见`TestSuite`。
+//
+// 运行器在每个测试前后比较分配器的已分配块数与字节数 (含before_each/after_each)，通过的测试
+// 若留下了内存即判为失败。会有意保留内存的测试 (注册设备、填充缓存、让出CPU等) 以
+// `kernel_test!`的`allow_leak`豁免；命令行的`leak_check=off`关闭检查。各测试共用、首次使用时
+// 扩大并保留的表由套件的`setup`预先扩大 (setup不在检查之内)，检查结果因此不依赖测试的运行顺序。
+//
+// 依赖SBI扩展、多个hart或特定设备的测试以`kernel_test!`的`requires [...]`声明条件，
+// 条件不满足时测试以原因跳过，见`requirement`模块。
+//
+// 以`coverage`特性编译时，运行器在测试前清零`cov!`的覆盖计数，测试后报告测试未触及的函数与分支，
+// 命令行的`coverage=dump`还会逐行输出全部计数，见`coverage`模块。
 
 pub mod console_test;
 pub mod sbi_test;
+pub mod trap_test;
+pub mod trap_stress;
+pub mod requirement;
 pub mod alloc_test;
+pub mod loader_test;
+pub mod mm_test;
+pub mod task_test;
+pub mod ipc_test;
+pub mod profiler_test;
+pub mod debug_test;
+pub mod driver_test;
+pub mod pm_test;
+pub mod block_test;
+pub mod fs_test;
+pub mod config_test;
+pub mod sysctl_test;
+pub mod net_test;
+pub mod time_test;
+pub mod init_test;
 
+use crate::cmdline;
+use crate::coverage;
+pub use self::requirement::Requirement;
+use crate::time::Instant;
+use crate::util::prng::Xoshiro256;
+use crate::util::rand;
 use crate::{println, info_print, warn_print, error_print};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
 
 /// 测试结果枚举
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -19,6 +76,309 @@ pub struct TestCase {
     pub name: &'static str,
     pub func: fn() -> TestResult,
     pub description: &'static str,
+    /// 测试有意保留分配的内存，不做泄漏检查
+    pub allow_leak: bool,
+    /// 运行条件，任一不满足时跳过测试
+    pub requires: &'static [Requirement],
+}
+
+/// 测试套件：同一套件的测试按注册顺序连续运行
+pub struct TestSuite {
+    pub name: &'static str,
+    /// 套件的运行顺序，小的先运行
+    pub order: u16,
+    /// 套件开始前调用，返回false时跳过整个套件
+    pub setup: Option<fn() -> bool>,
+    /// 套件结束后调用
+    pub teardown: Option<fn()>,
+    /// 每个测试开始前调用，返回false时跳过该测试
+    pub before_each: Option<fn() -> bool>,
+    /// 每个测试结束后调用，无论测试结果如何
+    pub after_each: Option<fn()>,
+}
+
+impl TestSuite {
+    /// 创建没有钩子的套件，钩子以结构体更新语法给出：
+    ///
+    /// ```ignore
+    /// static SUITE: TestSuite = TestSuite { after_each: Some(cleanup), ..TestSuite::new("Trap", 25) };
+    /// ```
+    pub const fn new(name: &'static str, order: u16) -> Self {
+        Self { name, order, setup: None, teardown: None, before_each: None, after_each: None }
+    }
+}
+
+/// 由`kernel_test!`放入`.kernel_test`链接段的测试
+pub struct KernelTest {
+    pub suite: &'static TestSuite,
+    pub case: TestCase,
+}
+
+/// 将测试函数`$func`以名称`$name`注册到套件`$suite` (一个`static TestSuite`)
+///
+/// 末尾加上`allow_leak`的测试不做泄漏检查，用于有意保留内存的测试；
+/// 加上`requires [...]`的测试在条件不满足时跳过，见`Requirement`。
+///
+/// ```ignore
+/// static SUITE: TestSuite = TestSuite::new("Block", 110);
+/// crate::kernel_test!(SUITE, "block_register", test_block_register, "Block devices register");
+/// crate::kernel_test!(SUITE, "cache_read_ahead", test_cache_read_ahead, "Read-ahead fills the cache", allow_leak);
+/// crate::kernel_test!(SUITE, "virtio_blk", test_virtio_blk, "Reads a virtio disk", requires [requirement::VIRTIO_BLK]);
+/// ```
+#[macro_export]
+macro_rules! kernel_test {
+    (@register $suite:path, $name:literal, $func:path, $description:literal, $allow_leak:literal,
+     [$($requirement:expr),*]) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".kernel_test"]
+            static TEST: $crate::test::KernelTest = $crate::test::KernelTest {
+                suite: &$suite,
+                case: $crate::test::TestCase {
+                    name: $name,
+                    func: $func,
+                    description: $description,
+                    allow_leak: $allow_leak,
+                    requires: &[$($requirement),*],
+                },
+            };
+        };
+    };
+    ($suite:path, $name:literal, $func:path, $description:literal) => {
+        $crate::kernel_test!(@register $suite, $name, $func, $description, false, []);
+    };
+    ($suite:path, $name:literal, $func:path, $description:literal, allow_leak) => {
+        $crate::kernel_test!(@register $suite, $name, $func, $description, true, []);
+    };
+    ($suite:path, $name:literal, $func:path, $description:literal, requires [$($requirement:expr),+ $(,)?]) => {
+        $crate::kernel_test!(@register $suite, $name, $func, $description, false, [$($requirement),+]);
+    };
+    ($suite:path, $name:literal, $func:path, $description:literal, allow_leak,
+     requires [$($requirement:expr),+ $(,)?]) => {
+        $crate::kernel_test!(@register $suite, $name, $func, $description, true, [$($requirement),+]);
+    };
+}
+
+/// 返回所有注册的测试，同一模块中的测试保持注册顺序
+pub fn kernel_tests() -> &'static [KernelTest] {
+    extern "C" {
+        static skernel_test: u8;
+        static ekernel_test: u8;
+    }
+    unsafe {
+        let start = ptr::addr_of!(skernel_test).cast::<KernelTest>();
+        let end = ptr::addr_of!(ekernel_test).cast::<KernelTest>();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// 按套件分组注册的测试，套件按顺序排列
+pub fn suites() -> Vec<(&'static TestSuite, Vec<&'static TestCase>)> {
+    let mut tests: Vec<&KernelTest> = kernel_tests().iter().collect();
+    // 稳定排序，保持套件内的注册顺序
+    tests.sort_by_key(|t| (t.suite.order, t.suite.name));
+    let mut suites: Vec<(&'static TestSuite, Vec<&'static TestCase>)> = Vec::new();
+    for test in tests {
+        match suites.last_mut() {
+            Some((suite, cases)) if ptr::eq(*suite, test.suite) => cases.push(&test.case),
+            _ => suites.push((test.suite, alloc::vec![&test.case])),
+        }
+    }
+    suites
+}
+
+/// 分配器的已分配块数与字节数，分配器未初始化时为None
+fn heap_usage() -> Option<(usize, usize)> {
+    crate::init::alloc::stats().map(|stats| (stats.alloc_count, stats.used_size))
+}
+
+/// 本次运行的种子
+static RUN_SEED: AtomicU64 = AtomicU64::new(0);
+/// 正在运行的测试的种子
+static CASE_SEED: AtomicU64 = AtomicU64::new(0);
+
+/// 解析十进制或0x开头的十六进制数
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// 本次运行的种子：命令行的`test_seed=`，未给出时取自内核随机数生成器
+pub fn seed_from_cmdline() -> u64 {
+    match cmdline::value("test_seed") {
+        Some(text) => parse_number(text).unwrap_or_else(|| {
+            warn_print!("Invalid test_seed={}, using a random seed", text);
+            rand::rand_u64()
+        }),
+        None => rand::rand_u64(),
+    }
+}
+
+/// 由运行种子与测试全名`suite::test`导出测试的种子 (FNV-1a)
+fn case_seed(seed: u64, suite: &str, test: &str) -> u64 {
+    let mut hash = seed ^ 0xcbf2_9ce4_8422_2325;
+    for byte in suite.bytes().chain(*b"::").chain(test.bytes()) {
+        hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// 本次运行的种子，复现时传给`test_seed=`
+pub fn test_seed() -> u64 {
+    RUN_SEED.load(Ordering::Relaxed)
+}
+
+/// 返回当前测试的伪随机数生成器；同一测试中每次调用都从头开始同一序列
+pub fn test_rng() -> Xoshiro256 {
+    Xoshiro256::new(CASE_SEED.load(Ordering::Relaxed))
+}
+
+/// 测试过滤器，来自命令行的`test=<模式>`与`skip=<模式>`
+///
+/// 模式以逗号分隔，任一模式是测试名或套件名的子串 (不区分大小写) 即匹配。
+/// 给出`test=`时只运行匹配的测试，再从中去掉匹配`skip=`的测试。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TestFilter {
+    pub include: Option<&'static str>,
+    pub exclude: Option<&'static str>,
+}
+
+/// `needle`是否为`haystack`的子串，不区分ASCII大小写
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    let (haystack, needle) = (haystack.as_bytes(), needle.as_bytes());
+    needle.len() <= haystack.len()
+        && haystack.windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle))
+}
+
+impl TestFilter {
+    /// 从内核命令行读取过滤器，空模式视为未给出
+    pub fn from_cmdline() -> Self {
+        Self {
+            include: cmdline::value("test").filter(|p| !p.is_empty()),
+            exclude: cmdline::value("skip").filter(|p| !p.is_empty()),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.include.is_some() || self.exclude.is_some()
+    }
+
+    fn matches(patterns: &str, suite: &str, test: &str) -> bool {
+        patterns
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .any(|p| contains_ignore_case(test, p) || contains_ignore_case(suite, p))
+    }
+
+    /// 套件`suite`中的测试`test`是否运行
+    pub fn selects(&self, suite: &str, test: &str) -> bool {
+        self.include.map_or(true, |p| Self::matches(p, suite, test))
+            && !self.exclude.is_some_and(|p| Self::matches(p, suite, test))
+    }
+}
+
+/// 测试结果的输出格式，来自命令行的`test_output=`
+///
+/// 机器可读格式下不再输出套件标题与带颜色的结果行；测试自身打印的内容照常输出，
+/// 解析时应忽略其余行：TAP本身允许非TAP行，JSON Lines的每条记录都以`{"type":`开头。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// 带颜色的可读输出
+    #[default]
+    Human,
+    /// TAP version 13，计划行在最后，每个测试附带YAML块记录耗时
+    Tap,
+    /// 每行一个JSON对象：`suite`、`test`，最后是最慢测试的`slow`与`summary`记录
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(Self::Human),
+            "tap" => Some(Self::Tap),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// 从内核命令行读取输出格式，未给出或无法识别时为`Human`
+    pub fn from_cmdline() -> Self {
+        let Some(name) = cmdline::value("test_output") else {
+            return Self::Human;
+        };
+        Self::parse(name).unwrap_or_else(|| {
+            warn_print!("Unknown test_output={}, using human-readable output", name);
+            Self::Human
+        })
+    }
+
+    fn is_machine(self) -> bool {
+        self != Self::Human
+    }
+}
+
+/// 以JSON字符串 (含引号) 格式化
+struct JsonStr<'a>(&'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+impl TestResult {
+    fn as_str(self) -> &'static str {
+        match self {
+            TestResult::Pass => "pass",
+            TestResult::Fail => "fail",
+            TestResult::Skip => "skip",
+        }
+    }
+}
+
+/// 总结中列出的最慢测试数
+const SLOWEST_REPORTED: usize = 5;
+
+/// 一个已运行测试的耗时
+#[derive(Debug, Clone, Copy)]
+pub struct TestTiming {
+    pub suite: &'static str,
+    pub test: &'static str,
+    pub duration: Duration,
+}
+
+/// 一次测试运行的计数与总耗时，供`report`等导出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestSummary {
+    pub total: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub filtered: usize,
+    pub elapsed: Duration,
+}
+
+/// 最近一次`run_all_tests`的总结
+static LAST_SUMMARY: Mutex<Option<TestSummary>> = Mutex::new(None);
+
+/// 返回最近一次`run_all_tests`的总结，尚未运行过测试时为`None`
+pub fn last_summary() -> Option<TestSummary> {
+    *LAST_SUMMARY.lock()
 }
 
 /// 测试运行器
@@ -27,56 +387,245 @@ pub struct TestRunner {
     passed: usize,
     failed: usize,
     skipped: usize,
+    /// 被过滤器排除、未运行的测试数
+    filtered: usize,
+    filter: TestFilter,
+    format: OutputFormat,
+    /// 随机输入的种子，见`test_rng`
+    seed: u64,
+    /// 测试留下内存时判为失败
+    leak_check: bool,
+    /// 已运行测试的总耗时
+    elapsed: Duration,
+    /// 每个已运行测试的耗时，按运行顺序
+    timings: Vec<TestTiming>,
 }
 
 impl TestRunner {
     /// 创建新的测试运行器
     pub fn new() -> Self {
+        Self::with_filter(TestFilter::default())
+    }
+
+    /// 创建只运行`filter`选中测试的运行器
+    pub fn with_filter(filter: TestFilter) -> Self {
         Self {
             total: 0,
             passed: 0,
             failed: 0,
             skipped: 0,
+            filtered: 0,
+            filter,
+            format: OutputFormat::Human,
+            seed: 0,
+            leak_check: true,
+            elapsed: Duration::ZERO,
+            timings: Vec::new(),
         }
     }
 
-    /// 运行单个测试用例
-    pub fn run_test(&mut self, test: &TestCase) {
+    /// 设置是否检查测试留下的内存
+    pub fn set_leak_check(&mut self, enabled: bool) {
+        self.leak_check = enabled;
+    }
+
+    /// 设置随机输入的种子
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// 设置结果的输出格式
+    pub fn set_format(&mut self, format: OutputFormat) {
+        self.format = format;
+    }
+
+    /// 运行套件`suite`中的单个测试用例
+    pub fn run_test(&mut self, suite: &TestSuite, test: &TestCase) {
         self.total += 1;
         
-        println!("Running test: {} - {}", test.name, test.description);
-        
-        let result = (test.func)();
+        if !self.format.is_machine() {
+            println!("Running test: {} - {}", test.name, test.description);
+        }
         
-        match result {
-            TestResult::Pass => {
-                self.passed += 1;
-                info_print!("  [PASS] {}", test.name);
+        RUN_SEED.store(self.seed, Ordering::Relaxed);
+        CASE_SEED.store(case_seed(self.seed, suite.name, test.name), Ordering::Relaxed);
+        // 在快照之前检查条件：首次探测的SBI扩展会被缓存，不算测试留下的内存
+        let unmet = requirement::first_unmet(test.requires);
+        let usage_before = if self.leak_check && !test.allow_leak { heap_usage() } else { None };
+        let mut skip_reason = None;
+        // 耗时不计入钩子
+        let (mut result, duration) = if let Some(unmet) = unmet {
+            skip_reason = Some(format!("requires {}", unmet));
+            (TestResult::Skip, Duration::ZERO)
+        } else if suite.before_each.map_or(true, |before| before()) {
+            let start = Instant::now();
+            let result = (test.func)();
+            let duration = start.elapsed();
+            if let Some(after) = suite.after_each {
+                after();
             }
-            TestResult::Fail => {
-                self.failed += 1;
-                error_print!("  [FAIL] {}", test.name);
+            (result, duration)
+        } else {
+            skip_reason = Some(String::from("skipped by the suite's before_each hook"));
+            (TestResult::Skip, Duration::ZERO)
+        };
+        if let (TestResult::Pass, Some((blocks, bytes)), Some((blocks_after, bytes_after))) =
+            (result, usage_before, heap_usage())
+        {
+            if blocks_after > blocks || bytes_after > bytes {
+                error_print!(
+                    "  Leak: {} block(s), {} byte(s) still allocated after the test",
+                    blocks_after as isize - blocks as isize,
+                    bytes_after as isize - bytes as isize
+                );
+                result = TestResult::Fail;
             }
-            TestResult::Skip => {
-                self.skipped += 1;
-                warn_print!("  [SKIP] {}", test.name);
+        }
+        self.elapsed += duration;
+        if result != TestResult::Skip {
+            self.timings.push(TestTiming { suite: suite.name, test: test.name, duration });
+        }
+        
+        match result {
+            TestResult::Pass => self.passed += 1,
+            TestResult::Fail => self.failed += 1,
+            TestResult::Skip => self.skipped += 1,
+        }
+        self.report(suite, test, result, duration, skip_reason.as_deref());
+    }
+
+    /// 按输出格式报告一个测试的结果，跳过的测试可附带原因
+    fn report(&self, suite: &TestSuite, test: &TestCase, result: TestResult, duration: Duration, reason: Option<&str>) {
+        match self.format {
+            OutputFormat::Human => match (result, reason) {
+                (TestResult::Pass, _) => info_print!("  [PASS] {} ({} us)", test.name, duration.as_micros()),
+                (TestResult::Fail, _) => error_print!("  [FAIL] {} ({} us)", test.name, duration.as_micros()),
+                (TestResult::Skip, Some(reason)) => warn_print!("  [SKIP] {} ({})", test.name, reason),
+                (TestResult::Skip, None) => warn_print!("  [SKIP] {}", test.name),
+            },
+            OutputFormat::Tap => {
+                let status = if result == TestResult::Fail { "not ok" } else { "ok" };
+                let directive = match (result, reason) {
+                    (TestResult::Skip, Some(reason)) => format!(" # SKIP {}", reason),
+                    (TestResult::Skip, None) => String::from(" # SKIP"),
+                    _ => String::new(),
+                };
+                println!("{} {} - {}::{}{}", status, self.total, suite.name, test.name, directive);
+                println!("  ---");
+                println!("  description: {}", JsonStr(test.description));
+                println!("  duration_us: {}", duration.as_micros());
+                println!("  ...");
             }
+            OutputFormat::Json => println!(
+                "{{\"type\":\"test\",\"suite\":{},\"name\":{},\"description\":{},\
+                 \"result\":\"{}\",\"duration_us\":{}{}}}",
+                JsonStr(suite.name),
+                JsonStr(test.name),
+                JsonStr(test.description),
+                result.as_str(),
+                duration.as_micros(),
+                reason.map(|r| format!(",\"reason\":{}", JsonStr(r))).unwrap_or_default()
+            ),
+        }
+    }
+
+    /// 报告套件开始，或因setup返回false而被跳过
+    fn report_suite(&self, suite: &TestSuite, tests: usize, skipped: bool) {
+        match self.format {
+            OutputFormat::Human if skipped => warn_print!("=== {} Test Suite skipped by its setup ===", suite.name),
+            OutputFormat::Human => println!("=== {} Test Suite (test_seed={:#x}) ===", suite.name, self.seed),
+            OutputFormat::Tap if skipped => println!("# {}: {} tests skipped by the suite setup", suite.name, tests),
+            OutputFormat::Tap => println!("# {} (test_seed={:#x})", suite.name, self.seed),
+            OutputFormat::Json => println!(
+                "{{\"type\":\"suite\",\"name\":{},\"tests\":{},\"skipped\":{},\"seed\":\"{:#x}\"}}",
+                JsonStr(suite.name),
+                tests,
+                skipped,
+                self.seed
+            ),
         }
     }
 
-    /// 运行测试套件
-    pub fn run_suite(&mut self, suite_name: &str, tests: &[TestCase]) {
-        println!("=== {} Test Suite ===", suite_name);
+    /// 运行测试套件中被过滤器选中的测试，一个都未选中时不输出该套件
+    pub fn run_suite(&mut self, suite: &TestSuite, tests: &[&TestCase]) {
+        let filter = self.filter;
+        let selected = tests.iter().filter(|t| filter.selects(suite.name, t.name)).count();
+        self.filtered += tests.len() - selected;
+        if selected == 0 {
+            return;
+        }
+        if !suite.setup.map_or(true, |setup| setup()) {
+            self.report_suite(suite, selected, true);
+            return;
+        }
+        self.report_suite(suite, selected, false);
         
-        for test in tests {
-            self.run_test(test);
+        for test in tests.iter().filter(|t| filter.selects(suite.name, t.name)) {
+            self.run_test(suite, test);
         }
         
-        println!("=== {} Test Suite Complete ===", suite_name);
+        if !self.format.is_machine() {
+            println!("=== {} Test Suite Complete ===", suite.name);
+        }
+        if let Some(teardown) = suite.teardown {
+            teardown();
+        }
+    }
+
+    /// 已运行测试的耗时，按运行顺序
+    pub fn timings(&self) -> &[TestTiming] {
+        &self.timings
+    }
+
+    /// 耗时最长的`count`个测试，从最慢的开始
+    pub fn slowest(&self, count: usize) -> Vec<TestTiming> {
+        let mut timings = self.timings.clone();
+        timings.sort_by(|a, b| b.duration.cmp(&a.duration));
+        timings.truncate(count);
+        timings
     }
 
     /// 打印测试总结
     pub fn print_summary(&self) {
+        let slowest = self.slowest(SLOWEST_REPORTED);
+        match self.format {
+            OutputFormat::Human => {}
+            OutputFormat::Tap => {
+                println!("1..{}", self.total);
+                println!("# pass {}", self.passed);
+                println!("# fail {}", self.failed);
+                println!("# skip {}", self.skipped);
+                println!("# filtered {}", self.filtered);
+                println!("# duration_us {}", self.elapsed.as_micros());
+                for (rank, timing) in slowest.iter().enumerate() {
+                    println!("# slowest {} {}::{} {} us", rank + 1, timing.suite, timing.test, timing.duration.as_micros());
+                }
+                return;
+            }
+            OutputFormat::Json => {
+                for (rank, timing) in slowest.iter().enumerate() {
+                    println!(
+                        "{{\"type\":\"slow\",\"rank\":{},\"suite\":{},\"name\":{},\"duration_us\":{}}}",
+                        rank + 1,
+                        JsonStr(timing.suite),
+                        JsonStr(timing.test),
+                        timing.duration.as_micros()
+                    );
+                }
+                println!(
+                    "{{\"type\":\"summary\",\"total\":{},\"passed\":{},\"failed\":{},\"skipped\":{},\
+                     \"filtered\":{},\"duration_us\":{},\"success\":{}}}",
+                    self.total,
+                    self.passed,
+                    self.failed,
+                    self.skipped,
+                    self.filtered,
+                    self.elapsed.as_micros(),
+                    self.all_passed()
+                );
+                return;
+            }
+        }
         println!("=== Test Summary ===");
         println!("Total tests: {}", self.total);
         info_print!("Passed: {}", self.passed);
@@ -90,6 +639,16 @@ impl TestRunner {
         } else {
             info_print!("Skipped: {}", self.skipped);
         }
+        if self.filtered > 0 {
+            println!("Filtered out: {}", self.filtered);
+        }
+        println!("Total time: {} ms", self.elapsed.as_millis());
+        if !slowest.is_empty() {
+            println!("Slowest tests:");
+            for timing in &slowest {
+                println!("  {:>8} us  {}::{}", timing.duration.as_micros(), timing.suite, timing.test);
+            }
+        }
         
         let success_rate = if self.total > 0 {
             (self.passed * 100) / self.total
@@ -105,30 +664,63 @@ impl TestRunner {
         println!("==================");
     }
 
+    /// 获取当前的计数与总耗时
+    pub fn summary(&self) -> TestSummary {
+        TestSummary {
+            total: self.total,
+            passed: self.passed,
+            failed: self.failed,
+            skipped: self.skipped,
+            filtered: self.filtered,
+            elapsed: self.elapsed,
+        }
+    }
+
     /// 获取是否所有测试都通过
     pub fn all_passed(&self) -> bool {
         self.failed == 0 && self.total > 0
     }
 }
 
-/// 运行所有测试
-pub fn run_all_tests() {
-    let mut runner = TestRunner::new();
-    
-    // 运行控制台测试
-    console_test::run_console_tests(&mut runner);
-    
-    // 运行SBI测试
-    sbi_test::run_sbi_tests(&mut runner);
-
-    alloc_test::run_alloc_tests(&mut runner);
+/// 运行所有注册的测试，返回是否全部通过
+///
+/// 命令行的`test=`与`skip=`选择要运行的测试，见`TestFilter`；`test_output=`选择输出格式，
+/// 见`OutputFormat`；`test_seed=`给出随机输入的种子，见`test_rng`；`leak_check=off`关闭泄漏检查；
+/// `coverage=dump`在覆盖报告后输出全部计数。
+pub fn run_all_tests() -> bool {
+    let filter = TestFilter::from_cmdline();
+    let format = OutputFormat::from_cmdline();
+    let seed = seed_from_cmdline();
+    if filter.is_active() {
+        info_print!("Test filter: test={}, skip={}", filter.include.unwrap_or("*"), filter.exclude.unwrap_or("-"));
+    }
+    let mut runner = TestRunner::with_filter(filter);
+    runner.set_format(format);
+    runner.set_seed(seed);
+    runner.set_leak_check(cmdline::value("leak_check") != Some("off"));
+    if format == OutputFormat::Tap {
+        println!("TAP version 13");
+    }
+    // 只统计测试触及的代码，不含引导过程
+    coverage::reset();
+    for (suite, tests) in suites() {
+        runner.run_suite(suite, &tests);
+    }
     
     // 打印最终总结
     runner.print_summary();
+    *LAST_SUMMARY.lock() = Some(runner.summary());
+    if coverage::is_enabled() {
+        coverage::print_report();
+        if cmdline::value("coverage") == Some("dump") {
+            coverage::dump();
+        }
+    }
     
     if runner.all_passed() {
         info_print!("All test suites completed successfully!");
     } else {
         warn_print!("Some tests failed or were skipped");
     }
+    runner.all_passed()
 }
\ No newline at end of file
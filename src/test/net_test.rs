@@ -0,0 +1,513 @@
+// 网络层测试模块
+
+use super::{TestResult, TestSuite};
+use crate::net::arp::{self, ArpPacket};
+use crate::net::ipv4::{self, Ipv4Addr, Ipv4Config, Packet, PROTO_ICMP};
+use crate::net::udp::{self, UdpSocket};
+use crate::net::{self, ethernet, icmp, loopback, EtherType, Frame, Interface, MacAddr};
+use crate::net::buf::{self as packet_buf, BUFFER_SIZE};
+use crate::net::{NetDevice, NetError, PacketBuf, ReceiveHandler};
+use crate::println;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+const TEST_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+const PEER_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+/// 测试协议使用本地实验用EtherType
+const TEST_TYPE: EtherType = EtherType(0x88B5);
+
+/// IPv4测试使用独立子网，避免与真实接口冲突
+const TEST_IP: Ipv4Addr = Ipv4Addr::new(10, 9, 0, 1);
+const PEER_IP: Ipv4Addr = Ipv4Addr::new(10, 9, 0, 2);
+const TEST_IPV4: Ipv4Config = Ipv4Config { addr: TEST_IP, prefix_len: 24, gateway: None };
+
+/// 模拟对端：根据发出的帧生成对端的回应帧
+type Responder = fn(&[u8]) -> Option<Vec<u8>>;
+
+/// 测试用网卡：记录发送的帧，由测试注入接收的帧
+struct TestNic {
+    name: String,
+    sent: Mutex<Vec<Vec<u8>>>,
+    handler: Mutex<Option<ReceiveHandler>>,
+    responder: Option<Responder>,
+}
+
+impl TestNic {
+    fn new(name: &str) -> Arc<Self> {
+        Self::with_responder(name, None)
+    }
+
+    fn with_responder(name: &str, responder: Option<Responder>) -> Arc<Self> {
+        let sent = Mutex::new(Vec::new());
+        Arc::new(Self { name: String::from(name), sent, handler: Mutex::new(None), responder })
+    }
+
+    /// 模拟收到一帧
+    fn inject(&self, frame: &[u8]) {
+        let handler = self.handler.lock().clone();
+        if let (Some(handler), Ok(frame)) = (handler, PacketBuf::from_slice(frame)) {
+            handler(frame);
+        }
+    }
+}
+
+impl NetDevice for TestNic {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac(&self) -> MacAddr {
+        TEST_MAC
+    }
+
+    fn mtu(&self) -> usize {
+        1500
+    }
+
+    fn transmit(&self, frame: PacketBuf) -> Result<(), NetError> {
+        self.sent.lock().push(frame.data().to_vec());
+        if let Some(answer) = self.responder.and_then(|respond| respond(frame.data())) {
+            self.inject(&answer);
+        }
+        Ok(())
+    }
+
+    fn set_receive_handler(&self, handler: Option<ReceiveHandler>) {
+        *self.handler.lock() = handler;
+    }
+}
+
+/// 以太网帧编码、填充与解析
+fn test_ethernet_frame() -> TestResult {
+    let short = ethernet::encode(PEER_MAC, TEST_MAC, EtherType::ARP, b"hi");
+    let parsed = Frame::parse(&short);
+    let long = ethernet::encode(MacAddr::BROADCAST, TEST_MAC, EtherType::IPV4, &[7u8; 100]);
+    let truncated = Frame::parse(&short[..10]).err();
+
+    let header_ok = parsed.is_ok_and(|f| f.dst == PEER_MAC && f.src == TEST_MAC && f.ether_type == EtherType::ARP
+        && f.payload.len() == ethernet::MIN_FRAME_LEN - ethernet::HEADER_LEN && f.payload.starts_with(b"hi"));
+    if short.len() == ethernet::MIN_FRAME_LEN && header_ok && long.len() == 114
+        && MacAddr::BROADCAST.is_multicast() && !TEST_MAC.is_multicast() && truncated == Some(NetError::Malformed) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: short={}, parsed={:?}, long={}, truncated={:?}", short.len(), parsed, long.len(), truncated);
+        TestResult::Fail
+    }
+}
+
+/// 包缓冲区：头部空间前插/剥离、共享时只读、过大时回退到堆，释放后归还池
+fn test_packet_buf() -> TestResult {
+    let free_before = packet_buf::pool_stats().free;
+    let Ok(mut packet) = PacketBuf::alloc(16) else {
+        println!("  FAIL: no packet buffer");
+        return TestResult::Fail;
+    };
+    let headroom = packet.headroom();
+    let put = packet.put(4).map(|tail| tail.copy_from_slice(b"data")).is_some();
+    let pushed = packet.push(2).map(|head| head.copy_from_slice(&[0xAA, 0xBB])).is_some();
+    let built = packet.data() == [0xAA, 0xBB, b'd', b'a', b't', b'a'];
+
+    let mut clone = packet.clone();
+    let shared = packet.is_shared() && packet.push(1).is_none() && clone.data_mut().is_none();
+    let pulled = clone.pull(2) == Some(&[0xAA, 0xBB][..]) && clone.data() == b"data" && packet.len() == 6;
+    let unshared = clone.unshare().is_ok() && !clone.is_shared() && !packet.is_shared() && clone.data() == b"data";
+    drop(clone);
+    packet.trim(3);
+    let trimmed = packet.data() == [0xAA, 0xBB, b'd'];
+    let pooled = packet.is_pooled();
+    drop(packet);
+    let returned = packet_buf::pool_stats().free == free_before;
+
+    let fallbacks = packet_buf::pool_stats().fallbacks;
+    let large = PacketBuf::with_headroom(0, BUFFER_SIZE + 1).map(|b| !b.is_pooled() && b.capacity() == BUFFER_SIZE + 1);
+    let counted = packet_buf::pool_stats().fallbacks == fallbacks + 1;
+
+    // 无头部空间的缓冲区先被复制，再补齐并加上以太网头
+    let framed = PacketBuf::from_slice(b"hi").and_then(|mut frame| {
+        ethernet::push_header(&mut frame, PEER_MAC, TEST_MAC, TEST_TYPE)?;
+        Ok(frame.data() == ethernet::encode(PEER_MAC, TEST_MAC, TEST_TYPE, b"hi").as_slice())
+    });
+
+    if headroom == packet_buf::DEFAULT_HEADROOM && put && pushed && built && shared && pulled && unshared && trimmed
+        && (pooled || free_before == 0) && returned && large == Ok(true) && counted && framed == Ok(true) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: headroom={}, put={}, pushed={}, built={}, shared={}, pulled={}, unshared={}, trimmed={}",
+            headroom, put, pushed, built, shared, pulled, unshared, trimmed);
+        println!("  pooled={}, returned={}, large={:?}, counted={}, framed={:?}", pooled, returned, large, counted,
+            framed);
+        TestResult::Fail
+    }
+}
+
+/// 按名称注册接口，发送经设备输出并计数，超过MTU被拒绝
+fn test_net_register_send() -> TestResult {
+    let nic = TestNic::new("test-nic");
+    let first = net::register(nic.clone());
+    let again = net::register(TestNic::new("test-nic")).err();
+    let found = net::find("test-nic").map(|i| i.mac());
+    let sent = first.as_ref().map_err(|e| *e).and_then(|i| i.send(PEER_MAC, TEST_TYPE, b"payload"));
+    let too_long = first.as_ref().ok().and_then(|i| i.send(PEER_MAC, TEST_TYPE, &[0u8; 1501]).err());
+    let stats = first.as_ref().map(|i| i.stats());
+    let frames = nic.sent.lock().clone();
+    let removed = net::unregister("test-nic");
+    let detached = nic.handler.lock().is_none();
+
+    let frame_ok = frames.len() == 1 && Frame::parse(&frames[0]).is_ok_and(|f| f.dst == PEER_MAC
+        && f.src == TEST_MAC && f.ether_type == TEST_TYPE && f.payload.starts_with(b"payload"));
+    if again == Some(NetError::AlreadyRegistered) && found == Some(TEST_MAC) && sent.is_ok()
+        && too_long == Some(NetError::TooLong) && stats.is_ok_and(|s| s.tx_packets == 1 && s.tx_bytes == 60)
+        && frame_ok && removed.is_ok() && detached {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: again={:?}, found={:?}, sent={:?}, too_long={:?}, frames={}, removed={:?}",
+                 again, found, sent, too_long, frames.len(), removed);
+        TestResult::Fail
+    }
+}
+
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+fn count_frame(_iface: &Arc<Interface>, frame: &Frame) {
+    if frame.payload.starts_with(b"ping") {
+        RECEIVED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 接收的帧按EtherType分发，发往其他主机或无协议的帧被丢弃
+fn test_net_receive_dispatch() -> TestResult {
+    let nic = TestNic::new("test-rx");
+    let iface = match net::register(nic.clone()) {
+        Ok(iface) => iface,
+        Err(_) => return TestResult::Fail,
+    };
+    RECEIVED.store(0, Ordering::Relaxed);
+    let registered = net::register_protocol(TEST_TYPE, count_frame);
+    let duplicate = net::register_protocol(TEST_TYPE, count_frame).err();
+
+    nic.inject(&ethernet::encode(TEST_MAC, PEER_MAC, TEST_TYPE, b"ping"));
+    nic.inject(&ethernet::encode(MacAddr::BROADCAST, PEER_MAC, TEST_TYPE, b"ping"));
+    // 目的地址为其他主机
+    nic.inject(&ethernet::encode(MacAddr([0x02, 0, 0, 0, 0, 0x03]), PEER_MAC, TEST_TYPE, b"ping"));
+    // 未注册协议
+    nic.inject(&ethernet::encode(TEST_MAC, PEER_MAC, EtherType(0x88B6), b"ping"));
+    // 截断的帧
+    nic.inject(&[0u8; 8]);
+    let received = RECEIVED.load(Ordering::Relaxed);
+    let stats = iface.stats();
+
+    let unregistered = net::unregister_protocol(TEST_TYPE);
+    let removed = net::unregister("test-rx");
+    // 注销后设备不再投递
+    nic.inject(&ethernet::encode(TEST_MAC, PEER_MAC, TEST_TYPE, b"ping"));
+    let after = RECEIVED.load(Ordering::Relaxed);
+
+    if registered.is_ok() && duplicate == Some(NetError::AlreadyRegistered) && received == 2
+        && stats.rx_packets == 4 && stats.rx_dropped == 2 && stats.rx_errors == 1
+        && unregistered.is_ok() && removed.is_ok() && after == 2 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: registered={:?}, duplicate={:?}, received={}, stats={:?}, after={}",
+                 registered, duplicate, received, stats, after);
+        TestResult::Fail
+    }
+}
+
+/// 对端PEER_IP：回答对它的ARP请求和ICMP回显请求
+fn peer_responder(bytes: &[u8]) -> Option<Vec<u8>> {
+    let frame = Frame::parse(bytes).ok()?;
+    if frame.ether_type == EtherType::ARP {
+        let request = ArpPacket::parse(frame.payload).ok()?;
+        if request.op != 1 || request.target_ip != PEER_IP {
+            return None;
+        }
+        let reply = ArpPacket {
+            op: 2,
+            sender_mac: PEER_MAC,
+            sender_ip: PEER_IP,
+            target_mac: request.sender_mac,
+            target_ip: request.sender_ip,
+        };
+        return Some(ethernet::encode(frame.src, PEER_MAC, EtherType::ARP, &reply.encode()));
+    }
+    let packet = Packet::parse(frame.payload).ok()?;
+    let message = packet.payload;
+    if packet.dst != PEER_IP || packet.protocol != PROTO_ICMP || message.first() != Some(&icmp::TYPE_ECHO_REQUEST) {
+        return None;
+    }
+    let ident = u16::from_be_bytes([message[4], message[5]]);
+    let seq = u16::from_be_bytes([message[6], message[7]]);
+    let reply = icmp::encode_echo(icmp::TYPE_ECHO_REPLY, ident, seq, &message[icmp::HEADER_LEN..]);
+    let ip = ipv4::encode(PEER_IP, packet.src, PROTO_ICMP, 64, &reply);
+    Some(ethernet::encode(frame.src, PEER_MAC, EtherType::IPV4, &ip))
+}
+
+/// 注册带IPv4地址的测试网卡；协议栈可能已在启动时注册
+fn ipv4_nic(name: &str, responder: Option<Responder>) -> Option<(Arc<TestNic>, Arc<Interface>)> {
+    let _ = net::init();
+    let nic = TestNic::with_responder(name, responder);
+    let iface = net::register(nic.clone()).ok()?;
+    iface.set_ipv4(Some(TEST_IPV4));
+    Some((nic, iface))
+}
+
+fn arp_frame(op: u16, sender: (MacAddr, Ipv4Addr), target: (MacAddr, Ipv4Addr), dst: MacAddr) -> Vec<u8> {
+    let packet = ArpPacket { op, sender_mac: sender.0, sender_ip: sender.1, target_mac: target.0, target_ip: target.1 };
+    ethernet::encode(dst, sender.0, EtherType::ARP, &packet.encode())
+}
+
+/// 校验和、地址解析与IPv4头部编解码
+fn test_ipv4_packet() -> TestResult {
+    // RFC 1071示例头部，校验和字段为0
+    let header = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+                  0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+    let sum = ipv4::checksum(&header);
+    let parsed: [Result<Ipv4Addr, NetError>; 4] =
+        ["10.0.2.15".parse(), "256.1.1.1".parse(), "1.2.3".parse(), "1..2.3".parse()];
+    let config = Ipv4Config { addr: Ipv4Addr::new(10, 0, 2, 15), prefix_len: 24, gateway: None };
+
+    let mut packet = ipv4::encode(TEST_IP, PEER_IP, PROTO_ICMP, 64, b"data");
+    // 以太网填充不属于数据报
+    packet.extend_from_slice(&[0u8; 6]);
+    let decoded = Packet::parse(&packet).map(|p| (p.src, p.dst, p.protocol, p.ttl, p.payload.to_vec()));
+    let mut corrupted = packet.clone();
+    corrupted[8] ^= 1;
+    let mut fragment = ipv4::encode(TEST_IP, PEER_IP, PROTO_ICMP, 64, b"data");
+    fragment[6] = 0x20;
+    fragment[10..12].fill(0);
+    let fragment_sum = ipv4::checksum(&fragment[..ipv4::HEADER_LEN]);
+    fragment[10..12].copy_from_slice(&fragment_sum.to_be_bytes());
+
+    if sum == 0xb861 && parsed[0] == Ok(Ipv4Addr::new(10, 0, 2, 15)) && parsed[1..].iter().all(Result::is_err)
+        && config.contains(Ipv4Addr::new(10, 0, 2, 2)) && !config.contains(Ipv4Addr::new(10, 0, 3, 2))
+        && config.broadcast() == Ipv4Addr::new(10, 0, 2, 255)
+        && decoded == Ok((TEST_IP, PEER_IP, PROTO_ICMP, 64, b"data".to_vec()))
+        && Packet::parse(&corrupted).err() == Some(NetError::Malformed)
+        && Packet::parse(&fragment).err() == Some(NetError::NotSupported) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: sum={:#x}, parsed={:?}, decoded={:?}", sum, parsed, decoded);
+        TestResult::Fail
+    }
+}
+
+/// 回答对本机地址的ARP请求；未解析的目的地先发ARP请求，收到回应后发出暂存的数据报
+fn test_arp_resolve() -> TestResult {
+    let Some((nic, iface)) = ipv4_nic("test-arp", None) else {
+        return TestResult::Fail;
+    };
+    let peer = (PEER_MAC, PEER_IP);
+    nic.inject(&arp_frame(1, peer, (MacAddr::ZERO, TEST_IP), MacAddr::BROADCAST));
+    // 询问其他主机的请求不回答
+    nic.inject(&arp_frame(1, peer, (MacAddr::ZERO, Ipv4Addr::new(10, 9, 0, 7)), MacAddr::BROADCAST));
+    let replies = core::mem::take(&mut *nic.sent.lock());
+    let learned = arp::lookup(&iface, PEER_IP);
+
+    let other_mac = MacAddr([0x02, 0, 0, 0, 0, 0x03]);
+    let other_ip = Ipv4Addr::new(10, 9, 0, 3);
+    let queued = ipv4::send(other_ip, PROTO_ICMP, b"held");
+    let requests = core::mem::take(&mut *nic.sent.lock());
+    nic.inject(&arp_frame(2, (other_mac, other_ip), (TEST_MAC, TEST_IP), TEST_MAC));
+    let flushed = core::mem::take(&mut *nic.sent.lock());
+    let _ = net::unregister("test-arp");
+    let forgotten = arp::neighbours().iter().all(|n| n.iface != "test-arp");
+
+    let reply_ok = replies.len() == 1 && Frame::parse(&replies[0]).is_ok_and(|f| f.dst == PEER_MAC
+        && ArpPacket::parse(f.payload).is_ok_and(|p| p.op == 2 && p.sender_mac == TEST_MAC
+            && p.sender_ip == TEST_IP && p.target_ip == PEER_IP));
+    let request_ok = requests.len() == 1 && Frame::parse(&requests[0]).is_ok_and(|f| f.dst == MacAddr::BROADCAST
+        && ArpPacket::parse(f.payload).is_ok_and(|p| p.op == 1 && p.target_ip == other_ip));
+    let flushed_ok = flushed.len() == 1 && Frame::parse(&flushed[0]).is_ok_and(|f| f.dst == other_mac
+        && f.ether_type == EtherType::IPV4 && Packet::parse(f.payload).is_ok_and(|p| p.payload == b"held"));
+    if reply_ok && learned == Some(PEER_MAC) && queued.is_ok() && request_ok && flushed_ok && forgotten {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: replies={}, learned={:?}, queued={:?}, requests={}, flushed={}, forgotten={}",
+                 replies.len(), learned, queued, requests.len(), flushed.len(), forgotten);
+        TestResult::Fail
+    }
+}
+
+/// 回显请求得到内容相同的回显应答，广播的回显请求被忽略
+fn test_icmp_echo_reply() -> TestResult {
+    let Some((nic, _iface)) = ipv4_nic("test-icmp", Some(peer_responder)) else {
+        return TestResult::Fail;
+    };
+    let request = icmp::encode_echo(icmp::TYPE_ECHO_REQUEST, 0x1234, 7, b"abcdefgh");
+    let to_us = ipv4::encode(PEER_IP, TEST_IP, PROTO_ICMP, 64, &request);
+    nic.inject(&ethernet::encode(TEST_MAC, PEER_MAC, EtherType::IPV4, &to_us));
+    let sent = core::mem::take(&mut *nic.sent.lock());
+    let broadcast = ipv4::encode(PEER_IP, Ipv4Addr::new(10, 9, 0, 255), PROTO_ICMP, 64, &request);
+    nic.inject(&ethernet::encode(MacAddr::BROADCAST, PEER_MAC, EtherType::IPV4, &broadcast));
+    let after_broadcast = nic.sent.lock().len();
+    let _ = net::unregister("test-icmp");
+
+    // 先经ARP解析对端地址，再发出应答
+    let expected = icmp::encode_echo(icmp::TYPE_ECHO_REPLY, 0x1234, 7, b"abcdefgh");
+    let reply_ok = sent.len() == 2 && Frame::parse(&sent[1]).is_ok_and(|f| f.dst == PEER_MAC
+        && Packet::parse(f.payload).is_ok_and(|p| p.src == TEST_IP && p.dst == PEER_IP
+            && p.protocol == PROTO_ICMP && p.payload == expected.as_slice() && ipv4::checksum(p.payload) == 0));
+    if reply_ok && after_broadcast == 0 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: sent={}, after_broadcast={}", sent.len(), after_broadcast);
+        TestResult::Fail
+    }
+}
+
+/// ping命令：对端应答时成功，无应答时超时，无效地址和不可达地址报错
+fn test_icmp_ping() -> TestResult {
+    let Some((_nic, _iface)) = ipv4_nic("test-ping", Some(peer_responder)) else {
+        return TestResult::Fail;
+    };
+    let answered = icmp::ping("10.9.0.2", 1);
+    let silent = icmp::echo(Ipv4Addr::new(10, 9, 0, 9), 1, 1, 8, 50).err();
+    let invalid = icmp::ping("10.9.0", 1).err();
+    let _ = net::unregister("test-ping");
+    let unreachable = icmp::echo(PEER_IP, 1, 1, 8, 50).err();
+
+    if answered.is_ok_and(|s| s.transmitted == 1 && s.received == 1) && silent == Some(NetError::TimedOut)
+        && invalid == Some(NetError::InvalidArgument) && unreachable == Some(NetError::NoRoute) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: answered={:?}, silent={:?}, invalid={:?}, unreachable={:?}",
+                 answered, silent, invalid, unreachable);
+        TestResult::Fail
+    }
+}
+
+fn udp_frame(src_port: u16, dst_port: u16, data: &[u8]) -> Vec<u8> {
+    let datagram = udp::encode(PEER_IP, src_port, TEST_IP, dst_port, data);
+    let packet = ipv4::encode(PEER_IP, TEST_IP, ipv4::PROTO_UDP, 64, &datagram);
+    ethernet::encode(TEST_MAC, PEER_MAC, EtherType::IPV4, &packet)
+}
+
+/// UDP套接字：端口绑定唯一，收发数据报并校验校验和，未绑定端口的数据报被丢弃
+fn test_udp_socket() -> TestResult {
+    let Some((nic, iface)) = ipv4_nic("test-udp", Some(peer_responder)) else {
+        return TestResult::Fail;
+    };
+    let socket = match UdpSocket::bind(7777) {
+        Ok(socket) => socket,
+        Err(_) => {
+            let _ = net::unregister("test-udp");
+            return TestResult::Fail;
+        }
+    };
+    let taken = UdpSocket::bind(7777).err();
+    let ephemeral = UdpSocket::bind(0).map(|s| s.local_port());
+
+    let sent = socket.send_to(b"hello host", PEER_IP, 5555);
+    let frames = core::mem::take(&mut *nic.sent.lock());
+    nic.inject(&udp_frame(5555, 7777, b"hello kernel"));
+    nic.inject(&udp_frame(5555, 7778, b"nobody"));
+    let mut corrupted = udp_frame(5555, 7777, b"corrupted");
+    corrupted[ethernet::HEADER_LEN + ipv4::HEADER_LEN + udp::HEADER_LEN] ^= 1;
+    nic.inject(&corrupted);
+    let dropped = iface.stats().rx_dropped;
+    let pending = socket.pending();
+    let mut buf = [0u8; 5];
+    let received = socket.recv_from(&mut buf);
+    let netcat = udp::netcat("10.9.0.2 5555 from netcat");
+    let netcat_frames = core::mem::take(&mut *nic.sent.lock());
+    drop(socket);
+    let rebound = UdpSocket::bind(7777).is_ok();
+    let _ = net::unregister("test-udp");
+
+    let datagram_of = |frame: &[u8]| Frame::parse(frame).ok().and_then(|f| {
+        let packet = Packet::parse(f.payload).ok()?;
+        let checksum = udp::checksum(packet.src, packet.dst, packet.payload);
+        (packet.protocol == ipv4::PROTO_UDP && checksum == 0xFFFF).then(|| packet.payload.to_vec())
+    });
+    // ARP请求之后是数据报
+    let sent_ok = frames.len() == 2 && datagram_of(&frames[1]).is_some_and(|d| d[2..4] == 5555u16.to_be_bytes()
+        && d[0..2] == 7777u16.to_be_bytes() && &d[udp::HEADER_LEN..] == b"hello host");
+    let netcat_ok = netcat_frames.len() == 1
+        && datagram_of(&netcat_frames[0]).is_some_and(|d| &d[udp::HEADER_LEN..] == b"from netcat");
+    if taken == Some(NetError::AddrInUse) && ephemeral.is_ok_and(|p| p >= 49152) && sent.is_ok() && sent_ok
+        && pending == 1 && dropped == 2 && received == (5, PEER_IP, 5555) && &buf == b"hello"
+        && netcat.is_ok() && netcat_ok && rebound {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: taken={:?}, ephemeral={:?}, sent={:?}, frames={}, pending={}, dropped={}",
+                 taken, ephemeral, sent, frames.len(), pending, dropped);
+        println!("        received={:?}, netcat={:?}", received, netcat);
+        TestResult::Fail
+    }
+}
+
+/// 回环接口：本机地址、回环子网与本机其他接口的地址均可ping通，UDP经回环收发
+fn test_loopback() -> TestResult {
+    let _ = net::init();
+    let Some(lo) = net::find(loopback::NAME) else {
+        return TestResult::Fail;
+    };
+    let before = lo.stats();
+    let localhost = icmp::echo(Ipv4Addr::LOCALHOST, 9, 1, 32, 100).map(|r| r.from);
+    let subnet = icmp::echo(Ipv4Addr::new(127, 0, 0, 5), 9, 2, 32, 100).map(|r| r.from);
+    let Some((nic, _iface)) = ipv4_nic("test-lo", None) else {
+        return TestResult::Fail;
+    };
+    let own = icmp::echo(TEST_IP, 9, 3, 32, 100).map(|r| r.from);
+    let nic_sent = nic.sent.lock().len();
+    let _ = net::unregister("test-lo");
+
+    let sockets = UdpSocket::bind(0).and_then(|a| UdpSocket::bind(0).map(|b| (a, b)));
+    let udp = sockets.as_ref().map_err(|e| *e).and_then(|(a, b)| {
+        a.send_to(b"over lo", Ipv4Addr::LOCALHOST, b.local_port())?;
+        Ok(b.try_recv().map(|d| (d.src, d.src_port == a.local_port(), d.data)))
+    });
+    let after = lo.stats();
+    let tx = after.tx_packets - before.tx_packets;
+    let rx = after.rx_packets - before.rx_packets;
+
+    if localhost == Ok(Ipv4Addr::LOCALHOST) && subnet == Ok(Ipv4Addr::new(127, 0, 0, 5)) && own == Ok(TEST_IP)
+        && nic_sent == 0 && udp == Ok(Some((Ipv4Addr::LOCALHOST, true, b"over lo".to_vec()))) && tx == 7 && rx == 7 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: localhost={:?}, subnet={:?}, own={:?}, nic_sent={}, udp={:?}, tx={}, rx={}",
+                 localhost, subnet, own, nic_sent, udp, tx, rx);
+        TestResult::Fail
+    }
+}
+
+/// setup：分配一个包缓冲区以建立缓冲池，注册后注销测试网卡与测试协议，经网卡学到两个邻居、
+/// 暂存并发出一个待解析的数据报，再同时绑定三个套接字。接口表、协议表、ARP表与套接字表
+/// 扩大后保留，单独运行的测试 (`test=`) 也不会因首次使用它们而被判为泄漏。
+fn prepare_tables() -> bool {
+    let _ = net::init();
+    let pooled = PacketBuf::alloc(16).is_ok();
+    let Some((nic, _iface)) = ipv4_nic("test-setup", None) else {
+        return false;
+    };
+    let protocol = net::register_protocol(TEST_TYPE, count_frame).is_ok() && net::unregister_protocol(TEST_TYPE).is_ok();
+    let other = (MacAddr([0x02, 0, 0, 0, 0, 0x03]), Ipv4Addr::new(10, 9, 0, 3));
+    nic.inject(&arp_frame(1, (PEER_MAC, PEER_IP), (MacAddr::ZERO, TEST_IP), MacAddr::BROADCAST));
+    let queued = ipv4::send(other.1, PROTO_ICMP, b"held").is_ok();
+    nic.inject(&arp_frame(2, other, (TEST_MAC, TEST_IP), TEST_MAC));
+    let sockets: Result<Vec<_>, _> = (0..3).map(|_| UdpSocket::bind(0)).collect();
+    let bound = sockets.is_ok();
+    drop(sockets);
+    let removed = net::unregister("test-setup").is_ok();
+    pooled && protocol && queued && bound && removed
+}
+
+/// 网络层测试套件；缓冲池与接口表、协议表、ARP表、套接字表由setup预先建立
+static SUITE: TestSuite = TestSuite { setup: Some(prepare_tables), ..TestSuite::new("Network", 140) };
+
+crate::kernel_test!(SUITE, "ethernet_frame", test_ethernet_frame, "Ethernet frames encode with padding and decode");
+crate::kernel_test!(SUITE, "packet_buf", test_packet_buf,
+    "Packet buffers grow at both ends, share read-only and return to the pool");
+crate::kernel_test!(SUITE, "net_register_send", test_net_register_send,
+    "Interfaces register by name and send through their device");
+crate::kernel_test!(SUITE, "net_receive_dispatch", test_net_receive_dispatch,
+    "Received frames reach the protocol of their EtherType");
+crate::kernel_test!(SUITE, "ipv4_packet", test_ipv4_packet, "IPv4 headers encode and decode with checked checksums");
+crate::kernel_test!(SUITE, "arp_resolve", test_arp_resolve, "ARP answers for our address and resolves held packets");
+crate::kernel_test!(SUITE, "icmp_echo_reply", test_icmp_echo_reply, "Echo requests to our address are answered");
+crate::kernel_test!(SUITE, "icmp_ping", test_icmp_ping, "ping gets replies, times out and rejects bad targets");
+crate::kernel_test!(SUITE, "udp_socket", test_udp_socket, "UDP sockets bind ports and exchange checked datagrams");
+crate::kernel_test!(SUITE, "loopback", test_loopback, "The loopback interface carries ICMP and UDP back to the kernel");
@@ -0,0 +1,104 @@
+// CPU空闲电源管理测试模块
+
+use super::{TestResult, TestSuite};
+use crate::pm::{self, Governor, IdleBudget, IdleState, PmError};
+use crate::println;
+use crate::task::{scheduler, ticks_per_ms, timer};
+use crate::trap;
+
+/// 测试用的深度空闲状态 (实际只执行wfi)
+struct DeepState;
+
+impl IdleState for DeepState {
+    fn name(&self) -> &'static str {
+        "test-deep"
+    }
+
+    fn exit_latency_us(&self) -> u64 {
+        50
+    }
+
+    fn target_residency_us(&self) -> u64 {
+        500
+    }
+
+    fn enter(&self) {
+        pm::WFI.enter();
+    }
+}
+
+static DEEP: DeepState = DeepState;
+
+/// menu调控器选择能在截止时间前达到目标驻留时间且满足延迟限制的最深状态
+fn test_governor_select() -> TestResult {
+    let states: [&'static dyn IdleState; 2] = [&pm::WFI, &DEEP];
+    let budget = |predicted_us, latency_limit_us| IdleBudget { predicted_us, latency_limit_us };
+    let short = pm::MENU.select(&states, budget(Some(100), u64::MAX));
+    let long = pm::MENU.select(&states, budget(Some(1000), u64::MAX));
+    let unbounded = pm::MENU.select(&states, budget(None, u64::MAX));
+    let limited = pm::MENU.select(&states, budget(None, 20));
+    let shallow = pm::SHALLOW.select(&states, budget(None, u64::MAX));
+    if (short, long, unbounded, limited, shallow) == (0, 1, 1, 0, 0) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: short={}, long={}, unbounded={}, limited={}, shallow={}",
+                 short, long, unbounded, limited, shallow);
+        TestResult::Fail
+    }
+}
+
+/// 重复注册被拒绝，调控器可按名称切换
+fn test_governor_switch() -> TestResult {
+    let duplicate_state = pm::register_state(&pm::WFI);
+    let duplicate_governor = pm::register_governor(&pm::MENU);
+    let unknown = pm::set_governor("no-such-governor");
+    let switched = pm::set_governor("shallow").map(|_| pm::governor_name());
+    let restored = pm::set_governor("menu");
+    if duplicate_state == Err(PmError::AlreadyRegistered) && duplicate_governor == Err(PmError::AlreadyRegistered)
+        && unknown == Err(PmError::UnknownGovernor) && switched == Ok("shallow") && restored.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: state={:?}, governor={:?}, unknown={:?}, switched={:?}",
+                 duplicate_state, duplicate_governor, unknown, switched);
+        TestResult::Fail
+    }
+}
+
+/// 空闲直到定时器截止时间，每次进入都记录驻留统计
+fn test_idle_residency() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let hart = scheduler::current_hart();
+    let entries = |stats: &[pm::StateStats]| stats.iter().map(|s| s.entries).sum::<u64>();
+    let before = entries(&pm::stats(hart));
+
+    let was_enabled = trap::disable_interrupts();
+    let deadline = scheduler::now_ticks() + ticks_per_ms();
+    timer::program(Some(deadline));
+    // wfi允许无故返回，直到截止时间前反复进入
+    let mut rounds = 0;
+    while scheduler::now_ticks() < deadline {
+        pm::idle(Some(deadline));
+        rounds += 1;
+    }
+    trap::restore_interrupts(was_enabled);
+
+    let after = entries(&pm::stats(hart));
+    pm::print_stats();
+    if rounds > 0 && after == before + rounds {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: entries {} -> {} in {} rounds", before, after, rounds);
+        TestResult::Fail
+    }
+}
+
+/// 电源管理测试套件
+static SUITE: TestSuite = TestSuite::new("Power Management", 90);
+
+crate::kernel_test!(SUITE, "governor_select", test_governor_select,
+    "The menu governor picks the deepest state that fits");
+crate::kernel_test!(SUITE, "governor_switch", test_governor_switch,
+    "Duplicate registrations fail and governors switch by name");
+crate::kernel_test!(SUITE, "idle_residency", test_idle_residency, "Idling until a timer deadline records residency");
@@ -0,0 +1,184 @@
+// 采样分析器测试模块
+
+use super::{Requirement, TestResult, TestSuite};
+use crate::println;
+use crate::profiler::{self, PmuEvent, ProfilerError, SampleSource};
+use crate::task::{scheduler, ticks_per_ms};
+use crate::util::backtrace;
+use crate::util::ksyms::{self, KernelSymbol};
+
+/// 测试参数检查与重复启动、停止
+fn test_profiler_start_stop() -> TestResult {
+    let zero = profiler::start(SampleSource::Timer { period: 0 });
+    let first = profiler::start(SampleSource::Timer { period: ticks_per_ms() });
+    let second = profiler::start(SampleSource::Timer { period: ticks_per_ms() });
+    let running = profiler::is_running();
+    let stopped = profiler::stop();
+    let again = profiler::stop();
+    if zero == Err(ProfilerError::InvalidPeriod) && first.is_ok()
+        && second == Err(ProfilerError::AlreadyRunning) && running
+        && stopped.is_ok() && again == Err(ProfilerError::NotRunning) && !profiler::is_running() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: zero={:?}, first={:?}, second={:?}, stopped={:?}, again={:?}",
+                 zero, first, second, stopped, again);
+        TestResult::Fail
+    }
+}
+
+/// 测试定时器采样：忙等一段时间后应采集到样本
+fn test_profiler_timer_samples() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    profiler::reset();
+    if profiler::start(SampleSource::Timer { period: ticks_per_ms() }).is_err() {
+        return TestResult::Fail;
+    }
+    let until = scheduler::now_ticks() + 20 * ticks_per_ms();
+    while scheduler::now_ticks() < until {
+        core::hint::spin_loop();
+    }
+    let _ = profiler::stop();
+
+    let samples = profiler::sample_count();
+    let hottest = profiler::profile().first().copied();
+    profiler::print_report(3);
+    // 中断可能被屏蔽，没有样本时跳过
+    if samples == 0 {
+        return TestResult::Skip;
+    }
+    if hottest.map_or(false, |h| h.samples > 0 && h.pc != 0) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: samples={}, hottest={:?}", samples, hottest);
+        TestResult::Fail
+    }
+}
+
+/// 测试PMU溢出采样：每个事件要么不受支持，要么能启动、采样并停止
+fn test_profiler_pmu_events() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let events = [
+        PmuEvent::Cycles,
+        PmuEvent::Instructions,
+        PmuEvent::CacheReferences,
+        PmuEvent::CacheMisses,
+        PmuEvent::BranchMisses,
+        PmuEvent::L1DataReadMisses,
+    ];
+    let mut buffer = [0u8; 4096];
+    let (mut started, mut sampled) = (0, 0);
+    for event in events {
+        profiler::reset();
+        match profiler::start(SampleSource::Pmu { event, period: 10_000 }) {
+            Ok(()) => started += 1,
+            Err(ProfilerError::PmuUnavailable) => continue,
+            Err(e) => {
+                println!("  FAIL: {}: {:?}", event, e);
+                return TestResult::Fail;
+            }
+        }
+        // 访问内存并执行分支，让各类事件都有机会发生
+        let until = scheduler::now_ticks() + 10 * ticks_per_ms();
+        let mut i = 0;
+        while scheduler::now_ticks() < until {
+            buffer[i % buffer.len()] = buffer[(i * 67) % buffer.len()].wrapping_add(i as u8);
+            i += 1;
+        }
+        let stopped = profiler::stop();
+        if stopped.is_err() || profiler::is_running() {
+            println!("  FAIL: {}: stop={:?}", event, stopped);
+            return TestResult::Fail;
+        }
+        if profiler::sample_count() > 0 {
+            sampled += 1;
+        }
+    }
+    core::hint::black_box(&buffer);
+    println!("  {} of {} events started, {} sampled", started, events.len(), sampled);
+    // 没有可用的PMU计数器，或溢出中断未送达时跳过
+    if sampled == 0 {
+        return TestResult::Skip;
+    }
+    TestResult::Pass
+}
+
+/// 测试符号解析：地址落在最近的前一个符号内，无序符号表被拒绝
+fn test_symbol_lookup() -> TestResult {
+    static SYMBOLS: [KernelSymbol; 2] = [
+        KernelSymbol { addr: 0x8020_0000, name: "_start" },
+        KernelSymbol { addr: 0x8020_0100, name: "rust_main" },
+    ];
+    static UNSORTED: [KernelSymbol; 2] = [
+        KernelSymbol { addr: 0x8020_0100, name: "b" },
+        KernelSymbol { addr: 0x8020_0000, name: "a" },
+    ];
+    let rejected = !ksyms::install(&UNSORTED);
+    let inside = ksyms::lookup(&SYMBOLS, 0x8020_0108).map(|(s, off)| (s.name, off));
+    let before = ksyms::lookup(&SYMBOLS, 0x8000_0000);
+    if rejected && inside == Some(("rust_main", 8)) && before.is_none() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: rejected={}, inside={:?}, before={:?}", rejected, inside, before);
+        TestResult::Fail
+    }
+}
+
+fn has_embedded_symbols() -> bool {
+    ksyms::embedded().is_some()
+}
+
+/// 测试内嵌符号表：内核函数的地址解析为它自己的名字
+fn test_embedded_symbols() -> TestResult {
+    let addr = test_embedded_symbols as usize;
+    let count = ksyms::embedded().map_or(0, |table| table.len());
+    let resolved = ksyms::embedded().and_then(|table| table.lookup(addr + 4));
+    println!("  {} symbols, {:#x} -> {:?}", count, addr + 4, resolved);
+    match resolved {
+        Some((name, 4)) if name.ends_with("test_embedded_symbols") => TestResult::Pass,
+        _ => TestResult::Fail,
+    }
+}
+
+#[inline(never)]
+fn nested_capture(depth: usize) -> backtrace::Backtrace {
+    // black_box让每层都不是尾调用，各自留下一帧
+    let trace = if depth == 0 { backtrace::capture() } else { nested_capture(depth - 1) };
+    core::hint::black_box(trace)
+}
+
+/// 测试帧指针回溯：嵌套调用的每一层都出现在回溯中，第一帧落在捕获的调用者内
+fn test_backtrace_capture() -> TestResult {
+    let trace = nested_capture(3);
+    let frames = trace.frames();
+    let caller = nested_capture as usize;
+    println!("  {} frames: {:x?}", frames.len(), frames);
+    // 三层递归加最外层调用各返回到nested_capture，最后一层返回到本测试
+    let in_caller = frames.iter().take(4).all(|&pc| pc > caller && pc < caller + 0x200);
+    let reaches_test = frames.get(4).is_some_and(|&pc| pc > test_backtrace_capture as usize);
+    if frames.len() >= 5 && in_caller && reaches_test {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 分析器测试套件
+static SUITE: TestSuite = TestSuite::new("Profiler", 80);
+
+// 扁平剖析表在停止采样后保留
+crate::kernel_test!(SUITE, "profiler_start_stop", test_profiler_start_stop,
+    "Invalid periods and double starts or stops are rejected", allow_leak);
+crate::kernel_test!(SUITE, "profiler_timer_samples", test_profiler_timer_samples,
+    "Timer sampling collects PCs into the flat profile", allow_leak);
+crate::kernel_test!(SUITE, "profiler_pmu_events", test_profiler_pmu_events,
+    "PMU counter overflows sample every supported hardware event", allow_leak);
+crate::kernel_test!(SUITE, "symbol_lookup", test_symbol_lookup, "Addresses resolve to the closest preceding symbol");
+crate::kernel_test!(SUITE, "embedded_symbols", test_embedded_symbols,
+    "Kernel addresses resolve through the table embedded after linking",
+    requires [Requirement::Probe("embedded symbol table", has_embedded_symbols)]);
+crate::kernel_test!(SUITE, "backtrace_capture", test_backtrace_capture,
+    "Frame-pointer backtraces list every frame of a nested call");
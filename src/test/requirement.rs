@@ -0,0 +1,76 @@
+// 测试的运行条件
+//
+// 测试以`kernel_test!`的`requires [...]`声明所需的SBI扩展、hart数或设备，运行器在测试前逐项检查，
+// 不满足时以原因跳过该测试，而不是在精简的平台上报告失败。
+//
+// SBI扩展的探测结果在首次检查时缓存，之后的测试不再陷入固件；hart数来自启动时缓存的cpuinfo。
+// 设备随测试注册与注销，每次检查当前的状态。
+
+use crate::block;
+use crate::smp;
+use crate::util::sbi;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+
+/// 测试的一项运行条件
+#[derive(Debug, Clone, Copy)]
+pub enum Requirement {
+    /// SBI扩展可用：扩展ID与名称
+    SbiExtension(usize, &'static str),
+    /// 至少有这么多hart
+    Harts(usize),
+    /// 注册了该名称的块设备
+    Disk(&'static str),
+    /// 由函数检查的条件，带有描述
+    Probe(&'static str, fn() -> bool),
+}
+
+/// SBI的HSM扩展，hart的启动与停止
+pub const HSM: Requirement = Requirement::SbiExtension(sbi::extension_ids::HSM, "HSM");
+/// 启动hart之外至少还有一个hart
+pub const SECOND_HART: Requirement = Requirement::Harts(2);
+/// virtio-blk设备，注册为`vda`
+pub const VIRTIO_BLK: Requirement = Requirement::Disk("vda");
+
+/// 已探测的SBI扩展及其是否可用
+static SBI_EXTENSIONS: Mutex<Vec<(usize, bool)>> = Mutex::new(Vec::new());
+
+/// 扩展`id`是否可用，每个扩展只探测一次
+fn sbi_extension_available(id: usize) -> bool {
+    let mut probed = SBI_EXTENSIONS.lock();
+    if let Some(&(_, available)) = probed.iter().find(|(probed_id, _)| *probed_id == id) {
+        return available;
+    }
+    let available = sbi::info::is_extension_available(id);
+    probed.push((id, available));
+    available
+}
+
+impl Requirement {
+    /// 条件是否满足
+    pub fn is_met(&self) -> bool {
+        match *self {
+            Requirement::SbiExtension(id, _) => sbi_extension_available(id),
+            Requirement::Harts(count) => smp::present_mask().count_ones() as usize >= count,
+            Requirement::Disk(name) => block::find(name).is_some(),
+            Requirement::Probe(_, probe) => probe(),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::SbiExtension(_, name) => write!(f, "SBI {} extension", name),
+            Requirement::Harts(count) => write!(f, "{} harts", count),
+            Requirement::Disk(name) => write!(f, "block device {}", name),
+            Requirement::Probe(description, _) => f.write_str(description),
+        }
+    }
+}
+
+/// 第一个不满足的条件
+pub fn first_unmet(requires: &[Requirement]) -> Option<Requirement> {
+    requires.iter().find(|r| !r.is_met()).copied()
+}
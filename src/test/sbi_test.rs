@@ -1,7 +1,12 @@
 // SBI功能测试模块
 
-use super::{TestCase, TestResult, TestRunner};
-use crate::{util::sbi, println};
+use super::{requirement, TestResult, TestSuite};
+use crate::{boot, platform, time, util::sbi, println};
+use crate::boot::park;
+use crate::smp::{self, HartState, SmpError};
+use crate::util::sbi::hsm;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// 测试SBI基础扩展
 fn test_sbi_base_extension() -> TestResult {
@@ -73,31 +78,143 @@ fn test_console_extension() -> TestResult {
     }
 }
 
-/// SBI测试用例列表
-const SBI_TESTS: &[TestCase] = &[
-    TestCase {
-        name: "sbi_base_extension",
-        func: test_sbi_base_extension,
-        description: "Test SBI base extension functionality"
-    },
-    TestCase {
-        name: "sbi_extension_probe",
-        func: test_sbi_extension_probe,
-        description: "Test SBI extension availability probing"
-    },
-    TestCase {
-        name: "timer_extension",
-        func: test_timer_extension,
-        description: "Test SBI timer extension"
-    },
-    TestCase {
-        name: "console_extension",
-        func: test_console_extension,
-        description: "Test SBI console functionality"
-    },
-];
-
-/// 运行所有SBI测试
-pub fn run_sbi_tests(runner: &mut TestRunner) {
-    runner.run_suite("SBI", SBI_TESTS);
-}
\ No newline at end of file
+/// 测试启动参数：OpenSBI传入的DTB地址指向有效的设备树头
+fn test_boot_info() -> TestResult {
+    const FDT_MAGIC: u32 = 0xd00d_feed;
+    let info = match boot::boot_info() {
+        Some(info) => info,
+        None => {
+            println!("  Boot arguments not recorded");
+            return TestResult::Fail;
+        }
+    };
+    println!("  Boot hart: {}, DTB at {:#x}", info.hart_id, info.dtb_addr);
+    if info.dtb_addr == 0 || info.dtb_addr % 8 != 0 {
+        return TestResult::Fail;
+    }
+    // 设备树头以大端序存储
+    let magic = u32::from_be(unsafe { core::ptr::read_volatile(info.dtb_addr as *const u32) });
+    if magic == FDT_MAGIC {
+        TestResult::Pass
+    } else {
+        println!("  Bad FDT magic: {:#x}", magic);
+        TestResult::Fail
+    }
+}
+
+/// 被释放的hart写入的 hart ID + 参数
+static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+/// 停放测试中hart被释放后的入口：记录后停止自身
+extern "C" fn released_entry(hart_id: usize, arg: usize) -> ! {
+    RELEASED.store(hart_id + arg, Ordering::Release);
+    loop {
+        let _ = hsm::hart_stop();
+    }
+}
+
+/// 在 `timeout_ms` 内等待 `done` 成立
+fn wait_for(timeout_ms: u64, done: impl Fn() -> bool) -> bool {
+    let deadline = time::deadline_after_ms(timeout_ms);
+    while !done() {
+        if time::reached(deadline) {
+            return false;
+        }
+    }
+    true
+}
+
+/// 测试多hart启动停放：从 _start 启动的其他hart抽签落选后停放，释放后运行指定入口
+fn test_hart_parking() -> TestResult {
+    const ARG: usize = 0x1000;
+    extern "C" {
+        fn _start();
+    }
+    let boot_hart = boot::boot_info().map_or(0, |info| info.hart_id);
+    let stopped = (0..platform::get().hart_count.min(park::MAX_PARKED)).find(|&hart| {
+        hart != boot_hart && hsm::hart_get_status(hart) == Ok(hsm::HART_STATE_STOPPED)
+    });
+    let Some(hart) = stopped else {
+        // 其它hart都在运行，无法从_start重新启动
+        return TestResult::Skip;
+    };
+
+    if hsm::hart_start(hart, _start as usize, 0).is_err() {
+        println!("  Failed to start hart {}", hart);
+        return TestResult::Fail;
+    }
+    if !wait_for(100, || park::is_parked(hart)) {
+        println!("  Hart {} did not park", hart);
+        return TestResult::Fail;
+    }
+    if park::parked_harts().all(|h| h != hart) || park::is_parked(boot_hart) {
+        println!("  Parked mask {:#x} is inconsistent", park::parked_mask());
+        return TestResult::Fail;
+    }
+
+    RELEASED.store(0, Ordering::Relaxed);
+    let stack = vec![0u128; 512];
+    let stack_top = stack.as_ptr_range().end as usize;
+    if let Err(e) = unsafe { park::release(hart, released_entry, stack_top, ARG) } {
+        println!("  Failed to release hart {}: {}", hart, e);
+        return TestResult::Fail;
+    }
+    let ran = wait_for(100, || RELEASED.load(Ordering::Acquire) == hart + ARG);
+    // 等hart停止后才释放它的栈
+    let stopped = wait_for(100, || hsm::hart_get_status(hart) == Ok(hsm::HART_STATE_STOPPED));
+    if !stopped {
+        core::mem::forget(stack);
+    }
+    let released_twice = unsafe { park::release(hart, released_entry, stack_top, ARG) };
+
+    if ran && stopped && released_twice == Err(park::ParkError::NotParked) {
+        TestResult::Pass
+    } else {
+        println!("  ran={}, stopped={}, second release={:?}", ran, stopped, released_twice);
+        TestResult::Fail
+    }
+}
+
+/// 测试hart热插拔：停止的hart上线后停放，下线后停止并退出在线掩码；启动hart不能下线
+fn test_hart_hotplug() -> TestResult {
+    let boot_hart = boot::boot_info().map_or(0, |info| info.hart_id);
+    let secondary = (0..park::MAX_PARKED).find(|&hart| {
+        hart != boot_hart && smp::present_mask() & (1 << hart) != 0
+            && matches!(smp::hart_state(hart), HartState::Stopped | HartState::Parked)
+    });
+    let Some(hart) = secondary else {
+        // 其它hart都在运行，不能从外部停止
+        return TestResult::Skip;
+    };
+
+    let online = smp::online_hart(hart);
+    let parked = smp::hart_state(hart) == HartState::Parked && smp::is_online(hart);
+    let offline = smp::offline_hart(hart);
+    let stopped = smp::hart_state(hart) == HartState::Stopped && !smp::is_online(hart);
+    let boot_refused = smp::offline_hart(boot_hart) == Err(SmpError::BootHart);
+    let runs_tasks = crate::task::scheduler::hart_loads().iter().any(|l| l.online);
+
+    if online.is_ok() && parked && offline.is_ok() && stopped && boot_refused && runs_tasks {
+        TestResult::Pass
+    } else {
+        println!("  hart {}: online={:?}, parked={}, offline={:?}, stopped={}, boot refused={}, runs tasks={}",
+                 hart, online, parked, offline, stopped, boot_refused, runs_tasks);
+        TestResult::Fail
+    }
+}
+
+/// SBI测试套件
+static SUITE: TestSuite = TestSuite::new("SBI", 20);
+
+crate::kernel_test!(SUITE, "sbi_base_extension", test_sbi_base_extension, "Test SBI base extension functionality");
+crate::kernel_test!(SUITE, "sbi_extension_probe", test_sbi_extension_probe, "Test SBI extension availability probing");
+crate::kernel_test!(SUITE, "timer_extension", test_timer_extension, "Test SBI timer extension");
+crate::kernel_test!(SUITE, "console_extension", test_console_extension, "Test SBI console functionality");
+crate::kernel_test!(SUITE, "boot_info", test_boot_info, "Boot hart ID and DTB pointer are captured from OpenSBI");
+crate::kernel_test!(SUITE, "hart_parking", test_hart_parking,
+    "Harts entering _start after the boot hart park until released",
+    requires [requirement::HSM, requirement::SECOND_HART]);
+// 下线的hart上排队的任务移到其他hart的就绪队列，队列扩大的容量保留
+crate::kernel_test!(SUITE, "hart_hotplug", test_hart_hotplug,
+    "Secondary harts go offline through HSM and come back parked", allow_leak,
+    requires [requirement::HSM, requirement::SECOND_HART]);
@@ -0,0 +1,134 @@
+// 运行时可调参数测试模块
+
+use super::{TestResult, TestSuite};
+use crate::console;
+use crate::println;
+use crate::sysctl::{self, Kind, SysctlError, Tunable, Value};
+use core::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// 变更回调看到的最近一个值与调用次数
+static LAST_CHANGE: AtomicI64 = AtomicI64::new(0);
+static CHANGES: AtomicU64 = AtomicU64::new(0);
+
+fn check_level(value: &Value) -> Result<(), &'static str> {
+    match value.as_int() {
+        Some(0..=10) => Ok(()),
+        _ => Err("level is 0 to 10"),
+    }
+}
+
+fn record_change(value: &Value) {
+    LAST_CHANGE.store(value.as_int().unwrap_or(-1), Ordering::Relaxed);
+    CHANGES.fetch_add(1, Ordering::Relaxed);
+}
+
+fn level_tunable() -> Tunable {
+    Tunable {
+        name: "test.sysctl.level",
+        description: "Test level",
+        default: Value::Int(5),
+        validate: Some(check_level),
+        changed: Some(record_change),
+    }
+}
+
+/// 测试设置与读取：类型检查、校验回调、变更回调、文本解析与恢复默认值
+fn test_sysctl_set() -> TestResult {
+    CHANGES.store(0, Ordering::Relaxed);
+    let registered = sysctl::register(level_tunable());
+    let flag = sysctl::register(Tunable {
+        name: "test.sysctl.flag",
+        description: "Test flag",
+        default: Value::Bool(false),
+        validate: None,
+        changed: None,
+    });
+    let initial = sysctl::get("test.sysctl.level");
+    let set = sysctl::set("test.sysctl.level", Value::Int(7));
+    let seen = LAST_CHANGE.load(Ordering::Relaxed);
+    let refused = sysctl::set("test.sysctl.level", Value::Int(11));
+    let wrong_type = sysctl::set("test.sysctl.level", Value::Bool(true));
+    let hex = sysctl::set_str("test.sysctl.level", "0x3");
+    let bad_text = sysctl::set_str("test.sysctl.level", "three");
+    let parsed = sysctl::get("test.sysctl.level");
+    let flag_on = sysctl::set_str("test.sysctl.flag", "on").and_then(|_| sysctl::get("test.sysctl.flag"));
+    let listed = sysctl::list("test.sysctl").iter().map(|e| e.name).eq(["test.sysctl.flag", "test.sysctl.level"]);
+    let reset = sysctl::reset("test.sysctl.level").and_then(|_| sysctl::get("test.sysctl.level"));
+    let changes = CHANGES.load(Ordering::Relaxed);
+    let removed = (sysctl::unregister("test.sysctl.level"), sysctl::unregister("test.sysctl.flag"));
+    let gone = sysctl::get("test.sysctl.level");
+
+    if registered.is_ok() && flag.is_ok() && initial == Ok(Value::Int(5)) && set.is_ok() && seen == 7
+        && refused == Err(SysctlError::Rejected("level is 0 to 10"))
+        && wrong_type == Err(SysctlError::WrongType(Kind::Int)) && hex.is_ok()
+        && bad_text == Err(SysctlError::Parse(Kind::Int)) && parsed == Ok(Value::Int(3))
+        && flag_on == Ok(Value::Bool(true)) && listed && reset == Ok(Value::Int(5)) && changes == 3
+        && removed == (Ok(Value::Int(5)), Ok(Value::Bool(true))) && gone == Err(SysctlError::NotFound) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: registered={:?}, flag={:?}, initial={:?}, set={:?}, seen={}, refused={:?}, wrong_type={:?}",
+                 registered, flag, initial, set, seen, refused, wrong_type);
+        println!("        hex={:?}, bad_text={:?}, parsed={:?}, flag_on={:?}, listed={}, reset={:?}, changes={}, removed={:?}",
+                 hex, bad_text, parsed, flag_on, listed, reset, changes, removed);
+        TestResult::Fail
+    }
+}
+
+/// 测试名称规则：非法名称、重复注册、参数与目录同名，以及按目录列出与命令接口
+fn test_sysctl_names() -> TestResult {
+    let invalid = ["", "test..x", ".test", "test.", "Test.x", "test.a-b"]
+        .iter()
+        .all(|&name| sysctl::register(Tunable { name, ..level_tunable() }) == Err(SysctlError::InvalidName));
+    let first = sysctl::register(Tunable { name: "test.tree.leaf", ..level_tunable() });
+    let duplicate = sysctl::register(Tunable { name: "test.tree.leaf", ..level_tunable() });
+    let parent = sysctl::register(Tunable { name: "test.tree", ..level_tunable() });
+    let child = sysctl::register(Tunable { name: "test.tree.leaf.sub", ..level_tunable() });
+    let bad_default = sysctl::register(Tunable { name: "test.tree.other", default: Value::Int(20), ..level_tunable() });
+    // 目录按完整的名称段匹配
+    let partial = sysctl::list("test.tre").len();
+    let command = sysctl::command("test.tree.leaf=9");
+    let value = sysctl::get("test.tree.leaf");
+    let unknown = sysctl::command("test.none");
+    let removed = sysctl::unregister("test.tree.leaf");
+
+    if invalid && first.is_ok() && duplicate == Err(SysctlError::AlreadyRegistered)
+        && parent == Err(SysctlError::Conflict) && child == Err(SysctlError::Conflict)
+        && bad_default == Err(SysctlError::Rejected("invalid default")) && partial == 0 && command.is_ok()
+        && value == Ok(Value::Int(9)) && unknown == Err(SysctlError::NotFound) && removed.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: invalid={}, first={:?}, duplicate={:?}, parent={:?}, child={:?}, bad_default={:?}",
+                 invalid, first, duplicate, parent, child, bad_default);
+        println!("        partial={}, command={:?}, value={:?}, unknown={:?}, removed={:?}",
+                 partial, command, value, unknown, removed);
+        TestResult::Fail
+    }
+}
+
+/// 测试kernel.log_level：改变控制台日志级别，超出范围的值被拒绝
+fn test_sysctl_log_level() -> TestResult {
+    let before = sysctl::get("kernel.log_level");
+    let lowered = sysctl::set("kernel.log_level", Value::Int(console::LOG_WARN as i64)).map(|_| console::log_level());
+    let info_shown = console::log_enabled(console::LOG_INFO);
+    let refused = sysctl::set("kernel.log_level", Value::Int(0));
+    let restored = before.clone().and_then(|value| sysctl::set("kernel.log_level", value));
+
+    if before.is_ok() && lowered == Ok(console::LOG_WARN) && !info_shown
+        && matches!(refused, Err(SysctlError::Rejected(_))) && restored.is_ok() {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: before={:?}, lowered={:?}, info_shown={}, refused={:?}, restored={:?}",
+                 before, lowered, info_shown, refused, restored);
+        TestResult::Fail
+    }
+}
+
+/// 运行时可调参数测试套件
+static SUITE: TestSuite = TestSuite::new("Sysctl", 135);
+
+crate::kernel_test!(SUITE, "sysctl_set", test_sysctl_set,
+    "Tunables check types and validators, notify changes, parse text and reset to defaults");
+crate::kernel_test!(SUITE, "sysctl_names", test_sysctl_names,
+    "Invalid, duplicate and directory-clashing names are refused; directories match whole parts");
+crate::kernel_test!(SUITE, "sysctl_log_level", test_sysctl_log_level,
+    "kernel.log_level changes the console log level within its range");
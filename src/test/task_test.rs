@@ -0,0 +1,225 @@
+// 任务管理测试模块
+
+use super::{TestResult, TestSuite};
+use crate::println;
+use crate::task::signal::DefaultAction;
+use crate::task::{self, futex, scheduler, timer, Signal, SignalAction, SignalState, TaskError, ALL_HARTS, MAX_HARTS};
+use crate::mm::{self, page_table, PAGE_SIZE};
+use crate::platform;
+use crate::task::task::KernelStack;
+use core::sync::atomic::AtomicU32;
+
+/// 测试信号默认动作
+fn test_signal_default_actions() -> TestResult {
+    if Signal::Segv.default_action() == DefaultAction::Terminate
+        && Signal::Chld.default_action() == DefaultAction::Ignore
+        && Signal::from_number(11) == Some(Signal::Segv)
+        && Signal::from_number(3).is_none() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试SIGKILL的动作不可修改，其余信号可修改
+fn test_signal_actions() -> TestResult {
+    let mut state = SignalState::new();
+    let kill = state.set_action(Signal::Kill, SignalAction::Ignore);
+    let usr1 = state.set_action(Signal::Usr1, SignalAction::Ignore);
+    if kill == Err(TaskError::InvalidArgument)
+        && usr1 == Ok(SignalAction::Default)
+        && state.action(Signal::Usr1) == SignalAction::Ignore
+        && state.action(Signal::Kill) == SignalAction::Default {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: kill={:?}, usr1={:?}", kill, usr1);
+        TestResult::Fail
+    }
+}
+
+/// 测试fork继承信号动作但不继承待处理信号
+fn test_signal_inherit() -> TestResult {
+    let mut parent = SignalState::new();
+    let _ = parent.set_action(Signal::Term, SignalAction::Ignore);
+    parent.post(Signal::Usr2);
+
+    let child = parent.inherit();
+    if parent.has_pending() && !child.has_pending() && child.action(Signal::Term) == SignalAction::Ignore {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试futex：值不匹配时不阻塞，未对齐地址被拒绝，无等待者时唤醒数为0
+fn test_futex_no_block() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let word = AtomicU32::new(1);
+    let addr = &word as *const AtomicU32 as usize;
+
+    let mismatch = futex::wait(addr, 0);
+    let misaligned = futex::wait(addr + 1, 1);
+    let woken = futex::wake(addr, 1);
+    if mismatch == Err(TaskError::WouldBlock)
+        && misaligned == Err(TaskError::InvalidArgument)
+        && woken == Ok(0)
+        && futex::waiter_count(addr) == Ok(0) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: mismatch={:?}, misaligned={:?}, woken={:?}", mismatch, misaligned, woken);
+        TestResult::Fail
+    }
+}
+
+/// 测试定时器按截止时间编程，无截止时间时解除
+fn test_timer_program() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let before = timer::stats();
+    let deadline = scheduler::now_ticks() + 1000 * scheduler::ticks_per_ms();
+    timer::program(Some(deadline));
+    let armed = timer::armed_deadline();
+    timer::program(None);
+    let disarmed = timer::armed_deadline();
+    // 恢复调度器的下一个截止时间
+    timer::program(scheduler::next_wakeup());
+    let after = timer::stats();
+    // Sstc路径仅在平台报告该扩展时启用；两次改变截止时间都应写入硬件
+    let path_ok = (after.path == timer::TimerPath::Sstc) == platform::get().sstc;
+    if armed == Some(deadline) && disarmed.is_none() && path_ok && after.reprograms >= before.reprograms + 2 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: armed={:?}, disarmed={:?}, stats={:?}", armed, disarmed, after);
+        TestResult::Fail
+    }
+}
+
+/// 测试CPU亲和性设置：不允许任何在线hart的掩码被拒绝
+fn test_affinity() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let pid = task::current_pid();
+    let none = task::set_affinity(pid, 0);
+    let offline_only = task::set_affinity(pid, 1 << (MAX_HARTS - 1));
+    let boot_only = task::set_affinity(pid, 1);
+    let mask = task::affinity(pid);
+    let _ = task::set_affinity(pid, ALL_HARTS);
+    let missing = task::set_affinity(u64::MAX, ALL_HARTS);
+
+    let loads = scheduler::hart_loads();
+    let boot_online = loads.first().map_or(false, |l| l.online);
+    if none == Err(TaskError::InvalidArgument) && offline_only == Err(TaskError::InvalidArgument)
+        && boot_only.is_ok() && mask == Some(1) && missing == Err(TaskError::NoSuchTask)
+        && loads.len() == MAX_HARTS && boot_online {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: none={:?}, offline_only={:?}, boot_only={:?}, mask={:?}, missing={:?}",
+                 none, offline_only, boot_only, mask, missing);
+        TestResult::Fail
+    }
+}
+
+/// 测试CPU时间统计：让出CPU后系统时间只增不减，当前任务有统计记录
+fn test_cpu_accounting() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let pid = task::current_pid();
+    let before = task::cpu_times(pid);
+    let stats_before = task::cpu_stats();
+    task::yield_now();
+    let after = task::cpu_times(pid);
+    let stats_after = task::cpu_stats();
+
+    let monotonic = match (before, after) {
+        (Some(b), Some(a)) => a.system >= b.system && a.user >= b.user && a.irq >= b.irq,
+        _ => false,
+    };
+    let missing = task::cpu_times(u64::MAX);
+    if monotonic && missing.is_none() && stats_after.system >= stats_before.system
+        && stats_after.idle >= stats_before.idle {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: before={:?}, after={:?}, missing={:?}", before, after, missing);
+        TestResult::Fail
+    }
+}
+
+/// 测试内核栈金丝雀与保护页：保护页未映射，覆盖栈底后金丝雀失效
+fn test_stack_canary() -> TestResult {
+    let stack = match KernelStack::new(task::KERNEL_STACK_SIZE) {
+        Ok(stack) => stack,
+        Err(e) => {
+            println!("  FAIL: cannot allocate stack: {:?}", e);
+            return TestResult::Fail;
+        }
+    };
+    let intact = stack.canary_intact();
+    // 分页启用后栈映射在内核栈区域，下方保护页不可访问
+    let guard_ok = match (mm::kernel_root(), stack.guard_page()) {
+        (Some(root), Some(guard)) => guard == stack.bottom() - PAGE_SIZE
+            && page_table::translate(root, guard).is_none()
+            && page_table::translate(root, stack.bottom()).is_some(),
+        (None, None) => true,
+        _ => false,
+    };
+    unsafe { (((stack.bottom() + 7) & !7) as *mut u64).write_volatile(0) };
+    let detected = !stack.canary_intact();
+
+    if intact && guard_ok && detected {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: intact={}, guard_ok={}, detected={}", intact, guard_ok, detected);
+        TestResult::Fail
+    }
+}
+
+/// 测试抢占控制：计数可嵌套，最外层恢复时执行挂起的重新调度
+fn test_preempt_count() -> TestResult {
+    if !scheduler::is_initialized() {
+        return TestResult::Skip;
+    }
+    let base = task::preempt_count();
+    task::preempt_disable();
+    task::preempt_disable();
+    let nested = task::preempt_count();
+    scheduler::request_resched();
+    task::preempt_enable();
+    // 内层恢复不会执行调度
+    let still_pending = scheduler::resched_pending();
+    task::preempt_enable();
+    let taken = !scheduler::resched_pending();
+
+    if nested == base + 2 && still_pending && taken && task::preempt_count() == base {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: base={}, nested={}, still_pending={}, taken={}",
+                 base, nested, still_pending, taken);
+        TestResult::Fail
+    }
+}
+
+/// 任务测试套件
+static SUITE: TestSuite = TestSuite::new("Task", 60);
+
+crate::kernel_test!(SUITE, "signal_default_actions", test_signal_default_actions,
+    "Signals map to Linux numbers and default actions");
+crate::kernel_test!(SUITE, "signal_actions", test_signal_actions,
+    "SIGKILL cannot be caught; other actions can be replaced");
+crate::kernel_test!(SUITE, "signal_inherit", test_signal_inherit,
+    "Forked children inherit actions but not pending signals");
+crate::kernel_test!(SUITE, "futex_no_block", test_futex_no_block, "futex_wait returns at once when the word differs");
+crate::kernel_test!(SUITE, "timer_program", test_timer_program,
+    "The timer is armed for a deadline and disarmed without one");
+crate::kernel_test!(SUITE, "affinity", test_affinity, "Affinity masks must allow an online hart");
+// 以下测试会让出CPU，其间其他任务的分配也会计入
+crate::kernel_test!(SUITE, "cpu_accounting", test_cpu_accounting, "Per-task and system CPU times never decrease",
+    allow_leak);
+crate::kernel_test!(SUITE, "stack_canary", test_stack_canary,
+    "Kernel stacks have a guard page and a canary that detects overflow");
+crate::kernel_test!(SUITE, "preempt_count", test_preempt_count,
+    "Preemption counts nest and the outermost enable reschedules", allow_leak);
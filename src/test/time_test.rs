@@ -0,0 +1,133 @@
+// 单调时钟测试模块
+
+use super::{TestResult, TestSuite};
+use crate::println;
+use crate::time::{self, wall, DateTime, Instant, MSEC_PER_SEC, NSEC_PER_SEC, USEC_PER_SEC};
+use alloc::format;
+use alloc::string::ToString;
+use core::time::Duration;
+
+/// 单位换算：128位中间值不溢出，超时向上取整，非整千赫兹时基不丢精度
+fn test_time_conversions() -> TestResult {
+    let one_second = time::scale(10_000_000, 10_000_000, NSEC_PER_SEC);
+    let saturated = time::scale(u64::MAX, 1, MSEC_PER_SEC);
+    // 约584年的纳秒数乘以时基仍可换算
+    let large = time::scale(u64::MAX / 2, NSEC_PER_SEC, 10_000_000);
+    let rounded_down = time::scale(999, 10_000_000, USEC_PER_SEC);
+    let rounded_up = time::scale_ceil(1, MSEC_PER_SEC, 1_000_003);
+    let freq = time::frequency();
+    let round_trip = time::ticks_to_duration(time::duration_to_ticks(Duration::from_millis(250)));
+
+    if one_second == NSEC_PER_SEC && saturated == u64::MAX && large == u64::MAX / 2 / 100
+        && rounded_down == 99 && rounded_up == 1001 && time::ms_to_ticks(1000) == freq
+        && time::ticks_to_us(freq) == USEC_PER_SEC && round_trip == Duration::from_millis(250) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: one_second={}, saturated={}, large={}, rounded_down={}, rounded_up={}, round_trip={:?}",
+                 one_second, saturated, large, rounded_down, rounded_up, round_trip);
+        TestResult::Fail
+    }
+}
+
+/// 计数器回绕：比较与差值跨越回绕点仍然正确
+fn test_time_wraparound() -> TestResult {
+    let before_wrap = u64::MAX - 5;
+    let after_wrap = 3u64;
+    if time::ticks_before(before_wrap, after_wrap) && !time::ticks_before(after_wrap, before_wrap)
+        && !time::ticks_before(after_wrap, after_wrap) && after_wrap.wrapping_sub(before_wrap) == 9
+        && time::reached(time::now_ticks()) && !time::reached(time::deadline_after_ms(1000)) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: wrap-around comparisons");
+        TestResult::Fail
+    }
+}
+
+/// 单调时钟不回退，随时间前进
+fn test_time_monotonic() -> TestResult {
+    let mut previous = time::monotonic_ns();
+    let mut backwards = 0;
+    for _ in 0..1000 {
+        let now = time::monotonic_ns();
+        if now < previous {
+            backwards += 1;
+        }
+        previous = now;
+    }
+    let start = Instant::now();
+    let start_us = time::monotonic_us();
+    while start.elapsed() < Duration::from_millis(2) {
+        core::hint::spin_loop();
+    }
+    let waited_us = time::monotonic_us() - start_us;
+    let boot = start.since_boot();
+
+    if backwards == 0 && waited_us >= 2000 && boot.as_nanos() as u64 <= time::monotonic_ns()
+        && Instant::now() > start && start.duration_since(Instant::now()) == Duration::ZERO {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: backwards={}, waited_us={}, boot={:?}", backwards, waited_us, boot);
+        TestResult::Fail
+    }
+}
+
+/// 忙等待延时：不短于要求的时长，短延时走校准过的循环
+fn test_time_delay() -> TestResult {
+    let measure = |delay: &dyn Fn()| {
+        let start = time::now_ticks();
+        delay();
+        time::now_ticks().wrapping_sub(start)
+    };
+    let udelay = measure(&|| time::udelay(500));
+    let mdelay = measure(&|| time::mdelay(2));
+    // 1µs 不足一次计数器读数的精度要求，走校准循环；读数量化最多少计一个tick
+    let ndelay = measure(&|| time::ndelay(1000));
+    let zero = measure(&|| time::ndelay(0));
+
+    if time::delay::is_calibrated() && udelay >= time::us_to_ticks(500) && mdelay >= time::ms_to_ticks(2)
+        && ndelay + 1 >= time::ns_to_ticks(1000) && zero < time::ms_to_ticks(1) {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: calibrated={} ({} loops/s), udelay(500)={} ticks, mdelay(2)={} ticks, ndelay(1000)={} ticks",
+                 time::delay::is_calibrated(), time::delay::loops_per_sec(), udelay, mdelay, ndelay);
+        TestResult::Fail
+    }
+}
+
+/// 日历换算：已知时刻、闰日、世纪年与往返换算
+fn test_time_calendar() -> TestResult {
+    let epoch = DateTime::from_unix(0, 0);
+    let leap_day = DateTime::from_unix(951_782_400, 0);
+    let known = DateTime::from_unix_ns(1_700_000_000_123_456_789);
+    let formatted = format!("{}", known);
+    // 1900年不是闰年，2000年是
+    let not_leap = wall::days_from_civil(1900, 3, 1) - wall::days_from_civil(1900, 2, 28);
+    let leap = wall::days_from_civil(2000, 3, 1) - wall::days_from_civil(2000, 2, 28);
+    let round_trip = (0..800).map(|i| i * 86_400 * 183 + i * 4_001).all(|secs| {
+        DateTime::from_unix(secs, 0).to_unix() == secs
+    });
+    let days_ok = (-1000..1000).all(|d| {
+        let (y, m, dd) = wall::civil_from_days(d * 97);
+        wall::days_from_civil(y, m, dd) == d * 97
+    });
+
+    if epoch.to_string() == "1970-01-01T00:00:00.000Z" && (leap_day.month, leap_day.day) == (2, 29)
+        && formatted == "2023-11-14T22:13:20.123Z" && not_leap == 1 && leap == 2 && round_trip && days_ok
+        && wall::civil_from_days(-1) == (1969, 12, 31) && time::now().year >= 1970 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: epoch={}, leap_day={}, known={}, round_trip={}, days_ok={}",
+                 epoch, leap_day, formatted, round_trip, days_ok);
+        TestResult::Fail
+    }
+}
+
+/// 单调时钟测试套件
+static SUITE: TestSuite = TestSuite::new("Time", 150);
+
+crate::kernel_test!(SUITE, "time_conversions", test_time_conversions,
+    "Tick conversions neither overflow nor end timeouts early");
+crate::kernel_test!(SUITE, "time_wraparound", test_time_wraparound, "Tick comparisons hold across a counter wrap");
+crate::kernel_test!(SUITE, "time_monotonic", test_time_monotonic, "The monotonic clock advances and never goes back");
+crate::kernel_test!(SUITE, "time_delay", test_time_delay, "Busy-wait delays last at least as long as asked");
+crate::kernel_test!(SUITE, "time_calendar", test_time_calendar, "Unix times convert to calendar dates and back");
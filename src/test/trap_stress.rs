@@ -0,0 +1,301 @@
+// 陷阱压力测试工具
+//
+// 在循环中反复注入陷阱，由注册的计数处理函数处理，验证每个陷阱都分发到了对应类型的处理函数，
+// 并测量处理路径的吞吐量。ebreak与非法指令是真实执行的指令，经陷阱入口分发；
+// S态的ecall进入SBI固件，未对齐访问由硬件或固件模拟，都不会陷入内核，因此以合成上下文注入。
+//
+// 计数按hart分开记录。运行内核代码的hart都可以调用`run`，各自注入、各自计数；
+// 目前次级hart停在park中不运行内核，实际只有引导hart参与。
+
+use crate::task::scheduler::current_hart;
+use crate::task::MAX_HARTS;
+use crate::time::Instant;
+use crate::trap::{
+    self, Exception, HandlerHandle, ProtectionLevel, TrapApiError, TrapContext, TrapHandler, TrapHandlerResult,
+    TrapType, KERNEL_REGISTRAR_ID,
+};
+use crate::println;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+/// 合成陷阱的`stval`标记
+const STRESS_STVAL: usize = 0x57e5_5000;
+/// 合成系统调用的调用号 (a7)
+const STRESS_SYSCALL: usize = 0x57e5;
+/// 处理函数的优先级，先于系统的默认处理函数调用
+const STRESS_PRIORITY: u8 = 1;
+
+/// 注入的陷阱种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    Breakpoint,
+    IllegalInstruction,
+    LoadMisaligned,
+    StoreMisaligned,
+    EnvCall,
+}
+
+impl TrapKind {
+    pub const ALL: [TrapKind; 5] = [
+        TrapKind::Breakpoint,
+        TrapKind::IllegalInstruction,
+        TrapKind::LoadMisaligned,
+        TrapKind::StoreMisaligned,
+        TrapKind::EnvCall,
+    ];
+    const COUNT: usize = Self::ALL.len();
+
+    fn trap_type(self) -> TrapType {
+        match self {
+            TrapKind::Breakpoint => TrapType::Breakpoint,
+            TrapKind::IllegalInstruction => TrapType::IllegalInstruction,
+            TrapKind::LoadMisaligned => TrapType::LoadMisaligned,
+            TrapKind::StoreMisaligned => TrapType::StoreMisaligned,
+            TrapKind::EnvCall => TrapType::SystemCall,
+        }
+    }
+
+    fn handler(self) -> TrapHandler {
+        match self {
+            TrapKind::Breakpoint => breakpoint_handler,
+            TrapKind::IllegalInstruction => illegal_instruction_handler,
+            TrapKind::LoadMisaligned => load_misaligned_handler,
+            TrapKind::StoreMisaligned => store_misaligned_handler,
+            TrapKind::EnvCall => env_call_handler,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            TrapKind::Breakpoint => "Trap Stress Breakpoint",
+            TrapKind::IllegalInstruction => "Trap Stress Illegal Instruction",
+            TrapKind::LoadMisaligned => "Trap Stress Load Misaligned",
+            TrapKind::StoreMisaligned => "Trap Stress Store Misaligned",
+            TrapKind::EnvCall => "Trap Stress Ecall",
+        }
+    }
+
+    /// 是否为真实执行的指令，否则以合成上下文注入
+    pub fn is_real(self) -> bool {
+        matches!(self, TrapKind::Breakpoint | TrapKind::IllegalInstruction)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TrapKind::Breakpoint => "ebreak",
+            TrapKind::IllegalInstruction => "illegal",
+            TrapKind::LoadMisaligned => "load-misaligned",
+            TrapKind::StoreMisaligned => "store-misaligned",
+            TrapKind::EnvCall => "ecall",
+        }
+    }
+}
+
+/// 各hart正在注入的陷阱种类 (`TrapKind`的序号加1)，0表示未在注入
+static INJECTING: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+/// 各hart每种陷阱被处理的次数
+static HANDLED: [[AtomicU64; TrapKind::COUNT]; MAX_HARTS] =
+    [const { [const { AtomicU64::new(0) }; TrapKind::COUNT] }; MAX_HARTS];
+/// 各hart的注入陷阱被其它类型的处理函数收到的次数
+static MISDISPATCHED: [AtomicU64; MAX_HARTS] = [const { AtomicU64::new(0) }; MAX_HARTS];
+
+/// 处理函数收到`kind`类型的陷阱：是本hart正在注入的陷阱时计数并跳过该指令
+fn count(kind: TrapKind, context: &mut TrapContext) -> TrapHandlerResult {
+    let hart = current_hart();
+    let injecting = INJECTING[hart].load(Ordering::Acquire);
+    if injecting == 0 {
+        return TrapHandlerResult::Pass;
+    }
+    if injecting != kind as usize + 1 {
+        MISDISPATCHED[hart].fetch_add(1, Ordering::Relaxed);
+        return TrapHandlerResult::Pass;
+    }
+    if !kind.is_real() && context.stval != STRESS_STVAL {
+        return TrapHandlerResult::Pass;
+    }
+    HANDLED[hart][kind as usize].fetch_add(1, Ordering::Relaxed);
+    if kind.is_real() {
+        // 真实指令可能是压缩指令
+        let instruction = unsafe { (context.sepc as *const u16).read() };
+        context.sepc += if instruction & 0b11 == 0b11 { 4 } else { 2 };
+    } else {
+        context.advance_sepc();
+    }
+    TrapHandlerResult::Handled
+}
+
+fn breakpoint_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    count(TrapKind::Breakpoint, context)
+}
+
+fn illegal_instruction_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    count(TrapKind::IllegalInstruction, context)
+}
+
+fn load_misaligned_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    count(TrapKind::LoadMisaligned, context)
+}
+
+fn store_misaligned_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    count(TrapKind::StoreMisaligned, context)
+}
+
+fn env_call_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.x[17] != STRESS_SYSCALL {
+        return TrapHandlerResult::Pass;
+    }
+    count(TrapKind::EnvCall, context)
+}
+
+/// 注入一次`kind`陷阱
+fn inject(kind: TrapKind) {
+    let scause = match kind {
+        TrapKind::Breakpoint => {
+            unsafe { asm!("ebreak") };
+            return;
+        }
+        TrapKind::IllegalInstruction => {
+            unsafe { asm!("unimp") };
+            return;
+        }
+        TrapKind::LoadMisaligned => Exception::LoadMisaligned,
+        TrapKind::StoreMisaligned => Exception::StoreMisaligned,
+        TrapKind::EnvCall => Exception::SupervisorEnvCall,
+    };
+    let mut context = TrapContext::new();
+    context.scause = scause as usize;
+    context.stval = STRESS_STVAL;
+    context.x[17] = STRESS_SYSCALL;
+    let _ = trap::dispatch_context(&mut context);
+}
+
+/// 一种陷阱的压力测试结果
+#[derive(Debug, Clone, Copy)]
+pub struct KindReport {
+    pub kind: TrapKind,
+    pub injected: u64,
+    pub handled: u64,
+    pub elapsed: Duration,
+}
+
+impl KindReport {
+    /// 每秒处理的陷阱数
+    pub fn per_second(&self) -> u64 {
+        match self.elapsed.as_nanos() {
+            0 => 0,
+            ns => (self.handled as u128 * 1_000_000_000 / ns) as u64,
+        }
+    }
+
+    /// 每个陷阱的平均耗时 (纳秒)
+    pub fn ns_per_trap(&self) -> u64 {
+        match self.handled {
+            0 => 0,
+            handled => (self.elapsed.as_nanos() / handled as u128) as u64,
+        }
+    }
+}
+
+/// 一次压力测试的结果
+#[derive(Debug, Clone)]
+pub struct StressReport {
+    pub hart: usize,
+    pub kinds: Vec<KindReport>,
+    /// 被其它类型的处理函数收到的注入陷阱数
+    pub misdispatched: u64,
+}
+
+impl StressReport {
+    /// 每个注入的陷阱都由对应类型的处理函数恰好处理一次
+    pub fn is_correct(&self) -> bool {
+        self.misdispatched == 0 && self.kinds.iter().all(|k| k.handled == k.injected)
+    }
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hart {}: {} misdispatched", self.hart, self.misdispatched)?;
+        for k in &self.kinds {
+            write!(
+                f,
+                "\n  {:<16} {}/{} handled, {} ns/trap, {} traps/s{}",
+                k.kind.name(),
+                k.handled,
+                k.injected,
+                k.ns_per_trap(),
+                k.per_second(),
+                if k.kind.is_real() { "" } else { " (synthetic)" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 在当前hart上对每种陷阱注入`rounds`次并计数
+///
+/// 计数处理函数在开始时以内核身份注册、结束时注销。多个hart同时注入时，由一个hart调用
+/// `register_handlers`，各hart运行`inject_rounds`，全部结束后再`unregister_handlers`。
+pub fn run(rounds: u64) -> Result<StressReport, TrapApiError> {
+    let handles = register_handlers()?;
+    let report = inject_rounds(rounds);
+    unregister_handlers(&handles);
+    Ok(report)
+}
+
+/// 为每种陷阱注册计数处理函数，任一注册失败时注销已注册的
+pub fn register_handlers() -> Result<Vec<HandlerHandle>, TrapApiError> {
+    let mut handles = Vec::new();
+    for kind in TrapKind::ALL {
+        match trap::register_trap_handler(
+            kind.trap_type(),
+            kind.handler(),
+            STRESS_PRIORITY,
+            kind.description(),
+            ProtectionLevel::Kernel,
+            KERNEL_REGISTRAR_ID,
+            None,
+        ) {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                println!("  Failed to register '{}': {}", kind.description(), e);
+                unregister_handlers(&handles);
+                return Err(e);
+            }
+        }
+    }
+    Ok(handles)
+}
+
+/// 注销`register_handlers`注册的处理函数
+pub fn unregister_handlers(handles: &[HandlerHandle]) {
+    for &handle in handles {
+        let _ = trap::unregister_trap_handler(handle, KERNEL_REGISTRAR_ID);
+    }
+}
+
+/// 在当前hart上对每种陷阱注入`rounds`次，需要已调用`register_handlers`
+pub fn inject_rounds(rounds: u64) -> StressReport {
+    let hart = current_hart();
+    MISDISPATCHED[hart].store(0, Ordering::Relaxed);
+    let mut kinds = Vec::new();
+    for kind in TrapKind::ALL {
+        HANDLED[hart][kind as usize].store(0, Ordering::Relaxed);
+        INJECTING[hart].store(kind as usize + 1, Ordering::Release);
+        let start = Instant::now();
+        for _ in 0..rounds {
+            inject(kind);
+        }
+        let elapsed = start.elapsed();
+        INJECTING[hart].store(0, Ordering::Release);
+        kinds.push(KindReport {
+            kind,
+            injected: rounds,
+            handled: HANDLED[hart][kind as usize].load(Ordering::Relaxed),
+            elapsed,
+        });
+    }
+    StressReport { hart, kinds, misdispatched: MISDISPATCHED[hart].load(Ordering::Relaxed) }
+}
@@ -0,0 +1,429 @@
+// 陷阱子系统测试模块
+//
+// 测试注册的处理函数只处理带有测试标记的陷阱，其余一律返回Pass，不影响系统自身的处理函数。
+// 测试注册的处理函数都记录下来，由套件的after_each钩子以内核身份注销。
+
+use super::trap_stress;
+use super::{parse_number, TestResult, TestSuite};
+use crate::trap::{
+    self, ErrorCode, ErrorLevel, ErrorSource, Exception, HandlerHandle, ProtectionLevel,
+    RegistrarId, TrapApiError, TrapContext, TrapHandler, TrapHandlerResult, TrapType, KERNEL_REGISTRAR_ID,
+    SYSTEM_REGISTRAR_ID,
+};
+use crate::{cmdline, println, time};
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// 合成陷阱的`stval`标记
+const TEST_STVAL: usize = 0x7e57_7ab0;
+/// 合成系统调用的调用号 (a7)
+const TEST_SYSCALL: usize = 0x7e57;
+/// 保留的异常号，对应`TrapType::Unknown`，系统不为其注册处理函数
+const RESERVED_EXCEPTION: usize = 10;
+
+/// 当前测试注册的处理函数，测试结束后注销
+static REGISTERED: Mutex<Vec<HandlerHandle>> = Mutex::new(Vec::new());
+
+/// 优先级测试中处理函数的调用顺序
+static CALL_ORDER: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// ebreak测试期间为true
+static BREAKPOINT_ARMED: AtomicBool = AtomicBool::new(false);
+static BREAKPOINT_HITS: AtomicUsize = AtomicUsize::new(0);
+static BREAKPOINT_SEPC: AtomicUsize = AtomicUsize::new(0);
+
+/// 以`owner`身份注册处理函数并记录下来，失败时打印原因
+fn register(
+    trap_type: TrapType,
+    handler: TrapHandler,
+    priority: u8,
+    description: &'static str,
+    level: ProtectionLevel,
+    owner: RegistrarId,
+) -> Option<HandlerHandle> {
+    match trap::register_trap_handler(trap_type, handler, priority, description, level, owner, None) {
+        Ok(handle) => {
+            REGISTERED.lock().push(handle);
+            Some(handle)
+        }
+        Err(e) => {
+            println!("  Failed to register '{}': {}", description, e);
+            None
+        }
+    }
+}
+
+/// setup领取后归还的注册者ID数，多于套件中测试领取的ID数
+const PREPARED_REGISTRAR_IDS: usize = 8;
+
+/// setup：按测试同时注册的最大数量注册后注销处理函数，为各陷阱类型建立处理函数表的表项、扩大句柄表，
+/// 并领取后归还一批注册者ID，使ID位图覆盖测试将领取的ID。这些在注销后保留，
+/// 单独运行的测试 (`test=`) 也不会因首次使用这些表而被判为泄漏。
+fn prepare_tables() -> bool {
+    let Ok(stress) = trap_stress::register_handlers() else {
+        return false;
+    };
+    let unknown: Vec<_> = [(100, "Trap Test Setup Early"), (200, "Trap Test Setup Late"), (250, "Trap Test Setup Last")]
+        .into_iter()
+        .filter_map(|(priority, description)| {
+            trap::register_trap_handler(
+                TrapType::Unknown,
+                noop_handler,
+                priority,
+                description,
+                ProtectionLevel::User,
+                KERNEL_REGISTRAR_ID,
+                None,
+            )
+            .ok()
+        })
+        .collect();
+    let registered = unknown.len();
+    trap_stress::unregister_handlers(&stress);
+    trap_stress::unregister_handlers(&unknown);
+
+    let ids: Vec<_> = (0..PREPARED_REGISTRAR_IDS).map(|_| trap::get_registrar_id()).collect();
+    for id in ids {
+        let _ = trap::release_registrar_id(id);
+    }
+    registered == 3
+}
+
+/// before_each：清除上一个测试留下的记录
+fn reset() -> bool {
+    CALL_ORDER.lock().clear();
+    BREAKPOINT_ARMED.store(false, Ordering::Release);
+    BREAKPOINT_HITS.store(0, Ordering::Relaxed);
+    true
+}
+
+/// after_each：以内核身份注销测试注册的、仍然注册着的处理函数
+fn cleanup() {
+    BREAKPOINT_ARMED.store(false, Ordering::Release);
+    for handle in core::mem::take(&mut *REGISTERED.lock()) {
+        let _ = trap::unregister_trap_handler(handle, KERNEL_REGISTRAR_ID);
+    }
+}
+
+/// 所有检查都成立时通过，否则打印不成立的检查
+fn expect_all(checks: &[(&str, bool)]) -> TestResult {
+    let failed: Vec<&str> = checks.iter().filter(|(_, ok)| !ok).map(|(name, _)| *name).collect();
+    for name in &failed {
+        println!("  Unexpected outcome: {}", name);
+    }
+    if failed.is_empty() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 带测试标记的合成陷阱上下文
+fn synthetic_context(scause: usize) -> TrapContext {
+    let mut context = TrapContext::new();
+    context.scause = scause;
+    context.stval = TEST_STVAL;
+    context
+}
+
+fn record(context: &TrapContext, name: &'static str, result: TrapHandlerResult) -> TrapHandlerResult {
+    if context.stval != TEST_STVAL {
+        return TrapHandlerResult::Pass;
+    }
+    CALL_ORDER.lock().push(name);
+    result
+}
+
+fn early_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    record(context, "early", TrapHandlerResult::Pass)
+}
+
+fn late_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    record(context, "late", TrapHandlerResult::Handled)
+}
+
+fn last_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    record(context, "last", TrapHandlerResult::Handled)
+}
+
+fn noop_handler(_context: &mut TrapContext) -> TrapHandlerResult {
+    TrapHandlerResult::Pass
+}
+
+/// 测试处理函数按优先级调用，第一个Handled之后的不再调用
+fn test_handler_priority() -> TestResult {
+    // 故意按与优先级相反的顺序注册
+    let handles = [
+        register(TrapType::Unknown, last_handler, 250, "Trap Test Last", ProtectionLevel::User, KERNEL_REGISTRAR_ID),
+        register(TrapType::Unknown, late_handler, 200, "Trap Test Late", ProtectionLevel::User, KERNEL_REGISTRAR_ID),
+        register(TrapType::Unknown, early_handler, 100, "Trap Test Early", ProtectionLevel::User, KERNEL_REGISTRAR_ID),
+    ];
+    if handles.iter().any(Option::is_none) {
+        return TestResult::Fail;
+    }
+
+    let duplicate = trap::register_trap_handler(
+        TrapType::Unknown,
+        noop_handler,
+        10,
+        "Trap Test Early",
+        ProtectionLevel::User,
+        KERNEL_REGISTRAR_ID,
+        None,
+    );
+    if let Ok(handle) = duplicate {
+        REGISTERED.lock().push(handle);
+    }
+    let result = trap::dispatch_context(&mut synthetic_context(RESERVED_EXCEPTION));
+    let order = core::mem::take(&mut *CALL_ORDER.lock());
+
+    println!("  Dispatch result: {:?}, call order: {:?}", result, order);
+    if duplicate.is_ok() {
+        println!("  A second handler with the same description and trap type was accepted");
+        return TestResult::Fail;
+    }
+    if result == Ok(TrapHandlerResult::Handled) && order == ["early", "late"] {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试所有权转移：只有当前所有者或内核可以转移，转移后由新所有者注销，注销后释放注册者ID
+fn test_ownership_transfer() -> TestResult {
+    let (old_owner, new_owner) = (trap::get_registrar_id(), trap::get_registrar_id());
+    let Some(handle) =
+        register(TrapType::Unknown, noop_handler, 120, "Trap Test Ownership", ProtectionLevel::User, old_owner)
+    else {
+        return TestResult::Fail;
+    };
+
+    let checks = [
+        ("non-owner transfers", trap::transfer_handler_ownership(handle, new_owner, new_owner).is_err()),
+        ("owner transfers", trap::transfer_handler_ownership(handle, old_owner, new_owner).is_ok()),
+        ("old owner unregisters", trap::unregister_trap_handler(handle, old_owner).is_err()),
+        ("kernel transfers back", trap::transfer_handler_ownership(handle, KERNEL_REGISTRAR_ID, old_owner).is_ok()),
+        ("new owner unregisters", trap::unregister_trap_handler(handle, new_owner).is_err()),
+        ("owner releases id in use", trap::release_registrar_id(old_owner) == Err(TrapApiError::PermissionDenied)),
+        ("owner unregisters", trap::unregister_trap_handler(handle, old_owner).is_ok()),
+        ("ids released", trap::release_registrar_id(old_owner).is_ok() && trap::release_registrar_id(new_owner).is_ok()),
+        ("id released twice", trap::release_registrar_id(old_owner) == Err(TrapApiError::InternalError)),
+    ];
+    expect_all(&checks)
+}
+
+/// 测试各保护级别的注销权限
+fn test_unregister_permissions() -> TestResult {
+    let module = trap::get_registrar_id();
+    let (kernel_level, system_level) = (ProtectionLevel::Kernel, ProtectionLevel::System);
+    let kernel = register(TrapType::Unknown, noop_handler, 130, "Trap Test Kernel", kernel_level, KERNEL_REGISTRAR_ID);
+    let system = register(TrapType::Unknown, noop_handler, 131, "Trap Test System", system_level, SYSTEM_REGISTRAR_ID);
+    let user = register(TrapType::Unknown, noop_handler, 132, "Trap Test User", ProtectionLevel::User, module);
+    let (Some(kernel), Some(system), Some(user)) = (kernel, system, user) else {
+        return TestResult::Fail;
+    };
+
+    let denied = Err(TrapApiError::UnregistrationFailed);
+    let checks = [
+        ("module unregisters kernel handler", trap::unregister_trap_handler(kernel, module) == denied),
+        ("system unregisters kernel handler", trap::unregister_trap_handler(kernel, SYSTEM_REGISTRAR_ID) == denied),
+        ("module unregisters system handler", trap::unregister_trap_handler(system, module) == denied),
+        ("other module unregisters user handler", trap::unregister_trap_handler(user, SYSTEM_REGISTRAR_ID) == denied),
+        ("kernel unregisters kernel handler", trap::unregister_trap_handler(kernel, KERNEL_REGISTRAR_ID).is_ok()),
+        ("system unregisters system handler", trap::unregister_trap_handler(system, SYSTEM_REGISTRAR_ID).is_ok()),
+        ("owner unregisters user handler", trap::unregister_trap_handler(user, module).is_ok()),
+        ("handler unregistered twice", trap::unregister_trap_handler(user, module) == denied),
+    ];
+    expect_all(&checks)
+}
+
+/// 处理测试自己的ebreak：记录地址并跳过该指令 (可能是压缩指令)
+fn breakpoint_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if !BREAKPOINT_ARMED.load(Ordering::Acquire) {
+        return TrapHandlerResult::Pass;
+    }
+    BREAKPOINT_HITS.fetch_add(1, Ordering::Relaxed);
+    BREAKPOINT_SEPC.store(context.sepc, Ordering::Relaxed);
+    let instruction = unsafe { (context.sepc as *const u16).read() };
+    context.sepc += if instruction & 0b11 == 0b11 { 4 } else { 2 };
+    TrapHandlerResult::Handled
+}
+
+/// 测试真实的ebreak经陷阱入口分发到注册的处理函数，并从下一条指令继续执行
+fn test_ebreak_dispatch() -> TestResult {
+    let Some(_) = register(
+        TrapType::Breakpoint,
+        breakpoint_handler,
+        10,
+        "Trap Test Breakpoint",
+        ProtectionLevel::User,
+        KERNEL_REGISTRAR_ID,
+    ) else {
+        return TestResult::Fail;
+    };
+
+    BREAKPOINT_ARMED.store(true, Ordering::Release);
+    unsafe {
+        asm!("ebreak");
+    }
+    BREAKPOINT_ARMED.store(false, Ordering::Release);
+
+    let hits = BREAKPOINT_HITS.load(Ordering::Relaxed);
+    println!("  ebreak handled {} time(s), at {:#x}", hits, BREAKPOINT_SEPC.load(Ordering::Relaxed));
+    if hits == 1 {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 处理测试的合成系统调用：返回a0的两倍
+fn syscall_handler(context: &mut TrapContext) -> TrapHandlerResult {
+    if context.stval != TEST_STVAL || context.x[17] != TEST_SYSCALL {
+        return TrapHandlerResult::Pass;
+    }
+    let value = context.x[10] * 2;
+    context.set_return_value(value);
+    context.advance_sepc();
+    TrapHandlerResult::Handled
+}
+
+/// 测试合成的ecall上下文分发到SystemCall处理函数
+///
+/// S态的ecall进入SBI固件而不会陷入内核，因此以合成上下文测试分发。
+fn test_ecall_dispatch() -> TestResult {
+    let Some(_) = register(
+        TrapType::SystemCall,
+        syscall_handler,
+        1,
+        "Trap Test Syscall",
+        ProtectionLevel::User,
+        KERNEL_REGISTRAR_ID,
+    ) else {
+        return TestResult::Fail;
+    };
+
+    let mut context = synthetic_context(Exception::SupervisorEnvCall as usize);
+    context.sepc = 0x1000;
+    context.x[17] = TEST_SYSCALL;
+    context.x[10] = 21;
+    let result = trap::dispatch_context(&mut context);
+    // 不是测试调用号的ecall不应被测试处理函数处理
+    let mut other = synthetic_context(Exception::SupervisorEnvCall as usize);
+    other.x[17] = TEST_SYSCALL + 1;
+    let other_result = trap::dispatch_context(&mut other);
+
+    println!("  Result: {:?}, a0 = {}, sepc = {:#x}", result, context.x[10], context.sepc);
+    let handled = result == Ok(TrapHandlerResult::Handled) && context.x[10] == 42 && context.sepc == 0x1004;
+    if handled && other_result == Ok(TrapHandlerResult::Pass) {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试报告的系统错误记录到错误日志
+fn test_error_logging() -> TestResult {
+    let code = ErrorCode::new(ErrorSource::Generic, ErrorLevel::Info, 0x7e57);
+    let error = trap::create_system_error(code, "trap test error", Some(TEST_STVAL), 0x1000, time::monotonic_ms());
+    let result = trap::report_system_error(error);
+
+    let Some(entry) = trap::recent_errors(1).pop() else {
+        println!("  The error log is empty");
+        return TestResult::Fail;
+    };
+    println!("  Logged: {} ({:?})", entry.error, entry.result);
+    let logged = entry.error.code == code
+        && entry.error.message == "trap test error"
+        && entry.error.address == Some(TEST_STVAL)
+        && entry.error.instruction_pointer == 0x1000;
+    if logged && entry.result == result {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 测试错误统计：按来源与级别计数，超过速率限制时恰好报告一次RATE_EXCEEDED警告
+fn test_error_stats() -> TestResult {
+    let Ok(saved) = trap::error_rate_limit() else {
+        println!("  The trap system is not initialized");
+        return TestResult::Fail;
+    };
+    let device_count = |level| {
+        trap::error_stats().iter().find(|s| s.source == ErrorSource::Device).map_or(0, |s| s.count(level))
+    };
+    let (errors_before, warnings_before) = (device_count(ErrorLevel::Error), device_count(ErrorLevel::Warning));
+    // 窗口足够长，测试期间的错误都在窗口内；此前的速率随限制的更换丢弃
+    let limit = trap::ErrorRateLimit { window_ticks: time::frequency() * 60, max_errors: Some(3) };
+    let set = trap::set_error_rate_limit(limit);
+    let code = ErrorCode::new(ErrorSource::Device, ErrorLevel::Error, 0x7e57);
+    for _ in 0..5 {
+        trap::report_system_error(trap::create_system_error(code, "error stats test", None, 0, time::monotonic_ms()));
+    }
+    let stats = trap::error_stats().into_iter().find(|s| s.source == ErrorSource::Device);
+    let warnings = trap::recent_errors(8).iter().filter(|e| e.error.code.is_rate_exceeded()).count();
+    let restored = trap::set_error_rate_limit(saved);
+
+    let counted = stats.is_some_and(|s| {
+        s.count(ErrorLevel::Error) == errors_before + 5 && s.first_ticks <= s.last_ticks
+            && s.last_ticks <= time::monotonic_ticks() && s.recent == 6
+    });
+    if set.is_ok() && restored.is_ok() && counted && warnings == 1
+        && device_count(ErrorLevel::Warning) == warnings_before + 1 {
+        TestResult::Pass
+    } else {
+        println!("  FAIL: set={:?}, restored={:?}, stats={:?}, warnings={}", set, restored, stats, warnings);
+        TestResult::Fail
+    }
+}
+
+/// 压力测试每种陷阱的默认注入次数，可由命令行的`trap_stress_rounds=`覆盖
+const STRESS_DEFAULT_ROUNDS: u64 = 1000;
+
+/// 压力测试：反复注入各种陷阱，每个都应由对应类型的处理函数恰好处理一次
+fn test_trap_stress() -> TestResult {
+    let rounds = cmdline::value("trap_stress_rounds").and_then(parse_number).unwrap_or(STRESS_DEFAULT_ROUNDS);
+    let report = match trap_stress::run(rounds) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("  Failed to set up the stress handlers: {}", e);
+            return TestResult::Fail;
+        }
+    };
+    println!("  {} rounds on {}", rounds, report);
+    if report.is_correct() {
+        TestResult::Pass
+    } else {
+        TestResult::Fail
+    }
+}
+
+/// 陷阱子系统测试套件
+///
+/// 处理函数表与句柄表由setup预先扩大；错误日志保留报告的错误及其消息，报告错误的测试不做泄漏检查。
+static SUITE: TestSuite = TestSuite {
+    setup: Some(prepare_tables),
+    before_each: Some(reset),
+    after_each: Some(cleanup),
+    ..TestSuite::new("Trap", 25)
+};
+
+crate::kernel_test!(SUITE, "handler_priority", test_handler_priority,
+    "Handlers run in priority order until one handles the trap");
+crate::kernel_test!(SUITE, "ownership_transfer", test_ownership_transfer,
+    "Only the owner or the kernel transfers a handler, and the new owner unregisters it");
+crate::kernel_test!(SUITE, "unregister_permissions", test_unregister_permissions,
+    "Protection levels decide who may unregister a handler");
+crate::kernel_test!(SUITE, "ebreak_dispatch", test_ebreak_dispatch,
+    "A real ebreak reaches its handler and resumes after the instruction");
+crate::kernel_test!(SUITE, "ecall_dispatch", test_ecall_dispatch,
+    "A synthetic ecall context is dispatched to the system call handlers");
+crate::kernel_test!(SUITE, "error_logging", test_error_logging, "Reported system errors are logged", allow_leak);
+crate::kernel_test!(SUITE, "error_stats", test_error_stats,
+    "System errors are counted per source and level, and a source over the rate limit raises one warning",
+    allow_leak);
+crate::kernel_test!(SUITE, "trap_stress", test_trap_stress,
+    "Injected traps of every kind reach their handler exactly once under load");
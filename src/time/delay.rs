@@ -0,0 +1,127 @@
+// nt_rustos/src/time/delay.rs
+
+//! Busy-wait delays.
+//!
+//! `ndelay`, `udelay` and `mdelay` spin without the scheduler or timer
+//! interrupts, so drivers can use them at any point of boot. Waits of at
+//! least `MIN_CSR_TICKS` timer ticks watch the `time` CSR itself. Shorter
+//! ones, below the counter's resolution or close to it (a tick is 100 ns at
+//! QEMU's 10 MHz), spin a loop whose speed `calibrate` measures once at
+//! boot against the CSR; until then they wait for the CSR as well.
+//!
+//! Delays do not end early, except a calibrated loop on a hart that runs
+//! faster than during calibration. They run long when the hart is
+//! interrupted.
+
+use super::{frequency, now_ticks, scale, scale_ceil, MSEC_PER_SEC, NSEC_PER_SEC, USEC_PER_SEC};
+use crate::info_print;
+use crate::init::initcall::InitResult;
+use core::hint::black_box;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Waits this many ticks long or longer watch the `time` CSR.
+const MIN_CSR_TICKS: u64 = 20;
+/// Calibration times a run of the loop at least this long.
+const CALIBRATION_US: u64 = 1000;
+/// Calibration gives up beyond this many loops, if the counter is stuck.
+const MAX_CALIBRATION_LOOPS: u64 = 1 << 36;
+
+/// Delay loops per second, or 0 before calibration.
+static LOOPS_PER_SEC: AtomicU64 = AtomicU64::new(0);
+
+/// The delay loop: `loops` iterations the compiler cannot remove.
+#[inline(never)]
+fn spin(loops: u64) {
+    for i in 0..loops {
+        black_box(i);
+    }
+}
+
+/// Waits until the `time` CSR changes and returns the new value, so that
+/// a measurement starts on a tick edge.
+fn next_tick() -> u64 {
+    let start = now_ticks();
+    loop {
+        let now = now_ticks();
+        if now != start {
+            return now;
+        }
+    }
+}
+
+/// Measures the delay loop against the `time` CSR and returns the loops
+/// per second. Returns 0, leaving every delay on the CSR, if the counter
+/// does not advance.
+pub fn calibrate() -> u64 {
+    let target = scale_ceil(CALIBRATION_US, USEC_PER_SEC, frequency()).max(MIN_CSR_TICKS);
+    let mut loops = 1024;
+    while loops <= MAX_CALIBRATION_LOOPS {
+        let start = next_tick();
+        spin(loops);
+        let elapsed = now_ticks().wrapping_sub(start);
+        if elapsed >= target {
+            let per_sec = scale(loops, elapsed, frequency()).max(1);
+            LOOPS_PER_SEC.store(per_sec, Ordering::Relaxed);
+            return per_sec;
+        }
+        loops *= 2;
+    }
+    0
+}
+
+/// Calibrates the delay loop at boot, for the drivers and early code that
+/// wait before the scheduler and timer are up.
+fn calibrate_at_boot() -> InitResult {
+    match calibrate() {
+        0 => info_print!("Delay loop calibration failed, short delays wait on the time CSR."),
+        loops => info_print!("Delay loop calibrated: {} loops/ms.", loops / MSEC_PER_SEC),
+    }
+    Ok(())
+}
+
+crate::initcall!(early, 30, calibrate_at_boot);
+
+/// Returns whether `calibrate` has measured the delay loop.
+pub fn is_calibrated() -> bool {
+    LOOPS_PER_SEC.load(Ordering::Relaxed) != 0
+}
+
+/// Returns the measured delay loops per second, or 0 before calibration.
+pub fn loops_per_sec() -> u64 {
+    LOOPS_PER_SEC.load(Ordering::Relaxed)
+}
+
+/// Spins until `ticks` full ticks of the `time` CSR have passed. One more
+/// tick is waited out, since the first reading may fall late in its tick.
+fn wait_ticks(ticks: u64) {
+    let start = now_ticks();
+    let wait = ticks.saturating_add(1);
+    while now_ticks().wrapping_sub(start) < wait {
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits at least `ns` nanoseconds.
+pub fn ndelay(ns: u64) {
+    if ns == 0 {
+        return;
+    }
+    let ticks = scale_ceil(ns, NSEC_PER_SEC, frequency());
+    let per_sec = loops_per_sec();
+    if ticks >= MIN_CSR_TICKS || per_sec == 0 {
+        wait_ticks(ticks);
+    } else {
+        spin(scale_ceil(ns, NSEC_PER_SEC, per_sec));
+    }
+}
+
+/// Waits at least `us` microseconds.
+pub fn udelay(us: u64) {
+    ndelay(us.saturating_mul(1000));
+}
+
+/// Waits at least `ms` milliseconds. Prefer `task::sleep_ms` once the
+/// scheduler runs.
+pub fn mdelay(ms: u64) {
+    wait_ticks(scale_ceil(ms, MSEC_PER_SEC, frequency()));
+}
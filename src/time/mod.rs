@@ -0,0 +1,205 @@
+// nt_rustos/src/time/mod.rs
+
+//! # Monotonic Clock
+//!
+//! Time since boot, from the `time` CSR. The CSR counts at the timebase
+//! frequency the device tree gives (see `platform`); `now_ticks` reads it
+//! raw, for timer deadlines, and the `*_to_*` helpers convert between
+//! ticks and real units with 128-bit intermediates, so no product
+//! overflows and no frequency is rounded to whole ticks per millisecond.
+//!
+//! `monotonic_ns` and friends count from the reading `init` takes at boot.
+//! They subtract with wrapping arithmetic, so a counter that wraps, or
+//! starts close to wrapping, still gives the right elapsed time. Harts
+//! read their own counters, which may be slightly out of step; the clock
+//! never goes backwards across harts, since no reading is returned that is
+//! older than one already handed out. Compare raw tick values with
+//! `ticks_before`, which stays right across a wrap.
+//!
+//! `delay` has busy-wait delays for code that runs before timers do, and
+//! `wall` the wall clock, which an RTC sets.
+
+pub mod delay;
+pub mod wall;
+
+pub use self::delay::{mdelay, ndelay, udelay};
+pub use self::wall::{now, DateTime};
+
+use crate::init::initcall::InitResult;
+use crate::platform;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+pub const USEC_PER_SEC: u64 = 1_000_000;
+pub const MSEC_PER_SEC: u64 = 1_000;
+
+/// Counter reading at boot.
+static BOOT_TICKS: AtomicU64 = AtomicU64::new(0);
+/// Latest tick count since boot handed out by `monotonic_ticks`.
+static LATEST: AtomicU64 = AtomicU64::new(0);
+
+/// Takes the boot reading that the monotonic clock counts from.
+pub fn init() {
+    BOOT_TICKS.store(now_ticks(), Ordering::Relaxed);
+    LATEST.store(0, Ordering::Relaxed);
+}
+
+/// Starts the monotonic clock, once the platform has given the timebase.
+fn start_clock() -> InitResult {
+    init();
+    Ok(())
+}
+
+crate::initcall!(early, 20, start_clock);
+
+/// Reads the `time` CSR.
+pub fn now_ticks() -> u64 {
+    let ticks: u64;
+    unsafe {
+        asm!("csrr {}, time", out(reg) ticks);
+    }
+    ticks
+}
+
+/// Returns the timebase frequency in Hz.
+pub fn frequency() -> u64 {
+    platform::get().timebase_frequency.max(1)
+}
+
+/// Returns the number of timer ticks per millisecond, from the timebase.
+pub fn ticks_per_ms() -> u64 {
+    platform::get().ticks_per_ms()
+}
+
+/// Converts `value` counted at `from_hz` to a count at `to_hz`, rounding
+/// down and saturating.
+pub const fn scale(value: u64, from_hz: u64, to_hz: u64) -> u64 {
+    let scaled = value as u128 * to_hz as u128 / from_hz as u128;
+    if scaled > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        scaled as u64
+    }
+}
+
+/// Like `scale`, rounding up: a timeout converted this way never ends
+/// early.
+pub const fn scale_ceil(value: u64, from_hz: u64, to_hz: u64) -> u64 {
+    let scaled = (value as u128 * to_hz as u128).div_ceil(from_hz as u128);
+    if scaled > u64::MAX as u128 {
+        u64::MAX
+    } else {
+        scaled as u64
+    }
+}
+
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    scale(ticks, frequency(), NSEC_PER_SEC)
+}
+
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    scale(ticks, frequency(), USEC_PER_SEC)
+}
+
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    scale(ticks, frequency(), MSEC_PER_SEC)
+}
+
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    scale_ceil(ns, NSEC_PER_SEC, frequency())
+}
+
+pub fn us_to_ticks(us: u64) -> u64 {
+    scale_ceil(us, USEC_PER_SEC, frequency())
+}
+
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    scale_ceil(ms, MSEC_PER_SEC, frequency())
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks_to_ns(ticks))
+}
+
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    ns_to_ticks(duration.as_nanos().min(u64::MAX as u128) as u64)
+}
+
+/// Returns the raw tick count `ms` milliseconds from now.
+pub fn deadline_after_ms(ms: u64) -> u64 {
+    now_ticks().wrapping_add(ms_to_ticks(ms))
+}
+
+/// Returns whether raw tick value `a` comes before `b`, assuming they are
+/// less than half the counter range apart.
+pub fn ticks_before(a: u64, b: u64) -> bool {
+    (a.wrapping_sub(b) as i64) < 0
+}
+
+/// Returns whether the raw tick value `deadline` has been reached.
+pub fn reached(deadline: u64) -> bool {
+    !ticks_before(now_ticks(), deadline)
+}
+
+/// Returns the ticks since boot, never less than a value already returned.
+pub fn monotonic_ticks() -> u64 {
+    let elapsed = now_ticks().wrapping_sub(BOOT_TICKS.load(Ordering::Relaxed));
+    let latest = LATEST.fetch_max(elapsed, Ordering::Relaxed);
+    elapsed.max(latest)
+}
+
+/// Returns the nanoseconds since boot.
+pub fn monotonic_ns() -> u64 {
+    ticks_to_ns(monotonic_ticks())
+}
+
+/// Returns the microseconds since boot.
+pub fn monotonic_us() -> u64 {
+    ticks_to_us(monotonic_ticks())
+}
+
+/// Returns the milliseconds since boot.
+pub fn monotonic_ms() -> u64 {
+    ticks_to_ms(monotonic_ticks())
+}
+
+/// A point on the monotonic clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(monotonic_ticks())
+    }
+
+    /// The instant `ticks` ticks after boot.
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    /// Ticks since boot.
+    pub const fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Time since boot.
+    pub fn since_boot(&self) -> Duration {
+        ticks_to_duration(self.0)
+    }
+
+    /// Time from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Time since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration_to_ticks(duration)).map(Self)
+    }
+}
@@ -0,0 +1,145 @@
+// nt_rustos/src/time/wall.rs
+
+//! Wall-clock time.
+//!
+//! The real-time clock is read once, when its driver probes, and the
+//! reading is kept as the Unix time at boot. `now` adds the monotonic clock
+//! to it, so the wall clock costs no device access and never goes back.
+//! Until an RTC has set it, the clock counts from the Unix epoch.
+//!
+//! `DateTime` is a Unix time broken down into the proleptic Gregorian
+//! calendar, in UTC; it prints in RFC 3339 form with milliseconds, such as
+//! `2024-05-01T12:30:05.123Z`.
+
+use super::{monotonic_ns, NSEC_PER_SEC};
+use crate::println;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+const SECS_PER_DAY: u64 = 86400;
+/// Days from 0000-03-01 to 1970-01-01.
+const UNIX_EPOCH_DAYS: i64 = 719468;
+const DAYS_PER_ERA: i64 = 146097;
+
+/// Unix time at boot, in nanoseconds.
+static BOOT_UNIX_NS: AtomicU64 = AtomicU64::new(0);
+static SET: AtomicBool = AtomicBool::new(false);
+
+/// Sets the wall clock to `unix_ns` nanoseconds since the epoch, as an RTC
+/// reads now.
+pub fn set_unix_ns(unix_ns: u64) {
+    BOOT_UNIX_NS.store(unix_ns.saturating_sub(monotonic_ns()), Ordering::Relaxed);
+    SET.store(true, Ordering::Release);
+}
+
+/// Returns whether an RTC has set the wall clock.
+pub fn is_set() -> bool {
+    SET.load(Ordering::Acquire)
+}
+
+/// Returns the nanoseconds since the Unix epoch.
+pub fn unix_ns() -> u64 {
+    BOOT_UNIX_NS.load(Ordering::Relaxed).saturating_add(monotonic_ns())
+}
+
+/// Returns the current date and time in UTC.
+pub fn now() -> DateTime {
+    DateTime::from_unix_ns(unix_ns())
+}
+
+/// Returns the days since 1970-01-01 of a civil date, after Howard
+/// Hinnant's `days_from_civil`. Negative before 1970.
+pub const fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let (month, day) = (month as i64, day as i64);
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * DAYS_PER_ERA + doe - UNIX_EPOCH_DAYS
+}
+
+/// Returns the civil date `days` days after 1970-01-01: the inverse of
+/// `days_from_civil`.
+pub const fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + UNIX_EPOCH_DAYS;
+    let era = z.div_euclid(DAYS_PER_ERA);
+    let doe = z - era * DAYS_PER_ERA;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A date and time in UTC.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Breaks down `secs` seconds and `nanos` nanoseconds since the epoch.
+    pub const fn from_unix(secs: u64, nanos: u32) -> Self {
+        let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+        let time = secs % SECS_PER_DAY;
+        Self {
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+            nanosecond: nanos,
+        }
+    }
+
+    pub const fn from_unix_ns(ns: u64) -> Self {
+        Self::from_unix(ns / NSEC_PER_SEC, (ns % NSEC_PER_SEC) as u32)
+    }
+
+    /// Returns the seconds since the epoch, 0 for dates before it.
+    pub const fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        if days < 0 {
+            return 0;
+        }
+        days as u64 * SECS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.nanosecond / 1_000_000
+        )
+    }
+}
+
+/// The `date` command: prints the current date and time. The kernel has no
+/// shell yet, so it is called directly for now.
+pub fn date() {
+    if is_set() {
+        println!("{}", now());
+    } else {
+        println!("{} (no RTC, counting from boot)", now());
+    }
+}
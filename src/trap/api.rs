@@ -7,10 +7,15 @@
 
 use crate::trap::ds::{
     self, TrapType, TrapHandler, TrapHandlerResult, HandlerHandle, RegistrarId, SystemError,
-    ErrorResult, ErrorSource, ErrorLevel, ErrorCode, ProtectionLevel, HandlerEntry,
+    ErrorResult, ErrorSource, ErrorLevel, ErrorCode, ProtectionLevel, HandlerEntry, ErrorLogEntry,
+    ErrorRateLimit, ErrorStats,
+    TrapContext,
 };
 use crate::trap::infrastructure::di::{self, with_trap_system};
+use crate::trap::infrastructure::di::traits::ErrorManager;
+use crate::trap::infrastructure::low_level;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::RwLock;
 
 /// Errors that can occur when interacting with the Trap API.
@@ -45,6 +50,23 @@ pub fn get_registrar_id() -> RegistrarId {
     ds::generate_registrar_id()
 }
 
+/// Returns a `RegistrarId` obtained from `get_registrar_id` for reuse, once
+/// the module is done with it.
+///
+/// Fails with `PermissionDenied` while handlers owned by `id` remain
+/// registered, and with `InternalError` if `id` was not handed out or was
+/// already released.
+pub fn release_registrar_id(id: RegistrarId) -> Result<(), TrapApiError> {
+    if handlers().iter().any(|handler| handler.registrar_id == id) {
+        return Err(TrapApiError::PermissionDenied);
+    }
+    if ds::release_registrar_id(id) {
+        Ok(())
+    } else {
+        Err(TrapApiError::InternalError)
+    }
+}
+
 /// Registers a trap handler.
 ///
 /// # Arguments
@@ -67,6 +89,7 @@ pub fn register_trap_handler(
     registrar_id: RegistrarId,
     context_id: Option<u64>,
 ) -> Result<HandlerHandle, TrapApiError> {
+    crate::cov!("register_trap_handler");
     if !di::is_initialized() {
         return Err(TrapApiError::SystemNotInitialized);
     }
@@ -81,8 +104,10 @@ pub fn register_trap_handler(
     };
     let entry_arc = Arc::new(RwLock::new(entry_data));
 
-    with_trap_system(|ts| ts.handler_manager().register(trap_type, entry_arc))
-        .map_err(|_| TrapApiError::RegistrationFailed)
+    with_trap_system(|ts| ts.handler_manager().register(trap_type, entry_arc)).map_err(|_| {
+        crate::cov!("register_trap_handler", "rejected");
+        TrapApiError::RegistrationFailed
+    })
 }
 
 /// Unregisters a trap handler using its handle.
@@ -92,6 +117,7 @@ pub fn register_trap_handler(
 /// * `requester_id` - The `RegistrarId` of the module attempting to unregister.
 ///   Must match the handler's current owner or be `KERNEL_REGISTRAR_ID` for privileged unregistration.
 pub fn unregister_trap_handler(handle: HandlerHandle, requester_id: RegistrarId) -> Result<(), TrapApiError> {
+    crate::cov!("unregister_trap_handler");
     if !di::is_initialized() {
         return Err(TrapApiError::SystemNotInitialized);
     }
@@ -110,6 +136,7 @@ pub fn transfer_handler_ownership(
     current_owner_id: RegistrarId,
     new_owner_id: RegistrarId,
 ) -> Result<(), TrapApiError> {
+    crate::cov!("transfer_handler_ownership");
     if !di::is_initialized() {
         return Err(TrapApiError::SystemNotInitialized);
     }
@@ -119,6 +146,86 @@ pub fn transfer_handler_ownership(
     .map_err(|_| TrapApiError::OwnershipTransferFailed) // More specific error needed
 }
 
+/// Unregisters every handler bound to `context_id`, regardless of its owner.
+///
+/// Used when the context (e.g. a task) the handlers were registered for
+/// goes away.
+pub fn unregister_context_handlers(context_id: u64) -> Result<(), TrapApiError> {
+    crate::cov!("unregister_context_handlers");
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    with_trap_system(|ts| ts.handler_manager().unregister_for_context(context_id));
+    Ok(())
+}
+
+/// Returns the number of handlers still bound to `context_id`.
+pub fn context_handler_count(context_id: u64) -> usize {
+    if !di::is_initialized() {
+        return 0;
+    }
+    with_trap_system(|ts| ts.handler_manager().count_for_context(context_id))
+}
+
+/// A registered handler, as listed by `handlers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerInfo {
+    pub trap_type: TrapType,
+    pub priority: u8,
+    pub description: &'static str,
+    pub protection_level: ProtectionLevel,
+    pub registrar_id: RegistrarId,
+    pub context_id: Option<u64>,
+}
+
+/// Lists the registered handlers by trap type, each in dispatch order.
+///
+/// Interrupts are disabled while the handler table is locked, as in
+/// `dispatch_context`.
+pub fn handlers() -> Vec<HandlerInfo> {
+    if !di::is_initialized() {
+        return Vec::new();
+    }
+    let handler_manager = with_trap_system(|ts| ts.handler_manager());
+    let was_enabled = disable_interrupts();
+    let entries = handler_manager.list();
+    restore_interrupts(was_enabled);
+    entries
+        .into_iter()
+        .map(|(trap_type, entry)| HandlerInfo {
+            trap_type,
+            priority: entry.priority,
+            description: entry.description,
+            protection_level: entry.protection_level,
+            registrar_id: entry.registrar_id,
+            context_id: entry.context_id,
+        })
+        .collect()
+}
+
+/// Returns how many traps of `trap_type` have been taken since boot.
+pub fn trap_count(trap_type: TrapType) -> u64 {
+    di::trap_count(trap_type)
+}
+
+/// Runs the handlers registered for the trap `context` describes, as if it
+/// had been taken, and returns the result.
+///
+/// Nothing is reported to the error manager if no handler handles it.
+/// Interrupts are disabled meanwhile, so a real trap cannot find the
+/// handler table locked.
+pub fn dispatch_context(context: &mut TrapContext) -> Result<TrapHandlerResult, TrapApiError> {
+    crate::cov!("dispatch_context");
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    let handler_manager = with_trap_system(|ts| ts.handler_manager());
+    let was_enabled = disable_interrupts();
+    let result = handler_manager.dispatch(context);
+    restore_interrupts(was_enabled);
+    Ok(result)
+}
+
 /// Enables all supervisor-level interrupts.
 pub fn enable_interrupts() -> bool {
     if !di::is_initialized() { return false; } // Default to false if not initialized
@@ -137,6 +244,62 @@ pub fn restore_interrupts(was_enabled: bool) {
     with_trap_system(|ts| ts.hardware_controller().restore_interrupts(was_enabled));
 }
 
+/// Installs a hook that runs after each trap has been dispatched.
+///
+/// The hook runs outside the trap system's locks, which makes it the place
+/// to perform work that may switch tasks, such as a deferred reschedule.
+pub fn set_trap_exit_hook(hook: di::TrapExitHook) -> Result<(), TrapApiError> {
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    di::set_trap_exit_hook(hook);
+    Ok(())
+}
+
+/// Installs a hook that runs when a trap is taken, before any handler.
+///
+/// The hook only runs for outermost traps, not for traps taken while
+/// another one is being dispatched, and outside the trap system's locks.
+pub fn set_trap_entry_hook(hook: di::TrapEntryHook) -> Result<(), TrapApiError> {
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    di::set_trap_entry_hook(hook);
+    Ok(())
+}
+
+/// Installs a hook that runs first on every trap, nested or not, before
+/// the trap system takes any lock. The hook may stop the hart for good
+/// instead of returning, even while another hart holds those locks.
+///
+/// Only one such hook can be installed.
+pub fn set_trap_stop_hook(hook: di::TrapStopHook) -> Result<(), TrapApiError> {
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    if di::set_trap_stop_hook(hook) {
+        Ok(())
+    } else {
+        Err(TrapApiError::RegistrationFailed)
+    }
+}
+
+/// Tells the trap entry code where the current kernel stack ends.
+///
+/// A trap taken from S-mode whose context would not fit above `bottom` is
+/// treated as a stack overflow: it is handled on a separate overflow stack,
+/// with the faulting stack pointer saved in the context, and the check is
+/// disabled until the next call. Pass 0 for stacks without a known bottom.
+pub fn set_kernel_stack_bottom(bottom: usize) {
+    let limit = if bottom == 0 { 0 } else { bottom + core::mem::size_of::<ds::TrapContext>() };
+    low_level::set_kernel_stack_limit(limit);
+}
+
+/// Returns `true` when called from within a trap handler.
+pub fn in_trap_context() -> bool {
+    di::in_trap_handler()
+}
+
 // --- Error Handling API ---
 
 type ErrorHandlerFn = fn(&SystemError) -> ErrorResult;
@@ -148,6 +311,7 @@ pub fn register_error_handler(
     level: Option<ErrorLevel>,
     handler: ErrorHandlerFn,
 ) -> Result<(), TrapApiError> {
+    crate::cov!("register_error_handler");
     if !di::is_initialized() {
         return Err(TrapApiError::SystemNotInitialized);
     }
@@ -169,6 +333,7 @@ pub fn register_error_handler(
 
 /// Reports a system error to be handled by the error management system.
 pub fn report_system_error(error: SystemError) -> ErrorResult {
+    crate::cov!("report_system_error");
     if !di::is_initialized() {
         // If the error system isn't up, we can't do much. Maybe a raw print?
         // nt_rustos::println!("Uninitialized error reported: {}", error);
@@ -177,6 +342,52 @@ pub fn report_system_error(error: SystemError) -> ErrorResult {
     with_trap_system(|ts| ts.error_manager().handle_error(error))
 }
 
+/// Returns up to `count` of the most recently logged system errors, oldest
+/// first.
+pub fn recent_errors(count: usize) -> Vec<ErrorLogEntry> {
+    if !di::is_initialized() {
+        return Vec::new();
+    }
+    with_trap_system(|ts| ts.error_manager().recent_errors(count))
+}
+
+/// Returns how many system errors were pushed out of the error log by
+/// newer ones.
+pub fn dropped_errors() -> u64 {
+    if !di::is_initialized() {
+        return 0;
+    }
+    with_trap_system(|ts| ts.error_manager().dropped_errors())
+}
+
+/// Returns the error statistics of each source that has reported errors,
+/// in the order of `ErrorSource::ALL`.
+pub fn error_stats() -> Vec<ErrorStats> {
+    if !di::is_initialized() {
+        return Vec::new();
+    }
+    with_trap_system(|ts| ts.error_manager().error_stats())
+}
+
+/// Returns the rate limit above which a source's errors raise a
+/// `RATE_EXCEEDED` warning.
+pub fn error_rate_limit() -> Result<ErrorRateLimit, TrapApiError> {
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    Ok(with_trap_system(|ts| ts.error_manager().rate_limit()))
+}
+
+/// Replaces the error rate limit. The rates measured so far are discarded;
+/// the counts are kept.
+pub fn set_error_rate_limit(limit: ErrorRateLimit) -> Result<(), TrapApiError> {
+    if !di::is_initialized() {
+        return Err(TrapApiError::SystemNotInitialized);
+    }
+    with_trap_system(|ts| ts.error_manager().set_rate_limit(limit));
+    Ok(())
+}
+
 /// Creates a new `SystemError` instance.
 /// This is a utility function to help construct errors consistently.
 pub fn create_system_error(
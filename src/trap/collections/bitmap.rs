@@ -0,0 +1,306 @@
+// nt_rustos/src/trap/collections/bitmap.rs
+
+//! # Bitmap
+//!
+//! A heap-allocated array of bits, for allocators that track which of a
+//! fixed set of objects (frames, IDs, interrupt lines, cache slots) are in
+//! use. Bits are stored 64 to a word, so searches for a clear or set bit
+//! skip whole words at a time.
+//!
+//! Indices past the end panic, as they do for slices.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A fixed-length array of bits, all clear when created.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    /// Creates a bitmap of `len` clear bits.
+    pub fn new(len: usize) -> Self {
+        Self { words: vec![0; len.div_ceil(WORD_BITS)], len }
+    }
+
+    /// Creates a bitmap of no bits, which can be `resize`d later. Unlike
+    /// `new`, this can be used in a `static`.
+    pub const fn empty() -> Self {
+        Self { words: Vec::new(), len: 0 }
+    }
+
+    /// Returns the number of bits.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn check(&self, index: usize) {
+        assert!(index < self.len, "bit index {} out of range for bitmap of length {}", index, self.len);
+    }
+
+    fn check_range(&self, range: &Range<usize>) {
+        assert!(range.start <= range.end && range.end <= self.len,
+                "bit range {:?} out of range for bitmap of length {}", range, self.len);
+    }
+
+    /// Returns whether bit `index` is set.
+    pub fn test(&self, index: usize) -> bool {
+        self.check(index);
+        self.words[index / WORD_BITS] & (1 << (index % WORD_BITS)) != 0
+    }
+
+    /// Sets bit `index`, returning whether it was set before.
+    pub fn set(&mut self, index: usize) -> bool {
+        self.check(index);
+        let word = &mut self.words[index / WORD_BITS];
+        let mask = 1 << (index % WORD_BITS);
+        let was_set = *word & mask != 0;
+        *word |= mask;
+        was_set
+    }
+
+    /// Clears bit `index`, returning whether it was set before.
+    pub fn clear(&mut self, index: usize) -> bool {
+        self.check(index);
+        let word = &mut self.words[index / WORD_BITS];
+        let mask = 1 << (index % WORD_BITS);
+        let was_set = *word & mask != 0;
+        *word &= !mask;
+        was_set
+    }
+
+    /// Sets every bit in `range`.
+    pub fn set_range(&mut self, range: Range<usize>) {
+        self.check_range(&range);
+        self.update_range(range, |word, mask| *word |= mask);
+    }
+
+    /// Clears every bit in `range`.
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        self.check_range(&range);
+        self.update_range(range, |word, mask| *word &= !mask);
+    }
+
+    /// Sets or clears every bit.
+    pub fn fill(&mut self, value: bool) {
+        if value {
+            self.set_range(0..self.len);
+        } else {
+            self.words.fill(0);
+        }
+    }
+
+    /// Applies `update` to each word overlapping `range`, with the mask of
+    /// the bits of `range` in that word.
+    fn update_range(&mut self, range: Range<usize>, update: impl Fn(&mut u64, u64)) {
+        let mut index = range.start;
+        while index < range.end {
+            let bit = index % WORD_BITS;
+            let bits = (WORD_BITS - bit).min(range.end - index);
+            let mask = if bits == WORD_BITS { u64::MAX } else { ((1 << bits) - 1) << bit };
+            update(&mut self.words[index / WORD_BITS], mask);
+            index += bits;
+        }
+    }
+
+    /// Returns whether every bit in `range` is clear.
+    pub fn is_range_clear(&self, range: Range<usize>) -> bool {
+        self.check_range(&range);
+        self.find_next_set(range.start).is_none_or(|index| index >= range.end)
+    }
+
+    /// Returns whether every bit in `range` is set.
+    pub fn is_range_set(&self, range: Range<usize>) -> bool {
+        self.check_range(&range);
+        self.find_next_zero(range.start).is_none_or(|index| index >= range.end)
+    }
+
+    /// Returns the lowest index at or after `from` whose bit, xored with
+    /// `invert`, is set.
+    fn find_next(&self, from: usize, invert: u64) -> Option<usize> {
+        if from >= self.len {
+            return None;
+        }
+        let mut word_index = from / WORD_BITS;
+        let mut word = (self.words[word_index] ^ invert) & (u64::MAX << (from % WORD_BITS));
+        loop {
+            if word != 0 {
+                let index = word_index * WORD_BITS + word.trailing_zeros() as usize;
+                // Clear bits beyond the end read as set when inverted.
+                return (index < self.len).then_some(index);
+            }
+            word_index += 1;
+            word = *self.words.get(word_index)? ^ invert;
+        }
+    }
+
+    /// Returns the lowest clear bit.
+    pub fn find_first_zero(&self) -> Option<usize> {
+        self.find_next(0, u64::MAX)
+    }
+
+    /// Returns the lowest set bit.
+    pub fn find_first_set(&self) -> Option<usize> {
+        self.find_next(0, 0)
+    }
+
+    /// Returns the lowest clear bit at or after `from`.
+    pub fn find_next_zero(&self, from: usize) -> Option<usize> {
+        self.find_next(from, u64::MAX)
+    }
+
+    /// Returns the lowest set bit at or after `from`.
+    pub fn find_next_set(&self, from: usize) -> Option<usize> {
+        self.find_next(from, 0)
+    }
+
+    /// Returns the start of the lowest run of `count` clear bits whose
+    /// start is a multiple of `align`, which must be a power of two.
+    pub fn find_zero_range(&self, count: usize, align: usize) -> Option<usize> {
+        assert!(align.is_power_of_two(), "alignment {} is not a power of two", align);
+        let mut start = self.find_first_zero()?;
+        loop {
+            start = start.checked_next_multiple_of(align)?;
+            let end = start.checked_add(count)?;
+            if end > self.len {
+                return None;
+            }
+            // Restart past the first set bit in the candidate run.
+            match self.find_next_set(start) {
+                Some(set) if set < end => start = self.find_next_zero(set)?,
+                _ => return Some(start),
+            }
+        }
+    }
+
+    /// Returns the number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Returns the number of clear bits.
+    pub fn count_zeros(&self) -> usize {
+        self.len - self.count_ones()
+    }
+
+    /// Iterates over the indices of the set bits in ascending order.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut next = 0;
+        core::iter::from_fn(move || {
+            let index = self.find_next_set(next)?;
+            next = index + 1;
+            Some(index)
+        })
+    }
+
+    /// Iterates over the indices of the clear bits in ascending order.
+    pub fn iter_zeros(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut next = 0;
+        core::iter::from_fn(move || {
+            let index = self.find_next_zero(next)?;
+            next = index + 1;
+            Some(index)
+        })
+    }
+
+    /// Changes the length to `len`. New bits are clear.
+    pub fn resize(&mut self, len: usize) {
+        if len < self.len && !len.is_multiple_of(WORD_BITS) {
+            // Keep bits beyond the end clear, as the searches expect.
+            self.words[len / WORD_BITS] &= (1 << (len % WORD_BITS)) - 1;
+        }
+        self.words.resize(len.div_ceil(WORD_BITS), 0);
+        self.len = len;
+    }
+}
+
+impl fmt::Debug for Bitmap {
+    /// Formats the set bits as ranges, like `{0..4, 9}`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut set = f.debug_set();
+        let mut next = 0;
+        while let Some(start) = self.find_next_set(next) {
+            let end = self.find_next_zero(start).unwrap_or(self.len);
+            if end - start == 1 {
+                set.entry(&start);
+            } else {
+                set.entry(&(start..end));
+            }
+            next = end;
+        }
+        set.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_clear_test_across_words() {
+        let mut bitmap = Bitmap::new(130);
+        assert_eq!(bitmap.find_first_set(), None);
+        for index in [0, 63, 64, 129] {
+            assert!(!bitmap.set(index));
+            assert!(bitmap.test(index));
+        }
+        assert!(bitmap.set(64));
+        assert_eq!(bitmap.count_ones(), 4);
+        assert_eq!(bitmap.iter_ones().collect::<Vec<_>>(), [0, 63, 64, 129]);
+        assert!(bitmap.clear(63));
+        assert!(!bitmap.clear(63));
+        assert_eq!(bitmap.find_next_set(1), Some(64));
+        assert_eq!(bitmap.find_first_zero(), Some(1));
+        assert_eq!(format!("{:?}", bitmap), "{0, 64, 129}");
+    }
+
+    #[test]
+    fn ranges_and_zero_runs() {
+        let mut bitmap = Bitmap::new(200);
+        bitmap.set_range(3..140);
+        assert_eq!(bitmap.count_ones(), 137);
+        assert!(bitmap.is_range_set(3..140));
+        assert!(bitmap.is_range_clear(140..200));
+        assert!(!bitmap.is_range_clear(0..4));
+        assert_eq!(bitmap.find_next_zero(3), Some(140));
+        assert_eq!(format!("{:?}", bitmap), "{3..140}");
+        // 0..3 is too short; the first aligned run of 8 starts at 144.
+        assert_eq!(bitmap.find_zero_range(3, 1), Some(0));
+        assert_eq!(bitmap.find_zero_range(8, 16), Some(144));
+        assert_eq!(bitmap.find_zero_range(61, 1), None);
+        bitmap.clear_range(64..128);
+        assert_eq!(bitmap.find_zero_range(64, 64), Some(64));
+        assert_eq!(bitmap.iter_zeros().take(4).collect::<Vec<_>>(), [0, 1, 2, 64]);
+    }
+
+    #[test]
+    fn full_bitmap_and_resize() {
+        let mut bitmap = Bitmap::new(70);
+        bitmap.fill(true);
+        assert_eq!(bitmap.find_first_zero(), None);
+        assert_eq!(bitmap.count_zeros(), 0);
+        bitmap.resize(66);
+        bitmap.resize(100);
+        assert_eq!(bitmap.find_first_zero(), Some(66));
+        assert_eq!(bitmap.count_ones(), 66);
+        bitmap.fill(false);
+        assert!(bitmap.is_range_clear(0..100));
+        assert!(Bitmap::new(0).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_range_panics() {
+        Bitmap::new(10).set(10);
+    }
+}
@@ -0,0 +1,369 @@
+// nt_rustos/src/trap/collections/hash_map.rs
+
+//! # Open-Addressing Hash Map
+//!
+//! An unordered map for lookups by small keys such as PIDs, interrupt
+//! numbers and handler ids, where `BTreeMap`'s ordering is not needed and
+//! its pointer chasing is. Entries live in one power-of-two table probed
+//! linearly; removal shifts the following entries back instead of leaving
+//! tombstones, so lookups stay short however many removals there have been.
+//!
+//! Keys are hashed with 64-bit FNV-1a by default, which is fast for
+//! integer keys and needs no random seed. It is not resistant to chosen
+//! keys, so maps keyed by untrusted input should supply another hasher.
+
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use core::mem;
+
+/// 64-bit FNV-1a.
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+pub type FnvBuildHasher = BuildHasherDefault<FnvHasher>;
+
+/// The table grows once more than 3/4 of its slots are used.
+const MAX_LOAD_NUM: usize = 3;
+const MAX_LOAD_DEN: usize = 4;
+/// Slots in the first table allocated.
+const MIN_SLOTS: usize = 8;
+
+/// An unordered map from keys to values.
+pub struct HashMap<K, V, S = FnvBuildHasher> {
+    slots: Vec<Option<(K, V)>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K, V> HashMap<K, V> {
+    pub const fn new() -> Self {
+        Self { slots: Vec::new(), len: 0, hasher: BuildHasherDefault::new() }
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    pub const fn with_hasher(hasher: S) -> Self {
+        Self { slots: Vec::new(), len: 0, hasher }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns how many entries fit before the table grows.
+    pub fn capacity(&self) -> usize {
+        self.slots.len() * MAX_LOAD_NUM / MAX_LOAD_DEN
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.len = 0;
+    }
+
+    /// Iterates over the entries in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.slots.iter().flatten().map(|(key, value)| (key, value))
+    }
+
+    /// Iterates over the entries, with mutable values, in no particular
+    /// order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> + '_ {
+        self.slots.iter_mut().flatten().map(|(key, value)| (&*key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Removes and returns every entry.
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> {
+        self.len = 0;
+        mem::take(&mut self.slots).into_iter().flatten()
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Creates a map that holds `capacity` entries without growing.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        map.reserve(capacity);
+        map
+    }
+
+    fn home<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+        self.hasher.hash_one(key) as usize & self.mask()
+    }
+
+    /// Returns the slot holding `key` in `Ok`, or in `Err` the empty slot
+    /// that ends its probe. The table must not be empty.
+    fn probe<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut index = self.home(key);
+        // The load limit guarantees an empty slot ends every probe.
+        while let Some((candidate, _)) = &self.slots[index] {
+            if candidate.borrow() == key {
+                return Ok(index);
+            }
+            index = (index + 1) & self.mask();
+        }
+        Err(index)
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+        self.probe(key).ok()
+    }
+
+    /// Makes room for `additional` more entries without growing.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len + additional;
+        if needed <= self.capacity() {
+            return;
+        }
+        let slots = (needed * MAX_LOAD_DEN).div_ceil(MAX_LOAD_NUM).next_power_of_two().max(MIN_SLOTS);
+        self.rehash(slots, |_, _| true);
+    }
+
+    /// Moves the entries `keep` accepts into a new table of `slots` slots.
+    fn rehash(&mut self, slots: usize, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        let old = mem::replace(&mut self.slots, (0..slots).map(|_| None).collect());
+        self.len = 0;
+        for (key, mut value) in old.into_iter().flatten() {
+            if keep(&key, &mut value) {
+                if let Err(index) = self.probe(&key) {
+                    self.slots[index] = Some((key, value));
+                    self.len += 1;
+                }
+            }
+        }
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(index) = self.find(&key) {
+            return self.slots[index].as_mut().map(|(_, old)| mem::replace(old, value));
+        }
+        self.reserve(1);
+        if let Err(index) = self.probe(&key) {
+            self.slots[index] = Some((key, value));
+            self.len += 1;
+        }
+        None
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find(key)?;
+        self.slots[index].as_ref().map(|(_, value)| value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find(key)?;
+        self.slots[index].as_mut().map(|(_, value)| value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Returns the value under `key`, inserting `make()` first if absent.
+    pub fn get_or_insert_with(&mut self, key: K, make: impl FnOnce() -> V) -> &mut V {
+        self.reserve(1);
+        let index = match self.probe(&key) {
+            Ok(index) => index,
+            Err(index) => {
+                self.slots[index] = Some((key, make()));
+                self.len += 1;
+                index
+            }
+        };
+        match &mut self.slots[index] {
+            Some((_, value)) => value,
+            None => unreachable!(),
+        }
+    }
+
+    /// Removes `key`, returning its value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut hole = self.find(key)?;
+        let (_, value) = self.slots[hole].take()?;
+        self.len -= 1;
+        // Shift back entries whose probe from their home slot passes the
+        // hole, so that no probe meets an empty slot before its key.
+        let mut index = (hole + 1) & self.mask();
+        while let Some((key, _)) = &self.slots[index] {
+            let distance = index.wrapping_sub(self.home(key)) & self.mask();
+            if distance >= (index.wrapping_sub(hole) & self.mask()) {
+                self.slots[hole] = self.slots[index].take();
+                hole = index;
+            }
+            index = (index + 1) & self.mask();
+        }
+        Some(value)
+    }
+
+    /// Keeps only the entries for which `keep` returns `true`.
+    pub fn retain(&mut self, keep: impl FnMut(&K, &mut V) -> bool) {
+        self.rehash(self.slots.len(), keep);
+    }
+}
+
+impl<K, V> Default for HashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone, V: Clone, S: Clone> Clone for HashMap<K, V, S> {
+    fn clone(&self) -> Self {
+        Self { slots: self.slots.clone(), len: self.len, hasher: self.hasher.clone() }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for HashMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for HashMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(items: I) -> Self {
+        let mut map = Self::with_hasher(S::default());
+        for (key, value) in items {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    /// Sends every key to the same slot, to exercise probing and removal.
+    #[derive(Default)]
+    struct Collide;
+
+    impl Hasher for Collide {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _bytes: &[u8]) {}
+    }
+
+    #[test]
+    fn insert_get_replace_remove() {
+        let mut map = HashMap::new();
+        assert_eq!(map.get(&1), None);
+        for pid in 0..100u64 {
+            assert_eq!(map.insert(pid, pid * 10), None);
+        }
+        assert_eq!(map.len(), 100);
+        assert!(map.capacity() >= 100);
+        assert_eq!(map.insert(7, 0), Some(70));
+        assert_eq!(map.get(&7), Some(&0));
+        *map.get_mut(&8).unwrap() += 1;
+        assert_eq!(map.remove(&8), Some(81));
+        assert_eq!(map.remove(&8), None);
+        assert!(!map.contains_key(&8));
+        assert!((0..100).filter(|&pid| pid != 8).all(|pid| map.contains_key(&pid)));
+        let mut values: Vec<u64> = map.values().copied().collect();
+        values.sort_unstable();
+        assert_eq!(values.len(), 99);
+    }
+
+    #[test]
+    fn removal_keeps_colliding_keys_reachable() {
+        let mut map: HashMap<u32, u32, BuildHasherDefault<Collide>> = HashMap::with_hasher(Default::default());
+        for key in 0..6 {
+            map.insert(key, key);
+        }
+        assert_eq!(map.remove(&0), Some(0));
+        assert_eq!(map.remove(&3), Some(3));
+        assert_eq!(map.len(), 4);
+        assert!([1, 2, 4, 5].iter().all(|key| map.get(key) == Some(key)));
+        map.insert(9, 9);
+        assert_eq!(map.get(&9), Some(&9));
+    }
+
+    #[test]
+    fn borrowed_keys_retain_and_drain() {
+        let mut map: HashMap<String, usize> = ["irq", "pid", "ctx"].iter().map(|&k| (String::from(k), k.len())).collect();
+        *map.get_or_insert_with(String::from("pid"), || 0) += 1;
+        assert_eq!(*map.get_or_insert_with(String::from("id"), || 2), 2);
+        assert_eq!(map.get("pid"), Some(&4));
+        map.retain(|key, _| key != "irq");
+        assert_eq!(map.len(), 3);
+        let mut drained: Vec<(String, usize)> = map.drain().collect();
+        drained.sort();
+        assert_eq!(drained, vec![(String::from("ctx"), 3), (String::from("id"), 2), (String::from("pid"), 4)]);
+        assert!(map.is_empty());
+        assert_eq!(map.get("ctx"), None);
+    }
+
+    #[test]
+    fn fnv_matches_reference() {
+        let mut hasher = FnvHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+}
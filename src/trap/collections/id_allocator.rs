@@ -0,0 +1,181 @@
+// nt_rustos/src/trap/collections/id_allocator.rs
+
+//! # ID Allocator
+//!
+//! Hands out integer IDs from a fixed range, such as PIDs or registrar IDs,
+//! and takes them back for reuse. Allocated IDs are tracked in a `Bitmap`
+//! that starts empty and doubles as IDs are handed out, so a large range
+//! costs nothing until it is used and the allocator can be built in a
+//! `static`.
+//!
+//! IDs are handed out in increasing order, and a freed ID is only reused
+//! once the search for a free ID has wrapped past the end of the range.
+//! A stale ID held somewhere after it was freed therefore refers to
+//! nothing for as long as possible instead of to its next owner.
+
+use super::Bitmap;
+use core::fmt;
+use core::ops::Range;
+
+/// Smallest number of IDs tracked once the allocator grows.
+const MIN_TRACKED: usize = 64;
+
+/// An allocator of unique IDs from a range.
+pub struct IdAllocator {
+    /// Bit `i` is set if ID `ids.start + i` is allocated.
+    used: Bitmap,
+    ids: Range<u64>,
+    /// Where the search for a free ID starts.
+    next: usize,
+    count: usize,
+}
+
+impl IdAllocator {
+    /// Creates an allocator of the IDs in `ids`, none of them allocated.
+    pub const fn new(ids: Range<u64>) -> Self {
+        Self { used: Bitmap::empty(), ids, next: 0, count: 0 }
+    }
+
+    /// Returns the range IDs are allocated from.
+    pub fn ids(&self) -> Range<u64> {
+        self.ids.clone()
+    }
+
+    /// Returns the number of allocated IDs.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the number of IDs in the range, capped at `usize::MAX`.
+    fn span(&self) -> usize {
+        usize::try_from(self.ids.end.saturating_sub(self.ids.start)).unwrap_or(usize::MAX)
+    }
+
+    fn index(&self, id: u64) -> Option<usize> {
+        if !self.ids.contains(&id) {
+            return None;
+        }
+        usize::try_from(id - self.ids.start).ok().filter(|&index| index < self.span())
+    }
+
+    /// Tracks at least `len` IDs, if the range has that many.
+    fn grow_to(&mut self, len: usize) -> bool {
+        if len > self.span() {
+            return false;
+        }
+        if len > self.used.len() {
+            let doubled = self.used.len().saturating_mul(2).max(MIN_TRACKED);
+            self.used.resize(doubled.max(len).min(self.span()));
+        }
+        true
+    }
+
+    /// Allocates the next free ID, or returns `None` if all are in use.
+    pub fn alloc(&mut self) -> Option<u64> {
+        // Prefer IDs never handed out, or not since the last wrap, over
+        // reusing ones freed behind the search position.
+        let tracked = self.used.len();
+        let index = match self.used.find_next_zero(self.next) {
+            Some(index) => index,
+            None if self.grow_to(tracked + 1) => tracked,
+            None => self.used.find_first_zero()?,
+        };
+        self.used.set(index);
+        self.next = index + 1;
+        self.count += 1;
+        Some(self.ids.start + index as u64)
+    }
+
+    /// Marks `id` allocated so `alloc` never returns it. Returns `false`
+    /// if it is outside the range or already allocated. Every ID below
+    /// `id` is tracked from then on.
+    pub fn reserve(&mut self, id: u64) -> bool {
+        let Some(index) = self.index(id) else {
+            return false;
+        };
+        if !self.grow_to(index + 1) || self.used.set(index) {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+
+    /// Frees `id` for reuse. Returns `false` if it was not allocated.
+    pub fn free(&mut self, id: u64) -> bool {
+        let Some(index) = self.index(id) else {
+            return false;
+        };
+        if index >= self.used.len() || !self.used.clear(index) {
+            return false;
+        }
+        self.count -= 1;
+        true
+    }
+
+    /// Returns whether `id` is allocated or reserved.
+    pub fn is_allocated(&self, id: u64) -> bool {
+        self.index(id).is_some_and(|index| index < self.used.len() && self.used.test(index))
+    }
+}
+
+impl fmt::Debug for IdAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdAllocator")
+            .field("ids", &self.ids)
+            .field("allocated", &self.count)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn allocates_in_order_and_reuses_after_wrap() {
+        let mut ids = IdAllocator::new(10..14);
+        assert_eq!((0..4).map(|_| ids.alloc()).collect::<Vec<_>>(), [Some(10), Some(11), Some(12), Some(13)]);
+        assert_eq!(ids.alloc(), None);
+        assert!(ids.free(11));
+        assert!(!ids.free(11));
+        assert!(ids.free(10));
+        assert_eq!(ids.len(), 2);
+        // The search wraps to the lowest freed ID.
+        assert_eq!(ids.alloc(), Some(10));
+        assert_eq!(ids.alloc(), Some(11));
+        assert_eq!(ids.alloc(), None);
+    }
+
+    #[test]
+    fn freed_ids_wait_for_fresh_ones() {
+        let mut ids = IdAllocator::new(0..1000);
+        assert_eq!(ids.alloc(), Some(0));
+        assert_eq!(ids.alloc(), Some(1));
+        assert!(ids.free(0));
+        // 0 is not handed out again until the rest of the range is used.
+        assert!((2..1000).all(|id| ids.alloc() == Some(id)));
+        assert_eq!(ids.alloc(), Some(0));
+        assert_eq!(ids.alloc(), None);
+    }
+
+    #[test]
+    fn reserved_ids_are_skipped() {
+        let mut ids = IdAllocator::new(1..u64::MAX);
+        assert!(ids.reserve(3));
+        assert!(!ids.reserve(3));
+        assert!(ids.reserve(500));
+        assert!(!ids.reserve(0));
+        assert!(ids.is_allocated(500));
+        assert!(!ids.is_allocated(499));
+        assert_eq!((0..4).map(|_| ids.alloc().unwrap()).collect::<Vec<_>>(), [1, 2, 4, 5]);
+        assert!(ids.free(500));
+        assert!(!ids.free(1 << 40));
+        assert_eq!(ids.len(), 5);
+        assert_eq!(format!("{:?}", ids), "IdAllocator { ids: 1..18446744073709551615, allocated: 5 }");
+    }
+}
@@ -3,10 +3,24 @@
 //! # Kernel Collections Module
 //!
 //! Provides common, heap-allocated data structures for use within the kernel,
-//! such as a generic ring buffer. These collections are designed to be safe
-//! and efficient for kernel-level programming.
+//! such as a generic ring buffer, a bounded MPSC queue, a lock-free SPSC ring,
+//! a bitmap, an ID allocator, a hash map and a map of disjoint address
+//! ranges. These collections are designed to be safe and efficient for
+//! kernel-level programming.
 
+pub mod bitmap;
+pub mod hash_map;
+pub mod id_allocator;
 pub mod ring_buffer;
+pub mod mpsc;
+pub mod range_map;
+pub mod spsc;
 
-// Re-export the RingBuffer for easy access.
-pub use self::ring_buffer::RingBuffer;
\ No newline at end of file
+// Re-export the collections for easy access.
+pub use self::bitmap::Bitmap;
+pub use self::hash_map::HashMap;
+pub use self::id_allocator::IdAllocator;
+pub use self::ring_buffer::RingBuffer;
+pub use self::mpsc::MpscQueue;
+pub use self::range_map::RangeMap;
+pub use self::spsc::SpscRing;
\ No newline at end of file
@@ -0,0 +1,81 @@
+// nt_rustos/src/trap/collections/mpsc.rs
+
+//! # Bounded MPSC Queue
+//!
+//! A fixed-capacity, first-in first-out queue for handing items from any
+//! number of producers to a single consumer. Unlike `RingBuffer`, a full
+//! queue rejects new items instead of overwriting the oldest one, so no
+//! message is ever lost silently.
+//!
+//! All operations take `&self`; the queue is protected by an internal spin
+//! lock held only for the duration of a single push or pop.
+
+use alloc::collections::VecDeque;
+use core::fmt;
+use spin::Mutex;
+
+/// A bounded multi-producer, single-consumer queue.
+pub struct MpscQueue<T> {
+    items: Mutex<VecDeque<T>>,
+    capacity: usize,
+}
+
+impl<T> MpscQueue<T> {
+    /// Creates an empty queue holding at most `capacity` items.
+    ///
+    /// # Panics
+    /// Panics if the capacity is 0.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "MpscQueue capacity cannot be zero");
+        Self {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Appends `item` to the back of the queue.
+    /// If the queue is full, the item is handed back in `Err`.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let mut items = self.items.lock();
+        if items.len() >= self.capacity {
+            return Err(item);
+        }
+        items.push_back(item);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest item.
+    /// Returns `None` if the queue is empty.
+    pub fn pop(&self) -> Option<T> {
+        self.items.lock().pop_front()
+    }
+
+    /// Returns the number of queued items.
+    pub fn len(&self) -> usize {
+        self.items.lock().len()
+    }
+
+    /// Returns the maximum number of items the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Checks if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.items.lock().is_empty()
+    }
+
+    /// Checks if the queue is full.
+    pub fn is_full(&self) -> bool {
+        self.items.lock().len() >= self.capacity
+    }
+}
+
+impl<T> fmt::Debug for MpscQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MpscQueue")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
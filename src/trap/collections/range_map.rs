@@ -0,0 +1,205 @@
+// nt_rustos/src/trap/collections/range_map.rs
+
+//! # Range Map
+//!
+//! A map from disjoint address ranges to values, ordered by address, for
+//! tracking regions such as mapped areas or allocated blocks. Inserting a
+//! range that overlaps one already present fails and names the range in
+//! the way, so building a map from `n` ranges also checks them for overlap
+//! in O(n log n).
+//!
+//! Because the ranges are disjoint, only the range starting last before an
+//! address can contain it: point and overlap queries are a tree lookup
+//! followed by a walk over the ranges they return.
+//!
+//! Ranges are half-open and must not be empty.
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::ops::Range;
+
+/// An insertion overlapped the range `start..end` already in the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "overlaps {:#x}..{:#x}", self.start, self.end)
+    }
+}
+
+/// Disjoint ranges with a value each.
+pub struct RangeMap<V> {
+    /// Range end and value by range start.
+    ranges: BTreeMap<usize, (usize, V)>,
+}
+
+impl<V> RangeMap<V> {
+    pub const fn new() -> Self {
+        Self { ranges: BTreeMap::new() }
+    }
+
+    /// Returns the number of ranges.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Adds `range` with `value`, unless it overlaps a range in the map.
+    ///
+    /// # Panics
+    /// Panics if `range` is empty.
+    pub fn insert(&mut self, range: Range<usize>, value: V) -> Result<(), Overlap> {
+        assert!(range.start < range.end, "empty range {:#x}..{:#x}", range.start, range.end);
+        if let Some((found, _)) = self.overlapping(range.clone()).next() {
+            return Err(Overlap { start: found.start, end: found.end });
+        }
+        self.ranges.insert(range.start, (range.end, value));
+        Ok(())
+    }
+
+    /// Removes the range starting at `start`, returning it and its value.
+    pub fn remove(&mut self, start: usize) -> Option<(Range<usize>, V)> {
+        self.ranges.remove(&start).map(|(end, value)| (start..end, value))
+    }
+
+    /// Returns the range containing `addr` and its value.
+    pub fn get(&self, addr: usize) -> Option<(Range<usize>, &V)> {
+        let (&start, (end, value)) = self.ranges.range(..=addr).next_back()?;
+        (addr < *end).then_some((start..*end, value))
+    }
+
+    /// Returns the range containing `addr` and its value, mutably.
+    pub fn get_mut(&mut self, addr: usize) -> Option<(Range<usize>, &mut V)> {
+        let (&start, (end, value)) = self.ranges.range_mut(..=addr).next_back()?;
+        (addr < *end).then_some((start..*end, value))
+    }
+
+    /// Returns whether a range contains `addr`.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.get(addr).is_some()
+    }
+
+    /// Iterates over the ranges overlapping `range`, in address order.
+    pub fn overlapping(&self, range: Range<usize>) -> impl Iterator<Item = (Range<usize>, &V)> + '_ {
+        // Only the last range starting before `range` can reach into it.
+        let before = self
+            .ranges
+            .range(..range.start)
+            .next_back()
+            .filter(|(_, (end, _))| *end > range.start);
+        let within = self.ranges.range(range.start..range.end.max(range.start));
+        before.into_iter().chain(within).map(|(&start, (end, value))| (start..*end, value))
+    }
+
+    /// Returns whether any range overlaps `range`.
+    pub fn overlaps(&self, range: Range<usize>) -> bool {
+        self.overlapping(range).next().is_some()
+    }
+
+    /// Iterates over all ranges in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (Range<usize>, &V)> + '_ {
+        self.ranges.iter().map(|(&start, (end, value))| (start..*end, value))
+    }
+
+    /// Returns the lowest address within `bounds`, a multiple of `align`
+    /// (a power of two), where `size` bytes overlap no range.
+    pub fn find_gap(&self, size: usize, align: usize, bounds: Range<usize>) -> Option<usize> {
+        assert!(align.is_power_of_two(), "alignment {} is not a power of two", align);
+        let mut candidate = bounds.start;
+        for (range, _) in self.overlapping(bounds.clone()) {
+            let start = candidate.checked_next_multiple_of(align)?;
+            if start.checked_add(size)? <= range.start {
+                return Some(start);
+            }
+            candidate = candidate.max(range.end);
+        }
+        let start = candidate.checked_next_multiple_of(align)?;
+        (start.checked_add(size)? <= bounds.end).then_some(start)
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+}
+
+impl<V> Default for RangeMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for RangeMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn starts<V>(ranges: impl Iterator<Item = (Range<usize>, V)>) -> Vec<usize> {
+        ranges.map(|(range, _)| range.start).collect()
+    }
+
+    #[test]
+    fn insert_rejects_overlaps() {
+        let mut map = RangeMap::new();
+        assert_eq!(map.insert(0x100..0x200, 'a'), Ok(()));
+        assert_eq!(map.insert(0x300..0x400, 'b'), Ok(()));
+        // Touching ranges do not overlap.
+        assert_eq!(map.insert(0x200..0x300, 'c'), Ok(()));
+        assert_eq!(map.insert(0x1ff..0x201, 'd'), Err(Overlap { start: 0x100, end: 0x200 }));
+        assert_eq!(map.insert(0x0..0x1000, 'e'), Err(Overlap { start: 0x100, end: 0x200 }));
+        assert_eq!(map.insert(0x380..0x390, 'f'), Err(Overlap { start: 0x300, end: 0x400 }));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.remove(0x200), Some((0x200..0x300, 'c')));
+        assert_eq!(map.remove(0x210), None);
+        assert_eq!(map.insert(0x280..0x300, 'g'), Ok(()));
+    }
+
+    #[test]
+    fn point_and_overlap_queries() {
+        let mut map = RangeMap::new();
+        for (i, start) in [0x1000, 0x3000, 0x5000, 0x7000].into_iter().enumerate() {
+            map.insert(start..start + 0x1000, i).unwrap();
+        }
+        assert_eq!(map.get(0x3fff), Some((0x3000..0x4000, &1)));
+        assert_eq!(map.get(0x4000), None);
+        assert_eq!(map.get(0x10), None);
+        *map.get_mut(0x5800).unwrap().1 = 9;
+        assert_eq!(map.get(0x5000).map(|(_, v)| *v), Some(9));
+        assert_eq!(starts(map.overlapping(0x3800..0x7001)), [0x3000, 0x5000, 0x7000]);
+        assert_eq!(starts(map.overlapping(0x4000..0x5000)), [0; 0]);
+        assert!(map.overlaps(0x0fff..0x1001));
+        assert!(!map.overlaps(0x2000..0x2000));
+        assert_eq!(starts(map.iter()), [0x1000, 0x3000, 0x5000, 0x7000]);
+    }
+
+    #[test]
+    fn gaps_respect_alignment_and_bounds() {
+        let mut map = RangeMap::new();
+        map.insert(0x1000..0x1800, ()).unwrap();
+        map.insert(0x2000..0x4000, ()).unwrap();
+        assert_eq!(map.find_gap(0x800, 0x100, 0x1000..0x8000), Some(0x1800));
+        assert_eq!(map.find_gap(0x900, 0x100, 0x1000..0x8000), Some(0x4000));
+        assert_eq!(map.find_gap(0x1000, 0x4000, 0x1000..0x8000), Some(0x4000));
+        assert_eq!(map.find_gap(0x100, 0x10, 0x1100..0x1900), Some(0x1800));
+        assert_eq!(map.find_gap(0x5000, 0x10, 0x1000..0x8000), None);
+        assert_eq!(map.find_gap(0x1000, 0x1000, 0..0x1000), Some(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_range_panics() {
+        let _ = RangeMap::new().insert(5..5, ());
+    }
+}
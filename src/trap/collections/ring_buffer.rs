@@ -4,11 +4,15 @@
 //!
 //! A heap-allocated, generic ring buffer (or circular queue) implementation.
 //! It provides a fixed-capacity buffer that overwrites the oldest elements
-//! when full.
+//! when full. An optional hook sees each element before it is overwritten,
+//! so that losses can at least be counted.
 
 use alloc::vec::Vec;
 use core::fmt;
 
+/// Called with each element a full buffer overwrites.
+pub type OverwriteHook<T> = fn(&T);
+
 /// A generic, circular buffer.
 pub struct RingBuffer<T> {
     buffer: Vec<Option<T>>,
@@ -16,9 +20,10 @@ pub struct RingBuffer<T> {
     head: usize,
     tail: usize,
     count: usize,
+    on_overwrite: Option<OverwriteHook<T>>,
 }
 
-impl<T: Clone> RingBuffer<T> {
+impl<T> RingBuffer<T> {
     /// Creates a new `RingBuffer` with a specified capacity.
     /// The buffer is allocated on the heap.
     ///
@@ -37,14 +42,24 @@ impl<T: Clone> RingBuffer<T> {
             head: 0,
             tail: 0,
             count: 0,
+            on_overwrite: None,
         }
     }
 
+    /// Sets the hook called with each element that is overwritten.
+    pub fn set_overwrite_hook(&mut self, hook: OverwriteHook<T>) {
+        self.on_overwrite = Some(hook);
+    }
+
     /// Pushes an element into the buffer.
-    /// If the buffer is full, the oldest element is overwritten.
+    /// If the buffer is full, the oldest element is overwritten, after
+    /// being passed to the overwrite hook if one is set.
     pub fn push(&mut self, item: T) {
         // Place the new item at the current head position.
-        self.buffer[self.head] = Some(item);
+        let old = self.buffer[self.head].replace(item);
+        if let (Some(old), Some(hook)) = (&old, self.on_overwrite) {
+            hook(old);
+        }
 
         // Advance the head, wrapping around if necessary.
         self.head = (self.head + 1) % self.capacity;
@@ -125,6 +140,12 @@ impl<T: Clone> RingBuffer<T> {
         self.count = 0;
     }
 
+    /// Removes all elements, yielding them from oldest to newest.
+    /// Elements the iterator has not yielded when dropped are removed too.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        Drain { buffer: self }
+    }
+
     /// Returns an iterator that yields references to the elements
     /// from oldest to newest.
     pub fn iter(&self) -> Iter<'_, T> {
@@ -158,8 +179,120 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
+/// A draining iterator over the elements of a `RingBuffer`.
+pub struct Drain<'a, T> {
+    buffer: &'a mut RingBuffer<T>,
+}
+
+impl<T> Iterator for Drain<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.buffer.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.buffer.len(), Some(self.buffer.len()))
+    }
+}
+
+impl<T> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.buffer.clear();
+    }
+}
+
 impl<T: fmt::Debug> fmt::Debug for RingBuffer<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_list().entries(self.iter()).finish()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_pop_wraps_around() {
+        let mut ring = RingBuffer::with_capacity(3);
+        for round in 0..5 {
+            ring.push(round * 2);
+            ring.push(round * 2 + 1);
+            assert_eq!(ring.len(), 2);
+            assert_eq!(ring.pop(), Some(round * 2));
+            assert_eq!(ring.pop(), Some(round * 2 + 1));
+            assert!(ring.is_empty());
+        }
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn full_buffer_overwrites_oldest() {
+        let mut ring = RingBuffer::with_capacity(4);
+        for i in 0..10 {
+            ring.push(i);
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.front(), Some(&6));
+        assert_eq!(ring.back(), Some(&9));
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [6, 7, 8, 9]);
+        assert_eq!(ring.pop(), Some(6));
+        ring.push(10);
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn clear_resets() {
+        let mut ring = RingBuffer::with_capacity(2);
+        ring.push("a");
+        ring.push("b");
+        ring.push("c");
+        ring.clear();
+        assert!(ring.is_empty());
+        assert_eq!(ring.iter().count(), 0);
+        ring.push("d");
+        assert_eq!(ring.front(), ring.back());
+        assert_eq!(format!("{:?}", ring), r#"["d"]"#);
+    }
+
+    #[test]
+    fn drain_empties_in_order() {
+        let mut ring = RingBuffer::with_capacity(3);
+        for i in 0..5 {
+            ring.push(i);
+        }
+        assert_eq!(ring.drain().collect::<Vec<_>>(), [2, 3, 4]);
+        assert!(ring.is_empty());
+        ring.push(5);
+        ring.push(6);
+        // Elements left in an unfinished drain are removed as well.
+        assert_eq!(ring.drain().next(), Some(5));
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn overwrite_hook_sees_lost_items() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static LOST: AtomicUsize = AtomicUsize::new(0);
+        // A type that is not Clone.
+        struct Entry(usize);
+        fn count(entry: &Entry) {
+            LOST.fetch_add(entry.0, Ordering::Relaxed);
+        }
+
+        let mut ring = RingBuffer::with_capacity(2);
+        ring.set_overwrite_hook(count);
+        for i in 1..=4 {
+            ring.push(Entry(i));
+        }
+        assert_eq!(LOST.load(Ordering::Relaxed), 1 + 2);
+        assert_eq!(ring.pop().map(|entry| entry.0), Some(3));
+        ring.push(Entry(5));
+        assert_eq!(LOST.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_capacity_panics() {
+        let _ = RingBuffer::<u8>::with_capacity(0);
+    }
+}
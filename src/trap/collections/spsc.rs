@@ -0,0 +1,239 @@
+// nt_rustos/src/trap/collections/spsc.rs
+
+//! # SPSC Ring
+//!
+//! A fixed-capacity, lock-free queue from one producer to one consumer,
+//! such as an interrupt handler passing received bytes or trace events to
+//! a thread. `push` and `pop` finish in a bounded number of steps and
+//! never wait for the other side, so an interrupt handler can push while
+//! the consumer it interrupted is in the middle of a `pop`.
+//!
+//! Like `MpscQueue`, a full ring rejects new items instead of overwriting
+//! the oldest one.
+//!
+//! The ring is meant for one producing and one consuming context. A push
+//! that overlaps another push (or a pop another pop), for instance from
+//! two harts, does not wait: it fails as if the ring were full (or empty),
+//! so misuse loses items rather than corrupting the ring.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A bounded single-producer, single-consumer ring.
+pub struct SpscRing<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// Items pushed so far; only the producer writes it.
+    head: AtomicUsize,
+    /// Items popped so far; only the consumer writes it.
+    tail: AtomicUsize,
+    pushing: AtomicBool,
+    popping: AtomicBool,
+}
+
+// Slots are handed between the two sides through `head` and `tail`; each
+// side is claimed by at most one context at a time.
+unsafe impl<T: Send> Send for SpscRing<T> {}
+unsafe impl<T: Send> Sync for SpscRing<T> {}
+
+/// Clears the claim on one side of the ring when dropped.
+struct Claim<'a>(&'a AtomicBool);
+
+impl<'a> Claim<'a> {
+    fn take(flag: &'a AtomicBool) -> Option<Self> {
+        (!flag.swap(true, Ordering::Acquire)).then_some(Self(flag))
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<T> SpscRing<T> {
+    /// Creates an empty ring holding at most `capacity` items.
+    ///
+    /// # Panics
+    /// Panics if the capacity is 0 or not a power of two.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two(), "SpscRing capacity must be a power of two");
+        Self {
+            slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            pushing: AtomicBool::new(false),
+            popping: AtomicBool::new(false),
+        }
+    }
+
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.slots[position & (self.slots.len() - 1)].get()
+    }
+
+    /// Appends `item` to the back of the ring.
+    /// If the ring is full, or another push is in progress, the item is
+    /// handed back in `Err`.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let Some(_claim) = Claim::take(&self.pushing) else {
+            return Err(item);
+        };
+        let head = self.head.load(Ordering::Relaxed);
+        // Acquire: the consumer has finished reading the slot it freed.
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == self.slots.len() {
+            return Err(item);
+        }
+        unsafe { (*self.slot(head)).write(item) };
+        // Release: the item is written before the consumer sees it.
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest item.
+    /// Returns `None` if the ring is empty or another pop is in progress.
+    pub fn pop(&self) -> Option<T> {
+        let _claim = Claim::take(&self.popping)?;
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let item = unsafe { (*self.slot(tail)).assume_init_read() };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    /// Returns the number of queued items. Either side may change it at
+    /// any moment, so this is only a snapshot.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        self.head.load(Ordering::Acquire).wrapping_sub(tail)
+    }
+
+    /// Returns the maximum number of items the ring can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Checks if the ring is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks if the ring is full.
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.slots.len()
+    }
+}
+
+impl<T: Copy> SpscRing<T> {
+    /// Pushes as many of `items` as fit, in order, returning how many.
+    pub fn push_slice(&self, items: &[T]) -> usize {
+        items.iter().take_while(|&&item| self.push(item).is_ok()).count()
+    }
+
+    /// Pops items into `buf` until it is full or the ring is empty,
+    /// returning how many.
+    pub fn pop_into(&self, buf: &mut [T]) -> usize {
+        let mut count = 0;
+        while count < buf.len() {
+            match self.pop() {
+                Some(item) => buf[count] = item,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+impl<T> Drop for SpscRing<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T> fmt::Debug for SpscRing<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpscRing")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn fills_rejects_and_wraps() {
+        let ring = SpscRing::with_capacity(4);
+        for round in 0..3 {
+            assert_eq!(ring.push_slice(&[1, 2, 3, 4, 5]), 4);
+            assert!(ring.is_full());
+            assert_eq!(ring.push(6), Err(6));
+            let mut buf = [0; 3];
+            assert_eq!(ring.pop_into(&mut buf), 3);
+            assert_eq!(buf, [1, 2, 3]);
+            assert_eq!(ring.pop(), Some(4));
+            assert_eq!(ring.pop(), None, "round {}", round);
+        }
+    }
+
+    #[test]
+    fn overlapping_push_is_refused() {
+        let ring = SpscRing::with_capacity(2);
+        let claim = Claim::take(&ring.pushing);
+        assert_eq!(ring.push('a'), Err('a'));
+        drop(claim);
+        assert_eq!(ring.push('a'), Ok(()));
+    }
+
+    #[test]
+    fn drops_remaining_items() {
+        let item = Arc::new(());
+        let ring = SpscRing::with_capacity(8);
+        for _ in 0..5 {
+            ring.push(item.clone()).unwrap();
+        }
+        drop(ring.pop());
+        assert_eq!(Arc::strong_count(&item), 5);
+        drop(ring);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn items_cross_threads_in_order() {
+        const COUNT: u32 = 10_000;
+        let ring = Arc::new(SpscRing::with_capacity(64));
+        let producer = {
+            let ring = ring.clone();
+            std::thread::spawn(move || {
+                for i in 0..COUNT {
+                    while ring.push(i).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            })
+        };
+        let mut received = Vec::with_capacity(COUNT as usize);
+        while received.len() < COUNT as usize {
+            match ring.pop() {
+                Some(i) => received.push(i),
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert!(received.iter().copied().eq(0..COUNT));
+    }
+
+    #[test]
+    #[should_panic]
+    fn capacity_must_be_power_of_two() {
+        let _ = SpscRing::<u8>::with_capacity(6);
+    }
+}
@@ -24,6 +24,13 @@ pub enum ErrorLevel {
     Info = 4,
 }
 
+impl ErrorLevel {
+    pub const COUNT: usize = 5;
+    /// All levels, most severe first, in the order of their values.
+    pub const ALL: [ErrorLevel; Self::COUNT] =
+        [ErrorLevel::Fatal, ErrorLevel::Critical, ErrorLevel::Error, ErrorLevel::Warning, ErrorLevel::Info];
+}
+
 /// Identifies the subsystem where an error originated.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(u8)]
@@ -39,6 +46,22 @@ pub enum ErrorSource {
     Syscall,
 }
 
+impl ErrorSource {
+    pub const COUNT: usize = 9;
+    /// All sources, in the order of their values.
+    pub const ALL: [ErrorSource; Self::COUNT] = [
+        ErrorSource::Unknown,
+        ErrorSource::Generic,
+        ErrorSource::Trap,
+        ErrorSource::Memory,
+        ErrorSource::Process,
+        ErrorSource::FileSystem,
+        ErrorSource::Device,
+        ErrorSource::Network,
+        ErrorSource::Syscall,
+    ];
+}
+
 /// A structured error code, combining source, level, and a specific code.
 /// Format: 32-bit integer
 /// - Bits 24-31: `ErrorSource`
@@ -48,6 +71,10 @@ pub enum ErrorSource {
 pub struct ErrorCode(u32);
 
 impl ErrorCode {
+    /// The number of the warning the error manager raises for a source whose
+    /// errors exceed the rate limit, see `ErrorRateLimit`.
+    pub const RATE_EXCEEDED: u16 = 0xFFFF;
+
     /// Creates a new `ErrorCode`.
     pub const fn new(source: ErrorSource, level: ErrorLevel, code: u16) -> Self {
         Self(((source as u32) << 24) | ((level as u32) << 16) | (code as u32))
@@ -89,6 +116,11 @@ impl ErrorCode {
     pub fn is_fatal(&self) -> bool {
         self.level() == ErrorLevel::Fatal
     }
+
+    /// Checks if the error is the warning raised for exceeding the rate limit.
+    pub fn is_rate_exceeded(&self) -> bool {
+        self.number() == Self::RATE_EXCEEDED && self.level() == ErrorLevel::Warning
+    }
 }
 
 impl fmt::Debug for ErrorCode {
@@ -170,4 +202,44 @@ pub struct ErrorLogEntry {
     pub error: SystemError,
     /// The final result of the handling process.
     pub result: ErrorResult,
-}
\ No newline at end of file
+}
+/// How many errors of one source the error manager accepts within a window
+/// of time before it raises a `RATE_EXCEEDED` warning for the source.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ErrorRateLimit {
+    /// The window the rate is measured over, in timebase ticks.
+    pub window_ticks: u64,
+    /// Errors within the window allowed before the warning; `None` never
+    /// warns.
+    pub max_errors: Option<u64>,
+}
+
+/// Error statistics of one `ErrorSource`.
+///
+/// Times are monotonic timebase ticks taken by the error manager when the
+/// error was reported, not the `timestamp` the reporter gave.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ErrorStats {
+    pub source: ErrorSource,
+    /// Errors reported, indexed by `ErrorLevel`.
+    pub by_level: [u64; ErrorLevel::COUNT],
+    /// When the first error was reported.
+    pub first_ticks: u64,
+    /// When the latest error was reported.
+    pub last_ticks: u64,
+    /// Errors reported within the rate window ending when the statistics
+    /// were taken.
+    pub recent: u64,
+}
+
+impl ErrorStats {
+    /// Returns the errors reported at `level`.
+    pub fn count(&self, level: ErrorLevel) -> u64 {
+        self.by_level[level as usize]
+    }
+
+    /// Returns the errors reported at any level.
+    pub fn total(&self) -> u64 {
+        self.by_level.iter().sum()
+    }
+}
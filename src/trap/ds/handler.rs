@@ -7,7 +7,8 @@
 
 use super::context::TrapContext;
 use core::hash::{Hash, Hasher};
-use core::sync::atomic::{AtomicU64, Ordering};
+use crate::trap::collections::IdAllocator;
+use spin::Mutex;
 
 /// A unique identifier for a module or subsystem that registers handlers.
 /// This is used to verify and manage handler ownership.
@@ -21,11 +22,18 @@ pub const KERNEL_REGISTRAR_ID: RegistrarId = 0;
 /// but not necessarily core kernel.
 pub const SYSTEM_REGISTRAR_ID: RegistrarId = 1;
 
+/// Registrar IDs in use. Allocation starts from 2 to reserve special IDs.
+static REGISTRAR_IDS: Mutex<IdAllocator> = Mutex::new(IdAllocator::new(2..RegistrarId::MAX));
+
 /// Generates a new, unique `RegistrarId`.
-/// The counter starts from 2 to reserve special IDs.
 pub fn generate_registrar_id() -> RegistrarId {
-    static NEXT_ID: AtomicU64 = AtomicU64::new(2);
-    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+    REGISTRAR_IDS.lock().alloc().expect("registrar IDs exhausted")
+}
+
+/// Returns `id` for reuse by a later `generate_registrar_id`.
+/// Returns `false` if it was not generated or was already released.
+pub fn release_registrar_id(id: RegistrarId) -> bool {
+    REGISTRAR_IDS.lock().free(id)
 }
 
 
@@ -117,7 +125,7 @@ impl HandlerHandle {
 
     /// Generates a unique ID for a handler based on its properties.
     pub(crate) fn generate_id(description: &'static str, trap_type: super::TrapType) -> u64 {
-        let mut hasher =TINGS_HASH_seed_0-27-02-17-91_545>
+        let mut hasher = FnvHasher::new();
         description.hash(&mut hasher);
         trap_type.hash(&mut hasher);
         hasher.finish()
@@ -127,4 +135,28 @@ impl HandlerHandle {
     pub fn id(&self) -> u64 {
         self.id
     }
+}
+
+/// FNV-1a hasher for handle IDs; `core` provides no default hasher.
+struct FnvHasher(u64);
+
+impl FnvHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
 }
\ No newline at end of file
@@ -25,11 +25,13 @@ pub use self::context::{
 
 pub use self::error::{
     SystemError, ErrorCode, ErrorResult,
-    ErrorSource, ErrorLevel, ErrorLogEntry
+    ErrorSource, ErrorLevel, ErrorLogEntry,
+    ErrorRateLimit, ErrorStats,
 };
 
 pub use self::handler::{
     TrapHandler, TrapHandlerResult, TrapError,
     HandlerEntry, HandlerHandle, ProtectionLevel,
-    RegistrarId, SYSTEM_REGISTRAR_ID, KERNEL_REGISTRAR_ID
+    RegistrarId, SYSTEM_REGISTRAR_ID, KERNEL_REGISTRAR_ID, generate_registrar_id,
+    release_registrar_id,
 };
\ No newline at end of file
@@ -24,6 +24,8 @@ pub enum Interrupt {
     SupervisorSoft = 1,
     SupervisorTimer = 5,
     SupervisorExternal = 9,
+    /// Local counter overflow (Sscofpmf).
+    CounterOverflow = 13,
 }
 
 /// Supervisor-level exceptions.
@@ -65,11 +67,12 @@ pub enum TrapType {
     LoadMisaligned,
     StoreMisaligned,
     Unknown,
+    CounterOverflowInterrupt,
 }
 
 impl TrapType {
     /// The total number of distinct trap types defined.
-    pub const COUNT: usize = 16;
+    pub const COUNT: usize = 17;
 
     /// Converts an index into a `TrapType`. Useful for iterating over all types.
     pub fn from_index(index: usize) -> Option<Self> {
@@ -90,6 +93,7 @@ impl TrapType {
             13 => Some(TrapType::LoadMisaligned),
             14 => Some(TrapType::StoreMisaligned),
             15 => Some(TrapType::Unknown),
+            16 => Some(TrapType::CounterOverflowInterrupt),
             _ => None,
         }
     }
@@ -131,6 +135,7 @@ impl TrapCause {
                 1 => TrapType::SoftwareInterrupt,
                 5 => TrapType::TimerInterrupt,
                 9 => TrapType::ExternalInterrupt,
+                13 => TrapType::CounterOverflowInterrupt,
                 _ => TrapType::Unknown,
             }
         } else {
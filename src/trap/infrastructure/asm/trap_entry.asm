@@ -10,17 +10,63 @@
 # RISC-V寄存器上下文大小 (32 gp + 4 CSR) * 8 = 288字节
 .equ CONTEXT_SIZE, 288
 
+# sscratch约定：在内核中运行时为0；返回用户态前设置为该任务的内核栈顶
+
+# 当前内核栈可用的最低sp（栈底加上下文大小），0表示不检查
+.section .data
+.align 3
+.globl __kstack_limit
+__kstack_limit:
+    .dword 0
+
+# 内核栈溢出后处理陷入所用的栈
+.section .bss
+.align 12
+.globl __overflow_stack_top
+__overflow_stack:
+    .space 4096 * 4
+__overflow_stack_top:
+
+.section .text
+.align 4
+
 # 中断入口点
 __trap_entry:
+    # 交换sp与sscratch：来自用户态时sp变为内核栈顶
+    csrrw sp, sscratch, sp
+    bnez sp, 1f
+    # 来自内核态：换回原来的sp，sscratch恢复为0
+    csrrw sp, sscratch, sp
+    # 栈溢出检查：sp低于__kstack_limit时上下文已放不下，改用溢出栈
+    csrw sscratch, t0
+    la t0, __kstack_limit
+    ld t0, 0(t0)
+    bltu sp, t0, 4f
+    csrrw t0, sscratch, zero
+    j 1f
+4:
+    # 恢复t0；溢出时的sp留在sscratch中，下面会作为原始sp保存
+    csrrw t0, sscratch, sp
+    # 清除界限，使溢出栈上的嵌套陷入不会再次切换栈
+    la sp, __kstack_limit
+    sd zero, 0(sp)
+    la sp, __overflow_stack_top
+1:
     # 分配栈空间保存上下文
     addi sp, sp, -CONTEXT_SIZE
     
     # 保存通用寄存器 (x0是零寄存器，不需要保存)
     sd x1, 8(sp)    # ra
-    sd x2, 16(sp)   # sp (原始sp值)
     sd x3, 24(sp)   # gp
     sd x4, 32(sp)   # tp
     sd x5, 40(sp)   # t0
+
+    # 保存原始sp：来自用户态时取自sscratch，否则为分配上下文前的sp
+    csrrw t0, sscratch, zero
+    bnez t0, 2f
+    addi t0, sp, CONTEXT_SIZE
+2:
+    sd t0, 16(sp)   # sp (原始sp值)
     sd x6, 48(sp)   # t1
     sd x7, 56(sp)   # t2
     sd x8, 64(sp)   # s0/fp
@@ -78,6 +124,14 @@ __trap_return:
     
     ld t0, 264(sp)
     csrw sepc, t0     # 恢复sepc
+
+    # 返回用户态(SPP=0)时，记录内核栈顶供下次陷入使用
+    ld t0, 256(sp)
+    andi t0, t0, 0x100
+    bnez t0, 3f
+    addi t0, sp, CONTEXT_SIZE
+    csrw sscratch, t0
+3:
     
     # 不需要恢复scause和stval，它们是只读的或由硬件设置
     
@@ -114,9 +168,8 @@ __trap_return:
     ld x30, 240(sp) # t5
     ld x31, 248(sp) # t6
     
-    # 最后恢复sp
-    ld x2, 16(sp)   # 先加载原始sp值到t0
-    addi sp, sp, CONTEXT_SIZE  # 调整栈指针
+    # 最后恢复sp (原始sp值)
+    ld x2, 16(sp)
     
     # 返回到中断点
     sret
\ No newline at end of file
@@ -7,9 +7,14 @@
 
 use super::traits::{HandlerManager, ErrorManager, ContextManager, HardwareController};
 use crate::trap::ds::{self, TrapContext, SystemError, ErrorResult};
+use crate::util::ksyms::WithSymbol;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
 
+/// `sstatus.SPP`: the trap was taken from S-mode.
+#[cfg(not(feature = "host-test"))]
+const SSTATUS_SPP: usize = 1 << 8;
+
 pub struct TrapSystem {
     handler_manager: Arc<dyn HandlerManager>,
     error_manager: Arc<dyn ErrorManager>,
@@ -58,9 +63,14 @@ impl TrapSystem {
                 // For critical unhandled exceptions, this might involve generating a
                 // SystemError and passing it to the ErrorManager, or panicking.
                 let cause = context.cause();
+                // Kernel code that traps without a handler: show how it got there.
+                #[cfg(not(feature = "host-test"))]
+                if context.sstatus & SSTATUS_SPP != 0 {
+                    crate::util::backtrace::capture_from(context.sepc, context.x[8]).print();
+                }
                 let error = SystemError::new(
                     ds::ErrorCode::new(ds::ErrorSource::Trap, ds::ErrorLevel::Critical, cause.code() as u16),
-                    alloc::format!("Unhandled trap: {:?}, SEPC: {:#x}, STVAL: {:#x}", cause.to_trap_type(), context.sepc, context.stval),
+                    alloc::format!("Unhandled trap: {:?}, SEPC: {}, STVAL: {:#x}", cause.to_trap_type(), WithSymbol(context.sepc), context.stval),
                     Some(context.stval),
                     context.sepc,
                     0, // Placeholder for timestamp; a real system would get current time.
@@ -72,7 +82,7 @@ impl TrapSystem {
                 let cause = context.cause();
                  let error = SystemError::new(
                     ds::ErrorCode::new(ds::ErrorSource::Trap, ds::ErrorLevel::Error, cause.code() as u16),
-                    alloc::format!("Trap handler failed for {:?}: {:?}, SEPC: {:#x}", cause.to_trap_type(), trap_err, context.sepc),
+                    alloc::format!("Trap handler failed for {:?}: {:?}, SEPC: {}", cause.to_trap_type(), trap_err, WithSymbol(context.sepc)),
                     Some(context.stval),
                     context.sepc,
                     0, 
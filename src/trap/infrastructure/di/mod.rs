@@ -5,8 +5,11 @@
 //! Manages the global instance of the `TrapSystem` and provides safe
 //! mechanisms for its initialization and access.
 
-use super::container::TrapSystem;
-use super::traits::{HandlerManager, ErrorManager, ContextManager, HardwareController};
+pub mod container;
+pub mod traits;
+
+use self::container::TrapSystem;
+use self::traits::{HandlerManager, ErrorManager, ContextManager, HardwareController};
 use crate::trap::ds::{self, TrapContext, TrapMode};
 use crate::trap::infrastructure::{
     handler_manager::HeapHandlerManager,
@@ -16,8 +19,8 @@ use crate::trap::infrastructure::{
 };
 use alloc::boxed::Box;
 use alloc::sync::Arc;
-use spin::Mutex;
-use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, Once, RwLock};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 /// The global `TrapSystem` instance, protected by a `Mutex` for safe access.
 static GLOBAL_TRAP_SYSTEM: Mutex<Option<TrapSystem>> = Mutex::new(None);
@@ -25,6 +28,31 @@ static GLOBAL_TRAP_SYSTEM: Mutex<Option<TrapSystem>> = Mutex::new(None);
 /// Flag to ensure the trap system is initialized only once.
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// The signature of a hook run after every trap has been dispatched.
+pub type TrapExitHook = fn(&mut TrapContext);
+
+/// The signature of a hook run when a trap is taken, before dispatch.
+pub type TrapEntryHook = fn(&TrapContext);
+
+/// The signature of a hook run first on every trap, which may stop the
+/// hart instead of returning.
+pub type TrapStopHook = fn(&mut TrapContext);
+
+/// Hook invoked first on every trap, before any lock is taken.
+static TRAP_STOP_HOOK: Once<TrapStopHook> = Once::new();
+
+/// Hook invoked on entry to an outermost trap, before dispatch.
+static TRAP_ENTRY_HOOK: Mutex<Option<TrapEntryHook>> = Mutex::new(None);
+
+/// Hook invoked on trap exit, outside the `GLOBAL_TRAP_SYSTEM` lock.
+static TRAP_EXIT_HOOK: Mutex<Option<TrapExitHook>> = Mutex::new(None);
+
+/// Number of traps currently being dispatched (nesting depth).
+static TRAP_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Traps dispatched since boot, indexed by `TrapType`.
+static TRAP_COUNTS: [AtomicU64; ds::TrapType::COUNT] = [const { AtomicU64::new(0) }; ds::TrapType::COUNT];
+
 /// Concrete implementation for `HardwareController`.
 struct LowLevelHardwareController;
 impl HardwareController for LowLevelHardwareController {
@@ -107,9 +135,64 @@ pub(super) fn dispatch_trap(context_ptr: *mut TrapContext) {
         &mut *context_ptr
     };
 
+    // Checked before any lock is taken, so a hart can be stopped while
+    // another one holds the trap system's locks.
+    if let Some(hook) = TRAP_STOP_HOOK.get() {
+        hook(context);
+    }
+
+    if TRAP_DEPTH.load(Ordering::Acquire) == 0 {
+        let hook = *TRAP_ENTRY_HOOK.lock();
+        if let Some(hook) = hook {
+            hook(context);
+        }
+    }
+
+    TRAP_COUNTS[context.cause().to_trap_type() as usize].fetch_add(1, Ordering::Relaxed);
+    TRAP_DEPTH.fetch_add(1, Ordering::AcqRel);
     with_trap_system(|ts| {
         ts.handle_trap(context);
     });
+    TRAP_DEPTH.fetch_sub(1, Ordering::AcqRel);
+
+    // The exit hook runs after the trap system lock is released, so it may
+    // switch to another task (which may itself trap) without deadlocking.
+    let hook = *TRAP_EXIT_HOOK.lock();
+    if let Some(hook) = hook {
+        hook(context);
+    }
+}
+
+/// Installs the hook run on entry to every outermost trap, replacing any
+/// previous one.
+pub fn set_trap_entry_hook(hook: TrapEntryHook) {
+    *TRAP_ENTRY_HOOK.lock() = Some(hook);
+}
+
+/// Installs the hook run first on every trap. Returns `false` if one is
+/// already installed.
+pub fn set_trap_stop_hook(hook: TrapStopHook) -> bool {
+    let mut installed = false;
+    TRAP_STOP_HOOK.call_once(|| {
+        installed = true;
+        hook
+    });
+    installed
+}
+
+/// Installs the hook run on every trap exit, replacing any previous one.
+pub fn set_trap_exit_hook(hook: TrapExitHook) {
+    *TRAP_EXIT_HOOK.lock() = Some(hook);
+}
+
+/// Returns how many traps of `trap_type` have been dispatched since boot.
+pub fn trap_count(trap_type: ds::TrapType) -> u64 {
+    TRAP_COUNTS[trap_type as usize].load(Ordering::Relaxed)
+}
+
+/// Returns `true` while trap handlers are being dispatched.
+pub fn in_trap_handler() -> bool {
+    TRAP_DEPTH.load(Ordering::Acquire) > 0
 }
 
 /// Checks if the trap system has been initialized.
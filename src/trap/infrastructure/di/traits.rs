@@ -11,6 +11,7 @@ use crate::trap::ds::{
     RegistrarId,
 };
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::RwLock;
 
 /// Interface for the Trap Handler Manager.
@@ -40,6 +41,13 @@ pub trait HandlerManager: Send + Sync {
     
     /// Unregisters all handlers associated with a given context ID.
     fn unregister_for_context(&self, context_id: u64);
+
+    /// Returns the number of handlers associated with a given context ID.
+    fn count_for_context(&self, context_id: u64) -> usize;
+
+    /// Returns a copy of every registered handler with its trap type, in
+    /// dispatch order within each trap type.
+    fn list(&self) -> Vec<(TrapType, ds::HandlerEntry)>;
 }
 
 /// Interface for the Error Manager.
@@ -60,6 +68,21 @@ pub trait ErrorManager: Send + Sync {
 
     /// Logs an error to the system error log.
     fn log_error(&self, error: SystemError, result: ErrorResult);
+
+    /// Returns up to `count` of the most recently logged errors, oldest first.
+    fn recent_errors(&self, count: usize) -> Vec<ds::ErrorLogEntry>;
+
+    /// Returns how many logged errors were overwritten by newer ones.
+    fn dropped_errors(&self) -> u64;
+
+    /// Returns the error statistics of each source that reported errors.
+    fn error_stats(&self) -> Vec<ds::ErrorStats>;
+
+    /// Returns the rate limit above which a source raises a warning.
+    fn rate_limit(&self) -> ds::ErrorRateLimit;
+
+    /// Replaces the rate limit, discarding the rates measured so far.
+    fn set_rate_limit(&self, limit: ds::ErrorRateLimit);
     
     /// Checks if the system is currently in a panic state.
     fn is_panic_mode(&self) -> bool;
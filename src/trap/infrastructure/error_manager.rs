@@ -4,19 +4,39 @@
 //!
 //! An implementation of the `ErrorManager` trait that uses heap-allocated
 //! collections for storing error handlers and a generic `RingBuffer` for logging.
+//!
+//! Every reported error is also counted in an `ErrorStatsTable`. When the
+//! errors of one source exceed the rate limit, the manager reports a
+//! `RATE_EXCEEDED` warning of that source after handling the error, which
+//! handlers can match like any other error. The warning is not raised for
+//! the warning itself.
 
+use super::error_stats::ErrorStatsTable;
+use crate::time;
 use crate::trap::collections::RingBuffer;
 use crate::trap::ds::{
-    self, SystemError, ErrorResult, ErrorSource, ErrorLevel, ErrorLogEntry,
+    self, SystemError, ErrorCode, ErrorResult, ErrorSource, ErrorLevel, ErrorLogEntry, ErrorRateLimit,
 };
 use crate::trap::infrastructure::di::traits::ErrorManager;
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::vec::Vec;
 use spin::Mutex;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 const ERROR_LOG_CAPACITY: usize = 256;
 
+/// Errors of one source within the rate window above which a warning is
+/// raised, by default. The window is one second.
+const DEFAULT_MAX_ERRORS: u64 = 100;
+
+/// Log entries overwritten before anyone read them.
+static DROPPED_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+fn count_dropped(_entry: &ErrorLogEntry) {
+    DROPPED_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
 type ErrorHandlerFn = fn(&SystemError) -> ErrorResult;
 
 struct ErrorHandlerEntry {
@@ -30,14 +50,21 @@ pub struct HeapErrorManager {
     // Handlers are stored in a BTreeMap, keyed by priority, to ensure sorted execution.
     handlers: Mutex<BTreeMap<u8, Vec<ErrorHandlerEntry>>>,
     log: Mutex<RingBuffer<ErrorLogEntry>>,
+    stats: Mutex<ErrorStatsTable>,
     panic_mode: AtomicBool,
 }
 
 impl HeapErrorManager {
     pub fn new() -> Self {
+        let mut log = RingBuffer::with_capacity(ERROR_LOG_CAPACITY);
+        log.set_overwrite_hook(count_dropped);
         Self {
             handlers: Mutex::new(BTreeMap::new()),
-            log: Mutex::new(RingBuffer::with_capacity(ERROR_LOG_CAPACITY)),
+            log: Mutex::new(log),
+            stats: Mutex::new(ErrorStatsTable::new(ErrorRateLimit {
+                window_ticks: time::frequency(),
+                max_errors: Some(DEFAULT_MAX_ERRORS),
+            })),
             panic_mode: AtomicBool::new(false),
         }
     }
@@ -56,29 +83,29 @@ impl HeapErrorManager {
         }
         true
     }
-}
 
-impl ErrorManager for HeapErrorManager {
-    fn register_handler(
-        &mut self,
-        priority: u8,
-        source: Option<ErrorSource>,
-        level: Option<ErrorLevel>,
-        handler: ErrorHandlerFn,
-    ) -> Result<(), ()> {
-        let entry = ErrorHandlerEntry {
-            priority,
-            source,
-            level,
-            handler,
-        };
-
-        let mut handlers = self.handlers.lock();
-        handlers.entry(priority).or_insert_with(Vec::new).push(entry);
-        Ok(())
+    /// Counts `error` and returns the warning to raise if it took its
+    /// source above the rate limit.
+    fn count(&self, error: &SystemError) -> Option<SystemError> {
+        let now = time::monotonic_ticks();
+        let source = error.code.source();
+        let mut stats = self.stats.lock();
+        let recent = stats.record(source, error.code.level(), now)?;
+        if error.code.is_rate_exceeded() {
+            return None;
+        }
+        let window = stats.limit().window_ticks;
+        Some(SystemError::new(
+            ErrorCode::new(source, ErrorLevel::Warning, ErrorCode::RATE_EXCEEDED),
+            format!("{:?} errors exceed the rate limit: {} in the last {} ticks", source, recent, window),
+            None,
+            0,
+            now,
+        ))
     }
 
-    fn handle_error(&self, error: SystemError) -> ErrorResult {
+    /// Runs the handlers matching `error` and logs it with the result.
+    fn dispatch(&self, error: SystemError) -> ErrorResult {
         if self.is_panic_mode() && !error.code.is_fatal() {
             // In panic mode, only process new fatal errors. Log others and ignore.
             self.log_error(error, ErrorResult::Unhandled);
@@ -117,11 +144,62 @@ impl ErrorManager for HeapErrorManager {
         self.log_error(error, final_result);
         final_result
     }
+}
+
+impl ErrorManager for HeapErrorManager {
+    fn register_handler(
+        &mut self,
+        priority: u8,
+        source: Option<ErrorSource>,
+        level: Option<ErrorLevel>,
+        handler: ErrorHandlerFn,
+    ) -> Result<(), ()> {
+        let entry = ErrorHandlerEntry {
+            priority,
+            source,
+            level,
+            handler,
+        };
+
+        let mut handlers = self.handlers.lock();
+        handlers.entry(priority).or_insert_with(Vec::new).push(entry);
+        Ok(())
+    }
+
+    fn handle_error(&self, error: SystemError) -> ErrorResult {
+        let rate_exceeded = self.count(&error);
+        let result = self.dispatch(error);
+        if let Some(warning) = rate_exceeded {
+            self.handle_error(warning);
+        }
+        result
+    }
 
     fn log_error(&self, error: SystemError, result: ErrorResult) {
         let log_entry = ErrorLogEntry { error, result };
         self.log.lock().push(log_entry);
     }
+
+    fn recent_errors(&self, count: usize) -> Vec<ErrorLogEntry> {
+        let log = self.log.lock();
+        log.iter().skip(log.len().saturating_sub(count)).cloned().collect()
+    }
+
+    fn dropped_errors(&self) -> u64 {
+        DROPPED_ERRORS.load(Ordering::Relaxed)
+    }
+
+    fn error_stats(&self) -> Vec<ds::ErrorStats> {
+        self.stats.lock().stats(time::monotonic_ticks())
+    }
+
+    fn rate_limit(&self) -> ErrorRateLimit {
+        self.stats.lock().limit()
+    }
+
+    fn set_rate_limit(&self, limit: ErrorRateLimit) {
+        self.stats.lock().set_limit(limit);
+    }
     
     fn is_panic_mode(&self) -> bool {
         self.panic_mode.load(Ordering::Relaxed)
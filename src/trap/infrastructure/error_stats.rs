@@ -0,0 +1,194 @@
+// nt_rustos/src/trap/infrastructure/error_stats.rs
+
+//! # Error Statistics
+//!
+//! Counts the errors reported to the error manager per `ErrorSource` and
+//! `ErrorLevel`, with the times of the first and latest error of each
+//! source, and measures each source's recent error rate against an
+//! `ErrorRateLimit`.
+//!
+//! The rate is counted in `BUCKETS` buckets that each span a slice of the
+//! window, kept in a ring indexed by time. A bucket left over from an
+//! earlier lap of the ring is recognized by its stored slice number and
+//! starts again from zero, so counting costs no allocation and no work
+//! proportional to the error rate. The window therefore moves in steps of
+//! one slice: errors are counted from the start of the oldest slice still
+//! in the window.
+//!
+//! `record` reports a source crossing the limit once; it does so again
+//! only after the source's rate has dropped back to the limit.
+
+use crate::trap::ds::{ErrorLevel, ErrorRateLimit, ErrorSource, ErrorStats};
+use alloc::vec::Vec;
+
+/// Slices the rate window is divided into.
+const BUCKETS: usize = 16;
+
+#[derive(Copy, Clone)]
+struct SourceCounters {
+    by_level: [u64; ErrorLevel::COUNT],
+    first_ticks: u64,
+    last_ticks: u64,
+    /// Slice number and errors counted in it.
+    buckets: [(u64, u64); BUCKETS],
+    /// The rate is above the limit and the crossing has been reported.
+    over_limit: bool,
+}
+
+impl SourceCounters {
+    const EMPTY: Self = Self {
+        by_level: [0; ErrorLevel::COUNT],
+        first_ticks: 0,
+        last_ticks: 0,
+        buckets: [(0, 0); BUCKETS],
+        over_limit: false,
+    };
+
+    fn total(&self) -> u64 {
+        self.by_level.iter().sum()
+    }
+
+    /// Errors in the `BUCKETS` slices up to and including `slice`.
+    fn recent(&self, slice: u64) -> u64 {
+        self.buckets
+            .iter()
+            .filter(|&&(number, _)| number <= slice && slice - number < BUCKETS as u64)
+            .map(|&(_, count)| count)
+            .sum()
+    }
+}
+
+/// Per-source error counts and rates.
+pub struct ErrorStatsTable {
+    sources: [SourceCounters; ErrorSource::COUNT],
+    limit: ErrorRateLimit,
+}
+
+impl ErrorStatsTable {
+    pub const fn new(limit: ErrorRateLimit) -> Self {
+        Self { sources: [SourceCounters::EMPTY; ErrorSource::COUNT], limit }
+    }
+
+    pub fn limit(&self) -> ErrorRateLimit {
+        self.limit
+    }
+
+    /// Replaces the rate limit. Rates measured so far are discarded, since
+    /// their slices no longer fit the window; the counts are kept.
+    pub fn set_limit(&mut self, limit: ErrorRateLimit) {
+        self.limit = limit;
+        for counters in &mut self.sources {
+            counters.buckets = [(0, 0); BUCKETS];
+            counters.over_limit = false;
+        }
+    }
+
+    fn slice(&self, now: u64) -> u64 {
+        now / (self.limit.window_ticks / BUCKETS as u64).max(1)
+    }
+
+    /// Counts an error of `source` at `level` reported at `now`. Returns the
+    /// source's errors within the window if this error took them above the
+    /// limit.
+    pub fn record(&mut self, source: ErrorSource, level: ErrorLevel, now: u64) -> Option<u64> {
+        let slice = self.slice(now);
+        let max_errors = self.limit.max_errors;
+        let counters = &mut self.sources[source as usize];
+        if counters.total() == 0 {
+            counters.first_ticks = now;
+        }
+        counters.last_ticks = now;
+        counters.by_level[level as usize] += 1;
+
+        let bucket = &mut counters.buckets[(slice % BUCKETS as u64) as usize];
+        if bucket.0 != slice {
+            *bucket = (slice, 0);
+        }
+        bucket.1 += 1;
+
+        let recent = counters.recent(slice);
+        match max_errors {
+            Some(max) if recent > max => {
+                let crossed = !counters.over_limit;
+                counters.over_limit = true;
+                crossed.then_some(recent)
+            }
+            _ => {
+                counters.over_limit = false;
+                None
+            }
+        }
+    }
+
+    /// Returns the statistics of the sources that reported errors, as of
+    /// `now`.
+    pub fn stats(&self, now: u64) -> Vec<ErrorStats> {
+        let slice = self.slice(now);
+        ErrorSource::ALL
+            .iter()
+            .zip(&self.sources)
+            .filter(|(_, counters)| counters.total() > 0)
+            .map(|(&source, counters)| ErrorStats {
+                source,
+                by_level: counters.by_level,
+                first_ticks: counters.first_ticks,
+                last_ticks: counters.last_ticks,
+                recent: counters.recent(slice),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: ErrorRateLimit = ErrorRateLimit { window_ticks: 1600, max_errors: Some(3) };
+
+    #[test]
+    fn counts_per_source_and_level() {
+        let mut table = ErrorStatsTable::new(ErrorRateLimit { max_errors: None, ..LIMIT });
+        table.record(ErrorSource::Memory, ErrorLevel::Error, 10);
+        table.record(ErrorSource::Memory, ErrorLevel::Warning, 20);
+        table.record(ErrorSource::Device, ErrorLevel::Fatal, 30);
+        table.record(ErrorSource::Memory, ErrorLevel::Error, 40);
+        let stats = table.stats(40);
+        assert_eq!(stats.iter().map(|s| s.source).collect::<Vec<_>>(), [ErrorSource::Memory, ErrorSource::Device]);
+        let memory = stats[0];
+        assert_eq!(memory.count(ErrorLevel::Error), 2);
+        assert_eq!(memory.count(ErrorLevel::Warning), 1);
+        assert_eq!(memory.total(), 3);
+        assert_eq!((memory.first_ticks, memory.last_ticks, memory.recent), (10, 40, 3));
+        assert_eq!(stats[1].count(ErrorLevel::Fatal), 1);
+    }
+
+    #[test]
+    fn rate_expires_with_the_window() {
+        let mut table = ErrorStatsTable::new(LIMIT);
+        // Slices are 100 ticks; the window holds 16 of them.
+        table.record(ErrorSource::Trap, ErrorLevel::Error, 50);
+        table.record(ErrorSource::Trap, ErrorLevel::Error, 1550);
+        assert_eq!(table.stats(1599)[0].recent, 2);
+        assert_eq!(table.stats(1600)[0].recent, 1);
+        assert_eq!(table.stats(3200)[0].recent, 0);
+        // A bucket reused on a later lap starts from zero.
+        table.record(ErrorSource::Trap, ErrorLevel::Error, 1650);
+        assert_eq!(table.stats(1650)[0].recent, 2);
+        assert_eq!(table.stats(1650)[0].total(), 3);
+    }
+
+    #[test]
+    fn limit_crossing_is_reported_once() {
+        let mut table = ErrorStatsTable::new(LIMIT);
+        let crossings: Vec<_> = (0..6).map(|i| table.record(ErrorSource::Network, ErrorLevel::Info, i)).collect();
+        assert_eq!(crossings, [None, None, None, Some(4), None, None]);
+        // Once the window has passed, the rate is back under the limit.
+        assert_eq!(table.record(ErrorSource::Network, ErrorLevel::Info, 5000), None);
+        assert_eq!((5001..5004).filter_map(|t| table.record(ErrorSource::Network, ErrorLevel::Info, t)).collect::<Vec<_>>(), [4]);
+        // Other sources have rates of their own.
+        assert_eq!(table.record(ErrorSource::Syscall, ErrorLevel::Info, 5004), None);
+        table.set_limit(ErrorRateLimit { max_errors: Some(0), ..LIMIT });
+        assert_eq!(table.limit().max_errors, Some(0));
+        assert_eq!(table.record(ErrorSource::Network, ErrorLevel::Info, 5005), Some(1));
+    }
+}
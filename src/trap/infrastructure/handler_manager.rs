@@ -8,6 +8,7 @@
 use crate::trap::ds::{
     self, HandlerEntry, HandlerHandle, RegistrarId, TrapType, TrapHandlerResult
 };
+use crate::trap::collections::HashMap;
 use crate::trap::infrastructure::di::traits::HandlerManager;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
@@ -19,8 +20,8 @@ type PriorityMap = BTreeMap<u8, Vec<HandlerStore>>;
 type TrapMap = BTreeMap<TrapType, PriorityMap>;
 
 /// A map from a handler's unique ID to its full `HandlerStore` (`Arc<RwLock<...>>`).
-/// This allows for O(log N) lookup of any handler by its handle.
-type HandleMap = BTreeMap<u64, HandlerStore>;
+/// This allows for O(1) lookup of any handler by its handle.
+type HandleMap = HashMap<u64, HandlerStore>;
 
 pub struct HeapHandlerManager {
     /// The primary storage for handlers, organized by trap type and priority.
@@ -33,7 +34,7 @@ impl HeapHandlerManager {
     pub fn new() -> Self {
         Self {
             handlers: Mutex::new(BTreeMap::new()),
-            handle_map: Mutex::new(BTreeMap::new()),
+            handle_map: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -46,7 +47,7 @@ impl HandlerManager for HeapHandlerManager {
     ) -> Result<HandlerHandle, ()> {
         let handle = {
             let read_entry = entry.read();
-            HandlerHandle::generate_id(read_entry.description, trap_type)
+            HandlerHandle::new(HandlerHandle::generate_id(read_entry.description, trap_type))
         };
         
         let mut handle_map = self.handle_map.lock();
@@ -178,17 +179,183 @@ impl HandlerManager for HeapHandlerManager {
             }
         }
         
-        // Now, remove them.
+        // Now, remove them. Entries are matched by identity so that handlers of
+        // other contexts sharing the same description are left alone.
         for handle_id in handles_to_remove {
              if let Some(handler_arc) = handle_map.remove(&handle_id) {
-                  let entry = handler_arc.read();
-                   // This is inefficient like above.
+                  let priority = handler_arc.read().priority;
                   for (_tt, p_map) in handlers.iter_mut() {
-                      if let Some(p_vec) = p_map.get_mut(&entry.priority) {
-                           p_vec.retain(|h| h.read().description != entry.description);
+                      if let Some(p_vec) = p_map.get_mut(&priority) {
+                           p_vec.retain(|h| !Arc::ptr_eq(h, &handler_arc));
+                           if p_vec.is_empty() {
+                               p_map.remove(&priority);
+                           }
                       }
                   }
              }
         }
     }
-}
\ No newline at end of file
+
+    fn count_for_context(&self, context_id: u64) -> usize {
+        self.handle_map
+            .lock()
+            .values()
+            .filter(|h| h.read().context_id == Some(context_id))
+            .count()
+    }
+
+    fn list(&self) -> Vec<(TrapType, HandlerEntry)> {
+        let handlers = self.handlers.lock();
+        handlers
+            .iter()
+            .flat_map(|(&trap_type, priority_map)| {
+                priority_map.values().flatten().map(move |h| (trap_type, h.read().clone()))
+            })
+            .collect()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trap::ds::{ProtectionLevel, TrapContext, KERNEL_REGISTRAR_ID, SYSTEM_REGISTRAR_ID};
+
+    /// `scause` of a breakpoint exception.
+    const BREAKPOINT_CAUSE: usize = 3;
+
+    /// Handlers append their digit to `a0`, so the order they ran in can be
+    /// read back from the context.
+    fn first(context: &mut TrapContext) -> TrapHandlerResult {
+        context.x[10] = context.x[10] * 10 + 1;
+        TrapHandlerResult::Pass
+    }
+
+    fn second(context: &mut TrapContext) -> TrapHandlerResult {
+        context.x[10] = context.x[10] * 10 + 2;
+        TrapHandlerResult::Failed(ds::TrapError::ExecutionFailed)
+    }
+
+    fn last(context: &mut TrapContext) -> TrapHandlerResult {
+        context.x[10] = context.x[10] * 10 + 3;
+        TrapHandlerResult::Handled
+    }
+
+    fn entry(
+        handler: ds::TrapHandler,
+        priority: u8,
+        description: &'static str,
+        level: ProtectionLevel,
+        owner: RegistrarId,
+    ) -> HandlerStore {
+        Arc::new(RwLock::new(HandlerEntry {
+            handler,
+            priority,
+            description,
+            protection_level: level,
+            registrar_id: owner,
+            context_id: None,
+        }))
+    }
+
+    fn breakpoint() -> TrapContext {
+        let mut context = TrapContext::new();
+        context.scause = BREAKPOINT_CAUSE;
+        context
+    }
+
+    #[test]
+    fn dispatches_by_priority() {
+        let manager = HeapHandlerManager::new();
+        let user = ProtectionLevel::User;
+        manager.register(TrapType::Breakpoint, entry(last, 30, "last", user, 7)).unwrap();
+        manager.register(TrapType::Breakpoint, entry(first, 10, "first", user, 7)).unwrap();
+        manager.register(TrapType::Breakpoint, entry(second, 20, "second", user, 7)).unwrap();
+
+        let mut context = breakpoint();
+        assert_eq!(manager.dispatch(&mut context), TrapHandlerResult::Handled);
+        assert_eq!(context.x[10], 123);
+
+        let mut other = TrapContext::new();
+        other.scause = 2;
+        assert_eq!(manager.dispatch(&mut other), TrapHandlerResult::Pass);
+        assert_eq!(other.x[10], 0);
+    }
+
+    #[test]
+    fn rejects_duplicate_description() {
+        let manager = HeapHandlerManager::new();
+        let user = ProtectionLevel::User;
+        manager.register(TrapType::Breakpoint, entry(first, 10, "dup", user, 7)).unwrap();
+        assert!(manager.register(TrapType::Breakpoint, entry(last, 20, "dup", user, 7)).is_err());
+        // The handle covers the trap type, so the description may be reused for another one.
+        assert!(manager.register(TrapType::IllegalInstruction, entry(last, 20, "dup", user, 7)).is_ok());
+    }
+
+    #[test]
+    fn unregister_checks_protection() {
+        let manager = HeapHandlerManager::new();
+        let bp = TrapType::Breakpoint;
+        let kernel = manager.register(bp, entry(first, 10, "kernel", ProtectionLevel::Kernel, 0)).unwrap();
+        let system = manager.register(bp, entry(first, 11, "system", ProtectionLevel::System, 1)).unwrap();
+        let user = manager.register(bp, entry(last, 12, "user", ProtectionLevel::User, 7)).unwrap();
+
+        assert!(manager.unregister(kernel, SYSTEM_REGISTRAR_ID).is_err());
+        assert!(manager.unregister(system, 7).is_err());
+        assert!(manager.unregister(user, 8).is_err());
+
+        assert!(manager.unregister(user, 7).is_ok());
+        assert!(manager.unregister(system, SYSTEM_REGISTRAR_ID).is_ok());
+        assert!(manager.unregister(kernel, KERNEL_REGISTRAR_ID).is_ok());
+        assert!(manager.unregister(kernel, KERNEL_REGISTRAR_ID).is_err());
+
+        let mut context = breakpoint();
+        assert_eq!(manager.dispatch(&mut context), TrapHandlerResult::Pass);
+        assert_eq!(context.x[10], 0);
+    }
+
+    #[test]
+    fn ownership_transfer() {
+        let manager = HeapHandlerManager::new();
+        let owned = entry(last, 10, "owned", ProtectionLevel::User, 7);
+        let handle = manager.register(TrapType::Breakpoint, owned).unwrap();
+
+        assert!(manager.transfer_ownership(handle, 8, 9).is_err());
+        assert!(manager.transfer_ownership(handle, 7, 8).is_ok());
+        assert!(manager.unregister(handle, 7).is_err());
+        assert!(manager.transfer_ownership(handle, KERNEL_REGISTRAR_ID, 9).is_ok());
+        assert!(manager.unregister(handle, 8).is_err());
+        assert!(manager.unregister(handle, 9).is_ok());
+    }
+
+    #[test]
+    fn context_handlers() {
+        let manager = HeapHandlerManager::new();
+        for (description, context_id) in [("a", Some(1)), ("b", Some(1)), ("c", Some(2)), ("d", None)] {
+            let store = entry(first, 10, description, ProtectionLevel::User, 7);
+            store.write().context_id = context_id;
+            manager.register(TrapType::Breakpoint, store).unwrap();
+        }
+        assert_eq!(manager.count_for_context(1), 2);
+        manager.unregister_for_context(1);
+        assert_eq!(manager.count_for_context(1), 0);
+        assert_eq!(manager.count_for_context(2), 1);
+
+        let mut context = breakpoint();
+        manager.dispatch(&mut context);
+        assert_eq!(context.x[10], 11);
+    }
+
+    #[test]
+    fn lists_in_dispatch_order() {
+        let manager = HeapHandlerManager::new();
+        let user = ProtectionLevel::User;
+        manager.register(TrapType::Breakpoint, entry(last, 30, "late", user, 7)).unwrap();
+        manager.register(TrapType::TimerInterrupt, entry(first, 50, "timer", user, 7)).unwrap();
+        manager.register(TrapType::Breakpoint, entry(first, 10, "early", user, 7)).unwrap();
+        let listed: Vec<_> = manager.list().iter().map(|(t, e)| (*t, e.priority, e.description)).collect();
+        assert_eq!(listed, [
+            (TrapType::TimerInterrupt, 50, "timer"),
+            (TrapType::Breakpoint, 10, "early"),
+            (TrapType::Breakpoint, 30, "late"),
+        ]);
+    }
+}
@@ -17,11 +17,25 @@ extern "C" {
     fn __trap_entry();
     /// The assembly exit point for all traps. It restores the full context.
     fn __trap_return();
+    /// Lowest stack pointer a kernel trap may be taken with; 0 disables the check.
+    static mut __kstack_limit: usize;
+}
+
+/// Sets the lowest stack pointer a trap from S-mode may be taken with.
+///
+/// `__trap_entry` handles kernel traps taken below the limit, whose context
+/// would not fit on the stack, on a separate overflow stack instead, and
+/// clears the limit. Pass 0 to disable the check.
+pub fn set_kernel_stack_limit(limit: usize) {
+    unsafe {
+        core::ptr::addr_of_mut!(__kstack_limit).write_volatile(limit);
+    }
 }
 
 /// Initializes the trap subsystem at the hardware level.
 ///
-/// Sets the Supervisor Trap Vector (`stvec`) register to point to our trap entry point.
+/// Sets the Supervisor Trap Vector (`stvec`) register to point to our trap entry point
+/// and clears `sscratch`, which `__trap_entry` uses to tell kernel traps from user traps.
 ///
 /// # Arguments
 ///
@@ -29,6 +43,7 @@ extern "C" {
 pub fn init_trap_vector(mode: TrapMode) {
     let stvec_value = __trap_entry as usize | mode as usize;
     unsafe {
+        asm!("csrw sscratch, zero");
         asm!("csrw stvec, {}", in(reg) stvec_value);
     }
 }
@@ -59,7 +74,7 @@ pub extern "C" fn handle_trap(context: *mut TrapContext) {
 pub fn enable_interrupts() -> bool {
     let mut sstatus: usize;
     unsafe {
-        asm!("csrrci {}, sstatus, 1 << 1", out(reg) sstatus);
+        asm!("csrrsi {}, sstatus, 1 << 1", out(reg) sstatus);
     }
     // Check if the SIE bit (bit 1) was set previously.
     (sstatus & (1 << 1)) != 0
@@ -74,7 +89,7 @@ pub fn enable_interrupts() -> bool {
 pub fn disable_interrupts() -> bool {
     let mut sstatus: usize;
     unsafe {
-        asm!("csrrc {}, sstatus, 1 << 1", out(reg) sstatus);
+        asm!("csrrci {}, sstatus, 1 << 1", out(reg) sstatus);
     }
     // Check if the SIE bit (bit 1) was set previously.
     (sstatus & (1 << 1)) != 0
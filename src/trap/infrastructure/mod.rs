@@ -7,17 +7,21 @@
 //! framework, and concrete implementations of the various managers for handlers,
 //! errors, and contexts.
 
-// The assembly code for the trap entry point.
-pub mod asm;
-
 // The Dependency Injection (DI) framework.
 pub mod di;
 
-// Low-level hardware interaction layer.
+// Low-level hardware interaction layer. The assembly trap entry point it
+// includes lives in `asm/`. Host tests use a mock that keeps the CSR state
+// in memory.
+#[cfg(not(feature = "host-test"))]
+pub mod low_level;
+#[cfg(feature = "host-test")]
+#[path = "../../host/low_level.rs"]
 pub mod low_level;
 
 // Concrete manager implementations.
 pub mod error_manager;
+pub mod error_stats;
 pub mod handler_manager;
 pub mod context_manager;
 
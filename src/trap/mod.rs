@@ -7,7 +7,7 @@
 //! for the `nt_rustos` kernel.
 
 // Make submodules accessible within the trap crate.
-mod collections;
+pub mod collections;
 mod ds;
 mod infrastructure;
 mod api;
@@ -22,10 +22,11 @@ pub use self::ds::{
     TrapHandler, TrapHandlerResult, TrapError,           // Handler signatures and results
     HandlerHandle, ProtectionLevel, RegistrarId,         // Handler identification and security
     SystemError, ErrorCode, ErrorSource, ErrorLevel,     // Error structures
-    ErrorResult,
+    ErrorResult, ErrorLogEntry, ErrorRateLimit, ErrorStats,
     KERNEL_REGISTRAR_ID, SYSTEM_REGISTRAR_ID,           // Standard Registrar IDs
 };
 
+use crate::init::initcall::InitResult;
 
 /// Initializes the entire trap subsystem.
 ///
@@ -46,4 +47,16 @@ pub fn init(mode: TrapMode) {
     infrastructure::di::initialize_trap_system(mode);
 
     // nt_rustos::println!("Trap subsystem fully initialized."); // Requires a println macro
-}
\ No newline at end of file
+}
+
+/// Sets up the trap subsystem in direct mode; vectored mode needs more
+/// hardware support and setup.
+fn init_direct() -> InitResult {
+    #[cfg(not(feature = "host-test"))]
+    let _timing = crate::boot::timing::measure(crate::boot::timing::Phase::Traps);
+    init(TrapMode::Direct);
+    crate::info_print!("Trap Subsystem initialized.");
+    Ok(())
+}
+
+crate::initcall!(arch, 20, init_direct);
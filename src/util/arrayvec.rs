@@ -0,0 +1,501 @@
+// nt_rustos/src/util/arrayvec.rs
+
+//! # Fixed-Capacity Vectors and Strings
+//!
+//! `ArrayVec` and `ArrayString` keep their contents inline, in an array of
+//! a capacity fixed at compile time, so they work before the heap is set
+//! up, inside trap handlers and in statics. They replace the pattern of an
+//! array plus a count of the elements in use.
+//!
+//! Adding beyond the capacity never panics or truncates silently: it fails
+//! with a `CapacityError`, which hands back what did not fit.
+
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+use core::str;
+
+/// The capacity would be exceeded; holds the rejected value.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError<T = ()> {
+    element: T,
+}
+
+impl<T> CapacityError<T> {
+    pub const fn new(element: T) -> Self {
+        Self { element }
+    }
+
+    /// Returns the value that did not fit.
+    pub fn element(self) -> T {
+        self.element
+    }
+
+    /// Drops the rejected value, keeping the error.
+    pub fn simplify(self) -> CapacityError {
+        CapacityError { element: () }
+    }
+}
+
+impl<T> fmt::Debug for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CapacityError: {}", self)
+    }
+}
+
+impl<T> fmt::Display for CapacityError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "insufficient capacity")
+    }
+}
+
+/// A vector of at most `N` elements, stored inline.
+pub struct ArrayVec<T, const N: usize> {
+    items: [MaybeUninit<T>; N],
+    len: usize,
+}
+
+impl<T, const N: usize> ArrayVec<T, N> {
+    /// Maximum number of elements.
+    pub const CAPACITY: usize = N;
+
+    pub const fn new() -> Self {
+        Self { items: [const { MaybeUninit::uninit() }; N], len: 0 }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns how many more elements fit.
+    pub const fn remaining_capacity(&self) -> usize {
+        N - self.len
+    }
+
+    /// Appends `item`, or hands it back if the vector is full.
+    pub fn push(&mut self, item: T) -> Result<(), CapacityError<T>> {
+        if self.len == N {
+            return Err(CapacityError::new(item));
+        }
+        self.items[self.len].write(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Inserts `item` at `index`, shifting later elements up, or hands it
+    /// back if the vector is full.
+    ///
+    /// # Panics
+    /// Panics if `index` is greater than the length.
+    pub fn insert(&mut self, index: usize, item: T) -> Result<(), CapacityError<T>> {
+        assert!(index <= self.len, "insertion index {} out of range for length {}", index, self.len);
+        if self.len == N {
+            return Err(CapacityError::new(item));
+        }
+        unsafe {
+            let at = self.as_mut_ptr().add(index);
+            ptr::copy(at, at.add(1), self.len - index);
+            at.write(item);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes and returns the last element.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.items[self.len].assume_init_read() })
+    }
+
+    /// Removes the element at `index`, shifting later elements down.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index {} out of range for length {}", index, self.len);
+        unsafe {
+            let at = self.as_mut_ptr().add(index);
+            let item = at.read();
+            ptr::copy(at.add(1), at, self.len - index - 1);
+            self.len -= 1;
+            item
+        }
+    }
+
+    /// Removes the element at `index`, replacing it with the last one.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of range.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index {} out of range for length {}", index, self.len);
+        let last = self.len - 1;
+        self.swap(index, last);
+        self.pop().unwrap()
+    }
+
+    /// Drops the elements from `len` on.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Keeps only the elements for which `keep` returns `true`, in order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        let mut index = 0;
+        while index < self.len {
+            if keep(&self[index]) {
+                index += 1;
+            } else {
+                drop(self.remove(index));
+            }
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.items.as_ptr().cast(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut T {
+        self.items.as_mut_ptr().cast()
+    }
+}
+
+impl<T: Clone, const N: usize> ArrayVec<T, N> {
+    /// Appends clones of `items`, or nothing if they do not all fit.
+    pub fn try_extend_from_slice(&mut self, items: &[T]) -> Result<(), CapacityError> {
+        if items.len() > self.remaining_capacity() {
+            return Err(CapacityError::new(()));
+        }
+        for item in items {
+            let _ = self.push(item.clone());
+        }
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayVec<T, N> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.as_mut_slice()) };
+    }
+}
+
+impl<T, const N: usize> Deref for ArrayVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T, const N: usize> DerefMut for ArrayVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T, const N: usize> Default for ArrayVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for ArrayVec<T, N> {
+    fn clone(&self) -> Self {
+        let mut copy = Self::new();
+        for item in self.iter() {
+            let _ = copy.push(item.clone());
+        }
+        copy
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for ArrayVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for ArrayVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for ArrayVec<T, N> {}
+
+impl<T: PartialEq, const N: usize> PartialEq<[T]> for ArrayVec<T, N> {
+    fn eq(&self, other: &[T]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a ArrayVec<T, N> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut ArrayVec<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, const N: usize> TryFrom<&[T]> for ArrayVec<T, N>
+where
+    T: Clone,
+{
+    type Error = CapacityError;
+
+    fn try_from(items: &[T]) -> Result<Self, CapacityError> {
+        let mut vec = Self::new();
+        vec.try_extend_from_slice(items)?;
+        Ok(vec)
+    }
+}
+
+/// A string of at most `N` bytes of UTF-8, stored inline.
+///
+/// Writing with `write!` fails with `fmt::Error` once a piece does not fit;
+/// the pieces written before it are kept.
+#[derive(Clone, Copy)]
+pub struct ArrayString<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> ArrayString<N> {
+    /// Maximum length in bytes.
+    pub const CAPACITY: usize = N;
+
+    pub const fn new() -> Self {
+        Self { bytes: [0; N], len: 0 }
+    }
+
+    /// Returns the length in bytes.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns how many more bytes fit.
+    pub const fn remaining_capacity(&self) -> usize {
+        N - self.len
+    }
+
+    /// Appends `s` whole, or hands it back if it does not fit.
+    pub fn push_str<'a>(&mut self, s: &'a str) -> Result<(), CapacityError<&'a str>> {
+        if s.len() > self.remaining_capacity() {
+            return Err(CapacityError::new(s));
+        }
+        self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+
+    /// Appends `c`, or hands it back if it does not fit.
+    pub fn push(&mut self, c: char) -> Result<(), CapacityError<char>> {
+        let mut utf8 = [0; 4];
+        self.push_str(c.encode_utf8(&mut utf8)).map_err(|_| CapacityError::new(c))
+    }
+
+    /// Removes and returns the last character.
+    pub fn pop(&mut self) -> Option<char> {
+        let c = self.chars().next_back()?;
+        self.len -= c.len_utf8();
+        Some(c)
+    }
+
+    /// Shortens the string to `len` bytes.
+    ///
+    /// # Panics
+    /// Panics if `len` is not on a character boundary.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            assert!(self.is_char_boundary(len), "truncation at {} is not a character boundary", len);
+            self.len = len;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole strings and characters are ever copied in.
+        unsafe { str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl<const N: usize> Deref for ArrayString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<'a, const N: usize> TryFrom<&'a str> for ArrayString<N> {
+    type Error = CapacityError<&'a str>;
+
+    /// Creates a string holding `s`, if it fits.
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        let mut string = Self::new();
+        string.push_str(s)?;
+        Ok(string)
+    }
+}
+
+impl<const N: usize> Default for ArrayString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for ArrayString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize> fmt::Display for ArrayString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for ArrayString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> PartialEq for ArrayString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> Eq for ArrayString<N> {}
+
+impl<const N: usize> PartialEq<str> for ArrayString<N> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&str> for ArrayString<N> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::fmt::Write;
+
+    #[test]
+    fn vec_push_until_full() {
+        let mut vec: ArrayVec<u32, 3> = ArrayVec::new();
+        assert_eq!(vec.push(1), Ok(()));
+        assert_eq!(vec.try_extend_from_slice(&[2, 3, 4]), Err(CapacityError::new(())));
+        assert_eq!(vec.try_extend_from_slice(&[2, 3]), Ok(()));
+        assert!(vec.is_full());
+        assert_eq!(vec.push(4).map_err(CapacityError::element), Err(4));
+        assert_eq!(vec, *[1, 2, 3].as_slice());
+        assert_eq!(vec.pop(), Some(3));
+        assert_eq!(vec.remaining_capacity(), 1);
+    }
+
+    #[test]
+    fn vec_insert_remove_retain() {
+        let mut vec: ArrayVec<char, 5> = ArrayVec::try_from(&['a', 'c', 'd'][..]).unwrap();
+        vec.insert(1, 'b').unwrap();
+        vec.insert(4, 'e').unwrap();
+        assert_eq!(vec.insert(0, 'z'), Err(CapacityError::new('z')));
+        assert_eq!(format!("{:?}", vec), "['a', 'b', 'c', 'd', 'e']");
+        assert_eq!(vec.remove(0), 'a');
+        assert_eq!(vec.swap_remove(0), 'b');
+        assert_eq!(vec.as_slice(), ['e', 'c', 'd']);
+        vec.retain(|&c| c != 'c');
+        assert_eq!(vec.as_slice(), ['e', 'd']);
+    }
+
+    #[test]
+    fn vec_drops_its_elements() {
+        let item = Rc::new(());
+        let mut vec: ArrayVec<Rc<()>, 4> = ArrayVec::new();
+        for _ in 0..4 {
+            vec.push(item.clone()).unwrap();
+        }
+        let copy = vec.clone();
+        vec.truncate(1);
+        assert_eq!(Rc::strong_count(&item), 6);
+        drop(vec);
+        drop(copy);
+        assert_eq!(Rc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn string_push_and_write() {
+        let mut s: ArrayString<8> = ArrayString::try_from("héllo").unwrap();
+        assert_eq!(s.len(), 6);
+        assert_eq!(s.push_str("abc"), Err(CapacityError::new("abc")));
+        assert_eq!(s.push('!'), Ok(()));
+        assert_eq!(s.push('é'), Err(CapacityError::new('é')));
+        assert_eq!(s.pop(), Some('!'));
+        assert_eq!(write!(s, "{}", 42), Ok(()));
+        assert_eq!(s, "héllo42");
+        assert_eq!(write!(s, "{}", 100), Err(fmt::Error));
+        assert_eq!(s, "héllo42");
+        s.truncate(1);
+        assert_eq!(format!("{:?}", s), "\"h\"");
+    }
+
+    #[test]
+    #[should_panic]
+    fn string_truncate_inside_char_panics() {
+        ArrayString::<4>::try_from("é").unwrap().truncate(1);
+    }
+}
@@ -0,0 +1,161 @@
+// nt_rustos/src/util/backtrace.rs
+
+//! # Frame-Pointer Backtraces
+//!
+//! The kernel is built with `-Cforce-frame-pointers=yes`, so every
+//! function keeps `s0` (`fp`) pointing just above its frame, with its
+//! return address at `fp - 8` and the caller's `fp` at `fp - 16`.
+//! `capture` follows that chain from its caller; `capture_from` starts at
+//! a trapped context instead.
+//!
+//! The walk never allocates and stops at the first frame that fails a
+//! sanity check, so it is safe on a corrupted stack or inside the panic
+//! handler: frame pointers must be 16-byte aligned, strictly increasing
+//! and within `MAX_STACK_SPAN` of where the walk started (for a trapped
+//! context, of the handler's own stack pointer), and return
+//! addresses must lie in kernel text. `_start` clears `fp` before
+//! entering Rust and new tasks start with it zeroed, which ends the chain.
+//!
+//! Frames print through `util::ksyms`, as names when the image carries a
+//! symbol table.
+
+use crate::error_print;
+use crate::util::arrayvec::ArrayVec;
+use crate::util::ksyms::WithSymbol;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Frames recorded at most.
+pub const MAX_FRAMES: usize = 32;
+
+/// How far above its start a walk may go; no kernel stack is larger.
+const MAX_STACK_SPAN: usize = 64 * 1024;
+
+/// Set once a failed `kassert!` has printed its backtrace, so the panic
+/// handler does not print the same frames again.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Return addresses of a call chain, innermost first.
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    frames: ArrayVec<usize, MAX_FRAMES>,
+}
+
+impl Backtrace {
+    const fn empty() -> Self {
+        Self { frames: ArrayVec::new() }
+    }
+
+    fn push(&mut self, pc: usize) -> bool {
+        self.frames.push(pc).is_ok()
+    }
+
+    /// The recorded addresses, innermost first.
+    pub fn frames(&self) -> &[usize] {
+        &self.frames
+    }
+
+    /// Prints one line per frame.
+    pub fn print(&self) {
+        error_print!("Backtrace:");
+        for (index, &pc) in self.frames().iter().enumerate() {
+            error_print!("  #{:<2} {}", index, WithSymbol(pc));
+        }
+        if self.frames.is_empty() {
+            error_print!("  (no frames)");
+        }
+    }
+}
+
+/// Returns the bounds of kernel text.
+pub fn text_range() -> (usize, usize) {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    (stext as usize, etext as usize)
+}
+
+/// Follows the frame-pointer chain from `fp`, appending return addresses.
+fn walk(trace: &mut Backtrace, mut fp: usize) {
+    let (text_start, text_end) = text_range();
+    let limit = fp.saturating_add(MAX_STACK_SPAN);
+    while fp != 0 && fp % 16 == 0 && fp <= limit {
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if !(text_start..text_end).contains(&ra) || !trace.push(ra) {
+            break;
+        }
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+}
+
+/// Captures the call chain of the caller, starting at its return address.
+#[inline(never)]
+pub fn capture() -> Backtrace {
+    let fp: usize;
+    unsafe { asm!("mv {}, s0", out(reg) fp) };
+    let mut trace = Backtrace::empty();
+    walk(&mut trace, fp);
+    trace
+}
+
+/// Captures the chain of trapped kernel code: the trapping `pc`, then the
+/// frames above the trapped `fp` (`s0` of the context).
+///
+/// Kernel code traps onto its own stack, so a trapped `fp` that is not
+/// just above the handler's stack pointer is not followed.
+pub fn capture_from(pc: usize, fp: usize) -> Backtrace {
+    let sp: usize;
+    unsafe { asm!("mv {}, sp", out(reg) sp) };
+    let mut trace = Backtrace::empty();
+    trace.push(pc);
+    if (sp..sp.saturating_add(MAX_STACK_SPAN)).contains(&fp) {
+        walk(&mut trace, fp);
+    }
+    trace
+}
+
+/// Captures and prints the call chain of the caller.
+#[inline(never)]
+pub fn print() {
+    capture().print();
+}
+
+/// Whether a failed `kassert!` already printed a backtrace; the flag is
+/// cleared by reading it.
+pub fn take_reported() -> bool {
+    REPORTED.swap(false, Ordering::AcqRel)
+}
+
+/// Reports a failed `kassert!` with a backtrace from the assertion and
+/// panics.
+#[inline(never)]
+#[cold]
+pub fn assertion_failed(file: &'static str, line: u32, message: fmt::Arguments) -> ! {
+    error_print!("Assertion failed at {}:{}: {}", file, line, message);
+    capture().print();
+    REPORTED.store(true, Ordering::Release);
+    panic!("assertion failed: {}", message);
+}
+
+/// Like `assert!`, but prints a backtrace from the failing assertion
+/// before panicking.
+///
+/// ```ignore
+/// crate::kassert!(tcb.kernel_stack.is_none(), "task {}: kernel stack leaked", tcb.pid);
+/// ```
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        $crate::kassert!($cond, "{}", stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::util::backtrace::assertion_failed(file!(), line!(), format_args!($($arg)+));
+        }
+    };
+}
@@ -0,0 +1,262 @@
+// nt_rustos/src/util/ksyms.rs
+
+//! # Kernel Symbol Table
+//!
+//! Maps kernel text addresses back to function names for diagnostics.
+//!
+//! The kernel image reserves `KSYMS_CAPACITY` bytes in the `.ksyms` linker
+//! section. A build cannot know the final addresses before linking, so
+//! `tools/ksyms.py` fills the section afterwards: it reads the function
+//! symbols of the linked image with `nm`, sorts them by address and
+//! writes them into the section in place, without moving anything else.
+//! An image that skipped this step has a zeroed section, and `resolve`
+//! finds nothing there.
+//!
+//! A table may also be installed at run time with `install`; it takes
+//! precedence over the embedded one. Callers that find no symbol fall back
+//! to raw addresses.
+//!
+//! ## Embedded layout
+//!
+//! All fields are little-endian.
+//!
+//! ```text
+//! header   magic "KSYM", symbol count (u32), strings offset (u32), strings length (u32)
+//! entries  count x { address (u64), name offset (u32), name length (u32) }, sorted by address
+//! strings  the names, UTF-8, at the strings offset from the start of the section
+//! ```
+
+use core::fmt;
+use spin::Once;
+
+/// Bytes reserved for the embedded table.
+pub const KSYMS_CAPACITY: usize = 256 * 1024;
+
+/// Magic at the start of a filled `.ksyms` section.
+pub const KSYMS_MAGIC: [u8; 4] = *b"KSYM";
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+/// The space `tools/ksyms.py` fills; read through `sksyms`/`eksyms` so the
+/// compiler cannot fold the zeroes it is initialized with.
+#[cfg(not(feature = "host-test"))]
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS_SPACE: [u8; KSYMS_CAPACITY] = [0; KSYMS_CAPACITY];
+
+/// A function symbol: its start address and name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KernelSymbol {
+    pub addr: usize,
+    pub name: &'static str,
+}
+
+static SYMBOLS: Once<&'static [KernelSymbol]> = Once::new();
+static EMBEDDED: Once<Option<EmbeddedTable<'static>>> = Once::new();
+
+/// Installs the symbol table. `symbols` must be sorted by address.
+///
+/// Returns `false` if the table is unsorted or one is already installed.
+pub fn install(symbols: &'static [KernelSymbol]) -> bool {
+    if !symbols.windows(2).all(|w| w[0].addr <= w[1].addr) {
+        return false;
+    }
+    let mut installed = false;
+    SYMBOLS.call_once(|| {
+        installed = true;
+        symbols
+    });
+    installed
+}
+
+/// Returns the contents of the `.ksyms` section.
+#[cfg(not(feature = "host-test"))]
+fn ksyms_section() -> &'static [u8] {
+    extern "C" {
+        static sksyms: u8;
+        static eksyms: u8;
+    }
+    unsafe {
+        let start = core::ptr::addr_of!(sksyms);
+        let end = core::ptr::addr_of!(eksyms);
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Host tests link no kernel image; there is nothing embedded.
+#[cfg(feature = "host-test")]
+fn ksyms_section() -> &'static [u8] {
+    &[]
+}
+
+/// Returns the table embedded in the image, if `tools/ksyms.py` filled it.
+pub fn embedded() -> Option<&'static EmbeddedTable<'static>> {
+    EMBEDDED.call_once(|| EmbeddedTable::parse(ksyms_section())).as_ref()
+}
+
+/// Returns the symbol containing `addr` and the offset of `addr` into it,
+/// from the installed table or else the embedded one.
+pub fn resolve(addr: usize) -> Option<(&'static str, usize)> {
+    match SYMBOLS.get() {
+        Some(symbols) => lookup(symbols, addr).map(|(symbol, offset)| (symbol.name, offset)),
+        None => embedded()?.lookup(addr),
+    }
+}
+
+/// Looks `addr` up in `symbols`, which must be sorted by address.
+pub fn lookup(symbols: &[KernelSymbol], addr: usize) -> Option<(&KernelSymbol, usize)> {
+    let index = symbols.partition_point(|s| s.addr <= addr).checked_sub(1)?;
+    let symbol = &symbols[index];
+    Some((symbol, addr - symbol.addr))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// A symbol table in the embedded layout, validated by `parse`.
+#[derive(Debug, Copy, Clone)]
+pub struct EmbeddedTable<'a> {
+    bytes: &'a [u8],
+    count: usize,
+    strings: &'a [u8],
+}
+
+impl<'a> EmbeddedTable<'a> {
+    /// Checks the header and that the entries and strings lie inside
+    /// `bytes`; `None` for an empty (zeroed) or malformed table.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.get(..4)? != KSYMS_MAGIC {
+            return None;
+        }
+        let count = read_u32(bytes, 4)? as usize;
+        let strings_offset = read_u32(bytes, 8)? as usize;
+        let strings_len = read_u32(bytes, 12)? as usize;
+        let entries_end = HEADER_SIZE.checked_add(count.checked_mul(ENTRY_SIZE)?)?;
+        if entries_end > strings_offset {
+            return None;
+        }
+        let strings = bytes.get(strings_offset..strings_offset.checked_add(strings_len)?)?;
+        Some(Self { bytes, count, strings })
+    }
+
+    /// Number of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn address(&self, index: usize) -> usize {
+        read_u64(self.bytes, HEADER_SIZE + index * ENTRY_SIZE).unwrap_or(0) as usize
+    }
+
+    /// Returns the name of symbol `index`, or `"?"` if it is out of the
+    /// string table or not UTF-8.
+    fn name(&self, index: usize) -> &'a str {
+        let entry = HEADER_SIZE + index * ENTRY_SIZE;
+        let name = (|| {
+            let offset = read_u32(self.bytes, entry + 8)? as usize;
+            let len = read_u32(self.bytes, entry + 12)? as usize;
+            core::str::from_utf8(self.strings.get(offset..offset.checked_add(len)?)?).ok()
+        })();
+        name.unwrap_or("?")
+    }
+
+    /// Returns the symbol containing `addr` and the offset into it.
+    pub fn lookup(&self, addr: usize) -> Option<(&'a str, usize)> {
+        // The first entry above `addr`, by binary search over the entries.
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.address(mid) <= addr {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let index = low.checked_sub(1)?;
+        let start = self.address(index);
+        Some((self.name(index), addr - start))
+    }
+}
+
+/// Formats an address as `name+0xoff`, or as the bare address if no
+/// symbol covers it.
+pub struct Symbolized(pub usize);
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match resolve(self.0) {
+            Some((name, offset)) => write!(f, "{}+{:#x}", name, offset),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// Formats an address as `0xaddr <name+0xoff>`, keeping the raw address
+/// for dumps that are also read against a disassembly.
+pub struct WithSymbol(pub usize);
+
+impl fmt::Display for WithSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)?;
+        if let Some((name, offset)) = resolve(self.0) {
+            write!(f, " <{}+{:#x}>", name, offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Builds a table in the layout `tools/ksyms.py` writes.
+    fn table(symbols: &[(u64, &str)]) -> Vec<u8> {
+        let strings_offset = HEADER_SIZE + symbols.len() * ENTRY_SIZE;
+        let mut bytes = Vec::from(KSYMS_MAGIC);
+        let mut strings = Vec::new();
+        let mut entries = Vec::new();
+        for (addr, name) in symbols {
+            entries.extend_from_slice(&addr.to_le_bytes());
+            entries.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+            entries.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            strings.extend_from_slice(name.as_bytes());
+        }
+        bytes.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(strings_offset as u32).to_le_bytes());
+        bytes.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&entries);
+        bytes.extend_from_slice(&strings);
+        // Padding to the section size, as in the image.
+        bytes.resize(bytes.len() + 64, 0);
+        bytes
+    }
+
+    #[test]
+    fn lookup_finds_the_closest_preceding_symbol() {
+        let bytes = table(&[(0x8020_0000, "_start"), (0x8020_0100, "rust_main"), (0x8020_0400, "panic")]);
+        let table = EmbeddedTable::parse(&bytes).expect("valid table");
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.lookup(0x8020_0000), Some(("_start", 0)));
+        assert_eq!(table.lookup(0x8020_0108), Some(("rust_main", 8)));
+        assert_eq!(table.lookup(0x8030_0000), Some(("panic", 0x0ff_c00)));
+        assert_eq!(table.lookup(0x8000_0000), None);
+    }
+
+    #[test]
+    fn zeroed_and_truncated_tables_are_rejected() {
+        assert!(EmbeddedTable::parse(&[0; 64]).is_none());
+        let bytes = table(&[(0x1000, "a"), (0x2000, "b")]);
+        assert!(EmbeddedTable::parse(&bytes[..HEADER_SIZE + ENTRY_SIZE]).is_none());
+    }
+}
@@ -1,2 +1,8 @@
 // 工具模块入口
-pub mod sbi;
\ No newline at end of file
+pub mod sbi;// SBI调用封装模块
+pub mod ksyms; // 内核符号表
+pub mod backtrace; // 基于帧指针的调用栈回溯
+pub mod rand; // 内核随机数生成器
+pub mod prng; // 可复现的伪随机数生成器 (测试用)
+pub mod crc; // CRC-32校验
+pub mod arrayvec; // 定长的ArrayVec与ArrayString (无需堆)
@@ -0,0 +1,79 @@
+// nt_rustos/src/util/prng.rs
+
+//! # Seeded Pseudo-Random Numbers
+//!
+//! `Xoshiro256` is the xoshiro256** generator: fast, small and fully
+//! determined by its seed, for code that needs random-looking input it can
+//! replay, such as the stress and fuzz tests. It is not suitable for
+//! anything security related; use `util::rand` for that.
+//!
+//! The 64-bit seed is expanded into the 256-bit state with SplitMix64, as
+//! the generator's authors recommend, so any seed, including 0, gives a
+//! usable state.
+
+/// The xoshiro256** generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+/// Advances a SplitMix64 state and returns its next output.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Xoshiro256 {
+    /// Returns the generator for `seed`; equal seeds give equal sequences.
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed;
+        Self { s: [(); 4].map(|_| splitmix64(&mut state)) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        result
+    }
+
+    /// Returns the upper half of the next output, the better-mixed bits.
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a value in `0..bound`, or 0 if `bound` is 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        // Rejects the top partial range so every value is equally likely.
+        let zone = u64::MAX - (u64::MAX - bound + 1) % bound;
+        loop {
+            let value = self.next_u64();
+            if value <= zone {
+                return value % bound;
+            }
+        }
+    }
+
+    /// Returns `true` with probability `1/n`; always for `n` of 0 or 1.
+    pub fn one_in(&mut self, n: u64) -> bool {
+        self.below(n) == 0
+    }
+
+    pub fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// nt_rustos/src/util/rand.rs
+
+//! # Kernel Random Number Generator
+//!
+//! A ChaCha20-based CSPRNG for kernel use. Output is produced with "fast key
+//! erasure": every block generated replaces the key with its first half and
+//! hands out only the second half, so a later state compromise does not
+//! reveal earlier output.
+//!
+//! The generator seeds itself on first use from timing jitter of the cycle
+//! and time counters. That seed is weak and only meant to get early users
+//! such as heap randomization going; entropy sources such as the virtio-rng
+//! driver feed real entropy with `add_entropy` once they are probed, and
+//! `seed_source` tells which kind of seed is in effect.
+//!
+//! The generator is protected by a spin lock and must not be used from trap
+//! handlers.
+
+use crate::init::initcall::InitResult;
+use core::arch::asm;
+use spin::Mutex;
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+const BLOCK_WORDS: usize = 16;
+const KEY_WORDS: usize = 8;
+/// Output bytes handed out per block; the other half becomes the next key.
+const OUTPUT_BYTES: usize = 32;
+
+/// Timing samples taken for the fallback seed.
+const JITTER_ROUNDS: usize = 64;
+
+/// Where the generator's seed came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeedSource {
+    /// Only timing jitter of the cycle counters; not cryptographically
+    /// strong.
+    CycleCounters,
+    /// A hardware entropy device, named by its driver.
+    Device(&'static str),
+}
+
+struct ChaChaRng {
+    key: [u32; KEY_WORDS],
+    counter: u64,
+    output: [u8; OUTPUT_BYTES],
+    /// Bytes of `output` already handed out.
+    used: usize,
+    source: SeedSource,
+}
+
+#[inline(always)]
+fn quarter_round(s: &mut [u32; BLOCK_WORDS], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// Computes the ChaCha20 block for `key` and `counter`, with a zero nonce.
+fn chacha20_block(key: &[u32; KEY_WORDS], counter: u64) -> [u32; BLOCK_WORDS] {
+    let mut input = [0u32; BLOCK_WORDS];
+    input[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (word, original) in state.iter_mut().zip(input.iter()) {
+        *word = word.wrapping_add(*original);
+    }
+    state
+}
+
+impl ChaChaRng {
+    fn new(source: SeedSource) -> Self {
+        Self { key: [0; KEY_WORDS], counter: 0, output: [0; OUTPUT_BYTES], used: OUTPUT_BYTES, source }
+    }
+
+    /// Generates a block: the first half rekeys, the second half is output.
+    fn refill(&mut self) {
+        let block = chacha20_block(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&block[..KEY_WORDS]);
+        for (bytes, word) in self.output.chunks_exact_mut(4).zip(block[KEY_WORDS..].iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        self.used = 0;
+    }
+
+    /// Folds `data` into the key and discards buffered output.
+    fn mix(&mut self, data: &[u8]) {
+        for chunk in data.chunks(KEY_WORDS * 4) {
+            for (i, bytes) in chunk.chunks(4).enumerate() {
+                let mut word = [0u8; 4];
+                word[..bytes.len()].copy_from_slice(bytes);
+                self.key[i] ^= u32::from_le_bytes(word);
+            }
+            self.refill();
+        }
+        self.output.fill(0);
+        self.used = OUTPUT_BYTES;
+    }
+
+    fn fill(&mut self, dest: &mut [u8]) {
+        let mut filled = 0;
+        while filled < dest.len() {
+            if self.used == OUTPUT_BYTES {
+                self.refill();
+            }
+            let n = (dest.len() - filled).min(OUTPUT_BYTES - self.used);
+            dest[filled..filled + n].copy_from_slice(&self.output[self.used..self.used + n]);
+            // Handed-out bytes are not kept around.
+            self.output[self.used..self.used + n].fill(0);
+            self.used += n;
+            filled += n;
+        }
+    }
+}
+
+static RNG: Mutex<Option<ChaChaRng>> = Mutex::new(None);
+
+fn read_cycle() -> u64 {
+    let cycles: u64;
+    unsafe {
+        asm!("csrr {}, cycle", out(reg) cycles);
+    }
+    cycles
+}
+
+fn read_time() -> u64 {
+    let ticks: u64;
+    unsafe {
+        asm!("csrr {}, time", out(reg) ticks);
+    }
+    ticks
+}
+
+/// Gathers a seed from the jitter between the cycle and time counters over
+/// short loops of varying length.
+fn cycle_counter_seed() -> [u8; KEY_WORDS * 4] {
+    let mut seed = [0u8; KEY_WORDS * 4];
+    let mut previous = read_cycle();
+    for round in 0..JITTER_ROUNDS {
+        let spins = (previous as usize & 0x3f) + round;
+        for _ in 0..spins {
+            core::hint::spin_loop();
+        }
+        let now = read_cycle();
+        let sample = now.wrapping_sub(previous) ^ read_time().rotate_left(round as u32);
+        previous = now;
+        for (i, byte) in sample.to_le_bytes().iter().enumerate() {
+            let slot = (round * 8 + i) % seed.len();
+            seed[slot] = seed[slot].rotate_left(3) ^ byte;
+        }
+    }
+    if let Some(info) = crate::boot::boot_info() {
+        for (byte, extra) in seed.iter_mut().zip(info.dtb_addr.to_le_bytes()) {
+            *byte ^= extra;
+        }
+    }
+    seed
+}
+
+fn with_rng<R>(f: impl FnOnce(&mut ChaChaRng) -> R) -> R {
+    let mut rng = RNG.lock();
+    let rng = rng.get_or_insert_with(|| {
+        let mut rng = ChaChaRng::new(SeedSource::CycleCounters);
+        rng.mix(&cycle_counter_seed());
+        rng
+    });
+    f(rng)
+}
+
+/// Mixes entropy gathered by `source` into the generator.
+///
+/// Callers should pass at least 32 bytes of full entropy before naming a
+/// hardware source.
+pub fn add_entropy(data: &[u8], source: SeedSource) {
+    with_rng(|rng| {
+        rng.mix(data);
+        if let SeedSource::Device(_) = source {
+            rng.source = source;
+        }
+    });
+}
+
+/// Returns where the current seed came from.
+pub fn seed_source() -> SeedSource {
+    with_rng(|rng| rng.source)
+}
+
+/// Reports the seed in effect once the entropy devices have been probed.
+fn report_seed() -> InitResult {
+    match seed_source() {
+        SeedSource::Device(name) => crate::info_print!("Kernel RNG seeded from {}.", name),
+        SeedSource::CycleCounters => crate::warn_print!("Kernel RNG seeded from cycle counter jitter only."),
+    }
+    Ok(())
+}
+
+crate::initcall!(late, 10, report_seed);
+
+/// Fills `dest` with random bytes.
+pub fn fill_bytes(dest: &mut [u8]) {
+    with_rng(|rng| rng.fill(dest));
+}
+
+/// Returns a random 64-bit value.
+pub fn rand_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns a random value in `0..bound`, or 0 if `bound` is 0.
+pub fn rand_below(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    // Rejects the top partial range so every value is equally likely.
+    let zone = u64::MAX - (u64::MAX - bound + 1) % bound;
+    loop {
+        let value = rand_u64();
+        if value <= zone {
+            return value % bound;
+        }
+    }
+}
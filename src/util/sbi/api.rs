@@ -227,8 +227,10 @@ pub mod rfence {
     }
 
     /// 远程sfence.vma指令
+    ///
+    /// hart_mask_base固定为0
     pub fn remote_sfence_vma(hart_mask: usize, start: usize, size: usize) -> SbiResult {
-        let ret = sbi_call(extension_ids::RFENCE, 1, [hart_mask, start, size, 0, 0, 0]);
+        let ret = sbi_call(extension_ids::RFENCE, 1, [hart_mask, 0, start, size, 0, 0]);
         match ret {
             Ok(0) => Ok(0),
             _ => Err(SbiError::Failed),
@@ -237,7 +239,7 @@ pub mod rfence {
 
     /// 远程sfence.vma.asid指令
     pub fn remote_sfence_vma_asid(hart_mask: usize, start: usize, size: usize, asid: usize) -> SbiResult {
-        let ret = sbi_call(extension_ids::RFENCE, 2, [hart_mask, start, size, asid, 0, 0]);
+        let ret = sbi_call(extension_ids::RFENCE, 2, [hart_mask, 0, start, size, asid, 0]);
         match ret {
             Ok(0) => Ok(0),
             _ => Err(SbiError::Failed),
@@ -289,6 +291,19 @@ pub mod hsm {
         let ret = sbi_call(extension_ids::HSM, 2, [hartid, 0, 0, 0, 0, 0]);
         ret
     }
+
+    /// 默认保持型挂起：中断到来后从调用处继续执行
+    pub const SUSPEND_TYPE_DEFAULT_RETENTIVE: usize = 0;
+
+    /// 挂起当前hart (SBI v0.3起)
+    /// 
+    /// # 参数
+    /// * `suspend_type` - 挂起类型
+    /// * `resume_addr` - 非保持型挂起的恢复地址
+    /// * `opaque` - 恢复时传递给hart的参数
+    pub fn hart_suspend(suspend_type: usize, resume_addr: usize, opaque: usize) -> SbiResult {
+        sbi_call(extension_ids::HSM, 3, [suspend_type, resume_addr, opaque, 0, 0, 0])
+    }
 }
 
 /// 系统重置扩展
@@ -360,6 +375,149 @@ pub mod pmu {
         let ret = sbi_call(extension_ids::PMU, 1, [counter_idx, 0, 0, 0, 0, 0]);
         ret
     }
+
+    /// 计数器信息：对应的CSR编号、位宽与是否为固件计数器
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct CounterInfo {
+        pub csr: usize,
+        pub width: usize,
+        pub firmware: bool,
+    }
+
+    /// 获取并解析计数器信息
+    pub fn counter_info(counter_idx: usize) -> Result<CounterInfo, SbiError> {
+        let info = get_counter_info(counter_idx)?;
+        Ok(CounterInfo {
+            csr: info & 0xfff,
+            width: ((info >> 12) & 0x3f) + 1,
+            firmware: info >> (usize::BITS - 1) != 0,
+        })
+    }
+
+    /// 配置时跳过匹配，直接使用counter_idx_base指定的计数器
+    pub const CFG_FLAG_SKIP_MATCH: usize = 1 << 0;
+    /// 配置时清零计数器
+    pub const CFG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+    /// 配置后立即启动计数器
+    pub const CFG_FLAG_AUTO_START: usize = 1 << 2;
+    /// 不在VU模式计数
+    pub const CFG_FLAG_SET_VUINH: usize = 1 << 3;
+    /// 不在VS模式计数
+    pub const CFG_FLAG_SET_VSINH: usize = 1 << 4;
+    /// 不在U模式计数
+    pub const CFG_FLAG_SET_UINH: usize = 1 << 5;
+    /// 不在S模式计数
+    pub const CFG_FLAG_SET_SINH: usize = 1 << 6;
+    /// 不在M模式计数
+    pub const CFG_FLAG_SET_MINH: usize = 1 << 7;
+    /// 启动时设置计数器初值
+    pub const START_FLAG_SET_INIT_VALUE: usize = 1 << 0;
+    /// 启动时从快照共享内存恢复计数器值
+    pub const START_FLAG_INIT_SNAPSHOT: usize = 1 << 1;
+    /// 停止时释放计数器
+    pub const STOP_FLAG_RESET: usize = 1 << 0;
+    /// 停止时把计数器值写入快照共享内存
+    pub const STOP_FLAG_TAKE_SNAPSHOT: usize = 1 << 1;
+
+    /// 事件类型 (事件编号的第16至19位)
+    pub const EVENT_TYPE_HW: usize = 0;
+    pub const EVENT_TYPE_HW_CACHE: usize = 1;
+    pub const EVENT_TYPE_HW_RAW: usize = 2;
+    pub const EVENT_TYPE_FW: usize = 15;
+
+    /// 硬件通用事件：CPU周期
+    pub const EVENT_HW_CPU_CYCLES: usize = 1;
+    /// 硬件通用事件：退休指令数
+    pub const EVENT_HW_INSTRUCTIONS: usize = 2;
+    /// 硬件通用事件：缓存访问
+    pub const EVENT_HW_CACHE_REFERENCES: usize = 3;
+    /// 硬件通用事件：缓存未命中
+    pub const EVENT_HW_CACHE_MISSES: usize = 4;
+    /// 硬件通用事件：分支指令
+    pub const EVENT_HW_BRANCH_INSTRUCTIONS: usize = 5;
+    /// 硬件通用事件：分支预测失败
+    pub const EVENT_HW_BRANCH_MISSES: usize = 6;
+    /// 硬件通用事件：总线周期
+    pub const EVENT_HW_BUS_CYCLES: usize = 7;
+    /// 硬件通用事件：前端停顿周期
+    pub const EVENT_HW_STALLED_CYCLES_FRONTEND: usize = 8;
+    /// 硬件通用事件：后端停顿周期
+    pub const EVENT_HW_STALLED_CYCLES_BACKEND: usize = 9;
+    /// 硬件通用事件：参考周期 (不受调频影响)
+    pub const EVENT_HW_REF_CPU_CYCLES: usize = 10;
+
+    /// 硬件缓存事件的缓存编号
+    pub const CACHE_L1D: usize = 0;
+    pub const CACHE_L1I: usize = 1;
+    pub const CACHE_LL: usize = 2;
+    pub const CACHE_DTLB: usize = 3;
+    pub const CACHE_ITLB: usize = 4;
+    pub const CACHE_BPU: usize = 5;
+    pub const CACHE_NODE: usize = 6;
+    /// 硬件缓存事件的操作
+    pub const CACHE_OP_READ: usize = 0;
+    pub const CACHE_OP_WRITE: usize = 1;
+    pub const CACHE_OP_PREFETCH: usize = 2;
+    /// 硬件缓存事件的结果
+    pub const CACHE_RESULT_ACCESS: usize = 0;
+    pub const CACHE_RESULT_MISS: usize = 1;
+
+    /// 硬件缓存事件的事件编号
+    pub const fn cache_event(cache: usize, op: usize, result: usize) -> usize {
+        EVENT_TYPE_HW_CACHE << 16 | cache << 3 | op << 1 | result
+    }
+
+    /// 原始硬件事件的事件编号，事件码经event_data传入
+    pub const EVENT_HW_RAW: usize = EVENT_TYPE_HW_RAW << 16;
+
+    /// 查找并配置一个能计数指定事件的计数器，返回计数器编号
+    pub fn counter_config_matching(
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        config_flags: usize,
+        event_idx: usize,
+        event_data: u64,
+    ) -> SbiResult {
+        sbi_call(
+            extension_ids::PMU,
+            2,
+            [counter_idx_base, counter_idx_mask, config_flags, event_idx, event_data as usize, 0],
+        )
+    }
+
+    /// 启动计数器
+    pub fn counter_start(
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        start_flags: usize,
+        initial_value: u64,
+    ) -> SbiResult {
+        sbi_call(
+            extension_ids::PMU,
+            3,
+            [counter_idx_base, counter_idx_mask, start_flags, initial_value as usize, 0, 0],
+        )
+    }
+
+    /// 停止计数器
+    pub fn counter_stop(counter_idx_base: usize, counter_idx_mask: usize, stop_flags: usize) -> SbiResult {
+        sbi_call(extension_ids::PMU, 4, [counter_idx_base, counter_idx_mask, stop_flags, 0, 0, 0])
+    }
+
+    /// 读取固件计数器的值 (低XLEN位)
+    pub fn counter_fw_read(counter_idx: usize) -> SbiResult {
+        sbi_call(extension_ids::PMU, 5, [counter_idx, 0, 0, 0, 0, 0])
+    }
+
+    /// 读取固件计数器值的高32位 (仅RV32)
+    pub fn counter_fw_read_hi(counter_idx: usize) -> SbiResult {
+        sbi_call(extension_ids::PMU, 6, [counter_idx, 0, 0, 0, 0, 0])
+    }
+
+    /// 设置计数器快照共享内存 (物理地址，4KB对齐)；地址全1时停用快照
+    pub fn snapshot_set_shmem(shmem_phys_lo: usize, shmem_phys_hi: usize, flags: usize) -> SbiResult {
+        sbi_call(extension_ids::PMU, 7, [shmem_phys_lo, shmem_phys_hi, flags, 0, 0, 0])
+    }
 }
 
 /// 调试控制台扩展
@@ -2,4 +2,12 @@
 pub mod api;
 
 // 重新导出API接口，方便外部使用
-pub use api::*;
\ No newline at end of file
+pub use api::*;
+
+/// 启动结束时显示SBI系统信息 (有助于调试)
+fn print_info_at_boot() -> crate::init::initcall::InitResult {
+    info::print_sbi_info();
+    Ok(())
+}
+
+crate::initcall!(late, 30, print_info_at_boot);
@@ -0,0 +1,97 @@
+#!/usr/bin/env python3
+"""Fill the `.ksyms` section of a linked kernel with its symbol table.
+
+The kernel reserves the section (see src/util/ksyms.rs for the layout);
+this script reads the function symbols of the image with `nm`, packs them
+sorted by address and writes them into the section in place, so no other
+section moves:
+
+    cargo build
+    python3 tools/ksyms.py target/riscv64gc-unknown-none-elf/debug/nt_rustos
+
+`NM` and `OBJCOPY` select the tools (default: llvm-nm and llvm-objcopy,
+`cargo install cargo-binutils` provides them as rust-nm and rust-objcopy).
+"""
+
+import os
+import re
+import struct
+import subprocess
+import sys
+import tempfile
+
+MAGIC = b"KSYM"
+HEADER = struct.Struct("<4sIII")
+ENTRY = struct.Struct("<QII")
+SECTION = ".ksyms"
+# Legacy Rust mangling leaves a hash after demangling, e.g. `foo::h0123456789abcdef`.
+HASH_SUFFIX = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def section_size(path, name):
+    """Size of section `name` in the ELF64 little-endian file at `path`."""
+    with open(path, "rb") as f:
+        data = f.read()
+    if data[:4] != b"\x7fELF" or data[4] != 2 or data[5] != 1:
+        sys.exit(f"{path}: not a little-endian ELF64 file")
+    shoff, = struct.unpack_from("<Q", data, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", data, 0x3A)
+    headers = [struct.unpack_from("<IIQQQQIIQQ", data, shoff + i * shentsize) for i in range(shnum)]
+    strtab = headers[shstrndx]
+    for header in headers:
+        start = strtab[4] + header[0]
+        if data[start:data.index(b"\0", start)].decode() == name:
+            return header[5]
+    sys.exit(f"{path}: no {name} section; the kernel must reserve it")
+
+
+def function_symbols(path):
+    """(address, name) of the text symbols, sorted, one name per address."""
+    nm = os.environ.get("NM", "llvm-nm")
+    output = subprocess.run([nm, "-n", "-C", "--defined-only", path],
+                            check=True, capture_output=True, text=True).stdout
+    symbols = {}
+    for line in output.splitlines():
+        fields = line.split(maxsplit=2)
+        if len(fields) != 3 or fields[1] not in "tTwW":
+            continue
+        addr = int(fields[0], 16)
+        name = HASH_SUFFIX.sub("", fields[2])
+        # Local labels (.L...) and compiler-internal aliases say nothing useful.
+        if name.startswith(".L") or name.startswith("$"):
+            continue
+        symbols.setdefault(addr, name)
+    return sorted(symbols.items())
+
+
+def pack(symbols):
+    strings = bytearray()
+    entries = bytearray()
+    for addr, name in symbols:
+        encoded = name.encode()
+        entries += ENTRY.pack(addr, len(strings), len(encoded))
+        strings += encoded
+    strings_offset = HEADER.size + len(entries)
+    return HEADER.pack(MAGIC, len(symbols), strings_offset, len(strings)) + entries + strings
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(f"usage: {sys.argv[0]} <kernel ELF>")
+    path = sys.argv[1]
+    capacity = section_size(path, SECTION)
+    symbols = function_symbols(path)
+    table = pack(symbols)
+    if len(table) > capacity:
+        sys.exit(f"symbol table needs {len(table)} bytes, {SECTION} has {capacity}; raise KSYMS_CAPACITY")
+    objcopy = os.environ.get("OBJCOPY", "llvm-objcopy")
+    with tempfile.NamedTemporaryFile(suffix=".ksyms") as blob:
+        # Pad to the reserved size so the section keeps its size and nothing moves.
+        blob.write(table + bytes(capacity - len(table)))
+        blob.flush()
+        subprocess.run([objcopy, "--update-section", f"{SECTION}={blob.name}", path], check=True)
+    print(f"{path}: {len(symbols)} symbols, {len(table)} of {capacity} bytes")
+
+
+if __name__ == "__main__":
+    main()